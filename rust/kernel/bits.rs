@@ -4,9 +4,321 @@
 //!
 //! C header: [`include/linux/bits.h`](srctree/include/linux/bits.h)
 
-/// Generate a mask where all bits >= `h` and <= `l` are set
+macro_rules! impl_genmask {
+    ($($name:ident: $ty:ty, $c:literal;)*) => {
+        $(
+            /// Generate a
+            #[doc = concat!("`", stringify!($ty), "`")]
+            /// mask where all bits <= `h` and >= `l` are set
+            ///
+            #[doc = concat!("This is a re-implementation in rust of `", $c, "`.")]
+            ///
+            #[doc = concat!("`h` must be below [`", stringify!($ty), "::BITS`], and `l` must not")]
+            /// be above `h`, which is checked at build time.
+            #[inline(always)]
+            pub const fn $name(h: u32, l: u32) -> $ty {
+                crate::build_assert!(h < <$ty>::BITS && l <= h, "invalid bit range");
+                (<$ty>::MAX - (1 << l) + 1) & (<$ty>::MAX >> (<$ty>::BITS - 1 - h))
+            }
+        )*
+    };
+}
+
+impl_genmask! {
+    genmask_u8: u8, "GENMASK";
+    genmask_u16: u16, "GENMASK";
+    genmask_u32: u32, "GENMASK";
+    genmask_u64: u64, "GENMASK_ULL";
+    genmask_u128: u128, "GENMASK_U128";
+}
+
+/// Generate a mask where all bits <= `h` and >= `l` are set
+///
+/// This is the same as [`genmask_u32`], which is the width of most registers. Fields of other
+/// widths use the function of their type, e.g., [`genmask_u16`], instead of casting.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::bits::{genmask, genmask_u16, genmask_u128};
 ///
-/// This is a re-implementation in rust of `GENMASK`
+/// assert_eq!(genmask(7, 4), 0xf0);
+/// assert_eq!(genmask_u16(15, 8), 0xff00);
+/// assert_eq!(genmask_u128(127, 96), 0xffff_ffff << 96);
+/// ```
+#[inline(always)]
 pub const fn genmask(h: u32, l: u32) -> u32 {
-    ((!0u32) - (1 << l) + 1) & ((!0u32) >> (32 - 1 - h))
+    genmask_u32(h, l)
+}
+
+pub use crate::bitflags;
+
+/// Defines a type for a set of flags stored in an integer.
+///
+/// The generated type is a transparent wrapper around the integer, so it can be used for register
+/// fields and passed to C as is (see `bits()` and `from_bits_retain()`). It provides:
+///
+/// - an associated constant for each flag;
+/// - `empty()`, `all()`, `bits()`, `from_bits()`, `from_bits_truncate()` and
+///   `from_bits_retain()`;
+/// - `is_empty()`, `is_all()`, `contains()` and `intersects()`;
+/// - `insert()`, `remove()`, `toggle()` and `set()`;
+/// - the `|`, `&`, `^`, `-` (difference) and `!` operators, and their assigning versions;
+/// - a [`Debug`] implementation that lists the names of the set flags, followed by the unknown
+///   bits, if any.
+///
+/// [`Debug`]: core::fmt::Debug
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{bits::bitflags, fmt, prelude::*, str::CString};
+///
+/// bitflags! {
+///     /// Flags of the interrupt status register.
+///     pub struct Status: u32 {
+///         /// Transmission completed.
+///         const TX_DONE = 1 << 0;
+///         /// Data received.
+///         const RX_READY = 1 << 1;
+///         /// Any error condition.
+///         const ERROR = 1 << 4;
+///     }
+/// }
+///
+/// let mut s = Status::TX_DONE | Status::ERROR;
+/// assert!(s.contains(Status::TX_DONE));
+/// assert!(!s.contains(Status::TX_DONE | Status::RX_READY));
+/// assert!(s.intersects(Status::TX_DONE | Status::RX_READY));
+///
+/// s.remove(Status::ERROR);
+/// assert_eq!(s, Status::TX_DONE);
+/// assert_eq!((s | Status::RX_READY).bits(), 0x3);
+/// assert_eq!(Status::all() - Status::ERROR, Status::TX_DONE | Status::RX_READY);
+///
+/// // Unknown bits are rejected, dropped or kept, depending on the constructor.
+/// assert_eq!(Status::from_bits(0x22), None);
+/// assert_eq!(Status::from_bits_truncate(0x22), Status::RX_READY);
+/// assert_eq!(Status::from_bits_retain(0x22).bits(), 0x22);
+///
+/// let s = CString::try_from_fmt(fmt!("{:?}", Status::TX_DONE | Status::ERROR))?;
+/// assert_eq!(s.to_str()?, "Status(TX_DONE | ERROR)");
+/// let s = CString::try_from_fmt(fmt!("{:?}", Status::from_bits_retain(0x22)))?;
+/// assert_eq!(s.to_str()?, "Status(RX_READY | 0x20)");
+/// let s = CString::try_from_fmt(fmt!("{:?}", Status::empty()))?;
+/// assert_eq!(s.to_str()?, "Status(empty)");
+/// # Ok::<(), Error>(())
+/// ```
+#[macro_export]
+macro_rules! bitflags {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident: $ty:ty {
+            $(
+                $(#[$flag_meta:meta])*
+                const $flag:ident = $value:expr;
+            )*
+        }
+    ) => {
+        $(#[$meta])*
+        #[repr(transparent)]
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+        $vis struct $name($ty);
+
+        #[allow(dead_code)]
+        impl $name {
+            $(
+                $(#[$flag_meta])*
+                pub const $flag: Self = Self($value);
+            )*
+
+            const NAMED: &'static [(&'static str, $ty)] = &[$((::core::stringify!($flag), $value)),*];
+
+            /// Returns a value with no flags set.
+            pub const fn empty() -> Self {
+                Self(0)
+            }
+
+            /// Returns a value with all the known flags set.
+            pub const fn all() -> Self {
+                Self(0 $(| $value)*)
+            }
+
+            /// Returns the raw value.
+            pub const fn bits(self) -> $ty {
+                self.0
+            }
+
+            /// Converts from a raw value, returning [`None`] if it contains unknown bits.
+            pub const fn from_bits(bits: $ty) -> ::core::option::Option<Self> {
+                if bits & !Self::all().0 == 0 {
+                    ::core::option::Option::Some(Self(bits))
+                } else {
+                    ::core::option::Option::None
+                }
+            }
+
+            /// Converts from a raw value, dropping unknown bits.
+            pub const fn from_bits_truncate(bits: $ty) -> Self {
+                Self(bits & Self::all().0)
+            }
+
+            /// Converts from a raw value, keeping unknown bits.
+            pub const fn from_bits_retain(bits: $ty) -> Self {
+                Self(bits)
+            }
+
+            /// Returns `true` if no flags are set.
+            pub const fn is_empty(self) -> bool {
+                self.0 == 0
+            }
+
+            /// Returns `true` if all the known flags are set.
+            pub const fn is_all(self) -> bool {
+                self.0 & Self::all().0 == Self::all().0
+            }
+
+            /// Returns `true` if all the flags in `other` are set.
+            pub const fn contains(self, other: Self) -> bool {
+                self.0 & other.0 == other.0
+            }
+
+            /// Returns `true` if any of the flags in `other` is set.
+            pub const fn intersects(self, other: Self) -> bool {
+                self.0 & other.0 != 0
+            }
+
+            /// Returns the flags set in `self` or in `other`.
+            pub const fn union(self, other: Self) -> Self {
+                Self(self.0 | other.0)
+            }
+
+            /// Returns the flags set in both `self` and `other`.
+            pub const fn intersection(self, other: Self) -> Self {
+                Self(self.0 & other.0)
+            }
+
+            /// Returns the flags set in `self` but not in `other`.
+            pub const fn difference(self, other: Self) -> Self {
+                Self(self.0 & !other.0)
+            }
+
+            /// Returns the known flags that are not set in `self`.
+            pub const fn complement(self) -> Self {
+                Self(!self.0 & Self::all().0)
+            }
+
+            /// Sets the flags in `other`.
+            pub fn insert(&mut self, other: Self) {
+                self.0 |= other.0;
+            }
+
+            /// Clears the flags in `other`.
+            pub fn remove(&mut self, other: Self) {
+                self.0 &= !other.0;
+            }
+
+            /// Inverts the flags in `other`.
+            pub fn toggle(&mut self, other: Self) {
+                self.0 ^= other.0;
+            }
+
+            /// Sets or clears the flags in `other`, depending on `value`.
+            pub fn set(&mut self, other: Self, value: bool) {
+                if value {
+                    self.insert(other);
+                } else {
+                    self.remove(other);
+                }
+            }
+        }
+
+        impl ::core::ops::BitOr for $name {
+            type Output = Self;
+            fn bitor(self, rhs: Self) -> Self {
+                self.union(rhs)
+            }
+        }
+
+        impl ::core::ops::BitOrAssign for $name {
+            fn bitor_assign(&mut self, rhs: Self) {
+                self.insert(rhs);
+            }
+        }
+
+        impl ::core::ops::BitAnd for $name {
+            type Output = Self;
+            fn bitand(self, rhs: Self) -> Self {
+                self.intersection(rhs)
+            }
+        }
+
+        impl ::core::ops::BitAndAssign for $name {
+            fn bitand_assign(&mut self, rhs: Self) {
+                *self = self.intersection(rhs);
+            }
+        }
+
+        impl ::core::ops::BitXor for $name {
+            type Output = Self;
+            fn bitxor(self, rhs: Self) -> Self {
+                Self(self.0 ^ rhs.0)
+            }
+        }
+
+        impl ::core::ops::BitXorAssign for $name {
+            fn bitxor_assign(&mut self, rhs: Self) {
+                self.toggle(rhs);
+            }
+        }
+
+        impl ::core::ops::Sub for $name {
+            type Output = Self;
+            fn sub(self, rhs: Self) -> Self {
+                self.difference(rhs)
+            }
+        }
+
+        impl ::core::ops::SubAssign for $name {
+            fn sub_assign(&mut self, rhs: Self) {
+                self.remove(rhs);
+            }
+        }
+
+        impl ::core::ops::Not for $name {
+            type Output = Self;
+            fn not(self) -> Self {
+                self.complement()
+            }
+        }
+
+        impl ::core::fmt::Debug for $name {
+            fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                f.write_str(::core::concat!(::core::stringify!($name), "("))?;
+                if self.0 == 0 {
+                    f.write_str("empty")?;
+                } else {
+                    let mut rest = self.0;
+                    let mut first = true;
+                    for &(name, value) in Self::NAMED {
+                        if value != 0 && self.0 & value == value {
+                            if !first {
+                                f.write_str(" | ")?;
+                            }
+                            first = false;
+                            f.write_str(name)?;
+                            rest &= !value;
+                        }
+                    }
+                    if rest != 0 {
+                        if !first {
+                            f.write_str(" | ")?;
+                        }
+                        ::core::write!(f, "{:#x}", rest)?;
+                    }
+                }
+                f.write_str(")")
+            }
+        }
+    };
 }
@@ -0,0 +1,244 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Block devices.
+//!
+//! Beyond reads and writes, block drivers of devices with a volatile write cache must handle cache
+//! flushes and forced unit access (FUA) writes, and drivers of devices that can deallocate or zero
+//! ranges advertise that through [`QueueLimits`]. Devices that store protection information with
+//! each sector register an [`integrity`] profile.
+//!
+//! C header: [`include/linux/blkdev.h`](srctree/include/linux/blkdev.h)
+
+use crate::{bindings, types::Opaque};
+
+/// The operation of a block request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    /// Read sectors from the device.
+    Read,
+    /// Write sectors to the device.
+    Write,
+    /// Flush the volatile write cache of the device.
+    ///
+    /// Requests with this operation carry no data. The block layer only issues them if the queue
+    /// has a write cache, see [`QueueLimits::write_cache`].
+    Flush,
+    /// Deallocate sectors, e.g., TRIM on SSDs.
+    Discard,
+    /// Fill sectors with zeroes.
+    WriteZeroes,
+    /// Any other operation, which drivers usually fail with `BLK_STS_NOTSUPP`.
+    Other,
+}
+
+/// A block request.
+#[repr(transparent)]
+pub struct Request(Opaque<bindings::request>);
+
+impl Request {
+    /// Creates a reference to a [`Request`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// Callers must ensure that `ptr` is valid and that the request is owned by the driver, e.g.,
+    /// in `queue_rq`, for the lifetime of the returned reference.
+    pub unsafe fn from_raw<'a>(ptr: *mut bindings::request) -> &'a Self {
+        // SAFETY: Guaranteed by the safety requirements of the function. `Request` is a
+        // transparent wrapper around `request`.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns a raw pointer to the underlying `struct request`.
+    pub fn as_raw(&self) -> *mut bindings::request {
+        self.0.get()
+    }
+
+    fn cmd_flags(&self) -> bindings::blk_opf_t {
+        // SAFETY: The request is valid and its flags do not change while the driver owns it.
+        unsafe { (*self.as_raw()).cmd_flags }
+    }
+
+    /// Returns the operation of the request.
+    pub fn operation(&self) -> Operation {
+        match self.cmd_flags() & bindings::REQ_OP_MASK {
+            bindings::req_op_REQ_OP_READ => Operation::Read,
+            bindings::req_op_REQ_OP_WRITE => Operation::Write,
+            bindings::req_op_REQ_OP_FLUSH => Operation::Flush,
+            bindings::req_op_REQ_OP_DISCARD => Operation::Discard,
+            bindings::req_op_REQ_OP_WRITE_ZEROES => Operation::WriteZeroes,
+            _ => Operation::Other,
+        }
+    }
+
+    /// Returns `true` if the data of a write must be on stable storage when the request completes.
+    ///
+    /// The block layer only sets this if the queue supports FUA, see [`QueueLimits::fua`];
+    /// otherwise it emulates FUA with a flush after the write.
+    pub fn is_fua(&self) -> bool {
+        self.cmd_flags() & bindings::REQ_FUA != 0
+    }
+
+    /// Returns `true` if the write cache must be flushed before the request is processed.
+    ///
+    /// The block layer turns such requests into a separate [`Operation::Flush`] before they reach
+    /// the driver, so this is only informative.
+    pub fn is_preflush(&self) -> bool {
+        self.cmd_flags() & bindings::REQ_PREFLUSH != 0
+    }
+
+    /// Returns the first sector of the request, in units of 512 bytes.
+    pub fn sector(&self) -> u64 {
+        // SAFETY: The request is valid.
+        unsafe { bindings::blk_rq_pos(self.as_raw()) }
+    }
+
+    /// Returns the number of sectors of the request, in units of 512 bytes.
+    pub fn sectors(&self) -> u32 {
+        // SAFETY: The request is valid.
+        unsafe { bindings::blk_rq_sectors(self.as_raw()) }
+    }
+}
+
+/// Limits of a request queue beyond reads and writes.
+///
+/// The default has no write cache and supports neither discard nor write zeroes.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::block::QueueLimits;
+///
+/// let limits = QueueLimits::new()
+///     .write_cache(true)
+///     .fua(true)
+///     .discard(1 << 16, 4096)
+///     .write_zeroes(1 << 16);
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct QueueLimits {
+    write_cache: bool,
+    fua: bool,
+    max_discard_sectors: u32,
+    discard_granularity: u32,
+    max_write_zeroes_sectors: u32,
+}
+
+impl QueueLimits {
+    /// Creates the default limits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether the device has a volatile write cache, in which case the block layer issues
+    /// [`Operation::Flush`] requests.
+    pub fn write_cache(mut self, enabled: bool) -> Self {
+        self.write_cache = enabled;
+        self
+    }
+
+    /// Sets whether the device supports FUA writes, see [`Request::is_fua`].
+    ///
+    /// This is only meaningful with a write cache.
+    pub fn fua(mut self, enabled: bool) -> Self {
+        self.fua = enabled;
+        self
+    }
+
+    /// Enables discard for up to `max_sectors` sectors per request, in units of `granularity`
+    /// bytes.
+    pub fn discard(mut self, max_sectors: u32, granularity: u32) -> Self {
+        self.max_discard_sectors = max_sectors;
+        self.discard_granularity = granularity;
+        self
+    }
+
+    /// Enables write zeroes for up to `max_sectors` sectors per request.
+    pub fn write_zeroes(mut self, max_sectors: u32) -> Self {
+        self.max_write_zeroes_sectors = max_sectors;
+        self
+    }
+
+    /// Applies the limits to `queue`.
+    ///
+    /// # Safety
+    ///
+    /// `queue` must be a valid request queue of the caller's driver that is not frozen. This is
+    /// usually the case before the disk is added.
+    pub unsafe fn apply(&self, queue: *mut bindings::request_queue) {
+        // SAFETY: `queue` is valid by the safety requirements.
+        unsafe {
+            bindings::blk_queue_write_cache(queue, self.write_cache, self.write_cache && self.fua);
+            bindings::blk_queue_max_discard_sectors(queue, self.max_discard_sectors);
+            (*queue).limits.discard_granularity = self.discard_granularity;
+            bindings::blk_queue_max_write_zeroes_sectors(queue, self.max_write_zeroes_sectors);
+        }
+    }
+}
+
+/// Data integrity, i.e., protection information stored with each sector.
+///
+/// C header: [`include/linux/blk-integrity.h`](srctree/include/linux/blk-integrity.h)
+#[cfg(CONFIG_BLK_DEV_INTEGRITY)]
+pub mod integrity {
+    use crate::bindings;
+    use core::ptr;
+
+    /// The format of the protection information.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Profile {
+        /// T10 PI type 1, with a CRC guard tag and the sector number as reference tag.
+        T10Type1Crc,
+        /// T10 PI type 1, with an IP checksum guard tag.
+        T10Type1Ip,
+        /// T10 PI type 3, with a CRC guard tag and no reference tag.
+        T10Type3Crc,
+        /// T10 PI type 3, with an IP checksum guard tag.
+        T10Type3Ip,
+    }
+
+    impl Profile {
+        fn as_raw(self) -> *const bindings::blk_integrity_profile {
+            // SAFETY: The profiles are static objects that are never written.
+            unsafe {
+                match self {
+                    Self::T10Type1Crc => ptr::addr_of!(bindings::t10_pi_type1_crc),
+                    Self::T10Type1Ip => ptr::addr_of!(bindings::t10_pi_type1_ip),
+                    Self::T10Type3Crc => ptr::addr_of!(bindings::t10_pi_type3_crc),
+                    Self::T10Type3Ip => ptr::addr_of!(bindings::t10_pi_type3_ip),
+                }
+            }
+        }
+    }
+
+    /// Registers the integrity profile of `disk`.
+    ///
+    /// `interval_exp` is the binary logarithm of the size of the interval covered by each tuple of
+    /// protection information, usually the logical block size. If `device_capable` is `true`, the
+    /// device generates and verifies the protection information itself.
+    ///
+    /// The profile is unregistered when the disk is deleted.
+    ///
+    /// # Safety
+    ///
+    /// `disk` must be a valid disk of the caller's driver.
+    pub unsafe fn register(
+        disk: *mut bindings::gendisk,
+        profile: Profile,
+        interval_exp: u8,
+        device_capable: bool,
+    ) {
+        let mut template = bindings::blk_integrity {
+            profile: profile.as_raw(),
+            flags: if device_capable {
+                bindings::BLK_INTEGRITY_DEVICE_CAPABLE as _
+            } else {
+                0
+            },
+            tuple_size: core::mem::size_of::<bindings::t10_pi_tuple>() as _,
+            interval_exp,
+            tag_size: 0,
+        };
+        // SAFETY: `disk` is valid by the safety requirements. The template is copied.
+        unsafe { bindings::blk_integrity_register(disk, &mut template) };
+    }
+}
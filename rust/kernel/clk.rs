@@ -0,0 +1,169 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Clocks.
+//!
+//! IP blocks of SoCs usually take several clocks, e.g., a bus clock, a core clock and clocks for
+//! their interfaces, which need to be enabled before the block is accessed. [`Bulk`] handles all
+//! the clocks of a device at once.
+//!
+//! C header: [`include/linux/clk.h`](srctree/include/linux/clk.h)
+
+use crate::{
+    bindings,
+    device::RawDevice,
+    error::{to_result, Error, Result},
+    str::CStr,
+};
+use core::{ffi::c_int, ptr};
+
+/// All the clocks of a device.
+///
+/// The clocks are released when this object is dropped. They are enabled by turning the object
+/// into an [`EnabledBulk`], which drivers usually keep in their data while the device is bound.
+///
+/// # Invariants
+///
+/// `clks` points to `num` clocks returned by `clk_bulk_get_all`, or is null if `num` is zero.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{clk, device::RawDevice, prelude::*};
+///
+/// fn power_up(dev: &impl RawDevice) -> Result<clk::EnabledBulk> {
+///     let clks = clk::Bulk::get_all(dev)?;
+///     for i in 0..clks.len() {
+///         if let Some(id) = clks.id(i) {
+///             dev_dbg!(dev, "clock {}: {} Hz\n", id, clks.rate(i).unwrap_or(0));
+///         }
+///     }
+///     // Either all clocks are on, or none.
+///     clks.enable().map_err(|(e, _)| e)
+/// }
+/// ```
+pub struct Bulk {
+    clks: *mut bindings::clk_bulk_data,
+    num: usize,
+}
+
+impl Bulk {
+    /// Gets all the clocks of `dev`, as listed in its firmware description.
+    ///
+    /// Devices without clocks get an empty set.
+    pub fn get_all(dev: &impl RawDevice) -> Result<Self> {
+        let mut clks = ptr::null_mut();
+        // SAFETY: `dev.raw_device()` is valid, and `clks` is valid for writes.
+        let ret = unsafe { bindings::clk_bulk_get_all(dev.raw_device(), &mut clks) };
+        if ret < 0 {
+            return Err(Error::from_errno(ret));
+        }
+        // INVARIANT: `clk_bulk_get_all` returned `ret` clocks in `clks`.
+        Ok(Self {
+            clks,
+            num: ret as usize,
+        })
+    }
+
+    /// Returns the number of clocks.
+    pub fn len(&self) -> usize {
+        self.num
+    }
+
+    /// Returns `true` if the device has no clocks.
+    pub fn is_empty(&self) -> bool {
+        self.num == 0
+    }
+
+    fn get(&self, i: usize) -> Option<&bindings::clk_bulk_data> {
+        if i >= self.num {
+            return None;
+        }
+        // SAFETY: By the type invariants, `clks` points to `num` entries, and `i` is in range.
+        Some(unsafe { &*self.clks.add(i) })
+    }
+
+    /// Returns the name of clock `i`, if it has one.
+    pub fn id(&self, i: usize) -> Option<&CStr> {
+        let id = self.get(i)?.id;
+        if id.is_null() {
+            return None;
+        }
+        // SAFETY: The name is a NUL-terminated string owned by the firmware description, which
+        // outlives the clocks.
+        Some(unsafe { CStr::from_char_ptr(id) })
+    }
+
+    /// Returns the rate of clock `i` in Hz, or [`None`] if `i` is out of range.
+    pub fn rate(&self, i: usize) -> Option<u64> {
+        let clk = self.get(i)?.clk;
+        // SAFETY: `clk` is a valid clock, by the type invariants.
+        Some(unsafe { bindings::clk_get_rate(clk) } as u64)
+    }
+
+    fn num(&self) -> c_int {
+        // `clk_bulk_get_all` returned the number as a `c_int`.
+        self.num as c_int
+    }
+
+    /// Prepares and enables all the clocks.
+    ///
+    /// If one of them fails to enable, the ones already enabled are disabled again, and the error
+    /// is returned along with the clocks.
+    pub fn enable(self) -> core::result::Result<EnabledBulk, (Error, Self)> {
+        // SAFETY: By the type invariants, `clks` holds `num` clocks.
+        match to_result(unsafe { bindings::clk_bulk_prepare_enable(self.num(), self.clks) }) {
+            // INVARIANT: The clocks were just enabled.
+            Ok(()) => Ok(EnabledBulk(self)),
+            Err(e) => Err((e, self)),
+        }
+    }
+}
+
+impl Drop for Bulk {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, the clocks were returned by `clk_bulk_get_all`.
+        unsafe { bindings::clk_bulk_put_all(self.num(), self.clks) };
+    }
+}
+
+// SAFETY: Clocks can be used and released from any thread.
+unsafe impl Send for Bulk {}
+
+// SAFETY: The clock framework serialises all operations on clocks internally.
+unsafe impl Sync for Bulk {}
+
+/// All the clocks of a device, enabled.
+///
+/// The clocks are disabled and released when this object is dropped.
+///
+/// # Invariants
+///
+/// The clocks of the inner [`Bulk`] are prepared and enabled.
+pub struct EnabledBulk(Bulk);
+
+impl EnabledBulk {
+    /// Disables all the clocks, e.g., for runtime suspend, and gives them back.
+    pub fn disable(self) -> Bulk {
+        let this = core::mem::ManuallyDrop::new(self);
+        // SAFETY: `this` is not dropped, so the clocks are moved out exactly once.
+        let bulk = unsafe { ptr::read(&this.0) };
+        // SAFETY: By the type invariants, the clocks are enabled.
+        unsafe { bindings::clk_bulk_disable_unprepare(bulk.num(), bulk.clks) };
+        bulk
+    }
+}
+
+impl core::ops::Deref for EnabledBulk {
+    type Target = Bulk;
+
+    fn deref(&self) -> &Bulk {
+        &self.0
+    }
+}
+
+impl Drop for EnabledBulk {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, the clocks are enabled.
+        unsafe { bindings::clk_bulk_disable_unprepare(self.0.num(), self.0.clks) };
+    }
+}
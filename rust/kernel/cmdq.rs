@@ -0,0 +1,308 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Command queues of devices that complete commands asynchronously.
+//!
+//! Many devices, e.g., NVMe controllers, SCMI firmware or crypto engines, take commands through a
+//! submission ring and report their completion later, identifying the command by a tag. A
+//! [`CommandQueue`] implements the part of this that is common to all of them: allocating tags,
+//! waiting for the completion with the matching tag, timing out, and failing the commands that
+//! are still in flight when the device is shut down. The device specific part, i.e., writing the
+//! descriptor to the ring, is provided through [`Hardware`].
+
+use crate::{
+    new_condvar, new_spinlock,
+    prelude::*,
+    sync::{
+        lock::{Backend, Guard},
+        CondVar, CondVarTimeoutResult, SpinLock,
+    },
+    time::Jiffies,
+};
+use core::mem;
+
+/// The tag of a command, the index of its slot in the queue.
+pub type Tag = u16;
+
+/// The device specific part of a [`CommandQueue`].
+pub trait Hardware: Send + Sync {
+    /// The descriptor of a command.
+    type Command;
+
+    /// The descriptor of the response to a command.
+    type Response: Send;
+
+    /// Posts `cmd` to the device, e.g., by writing it to the submission ring and ringing the
+    /// doorbell.
+    ///
+    /// The device must report the completion with `tag`, through [`CommandQueue::complete`].
+    fn submit(&self, tag: Tag, cmd: &Self::Command) -> Result;
+
+    /// Asks the device to drop the command with `tag`, after its submitter stopped waiting for
+    /// it.
+    ///
+    /// Returns `true` if the device will not complete the command anymore, so that `tag` can be
+    /// reused right away. Otherwise, `tag` is only reused once the device completes the command,
+    /// and the response is dropped. The default implementation does nothing and returns `false`.
+    fn abort(&self, tag: Tag) -> bool {
+        let _ = tag;
+        false
+    }
+}
+
+/// The state of the command with a given tag.
+enum Slot<R> {
+    /// The tag is not in use.
+    Free,
+    /// The command was submitted and its submitter waits for the response.
+    Pending,
+    /// The device completed the command, the submitter has not picked up the response yet.
+    Done(R),
+    /// The submitter stopped waiting, the device has not completed the command yet.
+    Abandoned,
+    /// The queue was shut down while the command was pending.
+    Cancelled,
+}
+
+struct State<R> {
+    slots: Vec<Slot<R>>,
+    shutdown: bool,
+}
+
+impl<R> State<R> {
+    fn is_busy(&self) -> bool {
+        self.slots
+            .iter()
+            .any(|s| matches!(s, Slot::Pending | Slot::Done(_)))
+    }
+}
+
+/// A queue of commands that a device completes asynchronously.
+///
+/// [`CommandQueue::execute`] submits a command and waits for its response, which is handed over
+/// by [`CommandQueue::complete`], typically from the threaded interrupt handler of the device.
+/// At most `depth` commands are in flight; further submitters wait for a tag to become free.
+///
+/// # Examples
+///
+/// ```
+/// use core::sync::atomic::{AtomicU16, Ordering};
+/// use kernel::{cmdq::{CommandQueue, Hardware, Tag}, prelude::*, time::msecs_to_jiffies};
+///
+/// struct Mailbox {
+///     // Stands in for the doorbell register.
+///     last_tag: AtomicU16,
+/// }
+///
+/// impl Hardware for Mailbox {
+///     type Command = u32;
+///     type Response = u32;
+///
+///     fn submit(&self, tag: Tag, _cmd: &u32) -> Result {
+///         self.last_tag.store(tag, Ordering::Relaxed);
+///         Ok(())
+///     }
+/// }
+///
+/// let q = Box::pin_init(
+///     CommandQueue::new(Mailbox { last_tag: AtomicU16::new(0) }, 4),
+///     GFP_KERNEL,
+/// )?;
+///
+/// // The device does not answer in time.
+/// assert_eq!(q.execute(0x10, msecs_to_jiffies(10)), Err(ETIMEDOUT));
+///
+/// // Its late completion frees the tag.
+/// let tag = q.hardware().last_tag.load(Ordering::Relaxed);
+/// assert_eq!(q.complete(tag, 0), Ok(()));
+/// assert_eq!(q.complete(tag, 0), Err(ENOENT));
+///
+/// q.shutdown(msecs_to_jiffies(10));
+/// assert_eq!(q.execute(0x11, msecs_to_jiffies(10)), Err(ESHUTDOWN));
+/// # Ok::<(), Error>(())
+/// ```
+#[pin_data]
+pub struct CommandQueue<H: Hardware> {
+    hw: H,
+    #[pin]
+    state: SpinLock<State<H::Response>>,
+    #[pin]
+    changed: CondVar,
+}
+
+impl<H: Hardware> CommandQueue<H> {
+    /// Creates a queue of at most `depth` commands in flight, with tags `0..depth`.
+    ///
+    /// Fails with [`EINVAL`] if `depth` is zero or larger than the number of tags.
+    pub fn new(hw: H, depth: usize) -> impl PinInit<Self, Error> {
+        try_pin_init!(Self {
+            hw,
+            state <- new_spinlock!(State {
+                slots: Self::new_slots(depth)?,
+                shutdown: false,
+            }),
+            changed <- new_condvar!(),
+        })
+    }
+
+    fn new_slots(depth: usize) -> Result<Vec<Slot<H::Response>>> {
+        if depth == 0 || depth > usize::from(Tag::MAX) + 1 {
+            return Err(EINVAL);
+        }
+        let mut slots = Vec::with_capacity(depth, GFP_KERNEL)?;
+        for _ in 0..depth {
+            slots.push(Slot::Free, GFP_KERNEL)?;
+        }
+        Ok(slots)
+    }
+
+    /// Returns the device specific part of the queue.
+    pub fn hardware(&self) -> &H {
+        &self.hw
+    }
+
+    /// Waits for a change of the state for at most `remaining` jiffies, and updates `remaining`.
+    ///
+    /// Fails with [`ETIMEDOUT`] once `remaining` is used up, and with [`ERESTARTSYS`] if a signal
+    /// is pending.
+    fn wait<B: Backend>(
+        &self,
+        guard: &mut Guard<'_, State<H::Response>, B>,
+        remaining: &mut Jiffies,
+    ) -> Result {
+        if *remaining == 0 {
+            return Err(ETIMEDOUT);
+        }
+        match self.changed.wait_interruptible_timeout(guard, *remaining) {
+            CondVarTimeoutResult::Timeout => Err(ETIMEDOUT),
+            CondVarTimeoutResult::Woken { jiffies } => {
+                *remaining = jiffies;
+                Ok(())
+            }
+            CondVarTimeoutResult::Signal { .. } => Err(ERESTARTSYS),
+        }
+    }
+
+    /// Submits `cmd` and waits for its response.
+    ///
+    /// `timeout` covers both the wait for a free tag and for the response. Fails with
+    /// [`ETIMEDOUT`] if it expires, with [`ERESTARTSYS`] if a signal is received, with
+    /// [`ESHUTDOWN`] if the queue is shut down before `cmd` is submitted and with [`ECANCELED`]
+    /// if it is shut down while `cmd` is in flight. Errors of [`Hardware::submit`] are returned as
+    /// is.
+    ///
+    /// This must be called in a context that can sleep.
+    pub fn execute(&self, cmd: H::Command, timeout: Jiffies) -> Result<H::Response> {
+        let mut remaining = timeout;
+        let tag = self.alloc_tag(&mut remaining)?;
+
+        if let Err(e) = self.hw.submit(tag, &cmd) {
+            self.state.lock().slots[usize::from(tag)] = Slot::Free;
+            self.changed.notify_all();
+            return Err(e);
+        }
+
+        let mut guard = self.state.lock();
+        loop {
+            let slot = &mut guard.slots[usize::from(tag)];
+            match mem::replace(slot, Slot::Free) {
+                Slot::Done(resp) => {
+                    drop(guard);
+                    self.changed.notify_all();
+                    return Ok(resp);
+                }
+                Slot::Cancelled => {
+                    drop(guard);
+                    self.changed.notify_all();
+                    return Err(ECANCELED);
+                }
+                other => *slot = other,
+            }
+
+            if let Err(e) = self.wait(&mut guard, &mut remaining) {
+                guard.slots[usize::from(tag)] = Slot::Abandoned;
+                drop(guard);
+                if self.hw.abort(tag) {
+                    let mut guard = self.state.lock();
+                    let slot = &mut guard.slots[usize::from(tag)];
+                    // The device may have completed the command before it was aborted.
+                    if matches!(slot, Slot::Abandoned) {
+                        *slot = Slot::Free;
+                    }
+                    drop(guard);
+                    self.changed.notify_all();
+                }
+                return Err(e);
+            }
+        }
+    }
+
+    fn alloc_tag(&self, remaining: &mut Jiffies) -> Result<Tag> {
+        let mut guard = self.state.lock();
+        loop {
+            if guard.shutdown {
+                return Err(ESHUTDOWN);
+            }
+            if let Some(i) = guard.slots.iter().position(|s| matches!(s, Slot::Free)) {
+                guard.slots[i] = Slot::Pending;
+                // `new` limits the number of slots to the number of tags.
+                return Ok(i as Tag);
+            }
+            self.wait(&mut guard, remaining)?;
+        }
+    }
+
+    /// Hands over the response to the command with `tag`, and wakes up its submitter.
+    ///
+    /// If the submitter stopped waiting, the response is dropped and the tag is freed. Fails with
+    /// [`EINVAL`] if `tag` is out of range, and with [`ENOENT`] if no command with `tag` is in
+    /// flight, e.g., because the device reported a completion twice.
+    ///
+    /// This takes a spinlock that is also taken with interrupts enabled, so it must not be called
+    /// from a hard interrupt handler; threaded handlers and work items are fine.
+    pub fn complete(&self, tag: Tag, resp: H::Response) -> Result {
+        let mut guard = self.state.lock();
+        let slot = guard.slots.get_mut(usize::from(tag)).ok_or(EINVAL)?;
+        match slot {
+            Slot::Pending => *slot = Slot::Done(resp),
+            Slot::Abandoned => *slot = Slot::Free,
+            _ => return Err(ENOENT),
+        }
+        drop(guard);
+        self.changed.notify_all();
+        Ok(())
+    }
+
+    /// Shuts the queue down.
+    ///
+    /// New commands fail with [`ESHUTDOWN`] right away. The commands in flight are given up to
+    /// `timeout` to complete, or until a signal is received; the ones still pending after that are
+    /// aborted through [`Hardware::abort`] and fail with [`ECANCELED`].
+    ///
+    /// This must be called in a context that can sleep.
+    pub fn shutdown(&self, timeout: Jiffies) {
+        let mut remaining = timeout;
+        let mut guard = self.state.lock();
+        guard.shutdown = true;
+        self.changed.notify_all();
+        while guard.is_busy() {
+            if self.wait(&mut guard, &mut remaining).is_err() {
+                break;
+            }
+        }
+        let depth = guard.slots.len();
+        drop(guard);
+
+        for i in 0..depth {
+            let mut guard = self.state.lock();
+            let slot = &mut guard.slots[i];
+            if matches!(slot, Slot::Pending) {
+                *slot = Slot::Cancelled;
+                drop(guard);
+                // `new` limits the number of slots to the number of tags. The tag is never reused
+                // after shutdown, so whether the device still completes the command is irrelevant.
+                self.hw.abort(i as Tag);
+            }
+        }
+        self.changed.notify_all();
+    }
+}
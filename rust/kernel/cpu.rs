@@ -0,0 +1,84 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! CPUs.
+//!
+//! Per-CPU data must be accessed on the CPU it belongs to, so its users must stay on that CPU.
+//! Disabling preemption does that, but on `PREEMPT_RT` the spinlocks usually held around such
+//! accesses are sleeping locks, which cannot be taken with preemption disabled. A
+//! [`MigrationGuard`] instead keeps the current task on its CPU while leaving it preemptible, so
+//! other tasks may run on the CPU in between; per-CPU data must then be protected by a lock as
+//! well.
+//!
+//! C header: [`include/linux/preempt.h`](srctree/include/linux/preempt.h)
+
+use crate::bindings;
+use core::marker::PhantomData;
+
+/// Returns the id of the CPU the current task runs on.
+///
+/// The task may be migrated to another CPU as soon as this returns, so the id is only a hint,
+/// e.g., to pick a queue with good locality. Use [`MigrationGuard::cpu`] to get an id that stays
+/// valid.
+#[inline]
+pub fn current_id() -> u32 {
+    // SAFETY: `raw_smp_processor_id` can be called from any context.
+    unsafe { bindings::raw_smp_processor_id() as u32 }
+}
+
+/// A guard that keeps the current task on its CPU, until the guard is dropped.
+///
+/// The guard can be nested, and is cheaper than disabling preemption on `PREEMPT_RT`, but the
+/// task can still be preempted, and sleep.
+///
+/// # Invariants
+///
+/// Migration of the current task is disabled for as long as the guard exists.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::cpu::MigrationGuard;
+///
+/// let guard = MigrationGuard::new();
+/// let cpu = guard.cpu();
+/// // Access the data of `cpu`, under the lock that protects it.
+/// assert_eq!(guard.cpu(), cpu);
+/// ```
+#[must_use = "migration is enabled again immediately when the guard is unused"]
+pub struct MigrationGuard {
+    _not_send: PhantomData<*mut ()>,
+}
+
+impl MigrationGuard {
+    /// Disables migration of the current task.
+    pub fn new() -> Self {
+        // SAFETY: `migrate_disable` can be called from any context, and is balanced in `drop`.
+        unsafe { bindings::migrate_disable() };
+        // INVARIANT: Migration was just disabled.
+        Self {
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Returns the id of the CPU the current task runs on, which does not change while the guard
+    /// exists.
+    pub fn cpu(&self) -> u32 {
+        // SAFETY: By the type invariants, migration is disabled, so the current task stays on the
+        // CPU.
+        unsafe { bindings::smp_processor_id() as u32 }
+    }
+}
+
+impl Default for MigrationGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for MigrationGuard {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, migration was disabled by this guard, on this task since
+        // the guard is not `Send`.
+        unsafe { bindings::migrate_enable() };
+    }
+}
@@ -0,0 +1,256 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! CPU masks.
+//!
+//! C header: [`include/linux/cpumask.h`](srctree/include/linux/cpumask.h)
+
+use crate::{
+    alloc::{box_ext::BoxExt, AllocError, Flags},
+    bindings,
+    error::{code::EINVAL, Result},
+    types::Opaque,
+};
+use alloc::boxed::Box;
+use core::ops::{Deref, DerefMut};
+
+/// Returns the number of CPU ids that are usable in this system.
+///
+/// CPU ids are always below this value.
+#[inline]
+pub fn nr_cpu_ids() -> u32 {
+    // SAFETY: `nr_cpu_ids` is only written during early boot, before any Rust code runs.
+    unsafe { bindings::nr_cpu_ids }
+}
+
+/// Returns the `i`-th CPU, preferring CPUs local to the NUMA node `node`.
+///
+/// This is used to spread per-queue resources across CPUs: queue `i` of a device attached to
+/// `node` is bound to `local_spread(i, node)`. Pass [`bindings::NUMA_NO_NODE`] when the node is not
+/// known.
+#[inline]
+pub fn local_spread(i: u32, node: i32) -> u32 {
+    // SAFETY: FFI call without safety requirements.
+    unsafe { bindings::cpumask_local_spread(i, node) }
+}
+
+/// A CPU mask.
+///
+/// This is a bitmap that has one bit for each possible CPU in the system.
+///
+/// # Invariants
+///
+/// The inner `cpumask` is always valid.
+#[repr(transparent)]
+pub struct Cpumask(Opaque<bindings::cpumask>);
+
+impl Cpumask {
+    /// Creates a reference to an existing `struct cpumask`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `ptr` is valid for the lifetime `'a` and that no other
+    /// references to it are used to modify it while the returned reference exists.
+    pub unsafe fn from_raw<'a>(ptr: *const bindings::cpumask) -> &'a Self {
+        // SAFETY: `Cpumask` is `repr(transparent)`, so the cast is fine. The caller guarantees the
+        // validity of the pointer for the lifetime `'a`.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Creates a mutable reference to an existing `struct cpumask`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `ptr` is valid for the lifetime `'a` and that it is not
+    /// accessed through any other reference while the returned reference exists.
+    pub unsafe fn from_raw_mut<'a>(ptr: *mut bindings::cpumask) -> &'a mut Self {
+        // SAFETY: `Cpumask` is `repr(transparent)`, so the cast is fine. The caller guarantees the
+        // validity and exclusivity of the pointer for the lifetime `'a`.
+        unsafe { &mut *ptr.cast() }
+    }
+
+    /// Returns the mask that only contains `cpu`.
+    ///
+    /// The returned mask is statically allocated, so it can be used where the C side keeps a
+    /// reference to the mask, for example as an interrupt affinity hint.
+    ///
+    /// Returns [`None`] if `cpu` is not a valid CPU id.
+    pub fn of(cpu: u32) -> Option<&'static Self> {
+        if cpu >= nr_cpu_ids() {
+            return None;
+        }
+        // SAFETY: `cpu` is a valid CPU id, so `cpumask_of` returns a pointer to a statically
+        // allocated mask that is never modified.
+        Some(unsafe { Self::from_raw(bindings::cpumask_of(cpu)) })
+    }
+
+    /// Returns the mask of online CPUs.
+    ///
+    /// The mask changes as CPUs go online and offline, so the result is only a snapshot unless CPU
+    /// hotplug is prevented by the caller.
+    pub fn online() -> &'static Self {
+        // SAFETY: `__cpu_online_mask` is statically allocated and valid for the lifetime of the
+        // kernel. It is only modified by atomic bit operations.
+        unsafe { Self::from_raw(core::ptr::addr_of!(bindings::__cpu_online_mask)) }
+    }
+
+    /// Returns a raw pointer to the underlying `struct cpumask`.
+    pub fn as_raw(&self) -> *mut bindings::cpumask {
+        self.0.get()
+    }
+
+    /// Adds `cpu` to the mask.
+    ///
+    /// Fails with [`EINVAL`] if `cpu` is not a valid CPU id.
+    pub fn set(&mut self, cpu: u32) -> Result {
+        if cpu >= nr_cpu_ids() {
+            return Err(EINVAL);
+        }
+        // SAFETY: By the type invariant, `self.as_raw()` is a valid mask and `cpu` is in range.
+        unsafe { bindings::cpumask_set_cpu(cpu, self.as_raw()) };
+        Ok(())
+    }
+
+    /// Removes `cpu` from the mask.
+    ///
+    /// Fails with [`EINVAL`] if `cpu` is not a valid CPU id.
+    pub fn clear(&mut self, cpu: u32) -> Result {
+        if cpu >= nr_cpu_ids() {
+            return Err(EINVAL);
+        }
+        // SAFETY: By the type invariant, `self.as_raw()` is a valid mask and `cpu` is in range.
+        unsafe { bindings::cpumask_clear_cpu(cpu, self.as_raw()) };
+        Ok(())
+    }
+
+    /// Checks whether `cpu` is in the mask.
+    pub fn test(&self, cpu: u32) -> bool {
+        if cpu >= nr_cpu_ids() {
+            return false;
+        }
+        // SAFETY: By the type invariant, `self.as_raw()` is a valid mask and `cpu` is in range.
+        unsafe { bindings::cpumask_test_cpu(cpu as _, self.as_raw()) }
+    }
+
+    /// Adds all CPUs to the mask.
+    pub fn set_all(&mut self) {
+        // SAFETY: By the type invariant, `self.as_raw()` is a valid mask.
+        unsafe { bindings::cpumask_setall(self.as_raw()) };
+    }
+
+    /// Removes all CPUs from the mask.
+    pub fn clear_all(&mut self) {
+        // SAFETY: By the type invariant, `self.as_raw()` is a valid mask.
+        unsafe { bindings::cpumask_clear(self.as_raw()) };
+    }
+
+    /// Copies the contents of `other` into `self`.
+    pub fn copy_from(&mut self, other: &Cpumask) {
+        // SAFETY: By the type invariant, both pointers are valid masks.
+        unsafe { bindings::cpumask_copy(self.as_raw(), other.as_raw()) };
+    }
+
+    /// Returns the number of CPUs in the mask.
+    pub fn weight(&self) -> u32 {
+        // SAFETY: By the type invariant, `self.as_raw()` is a valid mask.
+        unsafe { bindings::cpumask_weight(self.as_raw()) }
+    }
+
+    /// Checks whether the mask contains no CPUs.
+    pub fn is_empty(&self) -> bool {
+        // SAFETY: By the type invariant, `self.as_raw()` is a valid mask.
+        unsafe { bindings::cpumask_empty(self.as_raw()) }
+    }
+
+    /// Returns an iterator over the CPUs in the mask.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            mask: self,
+            next: 0,
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Cpumask {
+    type Item = u32;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// An iterator over the CPUs in a [`Cpumask`].
+///
+/// Equivalent to the C `for_each_cpu` macro.
+pub struct Iter<'a> {
+    mask: &'a Cpumask,
+    next: u32,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        if self.next >= nr_cpu_ids() {
+            return None;
+        }
+
+        // SAFETY: By the type invariant of `Cpumask`, the mask is valid. `cpumask_next` expects
+        // the CPU *before* the first one to check, which may be -1.
+        let cpu = unsafe { bindings::cpumask_next(self.next as i32 - 1, self.mask.as_raw()) };
+        if cpu >= nr_cpu_ids() {
+            self.next = nr_cpu_ids();
+            return None;
+        }
+
+        self.next = cpu + 1;
+        Some(cpu)
+    }
+}
+
+/// An owned, heap-allocated [`Cpumask`].
+///
+/// Equivalent to the C `cpumask_var_t` when `CONFIG_CPUMASK_OFFSTACK` is enabled; it is always
+/// allocated so that large masks do not end up on the stack.
+pub struct CpumaskVar {
+    mask: Box<Cpumask>,
+}
+
+impl CpumaskVar {
+    /// Allocates a new, empty mask.
+    pub fn new(flags: Flags) -> Result<Self, AllocError> {
+        let mask = Box::new(Cpumask(Opaque::uninit()), flags)?;
+        // SAFETY: `mask` points to a properly aligned allocation large enough for a
+        // `struct cpumask`, and `cpumask_clear` fully initialises it.
+        unsafe { bindings::cpumask_clear(mask.as_raw()) };
+        // INVARIANT: The mask was initialised above.
+        Ok(Self { mask })
+    }
+
+    /// Allocates a new mask with the same contents as `other`.
+    pub fn try_clone(other: &Cpumask, flags: Flags) -> Result<Self, AllocError> {
+        let mut new = Self::new(flags)?;
+        new.copy_from(other);
+        Ok(new)
+    }
+}
+
+impl Deref for CpumaskVar {
+    type Target = Cpumask;
+
+    fn deref(&self) -> &Cpumask {
+        &self.mask
+    }
+}
+
+impl DerefMut for CpumaskVar {
+    fn deref_mut(&mut self) -> &mut Cpumask {
+        &mut self.mask
+    }
+}
+
+// SAFETY: A `Cpumask` is just a bitmap, it can be accessed from any thread.
+unsafe impl Send for Cpumask {}
+
+// SAFETY: All mutation goes through `&mut Cpumask`, so shared references only allow reads.
+unsafe impl Sync for Cpumask {}
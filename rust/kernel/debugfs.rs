@@ -0,0 +1,349 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Debug filesystem.
+//!
+//! Files in debugfs are meant for developers, and may change or go away at any time. Errors
+//! creating them are therefore not reported to drivers, which should work the same without them.
+//!
+//! Statistics that are scraped at high frequency are exported with [`StatsBlob`] as a binary
+//! image of the statistics structure, along with a text file describing its layout, so that
+//! reading them costs a copy instead of formatting every field.
+//!
+//! C header: [`include/linux/debugfs.h`](srctree/include/linux/debugfs.h)
+
+use crate::{bindings, prelude::*, str::CStr, str::CString};
+use core::{ffi::c_void, marker::PhantomData, ptr};
+
+/// A directory in debugfs, which is removed, with everything in it, when dropped.
+///
+/// # Invariants
+///
+/// `dentry` was returned by `debugfs_create_dir`, and is either a valid directory or an error
+/// pointer, which the debugfs functions accept and ignore.
+pub struct Dir {
+    dentry: *mut bindings::dentry,
+}
+
+impl Dir {
+    /// Creates a directory named `name` in `parent`, or in the root of debugfs.
+    pub fn new(name: &CStr, parent: Option<&Dir>) -> Self {
+        // SAFETY: `name` is a valid C string, and the parent is either null or a directory.
+        let dentry =
+            unsafe { bindings::debugfs_create_dir(name.as_char_ptr(), parent_raw(parent)) };
+        // INVARIANT: `dentry` was just returned by `debugfs_create_dir`.
+        Self { dentry }
+    }
+}
+
+impl Drop for Dir {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, `dentry` is a directory or an error pointer.
+        unsafe { bindings::debugfs_remove(self.dentry) };
+    }
+}
+
+// SAFETY: Debugfs directories can be removed from any thread.
+unsafe impl Send for Dir {}
+
+// SAFETY: `Dir` has no methods that mutate it through a shared reference.
+unsafe impl Sync for Dir {}
+
+pub(crate) fn parent_raw(parent: Option<&Dir>) -> *mut bindings::dentry {
+    parent.map_or(ptr::null_mut(), |p| p.dentry)
+}
+
+/// The type of a field of a [`Stats`] structure, as named in its layout descriptor.
+///
+/// # Safety
+///
+/// Implementers must be plain integers, or atomic versions of them, without padding.
+pub unsafe trait StatField {
+    /// The name of the type in the layout descriptor, e.g., `u64`.
+    const NAME: &'static str;
+}
+
+macro_rules! impl_stat_field {
+    ($($ty:ty => $name:literal),* $(,)?) => {
+        $(
+            // SAFETY: The type is an integer, or an atomic version of one.
+            unsafe impl StatField for $ty {
+                const NAME: &'static str = $name;
+            }
+        )*
+    };
+}
+
+impl_stat_field! {
+    u32 => "u32",
+    u64 => "u64",
+    i32 => "s32",
+    i64 => "s64",
+    core::sync::atomic::AtomicU32 => "u32",
+    core::sync::atomic::AtomicU64 => "u64",
+    core::sync::atomic::AtomicI32 => "s32",
+    core::sync::atomic::AtomicI64 => "s64",
+}
+
+/// A field of a [`Stats`] structure.
+#[derive(Clone, Copy, Debug)]
+pub struct Field {
+    /// The name of the field.
+    pub name: &'static str,
+
+    /// The type of the field, see [`StatField::NAME`].
+    pub ty: &'static str,
+
+    /// The offset of the field from the start of the structure.
+    pub offset: usize,
+
+    /// The size of the field.
+    pub size: usize,
+}
+
+/// A statistics structure that can be exported with [`StatsBlob`].
+///
+/// This is implemented with the [`debugfs_stats`] macro, which also generates the layout.
+///
+/// # Safety
+///
+/// Implementers must be `#[repr(C)]` without padding, which would be exported to userspace
+/// uninitialised, and [`Stats::FIELDS`] must describe all of their fields.
+///
+/// [`debugfs_stats`]: crate::debugfs_stats
+pub unsafe trait Stats: Sync {
+    /// The version of the layout, which is increased whenever it changes.
+    const VERSION: u32;
+
+    /// The fields of the structure, in order.
+    const FIELDS: &'static [Field];
+}
+
+/// Defines a statistics structure that can be exported with [`StatsBlob`].
+///
+/// The fields must be types that implement [`StatField`], usually atomics that the driver
+/// updates through a shared reference. They must not need any padding, e.g., by being ordered
+/// from the largest to the smallest, which is checked at compile time.
+///
+/// # Examples
+///
+/// ```
+/// use core::sync::atomic::{AtomicU64, Ordering};
+/// use kernel::{c_str, debugfs::{Dir, StatsBlob}, prelude::*};
+///
+/// kernel::debugfs_stats! {
+///     /// The statistics of a receive queue.
+///     pub struct RxStats: 1 {
+///         packets: AtomicU64,
+///         bytes: AtomicU64,
+///     }
+/// }
+///
+/// let dir = Dir::new(c_str!("my_driver"), None);
+/// let rx = StatsBlob::new(
+///     c_str!("rx"),
+///     Some(&dir),
+///     RxStats { packets: AtomicU64::new(0), bytes: AtomicU64::new(0) },
+/// )?;
+/// rx.stats().packets.fetch_add(1, Ordering::Relaxed);
+/// rx.stats().bytes.fetch_add(1500, Ordering::Relaxed);
+/// # Ok::<(), Error>(())
+/// ```
+#[macro_export]
+macro_rules! debugfs_stats {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident: $version:literal {
+            $($(#[$fmeta:meta])* $field:ident: $ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[repr(C)]
+        $vis struct $name {
+            $($(#[$fmeta])* $vis $field: $ty),*
+        }
+
+        const _: () = ::core::assert!(
+            0 $(+ ::core::mem::size_of::<$ty>())* == ::core::mem::size_of::<$name>(),
+            "the fields of the statistics must not be padded"
+        );
+
+        // SAFETY: The structure is `#[repr(C)]`, has no padding as checked above, and `FIELDS`
+        // lists all its fields.
+        unsafe impl $crate::debugfs::Stats for $name {
+            const VERSION: u32 = $version;
+            const FIELDS: &'static [$crate::debugfs::Field] = &[$(
+                $crate::debugfs::Field {
+                    name: ::core::stringify!($field),
+                    ty: <$ty as $crate::debugfs::StatField>::NAME,
+                    offset: ::core::mem::offset_of!($name, $field),
+                    size: ::core::mem::size_of::<$ty>(),
+                }
+            ),*];
+        }
+    };
+}
+
+/// The magic number at the start of a statistics blob, `"STAT"` in ASCII.
+pub const STATS_MAGIC: u32 = u32::from_be_bytes(*b"STAT");
+
+/// The header of a statistics blob.
+#[repr(C)]
+struct Header {
+    magic: u32,
+    version: u32,
+    size: u32,
+    fields: u32,
+}
+
+#[repr(C)]
+struct Blob<T> {
+    header: Header,
+    stats: T,
+}
+
+struct Inner<T> {
+    blob: Blob<T>,
+    layout: Vec<u8>,
+    blob_wrapper: bindings::debugfs_blob_wrapper,
+    layout_wrapper: bindings::debugfs_blob_wrapper,
+}
+
+/// A statistics structure exported through debugfs.
+///
+/// Two read-only files are created: `name` holds a binary image of the structure after a header,
+/// in native endianness, and `name.layout` a text description of it, one line for the header and
+/// one for each field:
+///
+/// ```text
+/// version 1 size 16 endian little
+/// packets u64 0 8
+/// bytes u64 8 8
+/// ```
+///
+/// The fields are given with their type, and their offset and size in bytes from the end of the
+/// 16-byte header, which holds [`STATS_MAGIC`], the version, the size of the structure and its
+/// number of fields, as `u32` values. Readers copy the current values, without synchronisation
+/// with updates, so fields that span several words may be read torn on 32-bit architectures.
+///
+/// The files are removed when this object is dropped, which must happen before the parent
+/// directory is removed, hence the lifetime `'a` of the parent.
+///
+/// # Invariants
+///
+/// The wrappers in `inner` point to the blob and the layout in `inner`, which do not move while
+/// the files exist. `blob` and `layout` were returned by `debugfs_create_blob`, in a parent
+/// directory that outlives `'a`.
+pub struct StatsBlob<'a, T: Stats> {
+    inner: Box<Inner<T>>,
+    blob: *mut bindings::dentry,
+    layout: *mut bindings::dentry,
+    _parent: PhantomData<&'a Dir>,
+}
+
+impl<'a, T: Stats> StatsBlob<'a, T> {
+    /// Exports `stats` as the file `name`, and its layout as `name.layout`, in `parent`, or in
+    /// the root of debugfs.
+    pub fn new(name: &CStr, parent: Option<&'a Dir>, stats: T) -> Result<Self> {
+        let size = core::mem::size_of::<T>();
+        // There must not be padding after the header either, which needs alignments above 16.
+        crate::build_assert!(
+            core::mem::size_of::<Blob<T>>() == core::mem::size_of::<Header>() + size
+        );
+        let mut layout = Vec::new();
+        let endian = if cfg!(target_endian = "little") {
+            "little"
+        } else {
+            "big"
+        };
+        let line = CString::try_from_fmt(fmt!(
+            "version {} size {} endian {}\n",
+            T::VERSION,
+            size,
+            endian
+        ))?;
+        layout.extend_from_slice(line.as_bytes(), GFP_KERNEL)?;
+        for f in T::FIELDS {
+            let line =
+                CString::try_from_fmt(fmt!("{} {} {} {}\n", f.name, f.ty, f.offset, f.size))?;
+            layout.extend_from_slice(line.as_bytes(), GFP_KERNEL)?;
+        }
+
+        let mut inner = Box::new(
+            Inner {
+                blob: Blob {
+                    header: Header {
+                        magic: STATS_MAGIC,
+                        version: T::VERSION,
+                        size: size as u32,
+                        fields: T::FIELDS.len() as u32,
+                    },
+                    stats,
+                },
+                layout,
+                // SAFETY: All zeroes is a valid value of the wrappers, they are set below.
+                blob_wrapper: unsafe { core::mem::zeroed() },
+                // SAFETY: Same as above.
+                layout_wrapper: unsafe { core::mem::zeroed() },
+            },
+            GFP_KERNEL,
+        )?;
+        inner.blob_wrapper.data = ptr::addr_of!(inner.blob) as *mut c_void;
+        inner.blob_wrapper.size = core::mem::size_of::<Blob<T>>() as _;
+        inner.layout_wrapper.data = inner.layout.as_ptr() as *mut c_void;
+        inner.layout_wrapper.size = inner.layout.len() as _;
+
+        let layout_name = CString::try_from_fmt(fmt!("{}.layout", name))?;
+        let parent = parent_raw(parent);
+        // SAFETY: The names are valid C strings, the parent is either null or a directory, and
+        // the wrappers point to memory that lives until the files are removed in `drop`. Blobs
+        // are read-only, so the memory is only read by debugfs.
+        let (blob, layout) = unsafe {
+            (
+                bindings::debugfs_create_blob(
+                    name.as_char_ptr(),
+                    0o400,
+                    parent,
+                    &mut inner.blob_wrapper,
+                ),
+                bindings::debugfs_create_blob(
+                    layout_name.as_char_ptr(),
+                    0o400,
+                    parent,
+                    &mut inner.layout_wrapper,
+                ),
+            )
+        };
+        // INVARIANT: The wrappers point into `inner`, which is boxed, and the files were just
+        // created in `parent`, which is borrowed for `'a`.
+        Ok(Self {
+            inner,
+            blob,
+            layout,
+            _parent: PhantomData,
+        })
+    }
+
+    /// Returns the exported statistics, e.g., to update them.
+    pub fn stats(&self) -> &T {
+        &self.inner.blob.stats
+    }
+}
+
+impl<T: Stats> Drop for StatsBlob<'_, T> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, the files were created by `debugfs_create_blob`, and
+        // their parent was not removed yet, so they are still valid. Removing them waits for
+        // readers, so the memory can be freed afterwards.
+        unsafe {
+            bindings::debugfs_remove(self.blob);
+            bindings::debugfs_remove(self.layout);
+        }
+    }
+}
+
+// SAFETY: The files can be removed from any thread, and the statistics are `Sync`, as required
+// by `Stats`, so they can be accessed from any thread too.
+unsafe impl<T: Stats + Send> Send for StatsBlob<'_, T> {}
+
+// SAFETY: Only shared access to the statistics is given out, which `Stats` requires to be `Sync`.
+unsafe impl<T: Stats> Sync for StatsBlob<'_, T> {}
@@ -0,0 +1,249 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Freeing objects from atomic context.
+//!
+//! Objects are often released in interrupt handlers or under spinlocks, e.g., when a device
+//! completes a request, while their destructors must sleep, e.g., to unmap DMA buffers or to
+//! release a mutex-protected resource, or are too slow to run with interrupts disabled. A
+//! [`Drainer`] takes such objects from any context, without allocating, and drops them later from
+//! a work item, in batches so that a burst of completions does not hog a worker.
+//!
+//! C header: [`include/linux/llist.h`](srctree/include/linux/llist.h)
+
+use crate::{
+    alloc::{AllocError, Flags},
+    bindings, c_str,
+    prelude::*,
+    types::Opaque,
+};
+use core::{
+    cell::UnsafeCell,
+    marker::PhantomPinned,
+    ops::{Deref, DerefMut},
+    ptr,
+};
+
+/// An object that can be handed to a [`Drainer`], along with the list node it needs for that.
+///
+/// The node is allocated with the object, so that handing it over does not allocate.
+pub struct Deferred<T> {
+    node: Opaque<bindings::llist_node>,
+    value: T,
+}
+
+impl<T> Deferred<T> {
+    /// Allocates `value` so that it can be handed to a [`Drainer`].
+    pub fn new(value: T, flags: Flags) -> Result<Box<Self>, AllocError> {
+        Box::new(
+            Self {
+                // SAFETY: All zeroes is a valid value of a list node, which is not on any list.
+                node: Opaque::new(unsafe { core::mem::zeroed() }),
+                value,
+            },
+            flags,
+        )
+    }
+
+    /// Returns the object and frees the node.
+    pub fn into_inner(self: Box<Self>) -> T {
+        self.value
+    }
+}
+
+// SAFETY: The node is only used by the drainer the object is handed to, which owns it then.
+unsafe impl<T: Send> Send for Deferred<T> {}
+
+// SAFETY: Shared references only give access to the object.
+unsafe impl<T: Sync> Sync for Deferred<T> {}
+
+impl<T> Deref for Deferred<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Deferred<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+/// Drops objects handed to it from any context, from a work item of the system workqueue.
+///
+/// Each run of the work item drops at most `budget` objects, and queues it again if more are
+/// left. Objects are dropped in the order they were handed over. The objects that are left are
+/// dropped by the destructor of the drainer, which must therefore be called in a context that can
+/// sleep.
+///
+/// # Invariants
+///
+/// `list` holds the nodes of leaked `Box<Deferred<T>>`, as does `pending`, which is only
+/// accessed by the work item, or once it is cancelled. The work item is initialised with
+/// [`Drainer::work_callback`], and is cancelled before the drainer is freed.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{
+///     deferred_free::{Deferred, Drainer},
+///     prelude::*,
+/// };
+///
+/// struct Request {
+///     tag: u32,
+/// }
+///
+/// impl Drop for Request {
+///     fn drop(&mut self) {
+///         // Unmap the buffers of the request, which may sleep.
+///         pr_debug!("freeing request {}\n", self.tag);
+///     }
+/// }
+///
+/// /// Called from the interrupt handler of the device.
+/// fn complete(drainer: &Drainer<Request>, req: Box<Deferred<Request>>) {
+///     drainer.defer(req);
+/// }
+///
+/// let drainer = Box::pin_init(Drainer::new(64), GFP_KERNEL)?;
+/// let req = Deferred::new(Request { tag: 1 }, GFP_KERNEL)?;
+/// complete(&drainer, req);
+/// # Ok::<(), Error>(())
+/// ```
+#[pin_data(PinnedDrop)]
+pub struct Drainer<T: Send> {
+    list: Opaque<bindings::llist_head>,
+    pending: UnsafeCell<*mut bindings::llist_node>,
+    budget: usize,
+    #[pin]
+    work: Opaque<bindings::work_struct>,
+    #[pin]
+    _pin: PhantomPinned,
+    _p: core::marker::PhantomData<T>,
+}
+
+impl<T: Send> Drainer<T> {
+    /// Creates a drainer that drops at most `budget` objects per run of its work item.
+    pub fn new(budget: usize) -> impl PinInit<Self> {
+        pin_init!(Self {
+            // SAFETY: All zeroes is an empty list.
+            list: Opaque::new(unsafe { core::mem::zeroed() }),
+            pending: UnsafeCell::new(ptr::null_mut()),
+            budget: budget.max(1),
+            work <- Opaque::ffi_init(|work: *mut bindings::work_struct| {
+                // SAFETY: `work` is valid for writes, and the work item is cancelled in
+                // `PinnedDrop`, before it is freed.
+                unsafe {
+                    bindings::init_work_with_key(
+                        work,
+                        Some(Self::work_callback),
+                        false,
+                        c_str!("deferred_free").as_char_ptr(),
+                        crate::static_lock_class!().as_ptr(),
+                    )
+                }
+            }),
+            _pin: PhantomPinned,
+            _p: core::marker::PhantomData,
+        })
+    }
+
+    /// Hands `obj` over, to be dropped from the work item.
+    ///
+    /// This can be called from any context, including interrupt handlers.
+    pub fn defer(&self, obj: Box<Deferred<T>>) {
+        let node = Box::into_raw(obj);
+        // SAFETY: The node belongs to the leaked box, which is only reclaimed by the work item.
+        // `llist_add` can be called concurrently with itself and with `llist_del_all`.
+        let first = unsafe { bindings::llist_add((*node).node.get(), self.list.get()) };
+        if first {
+            // SAFETY: The work item is initialised, and it is cancelled before it is freed.
+            unsafe {
+                bindings::queue_work_on(
+                    bindings::wq_misc_consts_WORK_CPU_UNBOUND as _,
+                    bindings::system_wq,
+                    self.work.get(),
+                )
+            };
+        }
+    }
+
+    /// Drops the objects of the chain that starts at `*head`, at most `budget` of them, and
+    /// returns whether objects are left.
+    ///
+    /// # Safety
+    ///
+    /// The chain must be made of the nodes of leaked `Box<Deferred<T>>`, and not be accessed
+    /// concurrently.
+    unsafe fn drop_chain(head: &mut *mut bindings::llist_node, budget: usize) -> bool {
+        for _ in 0..budget {
+            if head.is_null() {
+                return false;
+            }
+            let node = *head;
+            // SAFETY: By the safety requirements, `node` is the node of a leaked
+            // `Box<Deferred<T>>`, which is reclaimed here.
+            unsafe {
+                *head = (*node).next;
+                drop(Box::from_raw(
+                    crate::container_of!(node, Deferred<T>, node).cast_mut(),
+                ));
+            }
+        }
+        !head.is_null()
+    }
+
+    unsafe extern "C" fn work_callback(work: *mut bindings::work_struct) {
+        // SAFETY: The work item is the `work` field of a live `Drainer<T>`, since it is cancelled
+        // before the drainer is freed.
+        let this = unsafe { &*crate::container_of!(work, Self, work) };
+        // SAFETY: A work item never runs concurrently with itself, so this is the only access to
+        // `pending`.
+        let pending = unsafe { &mut *this.pending.get() };
+        if pending.is_null() {
+            // SAFETY: `list` is valid. The nodes are the oldest last, so they are reversed.
+            *pending =
+                unsafe { bindings::llist_reverse_order(bindings::llist_del_all(this.list.get())) };
+        }
+        // SAFETY: By the type invariants, `pending` is a chain of leaked boxes.
+        let left = unsafe { Self::drop_chain(pending, this.budget) };
+        // SAFETY: `list` is valid.
+        if left || !unsafe { bindings::llist_empty(this.list.get()) } {
+            // SAFETY: The work item is initialised, and it is cancelled before it is freed.
+            unsafe {
+                bindings::queue_work_on(
+                    bindings::wq_misc_consts_WORK_CPU_UNBOUND as _,
+                    bindings::system_wq,
+                    work,
+                )
+            };
+        }
+    }
+}
+
+#[pinned_drop]
+impl<T: Send> PinnedDrop for Drainer<T> {
+    fn drop(self: Pin<&mut Self>) {
+        // SAFETY: By the type invariants, the work item is initialised. Once it is cancelled, it
+        // is neither queued nor running, and `defer` cannot queue it anymore since `self` is being
+        // dropped.
+        unsafe { bindings::cancel_work_sync(self.work.get()) };
+        // SAFETY: The work item is cancelled, so nothing else accesses the chains anymore, and
+        // by the type invariants, they are made of leaked boxes.
+        unsafe {
+            Self::drop_chain(&mut *self.pending.get(), usize::MAX);
+            let mut rest = bindings::llist_reverse_order(bindings::llist_del_all(self.list.get()));
+            Self::drop_chain(&mut rest, usize::MAX);
+        }
+    }
+}
+
+// SAFETY: The objects are dropped by the work item, which may run on any thread, so `T` must be
+// `Send`. The list can be used and the work item cancelled from any thread.
+unsafe impl<T: Send> Send for Drainer<T> {}
+
+// SAFETY: `defer` can be called concurrently, and `pending` is only accessed by the work item or
+// once it is cancelled.
+unsafe impl<T: Send> Sync for Drainer<T> {}
@@ -7,18 +7,21 @@
 use macros::pin_data;
 
 use crate::{
-    alloc::flags::*,
+    alloc::{box_ext::BoxExt, flags::*},
     bindings, c_str,
-    error::Result,
+    error::{code::*, to_result, Error, Result},
     init::InPlaceInit,
     init::PinInit,
     pin_init,
     revocable::{Revocable, RevocableGuard},
     str::CStr,
     sync::{LockClassKey, RevocableMutex, RevocableMutexGuard, UniqueArc},
-    types::{ARef, Opaque},
+    types::{ARef, ForeignOwnable, Opaque},
 };
+use alloc::boxed::Box;
 use core::{
+    any::TypeId,
+    ffi::c_void,
     fmt,
     ops::{Deref, DerefMut},
     pin::Pin,
@@ -40,6 +43,26 @@ pub unsafe trait RawDevice {
     /// Returns the raw `struct device` related to `self`.
     fn raw_device(&self) -> *mut bindings::device;
 
+    /// Returns the name of the device, e.g., `fe201000.serial`.
+    fn name(&self) -> &CStr {
+        // SAFETY: `self.raw_device` is valid because `self` is valid. The name is a valid C
+        // string that lives as long as the device, since devices are never renamed by the safety
+        // requirements of `RawDevice`.
+        unsafe { CStr::from_char_ptr(bindings::dev_name(self.raw_device())) }
+    }
+
+    /// Returns the name of the bus of the device, e.g., `platform`, if it is on a bus.
+    fn bus_name(&self) -> Option<&CStr> {
+        // SAFETY: `self.raw_device` is valid because `self` is valid.
+        let bus = unsafe { (*self.raw_device()).bus };
+        if bus.is_null() {
+            return None;
+        }
+        // SAFETY: The bus of a device is static, or lives at least as long as its devices, and
+        // its name is a valid C string.
+        Some(unsafe { CStr::from_char_ptr((*bus).name) })
+    }
+
     /// Prints an emergency-level message (level 0) prefixed with device information.
     ///
     /// More details are available from [`dev_emerg`].
@@ -122,6 +145,374 @@ fn pr_dbg(&self, args: fmt::Arguments<'_>) {
         }
     }
 
+    /// Returns the driver data of the device if it was stored as a `D`.
+    ///
+    /// Bus abstractions store the data returned by a driver's `probe` tagged with its type, so
+    /// callbacks that only receive a `struct device *` can recover it, e.g., by calling
+    /// `dev.drvdata::<Arc<MyData>>()`. It returns [`None`] if no driver data is set or if it is
+    /// of a different type.
+    ///
+    /// # Safety
+    ///
+    /// Callers must ensure that the device is bound to a driver registered through one of the
+    /// Rust bus abstractions and that it remains bound while the returned value is alive. This is
+    /// the case, for example, within driver callbacks that the driver core serialises against
+    /// unbinding.
+    unsafe fn drvdata<D: ForeignOwnable + 'static>(&self) -> Option<D::Borrowed<'_>> {
+        // SAFETY: `self.raw_device()` is valid by the safety requirements of `RawDevice`.
+        let ptr = unsafe { bindings::dev_get_drvdata(self.raw_device()) }.cast::<DrvData>();
+        if ptr.is_null() {
+            return None;
+        }
+
+        // SAFETY: By the safety requirements, the driver data was set by `set_drvdata`, so a
+        // non-null pointer points to a valid `DrvData`, which remains alive while bound.
+        let drvdata = unsafe { &*ptr };
+        if drvdata.type_id != TypeId::of::<D>() {
+            return None;
+        }
+
+        // SAFETY: The type id matches, so `drvdata.data` was returned by `D::into_foreign` and,
+        // by the safety requirements, `from_foreign` is not called while the borrow is alive.
+        Some(unsafe { D::borrow(drvdata.data) })
+    }
+
+    /// Reports an error that happened during probe and returns it.
+    ///
+    /// If `err` is [`EPROBE_DEFER`], the message is only printed at debug level and recorded as
+    /// the reason for the deferral, which is listed in `/sys/kernel/debug/devices_deferred`.
+    /// Otherwise it is printed as an error-level message.
+    ///
+    /// More details are available from [`dev_err_probe`].
+    ///
+    /// [`dev_err_probe`]: crate::dev_err_probe
+    /// [`EPROBE_DEFER`]: crate::error::code::EPROBE_DEFER
+    fn err_probe(&self, err: Error, args: fmt::Arguments<'_>) -> Error {
+        // SAFETY: `self.raw_device` is valid because `self` is valid. The "%pA" format string
+        // expects a pointer to `fmt::Arguments`, which is what we're passing as the last argument.
+        unsafe {
+            bindings::dev_err_probe(
+                self.raw_device(),
+                err.to_errno(),
+                c_str!("%pA").as_char_ptr(),
+                &args as *const _ as *const c_void,
+            )
+        };
+        err
+    }
+
+    /// Creates a device link from `self` (the consumer) to `supplier`.
+    ///
+    /// Device links tell the driver core that `self` depends on `supplier`, for example, to
+    /// enforce probe ordering or to keep the supplier runtime-resumed while the consumer is
+    /// active. `flags` is a combination of the constants in the [`link`] module.
+    ///
+    /// The link is deleted when the returned [`Link`] is dropped, which is only allowed for links
+    /// the driver core does not manage. `flags` must therefore contain [`link::STATELESS`], and
+    /// the flags that only apply to managed links ([`link::AUTOREMOVE_CONSUMER`],
+    /// [`link::AUTOREMOVE_SUPPLIER`], [`link::AUTOPROBE_CONSUMER`] and [`link::SYNC_STATE_ONLY`])
+    /// are rejected with [`EINVAL`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kernel::{
+    ///     device::{link, Link, RawDevice},
+    ///     prelude::*,
+    /// };
+    ///
+    /// fn link_phy(consumer: &impl RawDevice, phy: &impl RawDevice) -> Result<Link> {
+    ///     // Managed links are refused.
+    ///     assert!(consumer.link_to(phy, link::PM_RUNTIME).err() == Some(EINVAL));
+    ///     consumer.link_to(phy, link::STATELESS | link::PM_RUNTIME)
+    /// }
+    /// ```
+    ///
+    /// [`EINVAL`]: crate::error::code::EINVAL
+    fn link_to(&self, supplier: &impl RawDevice, flags: u32) -> Result<Link> {
+        const MANAGED: u32 = link::AUTOREMOVE_CONSUMER
+            | link::AUTOREMOVE_SUPPLIER
+            | link::AUTOPROBE_CONSUMER
+            | link::SYNC_STATE_ONLY;
+        if flags & link::STATELESS == 0 || flags & MANAGED != 0 {
+            return Err(EINVAL);
+        }
+
+        // SAFETY: Both raw devices are valid by the safety requirements of `RawDevice`.
+        let ptr =
+            unsafe { bindings::device_link_add(self.raw_device(), supplier.raw_device(), flags) };
+
+        // INVARIANT: The link was just created and it is only deleted when `Link` is dropped since
+        // it is stateless.
+        Ok(Link {
+            ptr: ptr::NonNull::new(ptr).ok_or(EINVAL)?,
+        })
+    }
+
+    /// Sets the mask of addresses the device can use for streaming DMA mappings to the lowest
+    /// `bits` bits.
+    ///
+    /// Fails with [`EIO`] if the platform cannot satisfy the mask, e.g., because no memory is
+    /// reachable with it, and with [`EINVAL`] if `bits` is larger than 64.
+    ///
+    /// [`EIO`]: crate::error::code::EIO
+    /// [`EINVAL`]: crate::error::code::EINVAL
+    fn dma_set_mask(&self, bits: u32) -> Result {
+        let mask = crate::dma::bit_mask(bits).ok_or(EINVAL)?;
+        // SAFETY: `self.raw_device` is valid because `self` is valid.
+        to_result(unsafe { bindings::dma_set_mask(self.raw_device(), mask) })
+    }
+
+    /// Sets the mask of addresses the device can use for coherent DMA allocations to the lowest
+    /// `bits` bits.
+    ///
+    /// Fails under the same conditions as [`RawDevice::dma_set_mask`].
+    fn dma_set_coherent_mask(&self, bits: u32) -> Result {
+        let mask = crate::dma::bit_mask(bits).ok_or(EINVAL)?;
+        // SAFETY: `self.raw_device` is valid because `self` is valid.
+        to_result(unsafe { bindings::dma_set_coherent_mask(self.raw_device(), mask) })
+    }
+
+    /// Sets both the streaming and the coherent DMA masks to the lowest `bits` bits.
+    ///
+    /// Drivers of devices that can address more than 32 bits usually try their full width first
+    /// and fall back to 32 bits if that fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use kernel::{device::RawDevice, prelude::*};
+    /// fn setup_dma(dev: &impl RawDevice) -> Result {
+    ///     dev.dma_set_mask_and_coherent(40)
+    ///         .or_else(|_| dev.dma_set_mask_and_coherent(32))
+    /// }
+    /// ```
+    fn dma_set_mask_and_coherent(&self, bits: u32) -> Result {
+        self.dma_set_mask(bits)?;
+        self.dma_set_coherent_mask(bits)
+    }
+
+    /// Returns the current streaming DMA mask, or zero if the device is not DMA capable.
+    fn dma_mask(&self) -> u64 {
+        // SAFETY: `self.raw_device` is valid because `self` is valid.
+        let mask = unsafe { (*self.raw_device()).dma_mask };
+        if mask.is_null() {
+            0
+        } else {
+            // SAFETY: A non-null `dma_mask` points to the mask stored by the bus code, which
+            // lives as long as the device.
+            unsafe { *mask }
+        }
+    }
+
+    /// Returns the current coherent DMA mask.
+    fn coherent_dma_mask(&self) -> u64 {
+        // SAFETY: `self.raw_device` is valid because `self` is valid.
+        unsafe { (*self.raw_device()).coherent_dma_mask }
+    }
+
+    /// Returns `true` if the DMA mask does not cover all the memory in the system.
+    ///
+    /// Streaming mappings of buffers beyond the mask are then bounced, unless an IOMMU remaps
+    /// them.
+    fn dma_addressing_limited(&self) -> bool {
+        // SAFETY: `self.raw_device` is valid because `self` is valid.
+        unsafe { bindings::dma_addressing_limited(self.raw_device()) }
+    }
+
+    /// Returns `true` if streaming DMA mappings of the device may be bounced through SWIOTLB.
+    ///
+    /// This is the case when all mappings are forced through it, e.g., for confidential
+    /// computing guests, or when the addressing of the device is limited. Drivers that map many
+    /// small buffers may then prefer to copy into a coherent buffer instead.
+    fn dma_needs_bounce(&self) -> bool {
+        #[cfg(CONFIG_SWIOTLB)]
+        {
+            // SAFETY: `self.raw_device` is valid because `self` is valid.
+            if unsafe { bindings::is_swiotlb_force_bounce(self.raw_device()) } {
+                return true;
+            }
+        }
+        self.dma_addressing_limited()
+    }
+
+    /// Returns the largest size of a single streaming DMA mapping.
+    ///
+    /// This is limited when mappings are bounced, to the size of a SWIOTLB slot run.
+    fn dma_max_mapping_size(&self) -> usize {
+        // SAFETY: `self.raw_device` is valid because `self` is valid.
+        unsafe { bindings::dma_max_mapping_size(self.raw_device()) }
+    }
+
+    /// Returns `true` if DMA of the device is coherent with the CPU caches.
+    ///
+    /// Streaming mappings of non-coherent devices need cache maintenance when they are mapped,
+    /// unmapped or synced, which makes them more expensive. Drivers of such devices may prefer
+    /// to reuse long-lived coherent buffers over mapping buffers for every transfer.
+    fn dma_is_coherent(&self) -> bool {
+        // SAFETY: `self.raw_device` is valid because `self` is valid.
+        unsafe { bindings::dev_is_dma_coherent(self.raw_device()) }
+    }
+
+    /// Returns `true` if the device is behind an IOMMU.
+    ///
+    /// The IOMMU may still pass DMA addresses through untranslated, see
+    /// [`RawDevice::dma_iommu_translated`].
+    fn iommu_mapped(&self) -> bool {
+        // SAFETY: `self.raw_device` is valid because `self` is valid.
+        unsafe { bindings::device_iommu_mapped(self.raw_device()) }
+    }
+
+    /// Returns `true` if the DMA addresses of the device are translated by an IOMMU.
+    ///
+    /// Buffers then do not need to be physically contiguous to be seen as contiguous by the
+    /// device, and streaming mappings are never bounced because of the DMA mask.
+    fn dma_iommu_translated(&self) -> bool {
+        #[cfg(CONFIG_IOMMU_API)]
+        {
+            // SAFETY: `self.raw_device` is valid because `self` is valid.
+            let domain = unsafe { bindings::iommu_get_domain_for_dev(self.raw_device()) };
+            // SAFETY: A non-null domain is valid while the device is attached to it, which lasts
+            // at least until its driver is unbound.
+            if !domain.is_null() && unsafe { bindings::iommu_is_dma_domain(domain) } {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Returns the NUMA node the device is attached to, or [`bindings::NUMA_NO_NODE`] if it is not
+    /// known.
+    ///
+    /// Memory and CPUs on that node are the closest to the device, so drivers of devices with
+    /// heavy traffic, e.g., on big servers, allocate their rings and buffers there, and spread
+    /// their queues over its CPUs with [`local_spread`].
+    ///
+    /// [`local_spread`]: crate::cpumask::local_spread
+    fn numa_node(&self) -> i32 {
+        // SAFETY: `self.raw_device` is valid because `self` is valid.
+        unsafe { bindings::dev_to_node(self.raw_device()) }
+    }
+
+    /// Marks the device as able, or not, to wake up the system from sleep.
+    ///
+    /// This creates or removes the `power/wakeup` sysfs attribute, through which user space
+    /// enables or disables wakeup. Drivers call this in `probe` for devices that can generate
+    /// wakeup events, e.g., keys or an RTC alarm.
+    fn set_wakeup_capable(&self, capable: bool) {
+        // SAFETY: `self.raw_device` is valid because `self` is valid.
+        unsafe { bindings::device_set_wakeup_capable(self.raw_device(), capable) };
+    }
+
+    /// Enables or disables the device to wake up the system from sleep.
+    ///
+    /// Fails with [`EINVAL`] if the device is not wakeup capable.
+    ///
+    /// [`EINVAL`]: crate::error::code::EINVAL
+    fn set_wakeup_enable(&self, enable: bool) -> Result {
+        // SAFETY: `self.raw_device` is valid because `self` is valid.
+        to_result(unsafe { bindings::device_set_wakeup_enable(self.raw_device(), enable) })
+    }
+
+    /// Marks the device as wakeup capable and enables or disables wakeup in one go.
+    ///
+    /// This is the equivalent of the C `device_init_wakeup` and is what most drivers call in
+    /// `probe`.
+    fn init_wakeup(&self, enable: bool) -> Result {
+        // SAFETY: `self.raw_device` is valid because `self` is valid.
+        to_result(unsafe { bindings::device_init_wakeup(self.raw_device(), enable) })
+    }
+
+    /// Returns `true` if the device is wakeup capable and wakeup is enabled.
+    ///
+    /// Drivers check this in their suspend callback to decide whether to arm their wakeup
+    /// interrupt, e.g., with `enable_irq_wake`.
+    fn may_wakeup(&self) -> bool {
+        // SAFETY: `self.raw_device` is valid because `self` is valid.
+        unsafe { bindings::device_may_wakeup(self.raw_device()) }
+    }
+
+    /// Reports a wakeup event of the device, keeping the system awake for `msec` milliseconds.
+    ///
+    /// This aborts a suspend in progress, or prevents the next one for that duration, if wakeup
+    /// is enabled for the device.
+    fn pm_wakeup_event(&self, msec: u32) {
+        // SAFETY: `self.raw_device` is valid because `self` is valid.
+        unsafe { bindings::pm_wakeup_event(self.raw_device(), msec) };
+    }
+
+    /// Uses `irq` as the dedicated wakeup interrupt of the device.
+    ///
+    /// The PM core arms the interrupt for wakeup when the system suspends, if wakeup is enabled
+    /// for the device, and reports a wakeup event when it fires. This also works for
+    /// suspend-to-idle, where the interrupt must stay enabled while the CPUs idle.
+    fn set_wake_irq(&self, irq: u32) -> Result {
+        let irq = core::ffi::c_int::try_from(irq).map_err(|_| EINVAL)?;
+        // SAFETY: `self.raw_device` is valid because `self` is valid.
+        to_result(unsafe { bindings::dev_pm_set_wake_irq(self.raw_device(), irq) })
+    }
+
+    /// Stops using the interrupt set with [`RawDevice::set_wake_irq`] for wakeup.
+    fn clear_wake_irq(&self) {
+        // SAFETY: `self.raw_device` is valid because `self` is valid.
+        unsafe { bindings::dev_pm_clear_wake_irq(self.raw_device()) };
+    }
+
+    /// Registers `f` to run when the driver is unbound from the device.
+    ///
+    /// Cleanups run in reverse order of registration, after `remove`, like the C
+    /// `devm_add_action`. This allows probe to acquire resources that have no typed wrapper yet in
+    /// a linear fashion, with each step undone automatically on unbind or when a later step of
+    /// probe fails.
+    ///
+    /// If the registration fails, `f` runs right away and the error is returned, so that an
+    /// acquired resource is never leaked.
+    ///
+    /// This must only be called while a driver is bound to the device, or is being probed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kernel::{device::RawDevice, prelude::*};
+    ///
+    /// fn probe(dev: &impl RawDevice) -> Result {
+    ///     // Power up the block, e.g., through a write to a register.
+    ///     dev.add_cleanup(|| {
+    ///         // Power down the block again.
+    ///     })?;
+    ///     Ok(())
+    /// }
+    /// ```
+    fn add_cleanup<F: FnOnce() + Send + 'static>(&self, f: F) -> Result {
+        // Allocate without moving `f` in, so that it can still run if the allocation fails.
+        let mut data = match <Box<_> as BoxExt<F>>::new_uninit(GFP_KERNEL) {
+            Ok(data) => data,
+            Err(_) => {
+                f();
+                return Err(ENOMEM);
+            }
+        };
+        data.write(f);
+        // SAFETY: `data` was just initialised.
+        let data = Box::into_raw(unsafe { data.assume_init() });
+
+        // SAFETY: `self.raw_device` is valid because `self` is valid. `data` is a valid pointer to
+        // an `F`, which the callback takes ownership of.
+        let ret = unsafe {
+            bindings::__devm_add_action(
+                self.raw_device(),
+                Some(cleanup_callback::<F>),
+                data.cast(),
+                c_str!("rust cleanup").as_char_ptr(),
+            )
+        };
+        if ret != 0 {
+            // SAFETY: The action was not registered, so we still own `data`.
+            unsafe { cleanup_callback::<F>(data.cast()) };
+        }
+        to_result(ret)
+    }
+
     /// Prints the provided message to the console.
     ///
     /// # Safety
@@ -145,6 +536,18 @@ unsafe fn printk(&self, klevel: &[u8], msg: fmt::Arguments<'_>) {
     }
 }
 
+/// Runs and frees a cleanup registered by [`RawDevice::add_cleanup`].
+///
+/// # Safety
+///
+/// `data` must have been allocated by [`RawDevice::add_cleanup`] with `Box<F>`, and not be used
+/// anymore afterwards.
+unsafe extern "C" fn cleanup_callback<F: FnOnce()>(data: *mut c_void) {
+    // SAFETY: By the safety requirements, `data` is an owned `Box<F>`.
+    let f = unsafe { Box::from_raw(data.cast::<F>()) };
+    f();
+}
+
 /// A ref-counted device.
 ///
 /// # Invariants
@@ -199,6 +602,35 @@ fn raw_device(&self) -> *mut bindings::device {
     }
 }
 
+impl fmt::Debug for Device {
+    /// Formats the name and the bus of the device.
+    ///
+    /// ```
+    /// # use kernel::{device::Device, pr_err};
+    /// fn report(dev: &Device) {
+    ///     // Prints, e.g., `Device { name: "fe201000.serial", bus: Some("platform") }`.
+    ///     pr_err!("unexpected device {:?}\n", dev);
+    /// }
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Device")
+            .field("name", &self.name())
+            .field("bus", &self.bus_name())
+            .finish()
+    }
+}
+
+impl fmt::Display for Device {
+    /// Formats the device like the prefix of `dev_*` messages: the name of its driver, or of its
+    /// bus if it is not bound, and the name of the device, e.g., `pl011 fe201000.serial`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // SAFETY: `self.0` is valid. `dev_driver_string` returns a valid C string, which lives
+        // at least as long as the driver is bound, like for `dev_*` messages.
+        let driver = unsafe { CStr::from_char_ptr(bindings::dev_driver_string(self.as_raw())) };
+        write!(f, "{} {}", driver, self.name())
+    }
+}
+
 // SAFETY: Instances of `Device` are always refcounted.
 unsafe impl crate::types::AlwaysRefCounted for Device {
     fn inc_ref(&self) {
@@ -212,6 +644,134 @@ unsafe fn dec_ref(obj: ptr::NonNull<Self>) {
     }
 }
 
+/// Flags for [`RawDevice::link_to`].
+pub mod link {
+    use crate::bindings;
+
+    /// The driver core does not manage the link; it only exists until it is explicitly deleted.
+    ///
+    /// Required by [`RawDevice::link_to`](super::RawDevice::link_to).
+    pub const STATELESS: u32 = bindings::DL_FLAG_STATELESS;
+
+    /// Deletes the link automatically when the consumer driver is unbound.
+    ///
+    /// Not allowed with [`RawDevice::link_to`](super::RawDevice::link_to).
+    pub const AUTOREMOVE_CONSUMER: u32 = bindings::DL_FLAG_AUTOREMOVE_CONSUMER;
+
+    /// Runtime-resumes the supplier whenever the consumer is runtime-resumed.
+    pub const PM_RUNTIME: u32 = bindings::DL_FLAG_PM_RUNTIME;
+
+    /// Runtime-resumes the supplier when the link is created; requires [`PM_RUNTIME`].
+    pub const RPM_ACTIVE: u32 = bindings::DL_FLAG_RPM_ACTIVE;
+
+    /// Deletes the link automatically when the supplier driver is unbound.
+    ///
+    /// Not allowed with [`RawDevice::link_to`](super::RawDevice::link_to).
+    pub const AUTOREMOVE_SUPPLIER: u32 = bindings::DL_FLAG_AUTOREMOVE_SUPPLIER;
+
+    /// Probes the consumer automatically after the supplier driver binds.
+    ///
+    /// Not allowed with [`RawDevice::link_to`](super::RawDevice::link_to).
+    pub const AUTOPROBE_CONSUMER: u32 = bindings::DL_FLAG_AUTOPROBE_CONSUMER;
+
+    /// Only uses the link to order the supplier's `sync_state()` callback.
+    ///
+    /// Not allowed with [`RawDevice::link_to`](super::RawDevice::link_to).
+    pub const SYNC_STATE_ONLY: u32 = bindings::DL_FLAG_SYNC_STATE_ONLY;
+}
+
+/// A link between a consumer and a supplier device.
+///
+/// Created by [`RawDevice::link_to`]. The link is deleted when this object is dropped.
+///
+/// # Invariants
+///
+/// `ptr` points to a valid device link that is only deleted when `self` is dropped.
+pub struct Link {
+    ptr: ptr::NonNull<bindings::device_link>,
+}
+
+impl Link {
+    /// Returns the raw `struct device_link` pointer.
+    pub fn as_raw(&self) -> *mut bindings::device_link {
+        self.ptr.as_ptr()
+    }
+}
+
+impl Drop for Link {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, `self.ptr` is a valid link that hasn't been deleted yet.
+        unsafe { bindings::device_link_del(self.ptr.as_ptr()) };
+    }
+}
+
+// SAFETY: `device_link_del` may be called from any thread.
+unsafe impl Send for Link {}
+
+// SAFETY: `Link` has no methods that act on `&self` other than returning the raw pointer.
+unsafe impl Sync for Link {}
+
+/// Driver data tagged with the type it was created from.
+///
+/// This is what Rust code stores as the driver data of a `struct device`, so that
+/// [`RawDevice::drvdata`] can check the type before handing the data out.
+struct DrvData {
+    type_id: TypeId,
+    data: *const c_void,
+}
+
+/// Calls `init` and stores the data it returns as the driver data of `dev`.
+///
+/// Everything that can fail is done before `init` is called: once the data exists, it is stored.
+/// Otherwise it would be dropped without the driver getting a chance to undo what creating it did,
+/// e.g., registering child devices, which [`DeviceRemoval::device_remove`] does.
+///
+/// # Safety
+///
+/// `dev` must be valid and its driver data must not be set.
+///
+/// [`DeviceRemoval::device_remove`]: crate::driver::DeviceRemoval::device_remove
+pub(crate) unsafe fn set_drvdata<D: ForeignOwnable + 'static>(
+    dev: *mut bindings::device,
+    init: impl FnOnce() -> Result<D>,
+) -> Result {
+    let mut drvdata = Box::new(
+        DrvData {
+            type_id: TypeId::of::<D>(),
+            data: ptr::null(),
+        },
+        GFP_KERNEL,
+    )?;
+    drvdata.data = init()?.into_foreign();
+
+    // SAFETY: `dev` is valid by the safety requirements of this function.
+    unsafe { bindings::dev_set_drvdata(dev, Box::into_raw(drvdata).cast()) };
+    Ok(())
+}
+
+/// Removes the driver data of `dev` and returns it.
+///
+/// # Safety
+///
+/// `dev` must be valid and its driver data must have been set by a previous call to
+/// [`set_drvdata`] with the same type `D`. There must be no users of values returned by
+/// [`RawDevice::drvdata`] left.
+pub(crate) unsafe fn take_drvdata<D: ForeignOwnable + 'static>(dev: *mut bindings::device) -> D {
+    // SAFETY: `dev` is valid by the safety requirements of this function.
+    let ptr = unsafe { bindings::dev_get_drvdata(dev) }.cast::<DrvData>();
+
+    // SAFETY: `dev` is valid by the safety requirements of this function.
+    unsafe { bindings::dev_set_drvdata(dev, ptr::null_mut()) };
+
+    // SAFETY: By the safety requirements, `ptr` was allocated by `set_drvdata` with `Box`.
+    let drvdata = unsafe { Box::from_raw(ptr) };
+    debug_assert!(drvdata.type_id == TypeId::of::<D>());
+
+    // SAFETY: By the safety requirements, `drvdata.data` was returned by `D::into_foreign` and
+    // there are no borrows of it left.
+    unsafe { D::from_foreign(drvdata.data) }
+}
+
 /// Device data.
 ///
 /// When a device is removed (for whatever reason, for example, because the device was unplugged or
@@ -245,11 +805,12 @@ pub struct Data<T, U, V> {
 macro_rules! new_device_data {
     ($reg:expr, $res:expr, $gen:expr, $name:literal) => {{
         static CLASS1: $crate::sync::LockClassKey = $crate::sync::LockClassKey::new();
+        static CLASS2: $crate::sync::LockClassKey = $crate::sync::LockClassKey::new();
         let regs = $reg;
         let res = $res;
         let gen = $gen;
         let name = $crate::c_str!($name);
-        $crate::device::Data::try_new(regs, res, gen, name, &CLASS1)
+        $crate::device::Data::try_new(regs, res, gen, name, &CLASS1, &CLASS2)
     }};
 }
 
@@ -264,6 +825,7 @@ pub fn try_new(
         general: impl PinInit<V>,
         name: &'static CStr,
         key1: &'static LockClassKey,
+        key2: &'static LockClassKey,
     ) -> Result<Pin<UniqueArc<Self>>> {
         let ret = UniqueArc::pin_init(
             pin_init!(Self {
@@ -272,7 +834,7 @@ pub fn try_new(
                     name,
                     key1,
                 ),
-                resources <- Revocable::new(resources),
+                resources <- Revocable::new(resources, name, key2),
                 general <- general,
             }),
             GFP_KERNEL,
@@ -330,6 +892,44 @@ macro_rules! dev_printk {
     }
 }
 
+/// Reports an error that happened during probe and evaluates to it.
+///
+/// If the error is [`EPROBE_DEFER`], the message is printed at debug level only and recorded as
+/// the reason for the deferral, so that it shows up in `/sys/kernel/debug/devices_deferred`.
+/// Other errors are printed as error-level messages prefixed with device information.
+///
+/// Equivalent to the kernel's `dev_err_probe` function.
+///
+/// Mimics the interface of [`std::print!`]. More information about the syntax is available from
+/// [`core::fmt`].
+///
+/// [`EPROBE_DEFER`]: crate::error::code::EPROBE_DEFER
+/// [`std::print!`]: https://doc.rust-lang.org/std/macro.print.html
+///
+/// # Examples
+///
+/// ```
+/// # use kernel::device::Device;
+///
+/// fn example(dev: &Device, supply_ready: bool) -> Result {
+///     if !supply_ready {
+///         return Err(dev_err_probe!(dev, EPROBE_DEFER, "supply {} not ready\n", "vdd"));
+///     }
+///     Ok(())
+/// }
+/// ```
+#[macro_export]
+macro_rules! dev_err_probe {
+    ($dev:expr, $err:expr, $($f:tt)*) => {
+        {
+            // We have an explicity `use` statement here so that callers of this macro are not
+            // required to explicitly use the `RawDevice` trait to use its functions.
+            use $crate::device::RawDevice;
+            ($dev).err_probe($err, core::format_args!($($f)*))
+        }
+    }
+}
+
 /// Prints an emergency-level message (level 0) prefixed with device information.
 ///
 /// This level should be used if the system is unusable.
@@ -509,11 +1109,16 @@ macro_rules! dev_info {
 ///
 /// This level should be used for debug messages.
 ///
-/// Equivalent to the kernel's `dev_dbg` macro, except that it doesn't support dynamic debug yet.
+/// Equivalent to the kernel's `dev_dbg` macro.
+///
+/// With `CONFIG_DYNAMIC_DEBUG`, each call site can be enabled at runtime through the dynamic debug
+/// control file, like [`pr_debug`]. Otherwise, messages are only printed if debug assertions are
+/// enabled.
 ///
 /// Mimics the interface of [`std::print!`]. More information about the syntax is available from
 /// [`core::fmt`].
 ///
+/// [`pr_debug`]: crate::pr_debug
 /// [`std::print!`]: https://doc.rust-lang.org/std/macro.print.html
 ///
 /// # Examples
@@ -527,5 +1132,24 @@ macro_rules! dev_info {
 /// ```
 #[macro_export]
 macro_rules! dev_dbg {
+    ($dev:expr, $fmt:literal $($f:tt)*) => {{
+        #[cfg(CONFIG_DYNAMIC_DEBUG)]
+        {
+            use $crate::device::RawDevice;
+            let descriptor = $crate::dynamic_debug_descriptor!($fmt);
+            if descriptor.enabled() {
+                let dev = ($dev).raw_device();
+                match core::format_args!($fmt $($f)*) {
+                    // SAFETY: `raw_device` returns a valid device by the safety requirements of
+                    // `RawDevice`.
+                    args => unsafe { $crate::print::call_dynamic_dev_dbg(descriptor, dev, args) },
+                }
+            }
+        }
+        #[cfg(not(CONFIG_DYNAMIC_DEBUG))]
+        {
+            $crate::dev_printk!(pr_dbg, $dev, $fmt $($f)*);
+        }
+    }};
     ($($f:tt)*) => { $crate::dev_printk!(pr_dbg, $($f)*); }
 }
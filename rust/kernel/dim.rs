@@ -0,0 +1,241 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Dynamic interrupt moderation (DIM).
+//!
+//! Devices that coalesce completion interrupts trade latency for CPU load: waiting for more
+//! completions, or for longer, before interrupting lowers the interrupt rate but delays each
+//! completion. The DIM library picks the coalescing parameters from the observed traffic, by
+//! trying neighbouring profiles of a table and keeping the one that gives the best throughput per
+//! interrupt. Drivers feed it samples of their event and traffic counters with [`Dim::sample`],
+//! and program the [`Moderation`] it chooses into the device in [`Handler::apply`].
+//!
+//! C header: [`include/linux/dim.h`](srctree/include/linux/dim.h)
+
+use crate::{bindings, c_str, prelude::*, types::Opaque};
+use core::{
+    marker::PhantomPinned,
+    ptr::addr_of_mut,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// The completion queue whose interrupts are moderated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// A receive queue.
+    Rx,
+
+    /// A transmit queue.
+    Tx,
+}
+
+/// When the coalescing period of a completion queue starts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PeriodMode {
+    /// The period starts at the first event, i.e., at the first interrupt that was held back.
+    FromEqe,
+
+    /// The period starts at the first completion, and is restarted by every completion.
+    FromCqe,
+}
+
+impl PeriodMode {
+    fn as_raw(self) -> u8 {
+        match self {
+            Self::FromEqe => bindings::dim_cq_period_mode_DIM_CQ_PERIOD_MODE_START_FROM_EQE as u8,
+            Self::FromCqe => bindings::dim_cq_period_mode_DIM_CQ_PERIOD_MODE_START_FROM_CQE as u8,
+        }
+    }
+}
+
+/// Interrupt coalescing parameters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Moderation {
+    /// The longest time to hold an interrupt back, in microseconds.
+    pub usec: u16,
+
+    /// The number of packets after which to interrupt.
+    pub pkts: u16,
+
+    /// The number of completions after which to interrupt.
+    pub comps: u16,
+}
+
+impl From<bindings::dim_cq_moder> for Moderation {
+    fn from(m: bindings::dim_cq_moder) -> Self {
+        Self {
+            usec: m.usec,
+            pkts: m.pkts,
+            comps: m.comps,
+        }
+    }
+}
+
+/// The handler of the decisions of a [`Dim`].
+pub trait Handler: Sync {
+    /// Programs `moderation` into the completion queue.
+    ///
+    /// This is called from a work item, so it may sleep, e.g., to send a command to the device.
+    fn apply(&self, moderation: Moderation);
+}
+
+/// The dynamic interrupt moderation of a completion queue.
+///
+/// # Invariants
+///
+/// `dim` is zero-initialised, except for its mode and its work item, which is initialised with
+/// [`Dim::work_callback`] and is cancelled before `dim` is freed.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{
+///     dim::{Dim, Direction, Handler, Moderation, PeriodMode},
+///     prelude::*,
+/// };
+///
+/// struct RxQueue {
+///     index: u32,
+/// }
+///
+/// impl Handler for RxQueue {
+///     fn apply(&self, m: Moderation) {
+///         pr_debug!("rx{}: coalesce {} us / {} packets\n", self.index, m.usec, m.pkts);
+///         // Write the parameters to the coalescing registers of the queue.
+///     }
+/// }
+///
+/// /// Called at the end of every NAPI poll of the queue.
+/// fn poll_done(dim: &Dim<RxQueue>, interrupts: u16, packets: u64, bytes: u64) {
+///     dim.sample(interrupts, packets, bytes);
+/// }
+///
+/// let dim = Box::pin_init(
+///     Dim::new(RxQueue { index: 0 }, Direction::Rx, PeriodMode::FromEqe),
+///     GFP_KERNEL,
+/// )?;
+/// dim.handler().apply(dim.initial_moderation());
+/// poll_done(&dim, 1, 64, 64 * 1500);
+/// # Ok::<(), Error>(())
+/// ```
+#[pin_data(PinnedDrop)]
+pub struct Dim<T: Handler> {
+    handler: T,
+    dir: Direction,
+    mode: PeriodMode,
+    sampling: AtomicBool,
+    #[pin]
+    dim: Opaque<bindings::dim>,
+    #[pin]
+    _pin: PhantomPinned,
+}
+
+impl<T: Handler> Dim<T> {
+    /// Creates the moderation of a completion queue, whose decisions are applied by `handler`.
+    ///
+    /// The device should be programmed with [`Dim::initial_moderation`] before the first sample.
+    pub fn new(handler: T, dir: Direction, mode: PeriodMode) -> impl PinInit<Self> {
+        pin_init!(Self {
+            handler,
+            dir,
+            mode,
+            sampling: AtomicBool::new(false),
+            dim <- Opaque::ffi_init(move |dim: *mut bindings::dim| {
+                // SAFETY: `dim` is valid for writes, and all zeroes is its initial state. The
+                // work item is cancelled in `PinnedDrop`, before `dim` is freed.
+                unsafe {
+                    dim.write(core::mem::zeroed());
+                    (*dim).mode = mode.as_raw();
+                    bindings::init_work_with_key(
+                        addr_of_mut!((*dim).work),
+                        Some(Self::work_callback),
+                        false,
+                        c_str!("dim").as_char_ptr(),
+                        crate::static_lock_class!().as_ptr(),
+                    );
+                }
+            }),
+            _pin: PhantomPinned,
+        })
+    }
+
+    /// Returns the handler.
+    pub fn handler(&self) -> &T {
+        &self.handler
+    }
+
+    /// Returns the moderation to start with, before the library made any decision.
+    pub fn initial_moderation(&self) -> Moderation {
+        let get: unsafe extern "C" fn(u8) -> bindings::dim_cq_moder = match self.dir {
+            Direction::Rx => bindings::net_dim_get_def_rx_moderation,
+            Direction::Tx => bindings::net_dim_get_def_tx_moderation,
+        };
+        // SAFETY: The functions accept any period mode.
+        unsafe { get(self.mode.as_raw()) }.into()
+    }
+
+    /// Reports the current values of the counters of the completion queue.
+    ///
+    /// `events` counts the interrupts, and `packets` and `bytes` the traffic, since the queue was
+    /// created; they may wrap around. This is usually called at the end of every NAPI poll. When
+    /// the library decides to change the moderation, [`Handler::apply`] is called from a work
+    /// item, and new decisions are only made after it returns.
+    ///
+    /// Samples reported while another one is being processed, e.g., from another CPU, are
+    /// dropped.
+    pub fn sample(&self, events: u16, packets: u64, bytes: u64) {
+        if self.sampling.swap(true, Ordering::Acquire) {
+            return;
+        }
+        // SAFETY: All zeroes is a valid sample, it is filled in below.
+        let mut sample: bindings::dim_sample = unsafe { core::mem::zeroed() };
+        // SAFETY: `sample` is valid for writes, and `dim` is initialised and, thanks to
+        // `sampling`, not used by another call of `net_dim`. The work item only writes the
+        // state while `net_dim` leaves it alone.
+        unsafe {
+            bindings::dim_update_sample(events, packets, bytes, &mut sample);
+            bindings::net_dim(self.dim.get(), sample);
+        }
+        self.sampling.store(false, Ordering::Release);
+    }
+
+    unsafe extern "C" fn work_callback(work: *mut bindings::work_struct) {
+        // SAFETY: The work item is the `work` field of the `dim` field of a live `Dim<T>`, since
+        // it is cancelled before the `Dim<T>` is freed.
+        let this = unsafe {
+            let dim = crate::container_of!(work, bindings::dim, work);
+            &*crate::container_of!(dim, Self, dim)
+        };
+        let dim = this.dim.get();
+        // SAFETY: `net_dim` only schedules the work item once it has chosen a profile, and does
+        // not touch it until the state is reset below.
+        let ix = unsafe { (*dim).profile_ix };
+        let get: unsafe extern "C" fn(u8, i32) -> bindings::dim_cq_moder = match this.dir {
+            Direction::Rx => bindings::net_dim_get_rx_moderation,
+            Direction::Tx => bindings::net_dim_get_tx_moderation,
+        };
+        // SAFETY: `ix` was chosen by `net_dim`, so it is within the profile table.
+        let moderation = unsafe { get(this.mode.as_raw(), ix.into()) };
+        this.handler.apply(moderation.into());
+        // SAFETY: Resetting the state lets `net_dim` make the next decision; it only reads it
+        // until then.
+        unsafe {
+            addr_of_mut!((*dim).state).write_volatile(bindings::dim_state_DIM_START_MEASURE as u8)
+        };
+    }
+}
+
+#[pinned_drop]
+impl<T: Handler> PinnedDrop for Dim<T> {
+    fn drop(self: Pin<&mut Self>) {
+        // SAFETY: By the type invariants, the work item is initialised. Once it is cancelled, it
+        // is neither queued nor running, and `sample` cannot queue it anymore since `self` is
+        // being dropped.
+        unsafe { bindings::cancel_work_sync(addr_of_mut!((*self.dim.get()).work)) };
+    }
+}
+
+// SAFETY: The work item can be cancelled from any thread, and the handler is `Sync`.
+unsafe impl<T: Handler + Send> Send for Dim<T> {}
+
+// SAFETY: Concurrent samples are serialised by `sampling`, and the handler is `Sync`.
+unsafe impl<T: Handler> Sync for Dim<T> {}
@@ -0,0 +1,307 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Direct memory access (DMA).
+//!
+//! Drivers describe the addresses their device can generate with a DMA mask, set through
+//! [`RawDevice::dma_set_mask`] and [`RawDevice::dma_set_coherent_mask`]. On systems where memory
+//! lives above what the device can address, the DMA API bounces streaming mappings through
+//! SWIOTLB, which drivers can detect with [`RawDevice::dma_needs_bounce`].
+//!
+//! Buffers shared with a device for longer periods, e.g., descriptor rings or frame buffers, are
+//! allocated with [`CoherentAllocation`]. On systems without an IOMMU, large coherent allocations
+//! come from the contiguous memory allocator (CMA).
+//!
+//! Descriptor rings, through which most devices are driven, are implemented by [`DescRing`].
+//!
+//! Kernel buffers that a device only accesses for a single transfer are mapped with
+//! [`StreamingMapping`], or [`SgMapping`] for transfers spanning several buffers.
+//!
+//! Transfers from and to userspace buffers, whose alignment the device may not support, are set
+//! up with [`user::UserIo`].
+//!
+//! C header: [`include/linux/dma-mapping.h`](srctree/include/linux/dma-mapping.h)
+//!
+//! [`RawDevice::dma_set_mask`]: crate::device::RawDevice::dma_set_mask
+//! [`RawDevice::dma_set_coherent_mask`]: crate::device::RawDevice::dma_set_coherent_mask
+//! [`RawDevice::dma_needs_bounce`]: crate::device::RawDevice::dma_needs_bounce
+
+use crate::{
+    alloc::{flags::*, Flags},
+    bindings,
+    device::{Device, RawDevice},
+    error::{code::*, Result},
+    types::ARef,
+};
+use core::ptr::{self, NonNull};
+
+pub mod ring;
+pub mod streaming;
+pub mod user;
+
+pub use ring::{DescRing, Descriptor};
+pub use streaming::{Direction, SgMapping, StreamingMapping};
+
+/// Returns a DMA mask covering the lowest `n` address bits.
+///
+/// This is the equivalent of the C `DMA_BIT_MASK` macro. Returns [`None`] if `n` is larger than
+/// 64.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::dma;
+///
+/// assert_eq!(dma::bit_mask(32), Some(0xffff_ffff));
+/// assert_eq!(dma::bit_mask(64), Some(u64::MAX));
+/// assert_eq!(dma::bit_mask(0), Some(0));
+/// assert_eq!(dma::bit_mask(65), None);
+/// ```
+pub const fn bit_mask(n: u32) -> Option<u64> {
+    match n {
+        64 => Some(u64::MAX),
+        0..=63 => Some((1 << n) - 1),
+        _ => None,
+    }
+}
+
+/// Attributes of coherent allocations.
+pub mod attrs {
+    use crate::bindings;
+
+    /// Maps the buffer write-combined instead of uncached, on architectures that support it.
+    pub const WRITE_COMBINE: usize = bindings::DMA_ATTR_WRITE_COMBINE as _;
+
+    /// Does not create a kernel mapping of the buffer; it is only accessed by the device or
+    /// mapped to user space.
+    pub const NO_KERNEL_MAPPING: usize = bindings::DMA_ATTR_NO_KERNEL_MAPPING as _;
+
+    /// Does not warn if the allocation fails.
+    pub const NO_WARN: usize = bindings::DMA_ATTR_NO_WARN as _;
+
+    /// Allocates physically contiguous memory even if an IOMMU could make it contiguous in the
+    /// device address space only.
+    pub const FORCE_CONTIGUOUS: usize = bindings::DMA_ATTR_FORCE_CONTIGUOUS as _;
+}
+
+/// A buffer allocated with the coherent DMA API.
+///
+/// The buffer is visible to both the CPU and the device without explicit synchronisation. It is
+/// freed when the allocation is dropped.
+///
+/// # Invariants
+///
+/// `cpu_addr` and `dma_handle` were returned by `dma_alloc_attrs` for `dev`, `size` and `attrs`,
+/// and the buffer has not been freed yet. `attrs` does not contain
+/// [`attrs::NO_KERNEL_MAPPING`].
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{device::RawDevice, dma::CoherentAllocation, prelude::*};
+///
+/// fn alloc_ring(dev: &impl RawDevice) -> Result<CoherentAllocation> {
+///     let ring = CoherentAllocation::alloc(dev, 4096, GFP_KERNEL | __GFP_ZERO, 0)?;
+///     pr_info!("ring at {:#x}\n", ring.dma_handle());
+///     Ok(ring)
+/// }
+/// ```
+pub struct CoherentAllocation {
+    dev: ARef<Device>,
+    cpu_addr: NonNull<u8>,
+    dma_handle: bindings::dma_addr_t,
+    size: usize,
+    attrs: usize,
+}
+
+impl CoherentAllocation {
+    /// Allocates a coherent buffer of `size` bytes for `dev`.
+    ///
+    /// `attrs` is a combination of the constants in the [`attrs`] module, except
+    /// [`attrs::NO_KERNEL_MAPPING`], which is rejected with [`EINVAL`].
+    pub fn alloc(dev: &impl RawDevice, size: usize, flags: Flags, attrs: usize) -> Result<Self> {
+        if size == 0 || attrs & attrs::NO_KERNEL_MAPPING != 0 {
+            return Err(EINVAL);
+        }
+
+        let mut dma_handle = 0;
+        // SAFETY: `dev.raw_device()` is valid by the safety requirements of `RawDevice`, and
+        // `dma_handle` is valid for writes.
+        let cpu_addr = unsafe {
+            bindings::dma_alloc_attrs(
+                dev.raw_device(),
+                size,
+                &mut dma_handle,
+                flags.as_raw(),
+                attrs as _,
+            )
+        };
+        let cpu_addr = NonNull::new(cpu_addr.cast::<u8>()).ok_or(ENOMEM)?;
+
+        // SAFETY: `dev.raw_device()` is valid and has a non-zero reference count.
+        let dev = unsafe { Device::new(dev.raw_device()) };
+
+        // INVARIANT: The buffer was just allocated with these parameters.
+        Ok(Self {
+            dev,
+            cpu_addr,
+            dma_handle,
+            size,
+            attrs,
+        })
+    }
+
+    /// Allocates a physically contiguous buffer of at most `size` and at least `min_size` bytes.
+    ///
+    /// Large contiguous allocations can fail once memory is fragmented or the CMA area is
+    /// exhausted. This first tries to allocate `size` bytes and then keeps halving the size, down
+    /// to `min_size`, until an allocation succeeds; [`CoherentAllocation::size`] returns the size
+    /// that was obtained. Only the last attempt warns on failure.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kernel::{device::RawDevice, dma::CoherentAllocation, prelude::*, sizes::*};
+    ///
+    /// fn alloc_frame_buffer(dev: &impl RawDevice) -> Result<CoherentAllocation> {
+    ///     let buf = CoherentAllocation::alloc_contiguous(dev, SZ_16M, SZ_4M, GFP_KERNEL)?;
+    ///     dev_info!(dev, "frame buffer of {} bytes\n", buf.size());
+    ///     Ok(buf)
+    /// }
+    /// ```
+    pub fn alloc_contiguous(
+        dev: &impl RawDevice,
+        size: usize,
+        min_size: usize,
+        flags: Flags,
+    ) -> Result<Self> {
+        let min_size = min_size.max(1);
+        if size < min_size {
+            return Err(EINVAL);
+        }
+
+        let mut size = size;
+        loop {
+            let last = size / 2 < min_size;
+            let (flags, attrs) = if last {
+                (flags, attrs::FORCE_CONTIGUOUS)
+            } else {
+                (
+                    flags | __GFP_NOWARN,
+                    attrs::FORCE_CONTIGUOUS | attrs::NO_WARN,
+                )
+            };
+            match Self::alloc(dev, size, flags, attrs) {
+                Err(e) if !last && e == ENOMEM => size /= 2,
+                r => return r,
+            }
+        }
+    }
+
+    /// Returns the size of the buffer in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the address of the buffer in the device address space.
+    ///
+    /// This is the address to program into the device.
+    pub fn dma_handle(&self) -> bindings::dma_addr_t {
+        self.dma_handle
+    }
+
+    /// Returns a raw pointer to the start of the buffer.
+    pub fn as_ptr(&self) -> *const u8 {
+        self.cpu_addr.as_ptr()
+    }
+
+    /// Returns a raw mutable pointer to the start of the buffer.
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.cpu_addr.as_ptr()
+    }
+
+    /// Returns the buffer as a slice.
+    ///
+    /// # Safety
+    ///
+    /// Callers must ensure that the device does not write to the buffer while the slice is alive.
+    pub unsafe fn as_slice(&self) -> &[u8] {
+        // SAFETY: By the type invariants, the buffer is mapped and valid for `size` bytes. By the
+        // safety requirements, it is not modified while the slice is alive.
+        unsafe { core::slice::from_raw_parts(self.cpu_addr.as_ptr(), self.size) }
+    }
+
+    /// Returns the buffer as a mutable slice.
+    ///
+    /// # Safety
+    ///
+    /// Callers must ensure that the device does not access the buffer while the slice is alive.
+    pub unsafe fn as_slice_mut(&mut self) -> &mut [u8] {
+        // SAFETY: By the type invariants, the buffer is mapped and valid for `size` bytes. By the
+        // safety requirements and since `self` is borrowed mutably, nothing else accesses it
+        // while the slice is alive.
+        unsafe { core::slice::from_raw_parts_mut(self.cpu_addr.as_ptr(), self.size) }
+    }
+
+    /// Copies `data` into the buffer, starting at `offset`.
+    ///
+    /// Fails with [`EINVAL`] if the range does not fit in the buffer.
+    pub fn write(&mut self, offset: usize, data: &[u8]) -> Result {
+        let end = offset.checked_add(data.len()).ok_or(EINVAL)?;
+        if end > self.size {
+            return Err(EINVAL);
+        }
+        // SAFETY: The range was checked to be within the buffer, which is mapped by the type
+        // invariants. `data` cannot overlap with it since the buffer is only reachable through
+        // `self`, which is borrowed mutably.
+        unsafe {
+            ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                self.cpu_addr.as_ptr().add(offset),
+                data.len(),
+            )
+        };
+        Ok(())
+    }
+
+    /// Copies from the buffer, starting at `offset`, into `data`.
+    ///
+    /// Fails with [`EINVAL`] if the range does not fit in the buffer.
+    pub fn read(&self, offset: usize, data: &mut [u8]) -> Result {
+        let end = offset.checked_add(data.len()).ok_or(EINVAL)?;
+        if end > self.size {
+            return Err(EINVAL);
+        }
+        // SAFETY: The range was checked to be within the buffer, which is mapped by the type
+        // invariants. `data` is a unique reference, so it cannot overlap with the buffer.
+        unsafe {
+            ptr::copy_nonoverlapping(
+                self.cpu_addr.as_ptr().add(offset),
+                data.as_mut_ptr(),
+                data.len(),
+            )
+        };
+        Ok(())
+    }
+}
+
+impl Drop for CoherentAllocation {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, the buffer was allocated for `dev` with these
+        // parameters and has not been freed yet.
+        unsafe {
+            bindings::dma_free_attrs(
+                self.dev.raw_device(),
+                self.size,
+                self.cpu_addr.as_ptr().cast(),
+                self.dma_handle,
+                self.attrs as _,
+            )
+        };
+    }
+}
+
+// SAFETY: The buffer can be freed from any thread, and `Device` is `Send`.
+unsafe impl Send for CoherentAllocation {}
+
+// SAFETY: Methods that access the buffer through `&self` only read from it.
+unsafe impl Sync for CoherentAllocation {}
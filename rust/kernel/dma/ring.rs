@@ -0,0 +1,368 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Descriptor rings shared with a device.
+//!
+//! Most DMA-capable devices are driven through a ring of descriptors in coherent memory: the
+//! driver fills descriptors and tells the device how far it got, usually by writing the index of
+//! the next free slot to a doorbell register; the device processes them in order and reports
+//! their completion, either by writing a status back to each descriptor or by advancing an index
+//! of its own. [`DescRing`] implements the driver side of such a ring, including the ordering of
+//! the accesses against the device and the wraparound of the indices, and keeps a context, e.g.,
+//! the buffer or request of the transfer, for every descriptor until it is reaped.
+//!
+//! Indices count descriptors and wrap at 2^32; the slot of index `i` is `i % capacity`.
+
+use super::CoherentAllocation;
+use crate::{bindings, device::RawDevice, prelude::*};
+use core::{marker::PhantomData, mem::size_of};
+
+/// A hardware descriptor that can be stored in a [`DescRing`].
+///
+/// # Safety
+///
+/// The device can write arbitrary bytes to the descriptors, so implementers must be valid for
+/// every bit pattern. They must not contain padding, which would leak kernel memory to the
+/// device, and their alignment must be at most `PAGE_SIZE`.
+///
+/// # Examples
+///
+/// A descriptor that the device polls, and that it owns while the `OWN` bit is set, must have
+/// the bit set only once the rest of it is visible to the device:
+///
+/// ```
+/// use core::ptr::addr_of_mut;
+/// use kernel::{dma::Descriptor, sync::atomic::AtomicCell};
+///
+/// const OWN: u32 = 1 << 31;
+///
+/// #[repr(C)]
+/// #[derive(Clone, Copy)]
+/// struct RxDesc {
+///     addr: u64,
+///     len: u32,
+///     ctrl: u32,
+/// }
+///
+/// // SAFETY: `RxDesc` only holds integers, without padding.
+/// unsafe impl Descriptor for RxDesc {
+///     fn is_complete(&self) -> bool {
+///         self.ctrl & OWN == 0
+///     }
+///
+///     unsafe fn write(slot: *mut Self, desc: Self) {
+///         // SAFETY: `slot` is valid and aligned, by the safety requirements.
+///         unsafe {
+///             addr_of_mut!((*slot).addr).write_volatile(desc.addr);
+///             addr_of_mut!((*slot).len).write_volatile(desc.len);
+///             AtomicCell::from_ptr(addr_of_mut!((*slot).ctrl)).store_release(desc.ctrl | OWN);
+///         }
+///     }
+/// }
+/// ```
+pub unsafe trait Descriptor: Copy {
+    /// Returns `true` if the device completed the descriptor, as written back by it.
+    ///
+    /// This is only used by [`DescRing::reap`]. Devices that report completions through an index
+    /// instead, which is passed to [`DescRing::reap_to`], return `false`.
+    fn is_complete(&self) -> bool;
+
+    /// Writes `desc` to `slot`, which the device may be polling.
+    ///
+    /// The default writes the whole descriptor at once, which is correct for devices that only
+    /// read the descriptors after being told about them, e.g., through a doorbell register.
+    ///
+    /// # Safety
+    ///
+    /// `slot` must be valid for writes and aligned.
+    unsafe fn write(slot: *mut Self, desc: Self) {
+        // SAFETY: `slot` is valid and aligned, by the safety requirements.
+        unsafe { slot.write_volatile(desc) };
+    }
+}
+
+/// A ring of descriptors of type `D` in coherent memory, each with a context of type `T`.
+///
+/// Descriptors are added with [`DescRing::push`] and handed back with their context, in order, by
+/// [`DescRing::reap`] or [`DescRing::reap_to`] once the device completed them. All of these take
+/// `&mut self`: drivers that fill or reap the ring from several contexts, e.g., the transmit path
+/// and the NAPI poll of a NIC, need to serialise them, e.g., with a spinlock.
+///
+/// The device must not write to the descriptors outside of the range of submitted descriptors,
+/// i.e., from [`DescRing::tail`] to [`DescRing::head`].
+///
+/// # Invariants
+///
+/// `mem` holds `len` descriptors, and `len` is a power of two of at least two. `head - tail`,
+/// with wrapping, is at most `len - 1`. The `ctx` slots of the indices from `tail` to `head` are
+/// [`Some`], the others [`None`].
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{
+///     device::RawDevice,
+///     dma::{DescRing, Descriptor},
+///     io_mem::Io,
+///     prelude::*,
+/// };
+///
+/// const TX_TAIL: usize = 0x10;
+/// const DONE: u32 = 1 << 31;
+///
+/// #[repr(C)]
+/// #[derive(Clone, Copy)]
+/// struct TxDesc {
+///     addr: u64,
+///     len: u32,
+///     status: u32,
+/// }
+///
+/// // SAFETY: `TxDesc` only holds integers, without padding.
+/// unsafe impl Descriptor for TxDesc {
+///     fn is_complete(&self) -> bool {
+///         self.status & DONE != 0
+///     }
+/// }
+///
+/// /// The context of a descriptor is the tag of the request it transfers.
+/// type TxRing = DescRing<TxDesc, u16>;
+///
+/// fn new_ring(dev: &impl RawDevice) -> Result<TxRing> {
+///     // The address of the ring is then programmed into the device with `ring.dma_handle()`.
+///     DescRing::new(dev, 256)
+/// }
+///
+/// fn submit(ring: &mut TxRing, regs: &impl Io, addr: u64, len: u32, tag: u16) -> Result {
+///     ring.push(TxDesc { addr, len, status: 0 }, tag)?;
+///     // The MMIO write is ordered after the descriptor.
+///     regs.try_writel(ring.head(), TX_TAIL)
+/// }
+///
+/// fn clean(ring: &mut TxRing, budget: u32) -> u32 {
+///     ring.reap(budget, |_desc, tag| pr_debug!("request {} done\n", tag))
+/// }
+/// ```
+pub struct DescRing<D: Descriptor, T = ()> {
+    mem: CoherentAllocation,
+    ctx: Vec<Option<T>>,
+    len: u32,
+    head: u32,
+    tail: u32,
+    _p: PhantomData<D>,
+}
+
+impl<D: Descriptor, T> DescRing<D, T> {
+    /// Allocates a zeroed ring of at least `len` descriptors for `dev`.
+    ///
+    /// `len` is rounded up to the next power of two, and to at least two, and must be at most
+    /// `2^31`. One descriptor is always kept free, see [`DescRing::capacity`].
+    pub fn new(dev: &impl RawDevice, len: u32) -> Result<Self> {
+        if len == 0 || len > 1 << 31 || size_of::<D>() == 0 {
+            return Err(EINVAL);
+        }
+        let len = len.max(2).next_power_of_two();
+        let size = size_of::<D>().checked_mul(len as usize).ok_or(ENOMEM)?;
+        let mem = CoherentAllocation::alloc(dev, size, GFP_KERNEL | __GFP_ZERO, 0)?;
+        let mut ctx = Vec::with_capacity(len as usize, GFP_KERNEL)?;
+        for _ in 0..len {
+            ctx.push(None, GFP_KERNEL)?;
+        }
+        // INVARIANT: The allocation holds `len` descriptors, a power of two of at least two, and
+        // the ring is empty.
+        Ok(Self {
+            mem,
+            ctx,
+            len,
+            head: 0,
+            tail: 0,
+            _p: PhantomData,
+        })
+    }
+
+    /// Returns the number of slots of the ring.
+    ///
+    /// At most `capacity - 1` descriptors are pending at any time: one slot is always kept free,
+    /// so that a full ring cannot be mistaken for an empty one, e.g., by [`DescRing::reap_to`].
+    pub fn capacity(&self) -> u32 {
+        self.len
+    }
+
+    /// Returns the address of the ring in the device address space.
+    pub fn dma_handle(&self) -> bindings::dma_addr_t {
+        self.mem.dma_handle()
+    }
+
+    /// Returns the address of the descriptor of `index` in the device address space, e.g., for
+    /// devices that chain descriptors.
+    pub fn dma_handle_of(&self, index: u32) -> bindings::dma_addr_t {
+        self.mem.dma_handle() + (self.slot(index) * size_of::<D>()) as bindings::dma_addr_t
+    }
+
+    /// Returns the slot of the next descriptor to be pushed, i.e., the value of the doorbell or
+    /// tail register of most devices.
+    pub fn head(&self) -> u32 {
+        self.head & (self.len - 1)
+    }
+
+    /// Returns the slot of the oldest descriptor that was not reaped yet.
+    pub fn tail(&self) -> u32 {
+        self.tail & (self.len - 1)
+    }
+
+    /// Returns the number of descriptors that were pushed but not reaped yet.
+    pub fn pending(&self) -> u32 {
+        self.head.wrapping_sub(self.tail)
+    }
+
+    /// Returns the number of descriptors that can be pushed before the ring is full.
+    pub fn space(&self) -> u32 {
+        // No underflow: by the type invariants, at most `len - 1` descriptors are pending.
+        self.len - 1 - self.pending()
+    }
+
+    /// Returns `true` if no descriptor is pending.
+    pub fn is_empty(&self) -> bool {
+        self.pending() == 0
+    }
+
+    /// Returns `true` if no descriptor can be pushed.
+    pub fn is_full(&self) -> bool {
+        self.space() == 0
+    }
+
+    fn slot(&self, index: u32) -> usize {
+        (index & (self.len - 1)) as usize
+    }
+
+    fn desc_ptr(&self, index: u32) -> *mut D {
+        // SAFETY: By the type invariants, the allocation holds `len` descriptors, and the slot is
+        // smaller than `len`. The allocation is page aligned, so the descriptors are aligned too.
+        unsafe {
+            self.mem
+                .as_ptr()
+                .cast_mut()
+                .cast::<D>()
+                .add(self.slot(index))
+        }
+    }
+
+    /// Adds `desc` to the ring, with the context `ctx`, and returns its slot.
+    ///
+    /// The device must be told about the descriptor afterwards, e.g., by writing
+    /// [`DescRing::head`] to its doorbell register. MMIO writes are ordered after the descriptor;
+    /// devices with a doorbell in memory need a [`AtomicCell::store_release`].
+    ///
+    /// Fails with [`ENOSPC`] if the ring is full, i.e., `capacity - 1` descriptors are pending.
+    ///
+    /// [`AtomicCell::store_release`]: crate::sync::atomic::AtomicCell::store_release
+    pub fn push(&mut self, desc: D, ctx: T) -> Result<u32> {
+        if self.is_full() {
+            return Err(ENOSPC);
+        }
+        let index = self.head;
+        // SAFETY: The slot is valid and aligned, and outside of the range of submitted
+        // descriptors, so the device does not access it.
+        unsafe { D::write(self.desc_ptr(index), desc) };
+        let slot = self.slot(index);
+        // INVARIANT: The context of the new descriptor is set before it is included in the range.
+        // The ring was not full, so at most `len - 1` descriptors are pending afterwards.
+        self.ctx[slot] = Some(ctx);
+        self.head = index.wrapping_add(1);
+        Ok(slot as u32)
+    }
+
+    /// Reads the oldest pending descriptor and takes its context, after the device completed it.
+    ///
+    /// Returns [`None`] if the ring is empty.
+    fn take(&mut self) -> Option<(D, T)> {
+        if self.is_empty() {
+            return None;
+        }
+        // SAFETY: The slot is valid and aligned. `D` is valid for all bit patterns, so reading it
+        // is fine even if the device misbehaves and writes to it concurrently.
+        let desc = unsafe { self.desc_ptr(self.tail).read_volatile() };
+        let slot = self.slot(self.tail);
+        // The context is `Some`, since the descriptor is pending.
+        let ctx = self.ctx[slot].take()?;
+        // INVARIANT: The context was taken, and `tail` is advanced past it.
+        self.tail = self.tail.wrapping_add(1);
+        Some((desc, ctx))
+    }
+
+    /// Reaps the descriptors that the device marked as complete, see [`Descriptor::is_complete`],
+    /// oldest first and at most `budget`, and returns how many were reaped.
+    ///
+    /// `f` is called with each descriptor, as written back by the device, and its context. Reaping
+    /// stops at the first descriptor that is not complete.
+    pub fn reap(&mut self, budget: u32, mut f: impl FnMut(D, T)) -> u32 {
+        let mut n = 0;
+        while n < budget && !self.is_empty() {
+            // SAFETY: The slot is valid and aligned, and `D` is valid for all bit patterns.
+            let desc = unsafe { self.desc_ptr(self.tail).read_volatile() };
+            if !desc.is_complete() {
+                break;
+            }
+            // Orders the reads of the rest of the descriptor, in `take`, after its status.
+            // SAFETY: `dma_rmb` can be called from any context.
+            unsafe { bindings::dma_rmb() };
+            let Some((desc, ctx)) = self.take() else {
+                break;
+            };
+            f(desc, ctx);
+            n += 1;
+        }
+        n
+    }
+
+    /// Reaps the descriptors up to, but not including, slot `hw_tail`, which is the next
+    /// descriptor the device processes, e.g., as read from its head register. Returns how many
+    /// were reaped.
+    ///
+    /// `f` is called with each descriptor and its context, oldest first.
+    ///
+    /// [`DescRing::push`] always keeps one slot free, so `hw_tail` equal to [`DescRing::tail`]
+    /// always means that nothing was completed, never that a full ring was.
+    ///
+    /// Fails with [`EINVAL`], without reaping anything, if `hw_tail` is not a slot of the ring or
+    /// is not within the pending descriptors, i.e., the device is confused.
+    pub fn reap_to(&mut self, hw_tail: u32, mut f: impl FnMut(D, T)) -> Result<u32> {
+        if hw_tail >= self.len {
+            return Err(EINVAL);
+        }
+        // At most `len - 1` descriptors are pending, so `n == 0` can only mean that none of them
+        // were completed.
+        let n = hw_tail.wrapping_sub(self.tail) & (self.len - 1);
+        if n > self.pending() {
+            return Err(EINVAL);
+        }
+        // Orders the reads of the descriptors after the read of the index, for devices that write
+        // it back to memory.
+        // SAFETY: `dma_rmb` can be called from any context.
+        unsafe { bindings::dma_rmb() };
+        for _ in 0..n {
+            if let Some((desc, ctx)) = self.take() {
+                f(desc, ctx);
+            }
+        }
+        Ok(n)
+    }
+
+    /// Takes the contexts of all pending descriptors, oldest first, and empties the ring.
+    ///
+    /// This is used once the device was stopped, e.g., to free the buffers of the transfers that
+    /// did not complete. The descriptors are zeroed, and the ring starts again at slot zero, as
+    /// after a reset of the device.
+    pub fn drain(&mut self, mut f: impl FnMut(T)) {
+        while let Some((_, ctx)) = self.take() {
+            f(ctx);
+        }
+        for i in 0..self.len {
+            // SAFETY: The slot is valid and aligned, and all zeroes is a valid `D`, which is valid
+            // for all bit patterns. The write is volatile in case the device was not stopped.
+            unsafe { self.desc_ptr(i).write_volatile(core::mem::zeroed()) };
+        }
+        // INVARIANT: The ring is empty.
+        self.head = 0;
+        self.tail = 0;
+    }
+}
@@ -0,0 +1,361 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Streaming DMA mappings.
+//!
+//! Buffers that a device only accesses for a single transfer, e.g., the payload of a network
+//! packet, are mapped for the duration of the transfer instead of being allocated as coherent
+//! memory. While a buffer is mapped, it belongs to the device: the CPU must not access it until it
+//! is unmapped again, which also makes the data written by the device visible to the CPU on
+//! systems without coherent DMA, or copies it back from a SWIOTLB bounce buffer.
+//!
+//! [`StreamingMapping`] maps a single buffer, and [`SgMapping`] several buffers through a
+//! scatter-gather table, which an IOMMU may merge into fewer segments of the device address space.
+//!
+//! C header: [`include/linux/dma-mapping.h`](srctree/include/linux/dma-mapping.h)
+
+use crate::{
+    bindings,
+    device::{Device, RawDevice},
+    error::to_result,
+    prelude::*,
+    types::ARef,
+};
+use core::marker::PhantomData;
+
+/// The direction of a transfer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// The device reads the buffer, e.g., for a write to a disk.
+    ToDevice,
+
+    /// The device writes the buffer, e.g., for a read from a disk.
+    FromDevice,
+}
+
+impl Direction {
+    pub(crate) fn as_raw(self) -> bindings::dma_data_direction {
+        match self {
+            Self::ToDevice => bindings::dma_data_direction_DMA_TO_DEVICE,
+            Self::FromDevice => bindings::dma_data_direction_DMA_FROM_DEVICE,
+        }
+    }
+}
+
+/// A contiguous range of the device address space that is part of a transfer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Segment {
+    /// The address to program into the device.
+    pub addr: bindings::dma_addr_t,
+
+    /// The length of the range in bytes.
+    pub len: usize,
+}
+
+/// A kernel buffer mapped for a single transfer by a device.
+///
+/// The buffer is unmapped when this object is dropped, or handed back by
+/// [`StreamingMapping::unmap`].
+///
+/// # Invariants
+///
+/// `handle` is the mapping of `buf` for `dev` in direction `dir`, returned by a successful call to
+/// `dma_map_single_attrs`.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{
+///     device::RawDevice,
+///     dma::{Direction, StreamingMapping},
+///     prelude::*,
+/// };
+///
+/// fn send(dev: &impl RawDevice, payload: &[u8]) -> Result<Vec<u8>> {
+///     let mut buf = Vec::new();
+///     buf.extend_from_slice(payload, GFP_KERNEL)?;
+///     let map = StreamingMapping::new(dev, buf, Direction::ToDevice)?;
+///     dev_dbg!(dev, "dma {:#x} len {}\n", map.dma_handle(), map.len());
+///     // Start the transfer and wait for it to complete.
+///     Ok(map.unmap())
+/// }
+/// ```
+pub struct StreamingMapping {
+    dev: ARef<Device>,
+    buf: Vec<u8>,
+    handle: bindings::dma_addr_t,
+    dir: Direction,
+}
+
+impl StreamingMapping {
+    /// Maps `buf` for a transfer by `dev` in direction `dir`.
+    ///
+    /// Fails with [`EINVAL`] if `buf` is empty, and with [`ENOMEM`] if it cannot be mapped, e.g.,
+    /// because the IOMMU address space or the SWIOTLB of the device is exhausted.
+    pub fn new(dev: &impl RawDevice, buf: Vec<u8>, dir: Direction) -> Result<Self> {
+        if buf.is_empty() {
+            return Err(EINVAL);
+        }
+
+        // SAFETY: `dev.raw_device()` is valid by the safety requirements of `RawDevice`. `buf` is
+        // allocated with `kmalloc`, so it is physically contiguous, and it is not accessed by the
+        // CPU while it is mapped, as it is only reachable through the returned object.
+        let handle = unsafe {
+            bindings::dma_map_single_attrs(
+                dev.raw_device(),
+                buf.as_ptr().cast_mut().cast(),
+                buf.len(),
+                dir.as_raw(),
+                0,
+            )
+        };
+        // SAFETY: `handle` was just returned by `dma_map_single_attrs` for `dev`.
+        to_result(unsafe { bindings::dma_mapping_error(dev.raw_device(), handle) })?;
+
+        // SAFETY: `dev.raw_device()` is valid and has a non-zero reference count.
+        let dev = unsafe { Device::new(dev.raw_device()) };
+        // INVARIANT: `buf` was just mapped for `dev` in `dir`.
+        Ok(Self {
+            dev,
+            buf,
+            handle,
+            dir,
+        })
+    }
+
+    /// Returns the address of the buffer in the device address space.
+    pub fn dma_handle(&self) -> bindings::dma_addr_t {
+        self.handle
+    }
+
+    /// Returns the length of the buffer in bytes.
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Returns `true` if the buffer is empty, which it never is.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Unmaps the buffer, after the device finished its transfer, and returns it.
+    pub fn unmap(self) -> Vec<u8> {
+        let mut this = core::mem::ManuallyDrop::new(self);
+        this.release();
+        let buf = core::mem::take(&mut this.buf);
+        // SAFETY: `this.dev` is valid and is not used after this, as `this` is not dropped.
+        unsafe { core::ptr::drop_in_place(&mut this.dev) };
+        buf
+    }
+
+    fn release(&mut self) {
+        // SAFETY: By the type invariants, `handle` is the mapping of `buf` for `dev` in `dir`. It
+        // is unmapped exactly once, as both callers consume the object.
+        unsafe {
+            bindings::dma_unmap_single_attrs(
+                self.dev.raw_device(),
+                self.handle,
+                self.buf.len(),
+                self.dir.as_raw(),
+                0,
+            )
+        };
+    }
+}
+
+impl Drop for StreamingMapping {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+// SAFETY: The mapping can be used and released from any thread.
+unsafe impl Send for StreamingMapping {}
+
+// SAFETY: `StreamingMapping` has no methods that mutate it through a shared reference.
+unsafe impl Sync for StreamingMapping {}
+
+/// Kernel buffers mapped for a single transfer by a device through a scatter-gather table.
+///
+/// The buffers are unmapped when this object is dropped, or handed back by [`SgMapping::unmap`].
+///
+/// # Invariants
+///
+/// `sgt` has one entry for each of the `bufs`, and is mapped for `dev` in direction `dir` with
+/// `dma_map_sgtable`.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{
+///     device::RawDevice,
+///     dma::{Direction, SgMapping},
+///     prelude::*,
+/// };
+///
+/// fn receive(dev: &impl RawDevice, bufs: Vec<Vec<u8>>) -> Result<Vec<Vec<u8>>> {
+///     let map = SgMapping::new(dev, bufs, Direction::FromDevice)?;
+///     for seg in map.segments() {
+///         dev_dbg!(dev, "dma {:#x} len {}\n", seg.addr, seg.len);
+///         // Queue the segment to the device.
+///     }
+///     // Wait for the device to complete the transfer.
+///     Ok(map.unmap())
+/// }
+/// ```
+pub struct SgMapping {
+    dev: ARef<Device>,
+    bufs: Vec<Vec<u8>>,
+    sgt: bindings::sg_table,
+    dir: Direction,
+}
+
+impl SgMapping {
+    /// Maps `bufs` for a transfer by `dev` in direction `dir`.
+    ///
+    /// Fails with [`EINVAL`] if there are no buffers or one of them is empty.
+    pub fn new(dev: &impl RawDevice, bufs: Vec<Vec<u8>>, dir: Direction) -> Result<Self> {
+        if bufs.is_empty() || bufs.iter().any(|b| b.is_empty()) {
+            return Err(EINVAL);
+        }
+        let nents = u32::try_from(bufs.len()).map_err(|_| EINVAL)?;
+
+        // SAFETY: All zeroes is a valid value of an empty table.
+        let mut sgt: bindings::sg_table = unsafe { core::mem::zeroed() };
+        // SAFETY: `sgt` is valid for writes.
+        to_result(unsafe { bindings::sg_alloc_table(&mut sgt, nents, GFP_KERNEL.as_raw()) })?;
+
+        let mut sg = sgt.sgl;
+        for (i, buf) in bufs.iter().enumerate() {
+            // SAFETY: `sg` is entry `i` of the `nents` entries of the table. `buf` is allocated
+            // with `kmalloc`, so its pages can be found with `virt_to_page`.
+            unsafe {
+                bindings::sg_set_buf(sg, buf.as_ptr().cast(), buf.len() as _);
+                if i + 1 < bufs.len() {
+                    sg = bindings::sg_next(sg);
+                }
+            }
+        }
+
+        // SAFETY: `sgt` is a valid table, and `dev.raw_device()` is a valid device. The buffers
+        // are not accessed by the CPU while they are mapped, as they are only reachable through
+        // the returned object.
+        let ret = unsafe { bindings::dma_map_sgtable(dev.raw_device(), &mut sgt, dir.as_raw(), 0) };
+        if let Err(e) = to_result(ret) {
+            // SAFETY: `sgt` was allocated above, and is not mapped.
+            unsafe { bindings::sg_free_table(&mut sgt) };
+            return Err(e);
+        }
+
+        // SAFETY: `dev.raw_device()` is valid and has a non-zero reference count.
+        let dev = unsafe { Device::new(dev.raw_device()) };
+        // INVARIANT: The table has one entry for each buffer, and was just mapped.
+        Ok(Self {
+            dev,
+            bufs,
+            sgt,
+            dir,
+        })
+    }
+
+    /// Returns the segments of the device address space to transfer, in order.
+    ///
+    /// There may be fewer segments than buffers, e.g., if an IOMMU mapped the buffers next to each
+    /// other.
+    pub fn segments(&self) -> Segments<'_> {
+        // SAFETY: By the type invariants, the table is mapped, and it stays mapped while `self` is
+        // borrowed.
+        unsafe { Segments::from_sgt(&self.sgt) }
+    }
+
+    /// Unmaps the buffers, after the device finished its transfer, and returns them.
+    pub fn unmap(self) -> Vec<Vec<u8>> {
+        let mut this = core::mem::ManuallyDrop::new(self);
+        this.release();
+        let bufs = core::mem::take(&mut this.bufs);
+        // SAFETY: `this.dev` is valid and is not used after this, as `this` is not dropped.
+        unsafe { core::ptr::drop_in_place(&mut this.dev) };
+        bufs
+    }
+
+    fn release(&mut self) {
+        // SAFETY: By the type invariants, the table is mapped for `dev` in `dir`. It is unmapped
+        // and freed exactly once, as both callers consume the object.
+        unsafe {
+            bindings::dma_unmap_sgtable(self.dev.raw_device(), &mut self.sgt, self.dir.as_raw(), 0);
+            bindings::sg_free_table(&mut self.sgt);
+        }
+    }
+}
+
+impl Drop for SgMapping {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+// SAFETY: The mapping can be used and released from any thread.
+unsafe impl Send for SgMapping {}
+
+// SAFETY: `SgMapping` has no methods that mutate it through a shared reference.
+unsafe impl Sync for SgMapping {}
+
+/// An iterator over the [`Segment`]s of a mapped transfer.
+pub struct Segments<'a> {
+    sg: *mut bindings::scatterlist,
+    left: usize,
+    single: Option<Segment>,
+    _p: PhantomData<&'a ()>,
+}
+
+impl<'a> Segments<'a> {
+    /// Creates an iterator over the mapped entries of `sgt`.
+    ///
+    /// # Safety
+    ///
+    /// `sgt` must be mapped with `dma_map_sgtable`, and stay mapped for `'a`.
+    pub(crate) unsafe fn from_sgt(sgt: &'a bindings::sg_table) -> Self {
+        Self {
+            sg: sgt.sgl,
+            left: sgt.nents as usize,
+            single: None,
+            _p: PhantomData,
+        }
+    }
+
+    /// Creates an iterator over the single segment `seg`.
+    pub(crate) fn single(seg: Segment) -> Self {
+        Self {
+            sg: core::ptr::null_mut(),
+            left: 1,
+            single: Some(seg),
+            _p: PhantomData,
+        }
+    }
+}
+
+impl Iterator for Segments<'_> {
+    type Item = Segment;
+
+    fn next(&mut self) -> Option<Segment> {
+        if self.left == 0 {
+            return None;
+        }
+        self.left -= 1;
+        if let Some(seg) = self.single {
+            return Some(seg);
+        }
+        // SAFETY: `sg` is one of the `nents` mapped entries of the table, which stays mapped for
+        // the lifetime of the iterator.
+        let seg = unsafe {
+            Segment {
+                addr: bindings::sg_dma_address(self.sg),
+                len: bindings::sg_dma_len(self.sg) as usize,
+            }
+        };
+        if self.left != 0 {
+            // SAFETY: There are more mapped entries after `sg`.
+            self.sg = unsafe { bindings::sg_next(self.sg) };
+        }
+        Some(seg)
+    }
+}
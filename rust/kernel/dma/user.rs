@@ -0,0 +1,296 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! DMA from and to userspace buffers.
+//!
+//! Storage-style drivers transfer data directly between the device and the buffer that userspace
+//! passed in, e.g., with `O_DIRECT`. That only works if the buffer meets the alignment
+//! constraints of the device, which userspace does not have to honour. [`UserIo`] maps the
+//! buffer directly when it can, and otherwise transparently goes through a bounce buffer, so that
+//! drivers handle both cases with the same code.
+//!
+//! C header: [`include/linux/scatterlist.h`](srctree/include/linux/scatterlist.h)
+
+pub use super::streaming::{Direction, Segment, Segments};
+
+use super::CoherentAllocation;
+use crate::{
+    bindings,
+    device::{Device, RawDevice},
+    prelude::*,
+    types::ARef,
+};
+
+const PAGE_SIZE: usize = bindings::PAGE_SIZE as usize;
+
+/// The constraints of a device on the buffers it transfers data from or to.
+#[derive(Clone, Copy, Debug)]
+pub struct Constraints {
+    /// The alignment of the start of buffers, in bytes. Must be a power of two.
+    pub addr_align: usize,
+
+    /// The granularity of the length of buffers, e.g., the logical block size. Must be a power of
+    /// two.
+    pub len_align: usize,
+}
+
+enum Mapping {
+    /// The pages of the user buffer, pinned and mapped through a scatter-gather table.
+    Direct {
+        pages: Vec<*mut bindings::page>,
+        sgt: bindings::sg_table,
+    },
+
+    /// A bounce buffer, at least as long as the user buffer rounded up to the length alignment.
+    Bounce(CoherentAllocation),
+}
+
+/// A userspace buffer prepared for a transfer by a device.
+///
+/// The buffer is mapped directly if the start and the length of each of its segments meet the
+/// [`Constraints`] of the device, and bounced otherwise. When bouncing, the data is copied from
+/// userspace when the transfer is prepared, for [`Direction::ToDevice`], or to userspace by
+/// [`UserIo::finish`], for [`Direction::FromDevice`]; the part of the bounce buffer beyond the
+/// user buffer, which the device may transfer when the length is not aligned, is zeroed.
+///
+/// The mapping is released when the object is dropped, after which the device must not access
+/// the memory anymore.
+///
+/// # Invariants
+///
+/// For [`Mapping::Direct`], `pages` are the pinned pages of the user buffer, and `sgt` is a table
+/// of them that is mapped for `dev` in direction `dir`.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{
+///     device::RawDevice,
+///     dma::user::{Constraints, Direction, UserIo},
+///     prelude::*,
+/// };
+///
+/// const CONSTRAINTS: Constraints = Constraints { addr_align: 512, len_align: 512 };
+///
+/// fn read_blocks(dev: &impl RawDevice, user_addr: usize, len: usize) -> Result {
+///     let io = UserIo::new(dev, user_addr, len, Direction::FromDevice, &CONSTRAINTS)?;
+///     for seg in io.segments() {
+///         dev_dbg!(dev, "dma {:#x} len {}\n", seg.addr, seg.len);
+///         // Queue the segment to the device.
+///     }
+///     // Wait for the device to transfer `len` bytes.
+///     io.finish(len)
+/// }
+/// ```
+pub struct UserIo {
+    dev: ARef<Device>,
+    mapping: Mapping,
+    user_addr: usize,
+    len: usize,
+    dir: Direction,
+}
+
+impl UserIo {
+    /// Prepares the transfer of the `len` bytes of userspace memory at `user_addr` by `dev`.
+    ///
+    /// Fails with [`EINVAL`] if `len` is zero or the constraints are not powers of two, and with
+    /// [`EFAULT`] if the buffer is not accessible. This must be called from the context of the
+    /// process that owns the buffer.
+    pub fn new(
+        dev: &impl RawDevice,
+        user_addr: usize,
+        len: usize,
+        dir: Direction,
+        c: &Constraints,
+    ) -> Result<Self> {
+        if len == 0 || !c.addr_align.is_power_of_two() || !c.len_align.is_power_of_two() {
+            return Err(EINVAL);
+        }
+        user_addr.checked_add(len).ok_or(EFAULT)?;
+
+        // Pages are aligned to their size, so only the first and the last segment can be
+        // unaligned, unless the constraints exceed a page. The first segment starts at the offset
+        // of `user_addr` in its page and runs up to the end of the page, and the last one ends at
+        // the offset of `user_addr + len`, so every segment length is a multiple of `len_align` if
+        // both `user_addr` and `len` are.
+        let direct = c.addr_align <= PAGE_SIZE
+            && c.len_align <= PAGE_SIZE
+            && user_addr % c.addr_align == 0
+            && user_addr % c.len_align == 0
+            && len % c.len_align == 0;
+        let mapping = if direct {
+            Self::map_direct(dev, user_addr, len, dir)?
+        } else {
+            Self::map_bounce(dev, user_addr, len, dir, c.len_align)?
+        };
+
+        // SAFETY: `dev.raw_device()` is valid and has a non-zero reference count.
+        let dev = unsafe { Device::new(dev.raw_device()) };
+        Ok(Self {
+            dev,
+            mapping,
+            user_addr,
+            len,
+            dir,
+        })
+    }
+
+    fn map_direct(
+        dev: &impl RawDevice,
+        user_addr: usize,
+        len: usize,
+        dir: Direction,
+    ) -> Result<Mapping> {
+        let offset = user_addr % PAGE_SIZE;
+        let npages = (offset + len).div_ceil(PAGE_SIZE);
+        let mut pages = Vec::with_capacity(npages, GFP_KERNEL)?;
+        let gup_flags = if dir == Direction::FromDevice {
+            bindings::FOLL_WRITE
+        } else {
+            0
+        };
+
+        // SAFETY: `pages` has room for `npages` pointers. The function fails for addresses that
+        // are not accessible userspace memory.
+        let pinned = unsafe {
+            bindings::pin_user_pages_fast(
+                (user_addr - offset) as _,
+                npages as _,
+                gup_flags,
+                pages.as_mut_ptr(),
+            )
+        };
+        if pinned > 0 {
+            // SAFETY: The first `pinned` entries were initialised by `pin_user_pages_fast`, which
+            // never pins more than the `npages` it was asked for.
+            unsafe { pages.set_len(pinned as usize) };
+        }
+        if pinned as usize != npages {
+            Self::unpin(&pages, false);
+            return Err(EFAULT);
+        }
+
+        // SAFETY: All zeroes is a valid value of an empty table.
+        let mut sgt: bindings::sg_table = unsafe { core::mem::zeroed() };
+        // SAFETY: `pages` holds `npages` pinned pages, which cover `offset..offset + len`.
+        let ret = unsafe {
+            bindings::sg_alloc_table_from_pages(
+                &mut sgt,
+                pages.as_mut_ptr(),
+                npages as _,
+                offset as _,
+                len as _,
+                GFP_KERNEL.as_raw(),
+            )
+        };
+        if let Err(e) = crate::error::to_result(ret) {
+            Self::unpin(&pages, false);
+            return Err(e);
+        }
+
+        // SAFETY: `sgt` is a valid table, and `dev.raw_device()` is a valid device.
+        let ret = unsafe { bindings::dma_map_sgtable(dev.raw_device(), &mut sgt, dir.as_raw(), 0) };
+        if let Err(e) = crate::error::to_result(ret) {
+            // SAFETY: `sgt` was allocated above, and is not mapped.
+            unsafe { bindings::sg_free_table(&mut sgt) };
+            Self::unpin(&pages, false);
+            return Err(e);
+        }
+
+        // INVARIANT: The pages are pinned, and the table is mapped for `dev` in `dir`.
+        Ok(Mapping::Direct { pages, sgt })
+    }
+
+    fn map_bounce(
+        dev: &impl RawDevice,
+        user_addr: usize,
+        len: usize,
+        dir: Direction,
+        len_align: usize,
+    ) -> Result<Mapping> {
+        let size = len.checked_next_multiple_of(len_align).ok_or(EINVAL)?;
+        let mut buf = CoherentAllocation::alloc(dev, size, GFP_KERNEL | __GFP_ZERO, 0)?;
+        if dir == Direction::ToDevice {
+            // SAFETY: The buffer has at least `len` bytes, and the device does not access it yet.
+            // `copy_from_user` checks that the source is accessible userspace memory.
+            let left = unsafe {
+                bindings::copy_from_user(buf.as_mut_ptr().cast(), user_addr as _, len as _)
+            };
+            if left != 0 {
+                return Err(EFAULT);
+            }
+        }
+        Ok(Mapping::Bounce(buf))
+    }
+
+    fn unpin(pages: &[*mut bindings::page], dirty: bool) {
+        // SAFETY: `pages` are pinned with `pin_user_pages_fast`, and are not used afterwards.
+        unsafe {
+            bindings::unpin_user_pages_dirty_lock(
+                pages.as_ptr().cast_mut(),
+                pages.len() as _,
+                dirty,
+            )
+        };
+    }
+
+    /// Returns `true` if the transfer goes through a bounce buffer.
+    pub fn is_bounced(&self) -> bool {
+        matches!(self.mapping, Mapping::Bounce(_))
+    }
+
+    /// Returns the segments of the device address space to transfer, in order.
+    ///
+    /// When the transfer is bounced, there is a single segment, whose length is rounded up to
+    /// [`Constraints::len_align`].
+    pub fn segments(&self) -> Segments<'_> {
+        match &self.mapping {
+            // SAFETY: By the type invariants, the table is mapped, and it stays mapped while
+            // `self` is borrowed.
+            Mapping::Direct { sgt, .. } => unsafe { Segments::from_sgt(sgt) },
+            Mapping::Bounce(buf) => Segments::single(Segment {
+                addr: buf.dma_handle(),
+                len: buf.size(),
+            }),
+        }
+    }
+
+    /// Completes a transfer in which the device transferred `transferred` bytes, and releases the
+    /// mapping.
+    ///
+    /// For bounced transfers from the device, the data is copied to userspace. Fails with
+    /// [`EFAULT`] if that fails.
+    pub fn finish(self, transferred: usize) -> Result {
+        if let (Mapping::Bounce(buf), Direction::FromDevice) = (&self.mapping, self.dir) {
+            let len = transferred.min(self.len);
+            // SAFETY: The device finished the transfer, and the buffer has at least `self.len`
+            // bytes. `copy_to_user` checks that the destination is accessible userspace memory.
+            let left = unsafe {
+                bindings::copy_to_user(self.user_addr as _, buf.as_ptr().cast(), len as _)
+            };
+            if left != 0 {
+                return Err(EFAULT);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for UserIo {
+    fn drop(&mut self) {
+        if let Mapping::Direct { pages, sgt } = &mut self.mapping {
+            // SAFETY: By the type invariants, the table is mapped for `dev` in `dir`. It is not
+            // used after this.
+            unsafe {
+                bindings::dma_unmap_sgtable(self.dev.raw_device(), sgt, self.dir.as_raw(), 0);
+                bindings::sg_free_table(sgt);
+            }
+            Self::unpin(pages, self.dir == Direction::FromDevice);
+        }
+    }
+}
+
+// SAFETY: The mapping can be used and released from any thread.
+unsafe impl Send for UserIo {}
+
+// SAFETY: `UserIo` has no methods that mutate it through a shared reference.
+unsafe impl Sync for UserIo {}
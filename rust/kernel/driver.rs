@@ -3,18 +3,23 @@
 //! Generic support for drivers of different buses (e.g., PCI, Platform, Amba, etc.).
 //!
 //! Each bus/subsystem is expected to implement [`DriverOps`], which allows drivers to register
-//! using the [`Registration`] class.
+//! using the [`Registration`] class, and [`Adapter`], which provides the parts of matching, probing
+//! and removing devices that are the same on all buses.
 
 use crate::{
     alloc::{box_ext::BoxExt, flags::*},
+    bindings,
+    device::{self, RawDevice},
     error::code::*,
-    error::Result,
+    error::{from_result, Result},
+    of, pm,
     str::CStr,
     sync::Arc,
+    types::ForeignOwnable,
     ThisModule,
 };
 use alloc::boxed::Box;
-use core::{cell::UnsafeCell, marker::PhantomData, ops::Deref, pin::Pin};
+use core::{cell::UnsafeCell, ffi::c_int, marker::PhantomData, ops::Deref, pin::Pin};
 
 /// A subsystem (e.g., PCI, Platform, Amba, etc.) that allows drivers to be written for it.
 pub trait DriverOps {
@@ -272,6 +277,142 @@ fn as_ref(&self) -> &T::RawType {
     }
 }
 
+/// Returns the context data of an entry of an id table.
+///
+/// Buses call this with the entry returned by their matching function and the offset that
+/// `to_rawid` stored in it.
+///
+/// # Safety
+///
+/// `id` must point to an entry of a static [`IdArray`] whose context data has type `U`, and
+/// `offset` must be the value that was stored in the entry by `to_rawid`.
+pub unsafe fn id_info<R, U: 'static>(id: *const R, offset: isize) -> Option<&'static U> {
+    // The context data always follows the ids, so an offset of zero means that the entry does not
+    // come from an `IdArray`, e.g., because it was added dynamically through sysfs.
+    if offset == 0 {
+        return None;
+    }
+
+    // SAFETY: The offset comes from a previous call to `offset_from` in `IdArray::new`, which
+    // guarantees that the resulting pointer is within the array, and the array has a static
+    // lifetime, so the pointer is valid for read.
+    unsafe { (*id.cast::<u8>().offset(offset).cast::<Option<U>>()).as_ref() }
+}
+
+/// The bus-independent part of the adapter between a bus and its drivers.
+///
+/// Buses implement this, in addition to [`DriverOps`], for the type through which they register
+/// drivers. The bus then only needs to convert between its C device type and its Rust device type;
+/// registering the OF id table, matching devices against it, and storing and releasing the driver
+/// data are handled here.
+///
+/// # Examples
+///
+/// The probe and remove callbacks of a bus usually look like this:
+///
+/// ```ignore
+/// impl<T: Driver> driver::Adapter for Adapter<T> {
+///     type IdInfo = T::IdInfo;
+///     type Data = T::Data;
+///     const OF_ID_TABLE: Option<driver::IdTable<'static, of::DeviceId, T::IdInfo>> =
+///         T::OF_DEVICE_ID_TABLE;
+/// }
+///
+/// impl<T: Driver> Adapter<T> {
+///     extern "C" fn probe_callback(ptr: *mut bindings::foo_device) -> c_int {
+///         // SAFETY: `ptr` is valid for the duration of the callback.
+///         let mut dev = unsafe { Device::from_ptr(ptr) };
+///         // SAFETY: The device is only now being bound.
+///         unsafe { Self::probe_device(&mut dev, |dev| T::probe(dev, Self::of_id_info(dev))) }
+///     }
+///
+///     extern "C" fn remove_callback(ptr: *mut bindings::foo_device) {
+///         // SAFETY: `ptr` is valid for the duration of the callback.
+///         let dev = unsafe { Device::from_ptr(ptr) };
+///         // SAFETY: The driver data was set in `probe_callback` and the device is being unbound.
+///         unsafe { Self::remove_device(&dev, T::remove) };
+///     }
+/// }
+/// ```
+pub trait Adapter {
+    /// The type holding information about each device id supported by the driver.
+    type IdInfo: 'static;
+
+    /// The data the driver stores on the devices it is bound to.
+    type Data: ForeignOwnable + Send + Sync + DeviceRemoval + 'static;
+
+    /// The table of OF device ids supported by the driver.
+    const OF_ID_TABLE: Option<IdTable<'static, of::DeviceId, Self::IdInfo>>;
+
+    /// The power management callbacks of the driver.
+    const PM_OPS: Option<&'static pm::Ops<Self::Data>> = None;
+
+    /// Initialises the bus-independent fields of the driver `drv` before it is registered.
+    fn init_driver(drv: &mut bindings::device_driver, name: &'static CStr) {
+        drv.name = name.as_char_ptr();
+        if let Some(t) = Self::OF_ID_TABLE {
+            drv.of_match_table = t.as_ref();
+        }
+        if let Some(ops) = Self::PM_OPS {
+            drv.pm = ops.as_raw();
+        }
+    }
+
+    /// Returns the information of the entry of the OF id table that matches `dev`.
+    fn of_id_info(dev: &impl RawDevice) -> Option<&'static Self::IdInfo> {
+        let table = Self::OF_ID_TABLE?;
+
+        // SAFETY: `table` has static lifetime, so it is valid for read. `dev` is guaranteed to be
+        // valid while it's alive, so is the raw device returned by it.
+        let id = unsafe { bindings::of_match_device(table.as_ref(), dev.raw_device()) };
+        if id.is_null() {
+            return None;
+        }
+
+        // SAFETY: `id` is a pointer within the static table, whose `data` field holds the offset
+        // stored by `to_rawid`.
+        unsafe { id_info(id, (*id).data as isize) }
+    }
+
+    /// Probes `dev` with `probe` and stores the returned data as the driver data of `dev`.
+    ///
+    /// Returns the value expected from the probe callback of a bus.
+    ///
+    /// # Safety
+    ///
+    /// The driver data of `dev` must not be set, i.e., the device is only now being bound.
+    unsafe fn probe_device<D: RawDevice>(
+        dev: &mut D,
+        probe: impl FnOnce(&mut D) -> Result<Self::Data>,
+    ) -> c_int {
+        from_result(|| {
+            let raw = dev.raw_device();
+            // SAFETY: `raw` is valid and, by the safety requirements, no driver data has been set
+            // yet.
+            unsafe { device::set_drvdata(raw, || probe(dev)) }?;
+            Ok(0)
+        })
+    }
+
+    /// Takes the driver data of `dev`, passes it to `remove` and then releases it.
+    ///
+    /// [`DeviceRemoval::device_remove`] is called on the data after `remove`, before it is
+    /// dropped. Returns the value returned by `remove`.
+    ///
+    /// # Safety
+    ///
+    /// The driver data of `dev` must have been set by [`Adapter::probe_device`], and no references
+    /// to it may be in use anymore.
+    unsafe fn remove_device<R>(dev: &impl RawDevice, remove: impl FnOnce(&Self::Data) -> R) -> R {
+        // SAFETY: By the safety requirements, the driver data was set with type `Self::Data` and
+        // is no longer used, so it can be converted back to a Rust value here.
+        let data = unsafe { device::take_drvdata::<Self::Data>(dev.raw_device()) };
+        let ret = remove(&data);
+        data.device_remove();
+        ret
+    }
+}
+
 /// Counts the number of parenthesis-delimited, comma-separated items.
 ///
 /// # Examples
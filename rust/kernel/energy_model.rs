@@ -0,0 +1,203 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Energy model.
+//!
+//! The energy model describes the power a device draws in each of its performance states. The
+//! scheduler uses the energy models of CPUs for energy-aware scheduling (EAS), e.g., to choose
+//! between the big and LITTLE clusters of a system, and thermal and devfreq governors use those of
+//! other devices.
+//!
+//! Drivers describe the model with [`Callbacks`] and register it with [`PerfDomain::register`],
+//! usually from the CPUFreq or devfreq driver that controls the performance states.
+//!
+//! C header: [`include/linux/energy_model.h`](srctree/include/linux/energy_model.h)
+
+use crate::{
+    bindings,
+    cpumask::Cpumask,
+    device::{Device, RawDevice},
+    error::{code::*, from_result, to_result, Result, VTABLE_DEFAULT_ERROR},
+    types::ARef,
+};
+use core::{ffi::c_int, ptr};
+use macros::vtable;
+
+/// A performance state of a device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PerfState {
+    /// The frequency of the state, in kHz.
+    pub freq_khz: u64,
+
+    /// The power drawn in the state, in microwatts or in an abstract scale; see
+    /// [`PerfDomain::register`].
+    pub power: u64,
+}
+
+/// Looks up the lowest state of `table` whose frequency is at least `freq_khz`.
+///
+/// `table` must be sorted by ascending frequency. Fails with [`ERANGE`] if `freq_khz` is higher
+/// than the frequency of all states.
+///
+/// This is a helper for drivers whose energy model is a static table, to implement
+/// [`Callbacks::active_power`].
+pub fn lookup(table: &[PerfState], freq_khz: u64) -> Result<PerfState> {
+    table
+        .iter()
+        .find(|s| s.freq_khz >= freq_khz)
+        .copied()
+        .ok_or(ERANGE)
+}
+
+/// The callbacks that describe the energy model of a device.
+///
+/// They are only called while the model is created in [`PerfDomain::register`].
+#[vtable]
+pub trait Callbacks {
+    /// Returns the lowest performance state of `dev` whose frequency is at least `freq_khz`.
+    ///
+    /// The energy model core calls this repeatedly with increasing frequencies to discover all
+    /// the states of the device, starting at zero.
+    fn active_power(dev: &Device, freq_khz: u64) -> Result<PerfState>;
+
+    /// Returns the cost of running `dev` at `freq_khz`, one of its state frequencies.
+    ///
+    /// The cost is used by EAS to compare performance states. If this is not implemented, the
+    /// core derives it from power and frequency, which is appropriate for most devices.
+    fn cost(_dev: &Device, _freq_khz: u64) -> Result<u64> {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+}
+
+/// A registered energy model, also called a performance domain.
+///
+/// The model is unregistered when this object is dropped.
+///
+/// # Invariants
+///
+/// A performance domain was registered for `dev` and has not been unregistered.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{
+///     cpumask::Cpumask,
+///     device::Device,
+///     energy_model::{self, Callbacks, PerfDomain, PerfState},
+///     prelude::*,
+/// };
+///
+/// const STATES: [PerfState; 3] = [
+///     PerfState { freq_khz: 500_000, power: 90_000 },
+///     PerfState { freq_khz: 1_000_000, power: 250_000 },
+///     PerfState { freq_khz: 1_800_000, power: 720_000 },
+/// ];
+///
+/// struct BigCluster;
+///
+/// #[vtable]
+/// impl Callbacks for BigCluster {
+///     fn active_power(_dev: &Device, freq_khz: u64) -> Result<PerfState> {
+///         energy_model::lookup(&STATES, freq_khz)
+///     }
+/// }
+///
+/// fn register(cpu_dev: &Device, policy_cpus: &Cpumask) -> Result<PerfDomain> {
+///     PerfDomain::register::<BigCluster>(cpu_dev, STATES.len(), Some(policy_cpus), true)
+/// }
+/// ```
+pub struct PerfDomain {
+    dev: ARef<Device>,
+}
+
+impl PerfDomain {
+    /// Registers the energy model of `dev`, which has `nr_states` performance states.
+    ///
+    /// For CPU devices, `span` is the set of CPUs that share the performance states, e.g., the
+    /// CPUs of a CPUFreq policy; it must be [`None`] for other devices. If `microwatts` is `true`,
+    /// the power values are in microwatts, which is required for EAS; otherwise they are in an
+    /// abstract scale that is only consistent within the device.
+    ///
+    /// Fails with [`EEXIST`] if `dev` already has an energy model.
+    pub fn register<T: Callbacks>(
+        dev: &Device,
+        nr_states: usize,
+        span: Option<&Cpumask>,
+        microwatts: bool,
+    ) -> Result<Self> {
+        let nr_states = u32::try_from(nr_states).map_err(|_| EINVAL)?;
+        let mut cb = bindings::em_data_callback {
+            active_power: Some(Adapter::<T>::active_power_callback),
+            get_cost: if T::HAS_COST {
+                Some(Adapter::<T>::cost_callback)
+            } else {
+                None
+            },
+        };
+        let span = span.map_or(ptr::null_mut(), Cpumask::as_raw);
+        // SAFETY: `dev` is valid, `cb` is valid for the duration of the call, which is the only
+        // time it is used, and `span` is either null or a valid CPU mask.
+        to_result(unsafe {
+            bindings::em_dev_register_perf_domain(
+                dev.raw_device(),
+                nr_states,
+                &mut cb,
+                span,
+                microwatts,
+            )
+        })?;
+        // INVARIANT: The performance domain was just registered.
+        Ok(Self { dev: dev.into() })
+    }
+
+    /// Returns the device whose energy model this is.
+    pub fn device(&self) -> &Device {
+        &self.dev
+    }
+}
+
+impl Drop for PerfDomain {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, a performance domain is registered for `dev`.
+        unsafe { bindings::em_dev_unregister_perf_domain(self.dev.raw_device()) };
+    }
+}
+
+// SAFETY: The performance domain can be unregistered from any thread.
+unsafe impl Send for PerfDomain {}
+
+// SAFETY: `PerfDomain` has no methods that change state through `&self`.
+unsafe impl Sync for PerfDomain {}
+
+struct Adapter<T: Callbacks>(T);
+
+impl<T: Callbacks> Adapter<T> {
+    unsafe extern "C" fn active_power_callback(
+        dev: *mut bindings::device,
+        power: *mut core::ffi::c_ulong,
+        freq: *mut core::ffi::c_ulong,
+    ) -> c_int {
+        from_result(|| {
+            // SAFETY: The energy model core passes a valid device for the duration of the call,
+            // and `power` and `freq` valid for reads and writes.
+            let (dev, freq, power) = unsafe { (Device::from_raw(dev), &mut *freq, &mut *power) };
+            let state = T::active_power(dev, *freq as u64)?;
+            *freq = state.freq_khz.try_into().map_err(|_| ERANGE)?;
+            *power = state.power.try_into().map_err(|_| ERANGE)?;
+            Ok(0)
+        })
+    }
+
+    unsafe extern "C" fn cost_callback(
+        dev: *mut bindings::device,
+        freq: core::ffi::c_ulong,
+        cost: *mut core::ffi::c_ulong,
+    ) -> c_int {
+        from_result(|| {
+            // SAFETY: The energy model core passes a valid device for the duration of the call,
+            // and `cost` valid for writes.
+            let (dev, cost) = unsafe { (Device::from_raw(dev), &mut *cost) };
+            *cost = T::cost(dev, freq as u64)?.try_into().map_err(|_| ERANGE)?;
+            Ok(0)
+        })
+    }
+}
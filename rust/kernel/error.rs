@@ -79,6 +79,10 @@ macro_rules! declare_err {
     declare_err!(ERECALLCONFLICT, "Conflict with recalled state.");
     declare_err!(ENOGRACE, "NFS file lock reclaim refused.");
     declare_err!(ENOTRECOVERABLE, "State not recoverable.");
+    declare_err!(ETIMEDOUT, "Connection timed out.");
+    declare_err!(ESHUTDOWN, "Cannot send after transport endpoint shutdown.");
+    declare_err!(ECANCELED, "Operation canceled.");
+    declare_err!(EBADSLT, "Invalid slot.");
 }
 
 /// Generic integer kernel error.
@@ -172,6 +176,89 @@ fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     }
 }
 
+impl fmt::Display for Error {
+    /// Formats the error like the `%pe` format of `printk`: its name, e.g., `-EINVAL`, or its
+    /// number if it has no name.
+    ///
+    /// ```
+    /// # use kernel::{fmt, prelude::*, str::CString};
+    /// let s = CString::try_from_fmt(fmt!("{}", ETIMEDOUT))?;
+    /// assert_eq!(s.to_str()?, "-ETIMEDOUT");
+    /// # Ok::<(), Error>(())
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            // SAFETY: These strings are ASCII-only.
+            Some(name) => write!(f, "-{}", unsafe { core::str::from_utf8_unchecked(name) }),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+/// Formats a pointer returned by a C function like the `%pe` format of `printk`.
+///
+/// Error pointers are formatted as their [`Error`], e.g., `-ENOMEM`. Other pointers are hashed
+/// like with `%p`, so that logs do not leak kernel addresses.
+///
+/// # Examples
+///
+/// ```
+/// use core::ffi::c_void;
+/// use kernel::{error::ErrPtr, prelude::*};
+///
+/// fn log_lookup(clk: *mut c_void) {
+///     // Prints, e.g., `clock: -EPROBE_DEFER` or `clock: 00000000e4ad3e7c`.
+///     pr_debug!("clock: {}\n", ErrPtr::new(clk));
+/// }
+/// ```
+pub struct ErrPtr<T>(*const T);
+
+impl<T> ErrPtr<T> {
+    /// Creates a formatter for `ptr`.
+    pub fn new(ptr: *const T) -> Self {
+        Self(ptr)
+    }
+}
+
+impl<T> fmt::Display for ErrPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Err(e) = from_err_ptr(self.0.cast_mut()) {
+            return fmt::Display::fmt(&e, f);
+        }
+        if self.0.is_null() {
+            return f.write_str("(null)");
+        }
+        print_hashed(self.0.cast(), f)
+    }
+}
+
+impl<T> fmt::Debug for ErrPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[cfg(not(testlib))]
+fn print_hashed(ptr: *const core::ffi::c_void, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let mut hash = 0;
+    // SAFETY: The pointer is only hashed, not dereferenced, and `hash` is valid for writes.
+    if unsafe { crate::bindings::ptr_to_hashval(ptr, &mut hash) } < 0 {
+        // The hash key is not ready yet, early during boot.
+        return f.write_str("(____ptrval____)");
+    }
+    write!(
+        f,
+        "{:0width$x}",
+        hash,
+        width = 2 * core::mem::size_of::<usize>()
+    )
+}
+
+#[cfg(testlib)]
+fn print_hashed(_ptr: *const core::ffi::c_void, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("(____ptrval____)")
+}
+
 impl From<AllocError> for Error {
     fn from(_: AllocError) -> Error {
         code::ENOMEM
@@ -230,6 +317,41 @@ fn from(e: core::convert::Infallible) -> Error {
 /// just an [`Error`].
 pub type Result<T = (), E = Error> = core::result::Result<T, E>;
 
+/// Extension methods for results, to report errors with device context.
+pub trait ResultExt<T> {
+    /// Logs `msg` with the error, prefixed with information about `dev`, and propagates the error.
+    ///
+    /// This is the report-then-bail pattern of probe functions. Errors are logged through
+    /// [`RawDevice::err_probe`], so [`EPROBE_DEFER`] is only recorded as the reason for the
+    /// deferral instead of being printed as an error.
+    ///
+    /// [`RawDevice::err_probe`]: crate::device::RawDevice::err_probe
+    /// [`EPROBE_DEFER`]: code::EPROBE_DEFER
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kernel::{device::RawDevice, prelude::*};
+    ///
+    /// fn enable_clock(_dev: &impl RawDevice) -> Result {
+    ///     Err(ETIMEDOUT)
+    /// }
+    ///
+    /// fn probe(dev: &impl RawDevice) -> Result {
+    ///     // Logs "error -ETIMEDOUT: failed to enable clock".
+    ///     enable_clock(dev).map_dev_err(dev, "failed to enable clock")?;
+    ///     Ok(())
+    /// }
+    /// ```
+    fn map_dev_err(self, dev: &impl crate::device::RawDevice, msg: &str) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> ResultExt<T> for core::result::Result<T, E> {
+    fn map_dev_err(self, dev: &impl crate::device::RawDevice, msg: &str) -> Result<T> {
+        self.map_err(|e| dev.err_probe(e.into(), format_args!("{msg}\n")))
+    }
+}
+
 /// Converts an integer as returned by a C kernel function to an error if it's negative, and
 /// `Ok(())` otherwise.
 pub fn to_result(err: core::ffi::c_int) -> Result {
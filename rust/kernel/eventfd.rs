@@ -0,0 +1,63 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Eventfd contexts.
+//!
+//! User space passes eventfds to drivers to be notified of events without a syscall on the
+//! driver side, e.g., the interrupts of a device assigned to a virtual machine, which the VMM or
+//! KVM injects into the guest when the eventfd is signalled.
+//!
+//! C header: [`include/linux/eventfd.h`](srctree/include/linux/eventfd.h)
+
+use crate::{bindings, error::from_err_ptr, prelude::*};
+use core::ptr::NonNull;
+
+/// A reference to the context of an eventfd.
+///
+/// The reference is dropped when this object is dropped; the eventfd itself lives as long as user
+/// space has it open or references to its context exist.
+///
+/// # Invariants
+///
+/// `ctx` is a valid eventfd context, of which this object owns a reference.
+pub struct EventFd {
+    ctx: NonNull<bindings::eventfd_ctx>,
+}
+
+impl EventFd {
+    /// Takes a reference to the context of the eventfd that is the file descriptor `fd` of the
+    /// current process.
+    ///
+    /// Fails with [`EBADF`] if `fd` is not an open file descriptor, and with [`EINVAL`] if it is
+    /// not an eventfd.
+    pub fn from_fd(fd: i32) -> Result<Self> {
+        // SAFETY: Any value can be passed as a file descriptor. On success, the returned context
+        // has its reference count incremented.
+        let ctx = from_err_ptr(unsafe { bindings::eventfd_ctx_fdget(fd) })?;
+        // INVARIANT: The reference taken above is owned by the returned object.
+        Ok(Self {
+            ctx: NonNull::new(ctx).ok_or(EINVAL)?,
+        })
+    }
+
+    /// Increments the counter of the eventfd, waking up its waiters.
+    ///
+    /// This can be called from any context, including interrupt handlers.
+    pub fn signal(&self) {
+        // SAFETY: By the type invariants, `ctx` is valid.
+        unsafe { bindings::eventfd_signal_mask(self.ctx.as_ptr(), 0) };
+    }
+}
+
+impl Drop for EventFd {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, this object owns a reference to `ctx`.
+        unsafe { bindings::eventfd_ctx_put(self.ctx.as_ptr()) };
+    }
+}
+
+// SAFETY: Eventfd contexts are reference-counted and can be signalled and released from any
+// thread.
+unsafe impl Send for EventFd {}
+
+// SAFETY: `signal` can be called concurrently; the eventfd core serialises it with its own lock.
+unsafe impl Sync for EventFd {}
@@ -0,0 +1,252 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Parsers of common firmware container formats.
+//!
+//! Firmware images are untrusted input, so every length read from them is checked against the
+//! size of the image before it is used. [`Sections`] splits an image made of length-prefixed
+//! sections, optionally followed by a CRC-32 each, and [`Downloader`] feeds their contents to a
+//! device in chunks of bounded size, so that large images can be downloaded without a copy of
+//! the whole image.
+
+use crate::{bindings, prelude::*};
+
+/// The layout of the headers of the sections of a firmware image.
+///
+/// Each section starts with an optional tag, followed by the length of its payload and the
+/// payload itself. With [`Layout::crc32`], the payload is followed by its CRC-32, and with
+/// [`Layout::align`], sections start at multiples of the given alignment from the start of the
+/// image. The default is a 32-bit little-endian length without tag, CRC or alignment.
+#[derive(Clone, Copy, Debug)]
+pub struct Layout {
+    tag_bytes: usize,
+    len_bytes: usize,
+    big_endian: bool,
+    crc32: bool,
+    align: usize,
+}
+
+impl Layout {
+    /// Creates the default layout.
+    pub const fn new() -> Self {
+        Self {
+            tag_bytes: 0,
+            len_bytes: 4,
+            big_endian: false,
+            crc32: false,
+            align: 1,
+        }
+    }
+
+    /// Sets the size of the tag that identifies sections.
+    ///
+    /// `bytes` must be 0, 1, 2 or 4, which is checked at build time.
+    #[inline(always)]
+    pub const fn tag_size(mut self, bytes: usize) -> Self {
+        crate::build_assert!(matches!(bytes, 0 | 1 | 2 | 4), "invalid tag size");
+        self.tag_bytes = bytes;
+        self
+    }
+
+    /// Sets the size of the length of sections.
+    ///
+    /// `bytes` must be 1, 2 or 4, which is checked at build time.
+    #[inline(always)]
+    pub const fn len_size(mut self, bytes: usize) -> Self {
+        crate::build_assert!(matches!(bytes, 1 | 2 | 4), "invalid length size");
+        self.len_bytes = bytes;
+        self
+    }
+
+    /// Makes the tags, lengths and CRCs big-endian.
+    pub const fn big_endian(mut self) -> Self {
+        self.big_endian = true;
+        self
+    }
+
+    /// Makes the payload of every section followed by its CRC-32, as computed by zlib.
+    pub const fn crc32(mut self) -> Self {
+        self.crc32 = true;
+        self
+    }
+
+    /// Makes sections start at multiples of `align` bytes from the start of the image.
+    ///
+    /// `align` must be a power of two, which is checked at build time.
+    #[inline(always)]
+    pub const fn align(mut self, align: usize) -> Self {
+        crate::build_assert!(align.is_power_of_two(), "invalid alignment");
+        self.align = align;
+        self
+    }
+
+    fn read(&self, bytes: &[u8]) -> u32 {
+        let fold = |v: u32, b: &u8| (v << 8) | u32::from(*b);
+        if self.big_endian {
+            bytes.iter().fold(0, fold)
+        } else {
+            bytes.iter().rev().fold(0, fold)
+        }
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A section of a firmware image.
+#[derive(Clone, Copy, Debug)]
+pub struct Section<'a> {
+    /// The tag of the section, zero if the layout has no tags.
+    pub tag: u32,
+
+    /// The payload of the section.
+    pub data: &'a [u8],
+}
+
+/// An iterator over the sections of a firmware image.
+///
+/// Each item is a section, or [`EINVAL`] if the image is truncated or a CRC does not match, after
+/// which the iteration ends. Padding after the last section, up to the alignment of the layout,
+/// is ignored.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::firmware::container::{Layout, Sections};
+///
+/// // An 8-bit tag, a 16-bit length and a CRC-32 per section.
+/// const LAYOUT: Layout = Layout::new().tag_size(1).len_size(2).crc32();
+///
+/// let image = [
+///     0x01, 0x03, 0x00, b'a', b'b', b'c', 0xc2, 0x41, 0x24, 0x35,
+///     0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+/// ];
+///
+/// let mut sections = Sections::new(&image, LAYOUT);
+/// let s = sections.next().unwrap()?;
+/// assert_eq!((s.tag, s.data), (1, &b"abc"[..]));
+/// let s = sections.next().unwrap()?;
+/// assert_eq!((s.tag, s.data.len()), (2, 0));
+/// assert!(sections.next().is_none());
+///
+/// // A truncated image, or a corrupted one, is rejected.
+/// assert_eq!(Sections::new(&image[..8], LAYOUT).next().unwrap().err(), Some(EINVAL));
+/// let mut corrupted = image;
+/// corrupted[4] = b'x';
+/// assert_eq!(Sections::new(&corrupted, LAYOUT).next().unwrap().err(), Some(EINVAL));
+/// # Ok::<(), Error>(())
+/// ```
+pub struct Sections<'a> {
+    image: &'a [u8],
+    pos: usize,
+    layout: Layout,
+}
+
+impl<'a> Sections<'a> {
+    /// Creates an iterator over the sections of `image`, laid out as described by `layout`.
+    pub fn new(image: &'a [u8], layout: Layout) -> Self {
+        Self {
+            image,
+            pos: 0,
+            layout,
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).ok_or(EINVAL)?;
+        let bytes = self.image.get(self.pos..end).ok_or(EINVAL)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn parse(&mut self) -> Result<Section<'a>> {
+        let l = self.layout;
+        let tag = l.read(self.take(l.tag_bytes)?);
+        let len = l.read(self.take(l.len_bytes)?);
+        let data = self.take(len.try_into().map_err(|_| EINVAL)?)?;
+        if l.crc32 {
+            let crc = l.read(self.take(4)?);
+            // SAFETY: `data` is valid for reads of `data.len()` bytes.
+            if unsafe { bindings::crc32_le(!0, data.as_ptr(), data.len()) } ^ !0 != crc {
+                return Err(EINVAL);
+            }
+        }
+        // Aligning past the end of the image ends the iteration.
+        self.pos = self
+            .pos
+            .checked_next_multiple_of(l.align)
+            .unwrap_or(usize::MAX);
+        Ok(Section { tag, data })
+    }
+}
+
+impl<'a> Iterator for Sections<'a> {
+    type Item = Result<Section<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.image.len() {
+            return None;
+        }
+        let ret = self.parse();
+        if ret.is_err() {
+            self.pos = usize::MAX;
+        }
+        Some(ret)
+    }
+}
+
+/// A buffer to download data to a device in chunks of bounded size.
+///
+/// Download routines often need the data in memory that can be used for DMA, which the firmware
+/// image may not be in, or can only take a limited amount of it at a time. [`Downloader::download`]
+/// copies the data into a buffer of at most `max_chunk` bytes, allocated once, and hands it over
+/// to the download routine one chunk at a time.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::firmware::{container::{Downloader, Layout, Sections}, Firmware};
+///
+/// fn download(fw: &Firmware, write: impl Fn(u32, usize, &[u8]) -> Result) -> Result {
+///     let mut dl = Downloader::new(256)?;
+///     for section in Sections::new(fw.data(), Layout::new().tag_size(4)) {
+///         let section = section?;
+///         dl.download(section.data, |offset, chunk| write(section.tag, offset, chunk))?;
+///     }
+///     Ok(())
+/// }
+/// ```
+pub struct Downloader {
+    buf: Vec<u8>,
+    max_chunk: usize,
+}
+
+impl Downloader {
+    /// Creates a downloader with chunks of at most `max_chunk` bytes.
+    ///
+    /// Fails with [`EINVAL`] if `max_chunk` is zero.
+    pub fn new(max_chunk: usize) -> Result<Self> {
+        if max_chunk == 0 {
+            return Err(EINVAL);
+        }
+        Ok(Self {
+            buf: Vec::with_capacity(max_chunk, GFP_KERNEL)?,
+            max_chunk,
+        })
+    }
+
+    /// Calls `f` with the offset and a copy of each chunk of `data`, in order.
+    ///
+    /// Stops at, and returns, the first error returned by `f`.
+    pub fn download(&mut self, data: &[u8], mut f: impl FnMut(usize, &[u8]) -> Result) -> Result {
+        for (i, chunk) in data.chunks(self.max_chunk).enumerate() {
+            self.buf.clear();
+            // The capacity was allocated in `new`, so this does not allocate.
+            self.buf.extend_from_slice(chunk, GFP_KERNEL)?;
+            f(i * self.max_chunk, &self.buf)?;
+        }
+        Ok(())
+    }
+}
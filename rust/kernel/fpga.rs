@@ -0,0 +1,337 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! FPGA manager framework.
+//!
+//! An FPGA manager knows how to program a specific type of FPGA. Drivers implement
+//! [`Operations`] and register it with [`Registration`]; bitstreams can then be loaded from a
+//! buffer or from [`Firmware`].
+//!
+//! [`Firmware`]: crate::firmware::Firmware
+//!
+//! C header: [`include/linux/fpga/fpga-mgr.h`](srctree/include/linux/fpga/fpga-mgr.h)
+
+use crate::{
+    bindings,
+    device::RawDevice,
+    error::{code::*, from_err_ptr, from_result, to_result, Result, VTABLE_DEFAULT_ERROR},
+    str::CStr,
+    types::{ForeignOwnable, Opaque},
+};
+use core::{ffi::c_char, marker::PhantomData, ptr::NonNull};
+use macros::vtable;
+
+/// Flags describing a bitstream, passed to [`Registration::load`].
+pub mod flags {
+    /// The bitstream only reconfigures part of the FPGA.
+    pub const PARTIAL_RECONFIG: u32 = bindings::FPGA_MGR_PARTIAL_RECONFIG;
+
+    /// The FPGA has already been programmed by an external entity.
+    pub const EXTERNAL_CONFIG: u32 = bindings::FPGA_MGR_EXTERNAL_CONFIG;
+
+    /// The bitstream is encrypted.
+    pub const ENCRYPTED_BITSTREAM: u32 = bindings::FPGA_MGR_ENCRYPTED_BITSTREAM;
+
+    /// The bitstream is stored with the least significant bit first.
+    pub const BITSTREAM_LSB_FIRST: u32 = bindings::FPGA_MGR_BITSTREAM_LSB_FIRST;
+
+    /// The bitstream is compressed.
+    pub const COMPRESSED_BITSTREAM: u32 = bindings::FPGA_MGR_COMPRESSED_BITSTREAM;
+}
+
+/// The state of an FPGA, as reported by [`Operations::state`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum State {
+    /// The state is not known.
+    Unknown = bindings::fpga_mgr_states_FPGA_MGR_STATE_UNKNOWN,
+    /// The FPGA is powered off.
+    PowerOff = bindings::fpga_mgr_states_FPGA_MGR_STATE_POWER_OFF,
+    /// The FPGA is powered up.
+    PowerUp = bindings::fpga_mgr_states_FPGA_MGR_STATE_POWER_UP,
+    /// The FPGA is in reset.
+    Reset = bindings::fpga_mgr_states_FPGA_MGR_STATE_RESET,
+    /// The FPGA is being programmed.
+    Write = bindings::fpga_mgr_states_FPGA_MGR_STATE_WRITE,
+    /// The FPGA is programmed and running.
+    Operating = bindings::fpga_mgr_states_FPGA_MGR_STATE_OPERATING,
+}
+
+/// Information about the bitstream that is being loaded.
+///
+/// Wraps the kernel's `struct fpga_image_info`.
+///
+/// # Invariants
+///
+/// The inner `fpga_image_info` is valid for as long as the reference exists.
+#[repr(transparent)]
+pub struct ImageInfo(Opaque<bindings::fpga_image_info>);
+
+impl ImageInfo {
+    /// Creates a reference to an [`ImageInfo`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `ptr` is valid and remains valid for the lifetime of the
+    /// returned [`ImageInfo`] instance.
+    unsafe fn from_raw<'a>(ptr: *mut bindings::fpga_image_info) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `ImageInfo` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns the flags of the bitstream, a combination of the constants in [`flags`].
+    pub fn flags(&self) -> u32 {
+        // SAFETY: By the type invariants, the pointer is valid.
+        unsafe { (*self.0.get()).flags }
+    }
+
+    /// Returns the timeout to wait for the FPGA to enable its bridges, in microseconds.
+    pub fn enable_timeout_us(&self) -> u32 {
+        // SAFETY: By the type invariants, the pointer is valid.
+        unsafe { (*self.0.get()).enable_timeout_us }
+    }
+
+    /// Returns the timeout to wait for the FPGA to finish configuration, in microseconds.
+    pub fn config_complete_timeout_us(&self) -> u32 {
+        // SAFETY: By the type invariants, the pointer is valid.
+        unsafe { (*self.0.get()).config_complete_timeout_us }
+    }
+}
+
+/// Operations of an FPGA manager.
+///
+/// The FPGA manager core calls [`Operations::write_init`] first, then [`Operations::write`] one or
+/// more times with the bitstream, and finally [`Operations::write_complete`].
+#[vtable]
+pub trait Operations {
+    /// The context data made available to the callbacks.
+    type Data: ForeignOwnable + Send + Sync;
+
+    /// The number of bytes of the bitstream that are passed to [`Operations::write_init`].
+    const INITIAL_HEADER_SIZE: usize = 0;
+
+    /// Returns the state of the FPGA.
+    fn state(_data: <Self::Data as ForeignOwnable>::Borrowed<'_>) -> State {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+
+    /// Prepares the FPGA for programming.
+    ///
+    /// `header` contains the first [`Operations::INITIAL_HEADER_SIZE`] bytes of the bitstream,
+    /// or the whole bitstream if it is shorter.
+    fn write_init(
+        data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        info: &ImageInfo,
+        header: &[u8],
+    ) -> Result;
+
+    /// Writes a chunk of the bitstream to the FPGA.
+    fn write(data: <Self::Data as ForeignOwnable>::Borrowed<'_>, buf: &[u8]) -> Result;
+
+    /// Finishes programming and puts the FPGA into operating mode.
+    fn write_complete(
+        data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        info: &ImageInfo,
+    ) -> Result;
+}
+
+/// A registered FPGA manager.
+///
+/// The manager is unregistered and its data freed when the registration is dropped.
+///
+/// # Invariants
+///
+/// `mgr` is a manager registered by [`Registration::register`], whose private data was obtained
+/// from [`ForeignOwnable::into_foreign`] on a `T::Data`.
+pub struct Registration<T: Operations> {
+    mgr: NonNull<bindings::fpga_manager>,
+    _p: PhantomData<T>,
+}
+
+impl<T: Operations> Registration<T> {
+    /// Registers a new FPGA manager as a child of `parent`.
+    pub fn register(parent: &impl RawDevice, name: &'static CStr, data: T::Data) -> Result<Self> {
+        let ptr = data.into_foreign();
+        let info = bindings::fpga_manager_info {
+            name: name.as_char_ptr(),
+            mops: &Adapter::<T>::OPS,
+            priv_: ptr as *mut _,
+            ..Default::default()
+        };
+
+        // SAFETY: `parent` is valid by its type invariants, and `info` is valid for the duration
+        // of the call. The ops table is static.
+        let mgr =
+            from_err_ptr(unsafe { bindings::fpga_mgr_register_full(parent.raw_device(), &info) });
+        let mgr = match mgr {
+            Ok(mgr) => mgr,
+            Err(e) => {
+                // SAFETY: `ptr` came from `into_foreign` above and was not registered.
+                drop(unsafe { T::Data::from_foreign(ptr) });
+                return Err(e);
+            }
+        };
+
+        // INVARIANT: The manager was registered above with `ptr` as its private data.
+        Ok(Self {
+            // SAFETY: `fpga_mgr_register_full` returns a valid pointer on success.
+            mgr: unsafe { NonNull::new_unchecked(mgr) },
+            _p: PhantomData,
+        })
+    }
+
+    /// Programs the FPGA with the bitstream in `buf`.
+    ///
+    /// `flags` is a combination of the constants in [`flags`]. Returns [`EBUSY`] if the manager
+    /// is already in use.
+    pub fn load(&self, buf: &[u8], flags: u32) -> Result {
+        let mgr = self.mgr.as_ptr();
+
+        // SAFETY: By the type invariants, `mgr` is a registered manager.
+        to_result(unsafe { bindings::fpga_mgr_lock(mgr) })?;
+
+        // SAFETY: By the type invariants, `mgr` is a registered manager, which is a valid
+        // device.
+        let info = unsafe { bindings::fpga_image_info_alloc(&mut (*mgr).dev) };
+        let ret = if info.is_null() {
+            Err(ENOMEM)
+        } else {
+            // SAFETY: `info` was allocated above. `buf` outlives the call to `fpga_mgr_load`,
+            // which does not keep a reference to it.
+            unsafe {
+                (*info).flags = flags;
+                (*info).buf = buf.as_ptr() as *const c_char;
+                (*info).count = buf.len();
+            }
+            // SAFETY: The manager is locked, and `info` describes a valid buffer.
+            let ret = to_result(unsafe { bindings::fpga_mgr_load(mgr, info) });
+            // SAFETY: `info` was allocated by `fpga_image_info_alloc`.
+            unsafe { bindings::fpga_image_info_free(info) };
+            ret
+        };
+
+        // SAFETY: The manager was locked above.
+        unsafe { bindings::fpga_mgr_unlock(mgr) };
+        ret
+    }
+
+    /// Programs the FPGA with a bitstream obtained from the firmware loader.
+    #[cfg(CONFIG_FW_LOADER)]
+    pub fn load_firmware(&self, fw: &crate::firmware::Firmware, flags: u32) -> Result {
+        self.load(fw.data(), flags)
+    }
+}
+
+impl<T: Operations> Drop for Registration<T> {
+    fn drop(&mut self) {
+        let mgr = self.mgr.as_ptr();
+        // SAFETY: By the type invariants, `mgr` is a registered manager.
+        let ptr = unsafe { (*mgr).priv_ };
+        // SAFETY: By the type invariants, `mgr` is a registered manager. No callbacks run after
+        // it is unregistered.
+        unsafe { bindings::fpga_mgr_unregister(mgr) };
+        // SAFETY: By the type invariants, `ptr` came from `into_foreign`, and it is no longer
+        // used by the unregistered manager.
+        drop(unsafe { T::Data::from_foreign(ptr) });
+    }
+}
+
+// SAFETY: The registration only holds a `T::Data`, which is `Send`, and the manager, which can be
+// unregistered from any thread.
+unsafe impl<T: Operations> Send for Registration<T> {}
+
+// SAFETY: `load` is serialised by the manager lock of the C side.
+unsafe impl<T: Operations> Sync for Registration<T> {}
+
+struct Adapter<T: Operations>(PhantomData<T>);
+
+impl<T: Operations> Adapter<T> {
+    const OPS: bindings::fpga_manager_ops = bindings::fpga_manager_ops {
+        initial_header_size: T::INITIAL_HEADER_SIZE,
+        state: if T::HAS_STATE {
+            Some(Self::state_callback)
+        } else {
+            None
+        },
+        write_init: Some(Self::write_init_callback),
+        write: Some(Self::write_callback),
+        write_complete: Some(Self::write_complete_callback),
+        // SAFETY: The remaining fields are optional callbacks, for which NULL is valid.
+        ..unsafe { core::mem::zeroed() }
+    };
+
+    /// # Safety
+    ///
+    /// `mgr` must be a manager registered by [`Registration::register`].
+    unsafe fn data<'a>(
+        mgr: *mut bindings::fpga_manager,
+    ) -> <T::Data as ForeignOwnable>::Borrowed<'a> {
+        // SAFETY: By the safety requirements, the private data came from `into_foreign`, and is
+        // only freed after the manager is unregistered.
+        unsafe { T::Data::borrow((*mgr).priv_) }
+    }
+
+    unsafe extern "C" fn state_callback(mgr: *mut bindings::fpga_manager) -> u32 {
+        // SAFETY: The C side only calls this for managers registered with these ops.
+        T::state(unsafe { Self::data(mgr) }) as u32
+    }
+
+    unsafe extern "C" fn write_init_callback(
+        mgr: *mut bindings::fpga_manager,
+        info: *mut bindings::fpga_image_info,
+        buf: *const c_char,
+        count: usize,
+    ) -> i32 {
+        from_result(|| {
+            // SAFETY: The C side only calls this for managers registered with these ops, with a
+            // valid image info and a buffer of `count` bytes.
+            let (data, info, header) = unsafe {
+                (
+                    Self::data(mgr),
+                    ImageInfo::from_raw(info),
+                    slice_or_empty(buf, count),
+                )
+            };
+            T::write_init(data, info, header)?;
+            Ok(0)
+        })
+    }
+
+    unsafe extern "C" fn write_callback(
+        mgr: *mut bindings::fpga_manager,
+        buf: *const c_char,
+        count: usize,
+    ) -> i32 {
+        from_result(|| {
+            // SAFETY: The C side only calls this for managers registered with these ops, with a
+            // buffer of `count` bytes.
+            let (data, buf) = unsafe { (Self::data(mgr), slice_or_empty(buf, count)) };
+            T::write(data, buf)?;
+            Ok(0)
+        })
+    }
+
+    unsafe extern "C" fn write_complete_callback(
+        mgr: *mut bindings::fpga_manager,
+        info: *mut bindings::fpga_image_info,
+    ) -> i32 {
+        from_result(|| {
+            // SAFETY: The C side only calls this for managers registered with these ops, with a
+            // valid image info.
+            let (data, info) = unsafe { (Self::data(mgr), ImageInfo::from_raw(info)) };
+            T::write_complete(data, info)?;
+            Ok(0)
+        })
+    }
+}
+
+/// # Safety
+///
+/// If `buf` is not NULL, it must be valid for reads of `count` bytes for the lifetime `'a`.
+unsafe fn slice_or_empty<'a>(buf: *const c_char, count: usize) -> &'a [u8] {
+    if buf.is_null() {
+        return &[];
+    }
+    // SAFETY: By the safety requirements, `buf` is valid for `count` bytes.
+    unsafe { core::slice::from_raw_parts(buf.cast(), count) }
+}
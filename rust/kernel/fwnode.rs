@@ -0,0 +1,375 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Firmware nodes.
+//!
+//! A firmware node describes a device in the firmware of the system, be it a devicetree node or an
+//! ACPI device object. [`FwNode`] gives access to its properties, references and children
+//! independently of the firmware type, so that drivers work unmodified on both.
+//!
+//! C header: [`include/linux/property.h`](srctree/include/linux/property.h)
+
+use crate::{
+    bindings,
+    device::RawDevice,
+    error::{code::*, from_err_ptr, to_result, Error, Result},
+    of,
+    str::CStr,
+    types::{ARef, AlwaysRefCounted, Opaque},
+};
+use core::{
+    ffi::c_uint,
+    mem::ManuallyDrop,
+    ptr::{self, NonNull},
+};
+
+/// A firmware node.
+///
+/// # Invariants
+///
+/// Instances of this type are always reference-counted, that is, a call to `fwnode_handle_get`
+/// ensures that the allocation remains valid at least until the matching call to
+/// `fwnode_handle_put`.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{c_str, device::RawDevice, fwnode::FwNode, prelude::*};
+///
+/// fn parse_leds(dev: &impl RawDevice) -> Result {
+///     let node = FwNode::from_device(dev).ok_or(ENODEV)?;
+///     for child in node.available_children() {
+///         let reg = child.read_u32(c_str!("reg"))?;
+///         let label = child.read_string(c_str!("label")).ok().or(child.name());
+///         let label = label.ok_or(EINVAL)?;
+///         let default_on = child.property_present(c_str!("default-on"));
+///         pr_info!("led {}: {} (on: {})\n", reg, label, default_on);
+///     }
+///     Ok(())
+/// }
+/// ```
+#[repr(transparent)]
+pub struct FwNode(Opaque<bindings::fwnode_handle>);
+
+impl FwNode {
+    /// Returns the firmware node of `dev`, if it has one.
+    pub fn from_device(dev: &impl RawDevice) -> Option<&FwNode> {
+        // SAFETY: `dev.raw_device()` is valid by the safety requirements of `RawDevice`.
+        let ptr = unsafe { bindings::dev_fwnode(dev.raw_device()) };
+        if ptr.is_null() {
+            return None;
+        }
+        // SAFETY: The node of a device remains valid while the device does, which outlives the
+        // returned reference.
+        Some(unsafe { Self::from_raw(ptr) })
+    }
+
+    /// Creates a reference to a [`FwNode`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// Callers must ensure that `ptr` is valid and remains valid for the lifetime of the returned
+    /// reference.
+    pub unsafe fn from_raw<'a>(ptr: *mut bindings::fwnode_handle) -> &'a Self {
+        // SAFETY: Guaranteed by the safety requirements of the function. `FwNode` is a
+        // transparent wrapper around `fwnode_handle`.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Takes ownership of the reference held by `ptr`, returning [`None`] if it is null.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be null or a valid node whose reference count was incremented for the caller.
+    unsafe fn from_owned(ptr: *mut bindings::fwnode_handle) -> Option<ARef<Self>> {
+        let ptr = NonNull::new(ptr.cast::<Self>())?;
+        // SAFETY: By the safety requirements, the reference is owned by the returned `ARef`.
+        Some(unsafe { ARef::from_raw(ptr) })
+    }
+
+    /// Returns a raw pointer to the underlying `struct fwnode_handle`.
+    pub fn as_raw(&self) -> *mut bindings::fwnode_handle {
+        self.0.get()
+    }
+
+    /// Returns the name of the node, or [`None`] if it has none.
+    ///
+    /// For devicetree nodes, this includes the unit address, e.g., `led@0`.
+    pub fn name(&self) -> Option<&CStr> {
+        // SAFETY: The node is valid.
+        let name = unsafe { bindings::fwnode_get_name(self.as_raw()) };
+        if name.is_null() {
+            return None;
+        }
+        // SAFETY: `name` was just checked to be non-null, and is a valid C string that lives as
+        // long as the node.
+        Some(unsafe { CStr::from_char_ptr(name) })
+    }
+
+    /// Returns `true` if the node is a devicetree node.
+    pub fn is_of_node(&self) -> bool {
+        // SAFETY: The node is valid.
+        unsafe { bindings::is_of_node(self.as_raw()) }
+    }
+
+    /// Returns `true` if the node is available for use, e.g., its devicetree status is "okay".
+    pub fn is_available(&self) -> bool {
+        // SAFETY: The node is valid.
+        unsafe { bindings::fwnode_device_is_available(self.as_raw()) }
+    }
+
+    /// Returns the parent of the node, if it has one.
+    pub fn parent(&self) -> Option<ARef<Self>> {
+        // SAFETY: The node is valid. On success, the parent has its reference count incremented.
+        unsafe { Self::from_owned(bindings::fwnode_get_parent(self.as_raw())) }
+    }
+
+    /// Returns `true` if the node has the property `name`.
+    ///
+    /// This is also how boolean properties are read.
+    pub fn property_present(&self, name: &CStr) -> bool {
+        // SAFETY: The node is valid and `name` is a valid C string.
+        unsafe { bindings::fwnode_property_present(self.as_raw(), name.as_char_ptr()) }
+    }
+
+    /// Reads the `u32` property `name`.
+    ///
+    /// Fails with [`EINVAL`] if the property does not exist, and with `ENODATA` or `EOVERFLOW` if
+    /// it does not hold a `u32` value.
+    pub fn read_u32(&self, name: &CStr) -> Result<u32> {
+        let mut val = [0];
+        self.read_u32_array(name, &mut val)?;
+        Ok(val[0])
+    }
+
+    /// Reads the `u64` property `name`.
+    ///
+    /// Fails like [`FwNode::read_u32`].
+    pub fn read_u64(&self, name: &CStr) -> Result<u64> {
+        let mut val = 0;
+        // SAFETY: The node is valid, `name` is a valid C string and `val` is valid for writes of
+        // one value.
+        to_result(unsafe {
+            bindings::fwnode_property_read_u64_array(self.as_raw(), name.as_char_ptr(), &mut val, 1)
+        })?;
+        Ok(val)
+    }
+
+    /// Reads the first `values.len()` elements of the `u32` array property `name`.
+    ///
+    /// Fails with `EOVERFLOW` if the property has fewer elements.
+    pub fn read_u32_array(&self, name: &CStr, values: &mut [u32]) -> Result {
+        // SAFETY: The node is valid, `name` is a valid C string and `values` is valid for writes
+        // of `values.len()` elements.
+        to_result(unsafe {
+            bindings::fwnode_property_read_u32_array(
+                self.as_raw(),
+                name.as_char_ptr(),
+                values.as_mut_ptr(),
+                values.len(),
+            )
+        })
+    }
+
+    /// Returns the number of elements of the `u32` array property `name`.
+    pub fn count_u32(&self, name: &CStr) -> Result<usize> {
+        // SAFETY: The node is valid and `name` is a valid C string. A null buffer requests the
+        // number of elements.
+        let ret = unsafe {
+            bindings::fwnode_property_read_u32_array(
+                self.as_raw(),
+                name.as_char_ptr(),
+                ptr::null_mut(),
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::from_errno(ret));
+        }
+        Ok(ret as usize)
+    }
+
+    /// Reads the string property `name`, or the first string of a string array property.
+    pub fn read_string(&self, name: &CStr) -> Result<&CStr> {
+        let mut val = ptr::null();
+        // SAFETY: The node is valid, `name` is a valid C string and `val` is valid for writes.
+        to_result(unsafe {
+            bindings::fwnode_property_read_string(self.as_raw(), name.as_char_ptr(), &mut val)
+        })?;
+        // SAFETY: On success, `val` points to a C string owned by the node, which lives as long
+        // as `self`.
+        Ok(unsafe { CStr::from_char_ptr(val) })
+    }
+
+    /// Returns the index of `value` in the string array property `name`.
+    ///
+    /// This is commonly used with `*-names` properties. Fails with `ENODATA` if the value is
+    /// not found.
+    pub fn match_string(&self, name: &CStr, value: &CStr) -> Result<usize> {
+        // SAFETY: The node is valid and the strings are valid C strings.
+        let ret = unsafe {
+            bindings::fwnode_property_match_string(
+                self.as_raw(),
+                name.as_char_ptr(),
+                value.as_char_ptr(),
+            )
+        };
+        if ret < 0 {
+            return Err(Error::from_errno(ret));
+        }
+        Ok(ret as usize)
+    }
+
+    /// Returns the node referenced by the `index`th entry of the reference property `name`.
+    pub fn find_reference(&self, name: &CStr, index: u32) -> Result<ARef<Self>> {
+        // SAFETY: The node is valid and `name` is a valid C string.
+        let ptr = from_err_ptr(unsafe {
+            bindings::fwnode_find_reference(self.as_raw(), name.as_char_ptr(), index)
+        })?;
+        // SAFETY: On success, the referenced node has its reference count incremented.
+        unsafe { Self::from_owned(ptr) }.ok_or(ENOENT)
+    }
+
+    /// Returns the `index`th entry of the reference property `prop`, with its arguments.
+    ///
+    /// The number of arguments of each entry is given by the property `nargs_prop` of the
+    /// referenced node, e.g., `#gpio-cells`, or by `nargs` if it is [`None`].
+    pub fn reference_args(
+        &self,
+        prop: &CStr,
+        nargs_prop: Option<&CStr>,
+        nargs: u32,
+        index: u32,
+    ) -> Result<ReferenceArgs> {
+        let nargs_prop = nargs_prop.map_or(ptr::null(), CStr::as_char_ptr);
+        // SAFETY: All-zeroes is a valid `fwnode_reference_args`.
+        let mut args: bindings::fwnode_reference_args = unsafe { core::mem::zeroed() };
+        // SAFETY: The node is valid, the strings are either null or valid C strings, and `args`
+        // is valid for writes.
+        to_result(unsafe {
+            bindings::fwnode_property_get_reference_args(
+                self.as_raw(),
+                prop.as_char_ptr(),
+                nargs_prop,
+                nargs,
+                index,
+                &mut args,
+            )
+        })?;
+        // SAFETY: On success, the referenced node has its reference count incremented.
+        let node = unsafe { Self::from_owned(args.fwnode) }.ok_or(ENOENT)?;
+        Ok(ReferenceArgs {
+            node,
+            nargs: (args.nargs as usize).min(args.args.len()),
+            args: args.args,
+        })
+    }
+
+    /// Returns the child of the node named `name`.
+    pub fn named_child(&self, name: &CStr) -> Option<ARef<Self>> {
+        // SAFETY: The node is valid and `name` is a valid C string. On success, the child has
+        // its reference count incremented.
+        unsafe {
+            Self::from_owned(bindings::fwnode_get_named_child_node(
+                self.as_raw(),
+                name.as_char_ptr(),
+            ))
+        }
+    }
+
+    /// Returns an iterator over the children of the node.
+    pub fn children(&self) -> Children<'_> {
+        Children {
+            parent: self,
+            prev: None,
+            available_only: false,
+        }
+    }
+
+    /// Returns an iterator over the children of the node that are available.
+    pub fn available_children(&self) -> Children<'_> {
+        Children {
+            parent: self,
+            prev: None,
+            available_only: true,
+        }
+    }
+}
+
+// SAFETY: Instances of `FwNode` are always reference-counted.
+unsafe impl AlwaysRefCounted for FwNode {
+    fn inc_ref(&self) {
+        // SAFETY: The existence of a shared reference means that the refcount is nonzero.
+        unsafe { bindings::fwnode_handle_get(self.as_raw()) };
+    }
+
+    unsafe fn dec_ref(obj: NonNull<Self>) {
+        // SAFETY: The safety requirements guarantee that the refcount is nonzero.
+        unsafe { bindings::fwnode_handle_put(obj.cast().as_ptr()) }
+    }
+}
+
+// SAFETY: Nodes are reference-counted and can be released from any thread.
+unsafe impl Send for FwNode {}
+
+// SAFETY: `FwNode` does not expose any method that mutates the node through `&self`.
+unsafe impl Sync for FwNode {}
+
+impl of::Node {
+    /// Returns the firmware node embedded in the devicetree node.
+    pub fn fwnode(&self) -> &FwNode {
+        // SAFETY: The node is valid, and so is the embedded firmware node, which lives as long as
+        // it.
+        unsafe { FwNode::from_raw(ptr::addr_of_mut!((*self.as_raw()).fwnode)) }
+    }
+}
+
+/// An entry of a reference property, returned by [`FwNode::reference_args`].
+pub struct ReferenceArgs {
+    node: ARef<FwNode>,
+    nargs: usize,
+    args: [u64; bindings::NR_FWNODE_REFERENCE_ARGS as usize],
+}
+
+impl ReferenceArgs {
+    /// Returns the referenced node.
+    pub fn node(&self) -> &FwNode {
+        &self.node
+    }
+
+    /// Returns the arguments of the reference.
+    pub fn args(&self) -> &[u64] {
+        &self.args[..self.nargs]
+    }
+}
+
+/// An iterator over the children of a [`FwNode`].
+///
+/// Returned by [`FwNode::children`] and [`FwNode::available_children`].
+pub struct Children<'a> {
+    parent: &'a FwNode,
+    prev: Option<ARef<FwNode>>,
+    available_only: bool,
+}
+
+impl Iterator for Children<'_> {
+    type Item = ARef<FwNode>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The reference to the previous child is given up by the C functions.
+        let prev = ManuallyDrop::new(self.prev.take());
+        let prev = prev.as_ref().map_or(ptr::null_mut(), |p| p.as_raw());
+        // SAFETY: The parent is valid, and `prev` is either null or a child of it whose
+        // reference is transferred to the call. The returned child, if any, has its reference
+        // count incremented.
+        let next = unsafe {
+            if self.available_only {
+                bindings::fwnode_get_next_available_child_node(self.parent.as_raw(), prev)
+            } else {
+                bindings::fwnode_get_next_child_node(self.parent.as_raw(), prev)
+            }
+        };
+        // SAFETY: The reference taken above is owned by `self.prev`.
+        self.prev = unsafe { FwNode::from_owned(next) };
+        self.prev.clone()
+    }
+}
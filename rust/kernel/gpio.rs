@@ -0,0 +1,259 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! GPIO consumers.
+//!
+//! Drivers obtain the GPIOs described for their device in the firmware, e.g., a `reset-gpios`
+//! devicetree property, as a [`Desc`].
+//!
+//! C header: [`include/linux/gpio/consumer.h`](srctree/include/linux/gpio/consumer.h)
+
+use crate::{
+    bindings,
+    device::RawDevice,
+    error::{code::*, from_err_ptr, Error, Result},
+    irq,
+    str::CStr,
+};
+use core::ptr::NonNull;
+
+/// The initial configuration of a GPIO when it is requested.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flags {
+    /// Keep the current direction.
+    AsIs = bindings::gpiod_flags_GPIOD_ASIS,
+
+    /// Configure the GPIO as an input.
+    In = bindings::gpiod_flags_GPIOD_IN,
+
+    /// Configure the GPIO as an output, initially inactive.
+    OutLow = bindings::gpiod_flags_GPIOD_OUT_LOW,
+
+    /// Configure the GPIO as an output, initially active.
+    OutHigh = bindings::gpiod_flags_GPIOD_OUT_HIGH,
+}
+
+/// The condition that triggers the interrupt of a GPIO.
+///
+/// The levels refer to the physical line; the active-low setting of the GPIO does not apply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    /// Trigger on the rising edge.
+    RisingEdge,
+
+    /// Trigger on the falling edge.
+    FallingEdge,
+
+    /// Trigger on both edges.
+    BothEdges,
+
+    /// Trigger while the line is high.
+    High,
+
+    /// Trigger while the line is low.
+    Low,
+}
+
+impl Trigger {
+    fn irq_flags(self) -> usize {
+        match self {
+            Trigger::RisingEdge => irq::flags::TRIGGER_RISING,
+            Trigger::FallingEdge => irq::flags::TRIGGER_FALLING,
+            Trigger::BothEdges => irq::flags::TRIGGER_RISING | irq::flags::TRIGGER_FALLING,
+            Trigger::High => irq::flags::TRIGGER_HIGH,
+            Trigger::Low => irq::flags::TRIGGER_LOW,
+        }
+    }
+}
+
+/// A GPIO descriptor owned by a consumer.
+///
+/// The GPIO is released when the descriptor is dropped.
+///
+/// # Invariants
+///
+/// `ptr` was returned by a successful call to `gpiod_get` and has not been released.
+pub struct Desc {
+    ptr: NonNull<bindings::gpio_desc>,
+}
+
+impl Desc {
+    /// Requests the GPIO `con_id` of `dev`.
+    ///
+    /// `con_id` is the function of the GPIO, e.g., `reset` for a `reset-gpios` devicetree
+    /// property, or [`None`] for an unnamed `gpios` property.
+    pub fn get(dev: &impl RawDevice, con_id: Option<&CStr>, flags: Flags) -> Result<Self> {
+        let con_id = con_id.map_or(core::ptr::null(), CStr::as_char_ptr);
+        // SAFETY: `dev.raw_device()` is valid by the safety requirements of `RawDevice`, and
+        // `con_id` is either null or a valid C string.
+        let ptr =
+            from_err_ptr(unsafe { bindings::gpiod_get(dev.raw_device(), con_id, flags as _) })?;
+        // INVARIANT: `gpiod_get` succeeded. It never returns null unless GPIO support is
+        // disabled, in which case it returns an error.
+        Ok(Self {
+            ptr: NonNull::new(ptr).ok_or(ENOENT)?,
+        })
+    }
+
+    /// Requests the GPIO `con_id` of `dev`, returning [`None`] if it is not described in the
+    /// firmware.
+    pub fn get_optional(
+        dev: &impl RawDevice,
+        con_id: Option<&CStr>,
+        flags: Flags,
+    ) -> Result<Option<Self>> {
+        match Self::get(dev, con_id, flags) {
+            Ok(desc) => Ok(Some(desc)),
+            Err(e) if e == ENOENT => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn as_raw(&self) -> *mut bindings::gpio_desc {
+        self.ptr.as_ptr()
+    }
+
+    /// Returns the logical value of the GPIO, taking its active-low setting into account.
+    ///
+    /// This may sleep.
+    pub fn value(&self) -> Result<bool> {
+        // SAFETY: By the type invariants, the descriptor is valid.
+        let ret = unsafe { bindings::gpiod_get_value_cansleep(self.as_raw()) };
+        if ret < 0 {
+            return Err(Error::from_errno(ret));
+        }
+        Ok(ret != 0)
+    }
+
+    /// Sets the logical value of the GPIO, taking its active-low setting into account.
+    ///
+    /// This may sleep.
+    pub fn set_value(&self, value: bool) {
+        // SAFETY: By the type invariants, the descriptor is valid.
+        unsafe { bindings::gpiod_set_value_cansleep(self.as_raw(), value.into()) };
+    }
+
+    /// Returns the interrupt number of the GPIO.
+    ///
+    /// Fails if the GPIO controller cannot generate interrupts for it.
+    pub fn to_irq(&self) -> Result<u32> {
+        // SAFETY: By the type invariants, the descriptor is valid.
+        let ret = unsafe { bindings::gpiod_to_irq(self.as_raw()) };
+        if ret < 0 {
+            return Err(Error::from_errno(ret));
+        }
+        // A zero interrupt number is not valid.
+        u32::try_from(ret).ok().filter(|&irq| irq != 0).ok_or(ENXIO)
+    }
+
+    /// Registers a handler for the interrupt of the GPIO, triggered by `trigger`.
+    ///
+    /// The returned registration owns the GPIO, so that it stays requested as an input for as long
+    /// as the handler is registered. Both are released when the registration is dropped; drivers
+    /// usually store it in their device data, or use [`Desc::devm_request_irq`] instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kernel::{c_str, device::RawDevice, gpio, irq, prelude::*};
+    ///
+    /// struct Sensor;
+    ///
+    /// impl irq::Handler for Sensor {
+    ///     type Data = Box<Sensor>;
+    ///
+    ///     fn handle_irq(_sensor: &Sensor) -> irq::Return {
+    ///         irq::Return::Handled
+    ///     }
+    /// }
+    ///
+    /// fn setup(dev: &impl RawDevice) -> Result<gpio::IrqRegistration<Sensor>> {
+    ///     let desc = gpio::Desc::get(dev, Some(c_str!("interrupt")), gpio::Flags::In)?;
+    ///     let data = Box::new(Sensor, GFP_KERNEL)?;
+    ///     desc.request_irq(gpio::Trigger::FallingEdge, data, c_str!("sensor"))
+    /// }
+    /// ```
+    pub fn request_irq<H: irq::Handler>(
+        self,
+        trigger: Trigger,
+        data: H::Data,
+        name: &'static CStr,
+    ) -> Result<IrqRegistration<H>> {
+        let irq = self.to_irq()?;
+        let irq = irq::Registration::try_new(irq, data, trigger.irq_flags(), name)?;
+        Ok(IrqRegistration { irq, desc: self })
+    }
+
+    /// Registers a handler for the interrupt of the GPIO, like [`Desc::request_irq`], until the
+    /// driver is unbound from `dev`.
+    ///
+    /// This must only be called while a driver is bound to `dev`, or is being probed, see
+    /// [`RawDevice::add_cleanup`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kernel::{c_str, device::RawDevice, gpio, irq, prelude::*};
+    ///
+    /// struct Button;
+    ///
+    /// impl irq::Handler for Button {
+    ///     type Data = Box<Button>;
+    ///
+    ///     fn handle_irq(_button: &Button) -> irq::Return {
+    ///         irq::Return::Handled
+    ///     }
+    /// }
+    ///
+    /// fn probe(dev: &impl RawDevice) -> Result {
+    ///     let desc = gpio::Desc::get(dev, None, gpio::Flags::In)?;
+    ///     let data = Box::new(Button, GFP_KERNEL)?;
+    ///     desc.devm_request_irq(dev, gpio::Trigger::BothEdges, data, c_str!("button"))
+    /// }
+    /// ```
+    pub fn devm_request_irq<H: irq::Handler + 'static>(
+        self,
+        dev: &impl RawDevice,
+        trigger: Trigger,
+        data: H::Data,
+        name: &'static CStr,
+    ) -> Result {
+        let reg = self.request_irq::<H>(trigger, data, name)?;
+        dev.add_cleanup(move || drop(reg))
+    }
+}
+
+/// The handler of the interrupt of a GPIO, registered with [`Desc::request_irq`].
+///
+/// The handler is unregistered and then the GPIO released when this object is dropped.
+pub struct IrqRegistration<H: irq::Handler> {
+    // Dropped before `desc`, so that the interrupt is freed before the GPIO.
+    irq: irq::Registration<H>,
+    desc: Desc,
+}
+
+impl<H: irq::Handler> IrqRegistration<H> {
+    /// Returns the GPIO, e.g., to read the level of the line.
+    pub fn desc(&self) -> &Desc {
+        &self.desc
+    }
+
+    /// Returns the interrupt registration, e.g., to set the affinity of the interrupt.
+    pub fn irq(&self) -> &irq::Registration<H> {
+        &self.irq
+    }
+}
+
+impl Drop for Desc {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, the descriptor was obtained from `gpiod_get` and has
+        // not been released yet.
+        unsafe { bindings::gpiod_put(self.as_raw()) };
+    }
+}
+
+// SAFETY: GPIO descriptors can be used and released from any thread.
+unsafe impl Send for Desc {}
+
+// SAFETY: The consumer API serialises concurrent accesses to the same GPIO.
+unsafe impl Sync for Desc {}
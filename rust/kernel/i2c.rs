@@ -7,9 +7,9 @@
 use crate::{
     bindings,
     device::RawDevice,
-    driver::{self, RawDeviceId},
-    error::{from_result, to_result, Result},
-    of,
+    driver::{self, Adapter as _, RawDeviceId},
+    error::{code::*, to_result, Result},
+    of, pm,
     str::{BStr, CStr},
     types::ForeignOwnable,
     ThisModule,
@@ -89,6 +89,14 @@ macro_rules! module_i2c_id_table {
 /// An adapter for the registration of i2c drivers.
 pub struct Adapter<T: Driver>(T);
 
+impl<T: Driver> driver::Adapter for Adapter<T> {
+    type IdInfo = T::IdInfo;
+    type Data = T::Data;
+    const OF_ID_TABLE: Option<driver::IdTable<'static, of::DeviceId, T::IdInfo>> =
+        T::OF_DEVICE_ID_TABLE;
+    const PM_OPS: Option<&'static pm::Ops<T::Data>> = T::PM_OPS;
+}
+
 impl<T: Driver> driver::DriverOps for Adapter<T> {
     type RegType = bindings::i2c_driver;
 
@@ -101,15 +109,12 @@ unsafe fn register(
         // `reg` is non-null and valid.
         let i2cdrv = unsafe { &mut *reg };
 
-        i2cdrv.driver.name = name.as_char_ptr();
+        Self::init_driver(&mut i2cdrv.driver, name);
         i2cdrv.probe = Some(Self::probe_callback);
         i2cdrv.remove = Some(Self::remove_callback);
         if let Some(t) = T::I2C_DEVICE_ID_TABLE {
             i2cdrv.id_table = t.as_ref();
         }
-        if let Some(t) = T::OF_DEVICE_ID_TABLE {
-            i2cdrv.driver.of_match_table = t.as_ref();
-        }
 
         // SAFETY:
         //   - `pdrv` lives at least until the call to `platform_driver_unregister()` returns.
@@ -131,28 +136,19 @@ unsafe fn unregister(reg: *mut Self::RegType) {
 
 impl<T: Driver> Adapter<T> {
     extern "C" fn probe_callback(i2c: *mut bindings::i2c_client) -> core::ffi::c_int {
-        from_result(|| {
-            let mut client = unsafe { Client::from_ptr(i2c) };
-            let data = T::probe(&mut client)?;
-
-            // SAFETY: `i2c` is guaranteed to be a valid, non-null pointer.
-            unsafe { bindings::i2c_set_clientdata(i2c, data.into_foreign() as _) };
-            Ok(0)
-        })
+        // SAFETY: `i2c` is valid by the contract with the C code. `client` is alive only for the
+        // duration of this call, so it is guaranteed to remain alive for the lifetime of `i2c`.
+        let mut client = unsafe { Client::from_ptr(i2c) };
+        // SAFETY: No driver data has been set yet because the client is only now being bound.
+        unsafe { Self::probe_device(&mut client, T::probe) }
     }
 
     extern "C" fn remove_callback(i2c: *mut bindings::i2c_client) {
         // SAFETY: `i2c` is guaranteed to be a valid, non-null pointer
-        let ptr = unsafe { bindings::i2c_get_clientdata(i2c) };
-        // SAFETY:
-        //   - we allocated this pointer using `T::Data::into_pointer`,
-        //     so it is safe to turn back into a `T::Data`.
-        //   - the allocation happened in `probe`, no-one freed the memory,
-        //     `remove` is the canonical kernel location to free driver data. so OK
-        //     to convert the pointer back to a Rust structure here.
-        let data = unsafe { T::Data::from_foreign(ptr) };
-        T::remove(&data);
-        <T::Data as driver::DeviceRemoval>::device_remove(&data);
+        let client = unsafe { Client::from_ptr(i2c) };
+        // SAFETY: The driver data was set in `probe`, and `remove` is the canonical kernel
+        // location to free it.
+        unsafe { Self::remove_device(&client, T::remove) };
     }
 }
 
@@ -165,7 +161,7 @@ pub trait Driver {
     ///
     /// Require that `Data` implements `PointerWrapper`. We guarantee to
     /// never move the underlying wrapped data structure. This allows
-    type Data: ForeignOwnable + Send + Sync + driver::DeviceRemoval = ();
+    type Data: ForeignOwnable + Send + Sync + driver::DeviceRemoval + 'static = ();
 
     /// The type holding information about each device id supported by the driver.
     type IdInfo: 'static = ();
@@ -176,10 +172,21 @@ pub trait Driver {
     /// The table of OF device ids supported by the driver.
     const OF_DEVICE_ID_TABLE: Option<driver::IdTable<'static, of::DeviceId, Self::IdInfo>> = None;
 
+    /// The power management callbacks of the driver, usually created with [`pm::ops`].
+    ///
+    /// [`pm::ops`]: crate::pm::ops
+    const PM_OPS: Option<&'static pm::Ops<Self::Data>> = None;
+
     /// I2C driver probe.
     ///
     /// Called when a new i2c client is added or discovered.
     /// Implementers should attempt to initialize the client here.
+    ///
+    /// If a resource the device depends on is not available yet, return [`EPROBE_DEFER`] so that
+    /// probing is retried later; [`dev_err_probe`] records the reason for the deferral.
+    ///
+    /// [`EPROBE_DEFER`]: crate::error::code::EPROBE_DEFER
+    /// [`dev_err_probe`]: crate::dev_err_probe
     fn probe(client: &mut Client) -> Result<Self::Data>;
 
     /// I2C driver remove.
@@ -222,6 +229,108 @@ fn raw_device(&self) -> *mut bindings::device {
     }
 }
 
+/// SMBus transfers to a device.
+///
+/// This is implemented by [`Client`] and by software models of devices, e.g.,
+/// `mock::Mock`, so that driver code that is generic over it can be tested
+/// without the device. Commands are usually register addresses.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{i2c::Smbus, prelude::*};
+///
+/// const WHO_AM_I: u8 = 0x0f;
+///
+/// fn identify(dev: &impl Smbus) -> Result {
+///     if dev.read_byte_data(WHO_AM_I)? != 0x6a {
+///         return Err(ENODEV);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub trait Smbus {
+    /// Reads the byte selected by `command`.
+    fn read_byte_data(&self, command: u8) -> Result<u8>;
+
+    /// Writes the byte selected by `command`.
+    fn write_byte_data(&self, command: u8, value: u8) -> Result;
+
+    /// Reads the little-endian word selected by `command`.
+    fn read_word_data(&self, command: u8) -> Result<u16>;
+
+    /// Writes the little-endian word selected by `command`.
+    fn write_word_data(&self, command: u8, value: u16) -> Result;
+
+    /// Reads up to [`SMBUS_BLOCK_MAX`] bytes starting at `command` into `buf`, and returns the
+    /// number of bytes read.
+    ///
+    /// This is an I2C block read, whose length is given by the host, not an SMBus block read,
+    /// which starts with a count byte sent by the device.
+    fn read_i2c_block_data(&self, command: u8, buf: &mut [u8]) -> Result<usize>;
+
+    /// Writes up to [`SMBUS_BLOCK_MAX`] bytes starting at `command`.
+    ///
+    /// This is an I2C block write, which unlike an SMBus block write sends no count byte.
+    fn write_i2c_block_data(&self, command: u8, buf: &[u8]) -> Result;
+}
+
+/// Maximum length of an SMBus or I2C block transfer.
+pub const SMBUS_BLOCK_MAX: usize = bindings::I2C_SMBUS_BLOCK_MAX as usize;
+
+impl Smbus for Client {
+    fn read_byte_data(&self, command: u8) -> Result<u8> {
+        // SAFETY: By the type invariants, `self.ptr` is valid.
+        let ret = unsafe { bindings::i2c_smbus_read_byte_data(self.ptr, command) };
+        to_result(ret)?;
+        Ok(ret as u8)
+    }
+
+    fn write_byte_data(&self, command: u8, value: u8) -> Result {
+        // SAFETY: By the type invariants, `self.ptr` is valid.
+        to_result(unsafe { bindings::i2c_smbus_write_byte_data(self.ptr, command, value) })
+    }
+
+    fn read_word_data(&self, command: u8) -> Result<u16> {
+        // SAFETY: By the type invariants, `self.ptr` is valid.
+        let ret = unsafe { bindings::i2c_smbus_read_word_data(self.ptr, command) };
+        to_result(ret)?;
+        Ok(ret as u16)
+    }
+
+    fn write_word_data(&self, command: u8, value: u16) -> Result {
+        // SAFETY: By the type invariants, `self.ptr` is valid.
+        to_result(unsafe { bindings::i2c_smbus_write_word_data(self.ptr, command, value) })
+    }
+
+    fn read_i2c_block_data(&self, command: u8, buf: &mut [u8]) -> Result<usize> {
+        let len = buf.len().min(SMBUS_BLOCK_MAX);
+        // SAFETY: By the type invariants, `self.ptr` is valid, and `buf` is valid for writes of
+        // `len` bytes.
+        let ret = unsafe {
+            bindings::i2c_smbus_read_i2c_block_data(self.ptr, command, len as u8, buf.as_mut_ptr())
+        };
+        to_result(ret)?;
+        Ok(ret as usize)
+    }
+
+    fn write_i2c_block_data(&self, command: u8, buf: &[u8]) -> Result {
+        if buf.len() > SMBUS_BLOCK_MAX {
+            return Err(EINVAL);
+        }
+        // SAFETY: By the type invariants, `self.ptr` is valid, and `buf` is valid for reads of
+        // its length, which fits in a `u8`.
+        to_result(unsafe {
+            bindings::i2c_smbus_write_i2c_block_data(
+                self.ptr,
+                command,
+                buf.len() as u8,
+                buf.as_ptr(),
+            )
+        })
+    }
+}
+
 /// Declares a kernel module that exposes a single i2c driver.
 ///
 /// # Examples
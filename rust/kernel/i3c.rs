@@ -0,0 +1,478 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! I3C devices and drivers.
+//!
+//! C header: [`include/linux/i3c/device.h`](srctree/include/linux/i3c/device.h)
+
+use crate::{
+    bindings,
+    device::{self, RawDevice},
+    driver::{self, Adapter as _, RawDeviceId},
+    error::{code::*, to_result, Result},
+    of, pm,
+    str::CStr,
+    types::ForeignOwnable,
+    ThisModule,
+};
+
+/// An I3C device id.
+#[derive(Clone, Copy)]
+pub enum DeviceId {
+    /// Matches devices with the given manufacturer and part ids.
+    Part {
+        /// The MIPI manufacturer id.
+        manuf_id: u16,
+        /// The part id.
+        part_id: u16,
+    },
+    /// Matches devices with the given manufacturer, part and extra info ids.
+    PartExtraInfo {
+        /// The MIPI manufacturer id.
+        manuf_id: u16,
+        /// The part id.
+        part_id: u16,
+        /// The vendor-defined extra info.
+        extra_info: u16,
+    },
+    /// Matches devices with the given device characteristics register (DCR), i.e., by class.
+    Class(u8),
+}
+
+// SAFETY: `ZERO` is all zeroed-out and `to_rawid` stores `offset` in `i3c_device_id::data`.
+unsafe impl driver::RawDeviceId for DeviceId {
+    type RawType = bindings::i3c_device_id;
+    const ZERO: Self::RawType = bindings::i3c_device_id {
+        match_flags: 0,
+        dcr: 0,
+        manuf_id: 0,
+        part_id: 0,
+        extra_info: 0,
+        data: core::ptr::null(),
+    };
+}
+
+impl DeviceId {
+    #[doc(hidden)]
+    pub const fn to_rawid(&self, offset: isize) -> <Self as RawDeviceId>::RawType {
+        let mut id = Self::ZERO;
+        match *self {
+            DeviceId::Part { manuf_id, part_id } => {
+                id.match_flags = (bindings::I3C_MATCH_MANUF | bindings::I3C_MATCH_PART) as _;
+                id.manuf_id = manuf_id;
+                id.part_id = part_id;
+            }
+            DeviceId::PartExtraInfo {
+                manuf_id,
+                part_id,
+                extra_info,
+            } => {
+                id.match_flags = (bindings::I3C_MATCH_MANUF
+                    | bindings::I3C_MATCH_PART
+                    | bindings::I3C_MATCH_EXTRA_INFO) as _;
+                id.manuf_id = manuf_id;
+                id.part_id = part_id;
+                id.extra_info = extra_info;
+            }
+            DeviceId::Class(dcr) => {
+                id.match_flags = bindings::I3C_MATCH_DCR as _;
+                id.dcr = dcr;
+            }
+        }
+        id.data = offset as _;
+        id
+    }
+}
+
+/// Defines a const I3C device id table that also carries per-entry data/context/info.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::i3c;
+///
+/// kernel::define_i3c_id_table! {MY_ID_TABLE, u32, [
+///     (i3c::DeviceId::Part { manuf_id: 0x1a0, part_id: 0x1 }, Some(0xff)),
+///     (i3c::DeviceId::Class(0xc6), None),
+/// ]};
+/// ```
+#[macro_export]
+macro_rules! define_i3c_id_table {
+    ($name:ident, $data_type:ty, $($t:tt)*) => {
+        $crate::define_id_array!($name, $crate::i3c::DeviceId, $data_type, $($t)*);
+    };
+}
+
+/// Convenience macro to declare which device ID table to use for a bus driver.
+#[macro_export]
+macro_rules! driver_i3c_id_table {
+    ($name:expr) => {
+        $crate::driver_id_table!(
+            I3C_DEVICE_ID_TABLE,
+            $crate::i3c::DeviceId,
+            Self::IdInfo,
+            $name
+        );
+    };
+}
+
+/// Declare a device ID table as a module-level table. This creates the necessary module alias
+/// entries to enable module autoloading.
+#[macro_export]
+macro_rules! module_i3c_id_table {
+    ($item_name:ident, $table_name:ident) => {
+        $crate::module_id_table!($item_name, "i3c", $crate::i3c::DeviceId, $table_name);
+    };
+}
+
+/// An adapter for the registration of I3C drivers.
+pub struct Adapter<T: Driver>(T);
+
+impl<T: Driver> driver::Adapter for Adapter<T> {
+    type IdInfo = T::IdInfo;
+    type Data = T::Data;
+    // I3C devices are discovered on the bus and matched by their ids only.
+    const OF_ID_TABLE: Option<driver::IdTable<'static, of::DeviceId, T::IdInfo>> = None;
+    const PM_OPS: Option<&'static pm::Ops<T::Data>> = T::PM_OPS;
+}
+
+impl<T: Driver> driver::DriverOps for Adapter<T> {
+    type RegType = bindings::i3c_driver;
+
+    unsafe fn register(
+        reg: *mut Self::RegType,
+        name: &'static CStr,
+        module: &'static ThisModule,
+    ) -> Result {
+        // SAFETY: By the safety requirements of this function (defined in the trait definition),
+        // `reg` is non-null and valid.
+        let i3cdrv = unsafe { &mut *reg };
+
+        Self::init_driver(&mut i3cdrv.driver, name);
+        i3cdrv.probe = Some(Self::probe_callback);
+        i3cdrv.remove = Some(Self::remove_callback);
+        if let Some(t) = T::I3C_DEVICE_ID_TABLE {
+            i3cdrv.id_table = t.as_ref();
+        }
+
+        // SAFETY:
+        //   - `i3cdrv` lives at least until the call to `i3c_driver_unregister()` returns.
+        //   - `name` pointer has static lifetime.
+        //   - `module.0` lives at least as long as the module.
+        //   - `probe()` and `remove()` are static functions.
+        //   - `id_table` is either a raw pointer with static lifetime,
+        //      as guaranteed by the [`driver::IdTable`] type, or null.
+        to_result(unsafe { bindings::i3c_driver_register_with_owner(reg, module.0) })
+    }
+
+    unsafe fn unregister(reg: *mut Self::RegType) {
+        // SAFETY: By the safety requirements of this function (defined in the trait definition),
+        // `reg` was passed (and updated) by a previous successful call to
+        // `i3c_driver_register_with_owner`.
+        unsafe { bindings::i3c_driver_unregister(reg) };
+    }
+}
+
+impl<T: Driver> Adapter<T> {
+    fn get_id_info(dev: &Device) -> Option<&'static T::IdInfo> {
+        let table = T::I3C_DEVICE_ID_TABLE?;
+
+        // SAFETY: `table` has static lifetime, so it is valid for read. `dev` is guaranteed to be
+        // valid while it's alive.
+        let id = unsafe { bindings::i3c_device_match_id(dev.ptr, table.as_ref()) };
+        if id.is_null() {
+            return None;
+        }
+
+        // SAFETY: `id` is a pointer within the static table, whose `data` field holds the offset
+        // stored by `to_rawid`.
+        unsafe { driver::id_info(id, (*id).data as isize) }
+    }
+
+    extern "C" fn probe_callback(i3cdev: *mut bindings::i3c_device) -> core::ffi::c_int {
+        // SAFETY: `i3cdev` is valid by the contract with the C code. `dev` is alive only for the
+        // duration of this call, so it is guaranteed to remain alive for the lifetime of `i3cdev`.
+        let mut dev = unsafe { Device::from_ptr(i3cdev) };
+        // SAFETY: No driver data has been set yet because the device is only now being bound.
+        let ret =
+            unsafe { Self::probe_device(&mut dev, |dev| T::probe(dev, Self::get_id_info(dev))) };
+        if ret != 0 {
+            return ret;
+        }
+
+        if let Some(setup) = T::IBI_SETUP {
+            if let Err(e) = Self::setup_ibi(&dev, &setup) {
+                // SAFETY: The driver data was set above, and the IBI handler, the only other user,
+                // is not registered.
+                unsafe { Self::remove_device(&dev, T::remove) };
+                return e.to_errno();
+            }
+        }
+        0
+    }
+
+    fn setup_ibi(dev: &Device, setup: &IbiSetup) -> Result {
+        let req = bindings::i3c_ibi_setup {
+            max_payload_len: setup.max_payload_len,
+            num_slots: setup.num_slots,
+            handler: Some(Self::ibi_callback),
+        };
+
+        // SAFETY: `dev.ptr` is valid, and `req` is valid for the duration of the call.
+        to_result(unsafe { bindings::i3c_device_request_ibi(dev.ptr, &req) })?;
+
+        // SAFETY: The IBI was requested above.
+        if let Err(e) = to_result(unsafe { bindings::i3c_device_enable_ibi(dev.ptr) }) {
+            // SAFETY: The IBI was requested above and is not enabled.
+            unsafe { bindings::i3c_device_free_ibi(dev.ptr) };
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    unsafe extern "C" fn ibi_callback(
+        i3cdev: *mut bindings::i3c_device,
+        payload: *const bindings::i3c_ibi_payload,
+    ) {
+        // SAFETY: The I3C core only calls this for devices the IBI was requested for, with a
+        // valid payload.
+        let (dev, payload) = unsafe {
+            let payload = &*payload;
+            (
+                Device::from_ptr(i3cdev),
+                core::slice::from_raw_parts(payload.data.cast::<u8>(), payload.len as usize),
+            )
+        };
+
+        // SAFETY: The IBI is freed in `remove_callback` before the driver data is, so the device
+        // is bound while the handler runs.
+        if let Some(data) = unsafe { dev.drvdata::<T::Data>() } {
+            T::ibi(data, payload);
+        }
+    }
+
+    extern "C" fn remove_callback(i3cdev: *mut bindings::i3c_device) {
+        // SAFETY: `i3cdev` is guaranteed to be a valid, non-null pointer.
+        let dev = unsafe { Device::from_ptr(i3cdev) };
+
+        if T::IBI_SETUP.is_some() {
+            // SAFETY: The IBI was requested and enabled in `probe_callback`. `free_ibi` waits for
+            // handlers that are still running.
+            unsafe {
+                bindings::i3c_device_disable_ibi(dev.ptr);
+                bindings::i3c_device_free_ibi(dev.ptr);
+            }
+        }
+
+        // SAFETY: The driver data was set in `probe`, and the IBI handler no longer runs.
+        unsafe { Self::remove_device(&dev, T::remove) };
+    }
+}
+
+/// Configuration of the in-band interrupts (IBIs) of a device.
+///
+/// See [`Driver::IBI_SETUP`].
+#[derive(Clone, Copy, Debug)]
+pub struct IbiSetup {
+    /// The maximum payload length, in bytes, that the device sends with an IBI.
+    pub max_payload_len: u32,
+
+    /// The number of IBIs that can be queued before they are handled.
+    pub num_slots: u32,
+}
+
+/// An I3C driver.
+pub trait Driver {
+    /// Data stored on device by driver.
+    ///
+    /// Corresponds to the data set or retrieved via the kernel's
+    /// `i3cdev_{set,get}_drvdata()` functions.
+    type Data: ForeignOwnable + Send + Sync + driver::DeviceRemoval + 'static = ();
+
+    /// The type holding information about each device id supported by the driver.
+    type IdInfo: 'static = ();
+
+    /// The table of I3C device ids supported by the driver.
+    const I3C_DEVICE_ID_TABLE: Option<driver::IdTable<'static, DeviceId, Self::IdInfo>> = None;
+
+    /// The configuration of in-band interrupts.
+    ///
+    /// If set, in-band interrupts are requested and enabled right after [`Driver::probe`]
+    /// succeeds, and disabled and freed before [`Driver::remove`] is called. Each interrupt is
+    /// delivered to [`Driver::ibi`].
+    const IBI_SETUP: Option<IbiSetup> = None;
+
+    /// The power management callbacks of the driver, usually created with [`pm::ops`].
+    ///
+    /// [`pm::ops`]: crate::pm::ops
+    const PM_OPS: Option<&'static pm::Ops<Self::Data>> = None;
+
+    /// I3C driver probe.
+    ///
+    /// Called when a new I3C device is added or discovered.
+    /// Implementers should attempt to initialize the device here.
+    ///
+    /// If a resource the device depends on is not available yet, return [`EPROBE_DEFER`] so that
+    /// probing is retried later; [`dev_err_probe`] records the reason for the deferral.
+    ///
+    /// [`EPROBE_DEFER`]: crate::error::code::EPROBE_DEFER
+    /// [`dev_err_probe`]: crate::dev_err_probe
+    fn probe(dev: &mut Device, id_info: Option<&Self::IdInfo>) -> Result<Self::Data>;
+
+    /// I3C driver remove.
+    ///
+    /// Called when an I3C device is removed.
+    fn remove(_data: &Self::Data) {}
+
+    /// Handles an in-band interrupt with the given payload.
+    ///
+    /// Called from a workqueue, so it may sleep, e.g. to issue private transfers to the device.
+    fn ibi(_data: <Self::Data as ForeignOwnable>::Borrowed<'_>, _payload: &[u8]) {}
+}
+
+/// A private SDR transfer, used with [`Device::priv_xfers`].
+pub enum Transfer<'a> {
+    /// Reads from the device into the buffer.
+    Read(&'a mut [u8]),
+
+    /// Writes the buffer to the device.
+    Write(&'a [u8]),
+}
+
+/// Information about an I3C device, as returned by [`Device::info`].
+#[derive(Clone, Copy, Debug)]
+pub struct DeviceInfo {
+    /// The 48-bit provisioned id.
+    pub pid: u64,
+    /// The bus characteristics register.
+    pub bcr: u8,
+    /// The device characteristics register.
+    pub dcr: u8,
+    /// The static address, or 0 if the device has none.
+    pub static_addr: u8,
+    /// The dynamic address assigned by the controller.
+    pub dyn_addr: u8,
+    /// The maximum IBI payload length.
+    pub max_ibi_len: u32,
+}
+
+/// An I3C device.
+///
+/// # Invariants
+///
+/// The field `ptr` is non-null and valid for the lifetime of the object.
+pub struct Device {
+    ptr: *mut bindings::i3c_device,
+}
+
+impl Device {
+    /// Creates a new device from the given pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be non-null and valid. It must remain valid for the lifetime of the returned
+    /// instance.
+    unsafe fn from_ptr(ptr: *mut bindings::i3c_device) -> Self {
+        // INVARIANT: The safety requirements of the function ensure the lifetime invariant.
+        Self { ptr }
+    }
+
+    /// Returns the raw I3C device structure.
+    pub fn raw_i3c_device(&self) -> *mut bindings::i3c_device {
+        self.ptr
+    }
+
+    /// Returns information about the device.
+    pub fn info(&self) -> DeviceInfo {
+        let mut info = bindings::i3c_device_info::default();
+        // SAFETY: By the type invariants, `self.ptr` is valid, and `info` is valid for writes.
+        unsafe { bindings::i3c_device_get_info(self.ptr, &mut info) };
+        DeviceInfo {
+            pid: info.pid,
+            bcr: info.bcr,
+            dcr: info.dcr,
+            static_addr: info.static_addr,
+            dyn_addr: info.dyn_addr,
+            max_ibi_len: info.max_ibi_len,
+        }
+    }
+
+    /// Performs the given private SDR transfers, in order, as a single bus transaction.
+    pub fn priv_xfers<const N: usize>(&self, xfers: &mut [Transfer<'_>; N]) -> Result {
+        // SAFETY: `i3c_priv_xfer` is a C struct for which all-zeroes is a valid value.
+        let mut raw: [bindings::i3c_priv_xfer; N] = unsafe { core::mem::zeroed() };
+        for (r, x) in raw.iter_mut().zip(xfers.iter_mut()) {
+            match x {
+                Transfer::Read(buf) => {
+                    r.rnw = 1;
+                    r.len = buf.len().try_into().map_err(|_| EINVAL)?;
+                    r.data.in_ = buf.as_mut_ptr().cast();
+                }
+                Transfer::Write(buf) => {
+                    r.rnw = 0;
+                    r.len = buf.len().try_into().map_err(|_| EINVAL)?;
+                    r.data.out = buf.as_ptr().cast();
+                }
+            }
+        }
+
+        // SAFETY: By the type invariants, `self.ptr` is valid. The buffers in `raw` are borrowed
+        // from `xfers` for the duration of the call.
+        to_result(unsafe { bindings::i3c_device_do_priv_xfers(self.ptr, raw.as_mut_ptr(), N as _) })
+    }
+
+    /// Writes `buf` to the device.
+    pub fn write(&self, buf: &[u8]) -> Result {
+        self.priv_xfers(&mut [Transfer::Write(buf)])
+    }
+
+    /// Reads from the device into `buf`.
+    pub fn read(&self, buf: &mut [u8]) -> Result {
+        self.priv_xfers(&mut [Transfer::Read(buf)])
+    }
+
+    /// Writes `wbuf`, typically a register address, then reads from the device into `rbuf`.
+    pub fn write_read(&self, wbuf: &[u8], rbuf: &mut [u8]) -> Result {
+        self.priv_xfers(&mut [Transfer::Write(wbuf), Transfer::Read(rbuf)])
+    }
+}
+
+// SAFETY: The device returned by `raw_device` is the raw I3C device.
+unsafe impl device::RawDevice for Device {
+    fn raw_device(&self) -> *mut bindings::device {
+        // SAFETY: By the type invariants, we know that `self.ptr` is non-null and valid.
+        unsafe { bindings::i3cdev_to_dev(self.ptr) }
+    }
+}
+
+/// Declares a kernel module that exposes a single I3C driver.
+///
+/// # Examples
+///
+/// ```ignore
+/// # use kernel::{i3c, define_i3c_id_table, module_i3c_driver};
+/// kernel::module_i3c_id_table!(MOD_TABLE, I3C_ID_TABLE);
+/// kernel::define_i3c_id_table! {I3C_ID_TABLE, (), [
+///     (i3c::DeviceId::Part { manuf_id: 0x1a0, part_id: 0x1 }, None),
+/// ]}
+/// struct MyDriver;
+/// impl i3c::Driver for MyDriver {
+///     kernel::driver_i3c_id_table!(I3C_ID_TABLE);
+///     // [...]
+/// #   fn probe(_dev: &mut i3c::Device, _id_info: Option<&Self::IdInfo>) -> Result {
+/// #       Ok(())
+/// #   }
+/// }
+///
+/// module_i3c_driver! {
+///     type: MyDriver,
+///     name: "module_name",
+///     author: "Author name",
+///     license: "GPL",
+/// }
+/// ```
+#[macro_export]
+macro_rules! module_i3c_driver {
+    ($($f:tt)*) => {
+        $crate::module_driver!(<T>, $crate::i3c::Adapter<T>, { $($f)* });
+    };
+}
@@ -191,7 +191,8 @@
 //! For the special case where initializing a field is a single FFI-function call that cannot fail,
 //! there exist the helper function [`Opaque::ffi_init`]. This function initialize a single
 //! [`Opaque`] field by just delegating to the supplied closure. You can use these in combination
-//! with [`pin_init!`].
+//! with [`pin_init!`]. The [`ffi_init!`] macro builds on top of it and only takes the C function
+//! call, which also covers C initialization functions that return an error code.
 //!
 //! For more information on how to use [`pin_init_from_closure()`], take a look at the uses inside
 //! the `kernel` crate. The [`sync`] module is a good starting point.
@@ -209,14 +210,15 @@
 //! [`Opaque::ffi_init`]: kernel::types::Opaque::ffi_init
 //! [`pin_data`]: ::macros::pin_data
 //! [`pin_init!`]: crate::pin_init!
+//! [`ffi_init!`]: crate::ffi_init!
 
 use crate::{
-    alloc::{box_ext::BoxExt, AllocError, Flags},
+    alloc::{box_ext::BoxExt, vec_ext::VecExt, AllocError, Flags},
     error::{self, Error},
     sync::UniqueArc,
     types::{Opaque, ScopeGuard},
 };
-use alloc::boxed::Box;
+use alloc::{boxed::Box, vec::Vec};
 use core::{
     cell::UnsafeCell,
     convert::Infallible,
@@ -742,6 +744,89 @@ macro_rules! try_init {
     };
 }
 
+/// Construct an in-place initializer for an [`Opaque`] C object by calling its C initialization
+/// function.
+///
+/// The first argument of the function call is an identifier, which is bound to the pointer to the
+/// uninitialized object; the remaining arguments are passed through unchanged. They are evaluated
+/// outside of the `unsafe` block around the call, so unsafe operations in them need their own. If
+/// the call is followed by `?`, the function must return a C error code, which is converted into
+/// an [`Error`] through [`to_result`], and the resulting initializer is fallible.
+///
+/// The leading `unsafe` is required, as the macro calls the function inside of an `unsafe` block.
+/// Like any other `unsafe` block, the invocation needs a `// SAFETY:` comment explaining why the
+/// call is sound. In addition, the function must fully initialize the object when it succeeds,
+/// and must leave it uninitialized when it fails.
+///
+/// # Examples
+///
+/// ```rust
+/// # #![allow(clippy::disallowed_names)]
+/// use kernel::{ffi_init, prelude::*, types::Opaque};
+/// # mod bindings {
+/// #     #![allow(non_camel_case_types)]
+/// #     pub struct foo;
+/// #     pub unsafe fn init_foo(_ptr: *mut foo, _flags: u32) {}
+/// #     pub unsafe fn try_init_foo(_ptr: *mut foo) -> core::ffi::c_int { 0 }
+/// # }
+///
+/// #[pin_data]
+/// struct Foo {
+///     #[pin]
+///     raw: Opaque<bindings::foo>,
+/// }
+///
+/// impl Foo {
+///     fn new(flags: u32) -> impl PinInit<Self> {
+///         pin_init!(Self {
+///             // SAFETY: `init_foo` can be called on uninitialized memory.
+///             raw <- ffi_init!(unsafe bindings::init_foo(slot, flags)),
+///         })
+///     }
+///
+///     fn try_new() -> impl PinInit<Self, Error> {
+///         try_pin_init!(Self {
+///             // SAFETY: `try_init_foo` can be called on uninitialized memory, and leaves it
+///             // uninitialized on failure.
+///             raw <- ffi_init!(unsafe bindings::try_init_foo(slot)?),
+///         })
+///     }
+/// }
+/// ```
+///
+/// [`Opaque`]: kernel::types::Opaque
+/// [`to_result`]: kernel::error::to_result
+#[macro_export]
+macro_rules! ffi_init {
+    (unsafe $($func:ident)::+ ($slot:ident $(, $arg:expr)* $(,)?)?) => {
+        $crate::types::Opaque::try_ffi_init(|$slot| {
+            $crate::ffi_init!(@result [$($func)::+] [$slot] [] $($arg,)*)
+        })
+    };
+    (unsafe $($func:ident)::+ ($slot:ident $(, $arg:expr)* $(,)?)) => {
+        $crate::types::Opaque::ffi_init(|$slot| {
+            $crate::ffi_init!(@unit [$($func)::+] [$slot] [] $($arg,)*)
+        })
+    };
+    (@result [$($func:ident)::+] [$slot:ident] [$($bound:ident)*]) => {
+        // SAFETY: The caller of the macro vouches for the call by writing `unsafe`.
+        $crate::error::to_result(unsafe { $($func)::+($slot $(, $bound)*) })
+    };
+    (@unit [$($func:ident)::+] [$slot:ident] [$($bound:ident)*]) => {
+        // SAFETY: The caller of the macro vouches for the call by writing `unsafe`.
+        unsafe { $($func)::+($slot $(, $bound)*) }
+    };
+    // Each argument is evaluated into its own `arg`, which hygiene keeps apart from the others,
+    // outside of the `unsafe` block.
+    (
+        @$kind:ident [$($func:ident)::+] [$slot:ident] [$($bound:ident)*]
+        $next:expr, $($rest:expr,)*
+    ) => {{
+        let arg = $next;
+        $crate::ffi_init!(@$kind [$($func)::+] [$slot] [$($bound)* arg] $($rest,)*)
+    }};
+}
+
 /// A pin-initializer for the type `T`.
 ///
 /// To use this initializer, you will need a suitable memory location that can hold a `T`. This can
@@ -1095,6 +1180,116 @@ pub fn pin_init_array_from_fn<I, const N: usize, T, E>(
     unsafe { pin_init_from_closure(init) }
 }
 
+/// Allocates a boxed slice of `len` elements and initializes each element with `init_one`.
+///
+/// Initialized elements are dropped in place if a later one fails.
+fn slice_from_fn<T, E>(
+    len: usize,
+    flags: Flags,
+    mut init_one: impl FnMut(usize, *mut T) -> Result<(), E>,
+) -> error::Result<Box<[T]>>
+where
+    Error: From<E>,
+{
+    let mut vec = <Vec<T> as VecExt<T>>::with_capacity(len, flags)?;
+    // The allocation is handed over to a `Box<[T]>` below, which requires it to be exactly `len`
+    // elements long. This is always the case since nothing else was reserved.
+    if core::mem::size_of::<T>() != 0 && vec.capacity() != len {
+        return Err(AllocError.into());
+    }
+    for i in 0..len {
+        // SAFETY: `i < len <= capacity`, so the pointer is in bounds of the allocation.
+        let ptr = unsafe { vec.as_mut_ptr().add(i) };
+        // If this fails, `vec` drops the `i` elements that have already been initialized.
+        init_one(i, ptr)?;
+        // SAFETY: The elements `0..=i` have been initialized.
+        unsafe { vec.set_len(i + 1) };
+    }
+    let mut vec = core::mem::ManuallyDrop::new(vec);
+    // SAFETY: `vec` owns an allocation of exactly `len` initialized elements, which is the layout
+    // of a `Box<[T]>` of that length, and does not free it.
+    Ok(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(vec.as_mut_ptr(), len)) })
+}
+
+/// Allocates a boxed slice of `len` elements and initializes each element via the provided
+/// initializer.
+///
+/// This is the equivalent of [`init_array_from_fn`] when the number of elements is only known at
+/// runtime. If any initializer fails, the elements initialized so far are dropped and the error is
+/// returned.
+///
+/// # Examples
+///
+/// ```rust
+/// use kernel::init::init_slice_from_fn;
+/// let slice: Box<[usize]> = init_slice_from_fn(1_000, |i| i * 2, GFP_KERNEL)?;
+/// assert_eq!(slice.len(), 1_000);
+/// assert_eq!(slice[21], 42);
+/// # Ok::<(), Error>(())
+/// ```
+pub fn init_slice_from_fn<I, T, E>(
+    len: usize,
+    mut make_init: impl FnMut(usize) -> I,
+    flags: Flags,
+) -> error::Result<Box<[T]>>
+where
+    I: Init<T, E>,
+    Error: From<E>,
+{
+    slice_from_fn(len, flags, |i, slot| {
+        // SAFETY: `slot` is valid for writes and considered uninitialized if this fails.
+        unsafe { make_init(i).__init(slot) }
+    })
+}
+
+/// Allocates a pinned boxed slice of `len` elements and pin-initializes each element via the
+/// provided initializer.
+///
+/// This is the equivalent of [`pin_init_array_from_fn`] when the number of elements is only known
+/// at runtime, e.g., one per hardware queue. If any initializer fails, the elements initialized so
+/// far are dropped in place and the error is returned.
+///
+/// # Examples
+///
+/// ```rust
+/// use kernel::{init::pin_init_slice_from_fn, new_mutex, sync::Mutex};
+///
+/// #[pin_data]
+/// struct Queue {
+///     index: usize,
+///     #[pin]
+///     pending: Mutex<usize>,
+/// }
+///
+/// fn alloc_queues(n: usize) -> Result<Pin<Box<[Queue]>>> {
+///     pin_init_slice_from_fn(
+///         n,
+///         |index| pin_init!(Queue { index, pending <- new_mutex!(0) }),
+///         GFP_KERNEL,
+///     )
+/// }
+///
+/// let queues = alloc_queues(8)?;
+/// assert_eq!(queues[7].index, 7);
+/// # Ok::<(), Error>(())
+/// ```
+pub fn pin_init_slice_from_fn<I, T, E>(
+    len: usize,
+    mut make_init: impl FnMut(usize) -> I,
+    flags: Flags,
+) -> error::Result<Pin<Box<[T]>>>
+where
+    I: PinInit<T, E>,
+    Error: From<E>,
+{
+    let slice = slice_from_fn(len, flags, |i, slot| {
+        // SAFETY: `slot` is valid for writes and considered uninitialized if this fails. The
+        // element is not moved afterwards, since the slice is pinned before it is returned.
+        unsafe { make_init(i).__pinned_init(slot) }
+    })?;
+    Ok(Box::into_pin(slice))
+}
+
 // SAFETY: Every type can be initialized by-value.
 unsafe impl<T, E> Init<T, E> for T {
     unsafe fn __init(self, slot: *mut T) -> Result<(), E> {
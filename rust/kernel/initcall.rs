@@ -0,0 +1,217 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Initcalls of built-in code.
+//!
+//! Built-in code is initialised by initcalls, which run once during boot, level after level. A
+//! driver initialises at [`initcall!`]`(device, ...)`, like C's `module_init`, but core platform
+//! code often needs to run earlier, e.g., a clocksource or an interrupt controller that other
+//! drivers depend on, or later, e.g., to act on the devices probed by then. [`initcall!`] places a
+//! hook at any of the levels, and can be used several times in a crate, e.g., for a hook that
+//! runs at `early`, before secondary CPUs are brought up, in addition to the main one.
+//!
+//! Like in C, initcalls only exist in built-in code: in loadable modules, the hooks are not called
+//! at all, and modules are initialised by [`Module::init`](crate::Module::init) instead.
+//!
+//! The `module!` macro always initialises built-in code at `device`. [`module_initcall!`] takes
+//! the same keys together with the level, and optionally a hook to run at `early`.
+//!
+//! C header: [`include/linux/init.h`](srctree/include/linux/init.h)
+
+use crate::error::Result;
+
+/// Calls an initcall hook and converts its result to the return value of an initcall.
+#[doc(hidden)]
+pub fn call(f: fn() -> Result) -> core::ffi::c_int {
+    match f() {
+        Ok(()) => 0,
+        Err(e) => e.to_errno(),
+    }
+}
+
+/// Registers a hook to run at an initcall level during boot.
+///
+/// The level is one of, in order, `early`, `pure`, `core`, `postcore`, `arch`, `subsys`, `fs`,
+/// `rootfs`, `device` and `late`, as in the C `*_initcall` macros. Hooks at the same level run in
+/// link order. If a hook fails, the failure is logged with `initcall_debug` and boot continues,
+/// so the hook must undo what it did before failing.
+///
+/// The hook must not be called after boot, since it may be placed in memory that is freed then.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{initcall, prelude::*};
+///
+/// fn timer_init() -> Result {
+///     pr_info!("registering the clocksource\n");
+///     Ok(())
+/// }
+///
+/// fn timer_late_init() -> Result {
+///     pr_info!("switching to the high-resolution mode\n");
+///     Ok(())
+/// }
+///
+/// initcall!(arch, timer_init);
+/// initcall!(late, timer_late_init);
+/// ```
+#[macro_export]
+macro_rules! initcall {
+    (early, $hook:path $(,)?) => { $crate::initcall!(@section ".initcallearly.init", $hook); };
+    (pure, $hook:path $(,)?) => { $crate::initcall!(@section ".initcall0.init", $hook); };
+    (core, $hook:path $(,)?) => { $crate::initcall!(@section ".initcall1.init", $hook); };
+    (postcore, $hook:path $(,)?) => { $crate::initcall!(@section ".initcall2.init", $hook); };
+    (arch, $hook:path $(,)?) => { $crate::initcall!(@section ".initcall3.init", $hook); };
+    (subsys, $hook:path $(,)?) => { $crate::initcall!(@section ".initcall4.init", $hook); };
+    (fs, $hook:path $(,)?) => { $crate::initcall!(@section ".initcall5.init", $hook); };
+    (rootfs, $hook:path $(,)?) => { $crate::initcall!(@section ".initcallrootfs.init", $hook); };
+    (device, $hook:path $(,)?) => { $crate::initcall!(@section ".initcall6.init", $hook); };
+    (late, $hook:path $(,)?) => { $crate::initcall!(@section ".initcall7.init", $hook); };
+    (@section $section:literal, $hook:path) => {
+        #[cfg(not(MODULE))]
+        const _: () = {
+            #[link_section = ".init.text"]
+            extern "C" fn __initcall() -> core::ffi::c_int {
+                $crate::initcall::call($hook)
+            }
+
+            // Architectures with 32-bit relative relocations store the offset of the function,
+            // which the linker script expects to be a `.long` in the section.
+            #[cfg(CONFIG_HAVE_ARCH_PREL32_RELOCATIONS)]
+            core::arch::global_asm!(
+                concat!(".section \"", $section, "\", \"a\""),
+                ".balign 4",
+                ".long {hook} - .",
+                ".previous",
+                hook = sym __initcall,
+            );
+
+            #[cfg(not(CONFIG_HAVE_ARCH_PREL32_RELOCATIONS))]
+            #[used]
+            #[link_section = $section]
+            static __INITCALL: extern "C" fn() -> core::ffi::c_int = __initcall;
+        };
+    };
+}
+
+/// Copies the modinfo string `s` into an array, so that it can be placed in the `.modinfo`
+/// section.
+#[doc(hidden)]
+pub const fn modinfo<const N: usize>(s: &str) -> [u8; N] {
+    let bytes = s.as_bytes();
+    let mut info = [0; N];
+    let mut i = 0;
+    while i < N {
+        info[i] = bytes[i];
+        i += 1;
+    }
+    info
+}
+
+/// Declares a kernel module, like `module!`, whose [`Module::init`] runs at the initcall level
+/// `initcall` when the module is built-in.
+///
+/// The level is one of those of [`initcall!`], e.g., `subsys` for a bus that drivers at `device`
+/// register with. The optional `early_init` hook runs at `early`, before secondary CPUs are brought
+/// up and long before [`Module::init`], e.g., to set up the clocksource that the rest of the
+/// driver needs.
+///
+/// When the crate is built as a loadable module, this is `module!`: [`Module::init`] runs when the
+/// module is loaded, and `early_init` is not called at all. Built-in modules are never unloaded,
+/// so the module is not dropped then.
+///
+/// Only the `type`, `name`, `author`, `description` and `license` keys of `module!` are supported,
+/// in this order.
+///
+/// # Examples
+///
+/// ```ignore
+/// use kernel::{module_initcall, prelude::*};
+///
+/// module_initcall! {
+///     type: ArchTimer,
+///     name: "arch_timer",
+///     author: "Rust for Linux Contributors",
+///     description: "Rust Arm architected timer",
+///     license: "GPL",
+///     initcall: subsys,
+///     early_init: arch_timer_early_init,
+/// }
+///
+/// fn arch_timer_early_init() -> Result {
+///     // Register the per-CPU clocksource of the boot CPU, before other CPUs are brought up.
+///     Ok(())
+/// }
+///
+/// struct ArchTimer;
+///
+/// impl kernel::Module for ArchTimer {
+///     fn init(_name: &'static CStr, _module: &'static ThisModule) -> Result<Self> {
+///         // Register the platform driver for the memory-mapped timers.
+///         Ok(ArchTimer)
+///     }
+/// }
+/// ```
+///
+/// [`Module::init`]: crate::Module::init
+#[macro_export]
+macro_rules! module_initcall {
+    (
+        type: $type:ident,
+        name: $name:literal,
+        $(author: $author:literal,)?
+        $(description: $description:literal,)?
+        license: $license:literal,
+        initcall: $level:ident,
+        $(early_init: $early:path,)?
+    ) => {
+        #[cfg(MODULE)]
+        $crate::prelude::module! {
+            type: $type,
+            name: $name,
+            $(author: $author,)?
+            $(description: $description,)?
+            license: $license,
+        }
+
+        /// The module itself, which is null for built-in code.
+        #[cfg(not(MODULE))]
+        static THIS_MODULE: $crate::ThisModule =
+            // SAFETY: `THIS_MODULE` is null for built-in code.
+            unsafe { $crate::ThisModule::from_ptr(core::ptr::null_mut()) };
+
+        #[cfg(not(MODULE))]
+        const __LOG_PREFIX: &[u8] = concat!($name, "\0").as_bytes();
+
+        $($crate::module_initcall!(@modinfo $name, "author", $author);)?
+        $($crate::module_initcall!(@modinfo $name, "description", $description);)?
+        $crate::module_initcall!(@modinfo $name, "license", $license);
+
+        #[cfg(not(MODULE))]
+        const _: () = {
+            static mut MODULE: Option<$type> = None;
+
+            fn init() -> $crate::error::Result {
+                let module = <$type as $crate::Module>::init($crate::c_str!($name), &THIS_MODULE)?;
+                // SAFETY: Initcalls run once, so nothing else accesses `MODULE` concurrently. It
+                // is only written to keep the module alive.
+                unsafe { core::ptr::addr_of_mut!(MODULE).write(Some(module)) };
+                Ok(())
+            }
+
+            $crate::initcall!($level, init);
+            $($crate::initcall!(early, $early);)?
+        };
+    };
+    (@modinfo $name:literal, $key:literal, $value:literal) => {
+        // Built-in modules prefix their modinfo with their name, see `modules.builtin.modinfo`.
+        #[cfg(not(MODULE))]
+        const _: () = {
+            const INFO: &str = concat!($name, ".", $key, "=", $value, "\0");
+
+            #[used]
+            #[link_section = ".modinfo"]
+            static MODINFO: [u8; INFO.len()] = $crate::initcall::modinfo(INFO);
+        };
+    };
+}
@@ -0,0 +1,248 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Interval trees.
+//!
+//! An interval tree maps closed ranges to values and finds all the ranges that overlap a given
+//! range or contain a given point. Drivers use it to track IOVA ranges, MMIO windows or file
+//! mappings.
+//!
+//! C header: [`include/linux/interval_tree.h`](srctree/include/linux/interval_tree.h)
+
+use crate::{alloc::Flags, bindings, prelude::*};
+use core::{marker::PhantomData, ops::RangeInclusive, ptr};
+
+/// A node of the tree, with the C node first so that pointers to both are interchangeable.
+#[repr(C)]
+struct Node<V> {
+    links: bindings::interval_tree_node,
+    value: V,
+}
+
+/// An interval tree mapping the closed ranges `start..=last` to values of type `V`.
+///
+/// Ranges may overlap, and the same range may be inserted more than once.
+///
+/// # Invariants
+///
+/// All the nodes linked in `root` are `Node<V>` allocated with [`Box`] and owned by the tree.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::interval_tree::IntervalTree;
+///
+/// let mut windows = IntervalTree::new();
+/// windows.insert(0x1000, 0x1fff, "regs", GFP_KERNEL)?;
+/// windows.insert(0x1800, 0x27ff, "fifo", GFP_KERNEL)?;
+/// windows.insert(0x8000, 0x8fff, "sram", GFP_KERNEL)?;
+///
+/// // Stabbing query.
+/// let mut it = windows.containing(0x1900);
+/// assert_eq!(it.next(), Some((0x1000..=0x1fff, &"regs")));
+/// assert_eq!(it.next(), Some((0x1800..=0x27ff, &"fifo")));
+/// assert_eq!(it.next(), None);
+///
+/// // Overlap query.
+/// assert!(windows.overlaps(0x2800, 0x7fff).is_none());
+/// assert_eq!(windows.overlaps(0x2000, 0x8000).map(|(_, v)| *v), Some("fifo"));
+///
+/// assert_eq!(windows.remove(0x1800, 0x27ff), Some("fifo"));
+/// assert_eq!(windows.remove(0x1800, 0x27ff), None);
+/// assert_eq!(windows.len(), 2);
+/// # Ok::<(), Error>(())
+/// ```
+pub struct IntervalTree<V> {
+    root: bindings::rb_root_cached,
+    len: usize,
+    _p: PhantomData<Box<Node<V>>>,
+}
+
+// SAFETY: The tree owns its values, so it can be sent to another thread if they can.
+unsafe impl<V: Send> Send for IntervalTree<V> {}
+
+// SAFETY: The tree only gives out shared references to its values through `&self`.
+unsafe impl<V: Sync> Sync for IntervalTree<V> {}
+
+impl<V> IntervalTree<V> {
+    /// Creates a new, empty tree.
+    pub const fn new() -> Self {
+        // INVARIANT: There are no nodes in the tree.
+        Self {
+            root: bindings::rb_root_cached {
+                rb_root: bindings::rb_root {
+                    rb_node: ptr::null_mut(),
+                },
+                rb_leftmost: ptr::null_mut(),
+            },
+            len: 0,
+            _p: PhantomData,
+        }
+    }
+
+    /// Returns the number of ranges in the tree.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the tree is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Inserts `value` for the range `start..=last`.
+    ///
+    /// Fails with [`EINVAL`] if `last` is smaller than `start`.
+    pub fn insert(&mut self, start: usize, last: usize, value: V, flags: Flags) -> Result {
+        if last < start {
+            return Err(EINVAL);
+        }
+
+        let node = Box::new(
+            Node {
+                // SAFETY: All-zeroes is a valid, unlinked `interval_tree_node`.
+                links: unsafe { core::mem::zeroed() },
+                value,
+            },
+            flags,
+        )?;
+        let node = Box::into_raw(node);
+
+        // SAFETY: `node` was just allocated and is valid. The tree is borrowed mutably, so there
+        // are no concurrent accesses to it.
+        unsafe {
+            (*node).links.start = start as _;
+            (*node).links.last = last as _;
+            bindings::interval_tree_insert(&mut (*node).links, &mut self.root);
+        }
+        // INVARIANT: The new node was allocated with `Box` and is now owned by the tree.
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Removes a range that is exactly `start..=last` and returns its value.
+    ///
+    /// If the range was inserted more than once, only one of them is removed.
+    pub fn remove(&mut self, start: usize, last: usize) -> Option<V> {
+        let mut it = self.overlapping(start, last);
+        let node = loop {
+            let node = it.next_node()?;
+            // SAFETY: Nodes returned by the iterator are linked in the tree and valid.
+            let links = unsafe { &(*node).links };
+            if links.start as usize == start && links.last as usize == last {
+                break node;
+            }
+        };
+
+        // SAFETY: `node` is linked in the tree. The tree is borrowed mutably, so there are no
+        // concurrent accesses to it.
+        unsafe { bindings::interval_tree_remove(&mut (*node).links, &mut self.root) };
+        self.len -= 1;
+
+        // SAFETY: By the type invariants, the node was allocated with `Box`. It was unlinked
+        // above, so the tree does not own it anymore.
+        let node = unsafe { Box::from_raw(node) };
+        Some(node.value)
+    }
+
+    /// Returns an iterator over the ranges that overlap `start..=last`, with their values.
+    ///
+    /// Ranges are returned in ascending order of their start.
+    pub fn overlapping(&self, start: usize, last: usize) -> Overlapping<'_, V> {
+        let next = if last < start {
+            ptr::null_mut()
+        } else {
+            // SAFETY: The tree is valid, and the nodes cannot be removed while it is borrowed.
+            unsafe {
+                bindings::interval_tree_iter_first(
+                    &self.root as *const _ as *mut _,
+                    start as _,
+                    last as _,
+                )
+            }
+        };
+        Overlapping {
+            next: next.cast(),
+            start,
+            last,
+            _p: PhantomData,
+        }
+    }
+
+    /// Returns an iterator over the ranges that contain `point`, with their values.
+    pub fn containing(&self, point: usize) -> Overlapping<'_, V> {
+        self.overlapping(point, point)
+    }
+
+    /// Returns the first range that overlaps `start..=last`, with its value.
+    pub fn overlaps(&self, start: usize, last: usize) -> Option<(RangeInclusive<usize>, &V)> {
+        self.overlapping(start, last).next()
+    }
+
+    /// Returns an iterator over all the ranges in the tree, with their values.
+    pub fn iter(&self) -> Overlapping<'_, V> {
+        self.overlapping(0, usize::MAX)
+    }
+}
+
+impl<V> Default for IntervalTree<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> Drop for IntervalTree<V> {
+    fn drop(&mut self) {
+        // SAFETY: The root is valid.
+        let mut next = unsafe { bindings::rb_first_postorder(&self.root.rb_root) };
+        while !next.is_null() {
+            let node = next;
+            // SAFETY: `node` is a valid node of the tree. In post-order, it is only freed after
+            // the next node is found, and its children have already been visited.
+            next = unsafe { bindings::rb_next_postorder(node) };
+            // SAFETY: `rb` is the first field of `interval_tree_node`, which is the first field of
+            // `Node<V>`, so the pointers are interchangeable. By the type invariants, the node was
+            // allocated with `Box` and is owned by the tree.
+            drop(unsafe { Box::from_raw(node.cast::<Node<V>>()) });
+        }
+    }
+}
+
+/// An iterator over ranges of an [`IntervalTree`].
+///
+/// Returned by [`IntervalTree::overlapping`] and similar methods.
+pub struct Overlapping<'a, V> {
+    next: *mut Node<V>,
+    start: usize,
+    last: usize,
+    _p: PhantomData<&'a IntervalTree<V>>,
+}
+
+impl<V> Overlapping<'_, V> {
+    fn next_node(&mut self) -> Option<*mut Node<V>> {
+        if self.next.is_null() {
+            return None;
+        }
+        let node = self.next;
+        // SAFETY: `node` is linked in the tree, which is borrowed for the lifetime of the
+        // iterator.
+        self.next = unsafe {
+            bindings::interval_tree_iter_next(&mut (*node).links, self.start as _, self.last as _)
+        }
+        .cast();
+        Some(node)
+    }
+}
+
+impl<'a, V> Iterator for Overlapping<'a, V> {
+    type Item = (RangeInclusive<usize>, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.next_node()?;
+        // SAFETY: `node` is linked in the tree, which is borrowed for `'a`.
+        let node = unsafe { &*node };
+        Some((
+            node.links.start as usize..=node.links.last as usize,
+            &node.value,
+        ))
+    }
+}
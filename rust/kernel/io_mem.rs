@@ -0,0 +1,939 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Memory-mapped IO.
+//!
+//! Device registers are accessed through an [`IoMem`] mapping of a memory [`Resource`] of the
+//! device, e.g., one obtained with [`platform::Device::ioremap_resource`].
+//!
+//! C header: [`include/asm-generic/io.h`](srctree/include/asm-generic/io.h)
+//!
+//! [`platform::Device::ioremap_resource`]: crate::platform::Device::ioremap_resource
+
+use crate::{
+    bindings,
+    device::{Device, RawDevice},
+    error::{code::*, Result},
+    types::ARef,
+};
+use core::{ffi::c_ulong, fmt, marker::PhantomData, ops::Range};
+
+/// Resource flags.
+pub mod flags {
+    use core::ffi::c_ulong;
+
+    /// The resource is a range of IO ports.
+    pub const IO: c_ulong = crate::bindings::IORESOURCE_IO as _;
+
+    /// The resource is a range of memory, e.g., device registers.
+    pub const MEM: c_ulong = crate::bindings::IORESOURCE_MEM as _;
+
+    /// The range can be mapped with caching or write-combining enabled.
+    pub const PREFETCH: c_ulong = crate::bindings::IORESOURCE_PREFETCH as _;
+
+    /// The range is read-only.
+    pub const READONLY: c_ulong = crate::bindings::IORESOURCE_READONLY as _;
+
+    /// The range must not be mapped or requested by more than one driver.
+    pub const EXCLUSIVE: c_ulong = crate::bindings::IORESOURCE_EXCLUSIVE as _;
+
+    /// The resource is disabled.
+    pub const DISABLED: c_ulong = crate::bindings::IORESOURCE_DISABLED as _;
+}
+
+/// A range of the physical address space of a device.
+///
+/// This is a copy of a C `struct resource`; it does not keep the resource alive, nor does it give
+/// ownership of the range.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Resource {
+    start: bindings::resource_size_t,
+    len: bindings::resource_size_t,
+    flags: c_ulong,
+}
+
+impl Resource {
+    /// Creates a resource covering `len` bytes starting at `start`.
+    ///
+    /// Returns [`None`] if `len` is zero or the range wraps around the address space.
+    pub fn new(
+        start: bindings::resource_size_t,
+        len: bindings::resource_size_t,
+        flags: c_ulong,
+    ) -> Option<Self> {
+        if len == 0 {
+            return None;
+        }
+        start.checked_add(len - 1)?;
+        Some(Self { start, len, flags })
+    }
+
+    /// Creates a copy of the C resource `res`.
+    ///
+    /// # Safety
+    ///
+    /// `res` must be valid for reads.
+    pub(crate) unsafe fn from_raw(res: *const bindings::resource) -> Option<Self> {
+        // SAFETY: By the safety requirements, `res` is valid for reads.
+        let (start, end, flags) = unsafe { ((*res).start, (*res).end, (*res).flags) };
+        if end < start {
+            return None;
+        }
+        Self::new(start, end - start + 1, flags)
+    }
+
+    /// Returns the first address of the range.
+    pub fn start(&self) -> bindings::resource_size_t {
+        self.start
+    }
+
+    /// Returns the length of the range in bytes.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> bindings::resource_size_t {
+        self.len
+    }
+
+    /// Returns the flags of the resource, a combination of the constants in [`flags`].
+    pub fn flags(&self) -> c_ulong {
+        self.flags
+    }
+
+    /// Returns `true` if the resource is a range of memory.
+    pub fn is_mem(&self) -> bool {
+        self.flags & flags::MEM != 0
+    }
+}
+
+impl fmt::Display for Resource {
+    /// Formats the resource like the `%pR` format of `printk`.
+    ///
+    /// ```
+    /// # use kernel::{fmt, io_mem::{flags, Resource}, prelude::*, str::CString};
+    /// let res = Resource::new(0xfe20_1000, 0x200, flags::MEM).ok_or(EINVAL)?;
+    /// let s = CString::try_from_fmt(fmt!("{}", res))?;
+    /// assert_eq!(s.to_str()?, "[mem 0xfe201000-0xfe2011ff]");
+    /// # Ok::<(), Error>(())
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (kind, width) = if self.flags & flags::IO != 0 {
+            ("io  ", 4)
+        } else if self.flags & flags::MEM != 0 {
+            ("mem ", 8)
+        } else {
+            ("", 8)
+        };
+        let end = self.start + (self.len - 1);
+        write!(
+            f,
+            "[{kind}{:#0w$x}-{:#0w$x}",
+            self.start,
+            end,
+            w = width + 2
+        )?;
+        if self.flags & flags::PREFETCH != 0 {
+            f.write_str(" pref")?;
+        }
+        if self.flags & flags::DISABLED != 0 {
+            f.write_str(" disabled")?;
+        }
+        f.write_str("]")
+    }
+}
+
+impl fmt::Debug for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Resource")
+            .field("start", &format_args!("{:#x}", self.start))
+            .field("len", &format_args!("{:#x}", self.len))
+            .field("flags", &format_args!("{:#x}", self.flags))
+            .finish()
+    }
+}
+
+/// A request for exclusive use of a memory range, released when dropped.
+///
+/// # Invariants
+///
+/// The range `start..start + len` was requested with `request_mem_region` and has not been
+/// released. `_dev` is the device whose name labels the request.
+struct MemRegion {
+    _dev: ARef<Device>,
+    start: bindings::resource_size_t,
+    len: bindings::resource_size_t,
+}
+
+impl MemRegion {
+    fn request(dev: &impl RawDevice, res: &Resource) -> Result<Self> {
+        // SAFETY: `dev.raw_device()` is valid and has a non-zero reference count.
+        let dev = unsafe { Device::new(dev.raw_device()) };
+        // SAFETY: The name of the device is a valid C string. The reference held in `dev` keeps
+        // it alive for as long as the region is requested.
+        let ret = unsafe {
+            bindings::request_mem_region(res.start, res.len, bindings::dev_name(dev.raw_device()))
+        };
+        if ret.is_null() {
+            return Err(EBUSY);
+        }
+        // INVARIANT: The region was just requested.
+        Ok(Self {
+            _dev: dev,
+            start: res.start,
+            len: res.len,
+        })
+    }
+}
+
+impl Drop for MemRegion {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, the region was requested and has not been released.
+        unsafe { bindings::release_mem_region(self.start, self.len) };
+    }
+}
+
+/// A mapping of memory-mapped IO registers.
+///
+/// `SIZE` is the size of the mapping that is known at compile time, it defaults to zero. Accesses
+/// through the infallible accessors, e.g., [`IoMem::readl`], are checked against it at build
+/// time; the `try_` accessors, e.g., [`IoMem::try_readl`], are checked at runtime against the
+/// actual size of the mapping, which is at least `SIZE`.
+///
+/// The registers are unmapped, and the memory region given back if it was requested, when the
+/// mapping is dropped.
+///
+/// # Invariants
+///
+/// `ptr` is the start of a mapping of `size` bytes created with `ioremap`, or, if `fake` is
+/// `true`, of the buffer of a live [`FakeRegs`], and `size >= SIZE`. If `_region` is not
+/// [`None`], it covers the mapped range.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{io_mem::IoMem, platform, prelude::*};
+///
+/// const CTRL: usize = 0x0;
+/// const STATUS: usize = 0x4;
+///
+/// fn enable(pdev: &platform::Device) -> Result<IoMem<0x100>> {
+///     let regs = pdev.ioremap_resource::<0x100>(0)?;
+///     // Set the enable bit and select mode 2 in bits 4..=5.
+///     regs.update_bits32(CTRL, 0x31, 0x21);
+///     pr_info!("status: {:#x}\n", regs.readl(STATUS));
+///     Ok(regs)
+/// }
+/// ```
+pub struct IoMem<const SIZE: usize = 0> {
+    ptr: usize,
+    size: usize,
+    _region: Option<MemRegion>,
+    #[cfg(CONFIG_RUST_KUNIT_FAKE_REGS)]
+    fake: bool,
+}
+
+macro_rules! define_read {
+    ($(#[$attr:meta])* $name:ident, $try_name:ident, $type_name:ty) => {
+        /// Reads IO data from the given offset known at compile time.
+        ///
+        /// If the offset is not known at compile time, the build will fail.
+        $(#[$attr])*
+        #[inline]
+        pub fn $name(&self, offset: usize) -> $type_name {
+            Self::check_offset::<$type_name>(offset);
+            let ptr = self.ptr.wrapping_add(offset);
+            // SAFETY: The type invariants guarantee that `ptr` is a valid pointer. The check
+            // above guarantees that the access is within the mapping and aligned.
+            unsafe { bindings::$name(ptr as _) }
+        }
+
+        /// Reads IO data from the given offset.
+        ///
+        /// It fails with [`EINVAL`] if the offset is out of bounds or unaligned.
+        $(#[$attr])*
+        pub fn $try_name(&self, offset: usize) -> Result<$type_name> {
+            if !self.offset_ok::<$type_name>(offset) {
+                return Err(EINVAL);
+            }
+            let ptr = self.ptr.wrapping_add(offset);
+            // SAFETY: The type invariants guarantee that `ptr` is a valid pointer. The check
+            // above guarantees that the access is within the mapping and aligned.
+            Ok(unsafe { bindings::$name(ptr as _) })
+        }
+    };
+}
+
+macro_rules! define_write {
+    ($(#[$attr:meta])* $name:ident, $try_name:ident, $type_name:ty) => {
+        /// Writes IO data to the given offset known at compile time.
+        ///
+        /// If the offset is not known at compile time, the build will fail.
+        $(#[$attr])*
+        #[inline]
+        pub fn $name(&self, value: $type_name, offset: usize) {
+            Self::check_offset::<$type_name>(offset);
+            let ptr = self.ptr.wrapping_add(offset);
+            // SAFETY: The type invariants guarantee that `ptr` is a valid pointer. The check
+            // above guarantees that the access is within the mapping and aligned.
+            unsafe { bindings::$name(value, ptr as _) }
+        }
+
+        /// Writes IO data to the given offset.
+        ///
+        /// It fails with [`EINVAL`] if the offset is out of bounds or unaligned.
+        $(#[$attr])*
+        pub fn $try_name(&self, value: $type_name, offset: usize) -> Result {
+            if !self.offset_ok::<$type_name>(offset) {
+                return Err(EINVAL);
+            }
+            let ptr = self.ptr.wrapping_add(offset);
+            // SAFETY: The type invariants guarantee that `ptr` is a valid pointer. The check
+            // above guarantees that the access is within the mapping and aligned.
+            unsafe { bindings::$name(value, ptr as _) };
+            Ok(())
+        }
+    };
+}
+
+macro_rules! define_update {
+    (
+        $(#[$attr:meta])*
+        $name:ident, $try_name:ident, $read:ident, $write:ident, $try_read:ident, $try_write:ident,
+        $type_name:ty
+    ) => {
+        /// Updates the bits selected by `mask` of the register at the given offset known at compile
+        /// time to the corresponding bits of `value`, leaving the other bits unchanged.
+        ///
+        /// The register is read and then written back; the two accesses are not atomic, so callers
+        /// must serialise concurrent updates of the same register. This is not suitable for
+        /// registers with bits that are cleared by writing one to them, since any such bit that is
+        /// set is written back.
+        ///
+        /// If the offset is not known at compile time, the build will fail.
+        $(#[$attr])*
+        #[inline]
+        pub fn $name(&self, offset: usize, mask: $type_name, value: $type_name) {
+            let old = self.$read(offset);
+            self.$write((old & !mask) | (value & mask), offset);
+        }
+
+        /// Updates the bits selected by `mask` of the register at the given offset to the
+        /// corresponding bits of `value`, leaving the other bits unchanged.
+        ///
+        /// It fails with [`EINVAL`] if the offset is out of bounds or unaligned. See the
+        /// infallible variant for the caveats of read-modify-write updates.
+        $(#[$attr])*
+        pub fn $try_name(&self, offset: usize, mask: $type_name, value: $type_name) -> Result {
+            let old = self.$try_read(offset)?;
+            self.$try_write((old & !mask) | (value & mask), offset)
+        }
+    };
+}
+
+/// Defines the accessors of [`IoMem`] and [`IoWindow`], which both have a `ptr` and a `size`
+/// field, and a `SIZE` parameter.
+macro_rules! define_accessors {
+    () => {
+        /// Returns the size of the mapping in bytes.
+        pub fn size(&self) -> usize {
+            self.size
+        }
+
+        const fn offset_ok_of_size<T>(offset: usize, size: usize) -> bool {
+            let type_size = core::mem::size_of::<T>();
+            if let Some(end) = offset.checked_add(type_size) {
+                end <= size && offset % type_size == 0
+            } else {
+                false
+            }
+        }
+
+        fn offset_ok<T>(&self, offset: usize) -> bool {
+            Self::offset_ok_of_size::<T>(offset, self.size)
+        }
+
+        const fn check_offset<T>(offset: usize) {
+            crate::build_assert!(
+                Self::offset_ok_of_size::<T>(offset, SIZE),
+                "IoMem offset overflow"
+            );
+        }
+
+        define_read!(readb, try_readb, u8);
+        define_read!(readw, try_readw, u16);
+        define_read!(readl, try_readl, u32);
+        define_read!(
+            #[cfg(CONFIG_64BIT)]
+            readq,
+            try_readq,
+            u64
+        );
+
+        define_read!(readb_relaxed, try_readb_relaxed, u8);
+        define_read!(readw_relaxed, try_readw_relaxed, u16);
+        define_read!(readl_relaxed, try_readl_relaxed, u32);
+        define_read!(
+            #[cfg(CONFIG_64BIT)]
+            readq_relaxed,
+            try_readq_relaxed,
+            u64
+        );
+
+        define_write!(writeb, try_writeb, u8);
+        define_write!(writew, try_writew, u16);
+        define_write!(writel, try_writel, u32);
+        define_write!(
+            #[cfg(CONFIG_64BIT)]
+            writeq,
+            try_writeq,
+            u64
+        );
+
+        define_write!(writeb_relaxed, try_writeb_relaxed, u8);
+        define_write!(writew_relaxed, try_writew_relaxed, u16);
+        define_write!(writel_relaxed, try_writel_relaxed, u32);
+        define_write!(
+            #[cfg(CONFIG_64BIT)]
+            writeq_relaxed,
+            try_writeq_relaxed,
+            u64
+        );
+
+        define_update!(
+            update_bits32,
+            try_update_bits32,
+            readl,
+            writel,
+            try_readl,
+            try_writel,
+            u32
+        );
+        define_update!(
+            update_bits32_relaxed,
+            try_update_bits32_relaxed,
+            readl_relaxed,
+            writel_relaxed,
+            try_readl_relaxed,
+            try_writel_relaxed,
+            u32
+        );
+        define_update!(
+            #[cfg(CONFIG_64BIT)]
+            update_bits64,
+            try_update_bits64,
+            readq,
+            writeq,
+            try_readq,
+            try_writeq,
+            u64
+        );
+        define_update!(
+            #[cfg(CONFIG_64BIT)]
+            update_bits64_relaxed,
+            try_update_bits64_relaxed,
+            readq_relaxed,
+            writeq_relaxed,
+            try_readq_relaxed,
+            try_writeq_relaxed,
+            u64
+        );
+    };
+}
+
+impl<const SIZE: usize> IoMem<SIZE> {
+    /// Maps the memory resource `res`, without requesting it.
+    ///
+    /// Fails with [`EINVAL`] if `res` is not a memory resource or is smaller than `SIZE`.
+    ///
+    /// # Safety
+    ///
+    /// Callers must ensure that no other driver uses the range described by `res`, e.g., because
+    /// it is a shared register block whose ownership is tracked elsewhere.
+    pub unsafe fn try_new(res: &Resource) -> Result<Self> {
+        // SAFETY: The safety requirements are forwarded to the caller.
+        unsafe { Self::map(res, None) }
+    }
+
+    /// Requests the memory resource `res` on behalf of `dev` and maps it.
+    ///
+    /// Fails with [`EBUSY`] if another driver has requested an overlapping range, and with
+    /// [`EINVAL`] if `res` is not a memory resource or is smaller than `SIZE`.
+    pub fn request(dev: &impl RawDevice, res: &Resource) -> Result<Self> {
+        let region = MemRegion::request(dev, res)?;
+        // SAFETY: The region was requested above, so no other driver uses it.
+        unsafe { Self::map(res, Some(region)) }
+    }
+
+    /// Maps `res`, taking ownership of `region`.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`IoMem::try_new`], unless `region` covers `res`.
+    unsafe fn map(res: &Resource, region: Option<MemRegion>) -> Result<Self> {
+        if !res.is_mem() {
+            return Err(EINVAL);
+        }
+        let size: usize = res.len.try_into().map_err(|_| EINVAL)?;
+        if size < SIZE {
+            return Err(EINVAL);
+        }
+
+        #[cfg(CONFIG_RUST_KUNIT_FAKE_REGS)]
+        if let Some(ptr) = FakeRegs::lookup(res.start, size) {
+            // INVARIANT: `lookup` checked that the buffer is live and holds `size` bytes.
+            return Ok(Self {
+                ptr,
+                size,
+                _region: region,
+                fake: true,
+            });
+        }
+
+        // SAFETY: By the safety requirements, the range can be mapped.
+        let addr = unsafe { bindings::ioremap(res.start, size) };
+        if addr.is_null() {
+            return Err(ENOMEM);
+        }
+
+        // INVARIANT: We just mapped `size` bytes, which is at least `SIZE`.
+        Ok(Self {
+            ptr: addr as usize,
+            size,
+            _region: region,
+            #[cfg(CONFIG_RUST_KUNIT_FAKE_REGS)]
+            fake: false,
+        })
+    }
+
+    /// Returns a window of the registers in `range`, known to be at least `S` bytes long.
+    ///
+    /// Fails with [`EINVAL`] if `range` is not within the mapping, is shorter than `S`, or does
+    /// not start at a multiple of 8 bytes, which keeps the accesses through the window aligned.
+    pub fn window<const S: usize>(&self, range: Range<usize>) -> Result<IoWindow<'_, S>> {
+        // SAFETY: By the type invariants, `ptr` is the start of a mapping of `size` bytes, which
+        // lives as long as `self`.
+        unsafe { IoWindow::new(self.ptr, self.size, range) }
+    }
+
+    /// Splits the registers into two windows, the one before `offset` and the one from `offset`
+    /// on.
+    ///
+    /// Fails with [`EINVAL`] if `offset` is beyond the end of the mapping or is not a multiple of
+    /// 8 bytes.
+    pub fn split_at(&self, offset: usize) -> Result<(IoWindow<'_>, IoWindow<'_>)> {
+        Ok((self.window(0..offset)?, self.window(offset..self.size)?))
+    }
+
+    define_accessors!();
+}
+
+impl<const SIZE: usize> Drop for IoMem<SIZE> {
+    fn drop(&mut self) {
+        #[cfg(CONFIG_RUST_KUNIT_FAKE_REGS)]
+        if self.fake {
+            return;
+        }
+        // SAFETY: By the type invariants, `self.ptr` is a value returned by a previous successful
+        // call to `ioremap`. The region, if any, is released after this, when `_region` is
+        // dropped.
+        unsafe { bindings::iounmap(self.ptr as _) };
+    }
+}
+
+// SAFETY: The mapping can be used and unmapped from any thread.
+unsafe impl<const SIZE: usize> Send for IoMem<SIZE> {}
+
+// SAFETY: Register accesses through a shared reference are single MMIO operations, which are
+// safe to issue concurrently.
+unsafe impl<const SIZE: usize> Sync for IoMem<SIZE> {}
+
+/// A window of the registers of an [`IoMem`] mapping, e.g., the bank of one of the channels of a
+/// device.
+///
+/// Windows have their own bounds: offsets are relative to the start of the window, and accesses
+/// are checked against its size, in the same way as for [`IoMem`]. This lets a driver hand each
+/// sub-block of the registers to the component that drives it, without giving it access to the
+/// rest. [`IoMem::split_at`] and [`IoWindow::split_at`] give windows that do not overlap.
+///
+/// # Invariants
+///
+/// `ptr` is `size` bytes within a mapping created with `ioremap`, that lives for `'a`, at an
+/// offset that is a multiple of 8 bytes from its start, and `size >= SIZE`.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{io_mem::{IoMem, IoWindow}, prelude::*};
+///
+/// const CHAN_CTRL: usize = 0x0;
+///
+/// fn start_channel(chan: &IoWindow<'_, 0x40>) {
+///     chan.writel(1, CHAN_CTRL);
+/// }
+///
+/// fn start_all(regs: &IoMem<0x100>) -> Result {
+///     // Four channels of 0x40 bytes each.
+///     for i in 0..4 {
+///         start_channel(&regs.window::<0x40>(i * 0x40..(i + 1) * 0x40)?);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub struct IoWindow<'a, const SIZE: usize = 0> {
+    ptr: usize,
+    size: usize,
+    _mem: PhantomData<&'a ()>,
+}
+
+impl<'a, const SIZE: usize> IoWindow<'a, SIZE> {
+    /// Creates a window of the `range` of the mapping of `size` bytes at `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be the start of `size` bytes within a mapping created with `ioremap` that lives
+    /// for `'a`, at an offset that is a multiple of 8 bytes from its start.
+    unsafe fn new(ptr: usize, size: usize, range: Range<usize>) -> Result<Self> {
+        if range.start > range.end || range.end > size || range.start % 8 != 0 {
+            return Err(EINVAL);
+        }
+        let len = range.end - range.start;
+        if len < SIZE {
+            return Err(EINVAL);
+        }
+        // INVARIANT: The range was checked to be within the mapping, to start at a multiple of 8
+        // bytes and to be at least `SIZE` bytes long.
+        Ok(Self {
+            ptr: ptr + range.start,
+            size: len,
+            _mem: PhantomData,
+        })
+    }
+
+    /// Returns a window of the registers in `range` of this window.
+    ///
+    /// This is the same as [`IoMem::window`], with `range` relative to the start of this window.
+    pub fn window<const S: usize>(&self, range: Range<usize>) -> Result<IoWindow<'a, S>> {
+        // SAFETY: By the type invariants, `ptr` is `size` bytes within a mapping that lives for
+        // `'a`.
+        unsafe { IoWindow::new(self.ptr, self.size, range) }
+    }
+
+    /// Splits this window into two, the one before `offset` and the one from `offset` on.
+    ///
+    /// This is the same as [`IoMem::split_at`], with `offset` relative to the start of this
+    /// window.
+    pub fn split_at(&self, offset: usize) -> Result<(IoWindow<'a>, IoWindow<'a>)> {
+        Ok((self.window(0..offset)?, self.window(offset..self.size)?))
+    }
+
+    define_accessors!();
+}
+
+// SAFETY: The window can be used from any thread, as the mapping it is part of.
+unsafe impl<const SIZE: usize> Send for IoWindow<'_, SIZE> {}
+
+// SAFETY: Register accesses through a shared reference are single MMIO operations, which are
+// safe to issue concurrently.
+unsafe impl<const SIZE: usize> Sync for IoWindow<'_, SIZE> {}
+
+/// Register accesses, checked at runtime.
+///
+/// This is implemented by [`IoMem`] and [`IoWindow`], with their `try_` accessors, and by software
+/// models of devices, e.g., `mock::Mock`. Driver code that is generic over it can therefore be
+/// tested against a model, e.g., to check the sequence of register accesses it makes, and used on
+/// the hardware without overhead.
+///
+/// Implementations fail with [`EINVAL`] if the offset is out of bounds or unaligned.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{io_mem::Io, prelude::*};
+///
+/// const CTRL: usize = 0x0;
+/// const STATUS: usize = 0x4;
+///
+/// /// Resets the device, which sets bit 0 of the status register once it is done.
+/// fn reset(regs: &impl Io) -> Result {
+///     regs.try_writel(1 << 31, CTRL)?;
+///     if regs.try_readl(STATUS)? & 1 == 0 {
+///         return Err(EIO);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub trait Io {
+    /// Returns the size of the registers in bytes.
+    fn size(&self) -> usize;
+
+    /// Reads the 8-bit register at `offset`.
+    fn try_readb(&self, offset: usize) -> Result<u8>;
+
+    /// Reads the 16-bit register at `offset`.
+    fn try_readw(&self, offset: usize) -> Result<u16>;
+
+    /// Reads the 32-bit register at `offset`.
+    fn try_readl(&self, offset: usize) -> Result<u32>;
+
+    /// Reads the 64-bit register at `offset`.
+    #[cfg(CONFIG_64BIT)]
+    fn try_readq(&self, offset: usize) -> Result<u64>;
+
+    /// Writes `value` to the 8-bit register at `offset`.
+    fn try_writeb(&self, value: u8, offset: usize) -> Result;
+
+    /// Writes `value` to the 16-bit register at `offset`.
+    fn try_writew(&self, value: u16, offset: usize) -> Result;
+
+    /// Writes `value` to the 32-bit register at `offset`.
+    fn try_writel(&self, value: u32, offset: usize) -> Result;
+
+    /// Writes `value` to the 64-bit register at `offset`.
+    #[cfg(CONFIG_64BIT)]
+    fn try_writeq(&self, value: u64, offset: usize) -> Result;
+
+    /// Updates the bits selected by `mask` of the 32-bit register at `offset` to the
+    /// corresponding bits of `value`, with the same caveats as [`IoMem::try_update_bits32`].
+    fn try_update_bits32(&self, offset: usize, mask: u32, value: u32) -> Result {
+        let old = self.try_readl(offset)?;
+        self.try_writel((old & !mask) | (value & mask), offset)
+    }
+}
+
+/// Implements [`Io`] with the inherent `try_` accessors defined by `define_accessors`.
+macro_rules! impl_io {
+    () => {
+        fn size(&self) -> usize {
+            self.size
+        }
+
+        fn try_readb(&self, offset: usize) -> Result<u8> {
+            Self::try_readb(self, offset)
+        }
+
+        fn try_readw(&self, offset: usize) -> Result<u16> {
+            Self::try_readw(self, offset)
+        }
+
+        fn try_readl(&self, offset: usize) -> Result<u32> {
+            Self::try_readl(self, offset)
+        }
+
+        #[cfg(CONFIG_64BIT)]
+        fn try_readq(&self, offset: usize) -> Result<u64> {
+            Self::try_readq(self, offset)
+        }
+
+        fn try_writeb(&self, value: u8, offset: usize) -> Result {
+            Self::try_writeb(self, value, offset)
+        }
+
+        fn try_writew(&self, value: u16, offset: usize) -> Result {
+            Self::try_writew(self, value, offset)
+        }
+
+        fn try_writel(&self, value: u32, offset: usize) -> Result {
+            Self::try_writel(self, value, offset)
+        }
+
+        #[cfg(CONFIG_64BIT)]
+        fn try_writeq(&self, value: u64, offset: usize) -> Result {
+            Self::try_writeq(self, value, offset)
+        }
+
+        fn try_update_bits32(&self, offset: usize, mask: u32, value: u32) -> Result {
+            Self::try_update_bits32(self, offset, mask, value)
+        }
+    };
+}
+
+impl<const SIZE: usize> Io for IoMem<SIZE> {
+    impl_io!();
+}
+
+impl<const SIZE: usize> Io for IoWindow<'_, SIZE> {
+    impl_io!();
+}
+
+/// Number of [`FakeRegs`] that can exist at the same time.
+#[cfg(CONFIG_RUST_KUNIT_FAKE_REGS)]
+const FAKE_SLOTS: usize = 16;
+
+/// Size of the physical address window of each [`FakeRegs`], and thus their maximum size.
+#[cfg(CONFIG_RUST_KUNIT_FAKE_REGS)]
+const FAKE_SLOT_SIZE: usize = 0x10_0000;
+
+/// Start of the fake physical addresses, above the 52 bits that arm64 can address.
+#[cfg(CONFIG_RUST_KUNIT_FAKE_REGS)]
+const FAKE_BASE: bindings::resource_size_t = 1 << 56;
+
+#[cfg(CONFIG_RUST_KUNIT_FAKE_REGS)]
+#[allow(clippy::declare_interior_mutable_const)]
+const FAKE_FREE: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
+
+/// Sizes of the live [`FakeRegs`], indexed by slot, or zero for free slots.
+#[cfg(CONFIG_RUST_KUNIT_FAKE_REGS)]
+static FAKE_SIZES: [core::sync::atomic::AtomicUsize; FAKE_SLOTS] = [FAKE_FREE; FAKE_SLOTS];
+
+/// Buffers of the live [`FakeRegs`], indexed by slot, or zero for slots that are free or being set
+/// up.
+#[cfg(CONFIG_RUST_KUNIT_FAKE_REGS)]
+static FAKE_BUFS: [core::sync::atomic::AtomicUsize; FAKE_SLOTS] = [FAKE_FREE; FAKE_SLOTS];
+
+/// Registers backed by memory, for tests.
+///
+/// Each instance is given a range of fake physical addresses, which is described to the device
+/// under test, e.g., with the `reg` property of a devicetree node. [`IoMem`] maps ranges within
+/// it to the buffer instead of calling `ioremap`, so that the driver accesses the buffer with the
+/// usual accessors, and the test can set its contents and check what the driver wrote.
+///
+/// The registers have no side effects: a value written is read back as is.
+///
+/// This is only available with `CONFIG_RUST_KUNIT_FAKE_REGS`, which is meant for test kernels,
+/// as every mapping then first checks whether it is of fake registers.
+///
+/// # Invariants
+///
+/// `FAKE_SIZES[slot]` is `size` and `FAKE_BUFS[slot]` is the address of `buf`, which holds at
+/// least `size` bytes.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{io_mem::{FakeRegs, IoMem}, prelude::*};
+///
+/// let regs = FakeRegs::new(0x100)?;
+/// regs.write32(0xdead_beef, 0x10);
+///
+/// let res = regs.resource();
+/// // SAFETY: The range belongs to `regs`, which no driver uses.
+/// let io = unsafe { IoMem::<0x100>::try_new(&res)? };
+/// assert_eq!(io.readl(0x10), 0xdead_beef);
+/// io.writel(1, 0x20);
+/// assert_eq!(regs.read32(0x20), 1);
+/// # Ok::<(), Error>(())
+/// ```
+#[cfg(CONFIG_RUST_KUNIT_FAKE_REGS)]
+pub struct FakeRegs {
+    slot: usize,
+    buf: crate::prelude::Vec<u64>,
+    size: usize,
+}
+
+#[cfg(CONFIG_RUST_KUNIT_FAKE_REGS)]
+impl FakeRegs {
+    /// Allocates `size` bytes of zeroed registers.
+    ///
+    /// Fails with [`EINVAL`] if `size` is zero or larger than 1 MiB, and with [`EBUSY`] if too
+    /// many instances exist already.
+    pub fn new(size: usize) -> Result<Self> {
+        use crate::prelude::*;
+        use core::sync::atomic::Ordering;
+
+        if size == 0 || size > FAKE_SLOT_SIZE {
+            return Err(EINVAL);
+        }
+        let words = size.div_ceil(8);
+        let mut buf = Vec::with_capacity(words, GFP_KERNEL)?;
+        for _ in 0..words {
+            buf.push(0u64, GFP_KERNEL)?;
+        }
+        let slot = FAKE_SIZES
+            .iter()
+            .position(|s| {
+                s.compare_exchange(0, size, Ordering::Relaxed, Ordering::Relaxed)
+                    .is_ok()
+            })
+            .ok_or(EBUSY)?;
+        // Publishes the buffer, after its size.
+        FAKE_BUFS[slot].store(buf.as_mut_ptr() as usize, Ordering::Release);
+
+        // INVARIANT: The slot was just set to `size` and the address of `buf`.
+        Ok(Self { slot, buf, size })
+    }
+
+    /// Returns the memory resource describing the fake physical range of the registers.
+    pub fn resource(&self) -> Resource {
+        Resource {
+            start: self.start(),
+            len: self.size as _,
+            flags: flags::MEM,
+        }
+    }
+
+    /// Returns the start of the fake physical range of the registers.
+    pub fn start(&self) -> bindings::resource_size_t {
+        FAKE_BASE + (self.slot * FAKE_SLOT_SIZE) as bindings::resource_size_t
+    }
+
+    /// Returns the size of the registers, in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Reads the 32-bit register at `offset`.
+    ///
+    /// Panics if `offset` is out of bounds or not 4-byte aligned.
+    pub fn read32(&self, offset: usize) -> u32 {
+        let ptr = self.reg32(offset);
+        // SAFETY: `reg32` checked that `ptr` is an aligned register within `buf`. The driver may
+        // access it concurrently, through `IoMem`, so the access is volatile.
+        unsafe { ptr.read_volatile() }
+    }
+
+    /// Writes `value` to the 32-bit register at `offset`, e.g., to set a status bit that the
+    /// driver polls for.
+    ///
+    /// Panics if `offset` is out of bounds or not 4-byte aligned.
+    pub fn write32(&self, value: u32, offset: usize) {
+        let ptr = self.reg32(offset);
+        // SAFETY: `reg32` checked that `ptr` is an aligned register within `buf`. The driver may
+        // access it concurrently, through `IoMem`, so the access is volatile.
+        unsafe { ptr.write_volatile(value) }
+    }
+
+    fn reg32(&self, offset: usize) -> *mut u32 {
+        assert!(offset % 4 == 0 && offset + 4 <= self.size);
+        // Registers are written through `IoMem`, which only has the address of the buffer, so
+        // they are accessed through a raw pointer here as well.
+        self.buf
+            .as_ptr()
+            .cast_mut()
+            .cast::<u8>()
+            .wrapping_add(offset)
+            .cast()
+    }
+
+    /// Returns the address of the buffer that backs `size` bytes at the fake physical address
+    /// `start`, if any.
+    fn lookup(start: bindings::resource_size_t, size: usize) -> Option<usize> {
+        use core::sync::atomic::Ordering;
+
+        let offset = usize::try_from(start.checked_sub(FAKE_BASE)?).ok()?;
+        let slot = offset / FAKE_SLOT_SIZE;
+        let offset = offset % FAKE_SLOT_SIZE;
+        let addr = FAKE_BUFS.get(slot)?.load(Ordering::Acquire);
+        if addr == 0 || offset.checked_add(size)? > FAKE_SIZES[slot].load(Ordering::Relaxed) {
+            return None;
+        }
+        Some(addr + offset)
+    }
+}
+
+#[cfg(CONFIG_RUST_KUNIT_FAKE_REGS)]
+impl Drop for FakeRegs {
+    fn drop(&mut self) {
+        use core::sync::atomic::Ordering;
+
+        // Drivers using the registers must be unbound before the registers are dropped. The slot
+        // is freed before the buffer.
+        FAKE_BUFS[self.slot].store(0, Ordering::Relaxed);
+        FAKE_SIZES[self.slot].store(0, Ordering::Release);
+    }
+}
+
+// SAFETY: The buffer can be used and freed from any thread.
+#[cfg(CONFIG_RUST_KUNIT_FAKE_REGS)]
+unsafe impl Send for FakeRegs {}
+
+// SAFETY: Registers are only accessed with volatile reads and writes, which can be issued
+// concurrently, as for real registers.
+#[cfg(CONFIG_RUST_KUNIT_FAKE_REGS)]
+unsafe impl Sync for FakeRegs {}
@@ -70,3 +70,117 @@ pub const fn _IOC_NR(nr: u32) -> u32 {
 pub const fn _IOC_SIZE(nr: u32) -> usize {
     ((nr >> uapi::_IOC_SIZESHIFT) & uapi::_IOC_SIZEMASK) as usize
 }
+
+/// Replaces the size field of the ioctl number `cmd` with `size`.
+#[inline(always)]
+const fn with_size(cmd: u32, size: usize) -> u32 {
+    build_assert!(size <= (uapi::_IOC_SIZEMASK as usize));
+
+    (cmd & !(uapi::_IOC_SIZEMASK << uapi::_IOC_SIZESHIFT)) | ((size as u32) << uapi::_IOC_SIZESHIFT)
+}
+
+/// A type whose layout differs between 64-bit and 32-bit userspace.
+///
+/// On 64-bit kernels that run 32-bit userspace, e.g., AArch32 tasks on aarch64, `long` and
+/// pointers only have 32 bits in the argument structures of ioctls. Drivers describe the 32-bit
+/// layout of such structures as [`Compat::Layout`] and convert between the two with this trait,
+/// usually in the `compat_ioctl` path of their file operations.
+///
+/// Structures that only consist of fixed-size fields, e.g., `u32` and `u64` aligned to their
+/// size, have the same layout for both and do not need this; their ioctls can be routed to the
+/// native handler, with their argument converted with [`compat_ptr`].
+///
+/// # Examples
+///
+/// ```
+/// use kernel::ioctl::{self, Compat};
+///
+/// #[repr(C)]
+/// #[derive(Clone, Copy)]
+/// struct Buffer {
+///     addr: usize,
+///     len: u32,
+/// }
+///
+/// #[repr(C)]
+/// #[derive(Clone, Copy)]
+/// struct CompatBuffer {
+///     addr: u32,
+///     len: u32,
+/// }
+///
+/// impl Compat for Buffer {
+///     type Layout = CompatBuffer;
+///
+///     fn from_compat(c: &CompatBuffer) -> Self {
+///         Self {
+///             addr: ioctl::compat_ptr(c.addr),
+///             len: c.len,
+///         }
+///     }
+///
+///     fn to_compat(&self) -> CompatBuffer {
+///         CompatBuffer {
+///             addr: self.addr as u32,
+///             len: self.len,
+///         }
+///     }
+/// }
+///
+/// const MAP: u32 = ioctl::_IOWR::<Buffer>(b'x' as u32, 1);
+/// const COMPAT_MAP: u32 = ioctl::compat_cmd::<Buffer>(MAP);
+///
+/// assert_eq!(ioctl::_IOC_SIZE(COMPAT_MAP), 8);
+/// assert_eq!(ioctl::native_cmd::<Buffer>(COMPAT_MAP), Some(MAP));
+///
+/// // On 32-bit kernels, the native and the compat layouts are the same.
+/// #[cfg(CONFIG_64BIT)]
+/// assert_eq!(ioctl::native_cmd::<Buffer>(MAP), None);
+/// #[cfg(not(CONFIG_64BIT))]
+/// assert_eq!(ioctl::native_cmd::<Buffer>(MAP), Some(MAP));
+/// ```
+pub trait Compat: Sized {
+    /// The layout of the type in 32-bit userspace.
+    type Layout: Copy;
+
+    /// Converts the 32-bit layout to the native one.
+    fn from_compat(c: &Self::Layout) -> Self;
+
+    /// Converts the native layout to the 32-bit one.
+    fn to_compat(&self) -> Self::Layout;
+}
+
+/// Returns the number of the ioctl `native_cmd` as seen by 32-bit userspace.
+///
+/// The size of the argument is encoded in the ioctl number, so an ioctl whose argument is `T` has
+/// a different number for 32-bit userspace if the layouts of `T` differ.
+#[inline(always)]
+pub const fn compat_cmd<T: Compat>(native_cmd: u32) -> u32 {
+    build_assert!(_IOC_SIZE(native_cmd) == core::mem::size_of::<T>());
+
+    with_size(native_cmd, core::mem::size_of::<T::Layout>())
+}
+
+/// Translates the ioctl number `cmd` of 32-bit userspace to the native number, if it refers to an
+/// ioctl whose argument is `T`.
+///
+/// Returns [`None`] if the size encoded in `cmd` is not that of [`Compat::Layout`], in which case
+/// `cmd` is some other ioctl.
+#[inline(always)]
+pub const fn native_cmd<T: Compat>(cmd: u32) -> Option<u32> {
+    if _IOC_SIZE(cmd) != core::mem::size_of::<T::Layout>() {
+        return None;
+    }
+
+    Some(with_size(cmd, core::mem::size_of::<T>()))
+}
+
+/// Converts a pointer passed by 32-bit userspace to a native user address.
+///
+/// This must be applied to the `arg` of `compat_ioctl` before it is used as a pointer, as well as
+/// to pointers embedded in [`Compat::Layout`]s.
+#[inline]
+pub const fn compat_ptr(uptr: u32) -> usize {
+    // Zero extension is enough on all architectures except s390, which Rust does not support.
+    uptr as usize
+}
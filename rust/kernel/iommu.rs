@@ -0,0 +1,609 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! IOMMU domains for device drivers.
+//!
+//! Most drivers use the DMA API and never see the IOMMU behind their device. Drivers that manage
+//! the I/O address space of their device themselves, e.g., accelerators that share the address
+//! space of a user process or fill it on demand, allocate a [`Domain`] and [`Domain::attach`] the
+//! device to it. The driver must then have opted out of the DMA API ownership of the device, e.g.,
+//! with `driver_managed_dma` for platform drivers.
+//!
+//! Faults in the domain are handled by the [`FaultHandler`] of the domain:
+//!
+//! - Recoverable faults are page requests that the device, e.g., a PCIe device with PRI or a
+//!   device that stalls its transactions behind an SMMUv3, waits on until the handler responded,
+//!   usually after mapping the page. They are only reported while [`Feature::Iopf`] is enabled
+//!   for the device.
+//! - Unrecoverable faults are only reported after the transaction was aborted.
+//!
+//! The IOMMU may reserve parts of the I/O address space of a device, see [`reserved_regions`],
+//! which the driver must not map anything else at.
+//!
+//! C header: [`include/linux/iommu.h`](srctree/include/linux/iommu.h)
+
+use crate::{
+    bindings,
+    device::{Device, RawDevice},
+    error::{from_result, to_result, VTABLE_DEFAULT_ERROR},
+    new_mutex,
+    prelude::*,
+    sync::Mutex,
+    types::{ARef, ForeignOwnable},
+};
+use core::{
+    ffi::{c_int, c_ulong, c_void},
+    marker::PhantomData,
+    pin::Pin,
+    ptr::{self, NonNull},
+};
+
+/// Protection flags of mappings and reserved regions.
+pub mod prot {
+    use crate::bindings;
+
+    /// The device can read the mapping.
+    pub const READ: u32 = bindings::IOMMU_READ;
+
+    /// The device can write the mapping.
+    pub const WRITE: u32 = bindings::IOMMU_WRITE;
+
+    /// Accesses are cache coherent.
+    pub const CACHE: u32 = bindings::IOMMU_CACHE;
+
+    /// The device cannot execute from the mapping.
+    pub const NOEXEC: u32 = bindings::IOMMU_NOEXEC;
+
+    /// The mapping is of device registers, e.g., an MSI doorbell.
+    pub const MMIO: u32 = bindings::IOMMU_MMIO;
+
+    /// Only privileged accesses of the device are allowed.
+    pub const PRIV: u32 = bindings::IOMMU_PRIV;
+}
+
+/// Permissions requested by a [`PageRequest`].
+pub mod perm {
+    use crate::bindings;
+
+    /// The device reads the page.
+    pub const READ: u32 = bindings::IOMMU_FAULT_PERM_READ;
+
+    /// The device writes the page.
+    pub const WRITE: u32 = bindings::IOMMU_FAULT_PERM_WRITE;
+
+    /// The device executes from the page.
+    pub const EXEC: u32 = bindings::IOMMU_FAULT_PERM_EXEC;
+
+    /// The access is privileged.
+    pub const PRIV: u32 = bindings::IOMMU_FAULT_PERM_PRIV;
+}
+
+/// The kind of a [`ReservedRegion`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReservedKind {
+    /// Memory the device accesses before the driver takes over, e.g., a framebuffer set up by the
+    /// firmware, which is mapped one to one.
+    Direct,
+    /// Like [`ReservedKind::Direct`], but the mapping may be dropped once the driver has taken
+    /// over the device.
+    DirectRelaxable,
+    /// A range the device cannot access through the IOMMU.
+    Reserved,
+    /// The MSI doorbell of a hardware MSI controller, which is not translated.
+    Msi,
+    /// The window the IOMMU maps MSI doorbells at, for MSI controllers behind the IOMMU.
+    ///
+    /// Drivers with their own domain must map the doorbells there themselves, e.g., with
+    /// `iommu_get_msi_cookie`, for the MSIs of the device to work.
+    SwMsi,
+}
+
+impl ReservedKind {
+    fn from_raw(kind: bindings::iommu_resv_type) -> Self {
+        match kind {
+            bindings::iommu_resv_type_IOMMU_RESV_DIRECT => Self::Direct,
+            bindings::iommu_resv_type_IOMMU_RESV_DIRECT_RELAXABLE => Self::DirectRelaxable,
+            bindings::iommu_resv_type_IOMMU_RESV_MSI => Self::Msi,
+            bindings::iommu_resv_type_IOMMU_RESV_SW_MSI => Self::SwMsi,
+            // Kinds added later are at least not usable for mappings.
+            _ => Self::Reserved,
+        }
+    }
+}
+
+/// A region of the I/O address space of a device that is reserved by the IOMMU or the platform.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReservedRegion {
+    /// The start of the region.
+    pub start: bindings::phys_addr_t,
+    /// The length of the region in bytes.
+    pub len: usize,
+    /// The protection of the region, see [`prot`].
+    pub prot: u32,
+    /// The kind of the region.
+    pub kind: ReservedKind,
+}
+
+impl ReservedRegion {
+    /// Returns `true` if the region overlaps with the `len` bytes at `iova`.
+    pub fn overlaps(&self, iova: u64, len: usize) -> bool {
+        let start = self.start as u64;
+        iova < start.saturating_add(self.len as u64) && start < iova.saturating_add(len as u64)
+    }
+}
+
+/// Returns the reserved regions of the I/O address space of `dev`.
+///
+/// This includes the regions reserved for all devices behind the same IOMMU, as well as the
+/// regions described by the firmware for `dev`, e.g., reserved memory regions in the devicetree.
+pub fn reserved_regions(dev: &impl RawDevice) -> Result<Vec<ReservedRegion>> {
+    let mut head = bindings::list_head {
+        next: ptr::null_mut(),
+        prev: ptr::null_mut(),
+    };
+    let head_ptr = ptr::addr_of_mut!(head);
+    // The list is empty, and `head` is not moved until it is released below.
+    head.next = head_ptr;
+    head.prev = head_ptr;
+
+    // SAFETY: `dev.raw_device()` is valid, and `head` is an empty list.
+    unsafe { bindings::iommu_get_resv_regions(dev.raw_device(), head_ptr) };
+
+    let mut regions = Vec::new();
+    let mut ret = Ok(());
+    let mut pos = head.next;
+    while pos != head_ptr {
+        // SAFETY: `pos` is the `list` field of a region added by `iommu_get_resv_regions`.
+        let region = unsafe { &*crate::container_of!(pos, bindings::iommu_resv_region, list) };
+        if ret.is_ok() {
+            let region = ReservedRegion {
+                start: region.start,
+                len: region.length,
+                prot: region.prot as u32,
+                kind: ReservedKind::from_raw(region.type_),
+            };
+            ret = regions.push(region, GFP_KERNEL);
+        }
+        pos = region.list.next;
+    }
+
+    // SAFETY: The list was filled by `iommu_get_resv_regions` for `dev`, and it is not used
+    // after this.
+    unsafe { bindings::iommu_put_resv_regions(dev.raw_device(), head_ptr) };
+    ret?;
+    Ok(regions)
+}
+
+/// An IOMMU feature of a device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Feature {
+    /// Shared virtual addressing, i.e., PASIDs bound to process address spaces.
+    Sva,
+    /// I/O page faults, i.e., page requests reported to the [`FaultHandler`] of the domain.
+    ///
+    /// Some IOMMU drivers, e.g., that of SMMUv3, only report page requests once
+    /// [`Feature::Sva`] is enabled as well, after this feature.
+    Iopf,
+}
+
+impl Feature {
+    fn as_raw(self) -> bindings::iommu_dev_features {
+        match self {
+            Self::Sva => bindings::iommu_dev_features_IOMMU_DEV_FEAT_SVA,
+            Self::Iopf => bindings::iommu_dev_features_IOMMU_DEV_FEAT_IOPF,
+        }
+    }
+}
+
+/// An IOMMU feature enabled for a device.
+///
+/// The feature is disabled when this object is dropped. Features that others depend on must be
+/// dropped last.
+///
+/// # Invariants
+///
+/// `feature` was enabled for `dev` with `iommu_dev_enable_feature`.
+pub struct EnabledFeature {
+    dev: ARef<Device>,
+    feature: Feature,
+}
+
+impl EnabledFeature {
+    /// Enables `feature` for `dev`.
+    ///
+    /// Fails with [`ENODEV`] if the device is not behind an IOMMU, and with another error if the
+    /// IOMMU or the device does not support the feature.
+    pub fn new(dev: &impl RawDevice, feature: Feature) -> Result<Self> {
+        // SAFETY: `dev.raw_device()` is valid by the safety requirements of `RawDevice`; a
+        // reference to it is taken, and released after the feature is disabled.
+        let dev = unsafe { Device::new(dev.raw_device()) };
+        // SAFETY: `dev` is valid.
+        to_result(unsafe { bindings::iommu_dev_enable_feature(dev.as_raw(), feature.as_raw()) })?;
+        // INVARIANT: The feature was just enabled for `dev`.
+        Ok(Self { dev, feature })
+    }
+}
+
+impl Drop for EnabledFeature {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, the feature is enabled for `dev`.
+        let ret = unsafe {
+            bindings::iommu_dev_disable_feature(self.dev.as_raw(), self.feature.as_raw())
+        };
+        if ret < 0 {
+            pr_warn!(
+                "failed to disable IOMMU feature {:?}: {}\n",
+                self.feature,
+                ret
+            );
+        }
+    }
+}
+
+/// A page request of a device, which waits for the response of the fault handler.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PageRequest {
+    /// The I/O virtual address of the access.
+    pub addr: u64,
+    /// The PASID of the address space of the access, if any.
+    pub pasid: Option<u32>,
+    /// The permissions the access needs, see [`perm`].
+    pub perm: u32,
+    /// `true` for the last request of its group.
+    pub last: bool,
+}
+
+impl PageRequest {
+    fn from_raw(prm: &bindings::iommu_fault_page_request) -> Self {
+        Self {
+            addr: prm.addr,
+            pasid: if prm.flags & bindings::IOMMU_FAULT_PAGE_REQUEST_PASID_VALID != 0 {
+                Some(prm.pasid)
+            } else {
+                None
+            },
+            perm: prm.perm,
+            last: prm.flags & bindings::IOMMU_FAULT_PAGE_REQUEST_LAST_PAGE != 0,
+        }
+    }
+}
+
+/// The response to a group of page requests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Response {
+    /// The pages are mapped, the device retries the accesses.
+    Success,
+    /// The accesses are invalid, the device aborts them but keeps issuing page requests.
+    Invalid,
+    /// Page requests cannot be handled, the device stops issuing them.
+    Failure,
+}
+
+impl Response {
+    fn as_raw(self) -> bindings::iommu_page_response_code {
+        match self {
+            Self::Success => bindings::iommu_page_response_code_IOMMU_PAGE_RESP_SUCCESS,
+            Self::Invalid => bindings::iommu_page_response_code_IOMMU_PAGE_RESP_INVALID,
+            Self::Failure => bindings::iommu_page_response_code_IOMMU_PAGE_RESP_FAILURE,
+        }
+    }
+}
+
+/// A group of page requests of a device, answered with a single [`Response`].
+///
+/// # Invariants
+///
+/// `group` is a valid fault group that is being handled, for the lifetime `'a`.
+pub struct FaultGroup<'a> {
+    group: NonNull<bindings::iopf_group>,
+    _p: PhantomData<&'a ()>,
+}
+
+impl FaultGroup<'_> {
+    /// Returns the device that issued the requests.
+    pub fn device(&self) -> &Device {
+        // SAFETY: By the type invariants, the group is valid, and it holds a reference to the
+        // fault parameters of the device, which hold the device.
+        unsafe { Device::from_raw((*(*self.group.as_ptr()).fault_param).dev) }
+    }
+
+    /// Returns the requests of the group, the last one included.
+    pub fn requests(&self) -> impl Iterator<Item = PageRequest> + '_ {
+        // SAFETY: By the type invariants, the group is valid, and its list of faults is not
+        // changed while it is handled.
+        let head = unsafe { ptr::addr_of_mut!((*self.group.as_ptr()).faults) };
+        // SAFETY: See above.
+        let mut pos = unsafe { (*head).next };
+        core::iter::from_fn(move || {
+            if pos == head {
+                return None;
+            }
+            // SAFETY: `pos` is the `list` field of a fault of the group, which is valid while the
+            // group is.
+            let fault = unsafe { &*crate::container_of!(pos, bindings::iopf_fault, list) };
+            pos = fault.list.next;
+            Some(PageRequest::from_raw(&fault.fault.prm))
+        })
+    }
+}
+
+/// The fault handler of an IOMMU [`Domain`].
+#[vtable]
+pub trait FaultHandler {
+    /// The context data made available to the callbacks.
+    type Data: ForeignOwnable + Send + Sync;
+
+    /// Handles a group of page requests.
+    ///
+    /// This is called in the context that reports the faults, e.g., the event queue thread of
+    /// SMMUv3, and may sleep. The device waits on the response, as may the other devices behind
+    /// the same IOMMU, so the handler must not block for long.
+    fn page_requests(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _group: &FaultGroup<'_>,
+    ) -> Response {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+
+    /// Handles an unrecoverable fault at `iova`, by a write if `write` is `true`.
+    ///
+    /// `dev` is the device that reported the fault, which is the IOMMU itself for some IOMMU
+    /// drivers. This may be called in interrupt context.
+    ///
+    /// Returns [`ENOSYS`] to let the IOMMU driver log the fault as if there was no handler.
+    fn unrecoverable(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _dev: &Device,
+        _iova: usize,
+        _write: bool,
+    ) -> Result {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+}
+
+/// An unmanaged IOMMU domain, i.e., an I/O address space that the driver maps itself.
+///
+/// The domain is freed, and its data dropped, when this object is dropped. Devices that are still
+/// attached then, e.g., because their [`Attachment`] was leaked, are detached first.
+///
+/// # Invariants
+///
+/// `domain` was allocated with `iommu_domain_alloc`, and `data` was obtained from
+/// [`ForeignOwnable::into_foreign`] on a `T::Data`. `attached` holds the devices attached to
+/// `domain`.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{
+///     device::RawDevice,
+///     iommu::{self, Domain, EnabledFeature, Feature, FaultGroup, FaultHandler, Response},
+///     prelude::*,
+/// };
+///
+/// struct Accel;
+///
+/// #[vtable]
+/// impl FaultHandler for Accel {
+///     type Data = ();
+///
+///     fn page_requests(_data: (), group: &FaultGroup<'_>) -> Response {
+///         for req in group.requests() {
+///             pr_info!("page request at {:#x}\n", req.addr);
+///         }
+///         // A real driver would map the pages here.
+///         Response::Invalid
+///     }
+/// }
+///
+/// fn setup(dev: &impl RawDevice, iova: u64, len: usize) -> Result<Domain<Accel>> {
+///     if iommu::reserved_regions(dev)?.iter().any(|r| r.overlaps(iova, len)) {
+///         return Err(EINVAL);
+///     }
+///     Domain::alloc(dev, ())
+/// }
+///
+/// fn run(dev: &impl RawDevice, domain: &Domain<Accel>) -> Result {
+///     let _iopf = EnabledFeature::new(dev, Feature::Iopf)?;
+///     let _attachment = domain.attach(dev)?;
+///     // ... start the device and wait for it to complete ...
+///     Ok(())
+/// }
+/// ```
+pub struct Domain<T: FaultHandler> {
+    domain: NonNull<bindings::iommu_domain>,
+    data: *const c_void,
+    attached: Pin<Box<Mutex<Vec<ARef<Device>>>>>,
+    _p: PhantomData<T>,
+}
+
+impl<T: FaultHandler> Domain<T> {
+    /// Allocates a domain for devices of the bus of `dev`, with `data` for the fault handler.
+    ///
+    /// Fails with [`ENODEV`] if the bus has no IOMMU.
+    pub fn alloc(dev: &impl RawDevice, data: T::Data) -> Result<Self> {
+        let attached = Box::pin_init(new_mutex!(Vec::new()), GFP_KERNEL)?;
+        // SAFETY: `dev.raw_device()` is valid, and so is its bus.
+        let domain = unsafe { bindings::iommu_domain_alloc((*dev.raw_device()).bus) };
+        let domain = NonNull::new(domain).ok_or(ENODEV)?;
+        let data = data.into_foreign();
+
+        if T::HAS_UNRECOVERABLE {
+            // SAFETY: `domain` is an unmanaged domain that was just allocated, and `data` lives
+            // until the domain is freed.
+            unsafe {
+                bindings::iommu_set_fault_handler(
+                    domain.as_ptr(),
+                    Some(Adapter::<T>::unrecoverable_callback),
+                    data.cast_mut(),
+                )
+            };
+        }
+        if T::HAS_PAGE_REQUESTS {
+            // SAFETY: `domain` was just allocated and is not attached to any device yet, so the
+            // fields are not accessed concurrently.
+            unsafe {
+                (*domain.as_ptr()).iopf_handler = Some(Adapter::<T>::page_requests_callback);
+                (*domain.as_ptr()).fault_data = data.cast_mut();
+            }
+        }
+
+        // INVARIANT: The domain was allocated above, and `data` came from `into_foreign`.
+        Ok(Self {
+            domain,
+            data,
+            attached,
+            _p: PhantomData,
+        })
+    }
+
+    /// Returns a raw pointer to the domain.
+    pub fn as_raw(&self) -> *mut bindings::iommu_domain {
+        self.domain.as_ptr()
+    }
+
+    /// Maps the `size` bytes at physical address `paddr` at `iova`, with the protection `prot`
+    /// (see [`prot`]).
+    ///
+    /// # Safety
+    ///
+    /// The devices attached to the domain must be allowed to access the memory with `prot` until
+    /// it is unmapped, e.g., because it is owned by the driver and freed after that.
+    pub unsafe fn map(
+        &self,
+        iova: u64,
+        paddr: bindings::phys_addr_t,
+        size: usize,
+        prot: u32,
+    ) -> Result {
+        // SAFETY: By the type invariants, the domain is valid. The memory may be accessed by the
+        // safety requirements.
+        to_result(unsafe {
+            bindings::iommu_map(
+                self.as_raw(),
+                iova as c_ulong,
+                paddr,
+                size,
+                prot as c_int,
+                bindings::GFP_KERNEL,
+            )
+        })
+    }
+
+    /// Unmaps the `size` bytes at `iova`, and returns the number of bytes that were unmapped.
+    pub fn unmap(&self, iova: u64, size: usize) -> usize {
+        // SAFETY: By the type invariants, the domain is valid.
+        unsafe { bindings::iommu_unmap(self.as_raw(), iova as c_ulong, size) }
+    }
+
+    /// Attaches `dev` to the domain, until the returned object is dropped.
+    pub fn attach(&self, dev: &impl RawDevice) -> Result<Attachment<'_, T>> {
+        // SAFETY: `dev.raw_device()` is valid by the safety requirements of `RawDevice`; a
+        // reference to it is taken, and released after the device is detached.
+        let dev = unsafe { Device::new(dev.raw_device()) };
+        let mut attached = self.attached.lock();
+        // Reserve the slot first, so that the device is not attached if it cannot be tracked.
+        attached.reserve(1, GFP_KERNEL)?;
+        // SAFETY: By the type invariants, the domain is valid, and so is `dev`.
+        to_result(unsafe { bindings::iommu_attach_device(self.as_raw(), dev.as_raw()) })?;
+        // INVARIANT: `dev` was just attached to the domain, and is tracked in `attached`.
+        attached.push(dev.clone(), GFP_KERNEL)?;
+        Ok(Attachment { domain: self, dev })
+    }
+}
+
+impl<T: FaultHandler> Drop for Domain<T> {
+    fn drop(&mut self) {
+        for dev in self.attached.lock().drain(..) {
+            pr_warn!("IOMMU domain freed with a device still attached\n");
+            // SAFETY: By the type invariants, `dev` is attached to the domain. Its `Attachment`
+            // was leaked, since attachments borrow the domain, so it is not detached again.
+            unsafe { bindings::iommu_detach_device(self.as_raw(), dev.as_raw()) };
+        }
+        // SAFETY: By the type invariants, the domain is valid. No device is attached to it
+        // anymore, so no fault handler runs after this.
+        unsafe { bindings::iommu_domain_free(self.as_raw()) };
+        // SAFETY: By the type invariants, the data came from `into_foreign`, and it is no longer
+        // used.
+        drop(unsafe { T::Data::from_foreign(self.data) });
+    }
+}
+
+// SAFETY: The domain can be used and freed from any thread, and its data is `Send`.
+unsafe impl<T: FaultHandler> Send for Domain<T> {}
+
+// SAFETY: The IOMMU core serialises concurrent mappings of a domain, and the data is `Sync`.
+unsafe impl<T: FaultHandler> Sync for Domain<T> {}
+
+/// A device attached to a [`Domain`].
+///
+/// # Invariants
+///
+/// `dev` is attached to `domain`, and tracked in its list of attached devices.
+pub struct Attachment<'a, T: FaultHandler> {
+    domain: &'a Domain<T>,
+    dev: ARef<Device>,
+}
+
+impl<T: FaultHandler> Drop for Attachment<'_, T> {
+    fn drop(&mut self) {
+        let mut attached = self.domain.attached.lock();
+        if let Some(i) = attached
+            .iter()
+            .position(|d| ptr::eq(d.as_raw(), self.dev.as_raw()))
+        {
+            attached.swap_remove(i);
+        }
+        // SAFETY: By the type invariants, `dev` is attached to the domain. Pending page requests
+        // are answered by the IOMMU core when the device is detached.
+        unsafe { bindings::iommu_detach_device(self.domain.as_raw(), self.dev.as_raw()) };
+    }
+}
+
+struct Adapter<T: FaultHandler>(PhantomData<T>);
+
+impl<T: FaultHandler> Adapter<T> {
+    unsafe extern "C" fn page_requests_callback(group: *mut bindings::iopf_group) -> c_int {
+        from_result(|| {
+            let group = NonNull::new(group).ok_or(EINVAL)?;
+            // SAFETY: The group is valid while it is handled. Its domain is one allocated by
+            // `Domain::alloc`, whose fault data came from `into_foreign` and lives until it is
+            // freed, which is after its devices were detached.
+            let data = unsafe { T::Data::borrow((*(*group.as_ptr()).domain).fault_data) };
+            // INVARIANT: The group is valid for the duration of the callback.
+            let fault_group = FaultGroup {
+                group,
+                _p: PhantomData,
+            };
+            let response = T::page_requests(data, &fault_group);
+            // SAFETY: The group is answered and freed once, after which it is not used. The IOMMU
+            // core leaves both to the handler when it returns zero.
+            unsafe {
+                bindings::iopf_group_response(group.as_ptr(), response.as_raw());
+                bindings::iopf_free_group(group.as_ptr());
+            }
+            Ok(0)
+        })
+    }
+
+    unsafe extern "C" fn unrecoverable_callback(
+        _domain: *mut bindings::iommu_domain,
+        dev: *mut bindings::device,
+        iova: c_ulong,
+        flags: c_int,
+        token: *mut c_void,
+    ) -> c_int {
+        from_result(|| {
+            if dev.is_null() {
+                return Err(ENOSYS);
+            }
+            // SAFETY: The token is the fault data of a domain allocated by `Domain::alloc`, which
+            // lives until the domain is freed.
+            let data = unsafe { T::Data::borrow(token) };
+            // SAFETY: `dev` is valid for the duration of the callback.
+            let dev = unsafe { Device::from_raw(dev) };
+            let write = flags as u32 & bindings::IOMMU_FAULT_WRITE != 0;
+            T::unrecoverable(data, dev, iova as usize, write)?;
+            Ok(0)
+        })
+    }
+}
@@ -0,0 +1,192 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Waiting for hardware conditions.
+//!
+//! [`read_poll_timeout`] polls a register until it reaches a state. Hardware that raises an
+//! interrupt when, e.g., a command completes, is waited for with [`IrqWait`] instead, which sleeps
+//! until the interrupt handler signals it and still polls now and then, so that a lost or
+//! misrouted interrupt only costs latency instead of a timeout.
+//!
+//! C header: [`include/linux/iopoll.h`](srctree/include/linux/iopoll.h)
+
+use crate::{bindings, prelude::*, sync::Completion, time::timekeeping};
+
+const NSEC_PER_USEC: i64 = 1000;
+
+fn deadline(timeout_us: u64) -> timekeeping::Ktime {
+    let timeout = i64::try_from(timeout_us)
+        .unwrap_or(i64::MAX)
+        .saturating_mul(NSEC_PER_USEC);
+    timekeeping::monotonic().saturating_add(timeout)
+}
+
+/// Calls `op` until `cond` is satisfied by its result, sleeping `sleep_us` microseconds in
+/// between, and returns that result.
+///
+/// Fails with [`ETIMEDOUT`] if `cond` is not satisfied within `timeout_us` microseconds. `op` is
+/// called one last time after the timeout, so that a condition that became true while the task
+/// was preempted is not missed. A `sleep_us` of zero busy-waits, which is only appropriate for
+/// very short timeouts.
+///
+/// This is the equivalent of the C `read_poll_timeout`, and must be called in a context that can
+/// sleep unless `sleep_us` is zero.
+///
+/// # Examples
+///
+/// ```
+/// use core::cell::Cell;
+/// use kernel::iopoll::read_poll_timeout;
+///
+/// // Stands in for a status register that reports ready after a few reads.
+/// let reads = Cell::new(0u32);
+/// let read = || {
+///     reads.set(reads.get() + 1);
+///     reads.get()
+/// };
+/// assert_eq!(read_poll_timeout(read, |s| *s >= 3, 10, 1000), Ok(3));
+///
+/// assert_eq!(read_poll_timeout(|| 0u32, |s| *s != 0, 10, 100), Err(ETIMEDOUT));
+/// ```
+pub fn read_poll_timeout<T>(
+    mut op: impl FnMut() -> T,
+    cond: impl Fn(&T) -> bool,
+    sleep_us: u64,
+    timeout_us: u64,
+) -> Result<T> {
+    let end = deadline(timeout_us);
+    loop {
+        let val = op();
+        if cond(&val) {
+            return Ok(val);
+        }
+        if timekeeping::monotonic() > end {
+            let val = op();
+            return if cond(&val) { Ok(val) } else { Err(ETIMEDOUT) };
+        }
+        if sleep_us != 0 {
+            // SAFETY: `fsleep` can be called with any duration from a context that can sleep,
+            // which is a requirement of the function.
+            unsafe { bindings::fsleep(sleep_us as _) };
+        } else {
+            // SAFETY: `cpu_relax` can be called from any context.
+            unsafe { bindings::cpu_relax() };
+        }
+    }
+}
+
+/// How [`IrqWait::wait`] waits.
+#[derive(Clone, Copy, Debug)]
+pub struct WaitConfig {
+    /// How long to sleep for the interrupt before the condition is polled anyway, in
+    /// microseconds.
+    ///
+    /// This bounds the latency when an interrupt is lost. Zero means the condition is only
+    /// checked when the interrupt fires and at the timeout.
+    pub poll_us: u64,
+
+    /// How long to wait in total, in microseconds.
+    pub timeout_us: u64,
+}
+
+/// A condition of the hardware that is signalled by an interrupt, with a polling fallback.
+///
+/// The interrupt handler calls [`IrqWait::signal`] when the hardware reports an event, e.g., the
+/// completion of a command, and the waiter checks the condition whenever it is woken up through
+/// [`IrqWait::wait`].
+///
+/// # Examples
+///
+/// ```
+/// use core::sync::atomic::{AtomicU32, Ordering};
+/// use kernel::{iopoll::{IrqWait, WaitConfig}, prelude::*};
+///
+/// #[pin_data]
+/// struct Mailbox {
+///     #[pin]
+///     done: IrqWait,
+///     // Stands in for a status register.
+///     status: AtomicU32,
+/// }
+///
+/// impl Mailbox {
+///     // Called from the interrupt handler.
+///     fn irq(&self) {
+///         self.done.signal();
+///     }
+///
+///     fn command(&self) -> Result<u32> {
+///         self.done.arm();
+///         // Write the command to the hardware.
+///         self.status.store(1, Ordering::Relaxed);
+///         self.done.wait(
+///             || match self.status.load(Ordering::Relaxed) {
+///                 0 => None,
+///                 s => Some(s),
+///             },
+///             &WaitConfig { poll_us: 1000, timeout_us: 100_000 },
+///         )
+///     }
+/// }
+///
+/// let mb = Box::pin_init(
+///     pin_init!(Mailbox { done <- IrqWait::new(), status: AtomicU32::new(0) }),
+///     GFP_KERNEL,
+/// )?;
+/// assert_eq!(mb.command(), Ok(1));
+/// # Ok::<(), Error>(())
+/// ```
+#[pin_data]
+pub struct IrqWait {
+    #[pin]
+    done: Completion,
+}
+
+impl IrqWait {
+    /// Creates a new, unsignalled waiter.
+    pub fn new() -> impl PinInit<Self> {
+        pin_init!(Self {
+            done <- Completion::new(),
+        })
+    }
+
+    /// Forgets earlier signals, before an operation whose completion is waited for is started.
+    pub fn arm(&self) {
+        self.done.reinit();
+    }
+
+    /// Wakes up the waiter.
+    ///
+    /// This can be called from interrupt context.
+    pub fn signal(&self) {
+        self.done.complete();
+    }
+
+    /// Waits until `check` returns a value, and returns it.
+    ///
+    /// `check` is called right away, whenever [`IrqWait::signal`] was called, every
+    /// [`WaitConfig::poll_us`] and a last time at the timeout. Fails with [`ETIMEDOUT`] if it did
+    /// not return a value within [`WaitConfig::timeout_us`].
+    ///
+    /// This must be called in a context that can sleep.
+    pub fn wait<T>(&self, mut check: impl FnMut() -> Option<T>, cfg: &WaitConfig) -> Result<T> {
+        let end = deadline(cfg.timeout_us);
+        let slice_us = if cfg.poll_us == 0 {
+            cfg.timeout_us
+        } else {
+            cfg.poll_us.min(cfg.timeout_us)
+        };
+        // SAFETY: The function can be called with any value.
+        let slice =
+            unsafe { bindings::__usecs_to_jiffies(slice_us.try_into().unwrap_or(u32::MAX)) };
+        loop {
+            if let Some(v) = check() {
+                return Ok(v);
+            }
+            if timekeeping::monotonic() > end {
+                return check().ok_or(ETIMEDOUT);
+            }
+            // Timeouts are handled through `end`.
+            let _ = self.done.wait_timeout(slice.max(1));
+        }
+    }
+}
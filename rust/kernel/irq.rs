@@ -0,0 +1,371 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Interrupts and interrupt handlers.
+//!
+//! C headers: [`include/linux/interrupt.h`](srctree/include/linux/interrupt.h) and
+//! [`include/linux/irq.h`](srctree/include/linux/irq.h)
+
+use crate::{
+    bindings,
+    cpumask::Cpumask,
+    error::{code::*, to_result, Result},
+    str::CStr,
+    types::ForeignOwnable,
+};
+use core::{ffi::c_void, fmt, marker::PhantomData, ptr::NonNull};
+
+#[cfg(CONFIG_IRQ_DOMAIN_HIERARCHY)]
+pub mod domain;
+
+/// Flags that can be passed to [`Registration::try_new`].
+pub mod flags {
+    /// Allow the interrupt line to be shared among several devices.
+    pub const SHARED: usize = bindings::IRQF_SHARED as _;
+
+    /// Keep the interrupt line disabled after the handler has run, until the threaded handler
+    /// re-enables it.
+    pub const ONESHOT: usize = bindings::IRQF_ONESHOT as _;
+
+    /// Exclude the interrupt from irq balancing.
+    pub const NOBALANCING: usize = bindings::IRQF_NOBALANCING as _;
+
+    /// The interrupt is per-CPU.
+    pub const PERCPU: usize = bindings::IRQF_PERCPU as _;
+
+    /// Do not disable the interrupt during suspend.
+    pub const NO_SUSPEND: usize = bindings::IRQF_NO_SUSPEND as _;
+
+    /// Do not enable the interrupt when it is requested; the driver enables it explicitly.
+    pub const NO_AUTOEN: usize = bindings::IRQF_NO_AUTOEN as _;
+
+    /// Run the handler in hard interrupt context even when interrupt handlers are forced into
+    /// threads, e.g., on `PREEMPT_RT`.
+    pub const NO_THREAD: usize = bindings::IRQF_NO_THREAD as _;
+
+    /// Trigger on the rising edge.
+    pub const TRIGGER_RISING: usize = bindings::IRQF_TRIGGER_RISING as _;
+
+    /// Trigger on the falling edge.
+    pub const TRIGGER_FALLING: usize = bindings::IRQF_TRIGGER_FALLING as _;
+
+    /// Trigger while the line is high.
+    pub const TRIGGER_HIGH: usize = bindings::IRQF_TRIGGER_HIGH as _;
+
+    /// Trigger while the line is low.
+    pub const TRIGGER_LOW: usize = bindings::IRQF_TRIGGER_LOW as _;
+}
+
+/// The value that can be returned from an interrupt handler.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Return {
+    /// The interrupt was not from this device or was not handled.
+    None = bindings::irqreturn_IRQ_NONE,
+
+    /// The interrupt was handled by this device.
+    Handled = bindings::irqreturn_IRQ_HANDLED,
+
+    /// The handler wants the handler thread to be woken up.
+    WakeThread = bindings::irqreturn_IRQ_WAKE_THREAD,
+}
+
+/// An interrupt handler.
+pub trait Handler {
+    /// The context data associated with and made available to the handler.
+    type Data: ForeignOwnable + Send + Sync;
+
+    /// Called from interrupt context when the interrupt fires.
+    ///
+    /// On `PREEMPT_RT`, this runs in a thread, so it may take a [`SpinLock`], unless the handler
+    /// was registered with [`flags::NO_THREAD`], [`flags::PERCPU`] or [`flags::ONESHOT`]. It then
+    /// runs in hard interrupt context on all configurations, and may only take a
+    /// [`RawSpinLock`].
+    ///
+    /// [`SpinLock`]: crate::sync::SpinLock
+    /// [`RawSpinLock`]: crate::sync::RawSpinLock
+    fn handle_irq(data: <Self::Data as ForeignOwnable>::Borrowed<'_>) -> Return;
+}
+
+/// A registration of an interrupt handler.
+///
+/// The handler is unregistered and its data freed when the registration is dropped.
+///
+/// # Examples
+///
+/// The following example binds the interrupt of each queue of a multi-queue device to a CPU
+/// close to the device:
+///
+/// ```
+/// use kernel::{c_str, cpumask::{self, Cpumask}, irq, prelude::*};
+///
+/// struct Queue;
+///
+/// impl irq::Handler for Queue {
+///     type Data = Box<Queue>;
+///
+///     fn handle_irq(_queue: &Queue) -> irq::Return {
+///         irq::Return::Handled
+///     }
+/// }
+///
+/// fn setup(irqs: &[u32], node: i32) -> Result<Vec<irq::Registration<Queue>>> {
+///     let mut regs = Vec::new();
+///     for (i, irq) in irqs.iter().enumerate() {
+///         let data = Box::new(Queue, GFP_KERNEL)?;
+///         let reg = irq::Registration::try_new(*irq, data, 0, c_str!("queue"))?;
+///         let cpu = cpumask::local_spread(i as u32, node);
+///         reg.set_affinity_and_hint(Cpumask::of(cpu).ok_or(EINVAL)?)?;
+///         regs.push(reg, GFP_KERNEL)?;
+///     }
+///     Ok(regs)
+/// }
+/// ```
+///
+/// # Invariants
+///
+/// `irq` was successfully requested with `data` as its cookie, and `data` was obtained from
+/// [`ForeignOwnable::into_foreign`] on a `H::Data`.
+pub struct Registration<H: Handler> {
+    irq: u32,
+    data: *const c_void,
+    _p: PhantomData<H>,
+}
+
+impl<H: Handler> Registration<H> {
+    /// Registers a new interrupt handler for `irq`.
+    ///
+    /// `flags` is a combination of the constants in [`flags`]. `name` is shown in
+    /// `/proc/interrupts`.
+    pub fn try_new(irq: u32, data: H::Data, flags: usize, name: &'static CStr) -> Result<Self> {
+        let ptr = data.into_foreign();
+        // SAFETY: `handler` and `ptr` remain valid until `free_irq` is called in `drop`, and
+        // `name` is a static NUL-terminated string.
+        let ret = unsafe {
+            bindings::request_irq(
+                irq,
+                Some(handler::<H>),
+                flags as _,
+                name.as_char_ptr(),
+                ptr as *mut c_void,
+            )
+        };
+        if let Err(e) = to_result(ret) {
+            // SAFETY: `ptr` came from `into_foreign` above and the handler was not registered,
+            // so nothing else refers to it.
+            drop(unsafe { H::Data::from_foreign(ptr) });
+            return Err(e);
+        }
+
+        // INVARIANT: The interrupt was requested successfully with `ptr` as its cookie.
+        Ok(Self {
+            irq,
+            data: ptr,
+            _p: PhantomData,
+        })
+    }
+
+    /// Returns the interrupt number.
+    pub fn irq(&self) -> u32 {
+        self.irq
+    }
+
+    /// Sets the affinity of the interrupt to `mask`.
+    ///
+    /// The mask is copied, so it does not need to outlive the call.
+    pub fn set_affinity(&self, mask: &Cpumask) -> Result {
+        // SAFETY: By the type invariant, `self.irq` is a requested interrupt, and `mask` is valid
+        // for the duration of the call.
+        to_result(unsafe { bindings::irq_set_affinity(self.irq, mask.as_raw()) })
+    }
+
+    /// Sets the affinity hint of the interrupt to `mask`, without changing its affinity.
+    ///
+    /// The hint is exposed in `/proc/irq/<irq>/affinity_hint` for userspace balancers to follow.
+    /// The C side keeps a reference to the mask, hence the `'static` lifetime; [`Cpumask::of`]
+    /// returns suitable masks.
+    pub fn update_affinity_hint(&self, mask: &'static Cpumask) -> Result {
+        // SAFETY: By the type invariant, `self.irq` is a requested interrupt, and `mask` is valid
+        // forever.
+        to_result(unsafe { bindings::irq_update_affinity_hint(self.irq, mask.as_raw()) })
+    }
+
+    /// Sets both the affinity hint and the affinity of the interrupt to `mask`.
+    ///
+    /// This is what multi-queue drivers typically use to bind each queue's interrupt to the
+    /// CPU that processes the queue.
+    pub fn set_affinity_and_hint(&self, mask: &'static Cpumask) -> Result {
+        // SAFETY: By the type invariant, `self.irq` is a requested interrupt, and `mask` is valid
+        // forever.
+        to_result(unsafe { bindings::irq_set_affinity_and_hint(self.irq, mask.as_raw()) })
+    }
+
+    /// Removes the affinity hint of the interrupt.
+    pub fn clear_affinity_hint(&self) -> Result {
+        // SAFETY: By the type invariant, `self.irq` is a requested interrupt. A null mask clears
+        // the hint.
+        to_result(unsafe { bindings::irq_update_affinity_hint(self.irq, core::ptr::null()) })
+    }
+}
+
+impl<H: Handler> Drop for Registration<H> {
+    fn drop(&mut self) {
+        // The C side warns when an interrupt with an affinity hint is freed, so clear it first.
+        // Failure is not possible for a requested interrupt.
+        let _ = self.clear_affinity_hint();
+
+        // SAFETY: By the type invariant, `self.irq` was requested with `self.data` as its cookie.
+        // `free_irq` waits for running handlers to complete.
+        unsafe { bindings::free_irq(self.irq, self.data as *mut c_void) };
+
+        // SAFETY: By the type invariant, `self.data` came from `into_foreign`, and the handler
+        // that used it has been unregistered above.
+        drop(unsafe { H::Data::from_foreign(self.data) });
+    }
+}
+
+impl<H: Handler> fmt::Debug for Registration<H> {
+    /// Formats the interrupt number and, if known, the hardware interrupt number and the name of
+    /// the interrupt controller, e.g., `Registration { irq: 42, hwirq: 0x61, chip: "GICv3" }`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("Registration");
+        d.field("irq", &self.irq);
+        // SAFETY: `irq_get_irq_data` can be called with any interrupt number.
+        let data = unsafe { bindings::irq_get_irq_data(self.irq) };
+        if !data.is_null() {
+            // SAFETY: By the type invariant, the interrupt is requested, so its data is valid, as
+            // is its chip if it has one.
+            let (hwirq, chip) = unsafe { ((*data).hwirq, (*data).chip) };
+            d.field("hwirq", &format_args!("{:#x}", hwirq));
+            // SAFETY: See above; the name of a chip is null or a valid C string.
+            if !chip.is_null() && !unsafe { (*chip).name }.is_null() {
+                // SAFETY: The name was just checked to be non-null.
+                d.field("chip", unsafe { &CStr::from_char_ptr((*chip).name) });
+            }
+        }
+        d.finish()
+    }
+}
+
+// SAFETY: The registration only holds a `H::Data`, which is `Send`, and the interrupt number.
+unsafe impl<H: Handler> Send for Registration<H> {}
+
+// SAFETY: All methods taking `&self` only call into the C irq core, which does its own locking.
+unsafe impl<H: Handler> Sync for Registration<H> {}
+
+unsafe extern "C" fn handler<H: Handler>(_irq: i32, data: *mut c_void) -> bindings::irqreturn_t {
+    // SAFETY: `data` is the cookie passed to `request_irq`, which came from `into_foreign`. It
+    // remains valid until `free_irq` returns, which only happens after all handlers complete.
+    let data = unsafe { H::Data::borrow(data) };
+    H::handle_irq(data) as _
+}
+
+/// Describes how interrupt vectors of a multi-queue device are spread across CPUs.
+///
+/// Vectors that are not used for queues (e.g. admin or configuration interrupts) are excluded
+/// from the spreading via `pre_vectors` and `post_vectors`. The remaining vectors get *managed*
+/// affinity: the irq core binds them to CPUs and shuts them down when all CPUs in their mask go
+/// offline.
+///
+/// Equivalent to the C `struct irq_affinity`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Affinity {
+    /// Number of vectors at the beginning that are not spread.
+    pub pre_vectors: u32,
+
+    /// Number of vectors at the end that are not spread.
+    pub post_vectors: u32,
+}
+
+impl Affinity {
+    fn to_raw(self) -> bindings::irq_affinity {
+        bindings::irq_affinity {
+            pre_vectors: self.pre_vectors,
+            post_vectors: self.post_vectors,
+            ..Default::default()
+        }
+    }
+
+    /// Returns how many vectors to allocate, between `min_vecs` and `max_vecs`, so that each
+    /// CPU gets a queue without wasting vectors.
+    pub fn calc_vectors(&self, min_vecs: u32, max_vecs: u32) -> u32 {
+        let affd = self.to_raw();
+        // SAFETY: `affd` is valid for the duration of the call.
+        unsafe { bindings::irq_calc_affinity_vectors(min_vecs, max_vecs, &affd) }
+    }
+
+    /// Computes the affinity masks for `nvecs` vectors.
+    pub fn create_masks(&self, nvecs: u32) -> Result<AffinityMasks> {
+        let mut affd = self.to_raw();
+        // SAFETY: `affd` is valid for the duration of the call.
+        let ptr = unsafe { bindings::irq_create_affinity_masks(nvecs, &mut affd) };
+        let ptr = NonNull::new(ptr).ok_or(ENOMEM)?;
+        // INVARIANT: `irq_create_affinity_masks` returned an array of `nvecs` descriptors.
+        Ok(AffinityMasks {
+            ptr,
+            len: nvecs as usize,
+        })
+    }
+}
+
+/// The per-vector affinity masks computed by [`Affinity::create_masks`].
+///
+/// # Invariants
+///
+/// `ptr` points to a `kmalloc`-ed array of `len` initialised descriptors.
+pub struct AffinityMasks {
+    ptr: NonNull<bindings::irq_affinity_desc>,
+    len: usize,
+}
+
+impl AffinityMasks {
+    /// Returns the number of vectors.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if there are no vectors.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the CPU mask of vector `index`.
+    pub fn mask(&self, index: usize) -> Option<&Cpumask> {
+        let desc = self.desc(index)?;
+        // SAFETY: The descriptor is valid while `self` is, and is only read from.
+        Some(unsafe { Cpumask::from_raw(&desc.mask) })
+    }
+
+    /// Returns `true` if vector `index` has managed affinity.
+    ///
+    /// Managed vectors are not spread by userspace balancers and are shut down by the irq core
+    /// when all the CPUs in their mask go offline.
+    pub fn is_managed(&self, index: usize) -> bool {
+        self.desc(index).map_or(false, |d| d.is_managed() != 0)
+    }
+
+    /// Returns a raw pointer to the descriptor array, e.g. to be passed to MSI allocation.
+    pub fn as_raw(&self) -> *const bindings::irq_affinity_desc {
+        self.ptr.as_ptr()
+    }
+
+    fn desc(&self, index: usize) -> Option<&bindings::irq_affinity_desc> {
+        if index >= self.len {
+            return None;
+        }
+        // SAFETY: By the type invariant, `index` is in bounds of the initialised array.
+        Some(unsafe { &*self.ptr.as_ptr().add(index) })
+    }
+}
+
+impl Drop for AffinityMasks {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariant, the array was allocated with `kmalloc`.
+        unsafe { bindings::kfree(self.ptr.as_ptr().cast()) };
+    }
+}
+
+// SAFETY: The descriptors are plain data owned by `AffinityMasks`.
+unsafe impl Send for AffinityMasks {}
+
+// SAFETY: `AffinityMasks` provides no interior mutability.
+unsafe impl Sync for AffinityMasks {}
@@ -0,0 +1,464 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Interrupt domains.
+//!
+//! Devices that multiplex several interrupt sources onto one interrupt line, e.g., the interrupt
+//! status register of a PMIC or of a GPIO block, expose the sources as interrupts of their own so
+//! that other drivers can request them like any other interrupt. The driver of such a device
+//! creates a [`Domain`] with a [`Chip`] that masks and unmasks the sources, allocates Linux
+//! interrupt numbers for the sources with [`Domain::alloc_irqs`], and dispatches the sources that
+//! are pending with [`Domain::handle`] from the handler of its own interrupt.
+//!
+//! C header: [`include/linux/irqdomain.h`](srctree/include/linux/irqdomain.h)
+
+use crate::{
+    bindings,
+    error::{code::*, from_result, to_result, Result, VTABLE_DEFAULT_ERROR},
+    fwnode::FwNode,
+    prelude::*,
+    str::CStr,
+    types::Opaque,
+};
+use core::{
+    ffi::{c_int, c_uint, c_void},
+    fmt,
+    ptr::NonNull,
+};
+
+/// The flow handler of the interrupts of a [`Domain`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flow {
+    /// The source stays pending until the device is serviced, so it is masked while the
+    /// handlers run.
+    Level,
+
+    /// The source is latched, and acknowledged with [`Chip::ack`] before the handlers run.
+    Edge,
+
+    /// The source needs no flow control, e.g., because the device clears it when its status
+    /// register is read.
+    Simple,
+}
+
+/// The operations on the interrupt sources of a [`Domain`].
+///
+/// The callbacks other than [`Chip::bus_lock`] and [`Chip::bus_sync_unlock`] run in atomic context,
+/// with the raw spinlock of the interrupt descriptor held, so they must not sleep. Chips behind a
+/// slow bus, e.g., I2C or SPI, only update a cached mask in [`Chip::mask`] and [`Chip::unmask`],
+/// and write it to the device in [`Chip::bus_sync_unlock`], which the irq core calls in process
+/// context after the descriptor is unlocked.
+#[vtable]
+pub trait Chip: Sync {
+    /// Data attached to each interrupt, e.g., the bit of the source in the status register.
+    type IrqData: Send + Sync;
+
+    /// The flow handler of the interrupts.
+    const FLOW: Flow = Flow::Level;
+
+    /// Masks the source `hwirq`, so that it does not trigger the interrupt of the device.
+    fn mask(&self, hwirq: u64, data: &Self::IrqData);
+
+    /// Unmasks the source `hwirq`.
+    fn unmask(&self, hwirq: u64, data: &Self::IrqData);
+
+    /// Acknowledges the source `hwirq`, for [`Flow::Edge`].
+    fn ack(&self, _hwirq: u64, _data: &Self::IrqData) {}
+
+    /// Locks the bus of the chip, before the irq core calls the other callbacks of an interrupt.
+    ///
+    /// This is called in process context and may sleep.
+    fn bus_lock(&self) {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+
+    /// Writes the state cached by the other callbacks to the device, and unlocks the bus of the
+    /// chip.
+    ///
+    /// This is called in process context and may sleep.
+    fn bus_sync_unlock(&self) {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+}
+
+struct Inner<T: Chip> {
+    chip_impl: T,
+    chip: Opaque<bindings::irq_chip>,
+    ops: bindings::irq_domain_ops,
+}
+
+struct Entry<D> {
+    hwirq: u64,
+    data: D,
+}
+
+/// An interrupt domain, which is removed when this object is dropped.
+///
+/// The domain is the root of its own hierarchy: its interrupts are dispatched by
+/// [`Domain::handle`], rather than by a parent domain.
+///
+/// # Invariants
+///
+/// `domain` was created with `irq_domain_create_hierarchy`, with the operations of `inner` and
+/// `inner` as its host data.
+///
+/// # Examples
+///
+/// ```
+/// use core::sync::atomic::{AtomicU32, Ordering};
+/// use kernel::{
+///     bindings, c_str,
+///     irq::domain::{Chip, Domain},
+///     prelude::*,
+/// };
+///
+/// struct Pmic {
+///     mask: AtomicU32,
+/// }
+///
+/// #[vtable]
+/// impl Chip for Pmic {
+///     type IrqData = ();
+///
+///     fn mask(&self, hwirq: u64, _data: &()) {
+///         self.mask.fetch_or(1 << hwirq, Ordering::Relaxed);
+///         // Write the mask register.
+///     }
+///
+///     fn unmask(&self, hwirq: u64, _data: &()) {
+///         self.mask.fetch_and(!(1 << hwirq), Ordering::Relaxed);
+///     }
+/// }
+///
+/// /// Called from the handler of the interrupt of the PMIC.
+/// fn demux(domain: &Domain<Pmic>, status: u32) {
+///     let pending = status & !domain.chip().mask.load(Ordering::Relaxed);
+///     for hwirq in (0..32).filter(|b| pending & (1 << b) != 0) {
+///         let _ = domain.handle(hwirq);
+///     }
+/// }
+///
+/// let pmic = Pmic { mask: AtomicU32::new(u32::MAX) };
+/// let domain = Domain::new(c_str!("pmic"), None, 32, pmic)?;
+/// // The power button and the charger are sources 3 and 7.
+/// let irqs = domain.alloc_irqs([(3, ()), (7, ())], bindings::NUMA_NO_NODE)?;
+/// pr_info!("power button: irq {}\n", irqs.irq(0).ok_or(EINVAL)?);
+/// demux(&domain, 1 << 3);
+/// # Ok::<(), Error>(())
+/// ```
+pub struct Domain<T: Chip> {
+    domain: NonNull<bindings::irq_domain>,
+    inner: Box<Inner<T>>,
+}
+
+struct AllocArg<D> {
+    entries: *const Entry<D>,
+}
+
+impl<T: Chip> Domain<T> {
+    /// Creates a domain named `name`, for `size` sources, whose interrupts are operated by `chip`.
+    ///
+    /// Sources are numbered from zero to `size - 1`. `fwnode` is the node of the device, if any,
+    /// which the domain is shown under in debugfs.
+    pub fn new(name: &'static CStr, fwnode: Option<&FwNode>, size: u32, chip: T) -> Result<Self> {
+        let raw_chip = bindings::irq_chip {
+            name: name.as_char_ptr(),
+            irq_mask: Some(Self::mask_callback),
+            irq_unmask: Some(Self::unmask_callback),
+            irq_ack: Some(Self::ack_callback),
+            irq_bus_lock: if T::HAS_BUS_LOCK {
+                Some(Self::bus_lock_callback)
+            } else {
+                None
+            },
+            irq_bus_sync_unlock: if T::HAS_BUS_SYNC_UNLOCK {
+                Some(Self::bus_sync_unlock_callback)
+            } else {
+                None
+            },
+            // SAFETY: The remaining callbacks are optional, and zero is valid for the flags.
+            ..unsafe { core::mem::zeroed() }
+        };
+        let ops = bindings::irq_domain_ops {
+            alloc: Some(Self::alloc_callback),
+            free: Some(bindings::irq_domain_free_irqs_common),
+            // SAFETY: The remaining callbacks are optional.
+            ..unsafe { core::mem::zeroed() }
+        };
+        let inner = Box::new(
+            Inner {
+                chip_impl: chip,
+                chip: Opaque::new(raw_chip),
+                ops,
+            },
+            GFP_KERNEL,
+        )?;
+        let fwnode = fwnode.map_or(core::ptr::null_mut(), FwNode::as_raw);
+        // SAFETY: The operations and the host data are boxed, so they do not move, and they live
+        // until the domain is removed in `drop`. `fwnode` is null or valid.
+        let domain = unsafe {
+            bindings::irq_domain_create_hierarchy(
+                core::ptr::null_mut(),
+                0,
+                size,
+                fwnode,
+                &inner.ops,
+                (&*inner as *const Inner<T>).cast_mut().cast(),
+            )
+        };
+        let domain = NonNull::new(domain).ok_or(ENOMEM)?;
+        // INVARIANT: The domain was just created with the operations and host data of `inner`.
+        Ok(Self { domain, inner })
+    }
+
+    /// Returns the chip.
+    pub fn chip(&self) -> &T {
+        &self.inner.chip_impl
+    }
+
+    /// Returns a raw pointer to the underlying `struct irq_domain`.
+    pub fn as_raw(&self) -> *mut bindings::irq_domain {
+        self.domain.as_ptr()
+    }
+
+    /// Allocates consecutive interrupt numbers for the sources of `irqs`, each with its data.
+    ///
+    /// The interrupts are freed when the returned object is dropped. `node` is the NUMA node of
+    /// the interrupt descriptors, or [`bindings::NUMA_NO_NODE`].
+    pub fn alloc_irqs(
+        &self,
+        irqs: impl IntoIterator<Item = (u64, T::IrqData)>,
+        node: i32,
+    ) -> Result<Irqs<'_, T>> {
+        let irqs = irqs.into_iter();
+        let mut entries = Vec::with_capacity(irqs.size_hint().0, GFP_KERNEL)?;
+        for (hwirq, data) in irqs {
+            entries.push(Entry { hwirq, data }, GFP_KERNEL)?;
+        }
+        let nr: c_uint = entries.len().try_into()?;
+        if nr == 0 {
+            return Err(EINVAL);
+        }
+        let mut arg = AllocArg {
+            entries: entries.as_ptr(),
+        };
+        // SAFETY: The domain is valid, and `arg` and the entries it points to outlive the call, in
+        // which `alloc_callback` sets the interrupts up. The entries are kept in the returned
+        // object, so the handler data of the interrupts lives until they are freed.
+        let virq = unsafe {
+            bindings::__irq_domain_alloc_irqs(
+                self.as_raw(),
+                -1,
+                nr,
+                node,
+                (&mut arg as *mut AllocArg<T::IrqData>).cast(),
+                false,
+                core::ptr::null(),
+            )
+        };
+        to_result(virq)?;
+        Ok(Irqs {
+            _domain: self,
+            virq: virq as u32,
+            entries,
+        })
+    }
+
+    /// Runs the handlers of the interrupt of source `hwirq`.
+    ///
+    /// This must be called from the interrupt handler of the device. It fails with [`EINVAL`] if
+    /// `hwirq` has no interrupt allocated.
+    pub fn handle(&self, hwirq: u64) -> Result {
+        // SAFETY: The domain is valid.
+        to_result(unsafe { bindings::generic_handle_domain_irq(self.as_raw(), hwirq as _) })
+    }
+
+    unsafe extern "C" fn alloc_callback(
+        domain: *mut bindings::irq_domain,
+        virq: c_uint,
+        nr_irqs: c_uint,
+        arg: *mut c_void,
+    ) -> c_int {
+        from_result(|| {
+            // SAFETY: By the type invariants, the host data is the `Inner<T>` of a live
+            // `Domain<T>`, and interrupts are only allocated by `alloc_irqs`, which passes an
+            // `AllocArg` pointing to `nr_irqs` entries.
+            let (inner, entries) = unsafe {
+                (
+                    &*(*domain).host_data.cast::<Inner<T>>(),
+                    core::slice::from_raw_parts(
+                        (*arg.cast::<AllocArg<T::IrqData>>()).entries,
+                        nr_irqs as usize,
+                    ),
+                )
+            };
+            let flow: unsafe extern "C" fn(*mut bindings::irq_desc) = match T::FLOW {
+                Flow::Level => bindings::handle_level_irq,
+                Flow::Edge => bindings::handle_edge_irq,
+                Flow::Simple => bindings::handle_simple_irq,
+            };
+            for (i, e) in (0..).zip(entries) {
+                // SAFETY: `virq + i` was just allocated in `domain`. The chip and its data are
+                // the boxed `inner`, and the handler data is the entry, which both outlive the
+                // interrupt.
+                unsafe {
+                    bindings::irq_domain_set_info(
+                        domain,
+                        virq + i,
+                        e.hwirq as _,
+                        inner.chip.get(),
+                        (inner as *const Inner<T>).cast_mut().cast(),
+                        Some(flow),
+                        (&e.data as *const T::IrqData).cast_mut().cast(),
+                        core::ptr::null(),
+                    )
+                };
+            }
+            Ok(0)
+        })
+    }
+
+    /// # Safety
+    ///
+    /// `d` must be the data of an interrupt of a live `Domain<T>`.
+    unsafe fn chip_and_data<'a>(d: *mut bindings::irq_data) -> (&'a T, u64, &'a T::IrqData) {
+        // SAFETY: By the safety requirements, `d` is valid, and `alloc_callback` set its chip
+        // data to the `Inner<T>` of the domain and its handler data to its entry, which live
+        // until the interrupt is freed.
+        unsafe {
+            let inner = &*bindings::irq_data_get_irq_chip_data(d).cast::<Inner<T>>();
+            let data = &*bindings::irq_data_get_irq_handler_data(d).cast::<T::IrqData>();
+            (&inner.chip_impl, (*d).hwirq as u64, data)
+        }
+    }
+
+    unsafe extern "C" fn mask_callback(d: *mut bindings::irq_data) {
+        // SAFETY: The chip is only used for the interrupts of the domain.
+        let (chip, hwirq, data) = unsafe { Self::chip_and_data(d) };
+        chip.mask(hwirq, data);
+    }
+
+    unsafe extern "C" fn unmask_callback(d: *mut bindings::irq_data) {
+        // SAFETY: The chip is only used for the interrupts of the domain.
+        let (chip, hwirq, data) = unsafe { Self::chip_and_data(d) };
+        chip.unmask(hwirq, data);
+    }
+
+    unsafe extern "C" fn ack_callback(d: *mut bindings::irq_data) {
+        // SAFETY: The chip is only used for the interrupts of the domain.
+        let (chip, hwirq, data) = unsafe { Self::chip_and_data(d) };
+        chip.ack(hwirq, data);
+    }
+
+    unsafe extern "C" fn bus_lock_callback(d: *mut bindings::irq_data) {
+        // SAFETY: The chip is only used for the interrupts of the domain.
+        let (chip, _, _) = unsafe { Self::chip_and_data(d) };
+        chip.bus_lock();
+    }
+
+    unsafe extern "C" fn bus_sync_unlock_callback(d: *mut bindings::irq_data) {
+        // SAFETY: The chip is only used for the interrupts of the domain.
+        let (chip, _, _) = unsafe { Self::chip_and_data(d) };
+        chip.bus_sync_unlock();
+    }
+}
+
+impl<T: Chip> Drop for Domain<T> {
+    fn drop(&mut self) {
+        // Interrupts whose `Irqs` was leaked, e.g., with `mem::forget`, are still mapped and use
+        // `inner` as their chip data, so they are freed before `inner` is. Their entries were
+        // leaked with them, so their handler data stays valid.
+        // SAFETY: By the type invariants, the domain is valid.
+        let size = unsafe { (*self.as_raw()).hwirq_max };
+        for hwirq in 0..size {
+            // SAFETY: The domain is valid.
+            let virq = unsafe { bindings::irq_find_mapping(self.as_raw(), hwirq) };
+            if virq != 0 {
+                // SAFETY: `virq` is mapped in the domain, and no `Irqs` owns it anymore, since
+                // they borrow `self`.
+                unsafe { bindings::irq_domain_free_irqs(virq, 1) };
+            }
+        }
+        // SAFETY: By the type invariants, the domain was created by `new`, and all its interrupts
+        // were freed above or by their `Irqs`.
+        unsafe { bindings::irq_domain_remove(self.as_raw()) };
+    }
+}
+
+impl<T: Chip> fmt::Debug for Domain<T> {
+    /// Formats the name of the domain and its number of sources.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // SAFETY: By the type invariants, the domain is valid. Its name is set by the irq core
+        // when it is created, and freed when it is removed.
+        let (name, size) = unsafe { ((*self.as_raw()).name, (*self.as_raw()).hwirq_max) };
+        let mut d = f.debug_struct("Domain");
+        if !name.is_null() {
+            // SAFETY: The name was just checked to be non-null, and is a valid C string.
+            d.field("name", unsafe { &CStr::from_char_ptr(name) });
+        }
+        d.field("size", &size).finish()
+    }
+}
+
+// SAFETY: The domain can be removed from any thread, and the chip is `Sync`.
+unsafe impl<T: Chip + Send> Send for Domain<T> {}
+
+// SAFETY: The methods that take `&self` can be called concurrently, and the chip is `Sync`.
+unsafe impl<T: Chip> Sync for Domain<T> {}
+
+/// Interrupts allocated in a [`Domain`], which are freed when this object is dropped.
+///
+/// As in C, the drivers that requested the interrupts must have freed them by then, e.g.,
+/// because the child devices they are bound to were removed first.
+///
+/// # Invariants
+///
+/// The interrupts from `virq` to `virq + entries.len() - 1` were allocated in the domain, with
+/// `entries` as their handler data.
+pub struct Irqs<'a, T: Chip> {
+    _domain: &'a Domain<T>,
+    virq: u32,
+    entries: Vec<Entry<T::IrqData>>,
+}
+
+impl<T: Chip> Irqs<'_, T> {
+    /// Returns the Linux interrupt number of the `i`-th source passed to [`Domain::alloc_irqs`].
+    ///
+    /// Other drivers request the interrupt with this number, e.g., with
+    /// [`Registration`](super::Registration).
+    pub fn irq(&self, i: usize) -> Option<u32> {
+        (i < self.entries.len()).then(|| self.virq + i as u32)
+    }
+
+    /// Returns the number of interrupts.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if there are no interrupts.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the data of the `i`-th interrupt.
+    pub fn data(&self, i: usize) -> Option<&T::IrqData> {
+        self.entries.get(i).map(|e| &e.data)
+    }
+}
+
+impl<T: Chip> fmt::Debug for Irqs<'_, T> {
+    /// Formats the range of Linux interrupt numbers, e.g., `Irqs { irqs: 40..42 }`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let end = self.virq + self.entries.len() as u32;
+        f.debug_struct("Irqs")
+            .field("irqs", &(self.virq..end))
+            .finish()
+    }
+}
+
+impl<T: Chip> Drop for Irqs<'_, T> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, the interrupts were allocated in the domain. Their
+        // handlers were freed, so the entries are not used anymore once they are freed.
+        unsafe { bindings::irq_domain_free_irqs(self.virq, self.entries.len() as c_uint) };
+    }
+}
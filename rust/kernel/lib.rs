@@ -31,36 +31,97 @@
 
 pub mod alloc;
 pub mod bits;
+#[cfg(CONFIG_BLOCK)]
+pub mod block;
 mod build_assert;
+#[cfg(CONFIG_COMMON_CLK)]
+pub mod clk;
+pub mod cmdq;
+pub mod cpu;
+pub mod cpumask;
+#[cfg(CONFIG_DEBUG_FS)]
+pub mod debugfs;
+pub mod deferred_free;
 pub mod device;
+#[cfg(CONFIG_DIMLIB)]
+pub mod dim;
+pub mod dma;
 pub mod driver;
+#[cfg(CONFIG_ENERGY_MODEL)]
+pub mod energy_model;
 pub mod error;
+#[cfg(CONFIG_EVENTFD)]
+pub mod eventfd;
+#[cfg(CONFIG_FW_LOADER)]
+pub mod firmware;
+#[cfg(CONFIG_FPGA)]
+pub mod fpga;
+pub mod fwnode;
+#[cfg(CONFIG_GPIOLIB)]
+pub mod gpio;
 #[cfg(any(CONFIG_I2C, doc))]
 #[doc(cfg(CONFIG_I2C))]
 pub mod i2c;
+#[cfg(CONFIG_I3C)]
+pub mod i3c;
 pub mod init;
+pub mod initcall;
+#[cfg(CONFIG_INTERVAL_TREE)]
+pub mod interval_tree;
+pub mod io_mem;
 pub mod ioctl;
+#[cfg(CONFIG_IOMMU_API)]
+pub mod iommu;
+pub mod iopoll;
+pub mod irq;
 #[cfg(CONFIG_KUNIT)]
 pub mod kunit;
+pub mod mm;
+#[cfg(CONFIG_KUNIT)]
+pub mod mock;
 #[cfg(CONFIG_NET)]
 pub mod net;
 pub mod of;
+pub mod overflow;
+pub mod params;
+#[cfg(any(CONFIG_NVMEM, CONFIG_EFI))]
+pub mod persist;
 pub mod platform;
+pub mod pm;
 pub mod prelude;
 pub mod print;
+#[cfg(CONFIG_PTP_1588_CLOCK)]
+pub mod ptp;
 #[cfg(CONFIG_REGMAP)]
 pub mod regmap;
 #[cfg(CONFIG_REGULATOR)]
 pub mod regulator;
+#[cfg(CONFIG_RESET_CONTROLLER)]
+pub mod reset;
 pub mod revocable;
+#[cfg(CONFIG_MMU)]
+pub mod shared_ring;
+pub mod sizes;
+#[cfg(CONFIG_SPMI)]
+pub mod spmi;
 mod static_assert;
+pub mod stats;
 #[doc(hidden)]
 pub mod std_vendor;
 pub mod str;
 pub mod sync;
 pub mod task;
 pub mod time;
+#[cfg(all(CONFIG_RING_BUFFER, CONFIG_DEBUG_FS))]
+pub mod trace_buffer;
 pub mod types;
+pub mod units;
+#[cfg(CONFIG_VFIO)]
+pub mod vfio;
+#[cfg(CONFIG_WATCH_QUEUE)]
+pub mod watch_queue;
+#[cfg(CONFIG_WATCHDOG_CORE)]
+pub mod watchdog;
 pub mod workqueue;
 
 #[doc(hidden)]
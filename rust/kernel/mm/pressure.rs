@@ -0,0 +1,171 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Memory pressure notifications.
+//!
+//! Drivers with large discretionary allocations, e.g., caches of decoded frames or of buffers
+//! that can be recreated, can give memory back when the system runs low on it, e.g., by dropping
+//! cached buffers or reducing a queue depth. A [`Monitor`] reports the pressure to a [`Handler`]
+//! in three [`Level`]s, following the levels of the memory cgroup `vmpressure` notifications:
+//! background reclaim, reclaim done by allocating tasks, and out of memory.
+//!
+//! C header: [`include/linux/oom.h`](srctree/include/linux/oom.h)
+
+use super::shrinker::{self, ShrinkControl, Shrinker};
+use crate::{bindings, error::to_result, prelude::*, str::CStr, types::Opaque};
+use core::ffi::{c_int, c_ulong, c_void};
+
+/// A level of memory pressure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Memory is reclaimed in the background, by `kswapd`.
+    ///
+    /// This is the normal operation of the system, and a good time to drop caches that are
+    /// cheap to recreate.
+    Low,
+
+    /// Allocating tasks reclaim memory themselves, so allocations are slowed down.
+    Medium,
+
+    /// The system is out of memory, and is about to kill a task to free some.
+    Critical,
+}
+
+/// The callbacks of a [`Monitor`].
+pub trait Handler: Sync {
+    /// Returns the number of pages the handler could free.
+    ///
+    /// This is called often, so it should be cheap, and must not sleep on locks.
+    fn reclaimable(&self) -> usize;
+
+    /// Frees memory under pressure `level`, and returns the number of pages freed.
+    ///
+    /// At [`Level::Low`] and [`Level::Medium`], the handler should free about `target` pages. At
+    /// [`Level::Critical`], `target` is [`usize::MAX`] and the handler should free all it can, as
+    /// the alternative is killing a task; it is called from the allocation path that ran out of
+    /// memory, so it must not allocate memory or wait for anything that may.
+    fn pressure(&self, level: Level, target: usize) -> usize;
+}
+
+struct Adapter<T: Handler> {
+    handler: T,
+    oom_nb: Opaque<bindings::notifier_block>,
+}
+
+// SAFETY: The notifier block is only accessed by the OOM notifier chain, and the handler is
+// `Sync`.
+unsafe impl<T: Handler> Sync for Adapter<T> {}
+
+// SAFETY: The notifier block can be unregistered from any thread.
+unsafe impl<T: Handler + Send> Send for Adapter<T> {}
+
+impl<T: Handler> shrinker::Handler for Adapter<T> {
+    fn count(&self, _sc: &ShrinkControl<'_>) -> usize {
+        self.handler.reclaimable()
+    }
+
+    fn scan(&self, sc: &mut ShrinkControl<'_>) -> Option<usize> {
+        // SAFETY: `current_is_kswapd` can be called from any context.
+        let level = if unsafe { bindings::current_is_kswapd() } {
+            Level::Low
+        } else {
+            Level::Medium
+        };
+        Some(self.handler.pressure(level, sc.nr_to_scan()))
+    }
+}
+
+/// A registration of a [`Handler`] of memory pressure, which is unregistered when this object is
+/// dropped.
+///
+/// # Invariants
+///
+/// The OOM notifier block of the handler of `shrinker` is registered with
+/// `register_oom_notifier`.
+///
+/// # Examples
+///
+/// ```
+/// use core::sync::atomic::{AtomicUsize, Ordering};
+/// use kernel::{
+///     c_str,
+///     mm::pressure::{Handler, Level, Monitor},
+///     prelude::*,
+/// };
+///
+/// struct FrameCache {
+///     cached_pages: AtomicUsize,
+/// }
+///
+/// impl Handler for FrameCache {
+///     fn reclaimable(&self) -> usize {
+///         self.cached_pages.load(Ordering::Relaxed)
+///     }
+///
+///     fn pressure(&self, level: Level, target: usize) -> usize {
+///         if level == Level::Critical {
+///             pr_warn!("out of memory, dropping all cached frames\n");
+///         }
+///         let n = target.min(self.cached_pages.load(Ordering::Relaxed));
+///         // Free `n` pages of cached frames.
+///         self.cached_pages.fetch_sub(n, Ordering::Relaxed);
+///         n
+///     }
+/// }
+///
+/// let cache = FrameCache { cached_pages: AtomicUsize::new(0) };
+/// let monitor = Monitor::new(c_str!("my-frame-cache"), cache)?;
+/// monitor.handler().cached_pages.fetch_add(256, Ordering::Relaxed);
+/// # Ok::<(), Error>(())
+/// ```
+pub struct Monitor<T: Handler> {
+    shrinker: Shrinker<Adapter<T>>,
+}
+
+impl<T: Handler> Monitor<T> {
+    /// Registers `handler` for memory pressure notifications, under `name` in debugfs.
+    pub fn new(name: &CStr, handler: T) -> Result<Self> {
+        let adapter = Adapter {
+            handler,
+            // SAFETY: All zeroes is a valid value of a notifier block, it is set up below.
+            oom_nb: Opaque::new(unsafe { core::mem::zeroed() }),
+        };
+        let shrinker = Shrinker::new(name, adapter)?;
+        let nb = shrinker.handler().oom_nb.get();
+        // SAFETY: The notifier block is boxed in the shrinker, so it does not move, and it is
+        // unregistered in `drop` before the shrinker is freed. It is not registered yet, so it
+        // can be written.
+        unsafe {
+            (*nb).notifier_call = Some(Self::oom_callback);
+            to_result(bindings::register_oom_notifier(nb))?;
+        }
+        // INVARIANT: The notifier block was just registered.
+        Ok(Self { shrinker })
+    }
+
+    /// Returns the handler.
+    pub fn handler(&self) -> &T {
+        &self.shrinker.handler().handler
+    }
+
+    unsafe extern "C" fn oom_callback(
+        nb: *mut bindings::notifier_block,
+        _action: c_ulong,
+        freed: *mut c_void,
+    ) -> c_int {
+        // SAFETY: `nb` is the `oom_nb` field of the adapter of a live `Monitor<T>`, since it is
+        // unregistered before the adapter is freed.
+        let adapter = unsafe { &*crate::container_of!(nb, Adapter<T>, oom_nb) };
+        let n = adapter.handler.pressure(Level::Critical, usize::MAX);
+        // SAFETY: The OOM notifier chain is called with a pointer to the number of pages freed.
+        unsafe { *freed.cast::<c_ulong>() += n as c_ulong };
+        bindings::NOTIFY_OK as c_int
+    }
+}
+
+impl<T: Handler> Drop for Monitor<T> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, the notifier block is registered. Unregistering waits
+        // for the running callbacks, so the adapter can be freed afterwards, with the shrinker.
+        unsafe { bindings::unregister_oom_notifier(self.shrinker.handler().oom_nb.get()) };
+    }
+}
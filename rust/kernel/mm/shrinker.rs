@@ -0,0 +1,201 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Shrinkers.
+//!
+//! Caches that can give memory back, e.g., pools of pages or of objects, register a [`Shrinker`].
+//! Under memory pressure, the memory management asks each shrinker how many objects it could
+//! free, and then to free some of them, in proportion to the reclaim work being done.
+//!
+//! C header: [`include/linux/shrinker.h`](srctree/include/linux/shrinker.h)
+
+use crate::{bindings, c_str, prelude::*, str::CStr};
+use core::{ffi::c_ulong, ptr::NonNull};
+
+/// The context of a call to a [`Handler`].
+pub struct ShrinkControl<'a>(&'a mut bindings::shrink_control);
+
+impl ShrinkControl<'_> {
+    /// Returns the number of objects to free in a call to [`Handler::scan`].
+    pub fn nr_to_scan(&self) -> usize {
+        self.0.nr_to_scan as usize
+    }
+
+    /// Returns the NUMA node whose memory is reclaimed.
+    ///
+    /// This is only meaningful for shrinkers with [`Handler::NUMA_AWARE`], which are called once
+    /// per node; it is zero otherwise.
+    pub fn nid(&self) -> i32 {
+        self.0.nid
+    }
+
+    /// Returns whether the reclaim may recurse into filesystems, e.g., to write pages back.
+    ///
+    /// This is `false` for reclaims triggered by `GFP_NOFS` allocations, which may already hold
+    /// filesystem locks.
+    pub fn may_enter_fs(&self) -> bool {
+        self.0.gfp_mask & bindings::__GFP_FS != 0
+    }
+
+    /// Returns whether the reclaim may start I/O.
+    ///
+    /// This is `false` for reclaims triggered by `GFP_NOIO` allocations, e.g., in block drivers.
+    pub fn may_do_io(&self) -> bool {
+        self.0.gfp_mask & bindings::__GFP_IO != 0
+    }
+
+    /// Reports that `n` objects were scanned, when it differs from [`ShrinkControl::nr_to_scan`].
+    pub fn set_nr_scanned(&mut self, n: usize) {
+        self.0.nr_scanned = n as c_ulong;
+    }
+}
+
+/// The callbacks of a [`Shrinker`].
+pub trait Handler: Sync {
+    /// Whether the objects are freed per NUMA node.
+    ///
+    /// If `true`, the callbacks are called once for every node under pressure, with the node in
+    /// [`ShrinkControl::nid`], and should only count and free the objects of that node.
+    const NUMA_AWARE: bool = false;
+
+    /// Returns the number of objects that could be freed.
+    ///
+    /// This is called often, so it should be cheap, e.g., read a counter that may be slightly
+    /// stale, and must not sleep on locks.
+    fn count(&self, sc: &ShrinkControl<'_>) -> usize;
+
+    /// Frees up to [`ShrinkControl::nr_to_scan`] objects, and returns how many were freed.
+    ///
+    /// Returns [`None`] if no progress can be made right now, e.g., because of the allocation
+    /// flags or a contended lock, so that the memory management stops calling this for the
+    /// current reclaim.
+    fn scan(&self, sc: &mut ShrinkControl<'_>) -> Option<usize>;
+}
+
+/// A registered [`Handler`], which is unregistered when this object is dropped.
+///
+/// # Invariants
+///
+/// `shrinker` was allocated with `shrinker_alloc` and registered with `shrinker_register`, and
+/// its private data points to `handler`.
+///
+/// # Examples
+///
+/// ```
+/// use core::sync::atomic::{AtomicUsize, Ordering};
+/// use kernel::{c_str, mm::shrinker::{Handler, ShrinkControl, Shrinker}};
+///
+/// struct PagePool {
+///     free_pages: AtomicUsize,
+/// }
+///
+/// impl Handler for PagePool {
+///     fn count(&self, _sc: &ShrinkControl<'_>) -> usize {
+///         self.free_pages.load(Ordering::Relaxed)
+///     }
+///
+///     fn scan(&self, sc: &mut ShrinkControl<'_>) -> Option<usize> {
+///         let n = sc.nr_to_scan().min(self.free_pages.load(Ordering::Relaxed));
+///         // Give `n` pages back to the page allocator.
+///         self.free_pages.fetch_sub(n, Ordering::Relaxed);
+///         Some(n)
+///     }
+/// }
+///
+/// let pool = PagePool { free_pages: AtomicUsize::new(0) };
+/// let shrinker = Shrinker::new(c_str!("my-page-pool"), pool)?;
+/// shrinker.handler().free_pages.fetch_add(16, Ordering::Relaxed);
+/// # Ok::<(), Error>(())
+/// ```
+pub struct Shrinker<T: Handler> {
+    shrinker: NonNull<bindings::shrinker>,
+    handler: Box<T>,
+}
+
+impl<T: Handler> Shrinker<T> {
+    /// Registers `handler` as a shrinker named `name`, as shown in debugfs.
+    pub fn new(name: &CStr, handler: T) -> Result<Self> {
+        let handler = Box::new(handler, GFP_KERNEL)?;
+        let flags = if T::NUMA_AWARE {
+            bindings::SHRINKER_NUMA_AWARE
+        } else {
+            0
+        };
+        // SAFETY: The format string takes one C string, which `name` is.
+        let shrinker = unsafe {
+            bindings::shrinker_alloc(flags, c_str!("%s").as_char_ptr(), name.as_char_ptr())
+        };
+        let shrinker = NonNull::new(shrinker).ok_or(ENOMEM)?;
+        let raw = shrinker.as_ptr();
+        // SAFETY: `raw` was just allocated and is not registered yet, so it can be set up. The
+        // handler is boxed, so it does not move while the shrinker is registered.
+        unsafe {
+            (*raw).count_objects = Some(Self::count_callback);
+            (*raw).scan_objects = Some(Self::scan_callback);
+            (*raw).private_data = (&*handler as *const T).cast_mut().cast();
+            bindings::shrinker_register(raw);
+        }
+        // INVARIANT: The shrinker was allocated and registered above, with the handler as its
+        // private data.
+        Ok(Self { shrinker, handler })
+    }
+
+    /// Returns the handler.
+    pub fn handler(&self) -> &T {
+        &self.handler
+    }
+
+    /// # Safety
+    ///
+    /// `shrinker` must be the shrinker of a live [`Shrinker<T>`], and `sc` must be valid.
+    unsafe fn handler_and_control<'a>(
+        shrinker: *mut bindings::shrinker,
+        sc: *mut bindings::shrink_control,
+    ) -> (&'a T, ShrinkControl<'a>) {
+        // SAFETY: By the safety requirements and the type invariants, the private data points to
+        // the handler, which lives until the shrinker is freed, and `sc` is valid for the call.
+        unsafe {
+            (
+                &*(*shrinker).private_data.cast::<T>(),
+                ShrinkControl(&mut *sc),
+            )
+        }
+    }
+
+    unsafe extern "C" fn count_callback(
+        shrinker: *mut bindings::shrinker,
+        sc: *mut bindings::shrink_control,
+    ) -> c_ulong {
+        // SAFETY: The callback is only called for registered shrinkers, with a valid context.
+        let (handler, sc) = unsafe { Self::handler_and_control(shrinker, sc) };
+        match handler.count(&sc) {
+            0 => bindings::SHRINK_EMPTY as c_ulong,
+            n => n as c_ulong,
+        }
+    }
+
+    unsafe extern "C" fn scan_callback(
+        shrinker: *mut bindings::shrinker,
+        sc: *mut bindings::shrink_control,
+    ) -> c_ulong {
+        // SAFETY: The callback is only called for registered shrinkers, with a valid context.
+        let (handler, mut sc) = unsafe { Self::handler_and_control(shrinker, sc) };
+        match handler.scan(&mut sc) {
+            Some(n) => n as c_ulong,
+            None => bindings::SHRINK_STOP as c_ulong,
+        }
+    }
+}
+
+impl<T: Handler> Drop for Shrinker<T> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, the shrinker is registered. Freeing it waits for the
+        // running callbacks, so the handler can be dropped afterwards.
+        unsafe { bindings::shrinker_free(self.shrinker.as_ptr()) };
+    }
+}
+
+// SAFETY: The shrinker can be freed from any thread, and the handler is `Sync`.
+unsafe impl<T: Handler + Send> Send for Shrinker<T> {}
+
+// SAFETY: Only shared access to the handler is given out, which is `Sync`.
+unsafe impl<T: Handler> Sync for Shrinker<T> {}
@@ -0,0 +1,283 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Software models of devices, for tests.
+//!
+//! Driver code that accesses its device through the [`Io`], [`RegAccess`] or [`Smbus`] traits
+//! can be run against a [`Mock`] instead, in a KUnit test. The mock forwards the accesses to a
+//! [`Model`] of the device and records them, so that the test can check the exact sequence of
+//! accesses, e.g., that an initialisation sequence is not reordered or a write is not dropped by
+//! a refactoring, before the driver runs on the hardware.
+//!
+//! [`Io`]: crate::io_mem::Io
+//! [`RegAccess`]: crate::regmap::RegAccess
+//! [`Smbus`]: crate::i2c::Smbus
+
+use crate::{io_mem::Io, prelude::*};
+use core::cell::{RefCell, RefMut};
+
+#[cfg(CONFIG_I2C)]
+use crate::i2c::{Smbus, SMBUS_BLOCK_MAX};
+#[cfg(CONFIG_REGMAP)]
+use crate::regmap::RegAccess;
+
+/// An access made through a [`Mock`]: the address, i.e., the offset, register or SMBus command,
+/// and the value read or written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    /// A read.
+    Read(usize, u64),
+    /// A write.
+    Write(usize, u64),
+}
+
+/// The behaviour of a device, as seen by its driver.
+///
+/// Models with side effects, e.g., a status bit that is set once a reset completes, or a FIFO
+/// register, implement this directly; [`RegFile`] covers devices whose registers simply hold
+/// what is written to them.
+pub trait Model {
+    /// Returns the value of the `width`-byte register at `addr`.
+    fn read(&mut self, addr: usize, width: usize) -> Result<u64>;
+
+    /// Writes `value` to the `width`-byte register at `addr`.
+    fn write(&mut self, addr: usize, width: usize, value: u64) -> Result;
+}
+
+/// A model whose registers hold the last value written to them, or zero.
+///
+/// Each register is identified by its address only, so accesses of different widths to
+/// overlapping registers are not modelled.
+#[derive(Default)]
+pub struct RegFile {
+    regs: Vec<(usize, u64)>,
+}
+
+impl RegFile {
+    /// Creates a model with all registers zeroed.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the value of the register at `addr`.
+    pub fn get(&self, addr: usize) -> u64 {
+        self.regs
+            .iter()
+            .find(|(a, _)| *a == addr)
+            .map_or(0, |(_, v)| *v)
+    }
+
+    /// Sets the register at `addr` to `value`, e.g., to give it its reset value.
+    pub fn set(&mut self, addr: usize, value: u64) -> Result {
+        match self.regs.iter_mut().find(|(a, _)| *a == addr) {
+            Some((_, v)) => *v = value,
+            None => self.regs.push((addr, value), GFP_KERNEL)?,
+        }
+        Ok(())
+    }
+}
+
+impl Model for RegFile {
+    fn read(&mut self, addr: usize, width: usize) -> Result<u64> {
+        Ok(self.get(addr) & mask(width))
+    }
+
+    fn write(&mut self, addr: usize, width: usize, value: u64) -> Result {
+        self.set(addr, value & mask(width))
+    }
+}
+
+/// Returns the mask of the bits of a `width`-byte register.
+fn mask(width: usize) -> u64 {
+    match width {
+        8.. => u64::MAX,
+        w => (1u64 << (w * 8)) - 1,
+    }
+}
+
+/// A device model that records the accesses made to it.
+///
+/// The model has `size` bytes of address space: accesses beyond it fail with [`EINVAL`], as do
+/// unaligned accesses through [`Io`]. Failed accesses are not recorded.
+///
+/// This is not [`Sync`]: the code under test must access it from a single thread.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{
+///     io_mem::Io,
+///     mock::{Access, Mock, RegFile},
+///     prelude::*,
+/// };
+///
+/// const CTRL: usize = 0x0;
+/// const DIV: usize = 0x4;
+///
+/// /// The clock must be gated while the divider is changed.
+/// fn set_divider(regs: &impl Io, div: u32) -> Result {
+///     regs.try_update_bits32(CTRL, 1, 0)?;
+///     regs.try_writel(div, DIV)?;
+///     regs.try_update_bits32(CTRL, 1, 1)
+/// }
+///
+/// let mut model = RegFile::new();
+/// model.set(CTRL, 0x11)?;
+/// let regs = Mock::new(model, 0x10);
+/// set_divider(&regs, 4)?;
+/// assert_eq!(
+///     regs.take_log(),
+///     [
+///         Access::Read(CTRL, 0x11),
+///         Access::Write(CTRL, 0x10),
+///         Access::Write(DIV, 4),
+///         Access::Read(CTRL, 0x10),
+///         Access::Write(CTRL, 0x11),
+///     ]
+/// );
+/// # Ok::<(), Error>(())
+/// ```
+pub struct Mock<M: Model = RegFile> {
+    model: RefCell<M>,
+    log: RefCell<Vec<Access>>,
+    size: usize,
+}
+
+impl<M: Model> Mock<M> {
+    /// Creates a mock of `size` bytes of address space, backed by `model`.
+    pub fn new(model: M, size: usize) -> Self {
+        Self {
+            model: RefCell::new(model),
+            log: RefCell::new(Vec::new()),
+            size,
+        }
+    }
+
+    /// Returns the model, e.g., to inspect its state or inject an event.
+    ///
+    /// Panics if the returned reference is still alive when the mock is accessed.
+    pub fn model(&self) -> RefMut<'_, M> {
+        self.model.borrow_mut()
+    }
+
+    /// Returns the accesses recorded since the last call, oldest first.
+    pub fn take_log(&self) -> Vec<Access> {
+        core::mem::take(&mut *self.log.borrow_mut())
+    }
+
+    fn check(&self, addr: usize, width: usize, aligned: bool) -> Result {
+        match addr.checked_add(width) {
+            Some(end) if end <= self.size && (!aligned || addr % width == 0) => Ok(()),
+            _ => Err(EINVAL),
+        }
+    }
+
+    fn load(&self, addr: usize, width: usize, aligned: bool) -> Result<u64> {
+        self.check(addr, width, aligned)?;
+        let value = self.model.borrow_mut().read(addr, width)? & mask(width);
+        self.log
+            .borrow_mut()
+            .push(Access::Read(addr, value), GFP_KERNEL)?;
+        Ok(value)
+    }
+
+    fn store(&self, addr: usize, width: usize, value: u64, aligned: bool) -> Result {
+        self.check(addr, width, aligned)?;
+        self.model.borrow_mut().write(addr, width, value)?;
+        self.log
+            .borrow_mut()
+            .push(Access::Write(addr, value), GFP_KERNEL)?;
+        Ok(())
+    }
+}
+
+impl<M: Model> Io for Mock<M> {
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn try_readb(&self, offset: usize) -> Result<u8> {
+        Ok(self.load(offset, 1, true)? as u8)
+    }
+
+    fn try_readw(&self, offset: usize) -> Result<u16> {
+        Ok(self.load(offset, 2, true)? as u16)
+    }
+
+    fn try_readl(&self, offset: usize) -> Result<u32> {
+        Ok(self.load(offset, 4, true)? as u32)
+    }
+
+    #[cfg(CONFIG_64BIT)]
+    fn try_readq(&self, offset: usize) -> Result<u64> {
+        self.load(offset, 8, true)
+    }
+
+    fn try_writeb(&self, value: u8, offset: usize) -> Result {
+        self.store(offset, 1, value.into(), true)
+    }
+
+    fn try_writew(&self, value: u16, offset: usize) -> Result {
+        self.store(offset, 2, value.into(), true)
+    }
+
+    fn try_writel(&self, value: u32, offset: usize) -> Result {
+        self.store(offset, 4, value.into(), true)
+    }
+
+    #[cfg(CONFIG_64BIT)]
+    fn try_writeq(&self, value: u64, offset: usize) -> Result {
+        self.store(offset, 8, value, true)
+    }
+}
+
+/// Registers are 32 bits wide, and addressed by their number: the model sees register `reg` at
+/// address `reg`.
+#[cfg(CONFIG_REGMAP)]
+impl<M: Model> RegAccess for Mock<M> {
+    fn read(&self, reg: u32) -> Result<u32> {
+        Ok(self.load(reg as usize, 4, false)? as u32)
+    }
+
+    fn write(&self, reg: u32, val: u32) -> Result {
+        self.store(reg as usize, 4, val.into(), false)
+    }
+}
+
+/// Commands are addresses of the model. Block transfers access consecutive byte registers from
+/// the command on, as for devices that auto-increment the register address.
+#[cfg(CONFIG_I2C)]
+impl<M: Model> Smbus for Mock<M> {
+    fn read_byte_data(&self, command: u8) -> Result<u8> {
+        Ok(self.load(command.into(), 1, false)? as u8)
+    }
+
+    fn write_byte_data(&self, command: u8, value: u8) -> Result {
+        self.store(command.into(), 1, value.into(), false)
+    }
+
+    fn read_word_data(&self, command: u8) -> Result<u16> {
+        Ok(self.load(command.into(), 2, false)? as u16)
+    }
+
+    fn write_word_data(&self, command: u8, value: u16) -> Result {
+        self.store(command.into(), 2, value.into(), false)
+    }
+
+    fn read_i2c_block_data(&self, command: u8, buf: &mut [u8]) -> Result<usize> {
+        let len = buf.len().min(SMBUS_BLOCK_MAX);
+        for (i, b) in buf[..len].iter_mut().enumerate() {
+            *b = self.load(usize::from(command) + i, 1, false)? as u8;
+        }
+        Ok(len)
+    }
+
+    fn write_i2c_block_data(&self, command: u8, buf: &[u8]) -> Result {
+        if buf.len() > SMBUS_BLOCK_MAX {
+            return Err(EINVAL);
+        }
+        for (i, b) in buf.iter().enumerate() {
+            self.store(usize::from(command) + i, 1, (*b).into(), false)?;
+        }
+        Ok(())
+    }
+}
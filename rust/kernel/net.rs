@@ -2,5 +2,13 @@
 
 //! Networking.
 
+pub mod dev;
+#[cfg(CONFIG_MDIO_BUS)]
+pub mod mdio;
 #[cfg(CONFIG_RUST_PHYLIB_ABSTRACTIONS)]
 pub mod phy;
+#[cfg(CONFIG_PHYLIB)]
+pub mod phy_link;
+pub mod rtnl;
+pub mod skb;
+pub mod tstamp;
@@ -0,0 +1,348 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Network devices.
+//!
+//! C header: [`include/linux/netdevice.h`](srctree/include/linux/netdevice.h)
+
+use crate::{
+    bindings,
+    error::{code::*, to_result, Result},
+    str::CStr,
+    types::{AlwaysRefCounted, Opaque},
+};
+use core::{marker::PhantomData, ops::Deref, ptr::NonNull};
+
+/// A network device.
+///
+/// # Invariants
+///
+/// Instances of this type are always reference-counted, that is, a call to `get_device` on the
+/// embedded `struct device` ensures that the allocation remains valid at least until the matching
+/// call to `put_device`: `free_netdev` only drops the initial reference of a registered device,
+/// which is freed by the release function of the embedded device. Devices of an [`Allocation`]
+/// that was never registered are not freed if a reference was taken on them.
+#[repr(transparent)]
+pub struct Device(Opaque<bindings::net_device>);
+
+impl Device {
+    /// Creates a reference to a [`Device`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// Callers must ensure that `ptr` is valid and remains valid for the lifetime of the returned
+    /// reference.
+    pub unsafe fn from_raw<'a>(ptr: *mut bindings::net_device) -> &'a Self {
+        // SAFETY: Guaranteed by the safety requirements of the function. `Device` is a
+        // transparent wrapper around `net_device`.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns a raw pointer to the underlying `struct net_device`.
+    pub fn as_raw(&self) -> *mut bindings::net_device {
+        self.0.get()
+    }
+
+    /// Returns the name of the device, e.g., `eth0`.
+    ///
+    /// The name may change while the device is not running, unless the RTNL lock is held.
+    pub fn name(&self) -> &CStr {
+        // SAFETY: The device is valid, and its name is always NUL-terminated.
+        unsafe { CStr::from_char_ptr((*self.as_raw()).name.as_ptr()) }
+    }
+
+    /// Returns the maximum transmission unit of the device, in bytes.
+    pub fn mtu(&self) -> u32 {
+        // SAFETY: The device is valid. The MTU may change concurrently, so it is read once, as C
+        // does with `READ_ONCE`.
+        unsafe { core::ptr::addr_of!((*self.as_raw()).mtu).read_volatile() }
+    }
+
+    /// Changes the maximum transmission unit of the device.
+    ///
+    /// This fails with [`EINVAL`] if `mtu` is out of the range the device supports, and with the
+    /// error returned by the driver of the device otherwise. Handlers of [`Event::ChangeMtu`] are
+    /// notified.
+    ///
+    /// [`Event::ChangeMtu`]: super::rtnl::Event::ChangeMtu
+    pub fn set_mtu(&self, mtu: u32, _rtnl: &super::rtnl::Guard) -> Result {
+        let mtu = mtu.try_into().map_err(|_| EINVAL)?;
+        // SAFETY: The device is valid, and the RTNL lock is held, as proven by `_rtnl`.
+        to_result(unsafe { bindings::dev_set_mtu(self.as_raw(), mtu) })
+    }
+
+    /// Returns `true` if the device is administratively up.
+    pub fn is_running(&self) -> bool {
+        // SAFETY: The device is valid.
+        unsafe { bindings::netif_running(self.as_raw()) }
+    }
+
+    /// Returns `true` if the device has a carrier, i.e., its link is up.
+    pub fn carrier_ok(&self) -> bool {
+        // SAFETY: The device is valid.
+        unsafe { bindings::netif_carrier_ok(self.as_raw()) }
+    }
+
+    /// Informs the stack that the link of the device is up.
+    pub fn carrier_on(&self) {
+        // SAFETY: The device is valid.
+        unsafe { bindings::netif_carrier_on(self.as_raw()) };
+    }
+
+    /// Informs the stack that the link of the device is down.
+    pub fn carrier_off(&self) {
+        // SAFETY: The device is valid.
+        unsafe { bindings::netif_carrier_off(self.as_raw()) };
+    }
+
+    /// Returns the private data of the device.
+    ///
+    /// This is how callbacks that only receive the device, e.g., the link change handlers of
+    /// [`phy_link`](super::phy_link), get to the state of the driver.
+    ///
+    /// # Safety
+    ///
+    /// The device must have been allocated by an [`Allocation<T>`] that is not dropped while the
+    /// returned reference is alive.
+    pub unsafe fn priv_data<T>(&self) -> &T {
+        // SAFETY: By the safety requirements, the private area holds an initialised `T`, which
+        // lives as long as the allocation.
+        unsafe { &*bindings::netdev_priv(self.as_raw()).cast::<T>() }
+    }
+
+    /// Returns the number of TX queues the device was allocated with.
+    pub fn num_tx_queues(&self) -> u32 {
+        // SAFETY: The device is valid, and the number does not change after allocation.
+        unsafe { (*self.as_raw()).num_tx_queues }
+    }
+
+    /// Returns the number of TX queues in use.
+    pub fn real_num_tx_queues(&self) -> u32 {
+        // SAFETY: The device is valid.
+        unsafe { (*self.as_raw()).real_num_tx_queues }
+    }
+
+    /// Sets the number of TX queues in use, e.g., after the number of channels was changed.
+    ///
+    /// `n` must be between one and [`Device::num_tx_queues`]. Once the device is registered, the
+    /// RTNL lock must be held.
+    pub fn set_real_num_tx_queues(&self, n: u32) -> Result {
+        // SAFETY: The device is valid. The function checks `n`.
+        to_result(unsafe { bindings::netif_set_real_num_tx_queues(self.as_raw(), n) })
+    }
+
+    /// Sets the number of RX queues in use.
+    ///
+    /// `n` must be between one and the number of RX queues the device was allocated with. Once the
+    /// device is registered, the RTNL lock must be held.
+    pub fn set_real_num_rx_queues(&self, n: u32) -> Result {
+        // SAFETY: The device is valid. The function checks `n`.
+        to_result(unsafe { bindings::netif_set_real_num_rx_queues(self.as_raw(), n) })
+    }
+
+    /// Returns the TX queue for a flow with the given hash, e.g., an RSS hash computed by the
+    /// hardware.
+    ///
+    /// The hash is spread evenly over the queues in use, like the stack does when the driver does
+    /// not select queues itself.
+    pub fn tx_queue_for_hash(&self, hash: u32) -> u16 {
+        ((u64::from(hash) * u64::from(self.real_num_tx_queues())) >> 32) as u16
+    }
+
+    fn check_tx_queue(&self, index: u16) -> Result {
+        if u32::from(index) >= self.num_tx_queues() {
+            return Err(EINVAL);
+        }
+        Ok(())
+    }
+
+    /// Stops the TX queue `index`, e.g., because its descriptor ring is full.
+    ///
+    /// The stack does not pass packets for the queue to the driver until it is woken with
+    /// [`Device::wake_subqueue`].
+    pub fn stop_subqueue(&self, index: u16) -> Result {
+        self.check_tx_queue(index)?;
+        // SAFETY: The device is valid, and `index` was checked above.
+        unsafe { bindings::netif_stop_subqueue(self.as_raw(), index) };
+        Ok(())
+    }
+
+    /// Wakes the TX queue `index`, e.g., after descriptors of its ring were completed.
+    pub fn wake_subqueue(&self, index: u16) -> Result {
+        self.check_tx_queue(index)?;
+        // SAFETY: The device is valid, and `index` was checked above.
+        unsafe { bindings::netif_wake_subqueue(self.as_raw(), index) };
+        Ok(())
+    }
+
+    /// Returns `true` if the TX queue `index` is stopped.
+    pub fn subqueue_stopped(&self, index: u16) -> Result<bool> {
+        self.check_tx_queue(index)?;
+        // SAFETY: The device is valid, and `index` was checked above.
+        Ok(unsafe { bindings::__netif_subqueue_stopped(self.as_raw(), index) })
+    }
+
+    /// Stops all TX queues.
+    pub fn stop_all_queues(&self) {
+        // SAFETY: The device is valid.
+        unsafe { bindings::netif_tx_stop_all_queues(self.as_raw()) };
+    }
+
+    /// Wakes all TX queues.
+    pub fn wake_all_queues(&self) {
+        // SAFETY: The device is valid.
+        unsafe { bindings::netif_tx_wake_all_queues(self.as_raw()) };
+    }
+
+    /// Sets the CPUs that transmit on the TX queue `index` by default, which is called transmit
+    /// packet steering (XPS).
+    ///
+    /// Drivers usually map each queue to the CPU that handles its interrupt. Receive packet
+    /// steering (RPS) maps, in contrast, are only configured by userspace through sysfs.
+    #[cfg(CONFIG_XPS)]
+    pub fn set_xps_queue(&self, cpus: &crate::cpumask::Cpumask, index: u16) -> Result {
+        self.check_tx_queue(index)?;
+        // SAFETY: The device and `cpus` are valid, and `index` was checked above.
+        to_result(unsafe { bindings::netif_set_xps_queue(self.as_raw(), cpus.as_raw(), index) })
+    }
+}
+
+/// An Ethernet device allocated by a driver, with private data of type `T`.
+///
+/// The device must be unregistered by the time this object is dropped. Dropping it drops the
+/// private data, then releases the initial reference of the device with `free_netdev`; other
+/// references, e.g., [`ARef<Device>`]s, keep the memory of the device alive until they are
+/// released.
+///
+/// The embedded `struct device` is only initialised when the device is registered, so references
+/// must not be taken before that. If one was, the device is leaked with a warning instead of
+/// being freed.
+///
+/// # Invariants
+///
+/// `ptr` points to a device allocated with `alloc_etherdev_mqs` whose private area holds an
+/// initialised `T`. The allocation holds the initial reference of the device.
+///
+/// [`ARef<Device>`]: crate::types::ARef
+///
+/// # Examples
+///
+/// ```
+/// use kernel::net::dev::Allocation;
+///
+/// struct MyPriv {
+///     rings: u32,
+/// }
+///
+/// let dev = Allocation::new_ether(MyPriv { rings: 8 }, 8, 8)?;
+/// assert_eq!(dev.num_tx_queues(), 8);
+/// dev.set_real_num_tx_queues(4)?;
+/// assert!(dev.tx_queue_for_hash(u32::MAX) < 4);
+/// assert_eq!(dev.priv_data().rings, 8);
+/// # Ok::<(), Error>(())
+/// ```
+pub struct Allocation<T> {
+    ptr: NonNull<bindings::net_device>,
+    _p: PhantomData<T>,
+}
+
+impl<T> Allocation<T> {
+    /// Allocates an Ethernet device with `tx_queues` TX and `rx_queues` RX queues.
+    ///
+    /// All the queues are in use initially; drivers that use fewer, e.g., depending on the number
+    /// of CPUs, change that with [`Device::set_real_num_tx_queues`] and
+    /// [`Device::set_real_num_rx_queues`].
+    pub fn new_ether(data: T, tx_queues: u32, rx_queues: u32) -> Result<Self> {
+        if tx_queues == 0 || rx_queues == 0 {
+            return Err(EINVAL);
+        }
+        crate::build_assert!(core::mem::align_of::<T>() <= bindings::NETDEV_ALIGN as usize);
+        let size = core::mem::size_of::<T>().try_into()?;
+        // SAFETY: The queue numbers were checked above.
+        let ptr = unsafe { bindings::alloc_etherdev_mqs(size, tx_queues, rx_queues) };
+        let ptr = NonNull::new(ptr).ok_or(ENOMEM)?;
+        // SAFETY: The private area is `size_of::<T>()` bytes long and aligned to `NETDEV_ALIGN`,
+        // which is enough for `T` as checked above.
+        unsafe { bindings::netdev_priv(ptr.as_ptr()).cast::<T>().write(data) };
+        // INVARIANT: The private data was just initialised.
+        Ok(Self {
+            ptr,
+            _p: PhantomData,
+        })
+    }
+
+    /// Returns the private data of the device.
+    pub fn priv_data(&self) -> &T {
+        // SAFETY: By the type invariants, the device was allocated by `Allocation<T>`.
+        unsafe { self.deref().priv_data() }
+    }
+}
+
+impl<T> Deref for Allocation<T> {
+    type Target = Device;
+
+    fn deref(&self) -> &Device {
+        // SAFETY: By the type invariants, `ptr` is valid while `self` is alive.
+        unsafe { Device::from_raw(self.ptr.as_ptr()) }
+    }
+}
+
+impl<T> Drop for Allocation<T> {
+    fn drop(&mut self) {
+        let ptr = self.ptr.as_ptr();
+
+        // The private data is dropped first, so that references it holds, e.g., in a
+        // `phy_link::Connection`, are released before the device is freed.
+        //
+        // SAFETY: By the type invariants, the private area holds an initialised `T`.
+        unsafe { core::ptr::drop_in_place(bindings::netdev_priv(ptr).cast::<T>()) };
+
+        // `free_netdev` frees a device that was never registered right away, so it must not be
+        // called if references were taken on its uninitialised embedded device. Taking one
+        // saturates the reference count, which therefore never drops back to zero.
+        //
+        // SAFETY: By the type invariants, `ptr` is valid until it is freed below.
+        let leaked = unsafe {
+            let kobj = core::ptr::addr_of_mut!((*ptr).dev.kobj);
+            (*kobj).state_initialized() == 0 && bindings::kref_read(&(*kobj).kref) != 0
+        };
+        if leaked {
+            crate::pr_warn!(
+                "net: leaking {}, referenced before it was registered\n",
+                // SAFETY: `ptr` is valid, see above.
+                unsafe { Device::from_raw(ptr) }.name()
+            );
+            return;
+        }
+
+        // SAFETY: By the type invariants, the device was allocated with `alloc_etherdev_mqs`. It
+        // is freed once the references taken on a registered device are released, and no
+        // reference was taken on an unregistered one, as checked above.
+        unsafe { bindings::free_netdev(ptr) };
+    }
+}
+
+// SAFETY: The device can be freed from any thread if the private data can be dropped there.
+unsafe impl<T: Send> Send for Allocation<T> {}
+
+// SAFETY: `Allocation` only gives shared access to the private data through `&self`.
+unsafe impl<T: Sync> Sync for Allocation<T> {}
+
+// SAFETY: Instances of `Device` are always reference-counted.
+unsafe impl AlwaysRefCounted for Device {
+    fn inc_ref(&self) {
+        // SAFETY: The existence of a shared reference means that the refcount is nonzero.
+        unsafe { bindings::get_device(core::ptr::addr_of_mut!((*self.as_raw()).dev)) };
+    }
+
+    unsafe fn dec_ref(obj: NonNull<Self>) {
+        let ptr = obj.cast::<bindings::net_device>().as_ptr();
+        // SAFETY: The safety requirements guarantee that the refcount is nonzero.
+        unsafe { bindings::put_device(core::ptr::addr_of_mut!((*ptr).dev)) }
+    }
+}
+
+// SAFETY: Network devices are reference-counted and can be released from any thread.
+unsafe impl Send for Device {}
+
+// SAFETY: The stack serialises the state changes made through `&Device` with its own locking.
+unsafe impl Sync for Device {}
@@ -0,0 +1,259 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! MDIO bus providers.
+//!
+//! MAC drivers whose controller also drives the MDIO lines register an MDIO bus, so that phylib
+//! can probe and manage the PHYs attached to it.
+//!
+//! C header: [`include/linux/phy.h`](srctree/include/linux/phy.h)
+
+use crate::{
+    bindings,
+    device::RawDevice,
+    error::{code::*, to_result, Result, VTABLE_DEFAULT_ERROR},
+    str::CStr,
+    types::ForeignOwnable,
+    ThisModule,
+};
+use core::{ffi::c_int, marker::PhantomData, ptr::NonNull};
+use macros::vtable;
+
+/// Number of PHY addresses on an MDIO bus.
+pub const PHY_MAX_ADDR: u8 = bindings::PHY_MAX_ADDR as u8;
+
+/// Operations of an MDIO bus.
+///
+/// `addr` is the address of the PHY on the bus, below [`PHY_MAX_ADDR`].
+#[vtable]
+pub trait Operations {
+    /// The context data made available to the callbacks.
+    type Data: ForeignOwnable + Send + Sync;
+
+    /// Reads register `regnum` using a clause 22 access.
+    fn read(
+        data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        addr: u8,
+        regnum: u16,
+    ) -> Result<u16>;
+
+    /// Writes `val` to register `regnum` using a clause 22 access.
+    fn write(
+        data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        addr: u8,
+        regnum: u16,
+        val: u16,
+    ) -> Result;
+
+    /// Reads register `regnum` of MMD `devnum` using a clause 45 access.
+    fn read_c45(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _addr: u8,
+        _devnum: u8,
+        _regnum: u16,
+    ) -> Result<u16> {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+
+    /// Writes `val` to register `regnum` of MMD `devnum` using a clause 45 access.
+    fn write_c45(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _addr: u8,
+        _devnum: u8,
+        _regnum: u16,
+        _val: u16,
+    ) -> Result {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+
+    /// Resets the bus.
+    ///
+    /// Called when the bus is registered, before it is scanned for PHYs.
+    fn reset(_data: <Self::Data as ForeignOwnable>::Borrowed<'_>) -> Result {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+}
+
+/// A registered MDIO bus.
+///
+/// The bus is unregistered and its data freed when the registration is dropped.
+///
+/// # Invariants
+///
+/// `bus` was allocated and registered by [`Registration::register`], and its private data was
+/// obtained from [`ForeignOwnable::into_foreign`] on a `T::Data`.
+pub struct Registration<T: Operations> {
+    bus: NonNull<bindings::mii_bus>,
+    _p: PhantomData<T>,
+}
+
+impl<T: Operations> Registration<T> {
+    /// Allocates and registers a new MDIO bus as a child of `parent`.
+    ///
+    /// `id` must be unique among MDIO buses, it is truncated if it does not fit. Addresses whose
+    /// bit is set in `phy_mask` are not scanned for PHYs.
+    pub fn register(
+        parent: &impl RawDevice,
+        name: &'static CStr,
+        id: &CStr,
+        phy_mask: u32,
+        data: T::Data,
+        module: &'static ThisModule,
+    ) -> Result<Self> {
+        // SAFETY: FFI call without safety requirements.
+        let bus = NonNull::new(unsafe { bindings::mdiobus_alloc_size(0) }).ok_or(ENOMEM)?;
+        let raw = bus.as_ptr();
+
+        // SAFETY: `raw` was just allocated and is not shared yet.
+        unsafe {
+            (*raw).name = name.as_char_ptr();
+            let dst = &mut (*raw).id;
+            let len = id.len().min(dst.len() - 1);
+            for (d, s) in dst.iter_mut().zip(&id.as_bytes()[..len]) {
+                *d = *s as _;
+            }
+            dst[len] = 0;
+            (*raw).parent = parent.raw_device();
+            (*raw).phy_mask = phy_mask;
+            (*raw).read = Some(Self::read_callback);
+            (*raw).write = Some(Self::write_callback);
+            if T::HAS_READ_C45 {
+                (*raw).read_c45 = Some(Self::read_c45_callback);
+            }
+            if T::HAS_WRITE_C45 {
+                (*raw).write_c45 = Some(Self::write_c45_callback);
+            }
+            if T::HAS_RESET {
+                (*raw).reset = Some(Self::reset_callback);
+            }
+            (*raw).priv_ = data.into_foreign() as *mut _;
+        }
+
+        // SAFETY: `raw` was allocated above and all the mandatory fields are set. `module` lives
+        // at least as long as the registration.
+        if let Err(e) = to_result(unsafe { bindings::__mdiobus_register(raw, module.0) }) {
+            // SAFETY: The bus was not registered, so it is only referenced from here.
+            unsafe { Self::free(raw) };
+            return Err(e);
+        }
+
+        // INVARIANT: The bus was allocated and registered above.
+        Ok(Self {
+            bus,
+            _p: PhantomData,
+        })
+    }
+
+    /// Returns a raw pointer to the underlying `struct mii_bus`.
+    ///
+    /// This can be used to look up the PHYs found on the bus, e.g. with `mdiobus_get_phy`.
+    pub fn as_raw(&self) -> *mut bindings::mii_bus {
+        self.bus.as_ptr()
+    }
+
+    /// Frees the private data and the bus.
+    ///
+    /// # Safety
+    ///
+    /// `bus` must have been allocated by [`Registration::register`] and must not be registered.
+    unsafe fn free(bus: *mut bindings::mii_bus) {
+        // SAFETY: By the safety requirements, `bus` is valid and its private data was set by
+        // `register`, from `into_foreign`. No callbacks can run anymore.
+        unsafe {
+            drop(T::Data::from_foreign((*bus).priv_));
+            bindings::mdiobus_free(bus);
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `bus` must be a bus registered by [`Registration::register`].
+    unsafe fn data<'a>(bus: *mut bindings::mii_bus) -> <T::Data as ForeignOwnable>::Borrowed<'a> {
+        // SAFETY: By the safety requirements, the private data came from `into_foreign`, and is
+        // only freed after the bus is unregistered.
+        unsafe { T::Data::borrow((*bus).priv_) }
+    }
+
+    unsafe extern "C" fn read_callback(
+        bus: *mut bindings::mii_bus,
+        addr: c_int,
+        regnum: c_int,
+    ) -> c_int {
+        // SAFETY: The MDIO core only calls this for buses registered with it.
+        let data = unsafe { Self::data(bus) };
+        match T::read(data, addr as u8, regnum as u16) {
+            Ok(v) => v.into(),
+            Err(e) => e.to_errno(),
+        }
+    }
+
+    unsafe extern "C" fn write_callback(
+        bus: *mut bindings::mii_bus,
+        addr: c_int,
+        regnum: c_int,
+        val: u16,
+    ) -> c_int {
+        // SAFETY: The MDIO core only calls this for buses registered with it.
+        let data = unsafe { Self::data(bus) };
+        match T::write(data, addr as u8, regnum as u16, val) {
+            Ok(()) => 0,
+            Err(e) => e.to_errno(),
+        }
+    }
+
+    unsafe extern "C" fn read_c45_callback(
+        bus: *mut bindings::mii_bus,
+        addr: c_int,
+        devnum: c_int,
+        regnum: c_int,
+    ) -> c_int {
+        // SAFETY: The MDIO core only calls this for buses registered with it.
+        let data = unsafe { Self::data(bus) };
+        match T::read_c45(data, addr as u8, devnum as u8, regnum as u16) {
+            Ok(v) => v.into(),
+            Err(e) => e.to_errno(),
+        }
+    }
+
+    unsafe extern "C" fn write_c45_callback(
+        bus: *mut bindings::mii_bus,
+        addr: c_int,
+        devnum: c_int,
+        regnum: c_int,
+        val: u16,
+    ) -> c_int {
+        // SAFETY: The MDIO core only calls this for buses registered with it.
+        let data = unsafe { Self::data(bus) };
+        match T::write_c45(data, addr as u8, devnum as u8, regnum as u16, val) {
+            Ok(()) => 0,
+            Err(e) => e.to_errno(),
+        }
+    }
+
+    unsafe extern "C" fn reset_callback(bus: *mut bindings::mii_bus) -> c_int {
+        // SAFETY: The MDIO core only calls this for buses registered with it.
+        let data = unsafe { Self::data(bus) };
+        match T::reset(data) {
+            Ok(()) => 0,
+            Err(e) => e.to_errno(),
+        }
+    }
+}
+
+impl<T: Operations> Drop for Registration<T> {
+    fn drop(&mut self) {
+        let bus = self.bus.as_ptr();
+        // SAFETY: By the type invariants, `bus` is registered. Unregistering it also removes the
+        // PHYs found on it.
+        unsafe { bindings::mdiobus_unregister(bus) };
+        // SAFETY: By the type invariants, `bus` was allocated by `register`, and it was
+        // unregistered above.
+        unsafe { Self::free(bus) };
+    }
+}
+
+// SAFETY: The registration only holds a `T::Data`, which is `Send`, and the bus, which can be
+// unregistered from any thread.
+unsafe impl<T: Operations> Send for Registration<T> {}
+
+// SAFETY: `Registration` does not expose any method that mutates the bus through `&self`.
+unsafe impl<T: Operations> Sync for Registration<T> {}
@@ -0,0 +1,284 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! PHY connections of MAC drivers.
+//!
+//! A MAC driver connects its network device to the PHY that drives the physical link. phylib then
+//! runs the PHY state machine, which negotiates the link and reports every change of its state to
+//! the MAC driver through [`LinkHandler::link_change`], so that the MAC can be configured to match,
+//! e.g., for the negotiated speed.
+//!
+//! C header: [`include/linux/phy.h`](srctree/include/linux/phy.h)
+
+use super::{dev::Device, mdio};
+use crate::{
+    bindings,
+    error::{code::*, to_result, Result},
+    types::ARef,
+};
+use core::{
+    marker::PhantomData,
+    ptr::{self, NonNull},
+};
+
+/// The interface between the MAC and the PHY.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Interface {
+    /// The PHY is internal to the MAC.
+    Internal = bindings::phy_interface_t_PHY_INTERFACE_MODE_INTERNAL,
+    /// Media independent interface.
+    Mii = bindings::phy_interface_t_PHY_INTERFACE_MODE_MII,
+    /// Reduced MII.
+    Rmii = bindings::phy_interface_t_PHY_INTERFACE_MODE_RMII,
+    /// Reduced gigabit MII, with the clock delays added on the board.
+    Rgmii = bindings::phy_interface_t_PHY_INTERFACE_MODE_RGMII,
+    /// RGMII, with both clock delays added by the PHY.
+    RgmiiId = bindings::phy_interface_t_PHY_INTERFACE_MODE_RGMII_ID,
+    /// RGMII, with the RX clock delay added by the PHY.
+    RgmiiRxid = bindings::phy_interface_t_PHY_INTERFACE_MODE_RGMII_RXID,
+    /// RGMII, with the TX clock delay added by the PHY.
+    RgmiiTxid = bindings::phy_interface_t_PHY_INTERFACE_MODE_RGMII_TXID,
+    /// Serial gigabit MII.
+    Sgmii = bindings::phy_interface_t_PHY_INTERFACE_MODE_SGMII,
+}
+
+/// The duplex mode of a link.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Duplex {
+    /// Half duplex.
+    Half,
+    /// Full duplex.
+    Full,
+    /// The duplex mode is not known, e.g., because the link is down.
+    Unknown,
+}
+
+/// The state of the link of a PHY.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LinkState {
+    /// Whether the link is up.
+    pub up: bool,
+
+    /// The speed of the link in Mb/s, or [`None`] if it is not known.
+    pub speed: Option<u32>,
+
+    /// The duplex mode of the link.
+    pub duplex: Duplex,
+
+    /// Whether the link partner supports symmetric pause frames.
+    pub pause: bool,
+
+    /// Whether the link partner supports asymmetric pause frames.
+    pub asym_pause: bool,
+}
+
+impl LinkState {
+    /// Reads the link state of `phydev`.
+    ///
+    /// # Safety
+    ///
+    /// `phydev` must be valid, and its lock must be held or the state machine not be running.
+    unsafe fn from_raw(phydev: *const bindings::phy_device) -> Self {
+        // SAFETY: By the safety requirements, `phydev` is valid and its state is stable.
+        let p = unsafe { &*phydev };
+        Self {
+            up: p.link() != 0,
+            speed: u32::try_from(p.speed).ok(),
+            duplex: match p.duplex as u32 {
+                bindings::DUPLEX_HALF => Duplex::Half,
+                bindings::DUPLEX_FULL => Duplex::Full,
+                _ => Duplex::Unknown,
+            },
+            pause: p.pause != 0,
+            asym_pause: p.asym_pause != 0,
+        }
+    }
+}
+
+/// The handler of link changes of a MAC driver.
+pub trait LinkHandler {
+    /// Called by the PHY state machine when the state of the link of `dev` changed.
+    ///
+    /// This is the place to reprogram the MAC for the new speed, duplex mode and flow control and
+    /// to report link changes, e.g., with [`Connection::print_status`]. The carrier of `dev` is
+    /// already updated when this is called. It runs in process context, with the lock of the PHY
+    /// held, so it must not call back into the [`Connection`].
+    fn link_change(dev: &Device, state: &LinkState);
+}
+
+/// A connection between a network device and a PHY.
+///
+/// The PHY state machine is stopped and the PHY detached when the connection is dropped, which
+/// usually happens when the device is stopped.
+///
+/// # Invariants
+///
+/// `phydev` is attached to `dev` with [`Connection::adjust_link_callback`] as link change
+/// handler.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::net::{dev::Device, phy_link::*};
+/// use kernel::{of, prelude::*};
+///
+/// struct MyMac;
+///
+/// impl LinkHandler for MyMac {
+///     fn link_change(dev: &Device, state: &LinkState) {
+///         if state.up {
+///             // Program the MAC for `state.speed` and `state.duplex`.
+///         }
+///         Connection::<Self>::print_status_of(dev);
+///     }
+/// }
+///
+/// fn open(dev: &Device, phy_node: &of::Node) -> Result<Connection<MyMac>> {
+///     let phy = Connection::<MyMac>::connect_of(dev, phy_node, Interface::RgmiiId)?;
+///     phy.start();
+///     Ok(phy)
+/// }
+/// ```
+pub struct Connection<T: LinkHandler> {
+    dev: ARef<Device>,
+    phydev: NonNull<bindings::phy_device>,
+    _p: PhantomData<T>,
+}
+
+impl<T: LinkHandler> Connection<T> {
+    /// Connects `dev` to the PHY described by the devicetree node `phy_node`.
+    ///
+    /// Fails with [`EPROBE_DEFER`] if the PHY has not been probed yet.
+    ///
+    /// [`EPROBE_DEFER`]: crate::error::code::EPROBE_DEFER
+    #[cfg(CONFIG_OF_MDIO)]
+    pub fn connect_of(
+        dev: &Device,
+        phy_node: &crate::of::Node,
+        interface: Interface,
+    ) -> Result<Self> {
+        // SAFETY: `dev` and `phy_node` are valid, and the handler is a static function.
+        let phydev = unsafe {
+            bindings::of_phy_connect(
+                dev.as_raw(),
+                phy_node.as_raw(),
+                Some(Self::adjust_link_callback),
+                0,
+                interface as _,
+            )
+        };
+        let phydev = NonNull::new(phydev).ok_or(EPROBE_DEFER)?;
+        // INVARIANT: `phydev` was just attached with our handler.
+        Ok(Self {
+            dev: dev.into(),
+            phydev,
+            _p: PhantomData,
+        })
+    }
+
+    /// Connects `dev` to the PHY with the lowest address on `bus`.
+    ///
+    /// Fails with [`ENODEV`] if no PHY was found on the bus.
+    pub fn connect_first<U: mdio::Operations>(
+        dev: &Device,
+        bus: &mdio::Registration<U>,
+        interface: Interface,
+    ) -> Result<Self> {
+        // SAFETY: The bus is registered.
+        let phydev =
+            NonNull::new(unsafe { bindings::phy_find_first(bus.as_raw()) }).ok_or(ENODEV)?;
+        // SAFETY: `dev` and `phydev` are valid, and the handler is a static function.
+        to_result(unsafe {
+            bindings::phy_connect_direct(
+                dev.as_raw(),
+                phydev.as_ptr(),
+                Some(Self::adjust_link_callback),
+                interface as _,
+            )
+        })?;
+        // INVARIANT: `phydev` was just attached with our handler.
+        Ok(Self {
+            dev: dev.into(),
+            phydev,
+            _p: PhantomData,
+        })
+    }
+
+    /// Starts the PHY state machine, which brings the link up.
+    ///
+    /// This is usually called when the network device is opened.
+    pub fn start(&self) {
+        // SAFETY: By the type invariants, `phydev` is attached.
+        unsafe { bindings::phy_start(self.phydev.as_ptr()) };
+    }
+
+    /// Stops the PHY state machine and brings the link down.
+    ///
+    /// [`LinkHandler::link_change`] is called for the link going down, if it was up.
+    pub fn stop(&self) {
+        // SAFETY: By the type invariants, `phydev` is attached.
+        unsafe { bindings::phy_stop(self.phydev.as_ptr()) };
+    }
+
+    /// Restarts auto-negotiation of the link.
+    pub fn restart_aneg(&self) -> Result {
+        // SAFETY: By the type invariants, `phydev` is attached.
+        to_result(unsafe { bindings::phy_restart_aneg(self.phydev.as_ptr()) })
+    }
+
+    /// Returns the current state of the link.
+    pub fn link_state(&self) -> LinkState {
+        let phydev = self.phydev.as_ptr();
+        // SAFETY: By the type invariants, `phydev` is valid. Its lock is held while the state is
+        // read.
+        unsafe {
+            bindings::mutex_lock(ptr::addr_of_mut!((*phydev).lock));
+            let state = LinkState::from_raw(phydev);
+            bindings::mutex_unlock(ptr::addr_of_mut!((*phydev).lock));
+            state
+        }
+    }
+
+    /// Returns the network device of the connection.
+    pub fn device(&self) -> &Device {
+        &self.dev
+    }
+
+    /// Logs the state of the link, e.g., `Link is Up - 1Gbps/Full - flow control rx/tx`.
+    pub fn print_status(&self) {
+        // SAFETY: By the type invariants, `phydev` is attached.
+        unsafe { bindings::phy_print_status(self.phydev.as_ptr()) };
+    }
+
+    /// Logs the state of the link of `dev`, from within [`LinkHandler::link_change`].
+    pub fn print_status_of(dev: &Device) {
+        // SAFETY: `dev` is valid. Its `phydev` is set while a PHY is attached, which is the case
+        // while a link change handler runs.
+        let phydev = unsafe { (*dev.as_raw()).phydev };
+        if !phydev.is_null() {
+            // SAFETY: `phydev` is attached to `dev`.
+            unsafe { bindings::phy_print_status(phydev) };
+        }
+    }
+
+    unsafe extern "C" fn adjust_link_callback(ndev: *mut bindings::net_device) {
+        // SAFETY: phylib only calls this for devices that a PHY is attached to with this handler,
+        // with the lock of the PHY held.
+        let (dev, state) = unsafe { (Device::from_raw(ndev), LinkState::from_raw((*ndev).phydev)) };
+        T::link_change(dev, &state);
+    }
+}
+
+impl<T: LinkHandler> Drop for Connection<T> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, `phydev` is attached. Disconnecting stops the state
+        // machine, so the handler does not run anymore afterwards.
+        unsafe { bindings::phy_disconnect(self.phydev.as_ptr()) };
+    }
+}
+
+// SAFETY: The PHY can be stopped and disconnected from any thread.
+unsafe impl<T: LinkHandler> Send for Connection<T> {}
+
+// SAFETY: phylib serialises the operations on the PHY with its lock.
+unsafe impl<T: LinkHandler> Sync for Connection<T> {}
@@ -0,0 +1,233 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! The routing netlink lock, and notifications of changes of network devices.
+//!
+//! The RTNL lock serialises the configuration of network devices, e.g., bringing them up or
+//! changing their MTU. [`Guard`] holds it, and is taken by the functions that require it as a
+//! proof that it is held. Changes of network devices are reported to a [`Handler`] registered
+//! with a [`Notifier`], which runs with the lock held.
+//!
+//! C header: [`include/linux/rtnetlink.h`](srctree/include/linux/rtnetlink.h)
+
+use super::dev::Device;
+use crate::{bindings, error::to_result, prelude::*, types::Opaque};
+use core::{ffi::c_void, marker::PhantomData, marker::PhantomPinned, mem::ManuallyDrop};
+
+/// A guard of the RTNL lock, which is released when the guard is dropped.
+///
+/// # Invariants
+///
+/// The RTNL lock is held by the current thread for as long as the guard exists.
+#[must_use = "the lock unlocks immediately when the guard is unused"]
+pub struct Guard {
+    _not_send: PhantomData<*mut ()>,
+}
+
+impl Guard {
+    /// Acquires the RTNL lock.
+    ///
+    /// This sleeps until the lock is available, so it must be called in a context that can sleep.
+    pub fn lock() -> Self {
+        // SAFETY: `rtnl_lock` can be called from any context that can sleep.
+        unsafe { bindings::rtnl_lock() };
+        // INVARIANT: The lock was just acquired.
+        Self {
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Tries to acquire the RTNL lock, and returns [`None`] if it is contended.
+    pub fn try_lock() -> Option<Self> {
+        // SAFETY: `rtnl_trylock` can be called from any context that can sleep.
+        if unsafe { bindings::rtnl_trylock() } != 0 {
+            // INVARIANT: The lock was just acquired.
+            Some(Self {
+                _not_send: PhantomData,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Creates a guard for the lock held by a caller higher up in the call chain.
+    ///
+    /// The returned guard must not be dropped, since it does not own the lock.
+    ///
+    /// # Safety
+    ///
+    /// The current thread must hold the RTNL lock for as long as the returned guard exists.
+    unsafe fn assume_held() -> ManuallyDrop<Self> {
+        // INVARIANT: By the safety requirements, the lock is held.
+        ManuallyDrop::new(Self {
+            _not_send: PhantomData,
+        })
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, the current thread holds the lock.
+        unsafe { bindings::rtnl_unlock() };
+    }
+}
+
+/// Returns `true` if the RTNL lock is held, by any thread.
+///
+/// This is meant for assertions only.
+pub fn is_locked() -> bool {
+    // SAFETY: `rtnl_is_locked` can be called from any context.
+    unsafe { bindings::rtnl_is_locked() != 0 }
+}
+
+/// A change of a network device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Event {
+    /// The device was brought up.
+    Up,
+
+    /// The device was brought down.
+    Down,
+
+    /// The MTU of the device changed. The new one is returned by [`Device::mtu`].
+    ChangeMtu {
+        /// The MTU before the change.
+        old_mtu: u32,
+    },
+}
+
+/// A handler of changes of network devices.
+pub trait Handler: Sync {
+    /// Called when `dev` changed.
+    ///
+    /// This runs with the RTNL lock held, as proven by `rtnl`, so it must not try to acquire it.
+    /// It is called for the devices of all network namespaces.
+    fn event(&self, dev: &Device, event: Event, rtnl: &Guard);
+}
+
+/// A registration of a [`Handler`] of changes of network devices.
+///
+/// On registration, the handler gets [`Event::Up`] for every device that is already up, and on
+/// unregistration, when this object is dropped, [`Event::Down`] for every device that is still
+/// up. This way, handlers that track devices do not need special cases for the devices that
+/// existed before them or that outlive them.
+///
+/// # Invariants
+///
+/// `nb` is registered with `register_netdevice_notifier`.
+///
+/// # Examples
+///
+/// ```
+/// use core::sync::atomic::{AtomicU32, Ordering};
+/// use kernel::net::{dev::Device, rtnl};
+/// use kernel::prelude::*;
+///
+/// struct UpCount(AtomicU32);
+///
+/// impl rtnl::Handler for UpCount {
+///     fn event(&self, dev: &Device, event: rtnl::Event, _rtnl: &rtnl::Guard) {
+///         match event {
+///             rtnl::Event::Up => {
+///                 self.0.fetch_add(1, Ordering::Relaxed);
+///             }
+///             rtnl::Event::Down => {
+///                 self.0.fetch_sub(1, Ordering::Relaxed);
+///             }
+///             rtnl::Event::ChangeMtu { old_mtu } => {
+///                 pr_info!("{}: mtu {} -> {}\n", dev.name(), old_mtu, dev.mtu());
+///             }
+///             _ => {}
+///         }
+///     }
+/// }
+///
+/// let _n = Box::pin_init(rtnl::Notifier::new(UpCount(AtomicU32::new(0))), GFP_KERNEL)?;
+/// # Ok::<(), Error>(())
+/// ```
+#[pin_data(PinnedDrop)]
+pub struct Notifier<T: Handler> {
+    handler: T,
+    #[pin]
+    nb: Opaque<bindings::notifier_block>,
+    #[pin]
+    _pin: PhantomPinned,
+}
+
+impl<T: Handler> Notifier<T> {
+    /// Registers `handler` for changes of network devices.
+    ///
+    /// This acquires the RTNL lock, so it must not be called with it held.
+    pub fn new(handler: T) -> impl PinInit<Self, Error> {
+        try_pin_init!(Self {
+            // Initialised before `nb`, so that it is ready when the notifier is registered.
+            handler,
+            nb <- Opaque::try_ffi_init(|nb: *mut bindings::notifier_block| {
+                // SAFETY: `nb` is valid for writes. The notifier block is pinned and unregistered
+                // before it is freed, by `PinnedDrop`.
+                unsafe {
+                    nb.write(bindings::notifier_block {
+                        notifier_call: Some(Self::notifier_callback),
+                        ..core::mem::zeroed()
+                    });
+                    to_result(bindings::register_netdevice_notifier(nb))
+                }
+            }),
+            _pin: PhantomPinned,
+        })
+    }
+
+    /// Returns the handler.
+    pub fn handler(&self) -> &T {
+        &self.handler
+    }
+
+    unsafe extern "C" fn notifier_callback(
+        nb: *mut bindings::notifier_block,
+        action: core::ffi::c_ulong,
+        ptr: *mut c_void,
+    ) -> core::ffi::c_int {
+        let info = ptr.cast::<bindings::netdev_notifier_info>();
+        let event = match action as bindings::netdev_cmd {
+            bindings::netdev_cmd_NETDEV_UP => Event::Up,
+            bindings::netdev_cmd_NETDEV_DOWN => Event::Down,
+            bindings::netdev_cmd_NETDEV_CHANGEMTU => {
+                // SAFETY: `NETDEV_CHANGEMTU` is always sent with the extended info, which holds
+                // the previous MTU.
+                let old_mtu = unsafe {
+                    (*crate::container_of!(info, bindings::netdev_notifier_info_ext, info))
+                        .ext
+                        .mtu
+                };
+                Event::ChangeMtu { old_mtu }
+            }
+            _ => return bindings::NOTIFY_DONE as _,
+        };
+
+        // SAFETY: `nb` is the `nb` field of a registered `Notifier<T>`, which stays alive until
+        // it is unregistered.
+        let this = unsafe { &*crate::container_of!(nb, Self, nb) };
+        // SAFETY: The notifier info of netdev notifiers always has a valid device, which is alive
+        // for the duration of the call.
+        let dev = unsafe { Device::from_raw(bindings::netdev_notifier_info_to_dev(info)) };
+        // SAFETY: The netdev notifier chain is called with the RTNL lock held.
+        let rtnl = unsafe { Guard::assume_held() };
+        this.handler.event(dev, event, &rtnl);
+        bindings::NOTIFY_DONE as _
+    }
+}
+
+#[pinned_drop]
+impl<T: Handler> PinnedDrop for Notifier<T> {
+    fn drop(self: Pin<&mut Self>) {
+        // SAFETY: By the type invariants, `nb` is registered. Unregistering takes the RTNL lock,
+        // under which the callbacks run, so none is running anymore afterwards.
+        unsafe { bindings::unregister_netdevice_notifier(self.nb.get()) };
+    }
+}
+
+// SAFETY: The notifier can be unregistered from any thread, and the handler is `Sync`.
+unsafe impl<T: Handler + Send> Send for Notifier<T> {}
+
+// SAFETY: The handler is `Sync`, and the notifier block is only accessed by the notifier chain.
+unsafe impl<T: Handler> Sync for Notifier<T> {}
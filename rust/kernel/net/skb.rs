@@ -0,0 +1,47 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Socket buffers.
+//!
+//! C header: [`include/linux/skbuff.h`](srctree/include/linux/skbuff.h)
+
+use crate::{bindings, types::Opaque};
+
+/// A socket buffer, i.e., a packet and its metadata.
+///
+/// # Invariants
+///
+/// The buffer is valid and owned by the current thread, e.g., a driver transmitting or receiving
+/// it, for as long as references to this type exist.
+#[repr(transparent)]
+pub struct SkBuff(Opaque<bindings::sk_buff>);
+
+impl SkBuff {
+    /// Creates a reference to a [`SkBuff`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// Callers must ensure that `ptr` is valid, and that the buffer is not used by another thread,
+    /// for the lifetime of the returned reference. This is the case, e.g., in `ndo_start_xmit`,
+    /// and for a received buffer until it is passed to the stack.
+    pub unsafe fn from_raw<'a>(ptr: *mut bindings::sk_buff) -> &'a Self {
+        // SAFETY: Guaranteed by the safety requirements of the function. `SkBuff` is a
+        // transparent wrapper around `sk_buff`.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns a raw pointer to the underlying `struct sk_buff`.
+    pub fn as_raw(&self) -> *mut bindings::sk_buff {
+        self.0.get()
+    }
+
+    /// Returns the length of the packet, in bytes.
+    pub fn len(&self) -> u32 {
+        // SAFETY: By the type invariants, the buffer is valid.
+        unsafe { (*self.as_raw()).len }
+    }
+
+    /// Returns `true` if the packet is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
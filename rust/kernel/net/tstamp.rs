@@ -0,0 +1,369 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Hardware timestamping of packets.
+//!
+//! MACs with a PTP hardware clock (see [`ptp`](crate::ptp)) can timestamp packets as they leave
+//! or enter the wire, which time synchronisation protocols need for sub-microsecond accuracy.
+//! Userspace enables timestamping with the `SIOCSHWTSTAMP` ioctl, or its netlink equivalent, and
+//! the driver receives the requested [`Config`] in `ndo_hwtstamp_set`. Received packets then
+//! carry their timestamp, attached with [`SkBuff::set_rx_hw_timestamp`], while transmitted
+//! packets that request one, as told by [`SkBuff::tx_hw_timestamp_requested`], are reported back
+//! to their socket with [`SkBuff::complete_tx_hw_timestamp`] once the hardware has sent them.
+//!
+//! C headers: [`include/linux/net_tstamp.h`](srctree/include/linux/net_tstamp.h) and
+//! [`include/uapi/linux/net_tstamp.h`](srctree/include/uapi/linux/net_tstamp.h)
+
+use super::skb::SkBuff;
+use crate::{
+    bindings,
+    error::{code::*, Result},
+    time::timekeeping::Ktime,
+};
+use core::{ffi::c_int, mem::size_of};
+
+/// The timestamping of transmitted packets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxType {
+    /// Transmitted packets are not timestamped.
+    Off,
+
+    /// Transmitted packets that request a timestamp are timestamped.
+    On,
+
+    /// Like [`TxType::On`], but the hardware also inserts the timestamp into PTP sync messages.
+    OneStepSync,
+
+    /// Like [`TxType::OneStepSync`], and the hardware also updates PTP peer delay responses.
+    OneStepP2p,
+}
+
+impl TxType {
+    fn from_raw(raw: c_int) -> Result<Self> {
+        Ok(match raw as u32 {
+            bindings::hwtstamp_tx_types_HWTSTAMP_TX_OFF => Self::Off,
+            bindings::hwtstamp_tx_types_HWTSTAMP_TX_ON => Self::On,
+            bindings::hwtstamp_tx_types_HWTSTAMP_TX_ONESTEP_SYNC => Self::OneStepSync,
+            bindings::hwtstamp_tx_types_HWTSTAMP_TX_ONESTEP_P2P => Self::OneStepP2p,
+            _ => return Err(ERANGE),
+        })
+    }
+
+    fn as_raw(self) -> c_int {
+        (match self {
+            Self::Off => bindings::hwtstamp_tx_types_HWTSTAMP_TX_OFF,
+            Self::On => bindings::hwtstamp_tx_types_HWTSTAMP_TX_ON,
+            Self::OneStepSync => bindings::hwtstamp_tx_types_HWTSTAMP_TX_ONESTEP_SYNC,
+            Self::OneStepP2p => bindings::hwtstamp_tx_types_HWTSTAMP_TX_ONESTEP_P2P,
+        }) as c_int
+    }
+}
+
+/// The received packets that are timestamped.
+///
+/// Hardware that cannot filter exactly the requested packets may timestamp more of them, and
+/// reports the filter it uses instead, e.g., [`RxFilter::All`] or [`RxFilter::PtpV2Event`] when
+/// [`RxFilter::PtpV2L2Event`] is requested.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RxFilter {
+    /// No packet is timestamped.
+    None,
+
+    /// All packets are timestamped.
+    All,
+
+    /// Some packets are timestamped, which is only valid as a reported filter.
+    Some,
+
+    /// PTPv1 event messages over UDP.
+    PtpV1L4Event,
+
+    /// PTPv1 sync messages over UDP.
+    PtpV1L4Sync,
+
+    /// PTPv1 delay request messages over UDP.
+    PtpV1L4DelayReq,
+
+    /// PTPv2 event messages over UDP.
+    PtpV2L4Event,
+
+    /// PTPv2 sync messages over UDP.
+    PtpV2L4Sync,
+
+    /// PTPv2 delay request messages over UDP.
+    PtpV2L4DelayReq,
+
+    /// PTPv2 event messages over Ethernet.
+    PtpV2L2Event,
+
+    /// PTPv2 sync messages over Ethernet.
+    PtpV2L2Sync,
+
+    /// PTPv2 delay request messages over Ethernet.
+    PtpV2L2DelayReq,
+
+    /// PTPv2 event messages over UDP or Ethernet.
+    PtpV2Event,
+
+    /// PTPv2 sync messages over UDP or Ethernet.
+    PtpV2Sync,
+
+    /// PTPv2 delay request messages over UDP or Ethernet.
+    PtpV2DelayReq,
+
+    /// All NTP packets.
+    NtpAll,
+}
+
+impl RxFilter {
+    fn from_raw(raw: c_int) -> Result<Self> {
+        use bindings::*;
+
+        Ok(match raw as u32 {
+            hwtstamp_rx_filters_HWTSTAMP_FILTER_NONE => Self::None,
+            hwtstamp_rx_filters_HWTSTAMP_FILTER_ALL => Self::All,
+            hwtstamp_rx_filters_HWTSTAMP_FILTER_SOME => Self::Some,
+            hwtstamp_rx_filters_HWTSTAMP_FILTER_PTP_V1_L4_EVENT => Self::PtpV1L4Event,
+            hwtstamp_rx_filters_HWTSTAMP_FILTER_PTP_V1_L4_SYNC => Self::PtpV1L4Sync,
+            hwtstamp_rx_filters_HWTSTAMP_FILTER_PTP_V1_L4_DELAY_REQ => Self::PtpV1L4DelayReq,
+            hwtstamp_rx_filters_HWTSTAMP_FILTER_PTP_V2_L4_EVENT => Self::PtpV2L4Event,
+            hwtstamp_rx_filters_HWTSTAMP_FILTER_PTP_V2_L4_SYNC => Self::PtpV2L4Sync,
+            hwtstamp_rx_filters_HWTSTAMP_FILTER_PTP_V2_L4_DELAY_REQ => Self::PtpV2L4DelayReq,
+            hwtstamp_rx_filters_HWTSTAMP_FILTER_PTP_V2_L2_EVENT => Self::PtpV2L2Event,
+            hwtstamp_rx_filters_HWTSTAMP_FILTER_PTP_V2_L2_SYNC => Self::PtpV2L2Sync,
+            hwtstamp_rx_filters_HWTSTAMP_FILTER_PTP_V2_L2_DELAY_REQ => Self::PtpV2L2DelayReq,
+            hwtstamp_rx_filters_HWTSTAMP_FILTER_PTP_V2_EVENT => Self::PtpV2Event,
+            hwtstamp_rx_filters_HWTSTAMP_FILTER_PTP_V2_SYNC => Self::PtpV2Sync,
+            hwtstamp_rx_filters_HWTSTAMP_FILTER_PTP_V2_DELAY_REQ => Self::PtpV2DelayReq,
+            hwtstamp_rx_filters_HWTSTAMP_FILTER_NTP_ALL => Self::NtpAll,
+            _ => return Err(ERANGE),
+        })
+    }
+
+    fn as_raw(self) -> c_int {
+        use bindings::*;
+
+        (match self {
+            Self::None => hwtstamp_rx_filters_HWTSTAMP_FILTER_NONE,
+            Self::All => hwtstamp_rx_filters_HWTSTAMP_FILTER_ALL,
+            Self::Some => hwtstamp_rx_filters_HWTSTAMP_FILTER_SOME,
+            Self::PtpV1L4Event => hwtstamp_rx_filters_HWTSTAMP_FILTER_PTP_V1_L4_EVENT,
+            Self::PtpV1L4Sync => hwtstamp_rx_filters_HWTSTAMP_FILTER_PTP_V1_L4_SYNC,
+            Self::PtpV1L4DelayReq => hwtstamp_rx_filters_HWTSTAMP_FILTER_PTP_V1_L4_DELAY_REQ,
+            Self::PtpV2L4Event => hwtstamp_rx_filters_HWTSTAMP_FILTER_PTP_V2_L4_EVENT,
+            Self::PtpV2L4Sync => hwtstamp_rx_filters_HWTSTAMP_FILTER_PTP_V2_L4_SYNC,
+            Self::PtpV2L4DelayReq => hwtstamp_rx_filters_HWTSTAMP_FILTER_PTP_V2_L4_DELAY_REQ,
+            Self::PtpV2L2Event => hwtstamp_rx_filters_HWTSTAMP_FILTER_PTP_V2_L2_EVENT,
+            Self::PtpV2L2Sync => hwtstamp_rx_filters_HWTSTAMP_FILTER_PTP_V2_L2_SYNC,
+            Self::PtpV2L2DelayReq => hwtstamp_rx_filters_HWTSTAMP_FILTER_PTP_V2_L2_DELAY_REQ,
+            Self::PtpV2Event => hwtstamp_rx_filters_HWTSTAMP_FILTER_PTP_V2_EVENT,
+            Self::PtpV2Sync => hwtstamp_rx_filters_HWTSTAMP_FILTER_PTP_V2_SYNC,
+            Self::PtpV2DelayReq => hwtstamp_rx_filters_HWTSTAMP_FILTER_PTP_V2_DELAY_REQ,
+            Self::NtpAll => hwtstamp_rx_filters_HWTSTAMP_FILTER_NTP_ALL,
+        }) as c_int
+    }
+
+    /// Returns `true` if the filter only selects PTP messages.
+    pub fn is_ptp(self) -> bool {
+        !matches!(self, Self::None | Self::All | Self::Some | Self::NtpAll)
+    }
+}
+
+/// A hardware timestamping configuration.
+///
+/// # Examples
+///
+/// A driver whose hardware timestamps either no packets or all of them:
+///
+/// ```
+/// use kernel::net::tstamp::{Config, RxFilter, TxType};
+///
+/// fn hwtstamp_set(requested: Config) -> Result<Config> {
+///     if !matches!(requested.tx_type, TxType::Off | TxType::On) {
+///         return Err(ERANGE);
+///     }
+///     let rx_filter = match requested.rx_filter {
+///         RxFilter::None => RxFilter::None,
+///         _ => RxFilter::All,
+///     };
+///     // Program the timestamping units of the MAC.
+///     Ok(Config { rx_filter, ..requested })
+/// }
+///
+/// let c = hwtstamp_set(Config { tx_type: TxType::On, rx_filter: RxFilter::PtpV2Event })?;
+/// assert_eq!(c.rx_filter, RxFilter::All);
+/// assert!(RxFilter::PtpV2Event.is_ptp());
+/// # Ok::<(), Error>(())
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// The timestamping of transmitted packets.
+    pub tx_type: TxType,
+
+    /// The received packets that are timestamped.
+    pub rx_filter: RxFilter,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            tx_type: TxType::Off,
+            rx_filter: RxFilter::None,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the configuration passed to `ndo_hwtstamp_set`.
+    ///
+    /// This fails with [`ERANGE`] if userspace requested an unknown mode, which `ndo_hwtstamp_set`
+    /// returns as is, as it does for modes the hardware does not support.
+    ///
+    /// # Safety
+    ///
+    /// `raw` must be valid for reads.
+    pub unsafe fn from_kernel(raw: *const bindings::kernel_hwtstamp_config) -> Result<Self> {
+        // SAFETY: By the safety requirements, `raw` is valid for reads.
+        let raw = unsafe { &*raw };
+        Ok(Self {
+            tx_type: TxType::from_raw(raw.tx_type)?,
+            rx_filter: RxFilter::from_raw(raw.rx_filter)?,
+        })
+    }
+
+    /// Writes the configuration in use, in `ndo_hwtstamp_get`, or the one actually applied, in
+    /// `ndo_hwtstamp_set`.
+    ///
+    /// # Safety
+    ///
+    /// `raw` must be valid for writes.
+    pub unsafe fn to_kernel(&self, raw: *mut bindings::kernel_hwtstamp_config) {
+        // SAFETY: By the safety requirements, `raw` is valid for writes.
+        let raw = unsafe { &mut *raw };
+        raw.tx_type = self.tx_type.as_raw();
+        raw.rx_filter = self.rx_filter.as_raw();
+    }
+
+    /// Reads the configuration passed to the `SIOCSHWTSTAMP` ioctl, for drivers that handle it in
+    /// `ndo_eth_ioctl` rather than implementing `ndo_hwtstamp_set`.
+    ///
+    /// This fails with [`EFAULT`] if the configuration cannot be copied from userspace, with
+    /// [`EINVAL`] if it has unknown flags, and with [`ERANGE`] for unknown modes.
+    ///
+    /// # Safety
+    ///
+    /// `ifr` must be the valid request passed to `ndo_eth_ioctl`.
+    pub unsafe fn from_ifreq(ifr: *const bindings::ifreq) -> Result<Self> {
+        let mut raw = bindings::hwtstamp_config {
+            flags: 0,
+            tx_type: 0,
+            rx_filter: 0,
+        };
+        // SAFETY: By the safety requirements, `ifr` is valid, and its data is a userspace pointer,
+        // which `copy_from_user` checks. `raw` is valid for writes of its size.
+        let left = unsafe {
+            bindings::copy_from_user(
+                (&mut raw as *mut bindings::hwtstamp_config).cast(),
+                (*ifr).ifr_ifru.ifru_data,
+                size_of::<bindings::hwtstamp_config>() as _,
+            )
+        };
+        if left != 0 {
+            return Err(EFAULT);
+        }
+        // `HWTSTAMP_FLAG_BONDED_PHC_INDEX` is set by bonding, which the device does not need to
+        // act on.
+        if raw.flags as u32 & !bindings::hwtstamp_flags_HWTSTAMP_FLAG_MASK != 0 {
+            return Err(EINVAL);
+        }
+        Ok(Self {
+            tx_type: TxType::from_raw(raw.tx_type)?,
+            rx_filter: RxFilter::from_raw(raw.rx_filter)?,
+        })
+    }
+
+    /// Writes the configuration back to userspace, in the `SIOCSHWTSTAMP` and `SIOCGHWTSTAMP`
+    /// ioctls handled in `ndo_eth_ioctl`.
+    ///
+    /// # Safety
+    ///
+    /// `ifr` must be the valid request passed to `ndo_eth_ioctl`.
+    pub unsafe fn to_ifreq(&self, ifr: *const bindings::ifreq) -> Result {
+        let raw = bindings::hwtstamp_config {
+            flags: 0,
+            tx_type: self.tx_type.as_raw(),
+            rx_filter: self.rx_filter.as_raw(),
+        };
+        // SAFETY: By the safety requirements, `ifr` is valid, and its data is a userspace pointer,
+        // which `copy_to_user` checks. `raw` is valid for reads of its size.
+        let left = unsafe {
+            bindings::copy_to_user(
+                (*ifr).ifr_ifru.ifru_data,
+                (&raw as *const bindings::hwtstamp_config).cast(),
+                size_of::<bindings::hwtstamp_config>() as _,
+            )
+        };
+        if left != 0 {
+            return Err(EFAULT);
+        }
+        Ok(())
+    }
+}
+
+impl SkBuff {
+    /// Attaches the time at which the hardware received the packet.
+    ///
+    /// This must be called before the packet is passed to the stack, and only if the
+    /// [`RxFilter`] in use selects the packet.
+    pub fn set_rx_hw_timestamp(&self, ts: Ktime) {
+        // SAFETY: By the type invariants, the buffer is valid and owned by the current thread, so
+        // its shared info can be written.
+        unsafe {
+            (*bindings::skb_hwtstamps(self.as_raw()))
+                .__bindgen_anon_1
+                .hwtstamp = ts
+        };
+    }
+
+    /// Returns `true` if the socket that sent the packet requested a hardware timestamp.
+    ///
+    /// Drivers check this in `ndo_start_xmit` when transmit timestamping is enabled, and then
+    /// call [`SkBuff::set_tx_hw_timestamp_in_progress`].
+    pub fn tx_hw_timestamp_requested(&self) -> bool {
+        // SAFETY: By the type invariants, the buffer is valid.
+        let flags = unsafe { (*bindings::skb_shinfo(self.as_raw())).tx_flags };
+        u32::from(flags) & bindings::SKBTX_HW_TSTAMP != 0
+    }
+
+    /// Tells the stack that the hardware timestamps the packet, so that it does not report a
+    /// software timestamp instead.
+    pub fn set_tx_hw_timestamp_in_progress(&self) {
+        // SAFETY: By the type invariants, the buffer is valid and owned by the current thread, so
+        // its shared info can be written.
+        unsafe {
+            (*bindings::skb_shinfo(self.as_raw())).tx_flags |= bindings::SKBTX_IN_PROGRESS as u8
+        };
+    }
+
+    /// Takes the software transmit timestamp, if the socket requested one.
+    ///
+    /// Drivers call this in `ndo_start_xmit` just before handing the packet to the hardware,
+    /// whether or not it is timestamped by the hardware.
+    pub fn tx_timestamp(&self) {
+        // SAFETY: By the type invariants, the buffer is valid.
+        unsafe { bindings::skb_tx_timestamp(self.as_raw()) };
+    }
+
+    /// Reports the time at which the hardware sent the packet to its socket.
+    ///
+    /// This is called on transmit completion, before the buffer is freed, for packets marked with
+    /// [`SkBuff::set_tx_hw_timestamp_in_progress`].
+    pub fn complete_tx_hw_timestamp(&self, ts: Ktime) {
+        // SAFETY: All zeroes is a valid value of the timestamps.
+        let mut hwtstamps: bindings::skb_shared_hwtstamps = unsafe { core::mem::zeroed() };
+        hwtstamps.__bindgen_anon_1.hwtstamp = ts;
+        // SAFETY: By the type invariants, the buffer is valid, and `hwtstamps` is valid for reads.
+        // The timestamp is copied into a clone of the buffer, queued to the error queue of its
+        // socket.
+        unsafe { bindings::skb_tstamp_tx(self.as_raw(), &mut hwtstamps) };
+    }
+}
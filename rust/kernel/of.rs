@@ -4,7 +4,17 @@
 //!
 //! C header: [`include/linux/of_*.h`](../../../../include/linux/of_*.h)
 
-use crate::{bindings, driver, str::BStr};
+use crate::{
+    bindings, driver,
+    str::{BStr, CStr},
+    types::{ARef, AlwaysRefCounted, Opaque},
+};
+use core::ptr::NonNull;
+
+#[cfg(all(CONFIG_RUST_KUNIT_FAKE_REGS, CONFIG_OF_OVERLAY))]
+pub mod fixture;
+#[cfg(CONFIG_OF_OVERLAY)]
+pub mod overlay;
 
 /// An open firmware device id.
 #[derive(Clone, Copy)]
@@ -88,3 +98,67 @@ pub const fn to_rawid(&self, offset: isize) -> <Self as driver::RawDeviceId>::Ra
         id
     }
 }
+
+/// A node of the devicetree.
+///
+/// # Invariants
+///
+/// Instances of this type are always reference-counted, that is, a call to `of_node_get`
+/// ensures that the allocation remains valid at least until the matching call to `of_node_put`.
+#[repr(transparent)]
+pub struct Node(Opaque<bindings::device_node>);
+
+impl Node {
+    /// Finds the node with the given full path, e.g., `/soc/i2c@7e804000`, or alias.
+    pub fn find_by_path(path: &CStr) -> Option<ARef<Self>> {
+        // SAFETY: `path` is a valid C string. On success, the returned node has its reference
+        // count incremented.
+        let ptr = unsafe { bindings::of_find_node_by_path(path.as_char_ptr()) };
+        let ptr = NonNull::new(ptr.cast::<Self>())?;
+        // SAFETY: The reference taken above is owned by the returned `ARef`.
+        Some(unsafe { ARef::from_raw(ptr) })
+    }
+
+    /// Creates a reference to a [`Node`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// Callers must ensure that `ptr` is valid and remains valid for the lifetime of the returned
+    /// reference.
+    pub unsafe fn from_raw<'a>(ptr: *mut bindings::device_node) -> &'a Self {
+        // SAFETY: Guaranteed by the safety requirements of the function. `Node` is a transparent
+        // wrapper around `device_node`.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns a raw pointer to the underlying `struct device_node`.
+    pub fn as_raw(&self) -> *mut bindings::device_node {
+        self.0.get()
+    }
+
+    /// Returns the full name of the node, i.e., its name and unit address.
+    pub fn full_name(&self) -> &CStr {
+        // SAFETY: The node is valid, and its full name is a C string that lives as long as it.
+        unsafe { CStr::from_char_ptr((*self.as_raw()).full_name) }
+    }
+}
+
+// SAFETY: Instances of `Node` are always reference-counted.
+unsafe impl AlwaysRefCounted for Node {
+    fn inc_ref(&self) {
+        // SAFETY: The existence of a shared reference means that the refcount is nonzero.
+        unsafe { bindings::of_node_get(self.as_raw()) };
+    }
+
+    unsafe fn dec_ref(obj: NonNull<Self>) {
+        // SAFETY: The safety requirements guarantee that the refcount is nonzero.
+        unsafe { bindings::of_node_put(obj.cast().as_ptr()) }
+    }
+}
+
+// SAFETY: Nodes are reference-counted and can be released from any thread.
+unsafe impl Send for Node {}
+
+// SAFETY: `Node` does not expose any method that mutates the node through `&self`; changes go
+// through changesets, which the OF core serialises.
+unsafe impl Sync for Node {}
@@ -0,0 +1,357 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Devicetree fixtures for driver tests.
+//!
+//! A [`Fixture`] adds synthetic nodes to the live tree, so that a platform driver can be probed
+//! and removed from a KUnit test, without the hardware it drives. Registers are backed by
+//! [`FakeRegs`], which the driver maps with the usual [`IoMem`] methods, and clocks are fixed-rate
+//! clocks registered by the fixture. Devices can be instantiated several times, e.g., to check
+//! that the instances of a driver do not share state.
+//!
+//! C header: [`include/linux/of_platform.h`](srctree/include/linux/of_platform.h)
+//!
+//! [`IoMem`]: crate::io_mem::IoMem
+
+use super::{overlay::Changeset, Node};
+use crate::{
+    bindings, c_str,
+    device::RawDevice,
+    error::{from_err_ptr, to_result},
+    io_mem::FakeRegs,
+    prelude::*,
+    str::CStr,
+    types::ARef,
+};
+use core::{
+    marker::PhantomData,
+    ptr,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+/// A property of a synthetic node.
+pub enum Prop<'a> {
+    /// A property holding `u32` cells.
+    U32(&'a CStr, &'a [u32]),
+    /// A string property.
+    Str(&'a CStr, &'a CStr),
+    /// A string list property, e.g., `compatible`.
+    Strs(&'a CStr, &'a [&'a CStr]),
+    /// An empty property, i.e., a boolean flag.
+    Bool(&'a CStr),
+    /// The `reg` property, describing the fake physical ranges of the given registers.
+    Regs(&'a [&'a FakeRegs]),
+    /// The `clocks` property, referencing the given clocks.
+    #[cfg(CONFIG_COMMON_CLK)]
+    Clocks(&'a [&'a Clock<'a>]),
+}
+
+impl Prop<'_> {
+    fn add(&self, cs: &Changeset, np: &Node) -> Result {
+        match self {
+            Self::U32(name, values) => cs.add_prop_u32_array(np, name, values),
+            Self::Str(name, value) => cs.add_prop_string(np, name, value),
+            Self::Strs(name, values) => cs.add_prop_string_array(np, name, values),
+            Self::Bool(name) => cs.add_prop_bool(np, name),
+            Self::Regs(regs) => {
+                let mut cells = Vec::with_capacity(regs.len() * 3, GFP_KERNEL)?;
+                for r in regs.iter() {
+                    // The fixture node has two address cells and one size cell.
+                    let start = u64::from(r.start());
+                    let size = u32::try_from(r.size()).map_err(|_| EINVAL)?;
+                    cells.extend_from_slice(
+                        &[(start >> 32) as u32, start as u32, size],
+                        GFP_KERNEL,
+                    )?;
+                }
+                cs.add_prop_u32_array(np, c_str!("reg"), &cells)
+            }
+            #[cfg(CONFIG_COMMON_CLK)]
+            Self::Clocks(clocks) => {
+                let mut cells = Vec::with_capacity(clocks.len(), GFP_KERNEL)?;
+                for c in clocks.iter() {
+                    cells.push(c.phandle(), GFP_KERNEL)?;
+                }
+                cs.add_prop_u32_array(np, c_str!("clocks"), &cells)
+            }
+        }
+    }
+}
+
+/// A node of the live tree that holds the synthetic nodes of a test.
+///
+/// The node is created under the root of the tree, with two address cells, one size cell and an
+/// empty `ranges` property, so that the `reg` properties of its children are identity-mapped. It
+/// is removed when the fixture is dropped; the devices and clocks created from the fixture borrow
+/// it, so they are removed first.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{
+///     c_str,
+///     io_mem::FakeRegs,
+///     of::fixture::{Fixture, Prop},
+///     prelude::*,
+/// };
+///
+/// const CTRL: usize = 0x0;
+///
+/// /// Checks that the driver of `vendor,foo` enables the device on probe, for two instances.
+/// fn test_probe() -> Result {
+///     let fixture = Fixture::new(c_str!("rust-foo-test"))?;
+///     let clk = fixture.add_fixed_clock(c_str!("rust-foo-test-osc"), 24_000_000)?;
+///
+///     for name in [c_str!("foo@0"), c_str!("foo@1")] {
+///         let regs = FakeRegs::new(0x100)?;
+///         let dev = fixture.add_device(
+///             name,
+///             &[
+///                 Prop::Strs(c_str!("compatible"), &[c_str!("vendor,foo")]),
+///                 Prop::Regs(&[&regs]),
+///                 Prop::Clocks(&[&clk]),
+///             ],
+///         )?;
+///         assert!(dev.is_bound());
+///         assert_eq!(regs.read32(CTRL) & 1, 1);
+///
+///         // Removes the device, which unbinds the driver.
+///         drop(dev);
+///         assert_eq!(regs.read32(CTRL) & 1, 0);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub struct Fixture {
+    np: ARef<Node>,
+    _cs: Pin<Box<Changeset>>,
+}
+
+impl Fixture {
+    /// Creates the node named `name` under the root of the tree.
+    ///
+    /// The name must not be used by another node of the root, e.g., by a fixture of another test
+    /// that runs at the same time.
+    pub fn new(name: &CStr) -> Result<Self> {
+        let root = Node::find_by_path(c_str!("/")).ok_or(ENODEV)?;
+        let cs = Box::pin_init(Changeset::new(), GFP_KERNEL)?;
+        let np = cs.create_node(&root, name)?;
+        cs.add_prop_u32_array(&np, c_str!("#address-cells"), &[2])?;
+        cs.add_prop_u32_array(&np, c_str!("#size-cells"), &[1])?;
+        cs.add_prop_bool(&np, c_str!("ranges"))?;
+        cs.apply()?;
+        Ok(Self { np, _cs: cs })
+    }
+
+    /// Returns the node of the fixture.
+    pub fn node(&self) -> &Node {
+        &self.np
+    }
+
+    /// Creates a node named `name` with the properties `props`, and a platform device for it.
+    ///
+    /// If a driver matching the node is registered, it is probed before this returns, unless it
+    /// prefers asynchronous probing. The device is removed when the returned [`Instance`] is
+    /// dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kernel::{
+    ///     c_str,
+    ///     io_mem::FakeRegs,
+    ///     of::fixture::{Fixture, Prop},
+    ///     prelude::*,
+    /// };
+    ///
+    /// let fixture = Fixture::new(c_str!("rust-fixture-doctest"))?;
+    /// let regs = FakeRegs::new(0x100)?;
+    /// let props = [
+    ///     Prop::Strs(c_str!("compatible"), &[c_str!("rust,fixture-doctest")]),
+    ///     Prop::U32(c_str!("rust,channels"), &[4]),
+    ///     Prop::Regs(&[&regs]),
+    /// ];
+    ///
+    /// let dev = fixture.add_device(c_str!("dev@0"), &props)?;
+    /// // No driver matches the node.
+    /// assert!(!dev.is_bound());
+    ///
+    /// // Dropping the instance removes both the device and the node, so they can be created
+    /// // again.
+    /// drop(dev);
+    /// let dev = fixture.add_device(c_str!("dev@0"), &props)?;
+    /// assert!(!dev.is_bound());
+    /// # Ok::<(), Error>(())
+    /// ```
+    pub fn add_device<'a>(&'a self, name: &CStr, props: &[Prop<'a>]) -> Result<Instance<'a>> {
+        let cs = Box::pin_init(Changeset::new(), GFP_KERNEL)?;
+        let np = cs.create_node(&self.np, name)?;
+        for p in props {
+            p.add(&cs, &np)?;
+        }
+        cs.apply()?;
+
+        // SAFETY: The node is attached to the tree and valid. A null bus id and parent select the
+        // defaults, i.e., a name derived from the node and the platform bus.
+        let pdev = unsafe {
+            bindings::of_platform_device_create(np.as_raw(), ptr::null(), ptr::null_mut())
+        };
+        if pdev.is_null() {
+            return Err(ENODEV);
+        }
+
+        // INVARIANT: The device was just created, and is only destroyed by `Instance::drop`.
+        Ok(Instance {
+            pdev,
+            _cs: cs,
+            _np: np,
+            _p: PhantomData,
+        })
+    }
+
+    /// Creates a clock provider node named `name`, with a fixed-rate clock of `rate` Hz.
+    ///
+    /// The clock is registered under the name `name` as well, which must therefore be unique
+    /// among all clocks.
+    #[cfg(CONFIG_COMMON_CLK)]
+    pub fn add_fixed_clock(&self, name: &CStr, rate: usize) -> Result<Clock<'_>> {
+        let phandle = alloc_phandle()?;
+        let cs = Box::pin_init(Changeset::new(), GFP_KERNEL)?;
+        let np = cs.create_node(&self.np, name)?;
+        cs.add_prop_u32_array(&np, c_str!("#clock-cells"), &[0])?;
+        cs.add_prop_u32_array(&np, c_str!("phandle"), &[phandle])?;
+        cs.apply()?;
+
+        // SAFETY: `name` is a valid C string, which is copied by the clock framework. There is no
+        // device nor parent clock.
+        let hw = from_err_ptr(unsafe {
+            bindings::clk_hw_register_fixed_rate(
+                ptr::null_mut(),
+                name.as_char_ptr(),
+                ptr::null(),
+                0,
+                rate as _,
+            )
+        })?;
+        // SAFETY: The node is valid and `hw` is a registered clock, which
+        // `of_clk_hw_simple_get` returns for all specifiers.
+        let ret = unsafe {
+            bindings::of_clk_add_hw_provider(
+                np.as_raw(),
+                Some(bindings::of_clk_hw_simple_get),
+                hw.cast(),
+            )
+        };
+        if let Err(e) = to_result(ret) {
+            // SAFETY: `hw` was registered above and is not used anymore.
+            unsafe { bindings::clk_hw_unregister_fixed_rate(hw) };
+            return Err(e);
+        }
+
+        // INVARIANT: The clock and its provider were just registered.
+        Ok(Clock {
+            hw,
+            phandle,
+            np,
+            _cs: cs,
+            _p: PhantomData,
+        })
+    }
+}
+
+/// Returns a phandle that no node of the tree uses.
+#[cfg(CONFIG_COMMON_CLK)]
+fn alloc_phandle() -> Result<u32> {
+    // Phandles of nodes from the devicetree blob are allocated from 1 by `dtc`; starting far from
+    // them avoids most collisions, and the lookup below catches the others.
+    static NEXT: AtomicU32 = AtomicU32::new(0x7f00_0000);
+
+    for _ in 0..16 {
+        let phandle = NEXT.fetch_add(1, Ordering::Relaxed);
+        // SAFETY: Looking up a phandle has no requirements. On success, the returned node has its
+        // reference count incremented.
+        let np = unsafe { bindings::of_find_node_by_phandle(phandle) };
+        if np.is_null() {
+            return Ok(phandle);
+        }
+        // SAFETY: The reference was taken by `of_find_node_by_phandle` above.
+        unsafe { bindings::of_node_put(np) };
+    }
+    Err(EBUSY)
+}
+
+/// A platform device instantiated from a synthetic node of a [`Fixture`].
+///
+/// Dropping it removes the device, which unbinds its driver, and then the node.
+///
+/// # Invariants
+///
+/// `pdev` is a platform device created with `of_platform_device_create`, that has not been
+/// destroyed.
+pub struct Instance<'a> {
+    pdev: *mut bindings::platform_device,
+    _cs: Pin<Box<Changeset>>,
+    _np: ARef<Node>,
+    _p: PhantomData<&'a Fixture>,
+}
+
+impl Instance<'_> {
+    /// Returns `true` if a driver is bound to the device, i.e., if it was probed successfully.
+    pub fn is_bound(&self) -> bool {
+        // SAFETY: The device is valid.
+        unsafe { bindings::device_is_bound(self.raw_device()) }
+    }
+}
+
+// SAFETY: By the type invariants, `pdev` is valid, so its embedded `struct device` is valid too.
+unsafe impl RawDevice for Instance<'_> {
+    fn raw_device(&self) -> *mut bindings::device {
+        // SAFETY: By the type invariants, `pdev` is valid.
+        unsafe { &mut (*self.pdev).dev }
+    }
+}
+
+impl Drop for Instance<'_> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, the device was created from a node and has not been
+        // destroyed. The node is removed after this, when `_cs` is dropped.
+        unsafe { bindings::of_platform_device_destroy(self.raw_device(), ptr::null_mut()) };
+    }
+}
+
+/// A fixed-rate clock provided by a synthetic node of a [`Fixture`].
+///
+/// Devices reference it with [`Prop::Clocks`]. Dropping it unregisters the clock, and then
+/// removes the node; devices using it must be removed first.
+///
+/// # Invariants
+///
+/// `hw` is a clock registered with `clk_hw_register_fixed_rate`, provided for `np`.
+#[cfg(CONFIG_COMMON_CLK)]
+pub struct Clock<'a> {
+    hw: *mut bindings::clk_hw,
+    phandle: u32,
+    np: ARef<Node>,
+    _cs: Pin<Box<Changeset>>,
+    _p: PhantomData<&'a Fixture>,
+}
+
+#[cfg(CONFIG_COMMON_CLK)]
+impl Clock<'_> {
+    /// Returns the phandle of the clock node, e.g., to reference it from a property other than
+    /// `clocks`.
+    pub fn phandle(&self) -> u32 {
+        self.phandle
+    }
+}
+
+#[cfg(CONFIG_COMMON_CLK)]
+impl Drop for Clock<'_> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, the provider is registered for `np`, and the clock is
+        // registered. Neither is used after this.
+        unsafe {
+            bindings::of_clk_del_provider(self.np.as_raw());
+            bindings::clk_hw_unregister_fixed_rate(self.hw);
+        }
+    }
+}
@@ -0,0 +1,269 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Devicetree overlays and changesets.
+//!
+//! Overlays are flattened devicetree fragments applied to the live tree at runtime, e.g., to
+//! describe an expansion board or the contents of an FPGA once it has been programmed.
+//! Changesets are the lower level mechanism: a list of node and property changes that are applied
+//! or reverted atomically.
+//!
+//! C header: [`include/linux/of.h`](srctree/include/linux/of.h)
+
+use super::Node;
+use crate::{
+    bindings,
+    error::to_result,
+    ffi_init,
+    prelude::*,
+    types::{ARef, Opaque},
+};
+use core::{cell::Cell, ffi::c_int, marker::PhantomPinned, ptr::NonNull};
+
+/// An overlay applied to the live devicetree.
+///
+/// The overlay is removed when this object is dropped. Removal fails if another overlay that was
+/// applied later modifies the same nodes and is still applied; overlays must therefore be dropped
+/// in the reverse order of their application.
+///
+/// # Invariants
+///
+/// `id` is the id of an applied overlay changeset owned by this object.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{c_str, of::{overlay::Overlay, Node}, prelude::*};
+///
+/// fn apply_cape(fdt: &[u8]) -> Result<Overlay> {
+///     let target = Node::find_by_path(c_str!("/soc")).ok_or(ENODEV)?;
+///     Overlay::apply(fdt, Some(&target))
+/// }
+/// ```
+pub struct Overlay {
+    id: c_int,
+}
+
+impl Overlay {
+    /// Applies the overlay contained in the flattened devicetree blob `fdt`.
+    ///
+    /// Fragments of the overlay that do not have an absolute target path are applied relative to
+    /// `target`, or to the root of the tree if it is [`None`].
+    pub fn apply(fdt: &[u8], target: Option<&Node>) -> Result<Self> {
+        let size = u32::try_from(fdt.len()).map_err(|_| EINVAL)?;
+        let target = target.map_or(core::ptr::null_mut(), Node::as_raw);
+        let mut id = 0;
+        // SAFETY: `fdt` is valid for reads of `size` bytes; it is copied by the OF core, so it
+        // does not need to outlive the call. `target` is either null or a valid node.
+        let ret =
+            unsafe { bindings::of_overlay_fdt_apply(fdt.as_ptr().cast(), size, &mut id, target) };
+        if let Err(e) = to_result(ret) {
+            if id != 0 {
+                // The overlay was partially applied and must be removed.
+                // SAFETY: `id` was just returned by `of_overlay_fdt_apply`.
+                unsafe { bindings::of_overlay_remove(&mut id) };
+            }
+            return Err(e);
+        }
+
+        // INVARIANT: The overlay was just applied.
+        Ok(Self { id })
+    }
+
+    /// Returns the id of the overlay changeset.
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+}
+
+impl Drop for Overlay {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, `id` refers to an applied overlay owned by `self`.
+        let ret = unsafe { bindings::of_overlay_remove(&mut self.id) };
+        if ret != 0 {
+            pr_err!("failed to remove devicetree overlay {}: {}\n", self.id, ret);
+        }
+    }
+}
+
+/// A set of changes to the live devicetree.
+///
+/// Changes are recorded with the `create_node`, `attach_node`, `detach_node` and `add_prop_*`
+/// methods, and then applied atomically with [`Changeset::apply`]. An applied changeset is
+/// reverted when it is dropped.
+///
+/// # Invariants
+///
+/// `cs` is initialised. `applied` is `true` if and only if the changeset is applied.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{c_str, of::{overlay::Changeset, Node}, prelude::*};
+///
+/// fn add_sensor(parent: &Node) -> Result<Pin<Box<Changeset>>> {
+///     let cs = Box::pin_init(Changeset::new(), GFP_KERNEL)?;
+///     let np = cs.create_node(parent, c_str!("sensor@48"))?;
+///     cs.add_prop_string(&np, c_str!("compatible"), c_str!("ti,tmp102"))?;
+///     cs.add_prop_u32_array(&np, c_str!("reg"), &[0x48])?;
+///     cs.apply()?;
+///     Ok(cs)
+/// }
+/// ```
+#[pin_data(PinnedDrop)]
+pub struct Changeset {
+    #[pin]
+    cs: Opaque<bindings::of_changeset>,
+    applied: Cell<bool>,
+    #[pin]
+    _pin: PhantomPinned,
+}
+
+impl Changeset {
+    /// Creates a new, empty changeset.
+    pub fn new() -> impl PinInit<Self> {
+        pin_init!(Self {
+            // SAFETY: `slot` is valid for writes while the closure is called.
+            cs <- ffi_init!(unsafe bindings::of_changeset_init(slot)),
+            applied: Cell::new(false),
+            _pin: PhantomPinned,
+        })
+    }
+
+    fn as_raw(&self) -> *mut bindings::of_changeset {
+        self.cs.get()
+    }
+
+    /// Records the creation of a node named `full_name` under `parent`.
+    ///
+    /// The node is attached to the tree when the changeset is applied; properties can be added to
+    /// it before that.
+    pub fn create_node(&self, parent: &Node, full_name: &CStr) -> Result<ARef<Node>> {
+        // SAFETY: The changeset is initialised, `parent` is a valid node and `full_name` a valid
+        // C string.
+        let np = unsafe {
+            bindings::of_changeset_create_node(
+                self.as_raw(),
+                parent.as_raw(),
+                full_name.as_char_ptr(),
+            )
+        };
+        let np = NonNull::new(np.cast::<Node>()).ok_or(ENOMEM)?;
+        // SAFETY: The node was just created with a reference for the caller, which is owned by
+        // the returned `ARef`. The changeset holds a reference of its own.
+        Ok(unsafe { ARef::from_raw(np) })
+    }
+
+    /// Records the attachment of `np` to the tree.
+    pub fn attach_node(&self, np: &Node) -> Result {
+        // SAFETY: The changeset is initialised and `np` is a valid node.
+        to_result(unsafe { bindings::of_changeset_attach_node(self.as_raw(), np.as_raw()) })
+    }
+
+    /// Records the detachment of `np` from the tree.
+    pub fn detach_node(&self, np: &Node) -> Result {
+        // SAFETY: The changeset is initialised and `np` is a valid node.
+        to_result(unsafe { bindings::of_changeset_detach_node(self.as_raw(), np.as_raw()) })
+    }
+
+    /// Records the addition of a string property to `np`.
+    pub fn add_prop_string(&self, np: &Node, name: &CStr, value: &CStr) -> Result {
+        // SAFETY: The changeset is initialised, `np` is a valid node and the strings are valid C
+        // strings. They are copied, so they do not need to outlive the call.
+        to_result(unsafe {
+            bindings::of_changeset_add_prop_string(
+                self.as_raw(),
+                np.as_raw(),
+                name.as_char_ptr(),
+                value.as_char_ptr(),
+            )
+        })
+    }
+
+    /// Records the addition of a string list property to `np`, e.g., `compatible`.
+    pub fn add_prop_string_array(&self, np: &Node, name: &CStr, values: &[&CStr]) -> Result {
+        let mut ptrs = Vec::new();
+        for v in values {
+            ptrs.push(v.as_char_ptr(), GFP_KERNEL)?;
+        }
+        // SAFETY: The changeset is initialised, `np` is a valid node, and `ptrs` holds
+        // `values.len()` valid C strings. Everything is copied, so it does not need to outlive
+        // the call.
+        to_result(unsafe {
+            bindings::of_changeset_add_prop_string_array(
+                self.as_raw(),
+                np.as_raw(),
+                name.as_char_ptr(),
+                ptrs.as_mut_ptr(),
+                ptrs.len(),
+            )
+        })
+    }
+
+    /// Records the addition of a property holding an array of `u32` cells to `np`.
+    pub fn add_prop_u32_array(&self, np: &Node, name: &CStr, values: &[u32]) -> Result {
+        // SAFETY: The changeset is initialised, `np` is a valid node, `name` is a valid C string
+        // and `values` is valid for reads of `values.len()` cells. Everything is copied, so it
+        // does not need to outlive the call.
+        to_result(unsafe {
+            bindings::of_changeset_add_prop_u32_array(
+                self.as_raw(),
+                np.as_raw(),
+                name.as_char_ptr(),
+                values.as_ptr(),
+                values.len(),
+            )
+        })
+    }
+
+    /// Records the addition of an empty property to `np`, e.g., a boolean flag or `ranges`.
+    pub fn add_prop_bool(&self, np: &Node, name: &CStr) -> Result {
+        // An empty array of cells gives a property without a value.
+        self.add_prop_u32_array(np, name, &[])
+    }
+
+    /// Applies the recorded changes to the live tree.
+    ///
+    /// Fails with [`EBUSY`] if the changeset is already applied. If applying fails, the changes
+    /// that were applied are reverted.
+    pub fn apply(&self) -> Result {
+        if self.applied.get() {
+            return Err(EBUSY);
+        }
+        // SAFETY: The changeset is initialised and not applied.
+        to_result(unsafe { bindings::of_changeset_apply(self.as_raw()) })?;
+        // INVARIANT: The changeset was just applied.
+        self.applied.set(true);
+        Ok(())
+    }
+
+    /// Reverts the changes, restoring the tree to its state before [`Changeset::apply`].
+    ///
+    /// Fails with [`EINVAL`] if the changeset is not applied.
+    pub fn revert(&self) -> Result {
+        if !self.applied.get() {
+            return Err(EINVAL);
+        }
+        // SAFETY: The changeset is initialised and applied.
+        to_result(unsafe { bindings::of_changeset_revert(self.as_raw()) })?;
+        // INVARIANT: The changeset was just reverted.
+        self.applied.set(false);
+        Ok(())
+    }
+}
+
+#[pinned_drop]
+impl PinnedDrop for Changeset {
+    fn drop(self: Pin<&mut Self>) {
+        if self.applied.get() {
+            if let Err(e) = self.revert() {
+                pr_err!("failed to revert devicetree changeset: {:?}\n", e);
+            }
+        }
+        // SAFETY: The changeset is initialised, and it is not used after this.
+        unsafe { bindings::of_changeset_destroy(self.as_raw()) };
+    }
+}
+
+// SAFETY: The changeset can be applied, reverted and destroyed from any thread; the OF core
+// serialises changes to the tree.
+unsafe impl Send for Changeset {}
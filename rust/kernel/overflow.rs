@@ -0,0 +1,129 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Overflow-checked arithmetic helpers.
+//!
+//! Allocation sizes are frequently computed from values controlled by userspace or by hardware.
+//! The `size_*` and `*_size` helpers below follow the C conventions: instead of wrapping around,
+//! they saturate to [`usize::MAX`] on overflow. Since no allocation of that size can ever succeed,
+//! passing the result straight to an allocator is safe and turns an overflow into an allocation
+//! failure.
+//!
+//! When the caller needs to know about the overflow itself, the `checked_*` helpers return
+//! [`None`] instead.
+//!
+//! C header: [`include/linux/overflow.h`](srctree/include/linux/overflow.h)
+//!
+//! # Examples
+//!
+//! ```
+//! use kernel::overflow::{array_size, checked_mul_add, size_add, struct_size};
+//!
+//! assert_eq!(size_add(1, 2), 3);
+//! assert_eq!(size_add(usize::MAX, 1), usize::MAX);
+//! assert_eq!(array_size(usize::MAX / 2, 3), usize::MAX);
+//! assert_eq!(checked_mul_add(4, 8, 2), Some(34));
+//! assert_eq!(checked_mul_add(usize::MAX, 2, 0), None);
+//!
+//! #[repr(C)]
+//! struct Header {
+//!     count: u32,
+//!     flags: u32,
+//! }
+//!
+//! assert_eq!(struct_size::<Header, u64>(4), 8 + 4 * 8);
+//! assert_eq!(struct_size::<Header, u64>(usize::MAX), usize::MAX);
+//! ```
+
+use core::mem::size_of;
+
+/// Adds two sizes, saturating to [`usize::MAX`] on overflow.
+///
+/// Equivalent to the C `size_add` helper.
+#[inline]
+pub const fn size_add(a: usize, b: usize) -> usize {
+    a.saturating_add(b)
+}
+
+/// Subtracts `b` from `a`, saturating to [`usize::MAX`] on underflow.
+///
+/// To preserve a previous saturation, the result is also [`usize::MAX`] if either argument is
+/// [`usize::MAX`]. Equivalent to the C `size_sub` helper.
+#[inline]
+pub const fn size_sub(a: usize, b: usize) -> usize {
+    if a == usize::MAX || b == usize::MAX {
+        return usize::MAX;
+    }
+
+    match a.checked_sub(b) {
+        Some(v) => v,
+        None => usize::MAX,
+    }
+}
+
+/// Multiplies two sizes, saturating to [`usize::MAX`] on overflow.
+///
+/// Equivalent to the C `size_mul` helper.
+#[inline]
+pub const fn size_mul(a: usize, b: usize) -> usize {
+    a.saturating_mul(b)
+}
+
+/// Calculates the size of a 2-dimensional array, saturating to [`usize::MAX`] on overflow.
+///
+/// Equivalent to the C `array_size` helper.
+#[inline]
+pub const fn array_size(a: usize, b: usize) -> usize {
+    size_mul(a, b)
+}
+
+/// Calculates the size of a 3-dimensional array, saturating to [`usize::MAX`] on overflow.
+///
+/// Equivalent to the C `array3_size` helper.
+#[inline]
+pub const fn array3_size(a: usize, b: usize, c: usize) -> usize {
+    size_mul(size_mul(a, b), c)
+}
+
+/// Calculates the size of `count` trailing elements of type `E`, saturating to [`usize::MAX`] on
+/// overflow.
+///
+/// Equivalent to the C `flex_array_size` helper.
+#[inline]
+pub const fn flex_array_size<E>(count: usize) -> usize {
+    size_mul(size_of::<E>(), count)
+}
+
+/// Calculates the size of a header `H` followed by `count` trailing elements of type `E`,
+/// saturating to [`usize::MAX`] on overflow.
+///
+/// Equivalent to the C `struct_size` helper, where `H` takes the role of the structure without
+/// its flexible array member.
+#[inline]
+pub const fn struct_size<H, E>(count: usize) -> usize {
+    size_add(size_of::<H>(), flex_array_size::<E>(count))
+}
+
+/// Computes `a * b + c`, returning [`None`] if any of the operations overflows.
+///
+/// This is the checked counterpart of combining [`size_mul`] and [`size_add`], useful when the
+/// caller must reject the request rather than let an allocation fail.
+#[inline]
+pub const fn checked_mul_add(a: usize, b: usize, c: usize) -> Option<usize> {
+    match a.checked_mul(b) {
+        Some(v) => v.checked_add(c),
+        None => None,
+    }
+}
+
+/// Calculates the size of a 2-dimensional array, returning [`None`] on overflow.
+#[inline]
+pub const fn checked_array_size(a: usize, b: usize) -> Option<usize> {
+    a.checked_mul(b)
+}
+
+/// Calculates the size of a header `H` followed by `count` trailing elements of type `E`,
+/// returning [`None`] on overflow.
+#[inline]
+pub const fn checked_struct_size<H, E>(count: usize) -> Option<usize> {
+    checked_mul_add(size_of::<E>(), count, size_of::<H>())
+}
@@ -0,0 +1,236 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Kernel command line parameters.
+//!
+//! Module parameters only cover loadable modules and the `module.param` syntax. Built-in code that
+//! needs boot-time configuration can query the command line with [`get`] or register a handler
+//! that runs during early boot with [`early_param!`].
+//!
+//! C header: [`include/linux/init.h`](srctree/include/linux/init.h)
+
+use crate::{bindings, str::BStr};
+use core::ffi::{c_char, c_int};
+
+/// Returns `true` if the parameter names `a` and `b` are equal.
+///
+/// As in C, dashes and underscores are interchangeable.
+fn parameq(a: &[u8], b: &[u8]) -> bool {
+    let norm = |c: u8| if c == b'-' { b'_' } else { c };
+    a.len() == b.len() && a.iter().zip(b).all(|(&x, &y)| norm(x) == norm(y))
+}
+
+/// An iterator over the parameters of a command line.
+///
+/// Each item is the name of a parameter and its value, if it has one. Values may be quoted to
+/// contain spaces, e.g., `foo="bar baz"`; the quotes are not part of the value. Iteration stops at
+/// `--`, which separates the kernel parameters from those of `init`.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{b_str, params::Args};
+///
+/// let mut args = Args::new(b"quiet  console=ttyAMA0,115200 dyndbg=\"file foo.c +p\" -- single");
+/// assert_eq!(args.next(), Some((b_str!("quiet"), None)));
+/// assert_eq!(args.next(), Some((b_str!("console"), Some(b_str!("ttyAMA0,115200")))));
+/// assert_eq!(args.next(), Some((b_str!("dyndbg"), Some(b_str!("file foo.c +p")))));
+/// assert_eq!(args.next(), None);
+/// ```
+#[derive(Clone)]
+pub struct Args<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Args<'a> {
+    /// Creates an iterator over the parameters of the command line `cmdline`.
+    pub fn new(cmdline: &'a [u8]) -> Self {
+        Self { rest: cmdline }
+    }
+
+    /// Returns the value of the last occurrence of the parameter `name`.
+    ///
+    /// A parameter given without `=` has an empty value. Returns [`None`] if the parameter is not
+    /// present.
+    pub fn get(self, name: &str) -> Option<&'a BStr> {
+        self.filter(|(param, _)| parameq(param, name.as_bytes()))
+            .last()
+            .map(|(_, value)| value.unwrap_or(BStr::from_bytes(b"")))
+    }
+}
+
+impl<'a> Iterator for Args<'a> {
+    type Item = (&'a BStr, Option<&'a BStr>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self
+            .rest
+            .iter()
+            .position(|c| !c.is_ascii_whitespace())
+            .unwrap_or(self.rest.len());
+        let args = &self.rest[start..];
+        if args.is_empty() {
+            self.rest = args;
+            return None;
+        }
+
+        // Find the end of the argument, which may contain quoted spaces, and the first `=`.
+        let mut in_quote = false;
+        let mut equals = None;
+        let mut end = args.len();
+        for (i, &c) in args.iter().enumerate() {
+            if c.is_ascii_whitespace() && !in_quote {
+                end = i;
+                break;
+            }
+            if c == b'=' && equals.is_none() {
+                equals = Some(i);
+            }
+            if c == b'"' {
+                in_quote = !in_quote;
+            }
+        }
+        self.rest = &args[end..];
+
+        let unquote = |s: &'a [u8]| {
+            let s = s.strip_prefix(b"\"").unwrap_or(s);
+            s.strip_suffix(b"\"").unwrap_or(s)
+        };
+        let (param, value) = match equals {
+            Some(i) => (&args[..i], Some(unquote(&args[i + 1..end]))),
+            None => (unquote(&args[..end]), None),
+        };
+        if param == b"--" && value.is_none() {
+            self.rest = &[];
+            return None;
+        }
+
+        Some((BStr::from_bytes(param), value.map(BStr::from_bytes)))
+    }
+}
+
+/// Returns an iterator over the parameters of the kernel command line.
+///
+/// The command line is empty until it has been saved early during boot, before any initcall runs.
+pub fn args() -> Args<'static> {
+    // SAFETY: `saved_command_line` is set once during boot and never freed or modified after.
+    let cmdline = unsafe { bindings::saved_command_line };
+    if cmdline.is_null() {
+        return Args::new(&[]);
+    }
+    // SAFETY: `cmdline` is a valid C string that lives until the system shuts down.
+    let cmdline = unsafe { crate::str::CStr::from_char_ptr(cmdline) };
+    Args::new(cmdline.as_bytes())
+}
+
+/// Returns the value of the kernel command line parameter `name`, e.g., `foo.bar`.
+///
+/// A parameter given without `=` has an empty value. If the parameter is given more than once,
+/// the last value is returned. Returns [`None`] if the parameter is not present.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{params, prelude::*};
+///
+/// if params::get("mydrv.polling").is_some_and(|v| v.is_empty() || &**v == b"1") {
+///     pr_info!("polling mode\n");
+/// }
+/// ```
+pub fn get(name: &str) -> Option<&'static BStr> {
+    args().get(name)
+}
+
+/// An entry of the table of boot-time parameter handlers.
+///
+/// Use [`early_param!`] to create one.
+#[doc(hidden)]
+#[repr(transparent)]
+pub struct ObsKernelParam(bindings::obs_kernel_param);
+
+// SAFETY: The entry is only read by the kernel during boot; the name it points to is static.
+unsafe impl Sync for ObsKernelParam {}
+
+impl ObsKernelParam {
+    /// Creates an entry for the early parameter `name` that calls `setup`.
+    #[doc(hidden)]
+    pub const fn new_early(
+        name: &'static crate::str::CStr,
+        setup: unsafe extern "C" fn(*mut c_char) -> c_int,
+    ) -> Self {
+        Self(bindings::obs_kernel_param {
+            str_: name.as_char_ptr(),
+            setup_func: Some(setup),
+            early: 1,
+        })
+    }
+}
+
+/// Calls the early parameter handler `f` with the C value `val`.
+///
+/// # Safety
+///
+/// `val` must be null or a valid C string.
+#[doc(hidden)]
+pub unsafe fn call_early(
+    val: *const c_char,
+    f: fn(Option<&BStr>) -> crate::error::Result,
+) -> c_int {
+    let val: Option<&BStr> = if val.is_null() {
+        None
+    } else {
+        // SAFETY: By the safety requirements, `val` is a valid C string.
+        Some(unsafe { crate::str::CStr::from_char_ptr(val) }.as_ref())
+    };
+    match f(val) {
+        Ok(()) => 0,
+        Err(e) => e.to_errno(),
+    }
+}
+
+/// Registers a handler for an early kernel command line parameter.
+///
+/// The handler is called during early boot, before any initcall runs, if the parameter `name` is
+/// present on the command line. It receives the value of the parameter, or [`None`] if it was
+/// given without `=`, and is called once for each occurrence. If it fails, a warning about a
+/// malformed option is printed and boot continues.
+///
+/// The slab allocator is not available yet at that point, so handlers usually just record the value
+/// in a static variable.
+///
+/// Like in C, this only has an effect in built-in code; the handler is ignored in loadable
+/// modules, which should use module parameters instead.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{early_param, prelude::*, str::BStr};
+/// use core::sync::atomic::{AtomicU32, Ordering};
+///
+/// static WATERMARK: AtomicU32 = AtomicU32::new(16);
+///
+/// fn parse_watermark(val: Option<&BStr>) -> Result {
+///     let val = core::str::from_utf8(val.ok_or(EINVAL)?).map_err(|_| EINVAL)?;
+///     WATERMARK.store(val.parse().map_err(|_| EINVAL)?, Ordering::Relaxed);
+///     Ok(())
+/// }
+///
+/// early_param!("mydrv.watermark", parse_watermark);
+/// ```
+#[macro_export]
+macro_rules! early_param {
+    ($name:literal, $handler:path $(,)?) => {
+        #[cfg(not(MODULE))]
+        const _: () = {
+            unsafe extern "C" fn __setup(val: *mut core::ffi::c_char) -> core::ffi::c_int {
+                // SAFETY: The kernel passes either null or the value of the parameter, which is a
+                // valid C string.
+                unsafe { $crate::params::call_early(val, $handler) }
+            }
+
+            #[used]
+            #[link_section = ".init.setup"]
+            static __PARAM: $crate::params::ObsKernelParam =
+                $crate::params::ObsKernelParam::new_early($crate::c_str!($name), __setup);
+        };
+    };
+}
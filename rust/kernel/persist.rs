@@ -0,0 +1,458 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Persistent storage of small driver values.
+//!
+//! Drivers that need to keep a handful of values across reboots, e.g., calibration results or
+//! counters, open a [`Store`] on whatever backend the platform provides for them, and read and
+//! write named values through it. Updates are atomic: after a power loss, a value is either the
+//! old or the new one.
+//!
+//! Two backends are supported:
+//!
+//! - An area of an nvmem device, e.g., an EEPROM. The area is split into two halves that hold
+//!   the whole set of values each, with a sequence number and a checksum. Updates write the
+//!   half that is not current, so the other one stays intact until the update is complete.
+//! - EFI variables, one per value, which the firmware updates atomically.
+//!
+//! C headers: [`include/linux/nvmem-consumer.h`](srctree/include/linux/nvmem-consumer.h) and
+//! [`include/linux/efi.h`](srctree/include/linux/efi.h)
+
+use crate::{bindings, device::RawDevice, prelude::*};
+
+/// A backend of a [`Store`] that a platform may provide.
+#[derive(Clone, Copy)]
+pub enum Source<'a> {
+    /// An area of the nvmem device named `name` in the firmware description of the device, at
+    /// `offset` and `size` bytes long.
+    Nvmem {
+        /// The name of the nvmem device, as listed in `nvmem-names`.
+        name: &'a CStr,
+        /// The offset of the area, in bytes.
+        offset: u32,
+        /// The size of the area, in bytes.
+        size: u32,
+    },
+    /// EFI variables of the given vendor GUID, named after the keys.
+    Efi {
+        /// The vendor GUID of the variables, which should be unique to the driver.
+        guid: [u8; 16],
+    },
+}
+
+/// The maximum length of a key, in bytes.
+pub const MAX_KEY_LEN: usize = 32;
+
+/// The maximum length of a value, in bytes.
+pub const MAX_VALUE_LEN: usize = 1024;
+
+#[cfg(CONFIG_NVMEM)]
+const MAGIC: u32 = 0x3156_4b52;
+#[cfg(CONFIG_NVMEM)]
+const HEADER_LEN: usize = 16;
+
+fn check_key(key: &CStr) -> Result {
+    let key = key.as_bytes();
+    if key.is_empty() || key.len() > MAX_KEY_LEN || !key.is_ascii() {
+        return Err(EINVAL);
+    }
+    Ok(())
+}
+
+#[cfg(CONFIG_NVMEM)]
+fn crc32(data: &[u8]) -> u32 {
+    // SAFETY: `data` is valid for reads of its length.
+    unsafe { bindings::crc32_le(!0, data.as_ptr(), data.len()) ^ !0 }
+}
+
+fn zeroed(len: usize) -> Result<Vec<u8>> {
+    let mut v = Vec::with_capacity(len, GFP_KERNEL)?;
+    for _ in 0..len {
+        v.push(0, GFP_KERNEL)?;
+    }
+    Ok(v)
+}
+
+#[cfg(CONFIG_NVMEM)]
+fn le32(b: &[u8]) -> u32 {
+    u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+}
+
+/// An nvmem area holding two copies of the encoded values.
+///
+/// Each half starts with a header of the magic, a sequence number, the length of the payload and
+/// its CRC32, all little endian, followed by the payload: entries of a key length byte, a
+/// little-endian 16-bit value length, the key and the value.
+#[cfg(CONFIG_NVMEM)]
+struct Nvmem {
+    nvmem: *mut bindings::nvmem_device,
+    offset: u32,
+    half: usize,
+    /// The sequence number of the current copy and the half it is in.
+    current: Option<(u32, usize)>,
+    payload: Vec<u8>,
+}
+
+#[cfg(CONFIG_NVMEM)]
+impl Nvmem {
+    fn open(dev: &impl RawDevice, name: &CStr, offset: u32, size: u32) -> Result<Self> {
+        let half = size as usize / 2;
+        if half <= HEADER_LEN {
+            return Err(EINVAL);
+        }
+        // SAFETY: `dev.raw_device()` is valid and `name` is a valid C string.
+        let nvmem = crate::error::from_err_ptr(unsafe {
+            bindings::nvmem_device_get(dev.raw_device(), name.as_char_ptr())
+        })?;
+        let mut this = Self {
+            nvmem,
+            offset,
+            half,
+            current: None,
+            payload: Vec::new(),
+        };
+        // SAFETY: `nvmem` is valid.
+        if u64::from(offset) + u64::from(size)
+            > unsafe { bindings::nvmem_device_size(nvmem) } as u64
+        {
+            return Err(EINVAL);
+        }
+        this.load()?;
+        Ok(this)
+    }
+
+    fn read(&self, at: usize, buf: &mut [u8]) -> Result {
+        // SAFETY: `nvmem` is valid, and `buf` is valid for writes of its length.
+        let ret = unsafe {
+            bindings::nvmem_device_read(
+                self.nvmem,
+                self.offset + at as u32,
+                buf.len(),
+                buf.as_mut_ptr().cast(),
+            )
+        };
+        if ret < 0 {
+            return Err(Error::from_errno(ret));
+        }
+        if ret as usize != buf.len() {
+            return Err(EIO);
+        }
+        Ok(())
+    }
+
+    fn write(&self, at: usize, buf: &[u8]) -> Result {
+        // SAFETY: `nvmem` is valid, and `buf` is valid for reads of its length. The function does
+        // not write to the buffer.
+        let ret = unsafe {
+            bindings::nvmem_device_write(
+                self.nvmem,
+                self.offset + at as u32,
+                buf.len(),
+                buf.as_ptr() as *mut _,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::from_errno(ret));
+        }
+        if ret as usize != buf.len() {
+            return Err(EIO);
+        }
+        Ok(())
+    }
+
+    /// Reads the valid copy with the highest sequence number, if any.
+    fn load(&mut self) -> Result {
+        for i in 0..2 {
+            let mut hdr = [0u8; HEADER_LEN];
+            self.read(i * self.half, &mut hdr)?;
+            let (seq, len, crc) = (le32(&hdr[4..]), le32(&hdr[8..]) as usize, le32(&hdr[12..]));
+            if le32(&hdr) != MAGIC || len > self.half - HEADER_LEN {
+                continue;
+            }
+            if matches!(self.current, Some((cur, _)) if seq.wrapping_sub(cur) as i32 <= 0) {
+                continue;
+            }
+            let mut payload = zeroed(len)?;
+            self.read(i * self.half + HEADER_LEN, &mut payload)?;
+            if crc32(&payload) == crc {
+                self.current = Some((seq, i));
+                self.payload = payload;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the offset and length of the entry for `key` in the payload, and the offset and
+    /// length of its value.
+    fn find(&self, key: &[u8]) -> Option<(usize, usize, usize, usize)> {
+        let p = &self.payload;
+        let mut i = 0;
+        while i + 3 <= p.len() {
+            let klen = p[i] as usize;
+            let vlen = u16::from_le_bytes([p[i + 1], p[i + 2]]) as usize;
+            let len = 3 + klen + vlen;
+            if i + len > p.len() {
+                break;
+            }
+            if &p[i + 3..i + 3 + klen] == key {
+                return Some((i, len, i + 3 + klen, vlen));
+            }
+            i += len;
+        }
+        None
+    }
+
+    fn get(&self, key: &[u8], buf: &mut [u8]) -> Result<usize> {
+        let (_, _, at, len) = self.find(key).ok_or(ENOENT)?;
+        let n = len.min(buf.len());
+        buf[..n].copy_from_slice(&self.payload[at..at + n]);
+        Ok(len)
+    }
+
+    fn set(&mut self, key: &[u8], value: Option<&[u8]>) -> Result {
+        // Build the new payload without the old entry, then append the new one.
+        let mut payload = Vec::with_capacity(self.payload.len() + 3 + key.len(), GFP_KERNEL)?;
+        match self.find(key) {
+            Some((at, len, _, _)) => {
+                payload.extend_from_slice(&self.payload[..at], GFP_KERNEL)?;
+                payload.extend_from_slice(&self.payload[at + len..], GFP_KERNEL)?;
+            }
+            None if value.is_none() => return Ok(()),
+            None => payload.extend_from_slice(&self.payload, GFP_KERNEL)?,
+        }
+        if let Some(value) = value {
+            payload.push(key.len() as u8, GFP_KERNEL)?;
+            payload.extend_from_slice(&(value.len() as u16).to_le_bytes(), GFP_KERNEL)?;
+            payload.extend_from_slice(key, GFP_KERNEL)?;
+            payload.extend_from_slice(value, GFP_KERNEL)?;
+        }
+        if payload.len() > self.half - HEADER_LEN {
+            return Err(ENOSPC);
+        }
+
+        // Write the half that does not hold the current copy: first the payload, then the header
+        // that makes it valid.
+        let (seq, i) = match self.current {
+            Some((seq, i)) => (seq.wrapping_add(1), 1 - i),
+            None => (0, 0),
+        };
+        let mut hdr = [0u8; HEADER_LEN];
+        hdr[..4].copy_from_slice(&MAGIC.to_le_bytes());
+        hdr[4..8].copy_from_slice(&seq.to_le_bytes());
+        hdr[8..12].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        hdr[12..].copy_from_slice(&crc32(&payload).to_le_bytes());
+        self.write(i * self.half + HEADER_LEN, &payload)?;
+        self.write(i * self.half, &hdr)?;
+
+        self.current = Some((seq, i));
+        self.payload = payload;
+        Ok(())
+    }
+}
+
+#[cfg(CONFIG_NVMEM)]
+impl Drop for Nvmem {
+    fn drop(&mut self) {
+        // SAFETY: `nvmem` was returned by `nvmem_device_get`.
+        unsafe { bindings::nvmem_device_put(self.nvmem) };
+    }
+}
+
+/// EFI variables of one vendor GUID.
+#[cfg(CONFIG_EFI)]
+struct Efi {
+    guid: bindings::efi_guid_t,
+}
+
+#[cfg(CONFIG_EFI)]
+impl Efi {
+    const ATTRS: u32 = bindings::EFI_VARIABLE_NON_VOLATILE
+        | bindings::EFI_VARIABLE_BOOTSERVICE_ACCESS
+        | bindings::EFI_VARIABLE_RUNTIME_ACCESS;
+
+    fn open(guid: [u8; 16]) -> Result<Self> {
+        // SAFETY: The function can be called at any time.
+        if !unsafe { bindings::efivar_is_available() } {
+            return Err(ENODEV);
+        }
+        Ok(Self {
+            guid: bindings::efi_guid_t { b: guid },
+        })
+    }
+
+    /// Returns `key` as a NUL-terminated UCS-2 variable name.
+    fn name(key: &[u8]) -> [u16; MAX_KEY_LEN + 1] {
+        let mut name = [0; MAX_KEY_LEN + 1];
+        for (d, s) in name.iter_mut().zip(key) {
+            *d = u16::from(*s);
+        }
+        name
+    }
+
+    fn status(status: bindings::efi_status_t) -> Result {
+        if status == bindings::EFI_SUCCESS as _ {
+            return Ok(());
+        }
+        // SAFETY: The function can be called with any status.
+        Err(Error::from_errno(unsafe {
+            bindings::efi_status_to_err(status)
+        }))
+    }
+
+    fn get(&mut self, key: &[u8], buf: &mut [u8]) -> Result<usize> {
+        let mut name = Self::name(key);
+        let mut data = zeroed(MAX_VALUE_LEN)?;
+        let mut size = data.len() as core::ffi::c_ulong;
+        // SAFETY: The function can be called at any time.
+        crate::error::to_result(unsafe { bindings::efivar_lock() })?;
+        // SAFETY: The lock is held, `name` is a NUL-terminated UCS-2 string and `data` is valid
+        // for writes of `size` bytes.
+        let status = unsafe {
+            bindings::efivar_get_variable(
+                name.as_mut_ptr(),
+                &mut self.guid,
+                core::ptr::null_mut(),
+                &mut size,
+                data.as_mut_ptr().cast(),
+            )
+        };
+        // SAFETY: The lock was taken above.
+        unsafe { bindings::efivar_unlock() };
+        Self::status(status)?;
+        let len = size as usize;
+        let n = len.min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        Ok(len)
+    }
+
+    fn set(&mut self, key: &[u8], value: Option<&[u8]>) -> Result {
+        let mut name = Self::name(key);
+        // Writing an empty variable deletes it.
+        let value = value.unwrap_or(&[]);
+        // SAFETY: `name` is a NUL-terminated UCS-2 string and `value` is valid for reads of its
+        // length. The firmware does not write to it.
+        let status = unsafe {
+            bindings::efivar_set_variable(
+                name.as_mut_ptr(),
+                &mut self.guid,
+                Self::ATTRS,
+                value.len() as _,
+                value.as_ptr() as *mut _,
+            )
+        };
+        match Self::status(status) {
+            Err(e) if e == ENOENT && value.is_empty() => Ok(()),
+            r => r,
+        }
+    }
+}
+
+enum Backend {
+    #[cfg(CONFIG_NVMEM)]
+    Nvmem(Nvmem),
+    #[cfg(CONFIG_EFI)]
+    Efi(Efi),
+}
+
+/// A persistent store of named values.
+///
+/// Keys are ASCII strings of at most [`MAX_KEY_LEN`] bytes, and values are byte strings of at
+/// most [`MAX_VALUE_LEN`] bytes. Writes go to the backend right away; they take `&mut self`, so
+/// drivers that write from several contexts need to serialise them, e.g., with a mutex.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{c_str, device::RawDevice, persist::{Source, Store}, prelude::*};
+///
+/// fn save_offset(dev: &impl RawDevice, offset: i32) -> Result {
+///     let mut store = Store::open(
+///         dev,
+///         &[
+///             Source::Nvmem { name: c_str!("calibration"), offset: 0, size: 512 },
+///             Source::Efi { guid: *b"my-sensor-driver" },
+///         ],
+///     )?;
+///     let mut old = [0; 4];
+///     if store.read(c_str!("offset"), &mut old).is_ok() && i32::from_le_bytes(old) == offset {
+///         return Ok(());
+///     }
+///     store.write(c_str!("offset"), &offset.to_le_bytes())
+/// }
+/// ```
+pub struct Store {
+    backend: Backend,
+}
+
+impl Store {
+    /// Opens the store on the first of `sources` that the platform provides.
+    ///
+    /// Fails with [`ENODEV`] if none of them is available.
+    #[cfg_attr(not(CONFIG_NVMEM), allow(unused_variables))]
+    pub fn open(dev: &impl RawDevice, sources: &[Source<'_>]) -> Result<Self> {
+        for source in sources {
+            let backend = match *source {
+                #[cfg(CONFIG_NVMEM)]
+                Source::Nvmem { name, offset, size } => {
+                    Nvmem::open(dev, name, offset, size).map(Backend::Nvmem)
+                }
+                #[cfg(CONFIG_EFI)]
+                Source::Efi { guid } => Efi::open(guid).map(Backend::Efi),
+                #[allow(unreachable_patterns)]
+                _ => Err(ENODEV),
+            };
+            match backend {
+                Ok(backend) => return Ok(Self { backend }),
+                // The nvmem provider may probe later.
+                Err(e) if e == EPROBE_DEFER => return Err(e),
+                Err(_) => continue,
+            }
+        }
+        Err(ENODEV)
+    }
+
+    /// Reads the value of `key` into `buf`, and returns its length.
+    ///
+    /// If the value is longer than `buf`, only the beginning is read. Fails with [`ENOENT`] if
+    /// there is no value for `key`.
+    pub fn read(&mut self, key: &CStr, buf: &mut [u8]) -> Result<usize> {
+        check_key(key)?;
+        match &mut self.backend {
+            #[cfg(CONFIG_NVMEM)]
+            Backend::Nvmem(n) => n.get(key.as_bytes(), buf),
+            #[cfg(CONFIG_EFI)]
+            Backend::Efi(e) => e.get(key.as_bytes(), buf),
+        }
+    }
+
+    /// Sets the value of `key` to `value`, atomically.
+    ///
+    /// Fails with [`ENOSPC`] if the backend is out of space, in which case the old value is kept.
+    pub fn write(&mut self, key: &CStr, value: &[u8]) -> Result {
+        check_key(key)?;
+        if value.is_empty() || value.len() > MAX_VALUE_LEN {
+            return Err(EINVAL);
+        }
+        self.set(key, Some(value))
+    }
+
+    /// Deletes the value of `key`, if there is one.
+    pub fn remove(&mut self, key: &CStr) -> Result {
+        check_key(key)?;
+        self.set(key, None)
+    }
+
+    fn set(&mut self, key: &CStr, value: Option<&[u8]>) -> Result {
+        match &mut self.backend {
+            #[cfg(CONFIG_NVMEM)]
+            Backend::Nvmem(n) => n.set(key.as_bytes(), value),
+            #[cfg(CONFIG_EFI)]
+            Backend::Efi(e) => e.set(key.as_bytes(), value),
+        }
+    }
+}
+
+// SAFETY: The nvmem device and EFI variables can be accessed from any thread.
+unsafe impl Send for Store {}
+
+// SAFETY: `Store` has no methods that take `&self`.
+unsafe impl Sync for Store {}
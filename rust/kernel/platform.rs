@@ -9,9 +9,10 @@
 use crate::{
     bindings,
     device::{self, RawDevice},
-    driver,
-    error::{from_result, to_result, Result},
-    of,
+    driver::{self, Adapter as _},
+    error::{code::*, from_result, to_result, Result},
+    io_mem::{self, IoMem, Resource},
+    of, pm,
     str::CStr,
     types::ForeignOwnable,
     ThisModule,
@@ -23,6 +24,14 @@
 /// An adapter for the registration of platform drivers.
 pub struct Adapter<T: Driver>(T);
 
+impl<T: Driver> driver::Adapter for Adapter<T> {
+    type IdInfo = T::IdInfo;
+    type Data = T::Data;
+    const OF_ID_TABLE: Option<driver::IdTable<'static, of::DeviceId, T::IdInfo>> =
+        T::OF_DEVICE_ID_TABLE;
+    const PM_OPS: Option<&'static pm::Ops<T::Data>> = T::PM_OPS;
+}
+
 impl<T: Driver> driver::DriverOps for Adapter<T> {
     type RegType = bindings::platform_driver;
 
@@ -35,12 +44,9 @@ unsafe fn register(
         // `reg` is non-null and valid.
         let pdrv = unsafe { &mut *reg };
 
-        pdrv.driver.name = name.as_char_ptr();
+        Self::init_driver(&mut pdrv.driver, name);
         pdrv.probe = Some(Self::probe_callback);
         pdrv.remove = Some(Self::remove_callback);
-        if let Some(t) = T::OF_DEVICE_ID_TABLE {
-            pdrv.driver.of_match_table = t.as_ref();
-        }
         // SAFETY:
         //   - `pdrv` lives at least until the call to `platform_driver_unregister()` returns.
         //   - `name` pointer has static lifetime.
@@ -60,65 +66,21 @@ unsafe fn unregister(reg: *mut bindings::platform_driver) {
 }
 
 impl<T: Driver> Adapter<T> {
-    fn get_id_info(dev: &Device) -> Option<&'static T::IdInfo> {
-        let table = T::OF_DEVICE_ID_TABLE?;
-
-        // SAFETY: `table` has static lifetime, so it is valid for read. `dev` is guaranteed to be
-        // valid while it's alive, so is the raw device returned by it.
-        let id = unsafe { bindings::of_match_device(table.as_ref(), dev.raw_device()) };
-        if id.is_null() {
-            return None;
-        }
-
-        // SAFETY: `id` is a pointer within the static table, so it's always valid.
-        let offset = unsafe { (*id).data };
-        if offset.is_null() {
-            return None;
-        }
-
-        // SAFETY: The offset comes from a previous call to `offset_from` in `IdArray::new`, which
-        // guarantees that the resulting pointer is within the table.
-        let ptr = unsafe {
-            id.cast::<u8>()
-                .offset(offset as _)
-                .cast::<Option<T::IdInfo>>()
-        };
-
-        // SAFETY: The id table has a static lifetime, so `ptr` is guaranteed to be valid for read.
-        #[allow(clippy::needless_borrow)]
-        unsafe {
-            (&*ptr).as_ref()
-        }
-    }
-
     extern "C" fn probe_callback(pdev: *mut bindings::platform_device) -> core::ffi::c_int {
-        from_result(|| {
-            // SAFETY: `pdev` is valid by the contract with the C code. `dev` is alive only for the
-            // duration of this call, so it is guaranteed to remain alive for the lifetime of
-            // `pdev`.
-            let mut dev = unsafe { Device::from_ptr(pdev) };
-            let info = Self::get_id_info(&dev);
-            let data = T::probe(&mut dev, info)?;
-            // SAFETY: `pdev` is guaranteed to be a valid, non-null pointer.
-            unsafe { bindings::platform_set_drvdata(pdev, data.into_foreign() as _) };
-            Ok(0)
-        })
+        // SAFETY: `pdev` is valid by the contract with the C code. `dev` is alive only for the
+        // duration of this call, so it is guaranteed to remain alive for the lifetime of `pdev`.
+        let mut dev = unsafe { Device::from_ptr(pdev) };
+        // SAFETY: No driver data has been set yet because the device is only now being bound.
+        unsafe { Self::probe_device(&mut dev, |dev| T::probe(dev, Self::of_id_info(dev))) }
     }
 
     extern "C" fn remove_callback(pdev: *mut bindings::platform_device) -> core::ffi::c_int {
         from_result(|| {
             // SAFETY: `pdev` is guaranteed to be a valid, non-null pointer.
-            let ptr = unsafe { bindings::platform_get_drvdata(pdev) };
-            // SAFETY:
-            //   - we allocated this pointer using `T::Data::into_foreign`,
-            //     so it is safe to turn back into a `T::Data`.
-            //   - the allocation happened in `probe`, no-one freed the memory,
-            //     `remove` is the canonical kernel location to free driver data. so OK
-            //     to convert the pointer back to a Rust structure here.
-            let data = unsafe { T::Data::from_foreign(ptr) };
-            let ret = T::remove(&data);
-            <T::Data as driver::DeviceRemoval>::device_remove(&data);
-            ret?;
+            let dev = unsafe { Device::from_ptr(pdev) };
+            // SAFETY: The driver data was set in `probe`, and `remove` is the canonical kernel
+            // location to free it.
+            unsafe { Self::remove_device(&dev, T::remove) }?;
             Ok(0)
         })
     }
@@ -133,7 +95,7 @@ pub trait Driver {
     ///
     /// Require that `Data` implements `ForeignOwnable`. We guarantee to
     /// never move the underlying wrapped data structure. This allows
-    type Data: ForeignOwnable + Send + Sync + driver::DeviceRemoval = ();
+    type Data: ForeignOwnable + Send + Sync + driver::DeviceRemoval + 'static = ();
 
     /// The type holding information about each device id supported by the driver.
     type IdInfo: 'static = ();
@@ -141,10 +103,21 @@ pub trait Driver {
     /// The table of device ids supported by the driver.
     const OF_DEVICE_ID_TABLE: Option<driver::IdTable<'static, of::DeviceId, Self::IdInfo>> = None;
 
+    /// The power management callbacks of the driver, usually created with [`pm::ops`].
+    ///
+    /// [`pm::ops`]: crate::pm::ops
+    const PM_OPS: Option<&'static pm::Ops<Self::Data>> = None;
+
     /// Platform driver probe.
     ///
     /// Called when a new platform device is added or discovered.
     /// Implementers should attempt to initialize the device here.
+    ///
+    /// If a resource the device depends on is not available yet, return [`EPROBE_DEFER`] so that
+    /// probing is retried later; [`dev_err_probe`] records the reason for the deferral.
+    ///
+    /// [`EPROBE_DEFER`]: crate::error::code::EPROBE_DEFER
+    /// [`dev_err_probe`]: crate::dev_err_probe
     fn probe(dev: &mut Device, id_info: Option<&Self::IdInfo>) -> Result<Self::Data>;
 
     /// Platform driver remove.
@@ -182,6 +155,189 @@ pub fn id(&self) -> i32 {
         // SAFETY: By the type invariants, we know that `self.ptr` is non-null and valid.
         unsafe { (*self.ptr).id }
     }
+
+    /// Returns the memory resource of the device at `index`.
+    ///
+    /// Memory resources are numbered in the order they are described in the firmware, e.g., the
+    /// entries of the `reg` devicetree property.
+    pub fn resource_by_index(&self, index: u32) -> Option<Resource> {
+        // SAFETY: By the type invariants, we know that `self.ptr` is non-null and valid.
+        let res =
+            unsafe { bindings::platform_get_resource(self.ptr, io_mem::flags::MEM as _, index) };
+        if res.is_null() {
+            return None;
+        }
+        // SAFETY: `res` is a resource of the device, which is valid while the device is.
+        unsafe { Resource::from_raw(res) }
+    }
+
+    /// Returns the memory resource of the device named `name`.
+    ///
+    /// The names come from the firmware, e.g., the `reg-names` devicetree property.
+    pub fn resource_by_name(&self, name: &CStr) -> Option<Resource> {
+        // SAFETY: By the type invariants, we know that `self.ptr` is non-null and valid. `name`
+        // is a valid C string.
+        let res = unsafe {
+            bindings::platform_get_resource_byname(
+                self.ptr,
+                io_mem::flags::MEM as _,
+                name.as_char_ptr(),
+            )
+        };
+        if res.is_null() {
+            return None;
+        }
+        // SAFETY: `res` is a resource of the device, which is valid while the device is.
+        unsafe { Resource::from_raw(res) }
+    }
+
+    /// Requests and maps the memory resource of the device at `index`.
+    ///
+    /// The region is owned by the returned mapping and released when it is dropped. Fails with
+    /// [`EINVAL`] if there is no such resource or it is smaller than `SIZE`, and with [`EBUSY`]
+    /// if another driver has requested it.
+    pub fn ioremap_resource<const SIZE: usize>(&self, index: u32) -> Result<IoMem<SIZE>> {
+        let res = self.resource_by_index(index).ok_or(EINVAL)?;
+        IoMem::request(self, &res)
+    }
+
+    /// Requests and maps the memory resource of the device named `name`.
+    ///
+    /// See [`Device::ioremap_resource`] for details.
+    pub fn ioremap_resource_by_name<const SIZE: usize>(&self, name: &CStr) -> Result<IoMem<SIZE>> {
+        let res = self.resource_by_name(name).ok_or(EINVAL)?;
+        IoMem::request(self, &res)
+    }
+}
+
+#[cfg(CONFIG_GENERIC_MSI_IRQ)]
+impl Device {
+    /// Allocates `nvec` platform MSI vectors for the device.
+    ///
+    /// The device must be attached to an MSI domain, e.g. through the `msi-parent` property of
+    /// its DT node when it sits behind a GIC ITS. Whenever the irq core composes a message for a
+    /// vector, [`MsiWriter::write_msg`] is called to program it into the device.
+    ///
+    /// The vectors are freed when the returned [`MsiVectors`] is dropped.
+    pub fn alloc_msi_irqs<W: MsiWriter>(&self, nvec: u32) -> Result<MsiVectors<W>> {
+        // SAFETY: By the type invariants, `self.raw_device()` is valid, and `write_msi_msg::<W>`
+        // can be called for the lifetime of the allocation.
+        to_result(unsafe {
+            bindings::platform_msi_domain_alloc_irqs(
+                self.raw_device(),
+                nvec,
+                Some(write_msi_msg::<W>),
+            )
+        })?;
+
+        // INVARIANT: The vectors were allocated successfully above.
+        Ok(MsiVectors {
+            // SAFETY: By the type invariants, `self.raw_device()` is valid.
+            dev: unsafe { device::Device::new(self.raw_device()) },
+            nvec,
+            _p: core::marker::PhantomData,
+        })
+    }
+}
+
+/// A message-signalled interrupt message.
+///
+/// This is the address/data pair the device has to write to in order to raise the interrupt.
+#[cfg(CONFIG_GENERIC_MSI_IRQ)]
+#[repr(transparent)]
+pub struct MsiMsg(bindings::msi_msg);
+
+#[cfg(CONFIG_GENERIC_MSI_IRQ)]
+impl MsiMsg {
+    /// Returns the address the device must write to.
+    pub fn address(&self) -> u64 {
+        // SAFETY: All variants of the unions are plain `u32` values.
+        let (lo, hi) = unsafe {
+            (
+                self.0.__bindgen_anon_1.address_lo,
+                self.0.__bindgen_anon_2.address_hi,
+            )
+        };
+        (u64::from(hi) << 32) | u64::from(lo)
+    }
+
+    /// Returns the data the device must write.
+    pub fn data(&self) -> u32 {
+        // SAFETY: All variants of the union are plain `u32` values.
+        unsafe { self.0.__bindgen_anon_3.data }
+    }
+}
+
+/// Programs MSI messages into a platform device.
+#[cfg(CONFIG_GENERIC_MSI_IRQ)]
+pub trait MsiWriter {
+    /// Writes `msg` into the device registers that correspond to the vector `index`.
+    ///
+    /// Called with the descriptor lock held, possibly in atomic context, so it must not sleep.
+    fn write_msg(dev: &device::Device, index: u32, msg: &MsiMsg);
+}
+
+#[cfg(CONFIG_GENERIC_MSI_IRQ)]
+unsafe extern "C" fn write_msi_msg<W: MsiWriter>(
+    desc: *mut bindings::msi_desc,
+    msg: *mut bindings::msi_msg,
+) {
+    // SAFETY: The irq core passes a valid descriptor, whose `dev` is the device the vectors
+    // were allocated for.
+    let (dev, index) = unsafe { (device::Device::from_raw((*desc).dev), (*desc).msi_index) };
+    // SAFETY: `msg` is valid for the duration of the call, and `MsiMsg` is `repr(transparent)`.
+    let msg = unsafe { &*msg.cast::<MsiMsg>() };
+    W::write_msg(dev, index.into(), msg);
+}
+
+/// Platform MSI vectors allocated by [`Device::alloc_msi_irqs`].
+///
+/// # Invariants
+///
+/// `nvec` platform MSI vectors are allocated for `dev`.
+#[cfg(CONFIG_GENERIC_MSI_IRQ)]
+pub struct MsiVectors<W: MsiWriter> {
+    dev: crate::types::ARef<device::Device>,
+    nvec: u32,
+    _p: core::marker::PhantomData<W>,
+}
+
+#[cfg(CONFIG_GENERIC_MSI_IRQ)]
+impl<W: MsiWriter> MsiVectors<W> {
+    /// Returns the number of allocated vectors.
+    pub fn len(&self) -> u32 {
+        self.nvec
+    }
+
+    /// Returns `true` if no vectors were allocated.
+    pub fn is_empty(&self) -> bool {
+        self.nvec == 0
+    }
+
+    /// Returns the Linux interrupt number of vector `index`.
+    ///
+    /// The result can be passed to [`irq::Registration::try_new`].
+    ///
+    /// [`irq::Registration::try_new`]: crate::irq::Registration::try_new
+    pub fn irq(&self, index: u32) -> Result<u32> {
+        if index >= self.nvec {
+            return Err(crate::error::code::EINVAL);
+        }
+        // SAFETY: By the type invariants, `self.dev` is valid and has MSI vectors allocated.
+        match unsafe { bindings::msi_get_virq(self.dev.as_raw(), index) } {
+            0 => Err(crate::error::code::ENOENT),
+            irq => Ok(irq),
+        }
+    }
+}
+
+#[cfg(CONFIG_GENERIC_MSI_IRQ)]
+impl<W: MsiWriter> Drop for MsiVectors<W> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, the vectors were allocated for `self.dev`. Interrupt
+        // registrations using them must have been dropped already.
+        unsafe { bindings::platform_msi_domain_free_irqs(self.dev.as_raw()) };
+    }
 }
 
 // SAFETY: The device returned by `raw_device` is the raw platform device.
@@ -0,0 +1,461 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Power management.
+//!
+//! Drivers take part in system sleep by implementing [`SleepOps`] and passing [`ops`] to their bus,
+//! e.g., through [`platform::Driver::PM_OPS`]. Besides suspend-to-RAM, this covers the phases of
+//! hibernation, after which buffers the device writes to may hold stale contents; drivers collect
+//! those in a [`RestoreSet`] to re-initialise them on restore.
+//!
+//! Wakeup sources keep the system from suspending while events that user space still has to
+//! handle are in flight, e.g., a key press or an incoming call. The wakeup capability of devices is
+//! configured through [`RawDevice`] methods such as [`RawDevice::init_wakeup`].
+//!
+//! Latency requirements of drivers are expressed with the requests of the [`qos`] module.
+//!
+//! C headers: [`include/linux/pm.h`](srctree/include/linux/pm.h) and
+//! [`include/linux/pm_wakeup.h`](srctree/include/linux/pm_wakeup.h)
+//!
+//! [`platform::Driver::PM_OPS`]: crate::platform::Driver::PM_OPS
+
+use crate::{
+    alloc::{flags::*, vec_ext::VecExt},
+    bindings,
+    device::{Device, RawDevice},
+    dma,
+    error::{code::*, from_result, Result, VTABLE_DEFAULT_ERROR},
+    str::CStr,
+    sync::{Arc, Mutex},
+    types::ForeignOwnable,
+};
+use alloc::vec::Vec;
+use core::{
+    ffi::c_int,
+    marker::PhantomData,
+    ptr::{self, NonNull},
+};
+use macros::vtable;
+
+pub mod qos;
+
+/// System sleep operations of a driver.
+///
+/// The callbacks receive the driver data that the bus stored when the device was probed, so
+/// [`SleepOps::Data`] must be the `Data` type of the driver. This is enforced by [`Ops`], which
+/// carries the data type and is what the buses accept. Each callback is optional:
+///
+/// - Suspend-to-RAM calls [`SleepOps::suspend`] and, on wakeup, [`SleepOps::resume`].
+/// - Hibernation calls [`SleepOps::freeze`] to quiesce the device before the image is created, and
+///   [`SleepOps::thaw`] afterwards so that the image can be written out. [`SleepOps::poweroff`]
+///   then prepares the device for the system to power off.
+/// - When the system boots from the image, [`SleepOps::restore`] is called once the image has been
+///   loaded. The device may have been reset, or initialised differently by the kernel that loaded
+///   the image, so drivers must not rely on its state.
+///
+/// As with `SIMPLE_DEV_PM_OPS` in C, [`SleepOps::freeze`] and [`SleepOps::poweroff`] default to
+/// [`SleepOps::suspend`], and [`SleepOps::thaw`] and [`SleepOps::restore`] to [`SleepOps::resume`].
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{
+///     device::Device,
+///     dma::CoherentAllocation,
+///     driver::DeviceRemoval,
+///     platform, pm,
+///     prelude::*,
+///     sync::{Arc, ArcBorrow, Mutex},
+/// };
+///
+/// struct MyData {
+///     completions: Arc<Mutex<CoherentAllocation>>,
+///     restore: pm::RestoreSet,
+/// }
+///
+/// impl DeviceRemoval for MyData {
+///     fn device_remove(&self) {}
+/// }
+///
+/// struct MyDriver;
+///
+/// impl platform::Driver for MyDriver {
+///     type Data = Arc<MyData>;
+///
+///     // Does not build if `SleepOps::Data` differs from `platform::Driver::Data`.
+///     const PM_OPS: Option<&'static pm::Ops<Arc<MyData>>> = Some(pm::ops::<Self>());
+///
+///     fn probe(_dev: &mut platform::Device, _id_info: Option<&()>) -> Result<Arc<MyData>> {
+/// #       Err(ENODEV)
+///         // [...]
+///     }
+/// }
+///
+/// #[vtable]
+/// impl pm::SleepOps for MyDriver {
+///     type Data = Arc<MyData>;
+///
+///     fn suspend(_data: ArcBorrow<'_, MyData>, _dev: &Device) -> Result {
+///         // Stop the DMA engine.
+///         Ok(())
+///     }
+///
+///     fn resume(_data: ArcBorrow<'_, MyData>, _dev: &Device) -> Result {
+///         // Restart the DMA engine.
+///         Ok(())
+///     }
+///
+///     fn restore(data: ArcBorrow<'_, MyData>, dev: &Device) -> Result {
+///         // The completion ring holds whatever the device wrote before the image was created.
+///         data.restore.reinit_all()?;
+///         Self::resume(data, dev)
+///     }
+/// }
+/// ```
+#[vtable]
+pub trait SleepOps {
+    /// The driver data of the device.
+    type Data: ForeignOwnable + Send + Sync + 'static;
+
+    /// Suspends the device before the system enters a sleep state.
+    fn suspend(_data: <Self::Data as ForeignOwnable>::Borrowed<'_>, _dev: &Device) -> Result {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+
+    /// Resumes the device after the system left a sleep state.
+    fn resume(_data: <Self::Data as ForeignOwnable>::Borrowed<'_>, _dev: &Device) -> Result {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+
+    /// Quiesces the device before the hibernation image is created.
+    ///
+    /// The device must stop DMA and interrupts, but need not be powered down.
+    fn freeze(_data: <Self::Data as ForeignOwnable>::Borrowed<'_>, _dev: &Device) -> Result {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+
+    /// Restarts the device after the hibernation image was created, or creating it failed.
+    fn thaw(_data: <Self::Data as ForeignOwnable>::Borrowed<'_>, _dev: &Device) -> Result {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+
+    /// Prepares the device for the system to power off after the hibernation image was saved.
+    fn poweroff(_data: <Self::Data as ForeignOwnable>::Borrowed<'_>, _dev: &Device) -> Result {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+
+    /// Restores the device after the system was restored from a hibernation image.
+    fn restore(_data: <Self::Data as ForeignOwnable>::Borrowed<'_>, _dev: &Device) -> Result {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+}
+
+/// A table of PM callbacks, to be passed to a bus, for a driver whose data has type `D`.
+///
+/// Created by [`ops`]. The buses only accept tables whose `D` is the `Data` type of the driver,
+/// since the callbacks retrieve the driver data as a `D`.
+#[repr(transparent)]
+pub struct Ops<D>(bindings::dev_pm_ops, PhantomData<fn() -> D>);
+
+// SAFETY: The table is immutable and only contains function pointers.
+unsafe impl<D> Sync for Ops<D> {}
+
+impl<D> Ops<D> {
+    /// Returns a raw pointer to the underlying `struct dev_pm_ops`.
+    pub(crate) fn as_raw(&self) -> *const bindings::dev_pm_ops {
+        &self.0
+    }
+}
+
+/// Returns the table of PM callbacks of `T`.
+pub const fn ops<T: SleepOps>() -> &'static Ops<T::Data> {
+    &SleepAdapter::<T>::OPS
+}
+
+struct SleepAdapter<T: SleepOps>(T);
+
+impl<T: SleepOps> SleepAdapter<T> {
+    const SUSPEND: Option<unsafe extern "C" fn(*mut bindings::device) -> c_int> = if T::HAS_SUSPEND
+    {
+        Some(Self::suspend_callback)
+    } else {
+        None
+    };
+
+    const RESUME: Option<unsafe extern "C" fn(*mut bindings::device) -> c_int> = if T::HAS_RESUME {
+        Some(Self::resume_callback)
+    } else {
+        None
+    };
+
+    const OPS: Ops<T::Data> = Ops(
+        bindings::dev_pm_ops {
+            suspend: Self::SUSPEND,
+            resume: Self::RESUME,
+            freeze: if T::HAS_FREEZE {
+                Some(Self::freeze_callback)
+            } else {
+                Self::SUSPEND
+            },
+            thaw: if T::HAS_THAW {
+                Some(Self::thaw_callback)
+            } else {
+                Self::RESUME
+            },
+            poweroff: if T::HAS_POWEROFF {
+                Some(Self::poweroff_callback)
+            } else {
+                Self::SUSPEND
+            },
+            restore: if T::HAS_RESTORE {
+                Some(Self::restore_callback)
+            } else {
+                Self::RESUME
+            },
+            // SAFETY: The remaining fields are optional, for which NULL is valid.
+            ..unsafe { core::mem::zeroed() }
+        },
+        PhantomData,
+    );
+
+    /// # Safety
+    ///
+    /// `dev` must be a valid device bound to a driver whose data has type `T::Data`, and remain
+    /// bound for `'a`.
+    unsafe fn args<'a>(
+        dev: *mut bindings::device,
+    ) -> Result<(<T::Data as ForeignOwnable>::Borrowed<'a>, &'a Device)> {
+        // SAFETY: By the safety requirements, `dev` is valid for `'a`.
+        let dev = unsafe { Device::from_raw(dev) };
+        // SAFETY: By the safety requirements, the device remains bound for `'a`; the driver core
+        // does not unbind it while PM callbacks run.
+        let data = unsafe { dev.drvdata::<T::Data>() }.ok_or(EINVAL)?;
+        Ok((data, dev))
+    }
+
+    unsafe extern "C" fn suspend_callback(dev: *mut bindings::device) -> c_int {
+        from_result(|| {
+            // SAFETY: The PM core only calls this for bound devices using these ops.
+            let (data, dev) = unsafe { Self::args(dev) }?;
+            T::suspend(data, dev)?;
+            Ok(0)
+        })
+    }
+
+    unsafe extern "C" fn resume_callback(dev: *mut bindings::device) -> c_int {
+        from_result(|| {
+            // SAFETY: The PM core only calls this for bound devices using these ops.
+            let (data, dev) = unsafe { Self::args(dev) }?;
+            T::resume(data, dev)?;
+            Ok(0)
+        })
+    }
+
+    unsafe extern "C" fn freeze_callback(dev: *mut bindings::device) -> c_int {
+        from_result(|| {
+            // SAFETY: The PM core only calls this for bound devices using these ops.
+            let (data, dev) = unsafe { Self::args(dev) }?;
+            T::freeze(data, dev)?;
+            Ok(0)
+        })
+    }
+
+    unsafe extern "C" fn thaw_callback(dev: *mut bindings::device) -> c_int {
+        from_result(|| {
+            // SAFETY: The PM core only calls this for bound devices using these ops.
+            let (data, dev) = unsafe { Self::args(dev) }?;
+            T::thaw(data, dev)?;
+            Ok(0)
+        })
+    }
+
+    unsafe extern "C" fn poweroff_callback(dev: *mut bindings::device) -> c_int {
+        from_result(|| {
+            // SAFETY: The PM core only calls this for bound devices using these ops.
+            let (data, dev) = unsafe { Self::args(dev) }?;
+            T::poweroff(data, dev)?;
+            Ok(0)
+        })
+    }
+
+    unsafe extern "C" fn restore_callback(dev: *mut bindings::device) -> c_int {
+        from_result(|| {
+            // SAFETY: The PM core only calls this for bound devices using these ops.
+            let (data, dev) = unsafe { Self::args(dev) }?;
+            T::restore(data, dev)?;
+            Ok(0)
+        })
+    }
+}
+
+/// Memory shared with a device that must be re-initialised after restore from hibernation.
+///
+/// The hibernation image holds the contents of memory from when it was created. Buffers that the
+/// device writes to on its own, e.g., completion rings or status blocks, therefore contain data
+/// that the device no longer agrees with once the system is restored.
+pub trait Reinit: Send + Sync {
+    /// Re-initialises the memory, while the device is quiesced.
+    fn reinit(&self) -> Result;
+}
+
+/// Coherent DMA buffers are re-initialised by clearing them.
+impl Reinit for Mutex<dma::CoherentAllocation> {
+    fn reinit(&self) -> Result {
+        let mut buf = self.lock();
+        let size = buf.size();
+        // SAFETY: The allocation is valid for writes of `size` bytes.
+        unsafe { ptr::write_bytes(buf.as_mut_ptr(), 0, size) };
+        Ok(())
+    }
+}
+
+/// A set of buffers to re-initialise after restore from hibernation.
+///
+/// Drivers add buffers to the set when they allocate them, usually during probe, and call
+/// [`RestoreSet::reinit_all`] from [`SleepOps::restore`].
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{device::Device, dma::CoherentAllocation, new_mutex, pm, prelude::*};
+/// use kernel::sync::{Arc, Mutex};
+///
+/// fn alloc_ring(
+///     dev: &Device,
+///     restore: &mut pm::RestoreSet,
+/// ) -> Result<Arc<Mutex<CoherentAllocation>>> {
+///     let ring = CoherentAllocation::alloc(dev, 4096, GFP_KERNEL, 0)?;
+///     let ring = Arc::pin_init(new_mutex!(ring), GFP_KERNEL)?;
+///     restore.add(ring.clone())?;
+///     Ok(ring)
+/// }
+/// ```
+#[derive(Default)]
+pub struct RestoreSet {
+    entries: Vec<Arc<dyn Reinit>>,
+}
+
+impl RestoreSet {
+    /// Creates an empty set.
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Marks `buf` as needing to be re-initialised after restore.
+    pub fn add(&mut self, buf: Arc<dyn Reinit>) -> Result {
+        self.entries.push(buf, GFP_KERNEL)?;
+        Ok(())
+    }
+
+    /// Re-initialises all buffers in the set, in the order they were added.
+    pub fn reinit_all(&self) -> Result {
+        for buf in &self.entries {
+            buf.reinit()?;
+        }
+        Ok(())
+    }
+}
+
+/// A registered wakeup source.
+///
+/// While the source is active, the system does not suspend, and a suspend in progress, including
+/// suspend-to-idle, is aborted. The source is unregistered when this object is dropped.
+///
+/// # Invariants
+///
+/// `ws` was returned by a successful call to `wakeup_source_register` and has not been
+/// unregistered.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{c_str, device::Device, pm::WakeupSource, prelude::*};
+///
+/// fn on_incoming_call(ws: &WakeupSource) {
+///     // Keep the system awake until user space has had a chance to ring.
+///     ws.wakeup_event(2000);
+/// }
+///
+/// fn setup(dev: &Device) -> Result<WakeupSource> {
+///     WakeupSource::register(Some(dev), c_str!("modem-ring"))
+/// }
+/// ```
+pub struct WakeupSource {
+    ws: NonNull<bindings::wakeup_source>,
+}
+
+impl WakeupSource {
+    /// Registers a wakeup source named `name`, optionally as a child of `dev` in sysfs.
+    pub fn register(dev: Option<&Device>, name: &CStr) -> Result<Self> {
+        let dev = dev.map_or(ptr::null_mut(), |d| d.raw_device());
+        // SAFETY: `dev` is either null or a valid device, and `name` is a valid C string that
+        // is copied by the wakeup core.
+        let ws = unsafe { bindings::wakeup_source_register(dev, name.as_char_ptr()) };
+        // INVARIANT: `ws` was just registered if it is not null.
+        Ok(Self {
+            ws: NonNull::new(ws).ok_or(ENOMEM)?,
+        })
+    }
+
+    fn as_raw(&self) -> *mut bindings::wakeup_source {
+        self.ws.as_ptr()
+    }
+
+    /// Activates the source until [`WakeupSource::relax`] is called.
+    ///
+    /// This can be called from any context, including interrupt handlers. Prefer
+    /// [`WakeupSource::stay_awake_guard`] when activation and deactivation happen in the same
+    /// scope.
+    pub fn stay_awake(&self) {
+        // SAFETY: By the type invariants, `ws` is a registered wakeup source.
+        unsafe { bindings::__pm_stay_awake(self.as_raw()) };
+    }
+
+    /// Deactivates the source, allowing the system to suspend again.
+    pub fn relax(&self) {
+        // SAFETY: By the type invariants, `ws` is a registered wakeup source.
+        unsafe { bindings::__pm_relax(self.as_raw()) };
+    }
+
+    /// Activates the source and keeps it active until the returned guard is dropped.
+    pub fn stay_awake_guard(&self) -> StayAwakeGuard<'_> {
+        self.stay_awake();
+        StayAwakeGuard { ws: self }
+    }
+
+    /// Reports a wakeup event, keeping the source active for `msec` milliseconds.
+    ///
+    /// If `msec` is zero, the event is reported without keeping the source active.
+    pub fn wakeup_event(&self, msec: u32) {
+        // SAFETY: By the type invariants, `ws` is a registered wakeup source.
+        unsafe { bindings::__pm_wakeup_event(self.as_raw(), msec) };
+    }
+}
+
+impl Drop for WakeupSource {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, `ws` is registered, and it is not used after this.
+        unsafe { bindings::wakeup_source_unregister(self.as_raw()) };
+    }
+}
+
+// SAFETY: The wakeup source can be used and unregistered from any thread.
+unsafe impl Send for WakeupSource {}
+
+// SAFETY: The wakeup core serialises concurrent accesses to the source with its own lock.
+unsafe impl Sync for WakeupSource {}
+
+/// A guard that keeps a [`WakeupSource`] active.
+///
+/// Returned by [`WakeupSource::stay_awake_guard`]; the source is relaxed when it is dropped.
+#[must_use = "the wakeup source is relaxed when the guard is dropped"]
+pub struct StayAwakeGuard<'a> {
+    ws: &'a WakeupSource,
+}
+
+impl Drop for StayAwakeGuard<'_> {
+    fn drop(&mut self) {
+        self.ws.relax();
+    }
+}
@@ -0,0 +1,229 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Power management quality of service.
+//!
+//! Drivers that must react to their device within a bounded time, e.g., audio drivers refilling a
+//! FIFO or industrial I/O drivers sampling at a high rate, add latency requests while the device
+//! is in use. The PM core then keeps the CPUs, or the device, out of the low-power states whose
+//! exit latency is longer than the tightest request.
+//!
+//! Requests are removed when they are dropped. Between uses of the device, they are usually kept
+//! and relaxed rather than removed and added again.
+//!
+//! C header: [`include/linux/pm_qos.h`](srctree/include/linux/pm_qos.h)
+
+use crate::{
+    bindings,
+    device::{Device, RawDevice},
+    error::to_result,
+    prelude::*,
+    types::{ARef, Opaque},
+};
+
+/// A request for a maximum CPU wakeup latency, for all CPUs.
+///
+/// While the request is active, CPU idle states with an exit latency larger than the requested
+/// latency are not entered.
+///
+/// # Invariants
+///
+/// `req` was added with `cpu_latency_qos_add_request` and has not been removed.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{pm::qos::CpuLatencyRequest, prelude::*};
+///
+/// struct Stream {
+///     qos: CpuLatencyRequest,
+/// }
+///
+/// impl Stream {
+///     fn new() -> Result<Self> {
+///         Ok(Self {
+///             qos: CpuLatencyRequest::new_relaxed()?,
+///         })
+///     }
+///
+///     fn start(&self) -> Result {
+///         // The FIFO holds 100us of samples; leave margin for the interrupt handler.
+///         self.qos.update(50)
+///     }
+///
+///     fn stop(&self) {
+///         self.qos.relax();
+///     }
+/// }
+/// ```
+pub struct CpuLatencyRequest {
+    req: Box<Opaque<bindings::pm_qos_request>>,
+}
+
+impl CpuLatencyRequest {
+    /// Adds a request for a wakeup latency of at most `latency_us` microseconds.
+    ///
+    /// Fails with [`EINVAL`] if `latency_us` is negative.
+    pub fn new(latency_us: i32) -> Result<Self> {
+        if latency_us < 0 {
+            return Err(EINVAL);
+        }
+        Self::add(latency_us)
+    }
+
+    /// Adds a request that does not constrain the latency until it is updated.
+    pub fn new_relaxed() -> Result<Self> {
+        Self::add(bindings::PM_QOS_DEFAULT_VALUE)
+    }
+
+    fn add(value: i32) -> Result<Self> {
+        // SAFETY: All zeroes is a request that has not been added.
+        let req = Box::new(Opaque::new(unsafe { core::mem::zeroed() }), GFP_KERNEL)?;
+        // SAFETY: `req` is a request that has not been added. It is boxed, so it does not move
+        // while it is on the list of requests.
+        unsafe { bindings::cpu_latency_qos_add_request(req.get(), value) };
+        // INVARIANT: The request was just added.
+        Ok(Self { req })
+    }
+
+    /// Changes the requested latency to at most `latency_us` microseconds.
+    ///
+    /// Fails with [`EINVAL`] if `latency_us` is negative.
+    pub fn update(&self, latency_us: i32) -> Result {
+        if latency_us < 0 {
+            return Err(EINVAL);
+        }
+        // SAFETY: By the type invariants, the request was added and not removed.
+        unsafe { bindings::cpu_latency_qos_update_request(self.req.get(), latency_us) };
+        Ok(())
+    }
+
+    /// Lifts the constraint of the request, without removing it.
+    pub fn relax(&self) {
+        // SAFETY: By the type invariants, the request was added and not removed.
+        unsafe {
+            bindings::cpu_latency_qos_update_request(self.req.get(), bindings::PM_QOS_DEFAULT_VALUE)
+        };
+    }
+}
+
+impl Drop for CpuLatencyRequest {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, the request was added and not removed, and it is not
+        // used after this.
+        unsafe { bindings::cpu_latency_qos_remove_request(self.req.get()) };
+    }
+}
+
+// SAFETY: The request can be updated and removed from any thread. It is not `Sync`: updates
+// read the current value of the request without the lock of the PM QoS core, so they must not
+// run concurrently.
+unsafe impl Send for CpuLatencyRequest {}
+
+/// The constraint set by a [`DeviceRequest`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceConstraint {
+    /// The maximum time the device may take to resume, in microseconds, when it is runtime
+    /// suspended or, for CPU devices, idle.
+    ResumeLatency,
+    /// The maximum latency the device tolerates, in microseconds, for devices whose hardware
+    /// enforces it, e.g., LTR capable PCIe devices.
+    LatencyTolerance,
+}
+
+impl DeviceConstraint {
+    fn as_raw(self) -> bindings::dev_pm_qos_req_type {
+        match self {
+            Self::ResumeLatency => bindings::dev_pm_qos_req_type_DEV_PM_QOS_RESUME_LATENCY,
+            Self::LatencyTolerance => bindings::dev_pm_qos_req_type_DEV_PM_QOS_LATENCY_TOLERANCE,
+        }
+    }
+}
+
+/// A latency request for a single device.
+///
+/// The request must be dropped before the device is unregistered, e.g., as part of the driver
+/// data of the device when it is unbound.
+///
+/// # Invariants
+///
+/// `req` was added for `dev` with `dev_pm_qos_add_request` and has not been removed.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{
+///     device::RawDevice,
+///     pm::qos::{DeviceConstraint, DeviceRequest},
+///     prelude::*,
+/// };
+///
+/// /// Keeps `codec` from entering a sleep state it takes more than 1ms to leave.
+/// fn constrain(codec: &impl RawDevice) -> Result<DeviceRequest> {
+///     DeviceRequest::new(codec, DeviceConstraint::ResumeLatency, 1000)
+/// }
+/// ```
+pub struct DeviceRequest {
+    req: Box<Opaque<bindings::dev_pm_qos_request>>,
+    _dev: ARef<Device>,
+}
+
+impl DeviceRequest {
+    /// Adds a request for the `constraint` of `dev` to be at most `value` microseconds.
+    ///
+    /// Fails with [`EINVAL`] if `value` is negative, and with [`ENODEV`] if the device is being
+    /// removed.
+    pub fn new(dev: &impl RawDevice, constraint: DeviceConstraint, value: i32) -> Result<Self> {
+        if value < 0 {
+            return Err(EINVAL);
+        }
+        // SAFETY: `dev.raw_device()` is valid by the safety requirements of `RawDevice`; a
+        // reference to it is taken, and released after the request is removed.
+        let dev = unsafe { Device::new(dev.raw_device()) };
+        // SAFETY: All zeroes is a request that has not been added.
+        let req = Box::new(Opaque::new(unsafe { core::mem::zeroed() }), GFP_KERNEL)?;
+        // SAFETY: `dev` is valid and `req` is a request that has not been added. It is boxed, so
+        // it does not move while it is on the list of requests of the device.
+        to_result(unsafe {
+            bindings::dev_pm_qos_add_request(dev.as_raw(), req.get(), constraint.as_raw(), value)
+        })?;
+        // INVARIANT: The request was just added for `dev`.
+        Ok(Self { req, _dev: dev })
+    }
+
+    /// Changes the requested value to at most `value` microseconds.
+    ///
+    /// Fails with [`EINVAL`] if `value` is negative.
+    pub fn update(&self, value: i32) -> Result {
+        if value < 0 {
+            return Err(EINVAL);
+        }
+        // SAFETY: By the type invariants, the request was added and not removed.
+        to_result(unsafe { bindings::dev_pm_qos_update_request(self.req.get(), value) })
+    }
+
+    /// Lifts the constraint of the request, without removing it.
+    pub fn relax(&self) -> Result {
+        // SAFETY: By the type invariants, the request was added and not removed.
+        to_result(unsafe {
+            bindings::dev_pm_qos_update_request(self.req.get(), bindings::PM_QOS_DEFAULT_VALUE)
+        })
+    }
+}
+
+impl Drop for DeviceRequest {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, the request was added and not removed, and it is not
+        // used after this. The device is released after this, when `_dev` is dropped.
+        let ret = unsafe { bindings::dev_pm_qos_remove_request(self.req.get()) };
+        if ret < 0 {
+            pr_warn!("failed to remove device PM QoS request: {}\n", ret);
+        }
+    }
+}
+
+// SAFETY: The request can be updated and removed from any thread, and the PM QoS core serialises
+// the accesses to device requests with its own mutex.
+unsafe impl Send for DeviceRequest {}
+
+// SAFETY: See above; updates through shared references are serialised by the PM QoS core.
+unsafe impl Sync for DeviceRequest {}
@@ -136,6 +136,177 @@ pub fn call_printk_cont(args: fmt::Arguments<'_>) {
     }
 }
 
+/// A dynamic debug descriptor, the equivalent of the C `struct _ddebug`.
+///
+/// Each `pr_debug!` and `dev_dbg!` call site has a descriptor in the `__dyndbg` section, which
+/// the dynamic debug core lists in `<debugfs>/dynamic_debug/control` and updates when call sites
+/// are enabled or disabled.
+///
+/// Rust has no equivalent of `__func__`, so the function of the descriptor is empty, and Rust call
+/// sites are selected by module, file or line in the control file instead.
+///
+/// Public but hidden since it should only be used from public macros.
+#[doc(hidden)]
+#[cfg(CONFIG_DYNAMIC_DEBUG)]
+#[repr(C, align(8))]
+pub struct DynDebug {
+    modname: *const c_char,
+    function: *const c_char,
+    filename: *const c_char,
+    format: *const c_char,
+    // The `lineno:18`, `class_id:6` and `flags:8` bitfields, see `DynDebug::LINENO_SHIFT`.
+    bits: core::cell::UnsafeCell<u32>,
+    #[cfg(CONFIG_JUMP_LABEL)]
+    key: core::cell::UnsafeCell<bindings::static_key_false>,
+}
+
+#[cfg(CONFIG_DYNAMIC_DEBUG)]
+crate::static_assert!(
+    core::mem::size_of::<DynDebug>() == core::mem::size_of::<bindings::_ddebug>()
+        && core::mem::align_of::<DynDebug>() == core::mem::align_of::<bindings::_ddebug>()
+);
+
+// SAFETY: The descriptor is only modified by the dynamic debug core, under its lock; Rust code
+// only reads its flags.
+#[cfg(CONFIG_DYNAMIC_DEBUG)]
+unsafe impl Sync for DynDebug {}
+
+#[cfg(CONFIG_DYNAMIC_DEBUG)]
+impl DynDebug {
+    // C compilers allocate bitfields from the least significant bit of their unit on little-endian
+    // targets, and from the most significant one on big-endian targets.
+    #[cfg(target_endian = "little")]
+    const LINENO_SHIFT: u32 = 0;
+    #[cfg(target_endian = "little")]
+    const CLASS_SHIFT: u32 = 18;
+    #[cfg(target_endian = "little")]
+    const FLAGS_SHIFT: u32 = 24;
+
+    #[cfg(target_endian = "big")]
+    const LINENO_SHIFT: u32 = 14;
+    #[cfg(target_endian = "big")]
+    const CLASS_SHIFT: u32 = 8;
+    #[cfg(target_endian = "big")]
+    const FLAGS_SHIFT: u32 = 0;
+
+    /// Creates a disabled descriptor for a call site.
+    ///
+    /// # Safety
+    ///
+    /// `modname` must be null-terminated.
+    pub const unsafe fn new(
+        modname: &'static [u8],
+        filename: &'static crate::str::CStr,
+        format: &'static crate::str::CStr,
+        lineno: u32,
+    ) -> Self {
+        const LINENO_MASK: u32 = (1 << 18) - 1;
+        Self {
+            modname: modname.as_ptr().cast(),
+            function: crate::c_str!("").as_char_ptr(),
+            filename: filename.as_char_ptr(),
+            format: format.as_char_ptr(),
+            bits: core::cell::UnsafeCell::new(
+                ((lineno & LINENO_MASK) << Self::LINENO_SHIFT)
+                    | (bindings::_DPRINTK_CLASS_DFLT << Self::CLASS_SHIFT),
+            ),
+            // SAFETY: An all-zeroes static key is a disabled `static_key_false`.
+            #[cfg(CONFIG_JUMP_LABEL)]
+            key: core::cell::UnsafeCell::new(unsafe { core::mem::zeroed() }),
+        }
+    }
+
+    /// Returns `true` if the call site is enabled.
+    #[inline]
+    pub fn enabled(&self) -> bool {
+        // SAFETY: `bits` is valid for reads. A volatile read is used since the dynamic debug core
+        // updates it concurrently.
+        let bits = unsafe { core::ptr::read_volatile(self.bits.get()) };
+        (bits >> Self::FLAGS_SHIFT) & bindings::_DPRINTK_FLAGS_PRINT != 0
+    }
+
+    fn as_raw(&self) -> *mut bindings::_ddebug {
+        (self as *const Self).cast_mut().cast()
+    }
+}
+
+/// Prints a debug message via the kernel's `__dynamic_pr_debug`, which adds the prefixes
+/// selected in the dynamic debug control file.
+///
+/// Public but hidden since it should only be used from public macros.
+///
+/// # Safety
+///
+/// The module name must be null-terminated.
+#[doc(hidden)]
+#[cfg(CONFIG_DYNAMIC_DEBUG)]
+pub unsafe fn call_dynamic_pr_debug(
+    descriptor: &'static DynDebug,
+    module_name: &[u8],
+    args: fmt::Arguments<'_>,
+) {
+    // SAFETY: The descriptor is valid, the format string is fixed and the module name is
+    // null-terminated.
+    unsafe {
+        bindings::__dynamic_pr_debug(
+            descriptor.as_raw(),
+            b"%s: %pA\0".as_ptr().cast(),
+            module_name.as_ptr(),
+            &args as *const _ as *const c_void,
+        );
+    }
+}
+
+/// Prints a debug message prefixed with device information via the kernel's
+/// `__dynamic_dev_dbg`.
+///
+/// Public but hidden since it should only be used from public macros.
+///
+/// # Safety
+///
+/// `dev` must be a valid device.
+#[doc(hidden)]
+#[cfg(CONFIG_DYNAMIC_DEBUG)]
+pub unsafe fn call_dynamic_dev_dbg(
+    descriptor: &'static DynDebug,
+    dev: *const bindings::device,
+    args: fmt::Arguments<'_>,
+) {
+    // SAFETY: The descriptor and `dev` are valid, and the format string is fixed.
+    unsafe {
+        bindings::__dynamic_dev_dbg(
+            descriptor.as_raw(),
+            dev,
+            b"%pA\0".as_ptr().cast(),
+            &args as *const _ as *const c_void,
+        );
+    }
+}
+
+/// Defines the dynamic debug descriptor of a call site and evaluates to a reference to it.
+///
+/// Public but hidden since it should only be used from public macros.
+#[doc(hidden)]
+#[cfg(CONFIG_DYNAMIC_DEBUG)]
+#[macro_export]
+#[allow(clippy::crate_in_macro_def)]
+macro_rules! dynamic_debug_descriptor (
+    ($fmt:literal) => ({
+        #[link_section = "__dyndbg"]
+        static DESCRIPTOR: $crate::print::DynDebug =
+            // SAFETY: All `__LOG_PREFIX`s are null-terminated.
+            unsafe {
+                $crate::print::DynDebug::new(
+                    crate::__LOG_PREFIX,
+                    $crate::c_str!(file!()),
+                    $crate::c_str!($fmt),
+                    line!(),
+                )
+            };
+        &DESCRIPTOR
+    })
+);
+
 /// Performs formatting and forwards the string to [`call_printk`].
 ///
 /// Public but hidden since it should only be used from public macros.
@@ -366,8 +537,11 @@ macro_rules! pr_info (
 ///
 /// Use this level for debug messages.
 ///
-/// Equivalent to the kernel's [`pr_debug`] macro, except that it doesn't support dynamic debug
-/// yet.
+/// Equivalent to the kernel's [`pr_debug`] macro.
+///
+/// With `CONFIG_DYNAMIC_DEBUG`, each call site can be enabled at runtime through the dynamic debug
+/// control file, e.g., with `echo 'module my_rust_drv +p' > <debugfs>/dynamic_debug/control`, and
+/// is disabled by default. Otherwise, messages are only printed if debug assertions are enabled.
 ///
 /// Mimics the interface of [`std::print!`]. See [`core::fmt`] and
 /// `alloc::format!` for information about the formatting syntax.
@@ -382,7 +556,28 @@ macro_rules! pr_info (
 /// ```
 #[macro_export]
 #[doc(alias = "print")]
+#[allow(clippy::crate_in_macro_def)]
 macro_rules! pr_debug (
+    ($fmt:literal $($arg:tt)*) => ({
+        #[cfg(CONFIG_DYNAMIC_DEBUG)]
+        {
+            let descriptor = $crate::dynamic_debug_descriptor!($fmt);
+            if descriptor.enabled() {
+                match format_args!($fmt $($arg)*) {
+                    // SAFETY: All `__LOG_PREFIX`s are null-terminated.
+                    args => unsafe {
+                        $crate::print::call_dynamic_pr_debug(descriptor, crate::__LOG_PREFIX, args)
+                    },
+                }
+            }
+        }
+        #[cfg(not(CONFIG_DYNAMIC_DEBUG))]
+        {
+            if cfg!(debug_assertions) {
+                $crate::print_macro!($crate::print::format_strings::DEBUG, false, $fmt $($arg)*)
+            }
+        }
+    });
     ($($arg:tt)*) => (
         if cfg!(debug_assertions) {
             $crate::print_macro!($crate::print::format_strings::DEBUG, false, $($arg)*)
@@ -0,0 +1,337 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! PTP hardware clocks (PHCs).
+//!
+//! NICs and timer blocks with a clock that timestamps packets or events implement [`Operations`]
+//! and register it with [`Registration::register`]. The PTP core exposes it to user space as
+//! `/dev/ptpN`, where, e.g., `ptp4l` disciplines it to a grandmaster clock, mostly through
+//! [`Operations::adjfine`].
+//!
+//! Times are in nanoseconds since the PTP epoch, which is the Unix epoch on the TAI time scale.
+//!
+//! C header: [`include/linux/ptp_clock_kernel.h`](srctree/include/linux/ptp_clock_kernel.h)
+
+use crate::{
+    bindings,
+    device::RawDevice,
+    error::{from_err_ptr, from_result, VTABLE_DEFAULT_ERROR},
+    prelude::*,
+    time::timekeeping::Ktime,
+    types::ForeignOwnable,
+};
+use core::{ffi::c_int, marker::PhantomData};
+
+const NSEC_PER_SEC: i64 = 1_000_000_000;
+
+fn to_timespec(ns: Ktime) -> bindings::timespec64 {
+    bindings::timespec64 {
+        tv_sec: ns.div_euclid(NSEC_PER_SEC),
+        tv_nsec: ns.rem_euclid(NSEC_PER_SEC) as _,
+    }
+}
+
+fn from_timespec(ts: &bindings::timespec64) -> Ktime {
+    ts.tv_sec
+        .saturating_mul(NSEC_PER_SEC)
+        .saturating_add(ts.tv_nsec as i64)
+}
+
+/// Returns `base` adjusted by `scaled_ppm`, as passed to [`Operations::adjfine`].
+///
+/// This is how drivers compute the new increment of a clock whose counter advances by a fixed
+/// addend per cycle of its input clock.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::ptp::adjust_by_scaled_ppm;
+///
+/// // +1 ppm, in parts per million with a 16-bit fractional part.
+/// assert_eq!(adjust_by_scaled_ppm(1 << 32, 1 << 16), 4_294_971_590);
+/// assert_eq!(adjust_by_scaled_ppm(1 << 32, -(1 << 16)), 4_294_963_002);
+/// assert_eq!(adjust_by_scaled_ppm(1000, 0), 1000);
+/// ```
+pub fn adjust_by_scaled_ppm(base: u64, scaled_ppm: i64) -> u64 {
+    let diff = (base as u128 * scaled_ppm.unsigned_abs() as u128 / (1_000_000 << 16)) as u64;
+    if scaled_ppm < 0 {
+        base.saturating_sub(diff)
+    } else {
+        base.saturating_add(diff)
+    }
+}
+
+/// The system time around a read of the hardware clock, see [`Operations::gettimex`].
+pub struct SystemTimestamp(*mut bindings::ptp_system_timestamp);
+
+impl SystemTimestamp {
+    /// Records the system time right before the hardware clock is latched.
+    pub fn pre(&mut self) {
+        // SAFETY: The pointer is either null, which the function ignores, or valid for the
+        // duration of the callback.
+        unsafe { bindings::ptp_read_system_prets(self.0) };
+    }
+
+    /// Records the system time right after the hardware clock is latched.
+    pub fn post(&mut self) {
+        // SAFETY: The pointer is either null, which the function ignores, or valid for the
+        // duration of the callback.
+        unsafe { bindings::ptp_read_system_postts(self.0) };
+    }
+}
+
+/// Operations of a PTP hardware clock.
+#[vtable]
+pub trait Operations {
+    /// The context data made available to the callbacks.
+    type Data: ForeignOwnable + Send + Sync;
+
+    /// The name of the clock shown in sysfs, at most 15 bytes long.
+    const NAME: &'static str;
+
+    /// The largest frequency adjustment supported by the hardware, in parts per billion.
+    const MAX_ADJ: i32;
+
+    /// Adjusts the frequency of the clock by `scaled_ppm`, in parts per million with a 16-bit
+    /// fractional part; see [`adjust_by_scaled_ppm`].
+    fn adjfine(data: <Self::Data as ForeignOwnable>::Borrowed<'_>, scaled_ppm: i64) -> Result;
+
+    /// Returns the time of the clock.
+    ///
+    /// Drivers call [`SystemTimestamp::pre`] and [`SystemTimestamp::post`] right around the
+    /// register access that latches the time, so that user space can correlate the clock with
+    /// the system clock precisely.
+    fn gettimex(
+        data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        sts: &mut SystemTimestamp,
+    ) -> Result<Ktime>;
+
+    /// Sets the time of the clock.
+    fn settime(data: <Self::Data as ForeignOwnable>::Borrowed<'_>, time: Ktime) -> Result;
+
+    /// Shifts the time of the clock by `delta` nanoseconds.
+    ///
+    /// If this is not implemented, the clock is read and set again, which loses the time between
+    /// the two accesses. Hardware with an atomic offset adjustment should implement it.
+    fn adjtime(_data: <Self::Data as ForeignOwnable>::Borrowed<'_>, _delta: i64) -> Result {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+}
+
+#[repr(C)]
+struct Inner {
+    // Must be the first field, so that callbacks can get from the info to the data.
+    info: bindings::ptp_clock_info,
+    data: *const core::ffi::c_void,
+}
+
+/// A registered PTP hardware clock.
+///
+/// The clock is unregistered and its data freed when the registration is dropped.
+///
+/// # Invariants
+///
+/// `clock` was returned by `ptp_clock_register` for `inner.info`, and `inner.data` was obtained
+/// from [`ForeignOwnable::into_foreign`] on a `T::Data`.
+///
+/// # Examples
+///
+/// ```
+/// use core::sync::atomic::{AtomicI64, Ordering};
+/// use kernel::{device::RawDevice, prelude::*, ptp, time::timekeeping::Ktime};
+///
+/// struct MyPhc {
+///     offset: AtomicI64,
+/// }
+///
+/// #[vtable]
+/// impl ptp::Operations for MyPhc {
+///     type Data = Box<MyPhc>;
+///
+///     const NAME: &'static str = "my-phc";
+///     const MAX_ADJ: i32 = 500_000;
+///
+///     fn adjfine(_data: &MyPhc, _scaled_ppm: i64) -> Result {
+///         // Program the new increment, e.g., `ptp::adjust_by_scaled_ppm(BASE, scaled_ppm)`.
+///         Ok(())
+///     }
+///
+///     fn gettimex(data: &MyPhc, sts: &mut ptp::SystemTimestamp) -> Result<Ktime> {
+///         sts.pre();
+///         // Latch the hardware counter.
+///         sts.post();
+///         Ok(data.offset.load(Ordering::Relaxed))
+///     }
+///
+///     fn settime(data: &MyPhc, time: Ktime) -> Result {
+///         data.offset.store(time, Ordering::Relaxed);
+///         Ok(())
+///     }
+/// }
+///
+/// fn register(dev: &impl RawDevice) -> Result<ptp::Registration<MyPhc>> {
+///     let phc = Box::new(MyPhc { offset: AtomicI64::new(0) }, GFP_KERNEL)?;
+///     let reg = ptp::Registration::register(dev, phc)?;
+///     dev_info!(dev, "registered /dev/ptp{}\n", reg.index());
+///     Ok(reg)
+/// }
+/// ```
+pub struct Registration<T: Operations> {
+    inner: Pin<Box<Inner>>,
+    clock: *mut bindings::ptp_clock,
+    _p: PhantomData<T>,
+}
+
+impl<T: Operations> Registration<T> {
+    /// Registers a new PTP clock as a child of `parent`.
+    pub fn register(parent: &impl RawDevice, data: T::Data) -> Result<Self> {
+        let mut info = bindings::ptp_clock_info {
+            max_adj: T::MAX_ADJ,
+            adjfine: Some(Adapter::<T>::adjfine_callback),
+            adjtime: Some(Adapter::<T>::adjtime_callback),
+            gettimex64: Some(Adapter::<T>::gettimex_callback),
+            settime64: Some(Adapter::<T>::settime_callback),
+            // SAFETY: The remaining fields are optional or counts of features that the clock
+            // does not have, for which zero is valid.
+            ..unsafe { core::mem::zeroed() }
+        };
+        let name = T::NAME.as_bytes();
+        // The last byte must be left as the NUL terminator.
+        if name.len() >= info.name.len() {
+            return Err(EINVAL);
+        }
+        for (d, s) in info.name.iter_mut().zip(name) {
+            *d = *s as _;
+        }
+
+        let mut inner = Box::new(
+            Inner {
+                info,
+                data: core::ptr::null(),
+            },
+            GFP_KERNEL,
+        )?;
+        let ptr = data.into_foreign();
+        inner.data = ptr;
+        let inner = Box::into_pin(inner);
+        // The core never writes to the info, it only needs a mutable pointer for the callbacks.
+        let raw = &inner.info as *const _ as *mut bindings::ptp_clock_info;
+
+        // SAFETY: `raw` is initialised above and stays valid until the clock is unregistered in
+        // `drop`. `parent.raw_device()` is valid.
+        let clock =
+            match from_err_ptr(unsafe { bindings::ptp_clock_register(raw, parent.raw_device()) }) {
+                Ok(clock) => clock,
+                Err(e) => {
+                    // SAFETY: `ptr` came from `into_foreign` above and the clock was not registered.
+                    drop(unsafe { T::Data::from_foreign(ptr) });
+                    return Err(e);
+                }
+            };
+
+        // INVARIANT: The clock was registered above with `ptr` as its data.
+        Ok(Self {
+            inner,
+            clock,
+            _p: PhantomData,
+        })
+    }
+
+    /// Returns the index of the clock, i.e., the `N` of `/dev/ptpN`.
+    ///
+    /// Drivers report it through ethtool, so that user space can find the clock of a NIC.
+    pub fn index(&self) -> i32 {
+        // SAFETY: By the type invariants, `clock` is registered.
+        unsafe { bindings::ptp_clock_index(self.clock) }
+    }
+}
+
+impl<T: Operations> Drop for Registration<T> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, `clock` is registered. No callbacks run after it is
+        // unregistered.
+        unsafe { bindings::ptp_clock_unregister(self.clock) };
+        // SAFETY: By the type invariants, the data came from `into_foreign`, and it is no longer
+        // used by the unregistered clock.
+        drop(unsafe { T::Data::from_foreign(self.inner.data) });
+    }
+}
+
+// SAFETY: The registration only holds a `T::Data`, which is `Send`, and the clock, which can be
+// unregistered from any thread.
+unsafe impl<T: Operations> Send for Registration<T> {}
+
+// SAFETY: `Registration` has no methods that change state through `&self`.
+unsafe impl<T: Operations> Sync for Registration<T> {}
+
+struct Adapter<T: Operations>(PhantomData<T>);
+
+impl<T: Operations> Adapter<T> {
+    /// # Safety
+    ///
+    /// `info` must be the info of a clock registered by [`Registration::register`].
+    unsafe fn data<'a>(
+        info: *mut bindings::ptp_clock_info,
+    ) -> <T::Data as ForeignOwnable>::Borrowed<'a> {
+        // SAFETY: By the safety requirements, `info` is the first field of an `Inner`, whose data
+        // came from `into_foreign` and is only freed after the clock is unregistered.
+        unsafe { T::Data::borrow((*info.cast::<Inner>()).data) }
+    }
+
+    unsafe extern "C" fn adjfine_callback(
+        info: *mut bindings::ptp_clock_info,
+        scaled_ppm: core::ffi::c_long,
+    ) -> c_int {
+        from_result(|| {
+            // SAFETY: The core only calls this for clocks registered with this info.
+            T::adjfine(unsafe { Self::data(info) }, scaled_ppm as i64)?;
+            Ok(0)
+        })
+    }
+
+    unsafe extern "C" fn adjtime_callback(
+        info: *mut bindings::ptp_clock_info,
+        delta: i64,
+    ) -> c_int {
+        from_result(|| {
+            if T::HAS_ADJTIME {
+                // SAFETY: The core only calls this for clocks registered with this info.
+                T::adjtime(unsafe { Self::data(info) }, delta)?;
+            } else {
+                let mut sts = SystemTimestamp(core::ptr::null_mut());
+                // SAFETY: The core only calls this for clocks registered with this info.
+                let now = T::gettimex(unsafe { Self::data(info) }, &mut sts)?;
+                // SAFETY: As above.
+                T::settime(unsafe { Self::data(info) }, now.saturating_add(delta))?;
+            }
+            Ok(0)
+        })
+    }
+
+    unsafe extern "C" fn gettimex_callback(
+        info: *mut bindings::ptp_clock_info,
+        ts: *mut bindings::timespec64,
+        sts: *mut bindings::ptp_system_timestamp,
+    ) -> c_int {
+        from_result(|| {
+            let mut sts = SystemTimestamp(sts);
+            // SAFETY: The core only calls this for clocks registered with this info.
+            let now = T::gettimex(unsafe { Self::data(info) }, &mut sts)?;
+            // SAFETY: `ts` is valid for writes for the duration of the call.
+            unsafe { ts.write(to_timespec(now)) };
+            Ok(0)
+        })
+    }
+
+    unsafe extern "C" fn settime_callback(
+        info: *mut bindings::ptp_clock_info,
+        ts: *const bindings::timespec64,
+    ) -> c_int {
+        from_result(|| {
+            // SAFETY: `ts` is valid for reads for the duration of the call.
+            let time = from_timespec(unsafe { &*ts });
+            // SAFETY: The core only calls this for clocks registered with this info.
+            T::settime(unsafe { Self::data(info) }, time)?;
+            Ok(0)
+        })
+    }
+}
@@ -72,6 +72,152 @@ pub fn alloc_fields<const N: usize>(
     pub fn as_ptr(&self) -> *mut bindings::regmap {
         self.0
     }
+
+    /// Put the regmap in cache-only mode, or take it out of it
+    ///
+    /// In cache-only mode, writes only update the cache, and reads of registers that are not
+    /// cached fail with `EBUSY`. Drivers enable it while the device is powered down, e.g., in
+    /// their suspend callback, and write the cached values back with [`Regmap::cache_sync`] once
+    /// it is powered up again.
+    pub fn cache_only(&self, enable: bool) {
+        // SAFETY: `self.0` is a valid regmap.
+        unsafe { bindings::regcache_cache_only(self.0, enable) }
+    }
+
+    /// Bypass the cache, or stop bypassing it
+    ///
+    /// While the cache is bypassed, reads and writes go to the device and the cache is not
+    /// updated, e.g., to run a reset or calibration sequence whose results should not be replayed
+    /// by [`Regmap::cache_sync`].
+    pub fn cache_bypass(&self, enable: bool) {
+        // SAFETY: `self.0` is a valid regmap.
+        unsafe { bindings::regcache_cache_bypass(self.0, enable) }
+    }
+
+    /// Mark the whole cache as dirty
+    ///
+    /// Drivers call this when the device lost its state, e.g., after it was powered down, so that
+    /// [`Regmap::cache_sync`] writes back all the cached registers that differ from their
+    /// defaults, rather than only those written in cache-only mode.
+    pub fn mark_dirty(&self) {
+        // SAFETY: `self.0` is a valid regmap.
+        unsafe { bindings::regcache_mark_dirty(self.0) }
+    }
+
+    /// Write the dirty registers of the cache back to the device
+    ///
+    /// The register patch, if any, is applied first. This is usually called on resume, after
+    /// leaving cache-only mode and marking the cache dirty.
+    pub fn cache_sync(&self) -> Result {
+        // SAFETY: `self.0` is a valid regmap.
+        to_result(unsafe { bindings::regcache_sync(self.0) })
+    }
+
+    /// Write the dirty registers between `min` and `max`, inclusive, back to the device
+    pub fn cache_sync_region(&self, min: u32, max: u32) -> Result {
+        if min > max {
+            return Err(EINVAL);
+        }
+        // SAFETY: `self.0` is a valid regmap.
+        to_result(unsafe { bindings::regcache_sync_region(self.0, min, max) })
+    }
+
+    /// Register a patch of register writes, applied now and on every [`Regmap::cache_sync`]
+    ///
+    /// Patches fix up the defaults of a device, e.g., errata workarounds recommended by the
+    /// vendor, without the cache knowing about the new values. The writes are copied, so the
+    /// table is usually a `const`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kernel::regmap::{Regmap, RegSequence};
+    ///
+    /// const ERRATA: [RegSequence; 2] = [
+    ///     RegSequence::new(0x30, 0x0c),
+    ///     // The charge pump needs time to settle after being enabled.
+    ///     RegSequence::with_delay(0x31, 0x01, 100),
+    /// ];
+    ///
+    /// fn init(regmap: &Regmap) -> Result {
+    ///     regmap.register_patch(&ERRATA)
+    /// }
+    ///
+    /// fn resume(regmap: &Regmap) -> Result {
+    ///     regmap.cache_only(false);
+    ///     regmap.mark_dirty();
+    ///     // Writes `ERRATA` again, then the cached registers.
+    ///     regmap.cache_sync()
+    /// }
+    /// ```
+    pub fn register_patch(&self, patch: &[RegSequence]) -> Result {
+        let len = patch.len().try_into()?;
+        // SAFETY: `self.0` is a valid regmap, and `RegSequence` is a transparent wrapper around
+        // `reg_sequence`, so `patch` is valid for reads of `len` of them.
+        to_result(unsafe { bindings::regmap_register_patch(self.0, patch.as_ptr().cast(), len) })
+    }
+}
+
+/// Register accesses through a register map
+///
+/// This is implemented by [`Regmap`] and by software models of devices, e.g.,
+/// `mock::Mock`, so that driver code that is generic over it can be tested
+/// without the device.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{prelude::*, regmap::RegAccess};
+///
+/// const POWER: u32 = 0x02;
+/// const POWER_ON: u32 = 1 << 7;
+///
+/// fn power_on(regs: &impl RegAccess) -> Result {
+///     regs.update_bits(POWER, POWER_ON, POWER_ON)
+/// }
+/// ```
+pub trait RegAccess {
+    /// Read the register `reg`
+    fn read(&self, reg: u32) -> Result<u32>;
+
+    /// Write `val` to the register `reg`
+    fn write(&self, reg: u32, val: u32) -> Result;
+
+    /// Update the bits of the register `reg` selected by `mask` to those of `val`
+    fn update_bits(&self, reg: u32, mask: u32, val: u32) -> Result {
+        let old = self.read(reg)?;
+        self.write(reg, (old & !mask) | (val & mask))
+    }
+}
+
+impl RegAccess for Regmap {
+    fn read(&self, reg: u32) -> Result<u32> {
+        let mut val = 0;
+        // SAFETY: `self.0` is a valid regmap and `val` is valid for writes.
+        to_result(unsafe { bindings::regmap_read(self.0, reg, &mut val) })?;
+        Ok(val)
+    }
+
+    fn write(&self, reg: u32, val: u32) -> Result {
+        // SAFETY: `self.0` is a valid regmap.
+        to_result(unsafe { bindings::regmap_write(self.0, reg, val) })
+    }
+
+    fn update_bits(&self, reg: u32, mask: u32, val: u32) -> Result {
+        // SAFETY: `self.0` is a valid regmap. The update is done under the lock of the regmap, and
+        // neither asynchronous nor forced.
+        to_result(unsafe {
+            bindings::regmap_update_bits_base(
+                self.0,
+                reg,
+                mask,
+                val,
+                core::ptr::null_mut(),
+                false,
+                false,
+            )
+        })
+    }
 }
 
 impl Drop for Regmap {
@@ -80,6 +226,38 @@ fn drop(&mut self) {
     }
 }
 
+/// Register default value
+///
+/// The cache starts with these values, and [`Regmap::cache_sync`] skips the registers that still
+/// hold them. Tables of defaults are passed to [`Config::with_reg_defaults`].
+#[repr(transparent)]
+pub struct RegDefault(bindings::reg_default);
+
+impl RegDefault {
+    /// Create the default `def` of register `reg`
+    pub const fn new(reg: u32, def: u32) -> Self {
+        Self(bindings::reg_default { reg, def })
+    }
+}
+
+/// Register write of a patch
+///
+/// Patches are registered with [`Regmap::register_patch`].
+#[repr(transparent)]
+pub struct RegSequence(bindings::reg_sequence);
+
+impl RegSequence {
+    /// Create a write of `def` to register `reg`
+    pub const fn new(reg: u32, def: u32) -> Self {
+        Self::with_delay(reg, def, 0)
+    }
+
+    /// Create a write of `def` to register `reg`, followed by a delay of `delay_us` microseconds
+    pub const fn with_delay(reg: u32, def: u32, delay_us: u32) -> Self {
+        Self(bindings::reg_sequence { reg, def, delay_us })
+    }
+}
+
 /// Field Descriptors
 ///
 /// FieldDescriptors can be created by calling the [`define_regmap_field_descs`] macro.
@@ -222,6 +400,13 @@ pub const fn new(reg_bits: i32, val_bits: i32) -> Self {
         cache_type: CacheType, cache_type as _
     );
 
+    /// Specifies the register defaults the cache starts with.
+    pub const fn with_reg_defaults(mut self, defaults: &'static [RegDefault]) -> Self {
+        self.raw.reg_defaults = defaults.as_ptr().cast();
+        self.raw.num_reg_defaults = defaults.len() as _;
+        self
+    }
+
     unsafe extern "C" fn writeable_reg_callback(_dev: *mut bindings::device, reg: u32) -> bool {
         T::is_writeable_reg(reg)
     }
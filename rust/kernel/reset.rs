@@ -0,0 +1,126 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Reset controls.
+//!
+//! IP blocks of SoCs are often held in reset through one or more reset lines until their driver
+//! takes them out of reset. [`Bulk`] handles all the reset lines of a device at once.
+//!
+//! C header: [`include/linux/reset.h`](srctree/include/linux/reset.h)
+
+use crate::{
+    bindings,
+    device::RawDevice,
+    error::{from_err_ptr, to_result, Error, Result},
+};
+use core::{mem::ManuallyDrop, ptr};
+
+/// All the reset lines of a device, acquired for exclusive use.
+///
+/// The lines are released when this object is dropped. They are deasserted by turning the object
+/// into a [`DeassertedBulk`].
+///
+/// # Invariants
+///
+/// `rstc` is a reset control array returned by `of_reset_control_array_get`. It may be null if
+/// the device has no reset lines, which the reset API treats as a no-op.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{clk, device::RawDevice, prelude::*, reset};
+///
+/// fn power_up(dev: &impl RawDevice) -> Result<(clk::EnabledBulk, reset::DeassertedBulk)> {
+///     let clks = clk::Bulk::get_all(dev)?.enable().map_err(|(e, _)| e)?;
+///     let resets = reset::Bulk::get_all(dev)?.deassert().map_err(|(e, _)| e)?;
+///     Ok((clks, resets))
+/// }
+/// ```
+pub struct Bulk {
+    rstc: *mut bindings::reset_control,
+}
+
+impl Bulk {
+    /// Gets all the reset lines of `dev`, as listed in its devicetree node.
+    ///
+    /// Devices without reset lines get an empty set, on which all operations succeed.
+    pub fn get_all(dev: &impl RawDevice) -> Result<Self> {
+        // SAFETY: `dev.raw_device()` is valid, so its `of_node` can be read.
+        let np = unsafe { (*dev.raw_device()).of_node };
+        // SAFETY: `np` is either null or a valid node. The lines are requested exclusively,
+        // optionally and acquired.
+        let rstc =
+            from_err_ptr(unsafe { bindings::of_reset_control_array_get(np, false, true, true) })?;
+        // INVARIANT: `rstc` was just returned by `of_reset_control_array_get`.
+        Ok(Self { rstc })
+    }
+
+    /// Asserts and deasserts all reset lines again, e.g., to recover the block from an error.
+    pub fn reset(&self) -> Result {
+        // SAFETY: By the type invariants, `rstc` is a valid or null reset control.
+        to_result(unsafe { bindings::reset_control_reset(self.rstc) })
+    }
+
+    /// Deasserts all the reset lines, taking the block out of reset.
+    ///
+    /// If one of them fails to deassert, the ones already deasserted are asserted again, and the
+    /// error is returned along with the lines.
+    pub fn deassert(self) -> core::result::Result<DeassertedBulk, (Error, Self)> {
+        // SAFETY: By the type invariants, `rstc` is a valid or null reset control.
+        match to_result(unsafe { bindings::reset_control_deassert(self.rstc) }) {
+            // INVARIANT: The lines were just deasserted.
+            Ok(()) => Ok(DeassertedBulk(self)),
+            Err(e) => Err((e, self)),
+        }
+    }
+}
+
+impl Drop for Bulk {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, `rstc` is a valid or null reset control.
+        unsafe { bindings::reset_control_put(self.rstc) };
+    }
+}
+
+// SAFETY: Reset controls can be used and released from any thread.
+unsafe impl Send for Bulk {}
+
+// SAFETY: The reset framework serialises the operations on reset controls internally.
+unsafe impl Sync for Bulk {}
+
+/// All the reset lines of a device, deasserted.
+///
+/// The lines are asserted again and released when this object is dropped.
+///
+/// # Invariants
+///
+/// The lines of the inner [`Bulk`] are deasserted.
+pub struct DeassertedBulk(Bulk);
+
+impl DeassertedBulk {
+    /// Asserts all the reset lines, putting the block back into reset, and gives them back.
+    ///
+    /// The lines are given back even if asserting fails.
+    pub fn assert(self) -> Bulk {
+        let this = ManuallyDrop::new(self);
+        // SAFETY: `this` is not dropped, so the lines are moved out exactly once.
+        let bulk = unsafe { ptr::read(&this.0) };
+        // SAFETY: By the type invariants, `rstc` is a valid or null reset control.
+        unsafe { bindings::reset_control_assert(bulk.rstc) };
+        bulk
+    }
+}
+
+impl core::ops::Deref for DeassertedBulk {
+    type Target = Bulk;
+
+    fn deref(&self) -> &Bulk {
+        &self.0
+    }
+}
+
+impl Drop for DeassertedBulk {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, `rstc` is a valid or null reset control.
+        unsafe { bindings::reset_control_assert(self.0.rstc) };
+    }
+}
@@ -4,31 +4,112 @@
 //!
 //! The [`Revocable`] type wraps other types and allows access to them to be revoked. The existence
 //! of a [`RevocableGuard`] ensures that objects remain valid.
+//!
+//! Access guards implement [`AccessGuard`], whose [`Context`] tells whether the holder of the
+//! guard may sleep. Code that needs to sleep while accessing a revocable object can require a
+//! [`Sleepable`] guard, so that passing it an [`Atomic`] one fails to compile.
+//!
+//! A [`RevocableRegistry`] holds many revocable objects indexed by a key, all of which can be
+//! revoked at once.
 
 use crate::{
     bindings,
     init::{self},
     prelude::*,
-    sync::rcu,
+    str::CStr,
+    sync::{new_spinlock, rcu, Arc, LockClassKey, LockdepMap, SpinLock},
 };
 use core::{
     cell::UnsafeCell,
-    marker::PhantomData,
     mem::MaybeUninit,
     ops::Deref,
     ptr::drop_in_place,
     sync::atomic::{fence, AtomicBool, AtomicU32, Ordering},
 };
 
+mod private {
+    pub trait Sealed {}
+}
+
+/// The context in which the holder of an access guard runs.
+///
+/// This is a sealed trait implemented by [`Atomic`] and [`Sleepable`].
+pub trait Context: private::Sealed {
+    /// Whether the holder of the guard may sleep.
+    const MAY_SLEEP: bool;
+}
+
+/// The context of guards whose holder must not sleep, e.g., because they hold the RCU read-side
+/// lock.
+///
+/// Sleeping while holding such a guard is reported at runtime by lockdep when
+/// `CONFIG_PROVE_RCU` is enabled.
+pub enum Atomic {}
+
+impl private::Sealed for Atomic {}
+
+impl Context for Atomic {
+    const MAY_SLEEP: bool = false;
+}
+
+/// The context of guards whose holder may sleep.
+pub enum Sleepable {}
+
+impl private::Sealed for Sleepable {}
+
+impl Context for Sleepable {
+    const MAY_SLEEP: bool = true;
+}
+
+/// A guard that gives access to a revocable object and keeps it alive.
+///
+/// # Examples
+///
+/// Functions that sleep while accessing the object can require a sleepable guard:
+///
+/// ```
+/// use kernel::revocable::{AccessGuard, AsyncRevocable, Sleepable};
+///
+/// fn wait_for_hw(regs: impl AccessGuard<Target = u32, Context = Sleepable>) -> u32 {
+///     // Sleeping here, e.g., with `msleep`, is fine.
+///     *regs
+/// }
+///
+/// let v = AsyncRevocable::new(42u32);
+/// assert_eq!(wait_for_hw(v.try_access().unwrap()), 42);
+/// ```
+///
+/// Passing an atomic guard to such a function fails to compile:
+///
+/// ```compile_fail
+/// use kernel::{new_revocable, revocable::{AccessGuard, Sleepable}};
+///
+/// fn wait_for_hw(regs: impl AccessGuard<Target = u32, Context = Sleepable>) -> u32 {
+///     *regs
+/// }
+///
+/// let v = Box::pin_init(new_revocable!(42u32), GFP_KERNEL)?;
+/// wait_for_hw(v.try_access().unwrap());
+/// # Ok::<(), Error>(())
+/// ```
+pub trait AccessGuard: Deref {
+    /// The context in which the holder of the guard runs.
+    type Context: Context;
+}
+
 /// An object that can become inaccessible at runtime.
 ///
 /// Once access is revoked and all concurrent users complete (i.e., all existing instances of
 /// [`RevocableGuard`] are dropped), the wrapped object is also dropped.
 ///
+/// Instances need a lock class, which lockdep uses to check that [`Revocable::revoke`] is never
+/// called while holding something that users of the object need, including access to the object
+/// itself. The recommended way to create them is with the [`new_revocable`] macro.
+///
 /// # Examples
 ///
 /// ```
-/// # use kernel::revocable::Revocable;
+/// use kernel::{new_revocable, revocable::Revocable};
 ///
 /// struct Example {
 ///     a: u32,
@@ -40,16 +121,17 @@
 ///     Some(guard.a + guard.b)
 /// }
 ///
-/// let v = Revocable::new(Example { a: 10, b: 20 });
+/// let v = Box::pin_init(new_revocable!(Example { a: 10, b: 20 }), GFP_KERNEL)?;
 /// assert_eq!(add_two(&v), Some(30));
 /// v.revoke();
 /// assert_eq!(add_two(&v), None);
+/// # Ok::<(), Error>(())
 /// ```
 ///
 /// Sample example as above, but explicitly using the rcu read side lock.
 ///
 /// ```
-/// # use kernel::revocable::Revocable;
+/// use kernel::{new_revocable, revocable::Revocable};
 /// use kernel::sync::rcu;
 ///
 /// struct Example {
@@ -63,18 +145,33 @@
 ///     Some(e.a + e.b)
 /// }
 ///
-/// let v = Revocable::new(Example { a: 10, b: 20 });
+/// let v = Box::pin_init(new_revocable!(Example { a: 10, b: 20 }), GFP_KERNEL)?;
 /// assert_eq!(add_two(&v), Some(30));
 /// v.revoke();
 /// assert_eq!(add_two(&v), None);
+/// # Ok::<(), Error>(())
 /// ```
 #[pin_data(PinnedDrop)]
 pub struct Revocable<T> {
     is_available: AtomicBool,
     #[pin]
+    lockdep: LockdepMap,
+    #[pin]
     data: MaybeUninit<UnsafeCell<T>>,
 }
 
+/// Creates a [`Revocable`] initialiser with the given name and a newly-created lock class.
+#[macro_export]
+macro_rules! new_revocable {
+    ($inner:expr $(, $name:literal)? $(,)?) => {
+        $crate::revocable::Revocable::new(
+            $inner,
+            $crate::optional_name!($($name)?),
+            $crate::static_lock_class!(),
+        )
+    };
+}
+
 // SAFETY: `Revocable` is `Send` if the wrapped object is also `Send`. This is because while the
 // functionality exposed by `Revocable` can be accessed from any thread/CPU, it is possible that
 // this isn't supported by the wrapped object.
@@ -87,9 +184,16 @@ unsafe impl<T: Sync + Send> Sync for Revocable<T> {}
 
 impl<T> Revocable<T> {
     /// Creates a new revocable instance of the given data.
-    pub fn new(data: impl PinInit<T>) -> impl PinInit<Self> {
+    ///
+    /// It is recommended to use the [`new_revocable`] macro, which creates the lock class.
+    pub fn new(
+        data: impl PinInit<T>,
+        name: &'static CStr,
+        key: &'static LockClassKey,
+    ) -> impl PinInit<Self> {
         pin_init!(Self {
             is_available: AtomicBool::new(true),
+            lockdep <- LockdepMap::new(name, key),
             data <- unsafe { init::pin_init_from_closure(move |slot: *mut MaybeUninit<UnsafeCell<T>>| {
                 init::PinInit::<T, core::convert::Infallible>::__pinned_init(data, slot as *mut T)?;
                 Ok::<(), core::convert::Infallible>(())
@@ -107,9 +211,12 @@ pub fn new(data: impl PinInit<T>) -> impl PinInit<Self> {
     pub fn try_access(&self) -> Option<RevocableGuard<'_, T>> {
         let guard = rcu::read_lock();
         if self.is_available.load(Ordering::Relaxed) {
+            self.lockdep.acquire_read();
             // SAFETY: Since `self.is_available` is true, data is initialised and has to remain
             // valid because the RCU read side lock prevents it from being dropped.
-            Some(unsafe { RevocableGuard::new(self.data.assume_init_ref().get(), guard) })
+            Some(unsafe {
+                RevocableGuard::new(self.data.assume_init_ref().get(), &self.lockdep, guard)
+            })
         } else {
             None
         }
@@ -139,18 +246,31 @@ pub fn try_access_with_guard<'a>(&'a self, _guard: &'a rcu::Guard) -> Option<&'a
     /// there are concurrent users of the object (i.e., ones that called [`Revocable::try_access`]
     /// beforehand and still haven't dropped the returned guard), this function waits for the
     /// concurrent access to complete before dropping the wrapped object.
+    ///
+    /// Callers must not hold a guard of the same object, or anything that holders of a guard may
+    /// wait for, since that would deadlock; lockdep reports such cases.
+    ///
+    /// The wait is reported to lock statistics and contention tracing under the name of the lock
+    /// class of the object, e.g., in `perf lock`.
     pub fn revoke(&self) {
+        self.lockdep.acquire();
+
         if self
             .is_available
             .compare_exchange(true, false, Ordering::Relaxed, Ordering::Relaxed)
             .is_ok()
         {
+            self.lockdep.contended();
             // SAFETY: Just an FFI call, there are no further requirements.
             unsafe { bindings::synchronize_rcu() };
+            self.lockdep.acquired();
+            self.lockdep.release();
 
             // SAFETY: We know `self.data` is valid because only one CPU can succeed the
             // `compare_exchange` above that takes `is_available` from `true` to `false`.
             unsafe { drop_in_place(self.data.assume_init_ref().get()) };
+        } else {
+            self.lockdep.release();
         }
     }
 }
@@ -174,23 +294,23 @@ fn drop(self: Pin<&mut Self>) {
 /// A guard that allows access to a revocable object and keeps it alive.
 ///
 /// CPUs may not sleep while holding on to [`RevocableGuard`] because it's in atomic context
-/// holding the RCU read-side lock.
+/// holding the RCU read-side lock. Its [`AccessGuard::Context`] is therefore [`Atomic`].
 ///
 /// # Invariants
 ///
-/// The RCU read-side lock is held while the guard is alive.
+/// The RCU read-side lock is held and `lockdep` is acquired for reading while the guard is alive.
 pub struct RevocableGuard<'a, T> {
     data_ref: *const T,
+    lockdep: &'a LockdepMap,
     _rcu_guard: rcu::Guard,
-    _p: PhantomData<&'a ()>,
 }
 
-impl<T> RevocableGuard<'_, T> {
-    fn new(data_ref: *const T, rcu_guard: rcu::Guard) -> Self {
+impl<'a, T> RevocableGuard<'a, T> {
+    fn new(data_ref: *const T, lockdep: &'a LockdepMap, rcu_guard: rcu::Guard) -> Self {
         Self {
             data_ref,
+            lockdep,
             _rcu_guard: rcu_guard,
-            _p: PhantomData,
         }
     }
 }
@@ -205,6 +325,18 @@ fn deref(&self) -> &Self::Target {
     }
 }
 
+impl<T> Drop for RevocableGuard<'_, T> {
+    fn drop(&mut self) {
+        // By the type invariants, `lockdep` was acquired for reading. The RCU read-side lock is
+        // released afterwards, when `_rcu_guard` is dropped.
+        self.lockdep.release();
+    }
+}
+
+impl<T> AccessGuard for RevocableGuard<'_, T> {
+    type Context = Atomic;
+}
+
 /// An object that can become inaccessible at runtime.
 ///
 /// Once access is revoked and all concurrent users complete (i.e., all existing instances of
@@ -387,6 +519,10 @@ pub struct AsyncRevocableGuard<'a, T> {
     revocable: &'a AsyncRevocable<T>,
 }
 
+impl<T> AccessGuard for AsyncRevocableGuard<'_, T> {
+    type Context = Sleepable;
+}
+
 impl<T> Deref for AsyncRevocableGuard<'_, T> {
     type Target = T;
 
@@ -436,3 +572,122 @@ fn drop(&mut self) {
         }
     }
 }
+
+/// A registry of revocable objects indexed by a key.
+///
+/// Bus and subsystem drivers often hand out contexts to child devices or clients, and must revoke
+/// access to all of them when the parent goes away. The registry allows concurrent accesses to
+/// the entries by key, and revokes all of them with [`RevocableRegistry::revoke_all`], after
+/// which no entries can be added anymore.
+///
+/// Entries are [`AsyncRevocable`]: revoking them does not wait for users that are still accessing
+/// them, the objects are dropped when the last one completes.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::revocable::RevocableRegistry;
+///
+/// struct Channel {
+///     irq: u32,
+/// }
+///
+/// let registry = Box::pin_init(RevocableRegistry::<u8, Channel>::new(), GFP_KERNEL)?;
+/// registry.insert(0, Channel { irq: 10 })?;
+/// registry.insert(1, Channel { irq: 11 })?;
+/// assert!(registry.insert(1, Channel { irq: 12 }).is_err());
+///
+/// assert_eq!(registry.try_access(&1, |c| c.irq), Some(11));
+/// assert_eq!(registry.try_access(&2, |c| c.irq), None);
+///
+/// assert!(registry.revoke(&0));
+/// assert_eq!(registry.try_access(&0, |c| c.irq), None);
+///
+/// registry.revoke_all();
+/// assert_eq!(registry.try_access(&1, |c| c.irq), None);
+/// assert!(registry.insert(2, Channel { irq: 12 }).is_err());
+/// # Ok::<(), Error>(())
+/// ```
+#[pin_data]
+pub struct RevocableRegistry<K, T> {
+    #[pin]
+    inner: SpinLock<RegistryInner<K, T>>,
+}
+
+struct RegistryInner<K, T> {
+    entries: Vec<(K, Arc<AsyncRevocable<T>>)>,
+    closed: bool,
+}
+
+impl<K: PartialEq, T: Send + Sync> RevocableRegistry<K, T> {
+    /// Creates a new, empty registry.
+    pub fn new() -> impl PinInit<Self> {
+        pin_init!(Self {
+            inner <- new_spinlock!(
+                RegistryInner {
+                    entries: Vec::new(),
+                    closed: false,
+                },
+                "RevocableRegistry::inner"
+            ),
+        })
+    }
+
+    /// Adds `data` to the registry under `key`.
+    ///
+    /// Fails with [`EEXIST`] if there already is an entry for `key`, and with [`ENODEV`] if
+    /// [`RevocableRegistry::revoke_all`] was called.
+    pub fn insert(&self, key: K, data: T) -> Result {
+        let entry = Arc::new(AsyncRevocable::new(data), GFP_KERNEL)?;
+        let mut inner = self.inner.lock();
+        if inner.closed {
+            return Err(ENODEV);
+        }
+        if inner.entries.iter().any(|(k, _)| *k == key) {
+            return Err(EEXIST);
+        }
+        inner.entries.push((key, entry), GFP_ATOMIC)?;
+        Ok(())
+    }
+
+    /// Calls `f` with the entry for `key`, unless there is none or it has been revoked.
+    ///
+    /// The entry is guaranteed to remain valid while `f` runs. The registry lock is not held
+    /// while `f` runs, so accesses to the same or to different entries can happen concurrently.
+    pub fn try_access<R>(&self, key: &K, f: impl FnOnce(&T) -> R) -> Option<R> {
+        let entry = {
+            let inner = self.inner.lock();
+            let (_, entry) = inner.entries.iter().find(|(k, _)| k == key)?;
+            entry.clone()
+        };
+        let guard = entry.try_access()?;
+        Some(f(&guard))
+    }
+
+    /// Revokes and removes the entry for `key`.
+    ///
+    /// Returns `false` if there was no entry for `key`.
+    pub fn revoke(&self, key: &K) -> bool {
+        let entry = {
+            let mut inner = self.inner.lock();
+            match inner.entries.iter().position(|(k, _)| k == key) {
+                Some(i) => inner.entries.swap_remove(i),
+                None => return false,
+            }
+        };
+        entry.1.revoke();
+        true
+    }
+
+    /// Revokes and removes all entries, and prevents new ones from being added.
+    pub fn revoke_all(&self) {
+        let entries = {
+            let mut inner = self.inner.lock();
+            inner.closed = true;
+            core::mem::take(&mut inner.entries)
+        };
+        for (_, entry) in &entries {
+            entry.revoke();
+        }
+    }
+}
@@ -0,0 +1,266 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Ring buffers shared with userspace through `mmap`.
+//!
+//! A [`SharedRing`] is a single-producer, single-consumer ring of fixed-size records that is
+//! mapped into a process, so that records can be passed without a system call per record, e.g.,
+//! for telemetry or capture interfaces. Either side can be the producer.
+//!
+//! The mapping has the following layout, with every index counting records modulo 2^32:
+//!
+//! | Offset           | Contents                                             |
+//! |------------------|------------------------------------------------------|
+//! | 0                | producer index (`u32`), written only by the producer |
+//! | `PAGE_SIZE`      | consumer index (`u32`), written only by the consumer |
+//! | `2 * PAGE_SIZE`  | `len` records                                        |
+//!
+//! The indices live in separate pages so that they do not share cache lines and so that
+//! userspace can map the page it must not write read-only. The producer writes a record and then
+//! publishes it with a store-release of the producer index; the consumer reads the producer index
+//! with a load-acquire, reads the record and then frees its slot with a store-release of the
+//! consumer index. Userspace must use the same ordering.
+//!
+//! C header: [`include/linux/vmalloc.h`](srctree/include/linux/vmalloc.h)
+
+use crate::{
+    bindings,
+    error::{code::*, to_result, Result},
+};
+use core::{
+    marker::PhantomData,
+    mem::size_of,
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+/// A type that can be stored in a [`SharedRing`].
+///
+/// # Safety
+///
+/// Userspace can write arbitrary bytes to the records, so implementers must be valid for every bit
+/// pattern and must not contain padding, which would leak kernel memory to userspace.
+pub unsafe trait Record: Copy {}
+
+macro_rules! impl_record {
+    ($($t:ty),*) => {
+        $(
+            // SAFETY: Integers are valid for every bit pattern and have no padding.
+            unsafe impl Record for $t {}
+        )*
+    };
+}
+
+impl_record!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+// SAFETY: Arrays of records are valid for every bit pattern and have no padding.
+unsafe impl<T: Record, const N: usize> Record for [T; N] {}
+
+/// The side of a [`SharedRing`] that the kernel is on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// The kernel produces records and userspace consumes them.
+    ToUser,
+    /// Userspace produces records and the kernel consumes them.
+    FromUser,
+}
+
+const PAGE_SIZE: usize = bindings::PAGE_SIZE as usize;
+const DATA_OFFSET: usize = 2 * PAGE_SIZE;
+
+/// A ring buffer that can be mapped into userspace.
+///
+/// Only one side of the ring is accessed from the kernel, depending on its [`Direction`]: records
+/// are added with [`SharedRing::push`] by producers and taken with [`SharedRing::pop`] by
+/// consumers. Both take `&mut self` because the ring only supports a single producer and
+/// consumer; drivers that produce from several contexts need to serialise them, e.g., with a
+/// spinlock.
+///
+/// Since userspace can write to the whole mapping at any time, indices written by it are never
+/// trusted: a producer index that claims more records than the ring can hold, or a consumer index
+/// beyond the last produced record, makes the operation fail with [`EINVAL`].
+///
+/// # Invariants
+///
+/// `mem` points to a zeroed `vmalloc_user` allocation of [`SharedRing::size`] bytes, with the
+/// layout of the module documentation. `len` is a power of two.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::shared_ring::{Direction, SharedRing};
+///
+/// let mut ring = SharedRing::<u64>::new(64, Direction::ToUser)?;
+/// ring.push(42)?;
+/// assert_eq!(ring.pending()?, 1);
+/// # Ok::<(), Error>(())
+/// ```
+pub struct SharedRing<T: Record> {
+    mem: NonNull<u8>,
+    len: usize,
+    dir: Direction,
+    _p: PhantomData<T>,
+}
+
+impl<T: Record> SharedRing<T> {
+    /// Allocates a ring of at least `len` records, in the given direction.
+    ///
+    /// `len` is rounded up to the next power of two, and must be at most `2^31`.
+    pub fn new(len: usize, dir: Direction) -> Result<Self> {
+        if len == 0 || len > 1 << 31 || size_of::<T>() == 0 {
+            return Err(EINVAL);
+        }
+        let len = len.next_power_of_two();
+        let size = Self::size_for(len).ok_or(ENOMEM)?;
+        // SAFETY: `vmalloc_user` can be called with any size; it returns zeroed memory that is
+        // suitable for mapping into userspace.
+        let mem = NonNull::new(unsafe { bindings::vmalloc_user(size as _) }).ok_or(ENOMEM)?;
+        // INVARIANT: The allocation is zeroed, so both indices are zero, and has room for `len`
+        // records, which is a power of two.
+        Ok(Self {
+            mem: mem.cast(),
+            len,
+            dir,
+            _p: PhantomData,
+        })
+    }
+
+    fn size_for(len: usize) -> Option<usize> {
+        let data = len.checked_mul(size_of::<T>())?;
+        let data = data.checked_add(PAGE_SIZE - 1)? & !(PAGE_SIZE - 1);
+        data.checked_add(DATA_OFFSET)
+    }
+
+    /// Returns the number of records the ring can hold.
+    pub fn capacity(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the size of the mapping of the ring, in bytes.
+    pub fn size(&self) -> usize {
+        // The size was checked when the ring was allocated.
+        Self::size_for(self.len).unwrap_or(0)
+    }
+
+    /// Returns the direction of the ring.
+    pub fn direction(&self) -> Direction {
+        self.dir
+    }
+
+    fn producer(&self) -> &AtomicU32 {
+        // SAFETY: By the type invariants, the producer index is at the start of the allocation,
+        // which is page aligned.
+        unsafe { &*self.mem.as_ptr().cast::<AtomicU32>() }
+    }
+
+    fn consumer(&self) -> &AtomicU32 {
+        // SAFETY: By the type invariants, the consumer index is at `PAGE_SIZE`.
+        unsafe { &*self.mem.as_ptr().add(PAGE_SIZE).cast::<AtomicU32>() }
+    }
+
+    fn slot(&self, index: u32) -> *mut T {
+        let i = index as usize & (self.len - 1);
+        // SAFETY: By the type invariants, there is room for `len` records at `DATA_OFFSET`, and
+        // `i` is less than `len`. The data is page aligned, so the records are aligned too.
+        unsafe { self.mem.as_ptr().add(DATA_OFFSET).cast::<T>().add(i) }
+    }
+
+    /// Returns the number of records that were produced but not consumed yet.
+    ///
+    /// Fails with [`EINVAL`] if userspace corrupted the indices.
+    pub fn pending(&self) -> Result<usize> {
+        let head = self.producer().load(Ordering::Acquire);
+        let tail = self.consumer().load(Ordering::Acquire);
+        let pending = head.wrapping_sub(tail) as usize;
+        if pending > self.len {
+            return Err(EINVAL);
+        }
+        Ok(pending)
+    }
+
+    /// Adds `record` to the ring.
+    ///
+    /// Fails with [`ENOSPC`] if the ring is full, or with [`EINVAL`] if the ring does not go to
+    /// userspace or userspace corrupted the consumer index.
+    pub fn push(&mut self, record: T) -> Result {
+        if self.dir != Direction::ToUser {
+            return Err(EINVAL);
+        }
+
+        // Only we write the producer index, so it does not need to be ordered against anything.
+        let head = self.producer().load(Ordering::Relaxed);
+        // Pairs with the store-release of the consumer index by userspace, so that its reads of
+        // the slot are complete before we overwrite it.
+        let tail = self.consumer().load(Ordering::Acquire);
+        match head.wrapping_sub(tail) as usize {
+            n if n == self.len => return Err(ENOSPC),
+            n if n > self.len => return Err(EINVAL),
+            _ => {}
+        }
+
+        // SAFETY: The slot is within the allocation and userspace does not read it before the
+        // producer index is published below. It may write to it anyway, which would only corrupt
+        // its own record, so the write is volatile.
+        unsafe { ptr::write_volatile(self.slot(head), record) };
+        // Publishes the record: pairs with the load-acquire of the producer index by userspace.
+        self.producer()
+            .store(head.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Takes the oldest record from the ring.
+    ///
+    /// Returns [`None`] if the ring is empty. Fails with [`EINVAL`] if the ring does not come from
+    /// userspace or userspace corrupted the producer index.
+    pub fn pop(&mut self) -> Result<Option<T>> {
+        if self.dir != Direction::FromUser {
+            return Err(EINVAL);
+        }
+
+        let tail = self.consumer().load(Ordering::Relaxed);
+        // Pairs with the store-release of the producer index by userspace, so that the record is
+        // visible.
+        let head = self.producer().load(Ordering::Acquire);
+        match head.wrapping_sub(tail) as usize {
+            0 => return Ok(None),
+            n if n > self.len => return Err(EINVAL),
+            _ => {}
+        }
+
+        // SAFETY: The slot is within the allocation. `T` is valid for all bit patterns, so reading
+        // it is fine even if userspace writes to it concurrently.
+        let record = unsafe { ptr::read_volatile(self.slot(tail)) };
+        // Frees the slot: pairs with the load-acquire of the consumer index by userspace.
+        self.consumer()
+            .store(tail.wrapping_add(1), Ordering::Release);
+        Ok(Some(record))
+    }
+
+    /// Maps the ring into `vma`.
+    ///
+    /// The mapping must cover the whole ring, i.e., start at offset zero and be
+    /// [`SharedRing::size`] bytes long.
+    ///
+    /// # Safety
+    ///
+    /// `vma` must be a valid VMA that is being set up, e.g., in the `mmap` file operation. The
+    /// ring must outlive the mapping, which is usually ensured by keeping it alive until the file
+    /// is released.
+    pub unsafe fn mmap(&self, vma: *mut bindings::vm_area_struct) -> Result {
+        // SAFETY: `vma` is valid by the safety requirements, and the allocation is from
+        // `vmalloc_user`. The function checks that the VMA fits the allocation.
+        to_result(unsafe { bindings::remap_vmalloc_range(vma, self.mem.as_ptr().cast(), 0) })
+    }
+}
+
+impl<T: Record> Drop for SharedRing<T> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, `mem` was allocated with `vmalloc_user`.
+        unsafe { bindings::vfree(self.mem.as_ptr().cast()) };
+    }
+}
+
+// SAFETY: The allocation can be freed from any thread, and records are `Copy`.
+unsafe impl<T: Record + Send> Send for SharedRing<T> {}
+
+// SAFETY: The methods that access the ring through `&self` only read the indices atomically.
+unsafe impl<T: Record + Sync> Sync for SharedRing<T> {}
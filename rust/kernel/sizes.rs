@@ -0,0 +1,107 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Commonly used sizes.
+//!
+//! The constants mirror the `SZ_*` definitions from the C side so that resource and buffer sizes
+//! used by Rust code line up with (and can be grepped for alongside) the C conventions.
+//!
+//! C header: [`include/linux/sizes.h`](srctree/include/linux/sizes.h)
+//!
+//! # Examples
+//!
+//! ```
+//! use kernel::sizes::{kb, mb, SZ_1M, SZ_4K};
+//!
+//! assert_eq!(kb(4), SZ_4K);
+//! assert_eq!(mb(1), SZ_1M);
+//! assert_eq!(kb(1024), mb(1));
+//! ```
+
+/// 0x00000001
+pub const SZ_1: usize = 0x00000001;
+/// 0x00000002
+pub const SZ_2: usize = 0x00000002;
+/// 0x00000004
+pub const SZ_4: usize = 0x00000004;
+/// 0x00000008
+pub const SZ_8: usize = 0x00000008;
+/// 0x00000010
+pub const SZ_16: usize = 0x00000010;
+/// 0x00000020
+pub const SZ_32: usize = 0x00000020;
+/// 0x00000040
+pub const SZ_64: usize = 0x00000040;
+/// 0x00000080
+pub const SZ_128: usize = 0x00000080;
+/// 0x00000100
+pub const SZ_256: usize = 0x00000100;
+/// 0x00000200
+pub const SZ_512: usize = 0x00000200;
+/// 0x00000400
+pub const SZ_1K: usize = 0x00000400;
+/// 0x00000800
+pub const SZ_2K: usize = 0x00000800;
+/// 0x00001000
+pub const SZ_4K: usize = 0x00001000;
+/// 0x00002000
+pub const SZ_8K: usize = 0x00002000;
+/// 0x00004000
+pub const SZ_16K: usize = 0x00004000;
+/// 0x00008000
+pub const SZ_32K: usize = 0x00008000;
+/// 0x00010000
+pub const SZ_64K: usize = 0x00010000;
+/// 0x00020000
+pub const SZ_128K: usize = 0x00020000;
+/// 0x00040000
+pub const SZ_256K: usize = 0x00040000;
+/// 0x00080000
+pub const SZ_512K: usize = 0x00080000;
+/// 0x00100000
+pub const SZ_1M: usize = 0x00100000;
+/// 0x00200000
+pub const SZ_2M: usize = 0x00200000;
+/// 0x00400000
+pub const SZ_4M: usize = 0x00400000;
+/// 0x00800000
+pub const SZ_8M: usize = 0x00800000;
+/// 0x01000000
+pub const SZ_16M: usize = 0x01000000;
+/// 0x02000000
+pub const SZ_32M: usize = 0x02000000;
+/// 0x04000000
+pub const SZ_64M: usize = 0x04000000;
+/// 0x08000000
+pub const SZ_128M: usize = 0x08000000;
+/// 0x10000000
+pub const SZ_256M: usize = 0x10000000;
+/// 0x20000000
+pub const SZ_512M: usize = 0x20000000;
+/// 0x40000000
+pub const SZ_1G: usize = 0x40000000;
+/// 0x80000000
+pub const SZ_2G: usize = 0x80000000;
+
+/// Returns the size in bytes of `n` kibibytes, i.e., `n * SZ_1K`.
+///
+/// Evaluating this in a const context with a value that overflows `usize` fails the build.
+#[inline]
+pub const fn kb(n: usize) -> usize {
+    n * SZ_1K
+}
+
+/// Returns the size in bytes of `n` mebibytes, i.e., `n * SZ_1M`.
+///
+/// Evaluating this in a const context with a value that overflows `usize` fails the build.
+#[inline]
+pub const fn mb(n: usize) -> usize {
+    n * SZ_1M
+}
+
+/// Returns the size in bytes of `n` gibibytes, i.e., `n * SZ_1G`.
+///
+/// Evaluating this in a const context with a value that overflows `usize` fails the build.
+#[inline]
+pub const fn gb(n: usize) -> usize {
+    n * SZ_1G
+}
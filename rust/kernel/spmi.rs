@@ -0,0 +1,449 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! SPMI devices, drivers and controllers.
+//!
+//! The System Power Management Interface is a two-wire bus that is mostly used to access PMICs.
+//! Each device on the bus has a 4-bit slave id (`usid`), and registers in a 16-bit address space.
+//!
+//! C header: [`include/linux/spmi.h`](srctree/include/linux/spmi.h)
+
+use crate::{
+    bindings,
+    device::{self, RawDevice},
+    driver::{self, Adapter as _},
+    error::{code::*, from_err_ptr, from_result, to_result, Result},
+    of, pm,
+    str::CStr,
+    types::ForeignOwnable,
+    ThisModule,
+};
+use core::{marker::PhantomData, ptr::NonNull};
+use macros::vtable;
+
+/// A registration of an SPMI driver.
+pub type Registration<T> = driver::Registration<Adapter<T>>;
+
+/// An adapter for the registration of SPMI drivers.
+pub struct Adapter<T: Driver>(T);
+
+impl<T: Driver> driver::Adapter for Adapter<T> {
+    type IdInfo = T::IdInfo;
+    type Data = T::Data;
+    const OF_ID_TABLE: Option<driver::IdTable<'static, of::DeviceId, T::IdInfo>> =
+        T::OF_DEVICE_ID_TABLE;
+    const PM_OPS: Option<&'static pm::Ops<T::Data>> = T::PM_OPS;
+}
+
+impl<T: Driver> driver::DriverOps for Adapter<T> {
+    type RegType = bindings::spmi_driver;
+
+    unsafe fn register(
+        reg: *mut bindings::spmi_driver,
+        name: &'static CStr,
+        module: &'static ThisModule,
+    ) -> Result {
+        // SAFETY: By the safety requirements of this function (defined in the trait definition),
+        // `reg` is non-null and valid.
+        let sdrv = unsafe { &mut *reg };
+
+        Self::init_driver(&mut sdrv.driver, name);
+        sdrv.probe = Some(Self::probe_callback);
+        sdrv.remove = Some(Self::remove_callback);
+
+        // SAFETY:
+        //   - `sdrv` lives at least until the call to `spmi_driver_unregister()` returns.
+        //   - `name` pointer has static lifetime.
+        //   - `module.0` lives at least as long as the module.
+        //   - `probe()` and `remove()` are static functions.
+        //   - `of_match_table` is either a raw pointer with static lifetime,
+        //      as guaranteed by the [`driver::IdTable`] type, or null.
+        to_result(unsafe { bindings::__spmi_driver_register(reg, module.0) })
+    }
+
+    unsafe fn unregister(reg: *mut bindings::spmi_driver) {
+        // SAFETY: By the safety requirements of this function (defined in the trait definition),
+        // `reg` was passed (and updated) by a previous successful call to
+        // `__spmi_driver_register`.
+        unsafe { bindings::spmi_driver_unregister(reg) };
+    }
+}
+
+impl<T: Driver> Adapter<T> {
+    extern "C" fn probe_callback(sdev: *mut bindings::spmi_device) -> core::ffi::c_int {
+        // SAFETY: `sdev` is valid by the contract with the C code. `dev` is alive only for the
+        // duration of this call, so it is guaranteed to remain alive for the lifetime of `sdev`.
+        let mut dev = unsafe { Device::from_ptr(sdev) };
+        // SAFETY: No driver data has been set yet because the device is only now being bound.
+        unsafe { Self::probe_device(&mut dev, |dev| T::probe(dev, Self::of_id_info(dev))) }
+    }
+
+    extern "C" fn remove_callback(sdev: *mut bindings::spmi_device) {
+        // SAFETY: `sdev` is guaranteed to be a valid, non-null pointer.
+        let dev = unsafe { Device::from_ptr(sdev) };
+        // SAFETY: The driver data was set in `probe`, and `remove` is the canonical kernel
+        // location to free it.
+        unsafe { Self::remove_device(&dev, T::remove) };
+    }
+}
+
+/// An SPMI driver.
+pub trait Driver {
+    /// Data stored on device by driver.
+    ///
+    /// Corresponds to the data set or retrieved via the kernel's
+    /// `spmi_device_{set,get}_drvdata()` functions.
+    type Data: ForeignOwnable + Send + Sync + driver::DeviceRemoval + 'static = ();
+
+    /// The type holding information about each device id supported by the driver.
+    type IdInfo: 'static = ();
+
+    /// The table of device ids supported by the driver.
+    const OF_DEVICE_ID_TABLE: Option<driver::IdTable<'static, of::DeviceId, Self::IdInfo>> = None;
+
+    /// The power management callbacks of the driver, usually created with [`pm::ops`].
+    ///
+    /// [`pm::ops`]: crate::pm::ops
+    const PM_OPS: Option<&'static pm::Ops<Self::Data>> = None;
+
+    /// SPMI driver probe.
+    ///
+    /// Called when a new SPMI device is added or discovered.
+    /// Implementers should attempt to initialize the device here.
+    ///
+    /// If a resource the device depends on is not available yet, return [`EPROBE_DEFER`] so that
+    /// probing is retried later; [`dev_err_probe`] records the reason for the deferral.
+    ///
+    /// [`EPROBE_DEFER`]: crate::error::code::EPROBE_DEFER
+    /// [`dev_err_probe`]: crate::dev_err_probe
+    fn probe(dev: &mut Device, id_info: Option<&Self::IdInfo>) -> Result<Self::Data>;
+
+    /// SPMI driver remove.
+    ///
+    /// Called when an SPMI device is removed.
+    fn remove(_data: &Self::Data) {}
+}
+
+/// An SPMI device.
+///
+/// # Invariants
+///
+/// The field `ptr` is non-null and valid for the lifetime of the object.
+pub struct Device {
+    ptr: *mut bindings::spmi_device,
+}
+
+impl Device {
+    /// Creates a new device from the given pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be non-null and valid. It must remain valid for the lifetime of the returned
+    /// instance.
+    unsafe fn from_ptr(ptr: *mut bindings::spmi_device) -> Self {
+        // INVARIANT: The safety requirements of the function ensure the lifetime invariant.
+        Self { ptr }
+    }
+
+    /// Returns the raw SPMI device structure.
+    pub fn raw_spmi_device(&self) -> *mut bindings::spmi_device {
+        self.ptr
+    }
+
+    /// Returns the slave id of the device.
+    pub fn usid(&self) -> u8 {
+        // SAFETY: By the type invariants, we know that `self.ptr` is non-null and valid.
+        unsafe { (*self.ptr).usid }
+    }
+
+    /// Reads a register in the 5-bit address space, using the "register read" command.
+    pub fn read(&self, addr: u8) -> Result<u8> {
+        if addr > 0x1f {
+            return Err(EINVAL);
+        }
+        let mut val = 0;
+        // SAFETY: By the type invariants, `self.ptr` is valid, and `val` is valid for writes.
+        to_result(unsafe { bindings::spmi_register_read(self.ptr, addr, &mut val) })?;
+        Ok(val)
+    }
+
+    /// Writes a register in the 5-bit address space, using the "register write" command.
+    pub fn write(&self, addr: u8, val: u8) -> Result {
+        if addr > 0x1f {
+            return Err(EINVAL);
+        }
+        // SAFETY: By the type invariants, `self.ptr` is valid.
+        to_result(unsafe { bindings::spmi_register_write(self.ptr, addr, val) })
+    }
+
+    /// Writes `val` to register 0, using the "register zero write" command.
+    pub fn zero_write(&self, val: u8) -> Result {
+        // SAFETY: By the type invariants, `self.ptr` is valid.
+        to_result(unsafe { bindings::spmi_register_zero_write(self.ptr, val) })
+    }
+
+    /// Reads `buf.len()` consecutive registers in the 16-bit address space, starting at `addr`.
+    ///
+    /// Between 1 and 8 registers can be read at once.
+    pub fn ext_read(&self, addr: u16, buf: &mut [u8]) -> Result {
+        if buf.is_empty() || buf.len() > 8 {
+            return Err(EINVAL);
+        }
+        // SAFETY: By the type invariants, `self.ptr` is valid, and `buf` is valid for writes of
+        // `buf.len()` bytes.
+        to_result(unsafe {
+            bindings::spmi_ext_register_readl(self.ptr, addr, buf.as_mut_ptr(), buf.len())
+        })
+    }
+
+    /// Writes `buf.len()` consecutive registers in the 16-bit address space, starting at `addr`.
+    ///
+    /// Between 1 and 8 registers can be written at once.
+    pub fn ext_write(&self, addr: u16, buf: &[u8]) -> Result {
+        if buf.is_empty() || buf.len() > 8 {
+            return Err(EINVAL);
+        }
+        // SAFETY: By the type invariants, `self.ptr` is valid, and `buf` is valid for reads of
+        // `buf.len()` bytes.
+        to_result(unsafe {
+            bindings::spmi_ext_register_writel(self.ptr, addr, buf.as_ptr(), buf.len())
+        })
+    }
+
+    /// Sends the "wakeup" command to the device.
+    pub fn wakeup(&self) -> Result {
+        // SAFETY: By the type invariants, `self.ptr` is valid.
+        to_result(unsafe { bindings::spmi_command_wakeup(self.ptr) })
+    }
+
+    /// Sends the "sleep" command to the device.
+    pub fn sleep(&self) -> Result {
+        // SAFETY: By the type invariants, `self.ptr` is valid.
+        to_result(unsafe { bindings::spmi_command_sleep(self.ptr) })
+    }
+
+    /// Sends the "reset" command to the device.
+    pub fn reset(&self) -> Result {
+        // SAFETY: By the type invariants, `self.ptr` is valid.
+        to_result(unsafe { bindings::spmi_command_reset(self.ptr) })
+    }
+
+    /// Sends the "shutdown" command to the device.
+    pub fn shutdown(&self) -> Result {
+        // SAFETY: By the type invariants, `self.ptr` is valid.
+        to_result(unsafe { bindings::spmi_command_shutdown(self.ptr) })
+    }
+}
+
+// SAFETY: The device returned by `raw_device` is the raw SPMI device.
+unsafe impl device::RawDevice for Device {
+    fn raw_device(&self) -> *mut bindings::device {
+        // SAFETY: By the type invariants, we know that `self.ptr` is non-null and valid.
+        unsafe { &mut (*self.ptr).dev }
+    }
+}
+
+/// Operations of an SPMI controller.
+///
+/// `opcode` is the SPMI command to send, and `sid` the slave id of the target device.
+#[vtable]
+pub trait Controller {
+    /// The context data made available to the callbacks.
+    type Data: ForeignOwnable + Send + Sync;
+
+    /// Sends a command that carries no data, e.g. "reset" or "sleep".
+    fn cmd(data: <Self::Data as ForeignOwnable>::Borrowed<'_>, opcode: u8, sid: u8) -> Result;
+
+    /// Sends a read command, and stores the data returned by the device into `buf`.
+    fn read_cmd(
+        data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        opcode: u8,
+        sid: u8,
+        addr: u16,
+        buf: &mut [u8],
+    ) -> Result;
+
+    /// Sends a write command with the data in `buf`.
+    fn write_cmd(
+        data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        opcode: u8,
+        sid: u8,
+        addr: u16,
+        buf: &[u8],
+    ) -> Result;
+}
+
+/// A registered SPMI controller.
+///
+/// Devices described as children of the controller's DT node are added when the controller is
+/// registered. The controller is removed and its data freed when the registration is dropped.
+///
+/// # Invariants
+///
+/// `ctrl` was allocated and added by [`ControllerRegistration::register`], and its driver data
+/// was obtained from [`ForeignOwnable::into_foreign`] on a `T::Data`.
+pub struct ControllerRegistration<T: Controller> {
+    ctrl: NonNull<bindings::spmi_controller>,
+    _p: PhantomData<T>,
+}
+
+impl<T: Controller> ControllerRegistration<T> {
+    /// Allocates and registers a new SPMI controller as a child of `parent`.
+    pub fn register(parent: &impl RawDevice, data: T::Data) -> Result<Self> {
+        // SAFETY: `parent` is valid by its type invariants. No extra private space is requested.
+        let ctrl = unsafe { bindings::spmi_controller_alloc(parent.raw_device(), 0) };
+        let ctrl = from_err_ptr(ctrl)?;
+
+        // SAFETY: `ctrl` was just allocated and is not shared yet.
+        unsafe {
+            (*ctrl).cmd = Some(Self::cmd_callback);
+            (*ctrl).read_cmd = Some(Self::read_cmd_callback);
+            (*ctrl).write_cmd = Some(Self::write_cmd_callback);
+            bindings::dev_set_drvdata(&mut (*ctrl).dev, data.into_foreign() as *mut _);
+        }
+
+        // SAFETY: `ctrl` was allocated above, and all the callbacks are set.
+        if let Err(e) = to_result(unsafe { bindings::spmi_controller_add(ctrl) }) {
+            // SAFETY: The controller was not added, so it is only referenced from here.
+            unsafe { Self::free(ctrl) };
+            return Err(e);
+        }
+
+        // INVARIANT: The controller was allocated and added above.
+        Ok(Self {
+            // SAFETY: `ctrl` is not null, `from_err_ptr` checked for errors.
+            ctrl: unsafe { NonNull::new_unchecked(ctrl) },
+            _p: PhantomData,
+        })
+    }
+
+    /// Returns the bus number of the controller.
+    pub fn nr(&self) -> u32 {
+        // SAFETY: By the type invariants, `self.ctrl` is valid.
+        unsafe { (*self.ctrl.as_ptr()).nr }
+    }
+
+    /// Frees the driver data and drops the reference to the controller.
+    ///
+    /// # Safety
+    ///
+    /// `ctrl` must have been allocated by [`ControllerRegistration::register`] and must not be
+    /// registered with the SPMI core.
+    unsafe fn free(ctrl: *mut bindings::spmi_controller) {
+        // SAFETY: By the safety requirements, `ctrl` is valid and its driver data was set by
+        // `register`, from `into_foreign`. No callbacks can run anymore.
+        unsafe {
+            let ptr = bindings::dev_get_drvdata(&mut (*ctrl).dev);
+            drop(T::Data::from_foreign(ptr));
+            bindings::spmi_controller_put(ctrl);
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `ctrl` must be a controller registered by [`ControllerRegistration::register`].
+    unsafe fn data<'a>(
+        ctrl: *mut bindings::spmi_controller,
+    ) -> <T::Data as ForeignOwnable>::Borrowed<'a> {
+        // SAFETY: By the safety requirements, the driver data came from `into_foreign`, and is
+        // only freed after the controller is removed.
+        unsafe { T::Data::borrow(bindings::dev_get_drvdata(&mut (*ctrl).dev)) }
+    }
+
+    unsafe extern "C" fn cmd_callback(
+        ctrl: *mut bindings::spmi_controller,
+        opcode: u8,
+        sid: u8,
+    ) -> core::ffi::c_int {
+        from_result(|| {
+            // SAFETY: The SPMI core only calls this for controllers registered with it.
+            T::cmd(unsafe { Self::data(ctrl) }, opcode, sid)?;
+            Ok(0)
+        })
+    }
+
+    unsafe extern "C" fn read_cmd_callback(
+        ctrl: *mut bindings::spmi_controller,
+        opcode: u8,
+        sid: u8,
+        addr: u16,
+        buf: *mut u8,
+        len: usize,
+    ) -> core::ffi::c_int {
+        from_result(|| {
+            // SAFETY: The SPMI core only calls this for controllers registered with it, with a
+            // buffer that is valid for writes of `len` bytes.
+            let (data, buf) =
+                unsafe { (Self::data(ctrl), core::slice::from_raw_parts_mut(buf, len)) };
+            T::read_cmd(data, opcode, sid, addr, buf)?;
+            Ok(0)
+        })
+    }
+
+    unsafe extern "C" fn write_cmd_callback(
+        ctrl: *mut bindings::spmi_controller,
+        opcode: u8,
+        sid: u8,
+        addr: u16,
+        buf: *const u8,
+        len: usize,
+    ) -> core::ffi::c_int {
+        from_result(|| {
+            // SAFETY: The SPMI core only calls this for controllers registered with it, with a
+            // buffer that is valid for reads of `len` bytes.
+            let (data, buf) = unsafe { (Self::data(ctrl), core::slice::from_raw_parts(buf, len)) };
+            T::write_cmd(data, opcode, sid, addr, buf)?;
+            Ok(0)
+        })
+    }
+}
+
+impl<T: Controller> Drop for ControllerRegistration<T> {
+    fn drop(&mut self) {
+        let ctrl = self.ctrl.as_ptr();
+        // SAFETY: By the type invariants, `ctrl` was added to the SPMI core. Removing it also
+        // removes its child devices.
+        unsafe { bindings::spmi_controller_remove(ctrl) };
+        // SAFETY: By the type invariants, `ctrl` was allocated by `register`, and it was removed
+        // above.
+        unsafe { Self::free(ctrl) };
+    }
+}
+
+// SAFETY: The registration only holds a `T::Data`, which is `Send`, and the controller, which can
+// be removed from any thread.
+unsafe impl<T: Controller> Send for ControllerRegistration<T> {}
+
+// SAFETY: All methods taking `&self` only read immutable fields of the controller.
+unsafe impl<T: Controller> Sync for ControllerRegistration<T> {}
+
+/// Declares a kernel module that exposes a single SPMI driver.
+///
+/// # Examples
+///
+/// ```ignore
+/// # use kernel::{spmi, define_of_id_table, module_spmi_driver};
+/// #
+/// struct MyDriver;
+/// impl spmi::Driver for MyDriver {
+///     // [...]
+/// #   fn probe(_dev: &mut spmi::Device, _id_info: Option<&Self::IdInfo>) -> Result {
+/// #       Ok(())
+/// #   }
+/// #   define_of_id_table! {(), [
+/// #       (of::DeviceId::Compatible(b"qcom,spmi-pmic"), None),
+/// #   ]}
+/// }
+///
+/// module_spmi_driver! {
+///     type: MyDriver,
+///     name: "module_name",
+///     author: "Author name",
+///     license: "GPL",
+/// }
+/// ```
+#[macro_export]
+macro_rules! module_spmi_driver {
+    ($($f:tt)*) => {
+        $crate::module_driver!(<T>, $crate::spmi::Adapter<T>, { $($f)* });
+    };
+}
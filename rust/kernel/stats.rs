@@ -0,0 +1,186 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Statistics counters.
+//!
+//! Driver statistics such as packet, byte and error counts are updated on hot paths from many
+//! CPUs but read rarely. [`PerCpuCounter`] keeps a local count on each CPU so that updates do not
+//! bounce cache lines between CPUs, and only folds the local counts together when read.
+//!
+//! C header: [`include/linux/percpu_counter.h`](srctree/include/linux/percpu_counter.h)
+
+use crate::{
+    alloc::Flags, bindings, error::Error, ffi_init, init::PinInit, sync::LockClassKey,
+    try_pin_init, types::Opaque,
+};
+use core::{fmt, marker::PhantomPinned, pin::Pin};
+use macros::{pin_data, pinned_drop};
+
+/// Creates a [`PerCpuCounter`] initialiser with a newly-created lock class.
+#[macro_export]
+macro_rules! new_percpu_counter {
+    ($flags:expr) => {
+        $crate::stats::PerCpuCounter::new($flags, $crate::static_lock_class!())
+    };
+}
+pub use new_percpu_counter;
+
+/// A counter with per-CPU local counts.
+///
+/// Updates ([`PerCpuCounter::add`], [`PerCpuCounter::inc`]) only touch the local CPU's count,
+/// which is folded into the global count once it grows beyond a batch size. Readers can choose
+/// between a cheap approximate value ([`PerCpuCounter::read`]) that may lag behind by up to the
+/// batch size times the number of CPUs, and an exact value ([`PerCpuCounter::sum`]) that visits
+/// every CPU.
+///
+/// Wraps the kernel's [`struct percpu_counter`].
+///
+/// # Examples
+///
+/// ```
+/// use kernel::stats::{self, new_percpu_counter, PerCpuCounter};
+///
+/// #[pin_data]
+/// struct Stats {
+///     #[pin]
+///     packets: PerCpuCounter,
+///     #[pin]
+///     bytes: PerCpuCounter,
+/// }
+///
+/// impl Stats {
+///     fn new() -> impl PinInit<Self, Error> {
+///         try_pin_init!(Self {
+///             packets <- new_percpu_counter!(GFP_KERNEL),
+///             bytes <- new_percpu_counter!(GFP_KERNEL),
+///         })
+///     }
+///
+///     fn rx(&self, len: usize) {
+///         self.packets.inc();
+///         self.bytes.add(len as u64);
+///     }
+///
+///     /// Fills `data` in the layout expected by ethtool's `get_ethtool_stats`.
+///     fn fill(&self, data: &mut [u64]) {
+///         stats::sum_into(&[&self.packets, &self.bytes], data);
+///     }
+/// }
+/// ```
+///
+/// [`struct percpu_counter`]: srctree/include/linux/percpu_counter.h
+#[pin_data(PinnedDrop)]
+pub struct PerCpuCounter {
+    #[pin]
+    counter: Opaque<bindings::percpu_counter>,
+
+    /// On SMP kernels, the counter is linked into a global list so that the local counts of CPUs
+    /// that go offline can be folded in, so it cannot be moved once it is initialised.
+    #[pin]
+    _pin: PhantomPinned,
+}
+
+// SAFETY: `percpu_counter` is designed to be used from any thread.
+unsafe impl Send for PerCpuCounter {}
+
+// SAFETY: `percpu_counter` is designed to be used concurrently from multiple threads; updates go
+// to per-CPU storage and the global count is protected by an internal spinlock.
+unsafe impl Sync for PerCpuCounter {}
+
+impl PerCpuCounter {
+    /// Constructs a new counter initialiser, with the counter starting at zero.
+    ///
+    /// `flags` is used to allocate the per-CPU local counts.
+    pub fn new(flags: Flags, key: &'static LockClassKey) -> impl PinInit<Self, Error> {
+        try_pin_init!(Self {
+            // SAFETY: `slot` is valid while the closure is called, and `key` has a static
+            // lifetime so it lives indefinitely. On failure, nothing is left to be cleaned up.
+            counter <- ffi_init!(unsafe bindings::__percpu_counter_init_many(
+                slot,
+                0,
+                flags.as_raw(),
+                1,
+                key.as_ptr(),
+            )?),
+            _pin: PhantomPinned,
+        }? Error)
+    }
+
+    fn as_raw(&self) -> *mut bindings::percpu_counter {
+        self.counter.get()
+    }
+
+    /// Adds `amount` to the counter.
+    ///
+    /// Only the local CPU's count is updated, unless it crosses the batch size.
+    #[inline]
+    pub fn add(&self, amount: u64) {
+        // SAFETY: The counter was initialised in `new`. Negative values are allowed by the C API,
+        // so the conversion cannot break it either.
+        unsafe { bindings::percpu_counter_add(self.as_raw(), amount as i64) };
+    }
+
+    /// Increments the counter by one.
+    #[inline]
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    /// Returns the approximate value of the counter.
+    ///
+    /// This is cheap, but does not include the counts that have not been folded in yet.
+    #[inline]
+    pub fn read(&self) -> u64 {
+        // SAFETY: The counter was initialised in `new`.
+        unsafe { bindings::percpu_counter_read_positive(self.as_raw()) as u64 }
+    }
+
+    /// Returns the exact value of the counter.
+    ///
+    /// This visits the local count of every online CPU, so it should not be used on hot paths.
+    pub fn sum(&self) -> u64 {
+        // SAFETY: The counter was initialised in `new`.
+        unsafe { bindings::percpu_counter_sum_positive(self.as_raw()) as u64 }
+    }
+
+    /// Resets the counter to zero.
+    ///
+    /// Concurrent updates may or may not be included in the new value.
+    pub fn reset(&self) {
+        // SAFETY: The counter was initialised in `new`.
+        unsafe { bindings::percpu_counter_set(self.as_raw(), 0) };
+    }
+}
+
+#[pinned_drop]
+impl PinnedDrop for PerCpuCounter {
+    fn drop(self: Pin<&mut Self>) {
+        // SAFETY: The counter was initialised in `new`, and is not used after this.
+        unsafe { bindings::percpu_counter_destroy(self.as_raw()) };
+    }
+}
+
+/// Formats the exact value of the counter, e.g. for sysfs or debugfs files.
+impl fmt::Display for PerCpuCounter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.sum(), f)
+    }
+}
+
+impl fmt::Debug for PerCpuCounter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PerCpuCounter")
+            .field("sum", &self.sum())
+            .finish()
+    }
+}
+
+/// Stores the exact values of `counters` into `data`, in order.
+///
+/// This matches the layout expected by ethtool's `get_ethtool_stats`, where the driver reports an
+/// array of `u64` values whose names were previously reported by `get_strings`. Only as many
+/// values as fit into the shorter of the two slices are stored.
+pub fn sum_into(counters: &[&PerCpuCounter], data: &mut [u64]) {
+    for (value, counter) in data.iter_mut().zip(counters) {
+        *value = counter.sum();
+    }
+}
@@ -41,8 +41,152 @@ pub const fn from_bytes(bytes: &[u8]) -> &Self {
     pub const fn deref_const(&self) -> &[u8] {
         &self.0
     }
+
+    /// Returns `true` if the string starts with `prefix`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use kernel::b_str;
+    /// assert!(b_str!("vendor,device").starts_with("vendor,"));
+    /// assert!(!b_str!("vendor,device").starts_with(b"device"));
+    /// ```
+    #[inline]
+    pub fn starts_with(&self, prefix: impl AsRef<[u8]>) -> bool {
+        self.0.starts_with(prefix.as_ref())
+    }
+
+    /// Returns `true` if the string ends with `suffix`.
+    #[inline]
+    pub fn ends_with(&self, suffix: impl AsRef<[u8]>) -> bool {
+        self.0.ends_with(suffix.as_ref())
+    }
+
+    /// Returns the string without `prefix`, or [`None`] if it does not start with `prefix`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use kernel::b_str;
+    /// let s = b_str!("vendor,device");
+    /// assert_eq!(s.strip_prefix("vendor,").unwrap(), b_str!("device"));
+    /// assert!(s.strip_prefix("other,").is_none());
+    /// ```
+    #[inline]
+    pub fn strip_prefix(&self, prefix: impl AsRef<[u8]>) -> Option<&BStr> {
+        self.0.strip_prefix(prefix.as_ref()).map(BStr::from_bytes)
+    }
+
+    /// Returns the string without `suffix`, or [`None`] if it does not end with `suffix`.
+    #[inline]
+    pub fn strip_suffix(&self, suffix: impl AsRef<[u8]>) -> Option<&BStr> {
+        self.0.strip_suffix(suffix.as_ref()).map(BStr::from_bytes)
+    }
+
+    /// Returns the string with leading and trailing ASCII whitespace removed.
+    ///
+    /// This is typically used on values written to sysfs or procfs files, which usually end with
+    /// a newline.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use kernel::b_str;
+    /// assert_eq!(b_str!("  on\n").trim(), b_str!("on"));
+    /// ```
+    pub fn trim(&self) -> &BStr {
+        self.trim_start().trim_end()
+    }
+
+    /// Returns the string with leading ASCII whitespace removed.
+    pub fn trim_start(&self) -> &BStr {
+        let start = self
+            .0
+            .iter()
+            .position(|b| !b.is_ascii_whitespace())
+            .unwrap_or(self.0.len());
+        BStr::from_bytes(&self.0[start..])
+    }
+
+    /// Returns the string with trailing ASCII whitespace removed.
+    pub fn trim_end(&self) -> &BStr {
+        let end = self
+            .0
+            .iter()
+            .rposition(|b| !b.is_ascii_whitespace())
+            .map_or(0, |i| i + 1);
+        BStr::from_bytes(&self.0[..end])
+    }
+
+    /// Returns an iterator over the substrings separated by `sep`.
+    ///
+    /// Like [`str::split`], empty substrings are returned for adjacent separators.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use kernel::b_str;
+    /// let mut it = b_str!("a,b,,c").split(b',');
+    /// assert_eq!(it.next(), Some(b_str!("a")));
+    /// assert_eq!(it.next(), Some(b_str!("b")));
+    /// assert_eq!(it.next(), Some(b_str!("")));
+    /// assert_eq!(it.next(), Some(b_str!("c")));
+    /// assert_eq!(it.next(), None);
+    /// ```
+    pub fn split(&self, sep: u8) -> impl Iterator<Item = &BStr> {
+        self.0.split(move |&b| b == sep).map(BStr::from_bytes)
+    }
+
+    /// Returns an iterator over the substrings separated by ASCII whitespace.
+    ///
+    /// Unlike [`BStr::split`], no empty substrings are returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use kernel::b_str;
+    /// let mut it = b_str!(" reset  0x10\n").split_ascii_whitespace();
+    /// assert_eq!(it.next(), Some(b_str!("reset")));
+    /// assert_eq!(it.next(), Some(b_str!("0x10")));
+    /// assert_eq!(it.next(), None);
+    /// ```
+    pub fn split_ascii_whitespace(&self) -> impl Iterator<Item = &BStr> {
+        self.0
+            .split(u8::is_ascii_whitespace)
+            .filter(|s| !s.is_empty())
+            .map(BStr::from_bytes)
+    }
+
+    /// Returns `true` if the string is equal to `other`, ignoring ASCII case.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use kernel::b_str;
+    /// assert!(b_str!("Enabled").eq_ignore_ascii_case("ENABLED"));
+    /// ```
+    #[inline]
+    pub fn eq_ignore_ascii_case(&self, other: impl AsRef<[u8]>) -> bool {
+        self.0.eq_ignore_ascii_case(other.as_ref())
+    }
+}
+
+impl AsRef<[u8]> for BStr {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
 }
 
+impl PartialEq for BStr {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for BStr {}
+
 impl fmt::Display for BStr {
     /// Formats printable ASCII characters, escaping the rest.
     ///
@@ -382,6 +526,26 @@ pub fn to_ascii_uppercase(&self) -> Result<CString, AllocError> {
 
         Ok(s)
     }
+
+    /// Returns the string without `prefix`, or [`None`] if it does not start with `prefix`.
+    ///
+    /// Unlike [`BStr::strip_prefix`], the result is still a [`CStr`], since removing a prefix
+    /// keeps the `NUL` terminator.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use kernel::c_str;
+    /// let compatible = c_str!("vendor,device");
+    /// assert_eq!(compatible.strip_prefix("vendor,").unwrap(), c_str!("device"));
+    /// ```
+    pub fn strip_prefix(&self, prefix: impl AsRef<[u8]>) -> Option<&CStr> {
+        let prefix = prefix.as_ref();
+        if !self.as_bytes().starts_with(prefix) {
+            return None;
+        }
+        Some(&self[prefix.len()..])
+    }
 }
 
 impl fmt::Display for CStr {
@@ -451,6 +615,22 @@ fn as_ref(&self) -> &BStr {
     }
 }
 
+impl PartialEq for CStr {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl Eq for CStr {}
+
+impl AsRef<[u8]> for CStr {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
 impl Deref for CStr {
     type Target = BStr;
 
@@ -635,6 +815,76 @@ fn test_bstr_debug() {
         let good_bytes = BStr::from_bytes(b"\xf0\x9f\xa6\x80");
         assert_eq!(format!("{:?}", good_bytes), "\"\\xf0\\x9f\\xa6\\x80\"");
     }
+
+    #[test]
+    fn test_bstr_trim() {
+        assert_eq!(
+            BStr::from_bytes(b" \t\xff\n").trim(),
+            BStr::from_bytes(b"\xff")
+        );
+        assert_eq!(BStr::from_bytes(b" \n ").trim(), BStr::from_bytes(b""));
+        assert_eq!(
+            BStr::from_bytes(b" a ").trim_start(),
+            BStr::from_bytes(b"a ")
+        );
+        assert_eq!(BStr::from_bytes(b" a ").trim_end(), BStr::from_bytes(b" a"));
+    }
+
+    #[test]
+    fn test_bstr_affixes() {
+        let s = BStr::from_bytes(b"d\xe9j\xe0 vu");
+        assert!(s.starts_with(b"d\xe9"));
+        assert!(s.ends_with("vu"));
+        assert_eq!(
+            s.strip_prefix(b"d\xe9j"),
+            Some(BStr::from_bytes(b"\xe0 vu"))
+        );
+        assert_eq!(s.strip_suffix(" vu"), Some(BStr::from_bytes(b"d\xe9j\xe0")));
+        assert_eq!(s.strip_prefix("vu"), None);
+        assert!(s.eq_ignore_ascii_case(b"D\xe9J\xe0 VU"));
+        assert!(!s.eq_ignore_ascii_case(b"D\xc9J\xe0 VU"));
+    }
+
+    #[test]
+    fn test_bstr_split() {
+        let s = BStr::from_bytes(b",a,\xff,");
+        let mut it = s.split(b',');
+        assert_eq!(it.next(), Some(BStr::from_bytes(b"")));
+        assert_eq!(it.next(), Some(BStr::from_bytes(b"a")));
+        assert_eq!(it.next(), Some(BStr::from_bytes(b"\xff")));
+        assert_eq!(it.next(), Some(BStr::from_bytes(b"")));
+        assert_eq!(it.next(), None);
+
+        let s = BStr::from_bytes(b"\t1 \n 2\n");
+        let mut it = s.split_ascii_whitespace();
+        assert_eq!(it.next(), Some(BStr::from_bytes(b"1")));
+        assert_eq!(it.next(), Some(BStr::from_bytes(b"2")));
+        assert_eq!(it.next(), None);
+    }
+
+    #[test]
+    fn test_seq_buf_truncation() {
+        let mut s = SeqBuf::<5>::new();
+        assert!(s.write_str("ab").is_ok());
+        // "é" is two bytes and does not fit after "abcd".
+        assert!(s.write_str("cdé").is_err());
+        assert_eq!(s.as_str(), "abcd");
+        assert!(s.is_truncated());
+        assert_eq!(s.remaining(), 1);
+        // Once truncated, later writes keep failing even if they fit.
+        assert!(s.write_str("e").is_err());
+        assert_eq!(s.as_str(), "abcd");
+        assert_eq!(format!("{s:?}"), "\"abcd\"");
+    }
+
+    #[test]
+    fn test_cstr_strip_prefix() {
+        let s = CStr::from_bytes_with_nul(b"vendor,\xffdev\0").unwrap();
+        let rest = s.strip_prefix("vendor,").unwrap();
+        assert_eq!(rest.as_bytes_with_nul(), b"\xffdev\0");
+        assert!(s.strip_prefix("other").is_none());
+        assert_eq!(s.strip_prefix(s.as_bytes()).unwrap().len(), 0);
+    }
 }
 
 /// Allows formatting of [`fmt::Arguments`] into a raw buffer.
@@ -774,6 +1024,145 @@ fn write_str(&mut self, s: &str) -> fmt::Result {
     }
 }
 
+/// A fixed-size string buffer that lives on the stack, similar to the C `struct seq_buf`.
+///
+/// Writing to it never allocates, so it can be used to build log lines or small sysfs outputs in
+/// atomic context. Output that does not fit is dropped, and the buffer remembers that it was
+/// truncated and drops all later output. Truncation never splits a UTF-8 character.
+///
+/// # Invariants
+///
+/// `len <= N` and `buf[..len]` is valid UTF-8.
+///
+/// # Examples
+///
+/// ```
+/// use core::fmt::Write;
+/// use kernel::str::SeqBuf;
+///
+/// let mut s = SeqBuf::<16>::new();
+/// write!(s, "irq {}: ", 42).unwrap();
+/// assert_eq!(s.as_str(), "irq 42: ");
+/// assert!(!s.is_truncated());
+///
+/// assert!(write!(s, "{}", "spurious interrupt").is_err());
+/// assert_eq!(s.as_str(), "irq 42: spurious");
+/// assert!(s.is_truncated());
+///
+/// s.clear();
+/// assert!(s.is_empty() && !s.is_truncated());
+/// ```
+pub struct SeqBuf<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    truncated: bool,
+}
+
+impl<const N: usize> SeqBuf<N> {
+    /// Creates a new, empty buffer.
+    pub const fn new() -> Self {
+        // INVARIANT: The buffer is empty.
+        Self {
+            buf: [0; N],
+            len: 0,
+            truncated: false,
+        }
+    }
+
+    /// Returns the number of bytes written to the buffer.
+    #[inline]
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if nothing was written to the buffer.
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of bytes that can still be written to the buffer.
+    #[inline]
+    pub const fn remaining(&self) -> usize {
+        N - self.len
+    }
+
+    /// Returns `true` if some output did not fit in the buffer and was dropped.
+    #[inline]
+    pub const fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Empties the buffer and clears the truncation flag.
+    pub fn clear(&mut self) {
+        // INVARIANT: The buffer is empty.
+        self.len = 0;
+        self.truncated = false;
+    }
+
+    /// Returns the contents of the buffer.
+    pub fn as_str(&self) -> &str {
+        // SAFETY: By the type invariants, `buf[..len]` is valid UTF-8.
+        unsafe { core::str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+
+    /// Returns the contents of the buffer as bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl<const N: usize> Default for SeqBuf<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> fmt::Write for SeqBuf<N> {
+    /// Appends `s` to the buffer.
+    ///
+    /// Fails if `s` does not fit in full, in which case as much of it as fits is written and the
+    /// buffer is marked as truncated. Once truncated, nothing is written until the buffer is
+    /// cleared, so that the output is never spliced around the dropped part.
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.truncated {
+            return Err(fmt::Error);
+        }
+
+        let remaining = self.remaining();
+        let mut n = s.len();
+        if n > remaining {
+            n = remaining;
+            while !s.is_char_boundary(n) {
+                n -= 1;
+            }
+            self.truncated = true;
+        }
+
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        // INVARIANT: `n <= remaining`, and `s[..n]` is valid UTF-8 as `n` is a char boundary.
+        self.len += n;
+
+        if self.truncated {
+            Err(fmt::Error)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<const N: usize> fmt::Display for SeqBuf<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl<const N: usize> fmt::Debug for SeqBuf<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
 /// An owned string that is guaranteed to have exactly one `NUL` byte, which is at the end.
 ///
 /// Used for interoperability with kernel APIs that take C strings.
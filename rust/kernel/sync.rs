@@ -5,21 +5,32 @@
 //! This module contains the kernel APIs related to synchronisation that have been ported or
 //! wrapped for usage by Rust code in the kernel.
 
-use crate::types::Opaque;
+use crate::{bindings, init::PinInit, pin_init, str::CStr, types::Opaque};
+use macros::pin_data;
 
 mod arc;
+pub mod atomic;
+mod completion;
 mod condvar;
 pub mod lock;
 mod locked_by;
+mod once_lock;
 pub mod rcu;
 mod revocable;
 
 pub use arc::{Arc, ArcBorrow, UniqueArc};
+pub use completion::Completion;
 pub use condvar::{new_condvar, CondVar, CondVarTimeoutResult};
 pub use lock::mutex::{new_mutex, Mutex};
+pub use lock::raw_spinlock::{new_raw_spinlock, RawSpinLock};
+pub use lock::rwsem::{new_rwsem, RwSemaphore};
 pub use lock::spinlock::{new_spinlock, SpinLock};
 pub use locked_by::LockedBy;
-pub use revocable::{RevocableMutex, RevocableMutexGuard};
+pub use once_lock::{LazyLock, OnceLock};
+pub use revocable::{
+    RevocableMutex, RevocableMutexGuard, RevocableRwSemaphore, RevocableRwSemaphoreGuard,
+    RevocableRwSemaphoreReadGuard, RevokePolicy,
+};
 
 /// Represents a lockdep class. It's a wrapper around C's `lock_class_key`.
 #[repr(transparent)]
@@ -40,6 +51,104 @@ pub(crate) fn as_ptr(&self) -> *mut bindings::lock_class_key {
     }
 }
 
+/// A lockdep map, which lets lockdep track dependencies on objects that are not locks.
+///
+/// Acquiring the map tells lockdep that the current context now depends on the object, e.g.,
+/// because it holds access to it, and acquiring it exclusively tells it that the current context
+/// waits for all such users to go away. Lockdep then reports waits that can deadlock, as it does
+/// for locks. When lock debugging is disabled, the map is empty and all operations are no-ops.
+#[pin_data]
+pub(crate) struct LockdepMap {
+    #[pin]
+    map: Opaque<bindings::lockdep_map>,
+}
+
+impl LockdepMap {
+    /// Creates a new lockdep map in the given lock class.
+    pub(crate) fn new(name: &'static CStr, key: &'static LockClassKey) -> impl PinInit<Self> {
+        pin_init!(Self {
+            map <- Opaque::ffi_init(|slot| {
+                #[cfg(CONFIG_DEBUG_LOCK_ALLOC)]
+                // SAFETY: `slot` is valid for writes, and `name` and `key` are static.
+                unsafe {
+                    bindings::lockdep_init_map(slot, name.as_char_ptr(), key.as_ptr(), 0)
+                };
+                #[cfg(not(CONFIG_DEBUG_LOCK_ALLOC))]
+                let _ = (slot, name, key);
+            }),
+        })
+    }
+
+    /// Records that the current context has shared access to the object.
+    ///
+    /// Shared acquisitions may nest, including recursively on the same map.
+    pub(crate) fn acquire_read(&self) {
+        #[cfg(CONFIG_DEBUG_LOCK_ALLOC)]
+        // SAFETY: The map was initialised in `new`.
+        unsafe {
+            bindings::lock_acquire(self.map.get(), 0, 0, 2, 1, core::ptr::null_mut(), 0)
+        };
+    }
+
+    /// Records that the current context waits for all users of the object to go away.
+    ///
+    /// The wait itself is reported with [`LockdepMap::contended`] and [`LockdepMap::acquired`],
+    /// and ends with [`LockdepMap::release`].
+    pub(crate) fn acquire(&self) {
+        #[cfg(CONFIG_DEBUG_LOCK_ALLOC)]
+        // SAFETY: The map was initialised in `new`.
+        unsafe {
+            bindings::lock_acquire(self.map.get(), 0, 0, 0, 1, core::ptr::null_mut(), 0)
+        };
+    }
+
+    /// Records that the current context starts waiting for the object, after
+    /// [`LockdepMap::acquire`].
+    ///
+    /// With `CONFIG_LOCK_STAT`, the wait is accounted in `/proc/lock_stat` and emitted as a
+    /// `lock:lock_contended` event under the name of the map, which is what `perf lock report`
+    /// shows. It is also emitted as a `lock:contention_begin` event for `perf lock contention`.
+    pub(crate) fn contended(&self) {
+        #[cfg(CONFIG_LOCK_STAT)]
+        // SAFETY: The map was initialised in `new` and, by the contract of this function, was
+        // acquired by the current context.
+        unsafe {
+            bindings::lock_contended(self.map.get(), 0)
+        };
+        // SAFETY: The tracepoint only records the address, it is never dereferenced.
+        unsafe { bindings::trace_contention_begin(self.map.get().cast(), bindings::LCB_F_WRITE) };
+    }
+
+    /// Records that the wait started with [`LockdepMap::contended`] is over.
+    pub(crate) fn acquired(&self) {
+        #[cfg(CONFIG_LOCK_STAT)]
+        // SAFETY: The map was initialised in `new` and, by the contract of this function, was
+        // acquired by the current context.
+        unsafe {
+            bindings::lock_acquired(self.map.get(), 0)
+        };
+        // SAFETY: The tracepoint only records the address, it is never dereferenced.
+        unsafe { bindings::trace_contention_end(self.map.get().cast(), 0) };
+    }
+
+    /// Releases an acquisition made with [`LockdepMap::acquire_read`] or [`LockdepMap::acquire`].
+    pub(crate) fn release(&self) {
+        #[cfg(CONFIG_DEBUG_LOCK_ALLOC)]
+        // SAFETY: The map was initialised in `new` and, by the contract of this function, was
+        // acquired by the current context.
+        unsafe {
+            bindings::lock_release(self.map.get(), 0)
+        };
+    }
+}
+
+// SAFETY: `lockdep_map` is designed to be used concurrently from multiple threads, lockdep
+// provides its own synchronisation.
+unsafe impl Send for LockdepMap {}
+
+// SAFETY: See the `Send` implementation.
+unsafe impl Sync for LockdepMap {}
+
 /// Defines a new static lock class and returns a pointer to it.
 #[doc(hidden)]
 #[macro_export]
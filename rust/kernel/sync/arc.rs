@@ -26,6 +26,7 @@
 use alloc::boxed::Box;
 use core::{
     alloc::Layout,
+    any::Any,
     fmt,
     marker::{PhantomData, Unsize},
     mem::{ManuallyDrop, MaybeUninit},
@@ -126,6 +127,37 @@
 /// let coerced: Arc<dyn MyTrait> = obj;
 /// # Ok::<(), Error>(())
 /// ```
+///
+/// Trait objects let a subsystem store callbacks of different types in one collection, without
+/// making the registration generic over each of them:
+///
+/// ```
+/// use kernel::sync::Arc;
+///
+/// trait Notifier: Send + Sync {
+///     fn notify(&self, event: u32) -> u32;
+/// }
+///
+/// struct Add(u32);
+/// impl Notifier for Add {
+///     fn notify(&self, event: u32) -> u32 {
+///         event + self.0
+///     }
+/// }
+///
+/// struct Mul(u32);
+/// impl Notifier for Mul {
+///     fn notify(&self, event: u32) -> u32 {
+///         event * self.0
+///     }
+/// }
+///
+/// let mut chain: Vec<Arc<dyn Notifier>> = Vec::new();
+/// chain.push(Arc::new(Add(1), GFP_KERNEL)?, GFP_KERNEL)?;
+/// chain.push(Arc::new(Mul(3), GFP_KERNEL)?, GFP_KERNEL)?;
+/// assert_eq!(chain.iter().fold(1, |ev, n| n.notify(ev)), 6);
+/// # Ok::<(), Error>(())
+/// ```
 pub struct Arc<T: ?Sized> {
     ptr: NonNull<ArcInner<T>>,
     _p: PhantomData<ArcInner<T>>,
@@ -148,6 +180,10 @@ impl<T: ?Sized + Unsize<U>, U: ?Sized> core::ops::CoerceUnsized<Arc<U>> for Arc<
 // This is to allow `Arc<U>` to be dispatched on when `Arc<T>` can be coerced into `Arc<U>`.
 impl<T: ?Sized + Unsize<U>, U: ?Sized> core::ops::DispatchFromDyn<Arc<U>> for Arc<T> {}
 
+// This is to allow coercion from `UniqueArc<T>` to `UniqueArc<U>`, and therefore also from
+// `Pin<UniqueArc<T>>` to `Pin<UniqueArc<U>>`, if `T` can be converted to the DST `U`.
+impl<T: ?Sized + Unsize<U>, U: ?Sized> core::ops::CoerceUnsized<UniqueArc<U>> for UniqueArc<T> {}
+
 // SAFETY: It is safe to send `Arc<T>` to another thread when the underlying `T` is `Sync` because
 // it effectively means sharing `&T` (which is safe because `T` is `Sync`); additionally, it needs
 // `T` to be `Send` because any thread that has an `Arc<T>` may ultimately access `T` using a
@@ -274,6 +310,34 @@ pub fn ptr_eq(this: &Self, other: &Self) -> bool {
     }
 }
 
+impl Arc<dyn Any + Send + Sync> {
+    /// Attempts to downcast the type-erased [`Arc`] to a concrete type.
+    ///
+    /// Returns the original [`Arc`] if the object is not a `T`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use core::any::Any;
+    /// use kernel::sync::Arc;
+    ///
+    /// let obj: Arc<dyn Any + Send + Sync> = Arc::new(42u32, GFP_KERNEL)?;
+    /// let obj = obj.downcast::<u64>().unwrap_err();
+    /// assert_eq!(*obj.downcast::<u32>().unwrap(), 42);
+    /// # Ok::<(), Error>(())
+    /// ```
+    pub fn downcast<T: Any + Send + Sync>(self) -> core::result::Result<Arc<T>, Self> {
+        if !(*self).is::<T>() {
+            return Err(self);
+        }
+        let ptr = ManuallyDrop::new(self).ptr.cast::<ArcInner<T>>();
+        // SAFETY: The object is a `T`, so the pointer without its metadata points to a valid
+        // `ArcInner<T>`. The reference count owned by `self`, which is not dropped, is transferred
+        // to the new `Arc`.
+        Ok(unsafe { Arc::from_inner(ptr) })
+    }
+}
+
 impl<T: 'static> ForeignOwnable for Arc<T> {
     type Borrowed<'a> = ArcBorrow<'a, T>;
 
@@ -0,0 +1,184 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Atomic accesses to memory shared with devices or firmware.
+//!
+//! Rust atomics follow the Rust memory model, which only covers other CPUs, and the compiler may
+//! merge or reorder them in ways that a device would observe. [`AtomicCell`] instead provides the
+//! accesses of the kernel memory model: `READ_ONCE`/`WRITE_ONCE` style volatile loads and stores,
+//! ordered against the device with the `dma_rmb`/`dma_wmb` barriers, and the fully ordered
+//! `cmpxchg` and `xchg` operations. It is meant for words in coherent DMA memory or pages shared
+//! with firmware, e.g., doorbells and sequence counters.
+//!
+//! C header: [`include/linux/atomic.h`](srctree/include/linux/atomic.h)
+
+use crate::bindings;
+use core::cell::UnsafeCell;
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// A type that can be accessed atomically through an [`AtomicCell`].
+///
+/// This is a sealed trait implemented by the 32-bit and 64-bit integers.
+pub trait Primitive: Copy + private::Sealed {
+    /// # Safety
+    ///
+    /// `ptr` must be valid for reads and writes, and aligned.
+    #[doc(hidden)]
+    unsafe fn cmpxchg(ptr: *mut Self, old: Self, new: Self) -> Self;
+
+    /// # Safety
+    ///
+    /// `ptr` must be valid for reads and writes, and aligned.
+    #[doc(hidden)]
+    unsafe fn xchg(ptr: *mut Self, new: Self) -> Self;
+}
+
+macro_rules! impl_primitive {
+    ($($ty:ty, $raw:ty => $cmpxchg:ident, $xchg:ident;)*) => {
+        $(
+            impl private::Sealed for $ty {}
+
+            impl Primitive for $ty {
+                unsafe fn cmpxchg(ptr: *mut Self, old: Self, new: Self) -> Self {
+                    // SAFETY: By the safety requirements, `ptr` is valid and aligned, and `$ty`
+                    // has the same size as `$raw`.
+                    unsafe { bindings::$cmpxchg(ptr.cast::<$raw>(), old as $raw, new as $raw) as _ }
+                }
+
+                unsafe fn xchg(ptr: *mut Self, new: Self) -> Self {
+                    // SAFETY: Same as above.
+                    unsafe { bindings::$xchg(ptr.cast::<$raw>(), new as $raw) as _ }
+                }
+            }
+        )*
+    };
+}
+
+impl_primitive! {
+    u32, u32 => cmpxchg_u32, xchg_u32;
+    i32, u32 => cmpxchg_u32, xchg_u32;
+    u64, u64 => cmpxchg_u64, xchg_u64;
+    i64, u64 => cmpxchg_u64, xchg_u64;
+}
+
+/// A word of memory shared with a device or firmware, accessed atomically.
+///
+/// Cells are either embedded in a `#[repr(C)]` structure that lives in the shared memory, or
+/// created over a word of it with [`AtomicCell::from_ptr`]. All accesses are volatile, so none is
+/// elided or merged, even if the CPU does not look at the result.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::sync::atomic::AtomicCell;
+///
+/// /// The header of a page shared with firmware.
+/// #[repr(C)]
+/// struct Mailbox {
+///     seq: AtomicCell<u32>,
+///     doorbell: AtomicCell<u32>,
+///     data: [u8; 56],
+/// }
+///
+/// /// Rings the doorbell, unless the firmware has not consumed the previous message yet.
+/// fn ring(mbox: &Mailbox) -> bool {
+///     let seq = mbox.seq.load();
+///     // Publishes the message: the firmware must see it before the doorbell.
+///     mbox.doorbell.cmpxchg(0, seq.wrapping_add(1)).is_ok()
+/// }
+///
+/// let mbox = Mailbox {
+///     seq: AtomicCell::new(7),
+///     doorbell: AtomicCell::new(0),
+///     data: [0; 56],
+/// };
+/// assert!(ring(&mbox));
+/// assert_eq!(mbox.doorbell.load_acquire(), 8);
+/// assert!(!ring(&mbox));
+/// assert_eq!(mbox.doorbell.xchg(0), 8);
+/// ```
+#[repr(transparent)]
+pub struct AtomicCell<T: Primitive>(UnsafeCell<T>);
+
+// SAFETY: All accesses through shared references are atomic.
+unsafe impl<T: Primitive> Sync for AtomicCell<T> {}
+
+impl<T: Primitive> AtomicCell<T> {
+    /// Creates a cell holding `v`.
+    pub const fn new(v: T) -> Self {
+        Self(UnsafeCell::new(v))
+    }
+
+    /// Creates a reference to a cell over the word at `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be aligned and valid for reads and writes for the lifetime `'a`, and must not be
+    /// accessed other than atomically, e.g., through other cells or by the device, during it.
+    pub unsafe fn from_ptr<'a>(ptr: *mut T) -> &'a Self {
+        // SAFETY: `AtomicCell<T>` is a transparent wrapper of `T`, and the pointer is valid for
+        // `'a` by the safety requirements.
+        unsafe { &*ptr.cast::<Self>() }
+    }
+
+    /// Returns a raw pointer to the word, e.g., to compute its DMA address.
+    pub fn as_ptr(&self) -> *mut T {
+        self.0.get()
+    }
+
+    /// Loads the value, with no ordering, like `READ_ONCE`.
+    pub fn load(&self) -> T {
+        // SAFETY: The word is valid and aligned, and only accessed atomically.
+        unsafe { self.0.get().read_volatile() }
+    }
+
+    /// Stores `v`, with no ordering, like `WRITE_ONCE`.
+    pub fn store(&self, v: T) {
+        // SAFETY: The word is valid and aligned, and only accessed atomically.
+        unsafe { self.0.get().write_volatile(v) }
+    }
+
+    /// Loads the value, and orders later reads of shared memory after it.
+    ///
+    /// This is used to read a flag or an index written by the device, the data it guards is then
+    /// read after it.
+    pub fn load_acquire(&self) -> T {
+        let v = self.load();
+        // SAFETY: `dma_rmb` can be called from any context.
+        unsafe { bindings::dma_rmb() };
+        v
+    }
+
+    /// Stores `v`, and orders earlier writes to shared memory before it.
+    ///
+    /// This is used to publish a flag or an index to the device, after the data it guards.
+    pub fn store_release(&self, v: T) {
+        // SAFETY: `dma_wmb` can be called from any context.
+        unsafe { bindings::dma_wmb() };
+        self.store(v);
+    }
+
+    /// Stores `new` if the value is `current`, with full ordering, like `cmpxchg`.
+    ///
+    /// Returns the previous value, as [`Ok`] if it was `current` and [`Err`] otherwise.
+    pub fn cmpxchg(&self, current: T, new: T) -> Result<T, T>
+    where
+        T: PartialEq,
+    {
+        // SAFETY: The word is valid and aligned, and only accessed atomically.
+        let old = unsafe { T::cmpxchg(self.0.get(), current, new) };
+        if old == current {
+            Ok(old)
+        } else {
+            Err(old)
+        }
+    }
+
+    /// Stores `new` and returns the previous value, with full ordering, like `xchg`.
+    pub fn xchg(&self, new: T) -> T {
+        // SAFETY: The word is valid and aligned, and only accessed atomically.
+        unsafe { T::xchg(self.0.get(), new) }
+    }
+}
@@ -0,0 +1,165 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! A completion.
+//!
+//! This module allows Rust code to use the kernel's `struct completion`, which threads wait on
+//! until another thread, or an interrupt handler, signals that an event happened.
+//!
+//! C header: [`include/linux/completion.h`](srctree/include/linux/completion.h)
+
+use crate::{
+    bindings,
+    error::{code::*, Result},
+    init::PinInit,
+    pin_init,
+    time::Jiffies,
+    types::Opaque,
+};
+use core::marker::PhantomPinned;
+use macros::pin_data;
+
+/// A completion.
+///
+/// Unlike a [`CondVar`](super::CondVar), a completion is "sticky": [`Completion::complete`] is
+/// remembered until a waiter consumes it, so it does not matter whether the waiter starts
+/// waiting before or after the event. This makes it the usual way to wait for the end of a
+/// transfer that an interrupt handler reports.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::sync::Completion;
+///
+/// #[pin_data]
+/// struct Transfer {
+///     #[pin]
+///     done: Completion,
+/// }
+///
+/// /// Called by the interrupt handler.
+/// fn transfer_done(t: &Transfer) {
+///     t.done.complete();
+/// }
+///
+/// /// Called by the ioctl that started the transfer.
+/// fn wait_transfer(t: &Transfer) -> Result {
+///     t.done.wait_interruptible()
+/// }
+///
+/// let t = Box::pin_init(pin_init!(Transfer { done <- Completion::new() }), GFP_KERNEL)?;
+/// transfer_done(&t);
+/// wait_transfer(&t)?;
+/// # Ok::<(), Error>(())
+/// ```
+#[pin_data]
+pub struct Completion {
+    #[pin]
+    inner: Opaque<bindings::completion>,
+
+    /// A completion needs to be pinned because it contains a wait queue, which is
+    /// self-referential.
+    #[pin]
+    _pin: PhantomPinned,
+}
+
+// SAFETY: `Completion` only uses a `struct completion`, which is safe to use on any thread.
+unsafe impl Send for Completion {}
+
+// SAFETY: `Completion` only uses a `struct completion`, which is safe to use on multiple threads
+// concurrently.
+unsafe impl Sync for Completion {}
+
+impl Completion {
+    /// Constructs a new completion initialiser, not completed yet.
+    pub fn new() -> impl PinInit<Self> {
+        pin_init!(Self {
+            // SAFETY: `slot` is valid for writes while the closure is called.
+            inner <- Opaque::ffi_init(|slot| unsafe { bindings::init_completion(slot) }),
+            _pin: PhantomPinned,
+        })
+    }
+
+    fn as_raw(&self) -> *mut bindings::completion {
+        self.inner.get()
+    }
+
+    /// Signals the completion, waking up one waiter, or the next one to wait.
+    ///
+    /// This can be called from any context, including interrupt handlers.
+    pub fn complete(&self) {
+        // SAFETY: The completion is initialised.
+        unsafe { bindings::complete(self.as_raw()) };
+    }
+
+    /// Signals the completion for good, waking up all the waiters, current and future.
+    pub fn complete_all(&self) {
+        // SAFETY: The completion is initialised.
+        unsafe { bindings::complete_all(self.as_raw()) };
+    }
+
+    /// Forgets earlier signals, e.g., before starting the next transfer.
+    ///
+    /// This must not be called while another thread may signal the completion for the previous
+    /// event, which would then be lost or counted for the next one.
+    pub fn reinit(&self) {
+        // SAFETY: The completion is initialised.
+        unsafe { bindings::reinit_completion(self.as_raw()) };
+    }
+
+    /// Waits for the completion in uninterruptible mode.
+    pub fn wait(&self) {
+        // SAFETY: The completion is initialised.
+        unsafe { bindings::wait_for_completion(self.as_raw()) };
+    }
+
+    /// Waits for the completion in interruptible mode.
+    ///
+    /// Fails with [`ERESTARTSYS`] if a signal arrives first, in which case the completion is not
+    /// consumed.
+    pub fn wait_interruptible(&self) -> Result {
+        // SAFETY: The completion is initialised.
+        match unsafe { bindings::wait_for_completion_interruptible(self.as_raw()) } {
+            0 => Ok(()),
+            _ => Err(ERESTARTSYS),
+        }
+    }
+
+    /// Waits for the completion in killable mode.
+    ///
+    /// This is like [`Completion::wait_interruptible`], except that only fatal signals interrupt
+    /// the wait.
+    pub fn wait_killable(&self) -> Result {
+        // SAFETY: The completion is initialised.
+        match unsafe { bindings::wait_for_completion_killable(self.as_raw()) } {
+            0 => Ok(()),
+            _ => Err(ERESTARTSYS),
+        }
+    }
+
+    /// Waits for the completion in uninterruptible mode, for at most `timeout`.
+    ///
+    /// Returns the jiffies left before the timeout, which are at least one. Fails with
+    /// [`ETIMEDOUT`] on timeout.
+    pub fn wait_timeout(&self, timeout: Jiffies) -> Result<Jiffies> {
+        // SAFETY: The completion is initialised.
+        match unsafe { bindings::wait_for_completion_timeout(self.as_raw(), timeout) } {
+            0 => Err(ETIMEDOUT),
+            n => Ok(n as Jiffies),
+        }
+    }
+
+    /// Waits for the completion in interruptible mode, for at most `timeout`.
+    ///
+    /// Returns the jiffies left before the timeout, which are at least one. Fails with
+    /// [`ETIMEDOUT`] on timeout, and with [`ERESTARTSYS`] if a signal arrives first.
+    pub fn wait_interruptible_timeout(&self, timeout: Jiffies) -> Result<Jiffies> {
+        // SAFETY: The completion is initialised.
+        let ret =
+            unsafe { bindings::wait_for_completion_interruptible_timeout(self.as_raw(), timeout) };
+        match ret {
+            0 => Err(ETIMEDOUT),
+            n if n < 0 => Err(ERESTARTSYS),
+            n => Ok(n as Jiffies),
+        }
+    }
+}
@@ -7,11 +7,13 @@
 
 use super::{lock::Backend, lock::Guard, LockClassKey};
 use crate::{
-    bindings,
+    bindings, ffi_init,
     init::PinInit,
     pin_init,
     str::CStr,
-    task::{MAX_SCHEDULE_TIMEOUT, TASK_INTERRUPTIBLE, TASK_NORMAL, TASK_UNINTERRUPTIBLE},
+    task::{
+        MAX_SCHEDULE_TIMEOUT, TASK_INTERRUPTIBLE, TASK_KILLABLE, TASK_NORMAL, TASK_UNINTERRUPTIBLE,
+    },
     time::Jiffies,
     types::Opaque,
 };
@@ -108,9 +110,11 @@ pub fn new(name: &'static CStr, key: &'static LockClassKey) -> impl PinInit<Self
             _pin: PhantomPinned,
             // SAFETY: `slot` is valid while the closure is called and both `name` and `key` have
             // static lifetimes so they live indefinitely.
-            wait_queue_head <- Opaque::ffi_init(|slot| unsafe {
-                bindings::__init_waitqueue_head(slot, name.as_char_ptr(), key.as_ptr())
-            }),
+            wait_queue_head <- ffi_init!(unsafe bindings::__init_waitqueue_head(
+                slot,
+                name.as_char_ptr(),
+                key.as_ptr(),
+            )),
         })
     }
 
@@ -161,6 +165,18 @@ pub fn wait_interruptible<T: ?Sized, B: Backend>(&self, guard: &mut Guard<'_, T,
         crate::current!().signal_pending()
     }
 
+    /// Releases the lock and waits for a notification in killable mode.
+    ///
+    /// Similar to [`CondVar::wait_interruptible`], except that only fatal signals wake the thread
+    /// up. It may also wake up spuriously.
+    ///
+    /// Returns whether there is a fatal signal pending.
+    #[must_use = "wait_killable returns if a fatal signal is pending, so the caller must check the return value"]
+    pub fn wait_killable<T: ?Sized, B: Backend>(&self, guard: &mut Guard<'_, T, B>) -> bool {
+        self.wait_internal(TASK_KILLABLE, guard, MAX_SCHEDULE_TIMEOUT);
+        crate::current!().fatal_signal_pending()
+    }
+
     /// Releases the lock and waits for a notification in interruptible mode.
     ///
     /// Atomically releases the given lock (whose ownership is proven by the guard) and puts the
@@ -6,11 +6,22 @@
 //! spinlocks, raw spinlocks) to be provided with minimal effort.
 
 use super::LockClassKey;
-use crate::{bindings, init::PinInit, pin_init, str::CStr, types::Opaque, types::ScopeGuard};
+use crate::{
+    bindings,
+    error::{code::*, to_result, Result},
+    ffi_init,
+    init::PinInit,
+    pin_init,
+    str::CStr,
+    types::Opaque,
+    types::ScopeGuard,
+};
 use core::{cell::UnsafeCell, marker::PhantomData, marker::PhantomPinned};
 use macros::pin_data;
 
 pub mod mutex;
+pub mod raw_spinlock;
+pub mod rwsem;
 pub mod spinlock;
 
 /// The "backend" of a lock.
@@ -77,10 +88,96 @@ unsafe fn relock(ptr: *mut Self::State, guard_state: &mut Self::GuardState) {
     }
 }
 
+/// A [`Backend`] whose lock can be acquired with a wait that signals can interrupt.
+///
+/// # Safety
+///
+/// Implementers must ensure that [`lock_interruptible`] and [`lock_killable`] only succeed once
+/// the caller owns the lock, as [`Backend::lock`] does, and that the guard state they return can
+/// be passed to [`Backend::unlock`].
+///
+/// [`lock_interruptible`]: InterruptibleBackend::lock_interruptible
+/// [`lock_killable`]: InterruptibleBackend::lock_killable
+pub unsafe trait InterruptibleBackend: Backend {
+    /// Acquires the lock, unless a signal arrives while waiting for it.
+    ///
+    /// # Safety
+    ///
+    /// Callers must ensure that [`Backend::init`] has been previously called.
+    unsafe fn lock_interruptible(ptr: *mut Self::State) -> Result<Self::GuardState>;
+
+    /// Acquires the lock, unless a fatal signal arrives while waiting for it.
+    ///
+    /// # Safety
+    ///
+    /// Callers must ensure that [`Backend::init`] has been previously called.
+    unsafe fn lock_killable(ptr: *mut Self::State) -> Result<Self::GuardState>;
+}
+
+/// A [`Backend`] whose lock can be acquired with a lockdep subclass.
+///
+/// # Safety
+///
+/// Implementers must ensure that [`lock_nested`] acquires the lock as [`Backend::lock`] does, and
+/// that the guard state it returns can be passed to [`Backend::unlock`].
+///
+/// [`lock_nested`]: NestedBackend::lock_nested
+pub unsafe trait NestedBackend: Backend {
+    /// Acquires the lock with the given lockdep subclass, making the caller its owner.
+    ///
+    /// # Safety
+    ///
+    /// Callers must ensure that [`Backend::init`] has been previously called.
+    #[must_use]
+    unsafe fn lock_nested(ptr: *mut Self::State, subclass: u32) -> Self::GuardState;
+}
+
+// SAFETY: `mutex_lock_nested` acquires the mutex like `mutex_lock`, and the guard state of mutexes
+// is empty.
+unsafe impl NestedBackend for mutex::MutexBackend {
+    unsafe fn lock_nested(ptr: *mut Self::State, subclass: u32) {
+        // SAFETY: The safety requirements of this function ensure that `ptr` points to valid
+        // memory, and that it has been initialised before.
+        unsafe { bindings::mutex_lock_nested(ptr, subclass as _) };
+    }
+}
+
+// SAFETY: `spin_lock_nested` acquires the spinlock like `spin_lock`, and the guard state of
+// spinlocks is empty.
+unsafe impl NestedBackend for spinlock::SpinLockBackend {
+    unsafe fn lock_nested(ptr: *mut Self::State, subclass: u32) {
+        // SAFETY: The safety requirements of this function ensure that `ptr` points to valid
+        // memory, and that it has been initialised before.
+        unsafe { bindings::spin_lock_nested(ptr, subclass as _) };
+    }
+}
+
+// SAFETY: `mutex_lock_interruptible` and `mutex_lock_killable` only return zero once the mutex is
+// owned, and the guard state of mutexes is empty.
+unsafe impl InterruptibleBackend for mutex::MutexBackend {
+    unsafe fn lock_interruptible(ptr: *mut Self::State) -> Result {
+        // SAFETY: The safety requirements of this function ensure that `ptr` points to valid
+        // memory, and that it has been initialised before.
+        to_result(unsafe { bindings::mutex_lock_interruptible(ptr) }).map_err(|_| ERESTARTSYS)
+    }
+
+    unsafe fn lock_killable(ptr: *mut Self::State) -> Result {
+        // SAFETY: The safety requirements of this function ensure that `ptr` points to valid
+        // memory, and that it has been initialised before.
+        to_result(unsafe { bindings::mutex_lock_killable(ptr) }).map_err(|_| ERESTARTSYS)
+    }
+}
+
 /// A mutual exclusion primitive.
 ///
 /// Exposes one of the kernel locking primitives. Which one is exposed depends on the lock
 /// [`Backend`] specified as the generic parameter `B`.
+///
+/// The backends are the C locks, so contention is accounted as for them: with `CONFIG_LOCK_STAT`,
+/// in `/proc/lock_stat` and in the `lock:lock_contended` events shown by `perf lock report`, under
+/// the name given to [`Lock::new`], and with the `lock:contention_begin` events used by
+/// `perf lock contention`. Locks created without an explicit name, e.g., with `new_mutex!(x)`, are
+/// named after the file and line where they are created.
 #[pin_data]
 pub struct Lock<T: ?Sized, B: Backend> {
     /// The kernel lock object.
@@ -112,9 +209,7 @@ pub fn new(t: T, name: &'static CStr, key: &'static LockClassKey) -> impl PinIni
             _pin: PhantomPinned,
             // SAFETY: `slot` is valid while the closure is called and both `name` and `key` have
             // static lifetimes so they live indefinitely.
-            state <- Opaque::ffi_init(|slot| unsafe {
-                B::init(slot, name.as_char_ptr(), key.as_ptr())
-            }),
+            state <- ffi_init!(unsafe B::init(slot, name.as_char_ptr(), key.as_ptr())),
         })
     }
 }
@@ -128,8 +223,90 @@ pub fn lock(&self) -> Guard<'_, T, B> {
         // SAFETY: The lock was just acquired.
         unsafe { Guard::new(self, state) }
     }
+
+    /// Acquires the lock with the given lockdep subclass.
+    ///
+    /// All locks initialised at the same place share a lock class, so lockdep reports a possible
+    /// deadlock when two of them are held at the same time, e.g., the locks of a parent and a
+    /// child device. Taking the inner lock with a different `subclass` tells lockdep that they
+    /// are always acquired in a fixed order. `subclass` must be below
+    /// [`MAX_LOCKDEP_SUBCLASSES`], with zero being the subclass used by [`Lock::lock`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kernel::sync::{lock::SINGLE_DEPTH_NESTING, Mutex};
+    ///
+    /// struct Node {
+    ///     count: u32,
+    /// }
+    ///
+    /// fn transfer(parent: &Mutex<Node>, child: &Mutex<Node>) {
+    ///     let mut p = parent.lock();
+    ///     let mut c = child.lock_nested(SINGLE_DEPTH_NESTING);
+    ///     c.count += p.count;
+    ///     p.count = 0;
+    /// }
+    /// ```
+    pub fn lock_nested(&self, subclass: u32) -> Guard<'_, T, B>
+    where
+        B: NestedBackend,
+    {
+        // SAFETY: The constructor of the type calls `init`, so the existence of the object proves
+        // that `init` was called.
+        let state = unsafe { B::lock_nested(self.state.get(), subclass) };
+        // SAFETY: The lock was just acquired.
+        unsafe { Guard::new(self, state) }
+    }
 }
 
+impl<T: ?Sized, B: InterruptibleBackend> Lock<T, B> {
+    /// Acquires the lock, unless the current task gets a signal while waiting for it.
+    ///
+    /// Fails with [`ERESTARTSYS`] if a signal is pending. System calls return it as is, and the
+    /// call is then restarted or fails with `EINTR` once the signal is handled. This is meant
+    /// for locks that may be held for a long time, e.g., across I/O, so that waiting for them
+    /// does not make a process unkillable.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kernel::sync::Mutex;
+    ///
+    /// fn set(config: &Mutex<u32>, v: u32) -> Result {
+    ///     *config.lock_interruptible()? = v;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn lock_interruptible(&self) -> Result<Guard<'_, T, B>> {
+        // SAFETY: The constructor of the type calls `init`, so the existence of the object proves
+        // that `init` was called.
+        let state = unsafe { B::lock_interruptible(self.state.get()) }?;
+        // SAFETY: The lock was just acquired.
+        Ok(unsafe { Guard::new(self, state) })
+    }
+
+    /// Acquires the lock, unless the current task gets a fatal signal while waiting for it.
+    ///
+    /// This is like [`Lock::lock_interruptible`], but other signals do not interrupt the wait,
+    /// for callers that cannot easily be restarted. It fails with [`ERESTARTSYS`], which the
+    /// process never sees since it is being killed.
+    pub fn lock_killable(&self) -> Result<Guard<'_, T, B>> {
+        // SAFETY: The constructor of the type calls `init`, so the existence of the object proves
+        // that `init` was called.
+        let state = unsafe { B::lock_killable(self.state.get()) }?;
+        // SAFETY: The lock was just acquired.
+        Ok(unsafe { Guard::new(self, state) })
+    }
+}
+
+/// The number of lockdep subclasses a lock class can have.
+pub const MAX_LOCKDEP_SUBCLASSES: u32 = bindings::MAX_LOCKDEP_SUBCLASSES as u32;
+
+/// The subclass to use with [`Lock::lock_nested`] for the second lock of the same class taken in
+/// a fixed order.
+pub const SINGLE_DEPTH_NESTING: u32 = bindings::SINGLE_DEPTH_NESTING as u32;
+
 /// A lock guard.
 ///
 /// Allows mutual exclusion primitives that implement the [`Backend`] trait to automatically unlock
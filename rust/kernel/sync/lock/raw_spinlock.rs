@@ -0,0 +1,105 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! A kernel raw spinlock.
+//!
+//! This module allows Rust code to use the kernel's `raw_spinlock_t`.
+//!
+//! On `PREEMPT_RT`, [`SpinLock`] is a sleeping lock based on an `rt_mutex`, so that its critical
+//! sections are preemptible, and it must not be taken in contexts that cannot sleep, e.g.,
+//! in a hard interrupt handler that is not threaded, in an interrupt chip callback, or with
+//! preemption or interrupts disabled. [`RawSpinLock`] spins on all configurations, and is for
+//! these contexts only: its critical sections add to the worst case latency of the whole system,
+//! so they must be short and bounded, and must not allocate memory or take sleeping locks,
+//! including [`SpinLock`]. Other code should use [`SpinLock`], which works on both
+//! configurations.
+//!
+//! C header: [`include/linux/spinlock.h`](srctree/include/linux/spinlock.h)
+//!
+//! [`SpinLock`]: super::spinlock::SpinLock
+
+use crate::bindings;
+
+/// Creates a [`RawSpinLock`] initialiser with the given name and a newly-created lock class.
+///
+/// It uses the name if one is given, otherwise it generates one based on the file name and line
+/// number.
+#[macro_export]
+macro_rules! new_raw_spinlock {
+    ($inner:expr $(, $name:literal)? $(,)?) => {
+        $crate::sync::RawSpinLock::new(
+            $inner, $crate::optional_name!($($name)?), $crate::static_lock_class!())
+    };
+}
+pub use new_raw_spinlock;
+
+/// A raw spinlock.
+///
+/// Exposes the kernel's `raw_spinlock_t`, which spins even on `PREEMPT_RT`. See the
+/// [module documentation](self) for when to use it rather than a [`SpinLock`].
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{new_raw_spinlock, sync::RawSpinLock};
+///
+/// struct Mask {
+///     enabled: u32,
+/// }
+///
+/// /// Called from the `irq_mask` callback of an interrupt chip, with interrupts disabled.
+/// fn mask(m: &RawSpinLock<Mask>, hwirq: u32) -> u32 {
+///     let mut g = m.lock();
+///     g.enabled &= !(1 << hwirq);
+///     // Write `g.enabled` to the enable register.
+///     g.enabled
+/// }
+///
+/// let m = Box::pin_init(new_raw_spinlock!(Mask { enabled: 0xff }), GFP_KERNEL)?;
+/// assert_eq!(mask(&m, 0), 0xfe);
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// [`SpinLock`]: super::spinlock::SpinLock
+pub type RawSpinLock<T> = super::Lock<T, RawSpinLockBackend>;
+
+/// A kernel `raw_spinlock_t` lock backend.
+pub struct RawSpinLockBackend;
+
+// SAFETY: The underlying kernel `raw_spinlock_t` object ensures mutual exclusion. `relock` uses the
+// default implementation that always calls the same locking method.
+unsafe impl super::Backend for RawSpinLockBackend {
+    type State = bindings::raw_spinlock_t;
+    type GuardState = ();
+
+    unsafe fn init(
+        ptr: *mut Self::State,
+        name: *const core::ffi::c_char,
+        key: *mut bindings::lock_class_key,
+    ) {
+        // SAFETY: The safety requirements ensure that `ptr` is valid for writes, and `name` and
+        // `key` are valid for read indefinitely.
+        unsafe { bindings::__raw_spin_lock_init(ptr, name, key) }
+    }
+
+    unsafe fn lock(ptr: *mut Self::State) -> Self::GuardState {
+        // SAFETY: The safety requirements of this function ensure that `ptr` points to valid
+        // memory, and that it has been initialised before.
+        unsafe { bindings::raw_spin_lock(ptr) }
+    }
+
+    unsafe fn unlock(ptr: *mut Self::State, _guard_state: &Self::GuardState) {
+        // SAFETY: The safety requirements of this function ensure that `ptr` is valid and that the
+        // caller is the owner of the spinlock.
+        unsafe { bindings::raw_spin_unlock(ptr) }
+    }
+}
+
+// SAFETY: `raw_spin_lock_nested` acquires the spinlock like `raw_spin_lock`, and the guard state is
+// empty.
+unsafe impl super::NestedBackend for RawSpinLockBackend {
+    unsafe fn lock_nested(ptr: *mut Self::State, subclass: u32) -> Self::GuardState {
+        // SAFETY: The safety requirements of this function ensure that `ptr` points to valid
+        // memory, and that it has been initialised before.
+        unsafe { bindings::raw_spin_lock_nested(ptr, subclass as _) }
+    }
+}
@@ -0,0 +1,147 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! A kernel read-write semaphore.
+//!
+//! This module allows Rust code to use the kernel's `struct rw_semaphore`.
+//!
+//! C header: [`include/linux/rwsem.h`](srctree/include/linux/rwsem.h)
+
+use super::Lock;
+use crate::bindings;
+use core::{marker::PhantomData, ops::Deref};
+
+/// Creates a [`RwSemaphore`] initialiser with the given name and a newly-created lock class.
+///
+/// It uses the name if one is given, otherwise it generates one based on the file name and line
+/// number.
+#[macro_export]
+macro_rules! new_rwsem {
+    ($inner:expr $(, $name:literal)? $(,)?) => {
+        $crate::sync::RwSemaphore::new(
+            $inner, $crate::optional_name!($($name)?), $crate::static_lock_class!())
+    };
+}
+pub use new_rwsem;
+
+/// A read-write semaphore.
+///
+/// Exposes the kernel's [`struct rw_semaphore`]. [`Lock::lock`] acquires it for writing, which
+/// gives exclusive access to the protected data, while [`Lock::read`] acquires it for reading,
+/// which many threads can do at the same time. Both may sleep.
+///
+/// Once a writer waits, new readers wait behind it, so writers are not starved by a steady stream
+/// of readers that each hold the semaphore briefly.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{new_rwsem, sync::RwSemaphore};
+///
+/// fn bump(config: &RwSemaphore<u32>) -> u32 {
+///     *config.lock() += 1;
+///     *config.read()
+/// }
+///
+/// let config = Box::pin_init(new_rwsem!(0), GFP_KERNEL)?;
+/// assert_eq!(bump(&config), 1);
+/// # Ok::<(), Error>(())
+/// ```
+///
+/// [`struct rw_semaphore`]: srctree/include/linux/rwsem.h
+pub type RwSemaphore<T> = Lock<T, RwSemBackend>;
+
+/// A kernel `struct rw_semaphore` lock backend.
+pub struct RwSemBackend;
+
+// SAFETY: The underlying kernel `struct rw_semaphore` object ensures mutual exclusion between
+// writers, and `relock` uses the default implementation that always calls the same locking
+// method.
+unsafe impl super::Backend for RwSemBackend {
+    type State = bindings::rw_semaphore;
+    type GuardState = ();
+
+    unsafe fn init(
+        ptr: *mut Self::State,
+        name: *const core::ffi::c_char,
+        key: *mut bindings::lock_class_key,
+    ) {
+        // SAFETY: The safety requirements ensure that `ptr` is valid for writes, and `name` and
+        // `key` are valid for read indefinitely.
+        unsafe { bindings::__init_rwsem(ptr, name, key) }
+    }
+
+    unsafe fn lock(ptr: *mut Self::State) -> Self::GuardState {
+        // SAFETY: The safety requirements of this function ensure that `ptr` points to valid
+        // memory, and that it has been initialised before.
+        unsafe { bindings::down_write(ptr) };
+    }
+
+    unsafe fn unlock(ptr: *mut Self::State, _guard_state: &Self::GuardState) {
+        // SAFETY: The safety requirements of this function ensure that `ptr` is valid and that the
+        // caller is the owner of the semaphore.
+        unsafe { bindings::up_write(ptr) };
+    }
+}
+
+// SAFETY: `down_write_nested` acquires the semaphore for writing like `down_write`, and the guard
+// state is empty.
+unsafe impl super::NestedBackend for RwSemBackend {
+    unsafe fn lock_nested(ptr: *mut Self::State, subclass: u32) -> Self::GuardState {
+        // SAFETY: The safety requirements of this function ensure that `ptr` points to valid
+        // memory, and that it has been initialised before.
+        unsafe { bindings::down_write_nested(ptr, subclass as _) };
+    }
+}
+
+impl<T: ?Sized> Lock<T, RwSemBackend> {
+    /// Acquires the semaphore for reading and gives the caller shared access to the data protected
+    /// by it.
+    ///
+    /// Other readers may access the data at the same time, hence `T` must be [`Sync`].
+    pub fn read(&self) -> ReadGuard<'_, T>
+    where
+        T: Sync,
+    {
+        // SAFETY: The constructor of the type calls `init`, so the existence of the object proves
+        // that `init` was called.
+        unsafe { bindings::down_read(self.state.get()) };
+        // INVARIANT: The semaphore was just acquired for reading.
+        ReadGuard {
+            lock: self,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+/// A guard of a [`RwSemaphore`] acquired for reading.
+///
+/// The semaphore is released when the guard is dropped.
+///
+/// # Invariants
+///
+/// The current thread holds `lock` for reading.
+#[must_use = "the lock unlocks immediately when the guard is unused"]
+pub struct ReadGuard<'a, T: ?Sized> {
+    lock: &'a Lock<T, RwSemBackend>,
+    _not_send: PhantomData<*mut ()>,
+}
+
+// SAFETY: Only shared access to the data is given out, and `read` requires `T: Sync`.
+unsafe impl<T: ?Sized + Sync> Sync for ReadGuard<'_, T> {}
+
+impl<T: ?Sized> Deref for ReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: By the type invariants, the semaphore is held for reading, so there is no
+        // writer that could access the data mutably.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, the semaphore is held for reading.
+        unsafe { bindings::up_read(self.lock.state.get()) };
+    }
+}
@@ -0,0 +1,272 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! One-time initialisation.
+//!
+//! [`OnceLock`] holds a value that is initialised at most once, on first use, and [`LazyLock`]
+//! pairs it with its initialiser. Both can be used in statics, e.g., for a workqueue or a lookup
+//! table shared by all the devices of a driver, without hand-written atomics.
+//!
+//! Initialisers get the allocation flags to use: [`GFP_KERNEL`] when waiting for the value may
+//! sleep, and [`GFP_ATOMIC`] for the `_atomic` variants, which can be used in atomic context.
+//! Those never wait for another initialiser, which may run on the same CPU, e.g., in the task an
+//! interrupt handler interrupted, and fail with [`EBUSY`] instead.
+//!
+//! C header: [`include/linux/wait_bit.h`](srctree/include/linux/wait_bit.h)
+
+use crate::{
+    alloc::{flags::*, Flags},
+    bindings,
+    error::{code::*, Result},
+    task::TASK_UNINTERRUPTIBLE,
+    types::Opaque,
+};
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{fence, AtomicU32, Ordering},
+};
+
+const UNINIT: u32 = 0;
+const RUNNING: u32 = 1;
+const DONE: u32 = 2;
+
+/// A value that is initialised at most once.
+///
+/// The first caller of [`OnceLock::get_or_try_init`] runs the initialiser, while concurrent
+/// callers wait for it to finish. If the initialiser fails, the value stays uninitialised and the
+/// next caller runs its own initialiser.
+///
+/// # Invariants
+///
+/// `value` is initialised if and only if `state` is `DONE`, after which it is never written.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{prelude::*, sync::OnceLock};
+///
+/// static TABLE: OnceLock<Box<[u16; 256]>> = OnceLock::new();
+///
+/// fn crc_table() -> Result<&'static [u16; 256]> {
+///     let table = TABLE.get_or_try_init(|flags| {
+///         let mut t = Box::new([0u16; 256], flags)?;
+///         for (i, e) in t.iter_mut().enumerate() {
+///             *e = (i as u16).rotate_left(3);
+///         }
+///         Ok(t)
+///     })?;
+///     Ok(table)
+/// }
+///
+/// assert_eq!(crc_table()?[1], 8);
+/// assert!(TABLE.get().is_some());
+/// # Ok::<(), Error>(())
+/// ```
+pub struct OnceLock<T> {
+    state: AtomicU32,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+// SAFETY: The value is only given out as a shared reference, and it can be initialised on one
+// thread and dropped on another, so `T` must be both `Sync` and `Send`.
+unsafe impl<T: Send + Sync> Sync for OnceLock<T> {}
+
+// SAFETY: `OnceLock<T>` owns the value, so it is `Send` if it is.
+unsafe impl<T: Send> Send for OnceLock<T> {}
+
+impl<T> OnceLock<T> {
+    /// Creates an uninitialised lock.
+    pub const fn new() -> Self {
+        // INVARIANT: The value is uninitialised.
+        Self {
+            state: AtomicU32::new(UNINIT),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Returns the value, or [`None`] if it is not initialised yet.
+    pub fn get(&self) -> Option<&T> {
+        if self.state.load(Ordering::Acquire) == DONE {
+            // SAFETY: By the type invariants, the value is initialised and never written again.
+            Some(unsafe { (*self.value.get()).assume_init_ref() })
+        } else {
+            None
+        }
+    }
+
+    /// Initialises the value to `value`, unless it is initialised already.
+    ///
+    /// This does not wait, so it can be called in atomic context, and returns `value` back if the
+    /// value is initialised already or another thread is initialising it.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let mut value = Some(value);
+        let _ = self.init_atomic(|_| value.take().ok_or(EINVAL));
+        value.map_or(Ok(()), Err)
+    }
+
+    /// Returns the value, initialised with `f` if needed.
+    ///
+    /// `f` is called with [`GFP_KERNEL`]. Its failure is returned, and leaves the value
+    /// uninitialised. This sleeps while another thread is initialising the value, so it must be
+    /// called in a context that can sleep.
+    pub fn get_or_try_init(&self, f: impl FnOnce(Flags) -> Result<T>) -> Result<&T> {
+        if let Some(v) = self.get() {
+            return Ok(v);
+        }
+        self.init(f, GFP_KERNEL, |this| {
+            this.wait();
+            Ok(())
+        })
+    }
+
+    /// Returns the value, initialised with `f` if needed, in atomic context.
+    ///
+    /// This is like [`OnceLock::get_or_try_init`], but `f` is called with [`GFP_ATOMIC`], and this
+    /// fails with [`EBUSY`] instead of waiting while another thread is initialising the value.
+    pub fn get_or_try_init_atomic(&self, f: impl FnOnce(Flags) -> Result<T>) -> Result<&T> {
+        if let Some(v) = self.get() {
+            return Ok(v);
+        }
+        self.init_atomic(f)
+    }
+
+    fn init_atomic(&self, f: impl FnOnce(Flags) -> Result<T>) -> Result<&T> {
+        self.init(f, GFP_ATOMIC, |_| Err(EBUSY))
+    }
+
+    fn init(
+        &self,
+        f: impl FnOnce(Flags) -> Result<T>,
+        flags: Flags,
+        wait: impl Fn(&Self) -> Result,
+    ) -> Result<&T> {
+        loop {
+            match self
+                .state
+                .compare_exchange(UNINIT, RUNNING, Ordering::Acquire, Ordering::Acquire)
+            {
+                Ok(_) => break,
+                Err(DONE) => {
+                    // SAFETY: By the type invariants, the value is initialised.
+                    return Ok(unsafe { (*self.value.get()).assume_init_ref() });
+                }
+                Err(_) => wait(self)?,
+            }
+        }
+
+        // SAFETY: This thread moved the state to `RUNNING`, so it is the only one accessing the
+        // value until it moves it out of `RUNNING` again.
+        let slot = unsafe { &mut *self.value.get() };
+        let ret = f(flags).map(|v| {
+            slot.write(v);
+        });
+        // INVARIANT: The value was initialised if and only if the initialiser succeeded.
+        let state = if ret.is_ok() { DONE } else { UNINIT };
+        self.state.store(state, Ordering::Release);
+        // `wake_up_var` only wakes waiters if it finds the wait queue active, which it checks
+        // without taking the lock of the queue. This full barrier, `smp_mb` in C, orders the store
+        // before that check, and pairs with the one in `prepare_to_wait`, which orders queueing
+        // the waiter before its check of the state.
+        fence(Ordering::SeqCst);
+        // SAFETY: `wake_up_var` can be called with any address.
+        unsafe { bindings::wake_up_var(self.state.as_ptr().cast()) };
+        ret?;
+        // SAFETY: The value was just initialised.
+        Ok(unsafe { (*self.value.get()).assume_init_ref() })
+    }
+
+    /// Sleeps until the state is not `RUNNING` anymore.
+    fn wait(&self) {
+        let var = self.state.as_ptr().cast();
+        // SAFETY: `__var_waitqueue` can be called with any address, and returns a wait queue that
+        // exists forever.
+        let wq = unsafe { bindings::__var_waitqueue(var) };
+        let wait = Opaque::<bindings::wait_queue_entry>::uninit();
+        // SAFETY: `wait` points to valid memory.
+        unsafe { bindings::init_wait(wait.get()) };
+        loop {
+            // SAFETY: Both `wait` and `wq` point to valid memory.
+            unsafe { bindings::prepare_to_wait(wq, wait.get(), TASK_UNINTERRUPTIBLE) };
+            // `prepare_to_wait` queues the entry followed by a full barrier, and the initialiser
+            // changes the state followed by a full barrier before checking for waiters, so either
+            // the state is seen here or the entry is seen by `wake_up_var`.
+            if self.state.load(Ordering::Acquire) != RUNNING {
+                break;
+            }
+            // SAFETY: Switches to another thread, which wakes this one up.
+            unsafe { bindings::schedule() };
+        }
+        // SAFETY: Both `wait` and `wq` point to valid memory.
+        unsafe { bindings::finish_wait(wq, wait.get()) };
+    }
+}
+
+impl<T> Default for OnceLock<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for OnceLock<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == DONE {
+            // SAFETY: By the type invariants, the value is initialised, and it is not used anymore.
+            unsafe { self.value.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+/// A value that is initialised by a given function on first use.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{alloc::Flags, prelude::*, sync::LazyLock};
+///
+/// fn make_names(flags: Flags) -> Result<Vec<u8>> {
+///     let mut v = Vec::new();
+///     v.extend_from_slice(b"eth0\0eth1\0", flags)?;
+///     Ok(v)
+/// }
+///
+/// static NAMES: LazyLock<Vec<u8>> = LazyLock::new(make_names);
+///
+/// assert_eq!(NAMES.force()?.len(), 10);
+/// # Ok::<(), Error>(())
+/// ```
+pub struct LazyLock<T, F = fn(Flags) -> Result<T>> {
+    once: OnceLock<T>,
+    init: F,
+}
+
+impl<T, F: Fn(Flags) -> Result<T>> LazyLock<T, F> {
+    /// Creates a lock whose value is initialised by `init`.
+    ///
+    /// `init` is called again on the next use if it fails.
+    pub const fn new(init: F) -> Self {
+        Self {
+            once: OnceLock::new(),
+            init,
+        }
+    }
+
+    /// Returns the value, initialising it if needed.
+    ///
+    /// This sleeps while another thread is initialising the value, see
+    /// [`OnceLock::get_or_try_init`].
+    pub fn force(&self) -> Result<&T> {
+        self.once.get_or_try_init(&self.init)
+    }
+
+    /// Returns the value, initialising it if needed, in atomic context.
+    ///
+    /// See [`OnceLock::get_or_try_init_atomic`].
+    pub fn force_atomic(&self) -> Result<&T> {
+        self.once.get_or_try_init_atomic(&self.init)
+    }
+
+    /// Returns the value, or [`None`] if it is not initialised yet.
+    pub fn get(&self) -> Option<&T> {
+        self.once.get()
+    }
+}
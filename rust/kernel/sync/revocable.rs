@@ -8,12 +8,13 @@
     init::PinInit,
     pin_init,
     str::CStr,
-    sync::{lock, lock::Lock, LockClassKey},
+    sync::{lock, lock::rwsem, lock::Lock, LockClassKey},
 };
 use core::{
     mem::MaybeUninit,
     ops::{Deref, DerefMut},
     pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
 };
 
 use super::lock::Guard;
@@ -61,10 +62,30 @@ fn drop(&mut self) {
     }
 }
 
+/// How [`Revocable::revoke`] competes with accessors that are still coming in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RevokePolicy {
+    /// Revocation waits for the lock like any other accessor.
+    ///
+    /// Whether it can be overtaken depends on the lock. A read-write semaphore makes new readers
+    /// wait behind a waiting writer, but on a busy device readers that spin optimistically, or
+    /// that retry in a loop, can still delay revocation for a long time.
+    #[default]
+    Fair,
+
+    /// Once revocation starts, new accessors fail as if the object were already revoked.
+    ///
+    /// Revocation then only waits for the accessors that already hold the lock, so teardown
+    /// completes in bounded time no matter how many accessors keep coming in.
+    WriterPriority,
+}
+
 #[pin_data]
 pub struct Revocable<T, B: lock::Backend> {
     #[pin]
     inner: Lock<Inner<T>, B>,
+    policy: RevokePolicy,
+    revoking: AtomicBool,
 }
 
 /// Safely initialises a [`Revocable`] instance with the given name, generating a new lock class.
@@ -81,19 +102,44 @@ impl<T, B> Revocable<T, B>
 {
     /// Creates a new revocable instance of the given lock.
     pub fn new(data: T, name: &'static CStr, key: &'static LockClassKey) -> impl PinInit<Self> {
+        Self::new_with_policy(data, RevokePolicy::Fair, name, key)
+    }
+
+    /// Creates a new revocable instance of the given lock, revoked according to `policy`.
+    pub fn new_with_policy(
+        data: T,
+        policy: RevokePolicy,
+        name: &'static CStr,
+        key: &'static LockClassKey,
+    ) -> impl PinInit<Self> {
         pin_init!(Self {
-            inner <- Lock::new(Inner::new(data), name, key) ,
+            inner <- Lock::new(Inner::new(data), name, key),
+            policy,
+            revoking: AtomicBool::new(false),
         })
     }
 
     /// Revokes access to and drops the wrapped object.
     ///
-    /// Revocation and dropping happen after ongoing accessors complete.
+    /// Revocation and dropping happen after ongoing accessors complete. With
+    /// [`RevokePolicy::WriterPriority`], accessors that come in while revocation waits fail.
     pub fn revoke(&self) {
+        if self.policy == RevokePolicy::WriterPriority {
+            self.revoking.store(true, Ordering::Relaxed);
+        }
         self.lock().drop_in_place();
     }
 
+    /// Returns whether new accessors are turned away because revocation started.
+    fn is_revoking(&self) -> bool {
+        self.revoking.load(Ordering::Relaxed)
+    }
+
     pub fn try_write(&self) -> Option<RevocableGuard<'_, T, B>> {
+        if self.is_revoking() {
+            return None;
+        }
+
         let inner = self.lock();
 
         if !inner.is_available {
@@ -103,11 +149,58 @@ pub fn try_write(&self) -> Option<RevocableGuard<'_, T, B>> {
         Some(RevocableGuard::new(inner))
     }
 
+    /// Calls `f` with shared access to the wrapped object, if it is still available.
+    ///
+    /// Returns [`None`] if the object has been revoked. The lock is only held while `f` runs, so
+    /// unlike with [`Revocable::try_write`], the critical section cannot be extended by
+    /// accidentally keeping a guard alive.
+    pub fn with<R>(&self, f: impl FnOnce(&T) -> R) -> Option<R> {
+        let guard = self.try_write()?;
+        Some(f(&guard))
+    }
+
+    /// Calls `f` with exclusive access to the wrapped object, if it is still available.
+    ///
+    /// Returns [`None`] if the object has been revoked. The lock is only held while `f` runs.
+    pub fn with_mut<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        let mut guard = self.try_write()?;
+        Some(f(&mut guard))
+    }
+
     fn lock(&self) -> Guard<'_, Inner<T>, B> {
         self.inner.lock()
     }
 }
 
+impl<T: Sync> Revocable<T, rwsem::RwSemBackend> {
+    /// Tries to access the wrapped object for reading.
+    ///
+    /// Many readers can hold the returned guard at the same time, and [`Revocable::revoke`] waits
+    /// for all of them. Returns [`None`] if the object has been revoked.
+    pub fn try_read(&self) -> Option<RevocableReadGuard<'_, T>> {
+        if self.is_revoking() {
+            return None;
+        }
+
+        let guard = self.inner.read();
+
+        if !guard.is_available {
+            return None;
+        }
+
+        Some(RevocableReadGuard { guard })
+    }
+
+    /// Calls `f` with shared access to the wrapped object, if it is still available.
+    ///
+    /// Unlike [`Revocable::with`], this takes the semaphore for reading, so calls from different
+    /// threads run concurrently.
+    pub fn with_read<R>(&self, f: impl FnOnce(&T) -> R) -> Option<R> {
+        let guard = self.try_read()?;
+        Some(f(&guard))
+    }
+}
+
 pub struct RevocableGuard<'a, T, B>
 where
     B: lock::Backend,
@@ -127,6 +220,16 @@ pub fn as_pinned_mut(&mut self) -> Pin<&mut T> {
     }
 }
 
+// Only mutex-backed guards are known to be sleepable; other backends may disable preemption.
+impl<T> crate::revocable::AccessGuard for RevocableGuard<'_, T, super::lock::mutex::MutexBackend> {
+    type Context = crate::revocable::Sleepable;
+}
+
+// Read-write semaphores sleep, so their holders can too.
+impl<T> crate::revocable::AccessGuard for RevocableGuard<'_, T, rwsem::RwSemBackend> {
+    type Context = crate::revocable::Sleepable;
+}
+
 impl<T, B: lock::Backend> Deref for RevocableGuard<'_, T, B> {
     type Target = T;
 
@@ -146,3 +249,60 @@ fn deref_mut(&mut self) -> &mut Self::Target {
 
 /// Type alias for a `RevocableGuard` with a `MutexBackend`.
 pub type RevocableMutexGuard<'a, T> = RevocableGuard<'a, T, super::lock::mutex::MutexBackend>;
+
+/// A guard that gives shared access to the object wrapped by a [`RevocableRwSemaphore`].
+///
+/// The semaphore is released when the guard is dropped.
+///
+/// # Invariants
+///
+/// The object guarded by `guard` is available.
+pub struct RevocableReadGuard<'a, T: Sync> {
+    guard: rwsem::ReadGuard<'a, Inner<T>>,
+}
+
+impl<T: Sync> crate::revocable::AccessGuard for RevocableReadGuard<'_, T> {
+    type Context = crate::revocable::Sleepable;
+}
+
+impl<T: Sync> Deref for RevocableReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: By the type invariants, the object is available, so `data` is initialised.
+        unsafe { &*self.guard.data.as_ptr() }
+    }
+}
+
+/// Type alias for a `Revocable` with a `RwSemBackend`.
+///
+/// Readers share access through [`Revocable::try_read`], while [`Revocable::revoke`] waits for all
+/// of them to complete. Busy devices with many readers should be created with
+/// [`RevokePolicy::WriterPriority`] so that teardown is not held up by new readers.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{c_str, static_lock_class, sync::{RevocableRwSemaphore, RevokePolicy}};
+///
+/// let regs = Box::pin_init(
+///     RevocableRwSemaphore::new_with_policy(
+///         0x1234u32,
+///         RevokePolicy::WriterPriority,
+///         c_str!("regs"),
+///         static_lock_class!(),
+///     ),
+///     GFP_KERNEL,
+/// )?;
+/// assert_eq!(regs.with_read(|r| *r), Some(0x1234));
+/// regs.revoke();
+/// assert!(regs.try_read().is_none());
+/// # Ok::<(), Error>(())
+/// ```
+pub type RevocableRwSemaphore<T> = Revocable<T, rwsem::RwSemBackend>;
+
+/// Type alias for a `RevocableGuard` with a `RwSemBackend`.
+pub type RevocableRwSemaphoreGuard<'a, T> = RevocableGuard<'a, T, rwsem::RwSemBackend>;
+
+/// Type alias for a guard giving shared access to a [`RevocableRwSemaphore`].
+pub type RevocableRwSemaphoreReadGuard<'a, T> = RevocableReadGuard<'a, T>;
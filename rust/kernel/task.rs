@@ -19,6 +19,8 @@
 pub const TASK_INTERRUPTIBLE: c_int = bindings::TASK_INTERRUPTIBLE as c_int;
 /// Bitmask for tasks that are sleeping in an uninterruptible state.
 pub const TASK_UNINTERRUPTIBLE: c_int = bindings::TASK_UNINTERRUPTIBLE as c_int;
+/// Bitmask for tasks that are sleeping in an uninterruptible state, except for fatal signals.
+pub const TASK_KILLABLE: c_int = bindings::TASK_KILLABLE as c_int;
 /// Convenience constant for waking up tasks regardless of whether they are in interruptible or
 /// uninterruptible sleep.
 pub const TASK_NORMAL: c_uint = bindings::TASK_NORMAL as c_uint;
@@ -154,6 +156,16 @@ pub fn signal_pending(&self) -> bool {
         unsafe { bindings::signal_pending(self.0.get()) != 0 }
     }
 
+    /// Determines whether the given task is being killed, i.e., has a pending `SIGKILL`.
+    ///
+    /// Long operations done on behalf of userspace, e.g., in an ioctl, should check this
+    /// regularly and bail out with [`ERESTARTSYS`](crate::error::code::ERESTARTSYS), so that the
+    /// process can exit.
+    pub fn fatal_signal_pending(&self) -> bool {
+        // SAFETY: By the type invariant, we know that `self.0` is valid.
+        unsafe { bindings::fatal_signal_pending(self.0.get()) != 0 }
+    }
+
     /// Wakes up the task.
     pub fn wake_up(&self) {
         // SAFETY: By the type invariant, we know that `self.0.get()` is non-null and valid.
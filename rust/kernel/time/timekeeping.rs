@@ -0,0 +1,168 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Reads of the system clocks, and notifications of changes of the real-time clock.
+//!
+//! All clocks are read in nanoseconds:
+//!
+//! - [`monotonic`] counts from boot and does not advance while the system is suspended.
+//! - [`boottime`] is like [`monotonic`], but also counts the time spent in suspend.
+//! - [`real`] is the wall clock time since the Unix epoch, i.e., `CLOCK_REALTIME`. It can be set,
+//!   or jump, at any time.
+//! - [`tai`] is the International Atomic Time, i.e., [`real`] without leap seconds.
+//!
+//! C header: [`include/linux/timekeeping.h`](srctree/include/linux/timekeeping.h)
+
+use crate::{bindings, error::to_result, prelude::*, types::Opaque};
+use core::{ffi::c_void, marker::PhantomPinned};
+
+/// A point in time, in nanoseconds, on one of the clocks of this module.
+pub type Ktime = bindings::ktime_t;
+
+/// Returns the time of the monotonic clock.
+#[inline]
+pub fn monotonic() -> Ktime {
+    // SAFETY: `ktime_get` can be called from any context.
+    unsafe { bindings::ktime_get() }
+}
+
+/// Returns the time of the boot clock, which includes the time spent in suspend.
+#[inline]
+pub fn boottime() -> Ktime {
+    // SAFETY: `ktime_get_boottime` can be called from any context.
+    unsafe { bindings::ktime_get_boottime() }
+}
+
+/// Returns the wall clock time.
+#[inline]
+pub fn real() -> Ktime {
+    // SAFETY: `ktime_get_real` can be called from any context.
+    unsafe { bindings::ktime_get_real() }
+}
+
+/// Returns the International Atomic Time.
+#[inline]
+pub fn tai() -> Ktime {
+    // SAFETY: `ktime_get_clocktai` can be called from any context.
+    unsafe { bindings::ktime_get_clocktai() }
+}
+
+/// Returns the wall clock time and the boot clock time, read atomically.
+///
+/// This is useful to convert timestamps of one clock into the other.
+pub fn real_and_boottime() -> (Ktime, Ktime) {
+    let mut snap = core::mem::MaybeUninit::<bindings::system_time_snapshot>::uninit();
+    // SAFETY: `snap` is valid for writes, and is fully written by the function.
+    let snap = unsafe {
+        bindings::ktime_get_snapshot(snap.as_mut_ptr());
+        snap.assume_init()
+    };
+    (snap.real, snap.boot)
+}
+
+/// A handler of changes of the real-time clock.
+pub trait ClockChange: Sync {
+    /// Called when the real-time clock was set, e.g., with `settimeofday` or `clock_settime`, by
+    /// a step of NTP, or on resume, when the time spent in suspend is injected.
+    ///
+    /// This runs in atomic context, with interrupts disabled and the timekeeping lock held, so it
+    /// must not read the clocks of this module. Drivers usually just schedule work, which then
+    /// re-reads the clock.
+    fn clock_was_set(&self);
+}
+
+/// A registration of a [`ClockChange`] handler.
+///
+/// The underlying notifier chain is called on every update of the timekeeper, i.e., on every
+/// tick, from the timer interrupt or the CPU that does the timekeeping for idle CPUs. The
+/// registration only forwards the updates that set the clock to [`ClockChange::clock_was_set`],
+/// but still adds a little overhead to every tick while it exists.
+///
+/// The handler is unregistered when this object is dropped.
+///
+/// # Invariants
+///
+/// `nb` is registered with `pvclock_gtod_register_notifier`.
+///
+/// # Examples
+///
+/// ```
+/// use core::sync::atomic::{AtomicBool, Ordering};
+/// use kernel::{prelude::*, time::timekeeping::{ClockChange, Notifier}};
+///
+/// struct Resync(AtomicBool);
+///
+/// impl ClockChange for Resync {
+///     fn clock_was_set(&self) {
+///         self.0.store(true, Ordering::Relaxed);
+///     }
+/// }
+///
+/// let _n = Box::pin_init(Notifier::new(Resync(AtomicBool::new(false))), GFP_KERNEL)?;
+/// # Ok::<(), Error>(())
+/// ```
+#[pin_data(PinnedDrop)]
+pub struct Notifier<T: ClockChange> {
+    handler: T,
+    #[pin]
+    nb: Opaque<bindings::notifier_block>,
+    #[pin]
+    _pin: PhantomPinned,
+}
+
+impl<T: ClockChange> Notifier<T> {
+    /// Registers `handler` for changes of the real-time clock.
+    pub fn new(handler: T) -> impl PinInit<Self, Error> {
+        try_pin_init!(Self {
+            handler,
+            _pin: PhantomPinned,
+            // Initialised last, so that the object is complete once the callback can run, which
+            // may happen before the registration returns.
+            nb <- Opaque::try_ffi_init(|nb: *mut bindings::notifier_block| {
+                // SAFETY: `nb` is valid for writes. The notifier block is pinned and unregistered
+                // before it is freed, by `PinnedDrop`.
+                unsafe {
+                    nb.write(bindings::notifier_block {
+                        notifier_call: Some(Self::notifier_callback),
+                        ..core::mem::zeroed()
+                    });
+                    to_result(bindings::pvclock_gtod_register_notifier(nb))
+                }
+            }),
+        })
+    }
+
+    /// Returns the handler.
+    pub fn handler(&self) -> &T {
+        &self.handler
+    }
+
+    unsafe extern "C" fn notifier_callback(
+        nb: *mut bindings::notifier_block,
+        was_set: core::ffi::c_ulong,
+        _priv: *mut c_void,
+    ) -> core::ffi::c_int {
+        // The chain runs on every update of the timekeeper; only forward actual changes.
+        if was_set != 0 {
+            // SAFETY: `nb` is the `nb` field of a registered `Notifier<T>`, which stays alive
+            // until it is unregistered.
+            let this = unsafe { &*crate::container_of!(nb, Self, nb) };
+            this.handler.clock_was_set();
+        }
+        bindings::NOTIFY_DONE as _
+    }
+}
+
+#[pinned_drop]
+impl<T: ClockChange> PinnedDrop for Notifier<T> {
+    fn drop(self: Pin<&mut Self>) {
+        // SAFETY: By the type invariants, `nb` is registered. Unregistering takes the timekeeping
+        // lock, under which the callbacks run, so none is running anymore afterwards.
+        unsafe { bindings::pvclock_gtod_unregister_notifier(self.nb.get()) };
+    }
+}
+
+// SAFETY: The notifier can be unregistered from any thread, and the handler is `Sync`.
+unsafe impl<T: ClockChange + Send> Send for Notifier<T> {}
+
+// SAFETY: The handler is `Sync`, and the notifier block is only accessed by the notifier chain.
+unsafe impl<T: ClockChange> Sync for Notifier<T> {}
@@ -0,0 +1,374 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Per-driver event buffers.
+//!
+//! Drivers that log hardware events at a high rate, e.g., every interrupt or completion of a
+//! device, would overwhelm the kernel log, and formatting each event would cost more than
+//! handling it. An [`EventBuffer`] instead records fixed-size binary [`Event`]s in a per-CPU ring
+//! buffer, the one the tracing subsystem uses, which can be written from any context and
+//! overwrites the oldest events when it is full. Userspace reads the events back in binary form,
+//! and decodes them with the layout that is exported next to them.
+//!
+//! C header: [`include/linux/ring_buffer.h`](srctree/include/linux/ring_buffer.h)
+
+use crate::{
+    bindings,
+    debugfs::{Dir, Field},
+    error::{code::*, from_result, Result},
+    prelude::*,
+    str::CString,
+};
+use core::{
+    ffi::{c_char, c_int, c_ulong, c_void},
+    mem::size_of,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// An event that can be recorded in an [`EventBuffer`].
+///
+/// This is implemented with the [`trace_buffer_event`] macro, which also generates the layout.
+///
+/// # Safety
+///
+/// Implementers must be `#[repr(C)]`, without padding, and [`Event::FIELDS`] must describe all of
+/// their fields.
+///
+/// [`trace_buffer_event`]: crate::trace_buffer_event
+pub unsafe trait Event: Copy + Send + 'static {
+    /// The version of the layout, which is increased whenever it changes.
+    const VERSION: u32;
+
+    /// The fields of the event, in order.
+    const FIELDS: &'static [Field];
+}
+
+/// Defines an event that can be recorded in an [`EventBuffer`].
+///
+/// The fields must be integer types that implement [`StatField`](crate::debugfs::StatField),
+/// and must be ordered so that the structure has no padding.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{c_str, debugfs::Dir, prelude::*, trace_buffer::EventBuffer};
+///
+/// kernel::trace_buffer_event! {
+///     /// The completion of a descriptor of a DMA channel.
+///     pub struct Completion: 1 {
+///         desc: u64,
+///         status: u32,
+///         channel: u32,
+///     }
+/// }
+///
+/// let dir = Dir::new(c_str!("my_dma"), None);
+/// let events = EventBuffer::<Completion>::new(c_str!("completions"), Some(&dir), 64 * 1024)?;
+/// events.record(&Completion { desc: 0x1000, status: 0, channel: 2 });
+/// # Ok::<(), Error>(())
+/// ```
+#[macro_export]
+macro_rules! trace_buffer_event {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident: $version:literal {
+            $($(#[$fmeta:meta])* $field:ident: $ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[repr(C)]
+        #[derive(Clone, Copy)]
+        $vis struct $name {
+            $($(#[$fmeta])* $vis $field: $ty),*
+        }
+
+        const _: () = ::core::assert!(
+            0 $(+ ::core::mem::size_of::<$ty>())* == ::core::mem::size_of::<$name>(),
+            "the fields of the event must not be padded"
+        );
+
+        // SAFETY: The structure is `#[repr(C)]`, has no padding as checked above, and `FIELDS`
+        // lists all its fields.
+        unsafe impl $crate::trace_buffer::Event for $name {
+            const VERSION: u32 = $version;
+            const FIELDS: &'static [$crate::debugfs::Field] = &[$(
+                $crate::debugfs::Field {
+                    name: ::core::stringify!($field),
+                    ty: <$ty as $crate::debugfs::StatField>::NAME,
+                    offset: ::core::mem::offset_of!($name, $field),
+                    size: ::core::mem::size_of::<$ty>(),
+                }
+            ),*];
+        }
+    };
+}
+
+/// The header of each record read from an [`EventBuffer`].
+#[repr(C)]
+struct RecordHeader {
+    ts: u64,
+    cpu: u32,
+    lost: u32,
+}
+
+struct Inner {
+    buffer: *mut bindings::trace_buffer,
+    record_size: usize,
+    reading: AtomicBool,
+    fops: bindings::file_operations,
+    layout: Vec<u8>,
+    layout_wrapper: bindings::debugfs_blob_wrapper,
+}
+
+/// A ring buffer of events of type `T`, exported through debugfs.
+///
+/// Two read-only files are created: `name` returns the recorded events, and `name.layout` a text
+/// description of them, one line for the records and one for each field of the event:
+///
+/// ```text
+/// version 1 size 16 header 16 endian little
+/// desc u64 0 8
+/// status u32 8 4
+/// channel u32 12 4
+/// ```
+///
+/// Each record is a 16-byte header, which holds the timestamp of the event in nanoseconds as a
+/// `u64`, the CPU that recorded it and the number of events of that CPU that were overwritten
+/// before it was read, as `u32` values, followed by the event. The fields are given with their
+/// type, and their offset and size in bytes from the end of the header.
+///
+/// Reading `name` consumes the events, so reads fail with `EBUSY` while another one is running.
+/// Each read returns whole records, those of each CPU in order, and returns zero once the buffer
+/// is empty. Reads with a buffer smaller than one record fail with `EINVAL`. Readers merge the
+/// records of the CPUs by timestamp.
+///
+/// The files are removed, and the events freed, when this object is dropped, which must happen
+/// before the parent directory is removed, hence the lifetime `'a` of the parent.
+///
+/// # Invariants
+///
+/// `inner.buffer` was allocated with `__ring_buffer_alloc`, and `inner` does not move while the
+/// files exist. `events` and `layout` were returned by `debugfs_create_file` and
+/// `debugfs_create_blob`, with `inner` as their data, in a parent directory that outlives `'a`.
+pub struct EventBuffer<'a, T: Event> {
+    inner: Box<Inner>,
+    events: *mut bindings::dentry,
+    layout: *mut bindings::dentry,
+    _p: core::marker::PhantomData<(T, &'a Dir)>,
+}
+
+impl<'a, T: Event> EventBuffer<'a, T> {
+    /// Creates a buffer of events of about `size` bytes per CPU, exported as the file `name`, and
+    /// its layout as `name.layout`, in `parent`, or in the root of debugfs.
+    pub fn new(name: &CStr, parent: Option<&'a Dir>, size: usize) -> Result<Self> {
+        let record_size = size_of::<RecordHeader>() + size_of::<T>();
+        let mut layout = Vec::new();
+        let endian = if cfg!(target_endian = "little") {
+            "little"
+        } else {
+            "big"
+        };
+        let line = CString::try_from_fmt(fmt!(
+            "version {} size {} header {} endian {}\n",
+            T::VERSION,
+            size_of::<T>(),
+            size_of::<RecordHeader>(),
+            endian
+        ))?;
+        layout.extend_from_slice(line.as_bytes(), GFP_KERNEL)?;
+        for f in T::FIELDS {
+            let line =
+                CString::try_from_fmt(fmt!("{} {} {} {}\n", f.name, f.ty, f.offset, f.size))?;
+            layout.extend_from_slice(line.as_bytes(), GFP_KERNEL)?;
+        }
+        let layout_name = CString::try_from_fmt(fmt!("{}.layout", name))?;
+
+        // SAFETY: The lock class key is static.
+        let buffer = unsafe {
+            bindings::__ring_buffer_alloc(
+                size as c_ulong,
+                bindings::RB_FL_OVERWRITE,
+                crate::static_lock_class!().as_ptr(),
+            )
+        };
+        if buffer.is_null() {
+            return Err(ENOMEM);
+        }
+        let inner = Box::new(
+            Inner {
+                buffer,
+                record_size,
+                reading: AtomicBool::new(false),
+                fops: bindings::file_operations {
+                    open: Some(bindings::simple_open),
+                    read: Some(Self::read_callback),
+                    llseek: Some(bindings::no_llseek),
+                    // SAFETY: The remaining callbacks are optional.
+                    ..unsafe { core::mem::zeroed() }
+                },
+                layout,
+                // SAFETY: All zeroes is a valid value of the wrapper, it is set below.
+                layout_wrapper: unsafe { core::mem::zeroed() },
+            },
+            GFP_KERNEL,
+        );
+        let mut inner = match inner {
+            Ok(inner) => inner,
+            Err(e) => {
+                // SAFETY: The buffer was just allocated and is not used by anything else.
+                unsafe { bindings::ring_buffer_free(buffer) };
+                return Err(e.into());
+            }
+        };
+        inner.layout_wrapper.data = inner.layout.as_ptr() as *mut c_void;
+        inner.layout_wrapper.size = inner.layout.len() as _;
+
+        let parent = crate::debugfs::parent_raw(parent);
+        let data = (&*inner as *const Inner).cast_mut().cast();
+        // SAFETY: The names are valid C strings, the parent is either null or a directory, and
+        // the operations, the wrapper and the data point into `inner`, which lives until the files
+        // are removed in `drop`.
+        let (events, layout) = unsafe {
+            (
+                bindings::debugfs_create_file(name.as_char_ptr(), 0o400, parent, data, &inner.fops),
+                bindings::debugfs_create_blob(
+                    layout_name.as_char_ptr(),
+                    0o400,
+                    parent,
+                    &mut inner.layout_wrapper,
+                ),
+            )
+        };
+        // INVARIANT: The buffer was allocated and the files created above, with `inner` as their
+        // data, in `parent`, which is borrowed for `'a`.
+        Ok(Self {
+            inner,
+            events,
+            layout,
+            _p: core::marker::PhantomData,
+        })
+    }
+
+    /// Records `event` in the buffer of the current CPU.
+    ///
+    /// This can be called from any context, including interrupt handlers. Events are silently
+    /// dropped while recording is disabled, e.g., by [`EventBuffer::set_enabled`].
+    pub fn record(&self, event: &T) {
+        // SAFETY: By the type invariants, the buffer is valid. `event` is valid for reads of its
+        // size, which the buffer copies.
+        unsafe {
+            bindings::ring_buffer_write(
+                self.inner.buffer,
+                size_of::<T>() as c_ulong,
+                (event as *const T).cast_mut().cast(),
+            )
+        };
+    }
+
+    /// Enables or disables recording, e.g., to keep the events that led to an error.
+    pub fn set_enabled(&self, enable: bool) {
+        let f: unsafe extern "C" fn(*mut bindings::trace_buffer) = if enable {
+            bindings::ring_buffer_record_on
+        } else {
+            bindings::ring_buffer_record_off
+        };
+        // SAFETY: By the type invariants, the buffer is valid.
+        unsafe { f(self.inner.buffer) };
+    }
+
+    /// Discards all the recorded events.
+    pub fn reset(&self) {
+        // SAFETY: By the type invariants, the buffer is valid.
+        unsafe { bindings::ring_buffer_reset(self.inner.buffer) };
+    }
+
+    unsafe extern "C" fn read_callback(
+        file: *mut bindings::file,
+        buf: *mut c_char,
+        count: usize,
+        _ppos: *mut bindings::loff_t,
+    ) -> isize {
+        // SAFETY: `simple_open` set the private data of the file to `inner`, and debugfs keeps it
+        // alive while the callback runs.
+        let inner = unsafe { &*(*file).private_data.cast::<Inner>() };
+        if inner.reading.swap(true, Ordering::Acquire) {
+            return EBUSY.to_errno() as isize;
+        }
+        let ret = from_result(|| {
+            // Returning zero would be taken as the end of the file.
+            if count < inner.record_size {
+                return Err(EINVAL);
+            }
+            let mut done = 0;
+            // SAFETY: `nr_cpu_ids` is only written during early boot.
+            'cpus: for cpu in 0..unsafe { bindings::nr_cpu_ids } {
+                while count - done >= inner.record_size {
+                    let mut ts = 0u64;
+                    let mut lost: c_ulong = 0;
+                    // SAFETY: The buffer is valid, and this is its only reader, thanks to
+                    // `reading`. CPUs that have no buffer have no events.
+                    let event = unsafe {
+                        bindings::ring_buffer_consume(
+                            inner.buffer,
+                            cpu as c_int,
+                            &mut ts,
+                            &mut lost,
+                        )
+                    };
+                    if event.is_null() {
+                        break;
+                    }
+                    let header = RecordHeader {
+                        ts,
+                        cpu,
+                        lost: lost.try_into().unwrap_or(u32::MAX),
+                    };
+                    let hdr_size = size_of::<RecordHeader>();
+                    // SAFETY: `buf` is a userspace buffer of `count` bytes, which `copy_to_user`
+                    // checks, and `done + record_size <= count`. The event holds a `T`, which was
+                    // recorded by `record`, and is not overwritten until the next consume.
+                    let left = unsafe {
+                        bindings::copy_to_user(
+                            buf.wrapping_add(done).cast(),
+                            (&header as *const RecordHeader).cast(),
+                            hdr_size as c_ulong,
+                        ) + bindings::copy_to_user(
+                            buf.wrapping_add(done + hdr_size).cast(),
+                            bindings::ring_buffer_event_data(event),
+                            size_of::<T>() as c_ulong,
+                        )
+                    };
+                    if left != 0 {
+                        // The records copied so far were consumed, so they are returned.
+                        if done > 0 {
+                            break 'cpus;
+                        }
+                        return Err(EFAULT);
+                    }
+                    done += inner.record_size;
+                }
+            }
+            Ok(done as isize)
+        });
+        inner.reading.store(false, Ordering::Release);
+        ret
+    }
+}
+
+impl<T: Event> Drop for EventBuffer<'_, T> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, the files were created above, in a parent that was not
+        // removed yet, and the buffer was allocated. Removing the files waits for the running callbacks, so the buffer and
+        // `inner` are not used anymore afterwards.
+        unsafe {
+            bindings::debugfs_remove(self.events);
+            bindings::debugfs_remove(self.layout);
+            bindings::ring_buffer_free(self.inner.buffer);
+        }
+    }
+}
+
+// SAFETY: The buffer can be written from any CPU, and freed from any thread.
+unsafe impl<T: Event> Send for EventBuffer<'_, T> {}
+
+// SAFETY: Recording is safe from any context, and reads are serialised by `reading`.
+unsafe impl<T: Event> Sync for EventBuffer<'_, T> {}
@@ -169,6 +169,42 @@ unsafe fn from_foreign(_: *const core::ffi::c_void) -> Self {}
 /// # assert_eq!(example3(true), Ok(()));
 /// ```
 ///
+/// In the example below, a probe-like function registers an undo action for every step that
+/// succeeds. If a later step fails, the undo actions run in reverse order; once everything has
+/// succeeded, they are all dismissed:
+///
+/// ```
+/// # use kernel::types::ScopeGuard;
+/// fn enable_clock() -> Result {
+///     pr_info!("clock enabled\n");
+///     Ok(())
+/// }
+///
+/// fn power_on(fail: bool) -> Result {
+///     if fail {
+///         return Err(EIO);
+///     }
+///     pr_info!("powered on\n");
+///     Ok(())
+/// }
+///
+/// fn probe(fail: bool) -> Result {
+///     enable_clock()?;
+///     let clock = ScopeGuard::new(|| pr_info!("clock disabled\n"));
+///
+///     power_on(fail)?;
+///     let power = ScopeGuard::new(|| pr_info!("powered off\n"));
+///
+///     // Everything succeeded, the device stays enabled.
+///     power.dismiss();
+///     clock.dismiss();
+///     Ok(())
+/// }
+///
+/// # assert_eq!(probe(false), Ok(()));
+/// # assert_eq!(probe(true), Err(EIO));
+/// ```
+///
 /// # Invariants
 ///
 /// The value stored in the struct is nearly always `Some(_)`, except between
@@ -259,14 +295,23 @@ pub const fn uninit() -> Self {
     /// uninitialized. Additionally, access to the inner `T` requires `unsafe`, so the caller needs
     /// to verify at that point that the inner value is valid.
     pub fn ffi_init(init_func: impl FnOnce(*mut T)) -> impl PinInit<Self> {
+        Self::try_ffi_init(move |slot| {
+            init_func(slot);
+            Ok::<(), core::convert::Infallible>(())
+        })
+    }
+
+    /// Creates a fallible pin-initializer from the given initializer closure.
+    ///
+    /// This is the fallible counterpart of [`Opaque::ffi_init`], meant for C initialisation
+    /// functions that may fail (e.g., ones that return an error code). When the closure returns an
+    /// error, the inner `T` is considered uninitialized and is not dropped.
+    pub fn try_ffi_init<E>(
+        init_func: impl FnOnce(*mut T) -> Result<(), E>,
+    ) -> impl PinInit<Self, E> {
         // SAFETY: We contain a `MaybeUninit`, so it is OK for the `init_func` to not fully
         // initialize the `T`.
-        unsafe {
-            init::pin_init_from_closure::<_, ::core::convert::Infallible>(move |slot| {
-                init_func(Self::raw_get(slot));
-                Ok(())
-            })
-        }
+        unsafe { init::pin_init_from_closure::<_, E>(move |slot| init_func(Self::raw_get(slot))) }
     }
 
     /// Returns a raw pointer to the opaque data.
@@ -402,6 +447,20 @@ fn drop(&mut self) {
 }
 
 /// A sum type that always holds either a value of type `L` or `R`.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::types::Either;
+///
+/// let idle: Either<u32, &str> = Either::Left(0);
+/// let busy: Either<u32, &str> = Either::Right("busy");
+///
+/// assert!(idle.is_left());
+/// assert_eq!(busy.right(), Some("busy"));
+/// assert_eq!(busy.left(), None);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Either<L, R> {
     /// Constructs an instance of [`Either`] containing a value of type `L`.
     Left(L),
@@ -409,3 +468,47 @@ pub enum Either<L, R> {
     /// Constructs an instance of [`Either`] containing a value of type `R`.
     Right(R),
 }
+
+impl<L, R> Either<L, R> {
+    /// Returns `true` if this is an [`Either::Left`] value.
+    pub fn is_left(&self) -> bool {
+        matches!(self, Either::Left(_))
+    }
+
+    /// Returns `true` if this is an [`Either::Right`] value.
+    pub fn is_right(&self) -> bool {
+        matches!(self, Either::Right(_))
+    }
+
+    /// Returns the contained [`Either::Left`] value, if any.
+    pub fn left(self) -> Option<L> {
+        match self {
+            Either::Left(l) => Some(l),
+            Either::Right(_) => None,
+        }
+    }
+
+    /// Returns the contained [`Either::Right`] value, if any.
+    pub fn right(self) -> Option<R> {
+        match self {
+            Either::Left(_) => None,
+            Either::Right(r) => Some(r),
+        }
+    }
+
+    /// Converts from `&Either<L, R>` to `Either<&L, &R>`.
+    pub fn as_ref(&self) -> Either<&L, &R> {
+        match self {
+            Either::Left(l) => Either::Left(l),
+            Either::Right(r) => Either::Right(r),
+        }
+    }
+
+    /// Converts from `&mut Either<L, R>` to `Either<&mut L, &mut R>`.
+    pub fn as_mut(&mut self) -> Either<&mut L, &mut R> {
+        match self {
+            Either::Left(l) => Either::Left(l),
+            Either::Right(r) => Either::Right(r),
+        }
+    }
+}
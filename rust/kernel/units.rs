@@ -0,0 +1,263 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Units and unit conversions.
+//!
+//! Sensor subsystems report values in fixed units: hwmon and thermal use millidegrees Celsius,
+//! IIO reports integer plus micro (or nano) parts. The helpers below convert between them without
+//! floating point.
+//!
+//! C header: [`include/linux/units.h`](srctree/include/linux/units.h)
+//!
+//! # Examples
+//!
+//! ```
+//! use kernel::units::{self, Fixed, KILO, MILLI};
+//!
+//! assert_eq!(5 * KILO, 5000);
+//! assert_eq!(units::kelvin_to_millicelsius(300), 26850);
+//! assert_eq!(units::millicelsius_to_kelvin(26850), 300);
+//! assert_eq!(units::deci_kelvin_to_millicelsius(2982), 25050);
+//! assert_eq!(units::milli_to_micro(-3), -3000);
+//!
+//! // 1.25 with 16 fractional bits.
+//! let v = Fixed::<16>::from_ratio(5, 4).unwrap();
+//! assert_eq!(v.to_int_plus_micro(), (1, 250000));
+//! assert_eq!(v.mul_int(3).unwrap().round(), 4);
+//! assert_eq!(Fixed::<16>::from_ratio(-5, 4).unwrap().to_int_plus_micro(), (-1, -250000));
+//! # let _ = MILLI;
+//! ```
+
+use core::fmt;
+
+/// 10^-3, the "milli" prefix, as the number of milli units in one unit.
+pub const MILLI: i64 = 1000;
+/// 10^-6, the "micro" prefix, as the number of micro units in one unit.
+pub const MICRO: i64 = 1_000_000;
+/// 10^-9, the "nano" prefix, as the number of nano units in one unit.
+pub const NANO: i64 = 1_000_000_000;
+/// 10^-12, the "pico" prefix, as the number of pico units in one unit.
+pub const PICO: i64 = 1_000_000_000_000;
+
+/// 10^3, the "kilo" prefix.
+pub const KILO: i64 = 1000;
+/// 10^6, the "mega" prefix.
+pub const MEGA: i64 = 1_000_000;
+/// 10^9, the "giga" prefix.
+pub const GIGA: i64 = 1_000_000_000;
+/// 10^12, the "tera" prefix.
+pub const TERA: i64 = 1_000_000_000_000;
+
+/// Hertz per kilohertz.
+pub const HZ_PER_KHZ: u64 = 1000;
+/// Hertz per megahertz.
+pub const HZ_PER_MHZ: u64 = 1_000_000;
+/// Milliwatts per watt.
+pub const MILLIWATT_PER_WATT: u32 = 1000;
+/// Microwatts per milliwatt.
+pub const MICROWATT_PER_MILLIWATT: u32 = 1000;
+/// Microwatts per watt.
+pub const MICROWATT_PER_WATT: u32 = 1_000_000;
+
+/// Absolute zero, in millidegrees Celsius.
+pub const ABSOLUTE_ZERO_MILLICELSIUS: i64 = -273_150;
+
+/// Converts a value in milli units to micro units.
+#[inline]
+pub const fn milli_to_micro(v: i64) -> i64 {
+    v * (MICRO / MILLI)
+}
+
+/// Converts a value in micro units to milli units, rounding towards zero.
+#[inline]
+pub const fn micro_to_milli(v: i64) -> i64 {
+    v / (MICRO / MILLI)
+}
+
+/// Converts millikelvin to millidegrees Celsius.
+#[inline]
+pub const fn milli_kelvin_to_millicelsius(t: i64) -> i64 {
+    t + ABSOLUTE_ZERO_MILLICELSIUS
+}
+
+/// Converts millidegrees Celsius to millikelvin.
+#[inline]
+pub const fn millicelsius_to_milli_kelvin(t: i64) -> i64 {
+    t - ABSOLUTE_ZERO_MILLICELSIUS
+}
+
+/// Converts kelvin to millidegrees Celsius.
+#[inline]
+pub const fn kelvin_to_millicelsius(t: i64) -> i64 {
+    milli_kelvin_to_millicelsius(t * MILLI)
+}
+
+/// Converts millidegrees Celsius to kelvin, rounding to the closest value.
+#[inline]
+pub const fn millicelsius_to_kelvin(t: i64) -> i64 {
+    div_round_closest(millicelsius_to_milli_kelvin(t), MILLI)
+}
+
+/// Converts decikelvin, as used by e.g. ACPI and battery fuel gauges, to millidegrees Celsius.
+#[inline]
+pub const fn deci_kelvin_to_millicelsius(t: i64) -> i64 {
+    milli_kelvin_to_millicelsius(t * (MILLI / 10))
+}
+
+/// Converts millidegrees Celsius to decikelvin, rounding to the closest value.
+#[inline]
+pub const fn millicelsius_to_deci_kelvin(t: i64) -> i64 {
+    div_round_closest(millicelsius_to_milli_kelvin(t), MILLI / 10)
+}
+
+/// Divides `n` by `d`, rounding to the closest value, with halves rounded away from zero.
+///
+/// Equivalent to the C `DIV_ROUND_CLOSEST` macro for signed values. `d` must be positive.
+#[inline]
+pub const fn div_round_closest(n: i64, d: i64) -> i64 {
+    if n >= 0 {
+        (n + d / 2) / d
+    } else {
+        (n - d / 2) / d
+    }
+}
+
+/// A signed binary fixed-point number with `FRAC` fractional bits.
+///
+/// This is meant for intermediate results of sensor value conversions, e.g. applying calibration
+/// coefficients, before they are reported as integers or as integer plus micro parts. `FRAC` can
+/// be at most 32, which keeps all conversions within 64-bit arithmetic.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Fixed<const FRAC: u32>(i64);
+
+impl<const FRAC: u32> Fixed<FRAC> {
+    const ONE: i64 = {
+        assert!(FRAC <= 32);
+        1 << FRAC
+    };
+
+    /// Creates a fixed-point number from its raw representation, i.e., the value times
+    /// `2^FRAC`.
+    pub const fn from_raw(raw: i64) -> Self {
+        Self(raw)
+    }
+
+    /// Returns the raw representation of the number.
+    pub const fn to_raw(self) -> i64 {
+        self.0
+    }
+
+    /// Creates a fixed-point number from an integer, returning [`None`] on overflow.
+    pub const fn from_int(v: i64) -> Option<Self> {
+        match v.checked_mul(Self::ONE) {
+            Some(raw) => Some(Self(raw)),
+            None => None,
+        }
+    }
+
+    /// Creates a fixed-point number from the ratio `num / den`, rounding towards zero.
+    ///
+    /// Returns [`None`] if `den` is zero or on overflow.
+    pub const fn from_ratio(num: i64, den: i64) -> Option<Self> {
+        match num.checked_mul(Self::ONE) {
+            Some(n) => match n.checked_div(den) {
+                Some(raw) => Some(Self(raw)),
+                None => None,
+            },
+            None => None,
+        }
+    }
+
+    /// Returns the integer part, rounding towards negative infinity.
+    pub const fn floor(self) -> i64 {
+        self.0 >> FRAC
+    }
+
+    /// Returns the integer part, rounding towards zero.
+    pub const fn trunc(self) -> i64 {
+        self.0 / Self::ONE
+    }
+
+    /// Returns the closest integer, with halves rounded away from zero.
+    pub const fn round(self) -> i64 {
+        div_round_closest(self.0, Self::ONE)
+    }
+
+    /// Adds two numbers, returning [`None`] on overflow.
+    pub const fn checked_add(self, other: Self) -> Option<Self> {
+        match self.0.checked_add(other.0) {
+            Some(raw) => Some(Self(raw)),
+            None => None,
+        }
+    }
+
+    /// Subtracts `other` from `self`, returning [`None`] on overflow.
+    pub const fn checked_sub(self, other: Self) -> Option<Self> {
+        match self.0.checked_sub(other.0) {
+            Some(raw) => Some(Self(raw)),
+            None => None,
+        }
+    }
+
+    /// Multiplies two numbers, rounding towards zero, returning [`None`] on overflow.
+    ///
+    /// The intermediate product must fit in 64 bits.
+    pub const fn checked_mul(self, other: Self) -> Option<Self> {
+        match self.0.checked_mul(other.0) {
+            Some(p) => Some(Self(p / Self::ONE)),
+            None => None,
+        }
+    }
+
+    /// Divides `self` by `other`, rounding towards zero.
+    ///
+    /// Returns [`None`] if `other` is zero or on overflow.
+    pub const fn checked_div(self, other: Self) -> Option<Self> {
+        Self::from_ratio(self.0, other.0)
+    }
+
+    /// Multiplies the number by an integer, returning [`None`] on overflow.
+    pub const fn mul_int(self, v: i64) -> Option<Self> {
+        match self.0.checked_mul(v) {
+            Some(raw) => Some(Self(raw)),
+            None => None,
+        }
+    }
+
+    /// Returns the number scaled by `scale`, as an integer part and a fractional part in units of
+    /// `1 / scale`, both rounded towards zero.
+    const fn split(self, scale: i64) -> (i64, i64) {
+        let int = self.trunc();
+        // `frac` is below `2^32` in magnitude, so the multiplication cannot overflow for the
+        // scales used below.
+        let frac = self.0 - int * Self::ONE;
+        (int, frac * scale / Self::ONE)
+    }
+
+    /// Returns the value as an integer part and a micro part, both rounded towards zero and with
+    /// the same sign.
+    ///
+    /// This is the representation IIO uses for `IIO_VAL_INT_PLUS_MICRO`.
+    pub const fn to_int_plus_micro(self) -> (i64, i64) {
+        self.split(MICRO)
+    }
+
+    /// Returns the value in milli units, rounded towards zero.
+    ///
+    /// Returns [`None`] on overflow.
+    pub const fn to_milli(self) -> Option<i64> {
+        let (int, milli) = self.split(MILLI);
+        match int.checked_mul(MILLI) {
+            Some(v) => v.checked_add(milli),
+            None => None,
+        }
+    }
+}
+
+/// Formats the number with six decimal places.
+impl<const FRAC: u32> fmt::Display for Fixed<FRAC> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (int, micro) = self.to_int_plus_micro();
+        let sign = if int == 0 && micro < 0 { "-" } else { "" };
+        write!(f, "{}{}.{:06}", sign, int, micro.unsigned_abs())
+    }
+}
@@ -0,0 +1,159 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! VFIO devices.
+//!
+//! VFIO exposes devices to user space, usually a VMM that assigns them to a virtual machine, as a
+//! file with regions that can be read, written and mapped, and with interrupts that are signalled
+//! through eventfds. Mediated devices ([`mdev`]) are virtual devices implemented by a driver on
+//! top of a physical device whose resources they share, e.g., a partition of an accelerator or a
+//! virtual serial port.
+//!
+//! Regions are laid out as for `vfio-pci`: region `i` starts at offset [`region_offset`]`(i)` of
+//! the device file.
+//!
+//! C header: [`include/linux/vfio.h`](srctree/include/linux/vfio.h)
+
+use crate::{bindings, error::to_result, prelude::*};
+use core::ptr::NonNull;
+
+#[cfg(CONFIG_VFIO_MDEV)]
+pub mod mdev;
+
+/// Device APIs, i.e., the kind of device that user space sees.
+pub mod api {
+    use crate::{c_str, str::CStr};
+
+    /// A PCI device, whose regions are the BARs and the configuration space.
+    pub const PCI: &CStr = c_str!("vfio-pci");
+
+    /// A platform device, whose regions are its memory resources.
+    pub const PLATFORM: &CStr = c_str!("vfio-platform");
+
+    /// An AMBA device.
+    pub const AMBA: &CStr = c_str!("vfio-amba");
+}
+
+/// Device flags, to be combined in `mdev::Driver::FLAGS`.
+pub mod flags {
+    use crate::bindings;
+
+    /// The device is a PCI device.
+    pub const PCI: u32 = bindings::VFIO_DEVICE_FLAGS_PCI;
+
+    /// The device is a platform device.
+    pub const PLATFORM: u32 = bindings::VFIO_DEVICE_FLAGS_PLATFORM;
+
+    /// The device is an AMBA device.
+    pub const AMBA: u32 = bindings::VFIO_DEVICE_FLAGS_AMBA;
+}
+
+/// Region flags, to be combined in [`RegionInfo::flags`].
+pub mod region {
+    use crate::bindings;
+
+    /// The region can be read.
+    pub const READ: u32 = bindings::VFIO_REGION_INFO_FLAG_READ;
+
+    /// The region can be written.
+    pub const WRITE: u32 = bindings::VFIO_REGION_INFO_FLAG_WRITE;
+
+    /// The region can be mapped.
+    pub const MMAP: u32 = bindings::VFIO_REGION_INFO_FLAG_MMAP;
+}
+
+/// Number of bits of the offset within a region, in offsets of the device file.
+const REGION_SHIFT: u32 = 40;
+
+/// Returns the offset of region `index` in the device file.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::vfio::region_offset;
+///
+/// assert_eq!(region_offset(0), 0);
+/// assert_eq!(region_offset(2), 0x200_0000_0000);
+/// ```
+pub fn region_offset(index: u32) -> u64 {
+    u64::from(index) << REGION_SHIFT
+}
+
+/// Splits an offset of the device file into a region index and an offset within the region.
+pub(crate) fn split_offset(pos: u64) -> (u32, u64) {
+    (
+        (pos >> REGION_SHIFT) as u32,
+        pos & ((1 << REGION_SHIFT) - 1),
+    )
+}
+
+/// The description of a region of a device.
+#[derive(Clone, Copy, Debug)]
+pub struct RegionInfo {
+    /// The size of the region in bytes, or zero if the device does not have it.
+    pub size: u64,
+    /// The accesses the region supports, see [`region`].
+    pub flags: u32,
+}
+
+/// A user space mapping of a region that is being set up.
+///
+/// # Invariants
+///
+/// `vma` is a valid VMA that is being set up by the `mmap` file operation of a VFIO device.
+pub struct MmapArea {
+    vma: NonNull<bindings::vm_area_struct>,
+}
+
+impl MmapArea {
+    /// Creates an area from a VMA.
+    ///
+    /// # Safety
+    ///
+    /// `vma` must be a valid VMA that is being set up by the `mmap` file operation of a VFIO
+    /// device, for the lifetime of the returned object.
+    pub(crate) unsafe fn from_raw(vma: NonNull<bindings::vm_area_struct>) -> Self {
+        // INVARIANT: Guaranteed by the safety requirements.
+        Self { vma }
+    }
+
+    /// Returns a raw pointer to the VMA.
+    pub fn as_raw(&self) -> *mut bindings::vm_area_struct {
+        self.vma.as_ptr()
+    }
+
+    /// Returns the length of the mapping in bytes.
+    pub fn len(&self) -> usize {
+        // SAFETY: By the type invariants, the VMA is valid.
+        let vma = unsafe { self.vma.as_ref() };
+        (vma.vm_end - vma.vm_start) as usize
+    }
+
+    /// Returns `true` if the mapping is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the offset of the mapping within its region, in bytes.
+    pub fn offset(&self) -> u64 {
+        // SAFETY: By the type invariants, the VMA is valid.
+        let pgoff = unsafe { self.vma.as_ref().vm_pgoff } as u64;
+        split_offset(pgoff << bindings::PAGE_SHIFT).1
+    }
+
+    /// Maps the physical pages starting at page frame `pfn` into the whole area.
+    ///
+    /// # Safety
+    ///
+    /// The pages must be safe to expose to user space, e.g., registers of the parent device that
+    /// belong to the mediated device, and must outlive the mapping, e.g., because they are only
+    /// freed once the device is released.
+    pub unsafe fn remap_pfn(&mut self, pfn: usize) -> Result {
+        // SAFETY: By the type invariants, the VMA is valid and being set up.
+        let (start, prot) = unsafe { (self.vma.as_ref().vm_start, self.vma.as_ref().vm_page_prot) };
+        // SAFETY: The VMA is valid, and the range covers it exactly. The pages can be exposed by
+        // the safety requirements.
+        to_result(unsafe {
+            bindings::remap_pfn_range(self.as_raw(), start, pfn as _, self.len(), prot)
+        })
+    }
+}
@@ -0,0 +1,778 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Mediated devices.
+//!
+//! A driver whose device can be shared registers as the parent of mediated device types with
+//! [`Registration::register`]. User space creates instances of a type by writing a UUID to
+//! `/sys/class/mdev_bus/<parent>/mdev_supported_types/<type>/create`; each instance is a VFIO
+//! device backed by a [`Driver::Device`], which the driver creates in [`Driver::create`].
+//!
+//! The VFIO device file is handled here: the information ioctls are answered from
+//! [`Driver::NUM_REGIONS`], [`Driver::region_info`], [`Driver::NUM_IRQS`] and
+//! [`Driver::irq_count`]; reads, writes and mappings at [`region_offset`]`(i) + off` go to the
+//! callbacks of the driver for region `i`; and the eventfds that user space sets as interrupt
+//! triggers with `VFIO_DEVICE_SET_IRQS` are handed to [`Driver::set_irq_trigger`].
+//!
+//! Devices use the emulated IOMMU, i.e., they do not access guest memory through DMA.
+//!
+//! C header: [`include/linux/mdev.h`](srctree/include/linux/mdev.h)
+//!
+//! [`region_offset`]: super::region_offset
+
+use super::{split_offset, MmapArea, RegionInfo};
+use crate::{
+    bindings,
+    device::RawDevice,
+    error::{from_err_ptr, from_result, to_result, VTABLE_DEFAULT_ERROR},
+    eventfd::EventFd,
+    ioctl::_IO,
+    prelude::*,
+    str::CStr,
+    types::{ForeignOwnable, Opaque},
+    ThisModule,
+};
+use core::{
+    ffi::{c_int, c_long, c_uint, c_ulong, c_void},
+    marker::PhantomData,
+    mem::{size_of, MaybeUninit},
+    ptr::{self, NonNull},
+};
+
+/// Largest read or write passed to the driver at once; larger accesses are short.
+const MAX_ACCESS: usize = bindings::PAGE_SIZE as usize;
+
+const VFIO_TYPE: u32 = b';' as u32;
+const VFIO_DEVICE_GET_INFO: u32 = _IO(VFIO_TYPE, bindings::VFIO_BASE + 7);
+const VFIO_DEVICE_GET_REGION_INFO: u32 = _IO(VFIO_TYPE, bindings::VFIO_BASE + 8);
+const VFIO_DEVICE_GET_IRQ_INFO: u32 = _IO(VFIO_TYPE, bindings::VFIO_BASE + 9);
+const VFIO_DEVICE_SET_IRQS: u32 = _IO(VFIO_TYPE, bindings::VFIO_BASE + 10);
+const VFIO_DEVICE_RESET: u32 = _IO(VFIO_TYPE, bindings::VFIO_BASE + 11);
+
+/// A type of mediated devices, listed in `mdev_supported_types`.
+pub struct Type {
+    sysfs_name: &'static CStr,
+    pretty_name: &'static CStr,
+}
+
+impl Type {
+    /// Creates a type with the directory name `sysfs_name`, which the mdev core prefixes with the
+    /// name of the driver, and the description `pretty_name`.
+    pub const fn new(sysfs_name: &'static CStr, pretty_name: &'static CStr) -> Self {
+        Self {
+            sysfs_name,
+            pretty_name,
+        }
+    }
+}
+
+/// A driver of mediated devices.
+#[vtable]
+pub trait Driver {
+    /// The data of the parent, shared by all its mediated devices.
+    type Data: ForeignOwnable + Send + Sync;
+
+    /// The state of one mediated device.
+    type Device: Send + Sync;
+
+    /// The device API, see [`super::api`].
+    const DEVICE_API: &'static CStr;
+
+    /// The device flags, see [`super::flags`].
+    ///
+    /// `VFIO_DEVICE_FLAGS_RESET` is added if the driver implements [`Driver::reset`].
+    const FLAGS: u32;
+
+    /// The maximum number of instances of each type.
+    const MAX_INSTANCES: u32;
+
+    /// The number of regions of the devices.
+    const NUM_REGIONS: u32;
+
+    /// The number of interrupt types of the devices, e.g., `VFIO_PCI_NUM_IRQS` for PCI devices.
+    const NUM_IRQS: u32 = 0;
+
+    /// Creates the state of a new instance of the type at `type_index` of the types passed to
+    /// [`Registration::register`].
+    fn create(
+        data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        type_index: usize,
+    ) -> Result<Self::Device>;
+
+    /// Called when user space opens the device for the first time.
+    fn open(_dev: &Self::Device) -> Result {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+
+    /// Called when user space closes the device for the last time.
+    ///
+    /// The interrupt triggers that are still set should be dropped here.
+    fn close(_dev: &Self::Device) {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+
+    /// Describes region `index`, which is smaller than [`Driver::NUM_REGIONS`].
+    fn region_info(dev: &Self::Device, index: u32) -> Result<RegionInfo>;
+
+    /// Reads from region `index`, at `offset` within the region, and returns the number of bytes
+    /// read.
+    fn read(dev: &Self::Device, index: u32, offset: u64, buf: &mut [u8]) -> Result<usize>;
+
+    /// Writes to region `index`, at `offset` within the region, and returns the number of bytes
+    /// written.
+    fn write(dev: &Self::Device, index: u32, offset: u64, buf: &[u8]) -> Result<usize>;
+
+    /// Maps region `index` into `area`, e.g., with [`MmapArea::remap_pfn`].
+    fn mmap(_dev: &Self::Device, _index: u32, _area: &mut MmapArea) -> Result {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+
+    /// Returns the number of interrupts of type `index`, which is smaller than
+    /// [`Driver::NUM_IRQS`].
+    fn irq_count(_dev: &Self::Device, _index: u32) -> u32 {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+
+    /// Sets the eventfd to signal for interrupt `vector` of type `index`, or, if `trigger` is
+    /// [`None`], disables the interrupt.
+    fn set_irq_trigger(
+        _dev: &Self::Device,
+        _index: u32,
+        _vector: u32,
+        _trigger: Option<EventFd>,
+    ) -> Result {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+
+    /// Resets the device.
+    fn reset(_dev: &Self::Device) -> Result {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+}
+
+struct Inner {
+    driver: Opaque<bindings::mdev_driver>,
+    parent: Opaque<bindings::mdev_parent>,
+    ops: bindings::vfio_device_ops,
+    types: Vec<Opaque<bindings::mdev_type>>,
+    type_ptrs: Vec<*mut bindings::mdev_type>,
+    data: *const c_void,
+}
+
+/// A VFIO device of a mediated device, allocated by `_vfio_alloc_device`.
+#[repr(C)]
+struct VfioDevice<T: Driver> {
+    // Must be the first field, as required by `_vfio_alloc_device`.
+    vdev: Opaque<bindings::vfio_device>,
+    dev: MaybeUninit<T::Device>,
+}
+
+/// The registration of a parent of mediated devices.
+///
+/// The mediated devices are removed, and the types unregistered, when the registration is
+/// dropped.
+///
+/// # Invariants
+///
+/// `inner.driver` is a registered mdev driver, and `inner.parent` a parent registered with it,
+/// for the types of `inner.types`. `inner.data` was obtained from
+/// [`ForeignOwnable::into_foreign`] on a `T::Data`.
+///
+/// # Examples
+///
+/// ```
+/// use core::sync::atomic::{AtomicU32, Ordering};
+/// use kernel::{
+///     c_str,
+///     device::RawDevice,
+///     prelude::*,
+///     vfio::{self, mdev, RegionInfo},
+/// };
+///
+/// /// A device with a single 4-byte scratch register.
+/// struct Scratch {
+///     value: AtomicU32,
+/// }
+///
+/// struct MyMdev;
+///
+/// #[vtable]
+/// impl mdev::Driver for MyMdev {
+///     type Data = ();
+///     type Device = Scratch;
+///
+///     const DEVICE_API: &'static CStr = vfio::api::PLATFORM;
+///     const FLAGS: u32 = vfio::flags::PLATFORM;
+///     const MAX_INSTANCES: u32 = 4;
+///     const NUM_REGIONS: u32 = 1;
+///
+///     fn create(_data: (), _type_index: usize) -> Result<Scratch> {
+///         Ok(Scratch { value: AtomicU32::new(0) })
+///     }
+///
+///     fn region_info(_dev: &Scratch, _index: u32) -> Result<RegionInfo> {
+///         Ok(RegionInfo {
+///             size: 4,
+///             flags: vfio::region::READ | vfio::region::WRITE,
+///         })
+///     }
+///
+///     fn read(dev: &Scratch, _index: u32, offset: u64, buf: &mut [u8]) -> Result<usize> {
+///         if offset != 0 || buf.len() != 4 {
+///             return Err(EINVAL);
+///         }
+///         buf.copy_from_slice(&dev.value.load(Ordering::Relaxed).to_le_bytes());
+///         Ok(4)
+///     }
+///
+///     fn write(dev: &Scratch, _index: u32, offset: u64, buf: &[u8]) -> Result<usize> {
+///         let bytes: [u8; 4] = buf.try_into().map_err(|_| EINVAL)?;
+///         if offset != 0 {
+///             return Err(EINVAL);
+///         }
+///         dev.value.store(u32::from_le_bytes(bytes), Ordering::Relaxed);
+///         Ok(4)
+///     }
+/// }
+///
+/// static TYPES: [mdev::Type; 1] =
+///     [mdev::Type::new(c_str!("scratch"), c_str!("Scratch register"))];
+///
+/// fn register(
+///     dev: &impl RawDevice,
+///     module: &'static ThisModule,
+/// ) -> Result<mdev::Registration<MyMdev>> {
+///     mdev::Registration::register(dev, c_str!("my-mdev"), module, &TYPES, ())
+/// }
+/// ```
+pub struct Registration<T: Driver> {
+    inner: Pin<Box<Inner>>,
+    _p: PhantomData<T>,
+}
+
+impl<T: Driver> Registration<T> {
+    /// Registers `parent` as the parent of mediated devices of `types`, driven by a driver named
+    /// `name`.
+    pub fn register(
+        parent: &impl RawDevice,
+        name: &'static CStr,
+        module: &'static ThisModule,
+        types: &'static [Type],
+        data: T::Data,
+    ) -> Result<Self> {
+        if types.is_empty() {
+            return Err(EINVAL);
+        }
+
+        #[allow(unused_mut)]
+        let mut ops = bindings::vfio_device_ops {
+            name: name.as_char_ptr(),
+            release: Some(Adapter::<T>::release_callback),
+            open_device: if T::HAS_OPEN {
+                Some(Adapter::<T>::open_callback)
+            } else {
+                None
+            },
+            close_device: if T::HAS_CLOSE {
+                Some(Adapter::<T>::close_callback)
+            } else {
+                None
+            },
+            read: Some(Adapter::<T>::read_callback),
+            write: Some(Adapter::<T>::write_callback),
+            ioctl: Some(Adapter::<T>::ioctl_callback),
+            mmap: if T::HAS_MMAP {
+                Some(Adapter::<T>::mmap_callback)
+            } else {
+                None
+            },
+            // SAFETY: The remaining callbacks are optional, for which null is valid.
+            ..unsafe { core::mem::zeroed() }
+        };
+        #[cfg(CONFIG_IOMMUFD)]
+        {
+            ops.bind_iommufd = Some(bindings::vfio_iommufd_emulated_bind);
+            ops.unbind_iommufd = Some(bindings::vfio_iommufd_emulated_unbind);
+            ops.attach_ioas = Some(bindings::vfio_iommufd_emulated_attach_ioas);
+            ops.detach_ioas = Some(bindings::vfio_iommufd_emulated_detach_ioas);
+        }
+
+        let driver = bindings::mdev_driver {
+            device_api: T::DEVICE_API.as_char_ptr(),
+            max_instances: T::MAX_INSTANCES,
+            probe: Some(Adapter::<T>::probe_callback),
+            remove: Some(Adapter::<T>::remove_callback),
+            driver: bindings::device_driver {
+                name: name.as_char_ptr(),
+                owner: module.0,
+                // SAFETY: The remaining fields are optional or set by the driver core, for which
+                // zero is valid.
+                ..unsafe { core::mem::zeroed() }
+            },
+            // SAFETY: The remaining fields are optional, for which zero is valid.
+            ..unsafe { core::mem::zeroed() }
+        };
+
+        let mut mdev_types = Vec::with_capacity(types.len(), GFP_KERNEL)?;
+        let mut type_ptrs = Vec::with_capacity(types.len(), GFP_KERNEL)?;
+        for t in types {
+            mdev_types.push(
+                Opaque::new(bindings::mdev_type {
+                    sysfs_name: t.sysfs_name.as_char_ptr(),
+                    pretty_name: t.pretty_name.as_char_ptr(),
+                    // SAFETY: The remaining fields are set by the mdev core, and zero is valid
+                    // until then.
+                    ..unsafe { core::mem::zeroed() }
+                }),
+                GFP_KERNEL,
+            )?;
+        }
+        // The types are not moved after this: the vector is not resized.
+        for t in mdev_types.iter() {
+            type_ptrs.push(t.get(), GFP_KERNEL)?;
+        }
+
+        let mut inner = Box::new(
+            Inner {
+                driver: Opaque::new(driver),
+                // SAFETY: All zeroes is a valid parent that has not been registered.
+                parent: Opaque::new(unsafe { core::mem::zeroed() }),
+                ops,
+                types: mdev_types,
+                type_ptrs,
+                data: ptr::null(),
+            },
+            GFP_KERNEL,
+        )?;
+        inner.data = data.into_foreign();
+        let inner = Box::into_pin(inner);
+
+        // SAFETY: The driver is initialised, and it lives until it is unregistered in `drop`.
+        if let Err(e) = to_result(unsafe { bindings::mdev_register_driver(inner.driver.get()) }) {
+            // SAFETY: The data came from `into_foreign` above and no callback used it.
+            drop(unsafe { T::Data::from_foreign(inner.data) });
+            return Err(e);
+        }
+
+        // SAFETY: The parent, the registered driver and the types live until the parent is
+        // unregistered in `drop`. `parent.raw_device()` is valid.
+        let ret = unsafe {
+            bindings::mdev_register_parent(
+                inner.parent.get(),
+                parent.raw_device(),
+                inner.driver.get(),
+                inner.type_ptrs.as_ptr().cast_mut(),
+                inner.type_ptrs.len() as c_uint,
+            )
+        };
+        if let Err(e) = to_result(ret) {
+            // SAFETY: The driver was registered above, and no device was bound to it since the
+            // parent was not registered.
+            unsafe { bindings::mdev_unregister_driver(inner.driver.get()) };
+            // SAFETY: The data came from `into_foreign` above and no callback used it.
+            drop(unsafe { T::Data::from_foreign(inner.data) });
+            return Err(e);
+        }
+
+        // INVARIANT: The driver and the parent were registered above, with `inner.data`.
+        Ok(Self {
+            inner,
+            _p: PhantomData,
+        })
+    }
+}
+
+impl<T: Driver> Drop for Registration<T> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants, the parent and the driver are registered. Unregistering
+        // the parent removes its mediated devices, so no callbacks run after this.
+        unsafe {
+            bindings::mdev_unregister_parent(self.inner.parent.get());
+            bindings::mdev_unregister_driver(self.inner.driver.get());
+        }
+        // SAFETY: By the type invariants, the data came from `into_foreign`, and it is no longer
+        // used.
+        drop(unsafe { T::Data::from_foreign(self.inner.data) });
+    }
+}
+
+// SAFETY: The registration only holds a `T::Data`, which is `Send`, and the parent and driver,
+// which can be unregistered from any thread.
+unsafe impl<T: Driver> Send for Registration<T> {}
+
+// SAFETY: `Registration` has no methods that change state through `&self`.
+unsafe impl<T: Driver> Sync for Registration<T> {}
+
+/// Reads the fixed part, `minsz` bytes long, of the argument of a VFIO ioctl.
+///
+/// Fails with [`EINVAL`] if the size declared by user space in the `argsz` field, which all these
+/// arguments start with, is smaller than `minsz`.
+///
+/// # Safety
+///
+/// `S` must be a C structure starting with a `u32` and valid for all bit patterns, and `minsz`
+/// must not exceed its size.
+unsafe fn read_arg<S>(arg: c_ulong, minsz: usize) -> Result<S> {
+    // SAFETY: `S` is valid for all bit patterns by the safety requirements.
+    let mut s = unsafe { MaybeUninit::<S>::zeroed().assume_init() };
+    // SAFETY: `s` is valid for writes of `minsz` bytes, and `copy_from_user` checks the user
+    // pointer.
+    let left = unsafe {
+        bindings::copy_from_user((&mut s as *mut S).cast(), arg as *const c_void, minsz as _)
+    };
+    if left != 0 {
+        return Err(EFAULT);
+    }
+    // SAFETY: `S` starts with a `u32` by the safety requirements.
+    let argsz = unsafe { *(&s as *const S).cast::<u32>() };
+    if (argsz as usize) < minsz {
+        return Err(EINVAL);
+    }
+    Ok(s)
+}
+
+/// Writes the fixed part, `minsz` bytes long, of the argument of a VFIO ioctl back.
+///
+/// # Safety
+///
+/// `minsz` must not exceed the size of `S`.
+unsafe fn write_arg<S>(arg: c_ulong, s: &S, minsz: usize) -> Result {
+    // SAFETY: `s` is valid for reads of `minsz` bytes, and `copy_to_user` checks the user
+    // pointer.
+    let left =
+        unsafe { bindings::copy_to_user(arg as *mut c_void, (s as *const S).cast(), minsz as _) };
+    if left != 0 {
+        return Err(EFAULT);
+    }
+    Ok(())
+}
+
+/// Returns the offset of the end of the `u32` or `u64` `$field` of `$ty`, i.e., the `minsz` of
+/// VFIO ioctls.
+macro_rules! minsz {
+    ($ty:ty, $field:ident: $fty:ty) => {
+        core::mem::offset_of!($ty, $field) + size_of::<$fty>()
+    };
+}
+
+/// Returns a zeroed buffer of `len` bytes.
+fn zeroed_buf(len: usize) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(len, GFP_KERNEL)?;
+    for _ in 0..len {
+        buf.push(0, GFP_KERNEL)?;
+    }
+    Ok(buf)
+}
+
+struct Adapter<T: Driver>(PhantomData<T>);
+
+impl<T: Driver> Adapter<T> {
+    /// # Safety
+    ///
+    /// `vdev` must be the VFIO device of a mediated device probed by [`Adapter::probe_callback`],
+    /// that has not been released.
+    unsafe fn device<'a>(vdev: *mut bindings::vfio_device) -> &'a T::Device {
+        // SAFETY: By the safety requirements, `vdev` is the first field of a `VfioDevice<T>`,
+        // whose `dev` was initialised right after it was allocated.
+        unsafe { (*vdev.cast::<VfioDevice<T>>()).dev.assume_init_ref() }
+    }
+
+    unsafe extern "C" fn probe_callback(mdev: *mut bindings::mdev_device) -> c_int {
+        from_result(|| {
+            // SAFETY: `mdev` is valid for the duration of the call.
+            let mtype = unsafe { (*mdev).type_ };
+            // SAFETY: The mdev core only probes devices of the types of parents registered with
+            // this driver, so the parent is the `parent` field of an `Inner`, which lives until
+            // the parent is unregistered.
+            let inner = unsafe { &*crate::container_of!((*mtype).parent, Inner, parent) };
+            let index = inner
+                .type_ptrs
+                .iter()
+                .position(|t| *t == mtype)
+                .ok_or(EINVAL)?;
+            // SAFETY: The data came from `into_foreign`, and it is only freed after the parent is
+            // unregistered, which removes the mediated devices first.
+            let dev = T::create(unsafe { T::Data::borrow(inner.data) }, index)?;
+
+            // SAFETY: `mdev` is valid, and `ops` lives as long as the parent, whose mediated
+            // devices are removed, and their VFIO devices released, before it is unregistered.
+            let vdev = from_err_ptr(unsafe {
+                bindings::_vfio_alloc_device(
+                    size_of::<VfioDevice<T>>(),
+                    &mut (*mdev).dev,
+                    &inner.ops,
+                )
+            })?;
+            // SAFETY: `vdev` was just allocated with the size of a `VfioDevice<T>`, of which it is
+            // the first field. `dev` is initialised before the device can be released.
+            unsafe {
+                ptr::addr_of_mut!((*vdev.cast::<VfioDevice<T>>()).dev)
+                    .cast::<T::Device>()
+                    .write(dev)
+            };
+
+            // SAFETY: `vdev` is allocated and initialised.
+            if let Err(e) = to_result(unsafe { bindings::vfio_register_emulated_iommu_dev(vdev) }) {
+                // SAFETY: This drops the only reference, which releases the device and `dev`.
+                unsafe { bindings::vfio_put_device(vdev) };
+                return Err(e);
+            }
+            // SAFETY: `mdev` is valid. The VFIO device is retrieved in `remove_callback`.
+            unsafe { bindings::dev_set_drvdata(&mut (*mdev).dev, vdev.cast()) };
+            Ok(0)
+        })
+    }
+
+    unsafe extern "C" fn remove_callback(mdev: *mut bindings::mdev_device) {
+        // SAFETY: `mdev` was probed successfully, so its driver data is its VFIO device.
+        let vdev = unsafe { bindings::dev_get_drvdata(&mut (*mdev).dev) }.cast();
+        // SAFETY: The VFIO device is registered. Unregistering it waits for user space to close
+        // it, so dropping the reference taken at allocation releases it.
+        unsafe {
+            bindings::vfio_unregister_group_dev(vdev);
+            bindings::vfio_put_device(vdev);
+        }
+    }
+
+    unsafe extern "C" fn release_callback(vdev: *mut bindings::vfio_device) {
+        // SAFETY: `vdev` is the first field of a `VfioDevice<T>` whose `dev` was initialised, and
+        // this is the last use of it; the VFIO core frees the allocation after this.
+        unsafe {
+            ptr::drop_in_place(
+                ptr::addr_of_mut!((*vdev.cast::<VfioDevice<T>>()).dev).cast::<T::Device>(),
+            )
+        };
+    }
+
+    unsafe extern "C" fn open_callback(vdev: *mut bindings::vfio_device) -> c_int {
+        from_result(|| {
+            // SAFETY: The VFIO core only calls this for registered devices.
+            T::open(unsafe { Self::device(vdev) })?;
+            Ok(0)
+        })
+    }
+
+    unsafe extern "C" fn close_callback(vdev: *mut bindings::vfio_device) {
+        // SAFETY: The VFIO core only calls this for registered devices.
+        T::close(unsafe { Self::device(vdev) });
+    }
+
+    /// Returns the region index and the offset within it of the access at `*ppos`.
+    ///
+    /// # Safety
+    ///
+    /// `ppos` must be valid for reads.
+    unsafe fn region_pos(ppos: *mut bindings::loff_t) -> Result<(u32, u64)> {
+        // SAFETY: By the safety requirements, `ppos` is valid for reads.
+        let pos = u64::try_from(unsafe { *ppos }).map_err(|_| EINVAL)?;
+        let (index, offset) = split_offset(pos);
+        if index >= T::NUM_REGIONS {
+            return Err(EINVAL);
+        }
+        Ok((index, offset))
+    }
+
+    unsafe extern "C" fn read_callback(
+        vdev: *mut bindings::vfio_device,
+        buf: *mut core::ffi::c_char,
+        count: usize,
+        ppos: *mut bindings::loff_t,
+    ) -> isize {
+        from_result(|| {
+            // SAFETY: `ppos` is valid for the duration of the call.
+            let (index, offset) = unsafe { Self::region_pos(ppos)? };
+            let mut kbuf = zeroed_buf(count.min(MAX_ACCESS))?;
+            // SAFETY: The VFIO core only calls this for registered devices.
+            let n = T::read(unsafe { Self::device(vdev) }, index, offset, &mut kbuf)?;
+            let n = n.min(kbuf.len());
+            // SAFETY: `kbuf` is valid for reads of `n` bytes, and `copy_to_user` checks `buf`.
+            if unsafe { bindings::copy_to_user(buf.cast(), kbuf.as_ptr().cast(), n as _) } != 0 {
+                return Err(EFAULT);
+            }
+            // SAFETY: `ppos` is valid for the duration of the call.
+            unsafe { *ppos += n as bindings::loff_t };
+            Ok(n as isize)
+        })
+    }
+
+    unsafe extern "C" fn write_callback(
+        vdev: *mut bindings::vfio_device,
+        buf: *const core::ffi::c_char,
+        count: usize,
+        ppos: *mut bindings::loff_t,
+    ) -> isize {
+        from_result(|| {
+            // SAFETY: `ppos` is valid for the duration of the call.
+            let (index, offset) = unsafe { Self::region_pos(ppos)? };
+            let mut kbuf = zeroed_buf(count.min(MAX_ACCESS))?;
+            // SAFETY: `kbuf` is valid for writes of its length, and `copy_from_user` checks
+            // `buf`.
+            let left = unsafe {
+                bindings::copy_from_user(kbuf.as_mut_ptr().cast(), buf.cast(), kbuf.len() as _)
+            };
+            if left != 0 {
+                return Err(EFAULT);
+            }
+            // SAFETY: The VFIO core only calls this for registered devices.
+            let n = T::write(unsafe { Self::device(vdev) }, index, offset, &kbuf)?;
+            let n = n.min(kbuf.len());
+            // SAFETY: `ppos` is valid for the duration of the call.
+            unsafe { *ppos += n as bindings::loff_t };
+            Ok(n as isize)
+        })
+    }
+
+    unsafe extern "C" fn mmap_callback(
+        vdev: *mut bindings::vfio_device,
+        vma: *mut bindings::vm_area_struct,
+    ) -> c_int {
+        from_result(|| {
+            let vma = NonNull::new(vma).ok_or(EINVAL)?;
+            // SAFETY: `vma` is valid for the duration of the call.
+            let pgoff = unsafe { vma.as_ref().vm_pgoff } as u64;
+            let (index, _) = split_offset(pgoff << bindings::PAGE_SHIFT);
+            if index >= T::NUM_REGIONS {
+                return Err(EINVAL);
+            }
+            // SAFETY: `vma` is being set up by the `mmap` operation of the device.
+            let mut area = unsafe { MmapArea::from_raw(vma) };
+            // SAFETY: The VFIO core only calls this for registered devices.
+            T::mmap(unsafe { Self::device(vdev) }, index, &mut area)?;
+            Ok(0)
+        })
+    }
+
+    unsafe extern "C" fn ioctl_callback(
+        vdev: *mut bindings::vfio_device,
+        cmd: c_uint,
+        arg: c_ulong,
+    ) -> c_long {
+        from_result(|| {
+            // SAFETY: The VFIO core only calls this for registered devices.
+            let dev = unsafe { Self::device(vdev) };
+            match cmd {
+                VFIO_DEVICE_GET_INFO => Self::get_info(arg)?,
+                VFIO_DEVICE_GET_REGION_INFO => Self::get_region_info(dev, arg)?,
+                VFIO_DEVICE_GET_IRQ_INFO => Self::get_irq_info(dev, arg)?,
+                VFIO_DEVICE_SET_IRQS => Self::set_irqs(dev, arg)?,
+                VFIO_DEVICE_RESET if T::HAS_RESET => T::reset(dev)?,
+                _ => return Err(ENOTTY),
+            }
+            Ok(0)
+        })
+    }
+
+    fn get_info(arg: c_ulong) -> Result {
+        let minsz = minsz!(bindings::vfio_device_info, num_irqs: u32);
+        // SAFETY: `vfio_device_info` starts with `argsz`, and `minsz` is within it.
+        let mut info: bindings::vfio_device_info = unsafe { read_arg(arg, minsz)? };
+        info.flags = T::FLAGS;
+        if T::HAS_RESET {
+            info.flags |= bindings::VFIO_DEVICE_FLAGS_RESET;
+        }
+        info.num_regions = T::NUM_REGIONS;
+        info.num_irqs = T::NUM_IRQS;
+        // SAFETY: `minsz` is within `vfio_device_info`.
+        unsafe { write_arg(arg, &info, minsz) }
+    }
+
+    fn get_region_info(dev: &T::Device, arg: c_ulong) -> Result {
+        let minsz = minsz!(bindings::vfio_region_info, offset: u64);
+        // SAFETY: `vfio_region_info` starts with `argsz`, and `minsz` is within it.
+        let mut info: bindings::vfio_region_info = unsafe { read_arg(arg, minsz)? };
+        if info.index >= T::NUM_REGIONS {
+            return Err(EINVAL);
+        }
+        let region = T::region_info(dev, info.index)?;
+        info.offset = super::region_offset(info.index);
+        info.size = region.size;
+        info.flags = region.flags;
+        info.cap_offset = 0;
+        // SAFETY: `minsz` is within `vfio_region_info`.
+        unsafe { write_arg(arg, &info, minsz) }
+    }
+
+    fn irq_count(dev: &T::Device, index: u32) -> u32 {
+        if T::HAS_IRQ_COUNT && index < T::NUM_IRQS {
+            T::irq_count(dev, index)
+        } else {
+            0
+        }
+    }
+
+    fn get_irq_info(dev: &T::Device, arg: c_ulong) -> Result {
+        let minsz = minsz!(bindings::vfio_irq_info, count: u32);
+        // SAFETY: `vfio_irq_info` starts with `argsz`, and `minsz` is within it.
+        let mut info: bindings::vfio_irq_info = unsafe { read_arg(arg, minsz)? };
+        if info.index >= T::NUM_IRQS {
+            return Err(EINVAL);
+        }
+        info.count = Self::irq_count(dev, info.index);
+        info.flags = if info.count != 0 {
+            bindings::VFIO_IRQ_INFO_EVENTFD
+        } else {
+            0
+        };
+        // SAFETY: `minsz` is within `vfio_irq_info`.
+        unsafe { write_arg(arg, &info, minsz) }
+    }
+
+    fn set_irqs(dev: &T::Device, arg: c_ulong) -> Result {
+        let minsz = minsz!(bindings::vfio_irq_set, count: u32);
+        // SAFETY: `vfio_irq_set` starts with `argsz`, and `minsz` is within it.
+        let mut hdr: bindings::vfio_irq_set = unsafe { read_arg(arg, minsz)? };
+        let count = Self::irq_count(dev, hdr.index);
+        let mut data_size = 0;
+        // SAFETY: `hdr` and `data_size` are valid for the duration of the call.
+        to_result(unsafe {
+            bindings::vfio_set_irqs_validate_and_prepare(
+                &mut hdr,
+                count as c_int,
+                T::NUM_IRQS as c_int,
+                &mut data_size,
+            )
+        })?;
+        if !T::HAS_SET_IRQ_TRIGGER
+            || hdr.flags & bindings::VFIO_IRQ_SET_ACTION_TYPE_MASK
+                != bindings::VFIO_IRQ_SET_ACTION_TRIGGER
+        {
+            return Err(EINVAL);
+        }
+
+        match hdr.flags & bindings::VFIO_IRQ_SET_DATA_TYPE_MASK {
+            // Disables all the interrupts of the type.
+            bindings::VFIO_IRQ_SET_DATA_NONE if hdr.count == 0 => {
+                for vector in 0..count {
+                    T::set_irq_trigger(dev, hdr.index, vector, None)?;
+                }
+                Ok(())
+            }
+            bindings::VFIO_IRQ_SET_DATA_EVENTFD => {
+                let mut fds = zeroed_buf(data_size)?;
+                // SAFETY: `fds` is valid for writes of `data_size` bytes, and `copy_from_user`
+                // checks the user pointer, which is that of the data following the header.
+                let left = unsafe {
+                    bindings::copy_from_user(
+                        fds.as_mut_ptr().cast(),
+                        (arg as usize).wrapping_add(minsz) as *const c_void,
+                        data_size as _,
+                    )
+                };
+                if left != 0 {
+                    return Err(EFAULT);
+                }
+                for (i, fd) in fds.chunks_exact(size_of::<i32>()).enumerate() {
+                    let fd = i32::from_ne_bytes([fd[0], fd[1], fd[2], fd[3]]);
+                    // A negative file descriptor disables the interrupt.
+                    let trigger = if fd < 0 {
+                        None
+                    } else {
+                        Some(EventFd::from_fd(fd)?)
+                    };
+                    T::set_irq_trigger(dev, hdr.index, hdr.start + i as u32, trigger)?;
+                }
+                Ok(())
+            }
+            _ => Err(EINVAL),
+        }
+    }
+}
@@ -0,0 +1,211 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! General notification queue.
+//!
+//! Userspace creates a notification queue from a pipe opened with `O_NOTIFICATION_PIPE` and asks
+//! a driver, e.g., through an ioctl, to watch one of its objects. The driver then posts structured
+//! notifications about the object to all the queues that watch it, which userspace reads from the
+//! pipe, and can wait for with `poll`. A [`WatchList`] holds the watches of an object.
+//!
+//! C header: [`include/linux/watch_queue.h`](srctree/include/linux/watch_queue.h)
+
+use crate::{
+    bindings, error::from_err_ptr, error::to_result, new_spinlock, prelude::*, sync::SpinLock,
+    types::Opaque,
+};
+use core::marker::PhantomPinned;
+
+/// The maximum size of a notification, including its header, in bytes.
+pub const MAX_SIZE: usize = bindings::WATCH_INFO_LENGTH as usize;
+
+const HEADER_SIZE: usize = core::mem::size_of::<bindings::watch_notification>();
+
+/// A notification that can be posted to a [`WatchList`].
+pub trait Notification {
+    /// The type of the notifications, one of the `WATCH_TYPE_*` constants of the uapi.
+    const TYPE: u32;
+
+    /// Returns the subtype of the notification, whose meaning depends on [`Notification::TYPE`].
+    fn subtype(&self) -> u8;
+
+    /// Returns the payload of the notification, which follows its header.
+    ///
+    /// It can be at most [`MAX_SIZE`] bytes, minus the size of the header.
+    fn payload(&self) -> &[u8] {
+        &[]
+    }
+}
+
+/// The watches of an object, whose notifications are posted to the queues of the watches.
+///
+/// The watches are removed when this object is dropped, which userspace sees as a
+/// `WATCH_META_REMOVAL_NOTIFICATION` in the queues.
+///
+/// # Invariants
+///
+/// `list` is initialised with `init_watch_list`. Watches are only added to or removed from it
+/// with `lock` held.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{prelude::*, watch_queue::{Notification, WatchList}};
+///
+/// struct Overheat {
+///     celsius: u8,
+/// }
+///
+/// impl Notification for Overheat {
+///     // Stands in for a type allocated in the uapi.
+///     const TYPE: u32 = 0x100;
+///
+///     fn subtype(&self) -> u8 {
+///         0
+///     }
+///
+///     fn payload(&self) -> &[u8] {
+///         core::slice::from_ref(&self.celsius)
+///     }
+/// }
+///
+/// fn watch(sensor: &WatchList, id: u64, pipe_fd: i32) -> Result {
+///     sensor.add(pipe_fd, id, 0)
+/// }
+///
+/// fn overheated(sensor: &WatchList, id: u64, celsius: u8) -> Result {
+///     sensor.post(id, &Overheat { celsius })
+/// }
+/// ```
+#[pin_data(PinnedDrop)]
+pub struct WatchList {
+    #[pin]
+    list: Opaque<bindings::watch_list>,
+    #[pin]
+    lock: SpinLock<()>,
+    #[pin]
+    _pin: PhantomPinned,
+}
+
+impl WatchList {
+    /// Creates an empty list of watches.
+    pub fn new() -> impl PinInit<Self> {
+        pin_init!(Self {
+            // SAFETY: `slot` is valid for writes while the closure is called.
+            list <- Opaque::ffi_init(|slot| unsafe { bindings::init_watch_list(slot, None) }),
+            lock <- new_spinlock!(()),
+            _pin: PhantomPinned,
+        })
+    }
+
+    fn as_raw(&self) -> *mut bindings::watch_list {
+        self.list.get()
+    }
+
+    /// Adds a watch of the notification queue of the pipe `fd`.
+    ///
+    /// `id` identifies the object in the notifications, for queues that watch several objects,
+    /// and `tag` is copied into the `WATCH_INFO_ID` field of the notifications that go to this
+    /// queue, so that userspace can tell its watches apart. Fails with [`EBADF`] if `fd` is not a
+    /// notification pipe and with [`EBUSY`] if the queue already watches the object with `id`.
+    pub fn add(&self, fd: i32, id: u64, tag: u8) -> Result {
+        // SAFETY: `get_watch_queue` can be called with any file descriptor.
+        let wqueue = from_err_ptr(unsafe { bindings::get_watch_queue(fd) })?;
+        let ret = self.add_to(wqueue, id, tag);
+        // SAFETY: `wqueue` was returned by `get_watch_queue` above.
+        unsafe { bindings::put_watch_queue(wqueue) };
+        ret
+    }
+
+    fn add_to(&self, wqueue: *mut bindings::watch_queue, id: u64, tag: u8) -> Result {
+        let mut watch = <Box<_> as BoxExt<_>>::new_uninit(GFP_KERNEL)?;
+        let w: *mut bindings::watch = watch.as_mut_ptr();
+        // SAFETY: `w` is valid for writes, and `wqueue` is a valid queue.
+        unsafe {
+            w.write(core::mem::zeroed());
+            bindings::init_watch(w, wqueue);
+            (*w).id = id;
+            (*w).info_id = u32::from(tag) << bindings::WATCH_INFO_ID__SHIFT;
+        }
+        let _guard = self.lock.lock();
+        // SAFETY: `w` is initialised above. The list is pinned by `self` and `wqueue` by the
+        // caller, and `lock` is held, which `add_watch_to_object` requires to serialise the
+        // duplicate check and the insertion against other additions and removals. On success,
+        // the watch takes a reference to `wqueue`, and the list owns the watch, which it frees
+        // when the watch is removed.
+        to_result(unsafe { bindings::add_watch_to_object(w, self.as_raw()) })?;
+        let _ = Box::into_raw(watch);
+        Ok(())
+    }
+
+    /// Removes the watch of the notification queue of the pipe `fd` on the object with `id`.
+    ///
+    /// Fails with [`EBADF`] if `fd` is not a notification pipe and with [`EBADSLT`] if the queue
+    /// does not watch the object.
+    pub fn remove(&self, fd: i32, id: u64) -> Result {
+        // SAFETY: `get_watch_queue` can be called with any file descriptor.
+        let wqueue = from_err_ptr(unsafe { bindings::get_watch_queue(fd) })?;
+        let guard = self.lock.lock();
+        // SAFETY: The list is initialised, `wqueue` is a valid queue, and `lock` is held, so no
+        // watch is added concurrently.
+        let ret = to_result(unsafe {
+            bindings::remove_watch_from_object(self.as_raw(), wqueue, id, false)
+        });
+        drop(guard);
+        // SAFETY: `wqueue` was returned by `get_watch_queue` above.
+        unsafe { bindings::put_watch_queue(wqueue) };
+        ret
+    }
+
+    /// Posts `n` to all the queues that watch the object with `id`.
+    ///
+    /// Queues that are full drop the notification and report the loss to userspace. Fails with
+    /// [`E2BIG`] if the payload of `n` is too large.
+    ///
+    /// This does not sleep, but must not be called from a hard interrupt handler.
+    pub fn post<N: Notification>(&self, id: u64, n: &N) -> Result {
+        #[repr(C)]
+        struct Buf {
+            hdr: bindings::watch_notification,
+            payload: [u8; MAX_SIZE - HEADER_SIZE],
+        }
+
+        let payload = n.payload();
+        if payload.len() > MAX_SIZE - HEADER_SIZE {
+            return Err(E2BIG);
+        }
+        // SAFETY: All zeroes is a valid value of both fields.
+        let mut buf: Buf = unsafe { core::mem::zeroed() };
+        buf.hdr.set_type(N::TYPE);
+        buf.hdr.set_subtype(n.subtype().into());
+        buf.hdr.info = (HEADER_SIZE + payload.len()) as u32;
+        buf.payload[..payload.len()].copy_from_slice(payload);
+        // SAFETY: The list is initialised, and `buf` starts with a notification whose length is
+        // within the buffer. The credentials of the current task are used to check whether the
+        // watchers may see the notification.
+        unsafe {
+            bindings::post_watch_notification(
+                self.as_raw(),
+                &mut buf.hdr,
+                bindings::current_cred(),
+                id,
+            )
+        };
+        Ok(())
+    }
+}
+
+#[pinned_drop]
+impl PinnedDrop for WatchList {
+    fn drop(self: Pin<&mut Self>) {
+        // SAFETY: By the type invariants, the list is initialised. All its watches are removed
+        // and freed, and their queues notified.
+        unsafe { bindings::remove_watch_list(self.as_raw(), 0) };
+    }
+}
+
+// SAFETY: The list of watches can be used and torn down from any thread.
+unsafe impl Send for WatchList {}
+
+// SAFETY: Additions and removals of watches are serialised by `lock`, and posting is protected by
+// RCU and the locks of the queues.
+unsafe impl Sync for WatchList {}
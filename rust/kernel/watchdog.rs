@@ -0,0 +1,459 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Watchdog devices.
+//!
+//! Watchdog drivers implement [`Operations`] and register a watchdog device with
+//! [`Registration::register`]. The watchdog core exposes it to user space as `/dev/watchdogN`.
+//!
+//! The core can keep pinging the hardware on behalf of user space: when the maximum hardware
+//! timeout ([`Config::max_hw_heartbeat_ms`]) is shorter than the timeout requested by user space,
+//! and while the device is not opened if the hardware watchdog is already running
+//! ([`Config::hw_running`]). Drivers whose hardware raises an interrupt some time before the
+//! watchdog expires call [`Device::notify_pretimeout`] from their interrupt handler, which hands
+//! the event to the pretimeout governor selected in sysfs (e.g., `panic` or `noop`).
+//!
+//! C header: [`include/linux/watchdog.h`](srctree/include/linux/watchdog.h)
+
+use crate::{
+    bindings,
+    device::RawDevice,
+    error::{from_result, to_result, VTABLE_DEFAULT_ERROR},
+    prelude::*,
+    types::{ForeignOwnable, Opaque},
+};
+use core::{ffi::c_uint, marker::PhantomData};
+
+/// Options a watchdog can support, to be combined in [`Operations::OPTIONS`].
+pub mod options {
+    use crate::bindings;
+
+    /// The timeout can be changed.
+    pub const SETTIMEOUT: u32 = bindings::WDIOF_SETTIMEOUT;
+
+    /// The watchdog is only stopped when user space writes `V` before closing the device.
+    pub const MAGICCLOSE: u32 = bindings::WDIOF_MAGICCLOSE;
+
+    /// The watchdog can be pinged.
+    pub const KEEPALIVEPING: u32 = bindings::WDIOF_KEEPALIVEPING;
+
+    /// The watchdog supports a pretimeout.
+    pub const PRETIMEOUT: u32 = bindings::WDIOF_PRETIMEOUT;
+}
+
+/// Operations of a watchdog device.
+///
+/// Timeouts are in seconds.
+#[vtable]
+pub trait Operations {
+    /// The context data made available to the callbacks.
+    type Data: ForeignOwnable + Send + Sync;
+
+    /// The identity of the watchdog reported to user space, at most 31 bytes long.
+    const IDENTITY: &'static str;
+
+    /// The supported options, a combination of the constants in [`options`].
+    const OPTIONS: u32;
+
+    /// Starts the watchdog.
+    fn start(data: <Self::Data as ForeignOwnable>::Borrowed<'_>, wdd: &Device) -> Result;
+
+    /// Stops the watchdog.
+    ///
+    /// If this is not implemented, the watchdog cannot be stopped once started and the core pings
+    /// it until the system reboots.
+    fn stop(_data: <Self::Data as ForeignOwnable>::Borrowed<'_>, _wdd: &Device) -> Result {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+
+    /// Pings the watchdog.
+    ///
+    /// If this is not implemented, the core calls [`Operations::start`] instead.
+    fn ping(_data: <Self::Data as ForeignOwnable>::Borrowed<'_>, _wdd: &Device) -> Result {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+
+    /// Sets the timeout of the watchdog.
+    ///
+    /// On success, the timeout of the device is updated to `timeout`.
+    fn set_timeout(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _wdd: &Device,
+        _timeout: u32,
+    ) -> Result {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+
+    /// Sets the pretimeout of the watchdog, i.e., how many seconds before the timeout the
+    /// pretimeout interrupt is raised. Zero disables it.
+    ///
+    /// On success, the pretimeout of the device is updated to `pretimeout`.
+    fn set_pretimeout(
+        _data: <Self::Data as ForeignOwnable>::Borrowed<'_>,
+        _wdd: &Device,
+        _pretimeout: u32,
+    ) -> Result {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+
+    /// Returns the number of seconds left before the watchdog resets the system.
+    fn get_timeleft(_data: <Self::Data as ForeignOwnable>::Borrowed<'_>, _wdd: &Device) -> u32 {
+        kernel::build_error(VTABLE_DEFAULT_ERROR)
+    }
+}
+
+/// A watchdog device.
+///
+/// # Invariants
+///
+/// The pointer is valid, and the device is registered by [`Registration::register`].
+#[repr(transparent)]
+pub struct Device(Opaque<bindings::watchdog_device>);
+
+impl Device {
+    /// # Safety
+    ///
+    /// `ptr` must point to a device registered by [`Registration::register`] that remains
+    /// registered for the lifetime `'a`.
+    unsafe fn from_raw<'a>(ptr: *mut bindings::watchdog_device) -> &'a Self {
+        // SAFETY: Guaranteed by the safety requirements of the function.
+        unsafe { &*ptr.cast() }
+    }
+
+    fn as_raw(&self) -> *mut bindings::watchdog_device {
+        self.0.get()
+    }
+
+    /// Returns the current timeout, in seconds.
+    pub fn timeout(&self) -> u32 {
+        // SAFETY: By the type invariants, the pointer is valid.
+        unsafe { (*self.as_raw()).timeout }
+    }
+
+    /// Returns the current pretimeout, in seconds, or zero if it is disabled.
+    pub fn pretimeout(&self) -> u32 {
+        // SAFETY: By the type invariants, the pointer is valid.
+        unsafe { (*self.as_raw()).pretimeout }
+    }
+
+    /// Returns `true` if the watchdog was started, by user space or by the core.
+    pub fn is_active(&self) -> bool {
+        self.test_status(bindings::WDOG_ACTIVE)
+    }
+
+    /// Tells the core whether the hardware watchdog is running.
+    ///
+    /// While it is running and the device is not opened by user space, the core pings it. Drivers
+    /// set this from [`Operations::stop`] when the hardware cannot be stopped.
+    pub fn set_hw_running(&self, running: bool) {
+        // SAFETY: By the type invariants, the pointer is valid. The bit operations are atomic, so
+        // the status can be changed through a raw pointer while it is shared.
+        unsafe {
+            let status = core::ptr::addr_of_mut!((*self.as_raw()).status);
+            if running {
+                bindings::set_bit(bindings::WDOG_HW_RUNNING as _, status);
+            } else {
+                bindings::clear_bit(bindings::WDOG_HW_RUNNING as _, status);
+            }
+        }
+    }
+
+    /// Returns `true` if the hardware watchdog is running.
+    pub fn is_hw_running(&self) -> bool {
+        self.test_status(bindings::WDOG_HW_RUNNING)
+    }
+
+    fn test_status(&self, bit: u32) -> bool {
+        // SAFETY: By the type invariants, the pointer is valid. The bit operation is atomic.
+        unsafe { bindings::test_bit(bit as _, core::ptr::addr_of!((*self.as_raw()).status)) }
+    }
+
+    /// Notifies the pretimeout governor that the pretimeout has expired.
+    ///
+    /// This can be called from interrupt context.
+    pub fn notify_pretimeout(&self) {
+        // SAFETY: By the type invariants, the device is registered.
+        unsafe { bindings::watchdog_notify_pretimeout(self.as_raw()) };
+    }
+}
+
+/// The configuration of a watchdog device.
+#[derive(Clone, Copy, Default)]
+pub struct Config {
+    /// The initial timeout, in seconds.
+    pub timeout: u32,
+
+    /// The initial pretimeout, in seconds, or zero if it is disabled.
+    pub pretimeout: u32,
+
+    /// The smallest timeout that can be set, in seconds.
+    pub min_timeout: u32,
+
+    /// The largest timeout that can be set, in seconds, or zero if
+    /// [`Config::max_hw_heartbeat_ms`] is used.
+    pub max_timeout: u32,
+
+    /// The largest timeout supported by the hardware, in milliseconds, or zero.
+    ///
+    /// If set, user space can request longer timeouts, and the core pings the hardware in between
+    /// user space pings.
+    pub max_hw_heartbeat_ms: u32,
+
+    /// The minimum time between pings of the hardware, in milliseconds.
+    pub min_hw_heartbeat_ms: u32,
+
+    /// Whether the hardware watchdog is already running, e.g., because the bootloader started
+    /// it. The core then pings it until user space opens the device.
+    pub hw_running: bool,
+
+    /// Whether the watchdog cannot be stopped once started.
+    pub nowayout: bool,
+}
+
+/// A registered watchdog device.
+///
+/// The device is unregistered and its data freed when the registration is dropped.
+///
+/// # Invariants
+///
+/// `wdd` is registered, and its driver data was obtained from [`ForeignOwnable::into_foreign`] on
+/// a `T::Data`.
+///
+/// # Examples
+///
+/// ```
+/// use kernel::{device::RawDevice, prelude::*, watchdog};
+///
+/// struct MyWdt;
+///
+/// #[vtable]
+/// impl watchdog::Operations for MyWdt {
+///     type Data = Box<MyWdt>;
+///
+///     const IDENTITY: &'static str = "my-wdt";
+///     const OPTIONS: u32 = watchdog::options::SETTIMEOUT
+///         | watchdog::options::KEEPALIVEPING
+///         | watchdog::options::MAGICCLOSE;
+///
+///     fn start(_data: &MyWdt, _wdd: &watchdog::Device) -> Result {
+///         Ok(())
+///     }
+///
+///     fn ping(_data: &MyWdt, _wdd: &watchdog::Device) -> Result {
+///         Ok(())
+///     }
+///
+///     fn set_timeout(_data: &MyWdt, _wdd: &watchdog::Device, _timeout: u32) -> Result {
+///         Ok(())
+///     }
+/// }
+///
+/// fn register(dev: &impl RawDevice) -> Result<watchdog::Registration<MyWdt>> {
+///     let config = watchdog::Config {
+///         timeout: 30,
+///         min_timeout: 1,
+///         // The hardware counter only covers four seconds; the core pings it in between.
+///         max_hw_heartbeat_ms: 4000,
+///         ..Default::default()
+///     };
+///     watchdog::Registration::register(dev, &config, Box::new(MyWdt, GFP_KERNEL)?)
+/// }
+/// ```
+pub struct Registration<T: Operations> {
+    wdd: Pin<Box<Opaque<bindings::watchdog_device>>>,
+    _p: PhantomData<T>,
+}
+
+impl<T: Operations> Registration<T> {
+    /// Registers a new watchdog device as a child of `parent`.
+    pub fn register(parent: &impl RawDevice, config: &Config, data: T::Data) -> Result<Self> {
+        let wdd = Box::into_pin(Box::new(Opaque::uninit(), GFP_KERNEL)?);
+        let raw = wdd.get();
+        let ptr = data.into_foreign();
+
+        // SAFETY: `raw` was just allocated and is not shared yet; all-zeroes is a valid initial
+        // state for `watchdog_device`.
+        unsafe {
+            raw.write(core::mem::zeroed());
+            (*raw).parent = parent.raw_device();
+            (*raw).info = &Adapter::<T>::INFO;
+            (*raw).ops = &Adapter::<T>::OPS;
+            (*raw).timeout = config.timeout;
+            (*raw).pretimeout = config.pretimeout;
+            (*raw).min_timeout = config.min_timeout;
+            (*raw).max_timeout = config.max_timeout;
+            (*raw).min_hw_heartbeat_ms = config.min_hw_heartbeat_ms;
+            (*raw).max_hw_heartbeat_ms = config.max_hw_heartbeat_ms;
+            (*raw).driver_data = ptr as *mut _;
+            if config.hw_running {
+                (*raw).status |= 1 << bindings::WDOG_HW_RUNNING;
+            }
+            if config.nowayout {
+                (*raw).status |= 1 << bindings::WDOG_NO_WAY_OUT;
+            }
+        }
+
+        // SAFETY: `raw` is initialised above and the ops and info tables are static. The device
+        // is only unregistered in `drop`, and its memory is freed after that.
+        if let Err(e) = to_result(unsafe { bindings::watchdog_register_device(raw) }) {
+            // SAFETY: `ptr` came from `into_foreign` above and the device was not registered.
+            drop(unsafe { T::Data::from_foreign(ptr) });
+            return Err(e);
+        }
+
+        // INVARIANT: The device was registered above with `ptr` as its driver data.
+        Ok(Self {
+            wdd,
+            _p: PhantomData,
+        })
+    }
+
+    /// Returns the registered watchdog device.
+    pub fn device(&self) -> &Device {
+        // SAFETY: By the type invariants, the device is registered while `self` is alive.
+        unsafe { Device::from_raw(self.wdd.get()) }
+    }
+}
+
+impl<T: Operations> Drop for Registration<T> {
+    fn drop(&mut self) {
+        let raw = self.wdd.get();
+        // SAFETY: By the type invariants, the device is registered. No callbacks run after it is
+        // unregistered.
+        unsafe { bindings::watchdog_unregister_device(raw) };
+        // SAFETY: By the type invariants, the driver data came from `into_foreign`, and it is no
+        // longer used by the unregistered device.
+        drop(unsafe { T::Data::from_foreign((*raw).driver_data) });
+    }
+}
+
+// SAFETY: The registration only holds a `T::Data`, which is `Send`, and the device, which can be
+// unregistered from any thread.
+unsafe impl<T: Operations> Send for Registration<T> {}
+
+// SAFETY: The methods of `Device` available through `&self` are safe to call concurrently.
+unsafe impl<T: Operations> Sync for Registration<T> {}
+
+struct Adapter<T: Operations>(PhantomData<T>);
+
+impl<T: Operations> Adapter<T> {
+    const INFO: bindings::watchdog_info = {
+        let mut info = bindings::watchdog_info {
+            options: T::OPTIONS,
+            firmware_version: 0,
+            identity: [0; 32],
+        };
+        let id = T::IDENTITY.as_bytes();
+        let mut i = 0;
+        // The last byte must be left as the NUL terminator; a build time error is triggered if
+        // `IDENTITY` does not fit.
+        while i < id.len() {
+            info.identity[i] = id[i];
+            i += 1;
+        }
+        assert!(i < 32);
+        info
+    };
+
+    const OPS: bindings::watchdog_ops = bindings::watchdog_ops {
+        start: Some(Self::start_callback),
+        stop: if T::HAS_STOP {
+            Some(Self::stop_callback)
+        } else {
+            None
+        },
+        ping: if T::HAS_PING {
+            Some(Self::ping_callback)
+        } else {
+            None
+        },
+        set_timeout: if T::HAS_SET_TIMEOUT {
+            Some(Self::set_timeout_callback)
+        } else {
+            None
+        },
+        set_pretimeout: if T::HAS_SET_PRETIMEOUT {
+            Some(Self::set_pretimeout_callback)
+        } else {
+            None
+        },
+        get_timeleft: if T::HAS_GET_TIMELEFT {
+            Some(Self::get_timeleft_callback)
+        } else {
+            None
+        },
+        // SAFETY: The remaining fields are optional, for which NULL is valid.
+        ..unsafe { core::mem::zeroed() }
+    };
+
+    /// # Safety
+    ///
+    /// `wdd` must be a device registered by [`Registration::register`].
+    unsafe fn args<'a>(
+        wdd: *mut bindings::watchdog_device,
+    ) -> (<T::Data as ForeignOwnable>::Borrowed<'a>, &'a Device) {
+        // SAFETY: By the safety requirements, the driver data came from `into_foreign`, and is
+        // only freed after the device is unregistered.
+        unsafe { (T::Data::borrow((*wdd).driver_data), Device::from_raw(wdd)) }
+    }
+
+    unsafe extern "C" fn start_callback(wdd: *mut bindings::watchdog_device) -> i32 {
+        from_result(|| {
+            // SAFETY: The core only calls this for devices registered with these ops.
+            let (data, dev) = unsafe { Self::args(wdd) };
+            T::start(data, dev)?;
+            Ok(0)
+        })
+    }
+
+    unsafe extern "C" fn stop_callback(wdd: *mut bindings::watchdog_device) -> i32 {
+        from_result(|| {
+            // SAFETY: The core only calls this for devices registered with these ops.
+            let (data, dev) = unsafe { Self::args(wdd) };
+            T::stop(data, dev)?;
+            Ok(0)
+        })
+    }
+
+    unsafe extern "C" fn ping_callback(wdd: *mut bindings::watchdog_device) -> i32 {
+        from_result(|| {
+            // SAFETY: The core only calls this for devices registered with these ops.
+            let (data, dev) = unsafe { Self::args(wdd) };
+            T::ping(data, dev)?;
+            Ok(0)
+        })
+    }
+
+    unsafe extern "C" fn set_timeout_callback(
+        wdd: *mut bindings::watchdog_device,
+        timeout: c_uint,
+    ) -> i32 {
+        from_result(|| {
+            // SAFETY: The core only calls this for devices registered with these ops.
+            let (data, dev) = unsafe { Self::args(wdd) };
+            T::set_timeout(data, dev, timeout)?;
+            // SAFETY: The core serialises the callbacks of a device.
+            unsafe { (*wdd).timeout = timeout };
+            Ok(0)
+        })
+    }
+
+    unsafe extern "C" fn set_pretimeout_callback(
+        wdd: *mut bindings::watchdog_device,
+        pretimeout: c_uint,
+    ) -> i32 {
+        from_result(|| {
+            // SAFETY: The core only calls this for devices registered with these ops.
+            let (data, dev) = unsafe { Self::args(wdd) };
+            T::set_pretimeout(data, dev, pretimeout)?;
+            // SAFETY: The core serialises the callbacks of a device.
+            unsafe { (*wdd).pretimeout = pretimeout };
+            Ok(0)
+        })
+    }
+
+    unsafe extern "C" fn get_timeleft_callback(wdd: *mut bindings::watchdog_device) -> c_uint {
+        // SAFETY: The core only calls this for devices registered with these ops.
+        let (data, dev) = unsafe { Self::args(wdd) };
+        T::get_timeleft(data, dev)
+    }
+}
@@ -133,7 +133,7 @@
 //! C header: [`include/linux/workqueue.h`](srctree/include/linux/workqueue.h)
 
 use crate::alloc::{AllocError, Flags};
-use crate::{bindings, prelude::*, sync::Arc, sync::LockClassKey, types::Opaque};
+use crate::{bindings, ffi_init, prelude::*, sync::Arc, sync::LockClassKey, types::Opaque};
 use alloc::boxed::Box;
 use core::marker::PhantomData;
 use core::pin::Pin;
@@ -376,19 +376,15 @@ pub fn new(name: &'static CStr, key: &'static LockClassKey) -> impl PinInit<Self
         T: WorkItem<ID>,
     {
         pin_init!(Self {
-            work <- Opaque::ffi_init(|slot| {
-                // SAFETY: The `WorkItemPointer` implementation promises that `run` can be used as
-                // the work item function.
-                unsafe {
-                    bindings::init_work_with_key(
-                        slot,
-                        Some(T::Pointer::run),
-                        false,
-                        name.as_char_ptr(),
-                        key.as_ptr(),
-                    )
-                }
-            }),
+            // SAFETY: The `WorkItemPointer` implementation promises that `run` can be used as the
+            // work item function.
+            work <- ffi_init!(unsafe bindings::init_work_with_key(
+                slot,
+                Some(T::Pointer::run),
+                false,
+                name.as_char_ptr(),
+                key.as_ptr(),
+            )),
             _inner: PhantomData,
         })
     }
@@ -0,0 +1,271 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Self-tests and a benchmark for the Rust DMA abstractions.
+//!
+//! Like the C `dma_map_benchmark` driver, this driver has no ID table and is bound to a real
+//! device, so that the tests run with the IOMMU or SWIOTLB configuration of that device:
+//!
+//! ```text
+//! echo rust_dma_selftest > /sys/bus/platform/devices/<dev>/driver_override
+//! echo <dev> > /sys/bus/platform/drivers_probe
+//! ```
+//!
+//! Binding the driver unbinds the driver the device had, so a device that is not otherwise in use
+//! should be picked. The tests change the DMA masks of the device, which are restored once they
+//! are done, so that the device works with its own driver again after it is rebound.
+//!
+//! The tests run when the device is bound, which fails if any of them fails. The benchmark then
+//! reports the average time of a coherent allocation and free, and of a streaming map and unmap,
+//! like `dma_map_benchmark` does for the C API. The tests are most useful with
+//! `CONFIG_DMA_API_DEBUG` and `CONFIG_KASAN` enabled, which catch misuse of the DMA API by the
+//! unsafe code of the abstractions.
+
+use kernel::{
+    device::RawDevice,
+    dma::{self, CoherentAllocation, Direction, SgMapping, StreamingMapping},
+    module_platform_driver, platform,
+    prelude::*,
+    sizes::*,
+    time::timekeeping,
+    types::ScopeGuard,
+};
+
+module_platform_driver! {
+    type: RustDmaSelftest,
+    name: "rust_dma_selftest",
+    author: "Rust for Linux Contributors",
+    description: "Self-tests for the Rust DMA abstractions",
+    license: "GPL",
+}
+
+/// Fails the current test if the condition does not hold.
+macro_rules! check {
+    ($cond:expr) => {
+        if !$cond {
+            pr_err!(
+                "{}:{}: check failed: {}\n",
+                file!(),
+                line!(),
+                stringify!($cond)
+            );
+            return Err(EINVAL);
+        }
+    };
+}
+
+/// The number of address bits the tests use, which all DMA capable devices support.
+const MASK_BITS: u32 = 32;
+
+/// The number of allocations timed by the benchmark.
+const BENCH_ITERATIONS: u32 = 1000;
+
+fn test_mask(dev: &platform::Device) -> Result {
+    dev.dma_set_mask_and_coherent(MASK_BITS)?;
+    check!(Some(dev.dma_mask()) == dma::bit_mask(MASK_BITS));
+    check!(dev.dma_set_mask(65) == Err(EINVAL));
+    check!(dma::bit_mask(64) == Some(u64::MAX));
+    check!(dma::bit_mask(65).is_none());
+    Ok(())
+}
+
+fn test_coherent(dev: &platform::Device) -> Result {
+    let mask = dma::bit_mask(MASK_BITS).ok_or(EINVAL)?;
+    for size in [1, SZ_4K - 1, SZ_4K, SZ_64K + 1] {
+        let mut buf = CoherentAllocation::alloc(dev, size, GFP_KERNEL, 0)?;
+        check!(buf.size() == size);
+
+        // Coherent buffers are aligned to at least a page, and within the coherent mask.
+        let start = buf.dma_handle();
+        check!(start % SZ_4K as u64 == 0);
+        check!(start + size as u64 - 1 <= mask);
+
+        let pattern = [0xa5u8, 0x5a, 0x00, 0xff];
+        let last = size.saturating_sub(pattern.len());
+        buf.write(0, &pattern[..size.min(pattern.len())])?;
+        buf.write(last, &pattern[..size - last])?;
+        let mut back = [0u8; 4];
+        buf.read(last, &mut back[..size - last])?;
+        check!(back[..size - last] == pattern[..size - last]);
+
+        // Accesses past the end are rejected.
+        check!(buf.write(size, &pattern[..1]) == Err(EINVAL));
+        check!(buf.read(usize::MAX, &mut back) == Err(EINVAL));
+    }
+
+    check!(CoherentAllocation::alloc(dev, 0, GFP_KERNEL, 0).err() == Some(EINVAL));
+    check!(
+        CoherentAllocation::alloc(dev, SZ_4K, GFP_KERNEL, dma::attrs::NO_KERNEL_MAPPING).err()
+            == Some(EINVAL)
+    );
+    Ok(())
+}
+
+fn test_contiguous(dev: &platform::Device) -> Result {
+    let buf = CoherentAllocation::alloc_contiguous(dev, SZ_1M, SZ_64K, GFP_KERNEL)?;
+    // The size is halved until the allocation succeeds.
+    check!(buf.size().is_power_of_two());
+    check!((SZ_64K..=SZ_1M).contains(&buf.size()));
+
+    check!(
+        CoherentAllocation::alloc_contiguous(dev, SZ_4K, SZ_8K, GFP_KERNEL).err() == Some(EINVAL)
+    );
+    Ok(())
+}
+
+/// Returns a buffer of `len` bytes filled with a pattern that depends on `seed`.
+fn pattern_buf(len: usize, seed: u8) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(len, GFP_KERNEL)?;
+    for i in 0..len {
+        buf.push((i as u8) ^ seed, GFP_KERNEL)?;
+    }
+    Ok(buf)
+}
+
+fn check_pattern(buf: &[u8], seed: u8) -> bool {
+    buf.iter().enumerate().all(|(i, &b)| b == (i as u8) ^ seed)
+}
+
+fn test_streaming(dev: &platform::Device) -> Result {
+    let mask = dma::bit_mask(MASK_BITS).ok_or(EINVAL)?;
+    for (size, dir) in [
+        (1, Direction::ToDevice),
+        (SZ_4K, Direction::ToDevice),
+        (SZ_4K + 1, Direction::FromDevice),
+        (SZ_64K, Direction::FromDevice),
+    ] {
+        let map = StreamingMapping::new(dev, pattern_buf(size, 0x5a)?, dir)?;
+        check!(map.len() == size);
+        check!(map.dma_handle() + size as u64 - 1 <= mask);
+
+        // Without a transfer, the buffer is handed back unchanged, also when it goes through a
+        // SWIOTLB bounce buffer, which is initialised from it in both directions.
+        let buf = map.unmap();
+        check!(buf.len() == size && check_pattern(&buf, 0x5a));
+    }
+
+    check!(StreamingMapping::new(dev, Vec::new(), Direction::ToDevice).err() == Some(EINVAL));
+    Ok(())
+}
+
+fn test_sg(dev: &platform::Device) -> Result {
+    let mask = dma::bit_mask(MASK_BITS).ok_or(EINVAL)?;
+    let sizes = [100, SZ_4K, SZ_8K + 1];
+    let mut bufs = Vec::with_capacity(sizes.len(), GFP_KERNEL)?;
+    for (i, &size) in sizes.iter().enumerate() {
+        bufs.push(pattern_buf(size, i as u8)?, GFP_KERNEL)?;
+    }
+
+    let map = SgMapping::new(dev, bufs, Direction::ToDevice)?;
+    // An IOMMU may merge the buffers, but the segments must cover all of them.
+    let mut total = 0;
+    let mut count = 0;
+    for seg in map.segments() {
+        check!(seg.len != 0);
+        check!(seg.addr + seg.len as u64 - 1 <= mask);
+        total += seg.len;
+        count += 1;
+    }
+    check!(total == sizes.iter().sum::<usize>());
+    check!((1..=sizes.len()).contains(&count));
+
+    let bufs = map.unmap();
+    check!(bufs.len() == sizes.len());
+    for (i, buf) in bufs.iter().enumerate() {
+        check!(buf.len() == sizes[i] && check_pattern(buf, i as u8));
+    }
+
+    check!(SgMapping::new(dev, Vec::new(), Direction::ToDevice).err() == Some(EINVAL));
+    let mut bufs = Vec::new();
+    bufs.push(Vec::new(), GFP_KERNEL)?;
+    check!(SgMapping::new(dev, bufs, Direction::FromDevice).err() == Some(EINVAL));
+    Ok(())
+}
+
+fn bench_coherent(dev: &platform::Device) -> Result {
+    let start = timekeeping::monotonic();
+    for _ in 0..BENCH_ITERATIONS {
+        drop(CoherentAllocation::alloc(dev, SZ_4K, GFP_KERNEL, 0)?);
+    }
+    let ns = (timekeeping::monotonic() - start) / i64::from(BENCH_ITERATIONS);
+    dev_info!(
+        dev,
+        "coherent alloc/free of 4 KiB: {} ns (bounce: {}, addressing limited: {})\n",
+        ns,
+        dev.dma_needs_bounce(),
+        dev.dma_addressing_limited()
+    );
+    Ok(())
+}
+
+fn bench_streaming(dev: &platform::Device) -> Result {
+    let mut buf = pattern_buf(SZ_4K, 0)?;
+    let start = timekeeping::monotonic();
+    for _ in 0..BENCH_ITERATIONS {
+        buf = StreamingMapping::new(dev, buf, Direction::ToDevice)?.unmap();
+    }
+    let ns = (timekeeping::monotonic() - start) / i64::from(BENCH_ITERATIONS);
+    dev_info!(dev, "streaming map/unmap of 4 KiB: {} ns\n", ns);
+    Ok(())
+}
+
+/// Returns the number of address bits of `mask`, if it is the mask of the lowest bits.
+fn mask_bits(mask: u64) -> Option<u32> {
+    let bits = u64::BITS - mask.leading_zeros();
+    (dma::bit_mask(bits) == Some(mask)).then_some(bits)
+}
+
+/// Restores the DMA masks the device had before the tests.
+fn restore_masks(dev: &platform::Device, (streaming, coherent): (u64, u64)) {
+    // A device without a streaming mask is not DMA capable, so its masks cannot have changed.
+    if streaming == 0 {
+        return;
+    }
+    let restored = match (mask_bits(streaming), mask_bits(coherent)) {
+        (Some(s), Some(c)) => dev
+            .dma_set_mask(s)
+            .and_then(|_| dev.dma_set_coherent_mask(c)),
+        _ => Err(EINVAL),
+    };
+    if restored.is_err() {
+        dev_warn!(
+            dev,
+            "cannot restore the DMA masks {:#x} and {:#x}\n",
+            streaming,
+            coherent
+        );
+    }
+}
+
+struct RustDmaSelftest;
+
+impl platform::Driver for RustDmaSelftest {
+    fn probe(dev: &mut platform::Device, _id_info: Option<&Self::IdInfo>) -> Result {
+        let dev = &*dev;
+        let masks = (dev.dma_mask(), dev.coherent_dma_mask());
+        let _restore = ScopeGuard::new(|| restore_masks(dev, masks));
+
+        let tests: [(&str, fn(&platform::Device) -> Result); 5] = [
+            ("mask", test_mask),
+            ("coherent", test_coherent),
+            ("contiguous", test_contiguous),
+            ("streaming", test_streaming),
+            ("sg", test_sg),
+        ];
+
+        let mut failed = 0;
+        for (name, test) in tests {
+            if let Err(e) = test(dev) {
+                dev_err!(dev, "{}: FAIL ({:?})\n", name, e);
+                failed += 1;
+            }
+        }
+
+        if failed != 0 {
+            dev_err!(dev, "{} of {} tests failed\n", failed, tests.len());
+            return Err(EINVAL);
+        }
+        dev_info!(dev, "all {} tests passed\n", tests.len());
+        bench_coherent(dev)?;
+        bench_streaming(dev)
+    }
+}